@@ -0,0 +1,116 @@
+//! Adjacency matrix aggregations
+//!
+//! Runs a set of named filters and puts the documents that match each one,
+//! as well as each pair of them, into a bucket. The filters are run over each
+//! index before the search, so every filter is only run once, rather than for
+//! each document.
+
+use std::sync::Arc;
+
+use query_parser::QueryBuilder;
+
+
+/// A filter of an adjacency matrix
+///
+/// Each bucket of a parent aggregation gets its own copy of the aggregation.
+/// The copies share their filters, so the documents that match a filter only
+/// need to be found once.
+#[derive(Debug, Clone)]
+pub struct AdjacencyFilter(Arc<QueryBuilder>);
+
+
+impl AdjacencyFilter {
+    pub fn new(query: Box<QueryBuilder>) -> AdjacencyFilter {
+        AdjacencyFilter(Arc::from(query))
+    }
+
+    pub fn query(&self) -> &QueryBuilder {
+        &*self.0
+    }
+
+    /// Identifies the filter, and the copies of it
+    pub fn id(&self) -> usize {
+        &*self.0 as *const QueryBuilder as *const () as usize
+    }
+}
+
+
+impl PartialEq for AdjacencyFilter {
+    fn eq(&self, other: &AdjacencyFilter) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdjacencyMatrixAggregation {
+    /// Filters in order of their names
+    pub filters: Vec<(String, AdjacencyFilter)>,
+
+    /// Goes between the names of a pair of filters in the keys of their bucket
+    pub separator: String,
+}
+
+
+impl AdjacencyMatrixAggregation {
+    /// Finds the keys of the buckets that a document goes into, from whether it
+    /// matched each filter. These are given in the order that buckets are
+    /// rendered in
+    pub fn bucket_keys(&self, matched: &[bool]) -> Vec<String> {
+        let mut keys = Vec::new();
+
+        for (i, &(ref name, _)) in self.filters.iter().enumerate() {
+            if !matched[i] {
+                continue;
+            }
+
+            keys.push(name.clone());
+
+            for (j, &(ref other_name, _)) in self.filters.iter().enumerate().skip(i + 1) {
+                if matched[j] {
+                    keys.push(format!("{}{}{}", name, self.separator, other_name));
+                }
+            }
+        }
+
+        keys
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use query_parser::parse;
+
+    use super::{AdjacencyMatrixAggregation, AdjacencyFilter};
+
+    fn filter(name: &str) -> (String, AdjacencyFilter) {
+        (name.to_string(), AdjacencyFilter::new(parse(&json!({"match_all": {}})).unwrap()))
+    }
+
+    #[test]
+    fn test_adjacency_matrix_bucket_keys() {
+        let aggregation = AdjacencyMatrixAggregation {
+            filters: vec![filter("grpA"), filter("grpB"), filter("grpC")],
+            separator: "&".to_string(),
+        };
+
+        assert_eq!(aggregation.bucket_keys(&[true, true, false]), vec!["grpA", "grpA&grpB", "grpB"]);
+        assert_eq!(aggregation.bucket_keys(&[false, true, true]), vec!["grpB", "grpB&grpC", "grpC"]);
+        assert_eq!(aggregation.bucket_keys(&[true, true, true]), vec!["grpA", "grpA&grpB", "grpA&grpC", "grpB", "grpB&grpC", "grpC"]);
+
+        // Documents that match no filters don't go into any bucket
+        assert_eq!(aggregation.bucket_keys(&[false, false, false]), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_adjacency_filter_copies() {
+        let (_, a) = filter("grpA");
+        let (_, b) = filter("grpA");
+
+        // Copies are the same filter, but filters with the same query aren't
+        assert_eq!(a.clone(), a);
+        assert_eq!(a.clone().id(), a.id());
+        assert!(a != b);
+    }
+}
@@ -0,0 +1,999 @@
+//! Aggregation collectors
+//!
+//! Every aggregation in the tree is given a collector, which is passed each
+//! document that matched the query while the search is running. Once the
+//! search has finished, the collectors render their results into the
+//! response.
+//!
+//! Collectors read the values of the documents through `DocumentValues`, so
+//! they don't depend on how the documents are stored.
+
+use std::collections::{HashMap, HashSet, BTreeMap};
+
+use serde_json;
+use serde_json::Value as Json;
+
+use search::query::geo_shape::Coordinate;
+use search::collectors::{Collector as SearchCollector, DocumentMatch};
+use mapping::histogram::Histogram;
+use aggregations::pipeline::PipelineError;
+use aggregations::geo::GeoAggregation;
+use aggregations::stats::{MetricAggregation, ExtendedStatsAggregation, ExtendedStats, MatrixStatsAggregation, MatrixStats};
+use aggregations::terms::{TermsAggregation, TermValue};
+use aggregations::histogram::HistogramAggregation;
+use aggregations::date_histogram::DateHistogramAggregation;
+use aggregations::adjacency_matrix::{AdjacencyMatrixAggregation, AdjacencyFilter};
+use aggregations::nested::{NestedAggregation, ReverseNestedAggregation};
+use aggregations::tree::{Aggregations, AggregationType};
+
+
+/// Reads the values of a document that matched the query
+pub trait DocumentValues {
+    /// The values of a numeric, boolean or date field. Booleans are given as 0
+    /// or 1 and dates are given in milliseconds since the epoch
+    fn numeric_values(&self, field: &str) -> Vec<f64>;
+
+    /// The points of a geo_point field
+    fn geo_points(&self, field: &str) -> Vec<Coordinate>;
+
+    /// The values of a keyword, integer or boolean field
+    fn term_values(&self, field: &str) -> Vec<TermValue>;
+
+    /// The pre-aggregated values of a histogram field
+    fn histograms(&self, field: &str) -> Vec<Histogram>;
+
+    /// Checks if the document matches a filter of an adjacency matrix
+    fn matches_filter(&self, filter: &AdjacencyFilter) -> bool;
+
+    /// The nested documents of a nested field
+    fn nested_documents<'s>(&'s self, path: &str) -> Vec<Box<DocumentValues + 's>>;
+
+    /// The path of the nested field that this document is in, or None if it's
+    /// a top level document
+    fn nested_path(&self) -> Option<&str>;
+
+    /// The document that this nested document is in
+    fn parent(&self) -> Option<&DocumentValues>;
+
+    /// Identifies the document, so documents that are reached through more than
+    /// one of their nested documents are only counted once
+    fn id(&self) -> u64;
+}
+
+
+/// Finds the document that a nested document is in at the given nested path,
+/// or the top level document if there's no path
+fn reverse_nested<'d>(doc: &'d DocumentValues, path: Option<&str>) -> Option<&'d DocumentValues> {
+    let mut doc = doc;
+    while doc.nested_path() != path {
+        doc = doc.parent()?;
+    }
+
+    Some(doc)
+}
+
+
+pub trait Collector {
+    fn collect(&mut self, doc: &DocumentValues);
+    fn render(&self) -> Result<Json, PipelineError>;
+}
+
+
+/// Adds the values of a numeric or histogram field to the stats. Each value of a
+/// histogram is weighted by its count
+fn collect_stats(stats: &mut ExtendedStats, doc: &DocumentValues, field: &str) {
+    for value in doc.numeric_values(field) {
+        stats.collect(value);
+    }
+
+    for histogram in doc.histograms(field) {
+        for (value, count) in histogram.values.iter().zip(histogram.counts.iter()) {
+            stats.collect_weighted(*value, *count);
+        }
+    }
+}
+
+
+/// Min, max, avg, sum and stats are all worked out from the same running totals
+#[derive(Debug)]
+struct MetricCollector {
+    aggregation: MetricAggregation,
+    stats: ExtendedStats,
+}
+
+
+impl Collector for MetricCollector {
+    fn collect(&mut self, doc: &DocumentValues) {
+        collect_stats(&mut self.stats, doc, &self.aggregation.field);
+    }
+
+    fn render(&self) -> Result<Json, PipelineError> {
+        Ok(self.stats.render_metric(self.aggregation.metric))
+    }
+}
+
+
+#[derive(Debug)]
+struct ExtendedStatsCollector {
+    aggregation: ExtendedStatsAggregation,
+    stats: ExtendedStats,
+}
+
+
+impl Collector for ExtendedStatsCollector {
+    fn collect(&mut self, doc: &DocumentValues) {
+        collect_stats(&mut self.stats, doc, &self.aggregation.field);
+    }
+
+    fn render(&self) -> Result<Json, PipelineError> {
+        Ok(self.stats.render(self.aggregation.sigma))
+    }
+}
+
+
+#[derive(Debug)]
+struct MatrixStatsCollector {
+    aggregation: MatrixStatsAggregation,
+    stats: MatrixStats,
+}
+
+
+impl Collector for MatrixStatsCollector {
+    fn collect(&mut self, doc: &DocumentValues) {
+        // Only the first value of each field is used. Documents that are
+        // missing any of the fields are left out
+        let mut values = Vec::with_capacity(self.aggregation.fields.len());
+        for field in self.aggregation.fields.iter() {
+            match doc.numeric_values(field).into_iter().next() {
+                Some(value) => values.push(value),
+                None => return,
+            }
+        }
+
+        self.stats.collect(&values);
+    }
+
+    fn render(&self) -> Result<Json, PipelineError> {
+        Ok(self.stats.render(&self.aggregation.fields))
+    }
+}
+
+
+/// Keeps the points of each document until the buckets are rendered
+#[derive(Debug)]
+struct GeoCollector {
+    aggregation: GeoAggregation,
+
+    /// Geo buckets can only have pipeline sub-aggregations
+    pipelines: Aggregations,
+    documents: Vec<Vec<Coordinate>>,
+}
+
+
+impl Collector for GeoCollector {
+    fn collect(&mut self, doc: &DocumentValues) {
+        self.documents.push(doc.geo_points(self.aggregation.field()));
+    }
+
+    fn render(&self) -> Result<Json, PipelineError> {
+        let mut buckets = self.aggregation.collect(self.documents.iter().map(|points| &points[..]));
+        self.pipelines.apply_pipelines(&mut buckets)?;
+        Ok(json!({"buckets": buckets}))
+    }
+}
+
+
+/// Gives each value of the field its own bucket, with its own collectors for
+/// the sub-aggregations
+struct TermsCollector {
+    aggregation: TermsAggregation,
+    sub_aggregations: Aggregations,
+    buckets: HashMap<TermValue, (u64, AggregationsCollector)>,
+}
+
+
+impl Collector for TermsCollector {
+    fn collect(&mut self, doc: &DocumentValues) {
+        let mut values = doc.term_values(&self.aggregation.field);
+        values.sort();
+        values.dedup();
+
+        for value in values {
+            let sub_aggregations = &self.sub_aggregations;
+            let bucket = self.buckets.entry(value).or_insert_with(|| (0, sub_aggregations.collector()));
+            bucket.0 += 1;
+            bucket.1.collect(doc);
+        }
+    }
+
+    fn render(&self) -> Result<Json, PipelineError> {
+        // Sub-aggregations are rendered first, as buckets can be ordered by them
+        let mut buckets = Vec::with_capacity(self.buckets.len());
+        for (value, &(doc_count, ref collector)) in self.buckets.iter() {
+            let mut bucket = Json::Object(collector.render_map()?);
+            bucket["doc_count"] = json!(doc_count);
+            value.render_key(&mut bucket);
+            buckets.push((value.clone(), bucket));
+        }
+
+        let total_doc_count = buckets.iter().map(|&(_, ref bucket)| bucket["doc_count"].as_u64().unwrap_or(0)).sum::<u64>();
+        let mut buckets = self.aggregation.select_buckets(buckets);
+        let doc_count = buckets.iter().map(|bucket| bucket["doc_count"].as_u64().unwrap_or(0)).sum::<u64>();
+
+        self.sub_aggregations.apply_pipelines(&mut buckets)?;
+
+        // Counts are exact, as all of the documents are collected in one place
+        Ok(json!({
+            "doc_count_error_upper_bound": 0,
+            "sum_other_doc_count": total_doc_count - doc_count,
+            "buckets": buckets,
+        }))
+    }
+}
+
+
+/// Gives each interval that has values its own bucket, with its own collectors
+/// for the sub-aggregations. Buckets are kept in order of their keys
+struct HistogramCollector {
+    aggregation: HistogramAggregation,
+    sub_aggregations: Aggregations,
+    buckets: BTreeMap<i64, (u64, AggregationsCollector)>,
+}
+
+
+impl Collector for HistogramCollector {
+    fn collect(&mut self, doc: &DocumentValues) {
+        let mut indices = doc.numeric_values(&self.aggregation.field).into_iter()
+            .filter(|value| value.is_finite())
+            .map(|value| self.aggregation.bucket_index(value))
+            .collect::<Vec<_>>();
+        indices.sort();
+        indices.dedup();
+
+        let mut counts = indices.into_iter().map(|index| (index, 1)).collect::<Vec<_>>();
+
+        // Histogram fields add their counts to the buckets, rather than one per document
+        for histogram in doc.histograms(&self.aggregation.field) {
+            counts.extend(histogram.bucket_counts(|value| self.aggregation.bucket_index(value)));
+        }
+
+        for (index, count) in counts {
+            let sub_aggregations = &self.sub_aggregations;
+            let bucket = self.buckets.entry(index).or_insert_with(|| (0, sub_aggregations.collector()));
+            bucket.0 += count;
+            bucket.1.collect(doc);
+        }
+    }
+
+    fn render(&self) -> Result<Json, PipelineError> {
+        let render_bucket = |index: i64, doc_count: u64, collector: &AggregationsCollector| -> Result<Json, PipelineError> {
+            let mut bucket = Json::Object(collector.render_map()?);
+            bucket["key"] = json!(self.aggregation.bucket_key(index));
+            bucket["doc_count"] = json!(doc_count);
+            Ok(bucket)
+        };
+
+        let collected = match (self.buckets.keys().next(), self.buckets.keys().next_back()) {
+            (Some(&first), Some(&last)) => Some((first, last)),
+            _ => None,
+        };
+
+        let mut buckets = Vec::new();
+        match self.aggregation.filled_range(collected) {
+            Some((first, last)) => {
+                let empty_collector = self.sub_aggregations.collector();
+                for index in first..last + 1 {
+                    match self.buckets.get(&index) {
+                        Some(&(doc_count, ref collector)) => buckets.push(render_bucket(index, doc_count, collector)?),
+                        None => buckets.push(render_bucket(index, 0, &empty_collector)?),
+                    }
+                }
+            }
+            None => {
+                for (&index, &(doc_count, ref collector)) in self.buckets.iter() {
+                    if doc_count >= self.aggregation.min_doc_count {
+                        buckets.push(render_bucket(index, doc_count, collector)?);
+                    }
+                }
+            }
+        }
+
+        self.sub_aggregations.apply_pipelines(&mut buckets)?;
+        Ok(json!({"buckets": buckets}))
+    }
+}
+
+
+/// Like `HistogramCollector`, but buckets are keyed by the date that they start
+struct DateHistogramCollector {
+    aggregation: DateHistogramAggregation,
+    sub_aggregations: Aggregations,
+    buckets: BTreeMap<i64, (u64, AggregationsCollector)>,
+}
+
+
+impl Collector for DateHistogramCollector {
+    fn collect(&mut self, doc: &DocumentValues) {
+        let mut keys = doc.numeric_values(&self.aggregation.field).into_iter()
+            .filter(|value| value.is_finite())
+            .map(|value| self.aggregation.bucket_key(value as i64))
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys.dedup();
+
+        for key in keys {
+            let sub_aggregations = &self.sub_aggregations;
+            let bucket = self.buckets.entry(key).or_insert_with(|| (0, sub_aggregations.collector()));
+            bucket.0 += 1;
+            bucket.1.collect(doc);
+        }
+    }
+
+    fn render(&self) -> Result<Json, PipelineError> {
+        let render_bucket = |key: i64, doc_count: u64, collector: &AggregationsCollector| -> Result<Json, PipelineError> {
+            let mut bucket = Json::Object(collector.render_map()?);
+            bucket["key"] = json!(key);
+            bucket["key_as_string"] = json!(self.aggregation.format_key(key));
+            bucket["doc_count"] = json!(doc_count);
+            Ok(bucket)
+        };
+
+        let collected = match (self.buckets.keys().next(), self.buckets.keys().next_back()) {
+            (Some(&first), Some(&last)) => Some((first, last)),
+            _ => None,
+        };
+
+        let mut buckets = Vec::new();
+        match self.aggregation.filled_keys(collected) {
+            Some(keys) => {
+                let empty_collector = self.sub_aggregations.collector();
+                for key in keys {
+                    match self.buckets.get(&key) {
+                        Some(&(doc_count, ref collector)) => buckets.push(render_bucket(key, doc_count, collector)?),
+                        None => buckets.push(render_bucket(key, 0, &empty_collector)?),
+                    }
+                }
+            }
+            None => {
+                for (&key, &(doc_count, ref collector)) in self.buckets.iter() {
+                    if doc_count >= self.aggregation.min_doc_count {
+                        buckets.push(render_bucket(key, doc_count, collector)?);
+                    }
+                }
+            }
+        }
+
+        self.sub_aggregations.apply_pipelines(&mut buckets)?;
+        Ok(json!({"buckets": buckets}))
+    }
+}
+
+
+/// Gives each filter, and each pair of filters, a bucket for the documents that
+/// match them, with its own collectors for the sub-aggregations
+struct AdjacencyMatrixCollector {
+    aggregation: AdjacencyMatrixAggregation,
+    sub_aggregations: Aggregations,
+    buckets: HashMap<String, (u64, AggregationsCollector)>,
+}
+
+
+impl Collector for AdjacencyMatrixCollector {
+    fn collect(&mut self, doc: &DocumentValues) {
+        let matched = self.aggregation.filters.iter().map(|&(_, ref filter)| doc.matches_filter(filter)).collect::<Vec<_>>();
+
+        for key in self.aggregation.bucket_keys(&matched) {
+            let sub_aggregations = &self.sub_aggregations;
+            let bucket = self.buckets.entry(key).or_insert_with(|| (0, sub_aggregations.collector()));
+            bucket.0 += 1;
+            bucket.1.collect(doc);
+        }
+    }
+
+    fn render(&self) -> Result<Json, PipelineError> {
+        // Buckets without any documents are left out
+        let all_keys = self.aggregation.bucket_keys(&vec![true; self.aggregation.filters.len()]);
+
+        let mut buckets = Vec::new();
+        for key in all_keys {
+            if let Some(&(doc_count, ref collector)) = self.buckets.get(&key) {
+                let mut bucket = Json::Object(collector.render_map()?);
+                bucket["key"] = json!(key);
+                bucket["doc_count"] = json!(doc_count);
+                buckets.push(bucket);
+            }
+        }
+
+        self.sub_aggregations.apply_pipelines(&mut buckets)?;
+        Ok(json!({"buckets": buckets}))
+    }
+}
+
+
+/// Passes the nested documents of each document to the sub-aggregations
+struct NestedCollector {
+    aggregation: NestedAggregation,
+    doc_count: u64,
+    sub_aggregations: AggregationsCollector,
+}
+
+
+impl Collector for NestedCollector {
+    fn collect(&mut self, doc: &DocumentValues) {
+        for nested_doc in doc.nested_documents(&self.aggregation.path) {
+            self.doc_count += 1;
+            self.sub_aggregations.collect(&*nested_doc);
+        }
+    }
+
+    fn render(&self) -> Result<Json, PipelineError> {
+        let mut result = Json::Object(self.sub_aggregations.render_map()?);
+        result["doc_count"] = json!(self.doc_count);
+        Ok(result)
+    }
+}
+
+
+/// Passes the documents that each nested document is in to the sub-aggregations,
+/// once each
+struct ReverseNestedCollector {
+    aggregation: ReverseNestedAggregation,
+    seen: HashSet<u64>,
+    sub_aggregations: AggregationsCollector,
+}
+
+
+impl Collector for ReverseNestedCollector {
+    fn collect(&mut self, doc: &DocumentValues) {
+        if let Some(parent) = reverse_nested(doc, self.aggregation.path.as_ref().map(|path| path.as_str())) {
+            if self.seen.insert(parent.id()) {
+                self.sub_aggregations.collect(parent);
+            }
+        }
+    }
+
+    fn render(&self) -> Result<Json, PipelineError> {
+        let mut result = Json::Object(self.sub_aggregations.render_map()?);
+        result["doc_count"] = json!(self.seen.len());
+        Ok(result)
+    }
+}
+
+
+/// Collects all of the aggregations at one level of the tree
+pub struct AggregationsCollector {
+    collectors: Vec<(String, Box<Collector>)>,
+    adjacency_filters: Vec<AdjacencyFilter>,
+}
+
+
+impl AggregationsCollector {
+    pub fn new(aggregations: &Aggregations) -> AggregationsCollector {
+        let collectors = aggregations.aggregations.iter().map(|aggregation| {
+            let collector: Box<Collector> = match aggregation.aggregation_type {
+                AggregationType::Metric(ref metric) => {
+                    Box::new(MetricCollector {
+                        aggregation: metric.clone(),
+                        stats: ExtendedStats::new(),
+                    })
+                }
+                AggregationType::ExtendedStats(ref extended_stats) => {
+                    Box::new(ExtendedStatsCollector {
+                        aggregation: extended_stats.clone(),
+                        stats: ExtendedStats::new(),
+                    })
+                }
+                AggregationType::MatrixStats(ref matrix_stats) => {
+                    Box::new(MatrixStatsCollector {
+                        aggregation: matrix_stats.clone(),
+                        stats: MatrixStats::new(matrix_stats.fields.len()),
+                    })
+                }
+                AggregationType::Geo(ref geo) => {
+                    Box::new(GeoCollector {
+                        aggregation: geo.clone(),
+                        pipelines: aggregation.sub_aggregations.clone(),
+                        documents: Vec::new(),
+                    })
+                }
+                AggregationType::Terms(ref terms) => {
+                    Box::new(TermsCollector {
+                        aggregation: terms.clone(),
+                        sub_aggregations: aggregation.sub_aggregations.clone(),
+                        buckets: HashMap::new(),
+                    })
+                }
+                AggregationType::Histogram(ref histogram) => {
+                    Box::new(HistogramCollector {
+                        aggregation: histogram.clone(),
+                        sub_aggregations: aggregation.sub_aggregations.clone(),
+                        buckets: BTreeMap::new(),
+                    })
+                }
+                AggregationType::DateHistogram(ref date_histogram) => {
+                    Box::new(DateHistogramCollector {
+                        aggregation: date_histogram.clone(),
+                        sub_aggregations: aggregation.sub_aggregations.clone(),
+                        buckets: BTreeMap::new(),
+                    })
+                }
+                AggregationType::AdjacencyMatrix(ref adjacency_matrix) => {
+                    Box::new(AdjacencyMatrixCollector {
+                        aggregation: adjacency_matrix.clone(),
+                        sub_aggregations: aggregation.sub_aggregations.clone(),
+                        buckets: HashMap::new(),
+                    })
+                }
+                AggregationType::Nested(ref nested) => {
+                    Box::new(NestedCollector {
+                        aggregation: nested.clone(),
+                        doc_count: 0,
+                        sub_aggregations: aggregation.sub_aggregations.collector(),
+                    })
+                }
+                AggregationType::ReverseNested(ref reverse_nested) => {
+                    Box::new(ReverseNestedCollector {
+                        aggregation: reverse_nested.clone(),
+                        seen: HashSet::new(),
+                        sub_aggregations: aggregation.sub_aggregations.collector(),
+                    })
+                }
+            };
+
+            (aggregation.name.clone(), collector)
+        }).collect();
+
+        AggregationsCollector {
+            collectors: collectors,
+            adjacency_filters: aggregations.adjacency_filters(),
+        }
+    }
+
+    /// The filters of the adjacency matrices in the tree. The documents that
+    /// match them must be found before the search
+    pub fn adjacency_filters(&self) -> &[AdjacencyFilter] {
+        &self.adjacency_filters
+    }
+
+    /// Renders the results of each aggregation under its name
+    pub fn render_map(&self) -> Result<serde_json::Map<String, Json>, PipelineError> {
+        let mut results = serde_json::Map::new();
+        for &(ref name, ref collector) in self.collectors.iter() {
+            results.insert(name.clone(), collector.render()?);
+        }
+
+        Ok(results)
+    }
+}
+
+
+impl Collector for AggregationsCollector {
+    fn collect(&mut self, doc: &DocumentValues) {
+        for &mut (_, ref mut collector) in self.collectors.iter_mut() {
+            collector.collect(doc);
+        }
+    }
+
+    fn render(&self) -> Result<Json, PipelineError> {
+        Ok(Json::Object(self.render_map()?))
+    }
+}
+
+
+/// Passes the matches of a search to another collector, and to the aggregations
+///
+/// The aggregations need every document that matched, so the other collector
+/// can't stop early in each segment. The values of each document are read
+/// with the given function.
+pub struct AggregatingCollector<'a, C: 'a, F> {
+    inner: &'a mut C,
+    aggregations: &'a mut AggregationsCollector,
+    read_values: F,
+}
+
+
+impl<'a, C: SearchCollector, D: DocumentValues, F: Fn(u64) -> D> AggregatingCollector<'a, C, F> {
+    pub fn new(inner: &'a mut C, aggregations: &'a mut AggregationsCollector, read_values: F) -> AggregatingCollector<'a, C, F> {
+        AggregatingCollector {
+            inner: inner,
+            aggregations: aggregations,
+            read_values: read_values,
+        }
+    }
+}
+
+
+impl<'a, C: SearchCollector, D: DocumentValues, F: Fn(u64) -> D> SearchCollector for AggregatingCollector<'a, C, F> {
+    fn needs_score(&self) -> bool {
+        self.inner.needs_score()
+    }
+
+    fn collect(&mut self, doc: DocumentMatch) {
+        self.aggregations.collect(&(self.read_values)(doc.doc_id()));
+        self.inner.collect(doc);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::DateTime;
+
+    use search::query::geo_shape::Coordinate;
+    use search::collectors::{Collector as SearchCollector, DocumentMatch};
+    use search::collectors::index_order::IndexOrderCollector;
+    use aggregations::parse::parse_aggregations;
+    use aggregations::terms::TermValue;
+    use aggregations::adjacency_matrix::AdjacencyFilter;
+    use mapping::histogram::Histogram;
+
+    use super::{DocumentValues, Collector, AggregatingCollector};
+
+    #[derive(Debug, Default, Clone)]
+    struct TestDocument {
+        id: u64,
+        numbers: HashMap<&'static str, Vec<f64>>,
+        points: HashMap<&'static str, Vec<Coordinate>>,
+        terms: HashMap<&'static str, Vec<TermValue>>,
+        histograms: HashMap<&'static str, Vec<Histogram>>,
+        nested: HashMap<&'static str, Vec<TestDocument>>,
+        nested_path: Option<String>,
+        parent: Option<Box<TestDocument>>,
+    }
+
+    impl DocumentValues for TestDocument {
+        fn numeric_values(&self, field: &str) -> Vec<f64> {
+            self.numbers.get(field).cloned().unwrap_or_else(Vec::new)
+        }
+
+        fn geo_points(&self, field: &str) -> Vec<Coordinate> {
+            self.points.get(field).cloned().unwrap_or_else(Vec::new)
+        }
+
+        fn term_values(&self, field: &str) -> Vec<TermValue> {
+            self.terms.get(field).cloned().unwrap_or_else(Vec::new)
+        }
+
+        fn histograms(&self, field: &str) -> Vec<Histogram> {
+            self.histograms.get(field).cloned().unwrap_or_else(Vec::new)
+        }
+
+        fn matches_filter(&self, _filter: &AdjacencyFilter) -> bool {
+            false
+        }
+
+        fn nested_documents<'s>(&'s self, path: &str) -> Vec<Box<DocumentValues + 's>> {
+            self.nested.get(path).map_or_else(Vec::new, |nested_docs| {
+                nested_docs.iter().map(|nested_doc| {
+                    Box::new(TestDocument {
+                        nested_path: Some(path.to_string()),
+                        parent: Some(Box::new(self.clone())),
+                        ..nested_doc.clone()
+                    }) as Box<DocumentValues + 's>
+                }).collect()
+            })
+        }
+
+        fn nested_path(&self) -> Option<&str> {
+            self.nested_path.as_ref().map(|path| path.as_str())
+        }
+
+        fn parent(&self) -> Option<&DocumentValues> {
+            self.parent.as_ref().map(|parent| &**parent as &DocumentValues)
+        }
+
+        fn id(&self) -> u64 {
+            self.id
+        }
+    }
+
+    fn document(price: &[f64], location: Option<Coordinate>) -> TestDocument {
+        let mut document = TestDocument::default();
+        document.numbers.insert("price", price.to_vec());
+        document.points.insert("location", location.into_iter().collect());
+        document
+    }
+
+    #[test]
+    fn test_collect_aggregations() {
+        let aggregations = parse_aggregations(&json!({
+            "price_stats": {"extended_stats": {"field": "price"}},
+            "price_matrix": {"matrix_stats": {"fields": ["price"]}},
+            "cells": {
+                "geohash_grid": {"field": "location", "precision": 1},
+                "aggs": {
+                    "total": {"cumulative_sum": {"buckets_path": "_count"}}
+                }
+            }
+        })).unwrap();
+
+        let mut collector = aggregations.collector();
+        collector.collect(&document(&[10.0], Some((4.9, 52.37))));
+        collector.collect(&document(&[20.0, 30.0], Some((4.48, 51.92))));
+        collector.collect(&document(&[], Some((-74.0, 40.7))));
+
+        let results = collector.render().unwrap();
+        assert_eq!(results["price_stats"]["count"], json!(3));
+        assert_eq!(results["price_stats"]["sum"], json!(60.0));
+
+        // Documents without a value are left out of matrix stats
+        assert_eq!(results["price_matrix"]["doc_count"], json!(2));
+
+        assert_eq!(results["cells"], json!({
+            "buckets": [
+                {"key": "u", "doc_count": 2, "total": {"value": 2.0}},
+                {"key": "d", "doc_count": 1, "total": {"value": 3.0}},
+            ]
+        }));
+    }
+
+    fn coloured_document(colours: &[&str], price: f64) -> TestDocument {
+        let mut document = document(&[price], None);
+        document.terms.insert("colour", colours.iter().map(|colour| TermValue::String(colour.to_string())).collect());
+        document
+    }
+
+    #[test]
+    fn test_collect_terms() {
+        let aggregations = parse_aggregations(&json!({
+            "colours": {
+                "terms": {"field": "colour", "size": 2},
+                "aggs": {
+                    "price_stats": {"extended_stats": {"field": "price"}},
+                    "total": {"cumulative_sum": {"buckets_path": "price_stats.sum"}}
+                }
+            }
+        })).unwrap();
+
+        let mut collector = aggregations.collector();
+        collector.collect(&coloured_document(&["red", "blue"], 10.0));
+        collector.collect(&coloured_document(&["red", "red"], 20.0));
+        collector.collect(&coloured_document(&["green"], 5.0));
+        collector.collect(&coloured_document(&[], 1.0));
+
+        // Each document is only counted once in each bucket, and the sub-aggregations
+        // only see the documents in the bucket
+        let results = collector.render().unwrap();
+        let buckets = results["colours"]["buckets"].as_array().unwrap();
+        assert_eq!(buckets.len(), 2);
+        assert_eq!(buckets[0]["key"], json!("red"));
+        assert_eq!(buckets[0]["doc_count"], json!(2));
+        assert_eq!(buckets[0]["price_stats"]["sum"], json!(30.0));
+        assert_eq!(buckets[0]["total"], json!({"value": 30.0}));
+        assert_eq!(buckets[1]["key"], json!("blue"));
+        assert_eq!(buckets[1]["total"], json!({"value": 40.0}));
+        assert_eq!(results["colours"]["sum_other_doc_count"], json!(1));
+    }
+
+    #[test]
+    fn test_collect_terms_ordered_by_sub_aggregation() {
+        let aggregations = parse_aggregations(&json!({
+            "colours": {
+                "terms": {"field": "colour", "order": {"price_stats.max": "asc"}},
+                "aggs": {
+                    "price_stats": {"extended_stats": {"field": "price"}}
+                }
+            }
+        })).unwrap();
+
+        let mut collector = aggregations.collector();
+        collector.collect(&coloured_document(&["red"], 10.0));
+        collector.collect(&coloured_document(&["red"], 20.0));
+        collector.collect(&coloured_document(&["green"], 15.0));
+
+        let results = collector.render().unwrap();
+        let keys = results["colours"]["buckets"].as_array().unwrap().iter().map(|bucket| bucket["key"].clone()).collect::<Vec<_>>();
+        assert_eq!(keys, vec![json!("green"), json!("red")]);
+    }
+
+    #[test]
+    fn test_collect_histogram() {
+        let aggregations = parse_aggregations(&json!({
+            "prices": {
+                "histogram": {"field": "price", "interval": 10, "extended_bounds": {"min": 0, "max": 40}},
+                "aggs": {
+                    "price_stats": {"extended_stats": {"field": "price"}}
+                }
+            }
+        })).unwrap();
+
+        let mut collector = aggregations.collector();
+        collector.collect(&document(&[12.0, 15.0], None));
+        collector.collect(&document(&[18.0], None));
+        collector.collect(&document(&[25.0], None));
+        collector.collect(&document(&[], None));
+
+        // Empty buckets are given for the gaps, and as far as the extended bounds
+        let results = collector.render().unwrap();
+        let buckets = results["prices"]["buckets"].as_array().unwrap();
+        assert_eq!(buckets.iter().map(|bucket| bucket["key"].clone()).collect::<Vec<_>>(), vec![json!(0.0), json!(10.0), json!(20.0), json!(30.0), json!(40.0)]);
+        assert_eq!(buckets.iter().map(|bucket| bucket["doc_count"].clone()).collect::<Vec<_>>(), vec![json!(0), json!(2), json!(1), json!(0), json!(0)]);
+        assert_eq!(buckets[1]["price_stats"]["sum"], json!(45.0));
+        assert_eq!(buckets[0]["price_stats"]["count"], json!(0));
+    }
+
+    #[test]
+    fn test_collect_histogram_min_doc_count() {
+        let aggregations = parse_aggregations(&json!({
+            "prices": {"histogram": {"field": "price", "interval": 10, "min_doc_count": 1}}
+        })).unwrap();
+
+        let mut collector = aggregations.collector();
+        collector.collect(&document(&[-5.0], None));
+        collector.collect(&document(&[25.0], None));
+
+        let results = collector.render().unwrap();
+        assert_eq!(results["prices"], json!({"buckets": [
+            {"key": -10.0, "doc_count": 1},
+            {"key": 20.0, "doc_count": 1},
+        ]}));
+    }
+
+    #[test]
+    fn test_collect_metrics() {
+        let aggregations = parse_aggregations(&json!({
+            "min_price": {"min": {"field": "price"}},
+            "avg_price": {"avg": {"field": "price"}},
+            "price_stats": {"stats": {"field": "price"}},
+            "colours": {
+                "terms": {"field": "colour", "order": {"max_price": "desc"}},
+                "aggs": {
+                    "max_price": {"max": {"field": "price"}}
+                }
+            }
+        })).unwrap();
+
+        let mut collector = aggregations.collector();
+        collector.collect(&coloured_document(&["red"], 10.0));
+        collector.collect(&coloured_document(&["red"], 20.0));
+        collector.collect(&coloured_document(&["green"], 15.0));
+
+        let results = collector.render().unwrap();
+        assert_eq!(results["min_price"], json!({"value": 10.0}));
+        assert_eq!(results["avg_price"], json!({"value": 15.0}));
+        assert_eq!(results["price_stats"], json!({"count": 3, "min": 10.0, "max": 20.0, "avg": 15.0, "sum": 45.0}));
+
+        let buckets = results["colours"]["buckets"].as_array().unwrap();
+        assert_eq!(buckets[0]["key"], json!("red"));
+        assert_eq!(buckets[0]["max_price"], json!({"value": 20.0}));
+        assert_eq!(buckets[1]["max_price"], json!({"value": 15.0}));
+    }
+
+    #[test]
+    fn test_collect_histogram_field() {
+        let aggregations = parse_aggregations(&json!({
+            "avg_latency": {"avg": {"field": "latency"}},
+            "latency_stats": {"stats": {"field": "latency"}},
+            "latencies": {"histogram": {"field": "latency", "interval": 10, "min_doc_count": 1}}
+        })).unwrap();
+
+        // Each value counts as many times as it was seen
+        let mut document = TestDocument::default();
+        document.histograms.insert("latency", vec![Histogram {
+            values: vec![5.0, 8.0, 25.0],
+            counts: vec![3, 1, 4],
+        }]);
+
+        let mut collector = aggregations.collector();
+        collector.collect(&document);
+
+        let results = collector.render().unwrap();
+        assert_eq!(results["avg_latency"], json!({"value": 15.375}));
+        assert_eq!(results["latency_stats"], json!({"count": 8, "min": 5.0, "max": 25.0, "avg": 15.375, "sum": 123.0}));
+        assert_eq!(results["latencies"], json!({"buckets": [
+            {"key": 0.0, "doc_count": 4},
+            {"key": 20.0, "doc_count": 4},
+        ]}));
+    }
+
+    fn dated_document(date: &str) -> TestDocument {
+        let mut document = TestDocument::default();
+        let date = DateTime::parse_from_rfc3339(date).unwrap();
+        document.numbers.insert("date", vec![(date.timestamp() * 1000) as f64]);
+        document
+    }
+
+    #[test]
+    fn test_collect_date_histogram() {
+        let aggregations = parse_aggregations(&json!({
+            "per_month": {
+                "date_histogram": {"field": "date", "calendar_interval": "month", "time_zone": "+01:00"},
+                "aggs": {
+                    "running_total": {"cumulative_sum": {"buckets_path": "_count"}}
+                }
+            }
+        })).unwrap();
+
+        let mut collector = aggregations.collector();
+        collector.collect(&dated_document("2019-01-10T12:00:00Z"));
+        collector.collect(&dated_document("2019-03-31T23:30:00Z"));
+        collector.collect(&dated_document("2019-03-31T22:30:00Z"));
+
+        // The second document is in April in the aggregation's time zone
+        let results = collector.render().unwrap();
+        let buckets = results["per_month"]["buckets"].as_array().unwrap();
+        assert_eq!(buckets.iter().map(|bucket| bucket["key_as_string"].clone()).collect::<Vec<_>>(), vec![
+            json!("2019-01-01T00:00:00.000+01:00"),
+            json!("2019-02-01T00:00:00.000+01:00"),
+            json!("2019-03-01T00:00:00.000+01:00"),
+            json!("2019-04-01T00:00:00.000+01:00"),
+        ]);
+        assert_eq!(buckets.iter().map(|bucket| bucket["doc_count"].clone()).collect::<Vec<_>>(), vec![json!(1), json!(0), json!(1), json!(1)]);
+        assert_eq!(buckets[3]["running_total"], json!({"value": 3.0}));
+    }
+
+    fn commented_document(id: u64, authors: &[&str]) -> TestDocument {
+        let mut document = TestDocument::default();
+        document.id = id;
+        document.nested.insert("comments", authors.iter().enumerate().map(|(i, author)| {
+            let mut comment = TestDocument::default();
+            comment.id = id * 100 + i as u64;
+            comment.terms.insert("comments.author", vec![TermValue::String(author.to_string())]);
+            comment
+        }).collect());
+        document
+    }
+
+    #[test]
+    fn test_collect_nested() {
+        let aggregations = parse_aggregations(&json!({
+            "comments": {
+                "nested": {"path": "comments"},
+                "aggs": {
+                    "authors": {
+                        "terms": {"field": "comments.author"},
+                        "aggs": {
+                            "posts": {"reverse_nested": {}}
+                        }
+                    },
+                    "posts": {"reverse_nested": {}}
+                }
+            }
+        })).unwrap();
+
+        let mut collector = aggregations.collector();
+        collector.collect(&commented_document(1, &["alice", "alice", "bob"]));
+        collector.collect(&commented_document(2, &["alice"]));
+        collector.collect(&commented_document(3, &[]));
+
+        // Each comment is counted, but each post is only counted once
+        let results = collector.render().unwrap();
+        assert_eq!(results["comments"]["doc_count"], json!(4));
+        assert_eq!(results["comments"]["posts"]["doc_count"], json!(2));
+
+        let buckets = results["comments"]["authors"]["buckets"].as_array().unwrap();
+        assert_eq!(buckets[0]["key"], json!("alice"));
+        assert_eq!(buckets[0]["doc_count"], json!(3));
+        assert_eq!(buckets[0]["posts"]["doc_count"], json!(2));
+        assert_eq!(buckets[1]["key"], json!("bob"));
+        assert_eq!(buckets[1]["posts"]["doc_count"], json!(1));
+    }
+
+    #[test]
+    fn test_aggregating_collector() {
+        let aggregations = parse_aggregations(&json!({
+            "price_stats": {"extended_stats": {"field": "price"}}
+        })).unwrap();
+
+        let mut aggregations_collector = aggregations.collector();
+        let mut top_collector = IndexOrderCollector::new(1);
+
+        {
+            let mut collector = AggregatingCollector::new(&mut top_collector, &mut aggregations_collector, |doc_id| document(&[doc_id as f64], None));
+
+            // Every match is needed, even though only one is kept
+            assert_eq!(collector.segment_limit(), None);
+            assert_eq!(collector.needs_score(), false);
+
+            for doc_id in 1..5 {
+                collector.collect(DocumentMatch::new_unscored(doc_id));
+            }
+        }
+
+        assert_eq!(top_collector.get_total_count(), 4);
+        assert_eq!(top_collector.into_sorted_vec().len(), 1);
+        assert_eq!(aggregations_collector.render().unwrap()["price_stats"]["sum"], json!(10.0));
+    }
+}
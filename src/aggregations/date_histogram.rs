@@ -0,0 +1,340 @@
+//! Date histogram aggregations
+//!
+//! Buckets documents by the values of a date field. Intervals are either a
+//! fixed length of time, such as "30m", or a calendar unit, such as "month",
+//! which varies in length.
+//!
+//! Buckets are rounded in the time zone of the aggregation. Only fixed offsets
+//! from UTC are supported, such as "+01:00", as these don't need a database of
+//! time zones. Bucket keys are given in milliseconds since the epoch.
+
+use chrono::{NaiveDate, NaiveDateTime, DateTime, FixedOffset, Datelike};
+
+use aggregations::histogram::MAX_FILLED_BUCKETS;
+
+
+const MILLIS_PER_DAY: i64 = 24 * 60 * 60 * 1000;
+
+/// The number of days from 0001-01-01 to 1970-01-01
+const EPOCH_DAYS_FROM_CE: i64 = 719163;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CalendarUnit {
+    Minute,
+    Hour,
+    Day,
+
+    /// Weeks start on Monday
+    Week,
+    Month,
+    Quarter,
+    Year,
+}
+
+
+impl CalendarUnit {
+    pub fn parse(unit: &str) -> Option<CalendarUnit> {
+        match unit {
+            "minute" | "1m" => Some(CalendarUnit::Minute),
+            "hour" | "1h" => Some(CalendarUnit::Hour),
+            "day" | "1d" => Some(CalendarUnit::Day),
+            "week" | "1w" => Some(CalendarUnit::Week),
+            "month" | "1M" => Some(CalendarUnit::Month),
+            "quarter" | "1q" => Some(CalendarUnit::Quarter),
+            "year" | "1y" => Some(CalendarUnit::Year),
+            _ => None,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DateInterval {
+    /// A number of milliseconds
+    Fixed(i64),
+
+    Calendar(CalendarUnit),
+}
+
+
+impl DateInterval {
+    /// Parses a fixed interval, such as "500ms", "30s", "15m", "1h" or "2d"
+    pub fn parse_fixed(interval: &str) -> Option<DateInterval> {
+        let split_at = interval.find(|c: char| !c.is_digit(10)).unwrap_or(interval.len());
+        let amount = interval[..split_at].parse::<i64>().ok()?;
+
+        let unit_millis = match &interval[split_at..] {
+            "ms" => 1,
+            "s" => 1000,
+            "m" => 60 * 1000,
+            "h" => 60 * 60 * 1000,
+            "d" => MILLIS_PER_DAY,
+            _ => return None,
+        };
+
+        if amount > 0 {
+            Some(DateInterval::Fixed(amount * unit_millis))
+        } else {
+            None
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DateHistogramAggregation {
+    pub field: String,
+    pub interval: DateInterval,
+    pub time_zone: FixedOffset,
+
+    /// Buckets with fewer documents are left out. If this is 0, empty buckets
+    /// are given for the gaps between buckets
+    pub min_doc_count: u64,
+
+    /// The first and last dates that empty buckets are given for, when
+    /// min_doc_count is 0. In milliseconds since the epoch
+    pub extended_bounds: Option<(i64, i64)>,
+}
+
+
+/// Converts days since the epoch to a date
+fn date_from_epoch_days(days: i64) -> NaiveDate {
+    NaiveDate::from_num_days_from_ce((days + EPOCH_DAYS_FROM_CE) as i32)
+}
+
+
+fn epoch_days_from_date(date: NaiveDate) -> i64 {
+    date.num_days_from_ce() as i64 - EPOCH_DAYS_FROM_CE
+}
+
+
+fn floor_div(value: i64, divisor: i64) -> i64 {
+    let quotient = value / divisor;
+    if value % divisor < 0 { quotient - 1 } else { quotient }
+}
+
+
+impl DateHistogramAggregation {
+    fn offset_millis(&self) -> i64 {
+        self.time_zone.local_minus_utc() as i64 * 1000
+    }
+
+    /// Finds the key of the bucket that a date falls into. This is the start of
+    /// the bucket, in milliseconds since the epoch
+    pub fn bucket_key(&self, millis: i64) -> i64 {
+        // The offset doesn't change, so rounding can be done on the local time
+        let local = millis + self.offset_millis();
+
+        let local_key = match self.interval {
+            DateInterval::Fixed(interval) => floor_div(local, interval) * interval,
+            DateInterval::Calendar(unit) => {
+                let days = floor_div(local, MILLIS_PER_DAY);
+
+                match unit {
+                    CalendarUnit::Minute => floor_div(local, 60 * 1000) * 60 * 1000,
+                    CalendarUnit::Hour => floor_div(local, 60 * 60 * 1000) * 60 * 60 * 1000,
+                    CalendarUnit::Day => days * MILLIS_PER_DAY,
+                    CalendarUnit::Week => {
+                        // The epoch was on a Thursday
+                        (floor_div(days + 3, 7) * 7 - 3) * MILLIS_PER_DAY
+                    }
+                    CalendarUnit::Month | CalendarUnit::Quarter | CalendarUnit::Year => {
+                        let date = date_from_epoch_days(days);
+                        let month = match unit {
+                            CalendarUnit::Month => date.month(),
+                            CalendarUnit::Quarter => (date.month() - 1) / 3 * 3 + 1,
+                            _ => 1,
+                        };
+
+                        epoch_days_from_date(NaiveDate::from_ymd(date.year(), month, 1)) * MILLIS_PER_DAY
+                    }
+                }
+            }
+        };
+
+        local_key - self.offset_millis()
+    }
+
+    /// Finds the key of the bucket after the given one
+    pub fn next_bucket_key(&self, key: i64) -> i64 {
+        let months = match self.interval {
+            DateInterval::Fixed(interval) => return key + interval,
+            DateInterval::Calendar(CalendarUnit::Minute) => return key + 60 * 1000,
+            DateInterval::Calendar(CalendarUnit::Hour) => return key + 60 * 60 * 1000,
+            DateInterval::Calendar(CalendarUnit::Day) => return key + MILLIS_PER_DAY,
+            DateInterval::Calendar(CalendarUnit::Week) => return key + 7 * MILLIS_PER_DAY,
+            DateInterval::Calendar(CalendarUnit::Month) => 1,
+            DateInterval::Calendar(CalendarUnit::Quarter) => 3,
+            DateInterval::Calendar(CalendarUnit::Year) => 12,
+        };
+
+        // Keys are always at the start of a day in local time
+        let date = date_from_epoch_days(floor_div(key + self.offset_millis(), MILLIS_PER_DAY));
+        let month_index = date.year() as i64 * 12 + date.month0() as i64 + months;
+        let year = floor_div(month_index, 12);
+        let next = NaiveDate::from_ymd(year as i32, (month_index - year * 12) as u32 + 1, 1);
+
+        epoch_days_from_date(next) * MILLIS_PER_DAY - self.offset_millis()
+    }
+
+    /// Formats the key of a bucket as a date in the time zone of the aggregation
+    pub fn format_key(&self, key: i64) -> String {
+        let datetime = NaiveDateTime::from_timestamp(floor_div(key, 1000), (key - floor_div(key, 1000) * 1000) as u32 * 1_000_000);
+        let datetime = DateTime::<FixedOffset>::from_utc(datetime, self.time_zone);
+
+        if self.time_zone.local_minus_utc() == 0 {
+            datetime.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+        } else {
+            datetime.format("%Y-%m-%dT%H:%M:%S%.3f%:z").to_string()
+        }
+    }
+
+    /// Finds the keys of the buckets to give when empty buckets are filled in,
+    /// from the first and last buckets that have documents. Returns None if
+    /// empty buckets shouldn't be given
+    pub fn filled_keys(&self, collected: Option<(i64, i64)>) -> Option<Vec<i64>> {
+        if self.min_doc_count > 0 {
+            return None;
+        }
+
+        let bounds = self.extended_bounds.map(|(min, max)| (self.bucket_key(min), self.bucket_key(max)));
+        let (first, last) = match (collected, bounds) {
+            (Some((first, last)), Some((min, max))) => (first.min(min), last.max(max)),
+            (Some(range), None) | (None, Some(range)) => range,
+            (None, None) => return None,
+        };
+
+        let mut keys = Vec::new();
+        let mut key = first;
+        while key <= last {
+            if keys.len() as i64 >= MAX_FILLED_BUCKETS {
+                return None;
+            }
+
+            keys.push(key);
+            key = self.next_bucket_key(key);
+        }
+
+        Some(keys)
+    }
+}
+
+
+/// Parses a time zone given as an offset from UTC, such as "+01:00", "-0530" or "UTC"
+pub fn parse_time_zone(time_zone: &str) -> Option<FixedOffset> {
+    if time_zone == "UTC" || time_zone == "Z" {
+        return Some(FixedOffset::east(0));
+    }
+
+    let sign = match time_zone.chars().next() {
+        Some('+') => 1,
+        Some('-') => -1,
+        _ => return None,
+    };
+
+    let digits = time_zone[1..].replace(":", "");
+    if !digits.chars().all(|c| c.is_digit(10)) {
+        return None;
+    }
+
+    let (hours, minutes) = match digits.len() {
+        2 => (digits.parse::<i32>().ok()?, 0),
+        4 => (digits[..2].parse::<i32>().ok()?, digits[2..].parse::<i32>().ok()?),
+        _ => return None,
+    };
+
+    if hours > 18 || minutes > 59 {
+        return None;
+    }
+
+    Some(FixedOffset::east(sign * (hours * 60 * 60 + minutes * 60)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, FixedOffset};
+
+    use super::{DateHistogramAggregation, DateInterval, CalendarUnit, parse_time_zone};
+
+    fn date_histogram(interval: DateInterval, time_zone: &str) -> DateHistogramAggregation {
+        DateHistogramAggregation {
+            field: "date".to_string(),
+            interval: interval,
+            time_zone: parse_time_zone(time_zone).unwrap(),
+            min_doc_count: 0,
+            extended_bounds: None,
+        }
+    }
+
+    fn millis(date: &str) -> i64 {
+        let date = DateTime::parse_from_rfc3339(date).unwrap();
+        date.timestamp() * 1000 + date.timestamp_subsec_millis() as i64
+    }
+
+    #[test]
+    fn test_parse_fixed_interval() {
+        assert_eq!(DateInterval::parse_fixed("30m"), Some(DateInterval::Fixed(30 * 60 * 1000)));
+        assert_eq!(DateInterval::parse_fixed("500ms"), Some(DateInterval::Fixed(500)));
+        assert_eq!(DateInterval::parse_fixed("2d"), Some(DateInterval::Fixed(2 * 24 * 60 * 60 * 1000)));
+        assert_eq!(DateInterval::parse_fixed("0h"), None);
+        assert_eq!(DateInterval::parse_fixed("month"), None);
+    }
+
+    #[test]
+    fn test_parse_time_zone() {
+        assert_eq!(parse_time_zone("UTC"), Some(FixedOffset::east(0)));
+        assert_eq!(parse_time_zone("+01:00"), Some(FixedOffset::east(60 * 60)));
+        assert_eq!(parse_time_zone("-0530"), Some(FixedOffset::west(5 * 60 * 60 + 30 * 60)));
+        assert_eq!(parse_time_zone("Europe/London"), None);
+    }
+
+    #[test]
+    fn test_fixed_bucket_key() {
+        let date_histogram = date_histogram(DateInterval::Fixed(30 * 60 * 1000), "UTC");
+
+        assert_eq!(date_histogram.bucket_key(millis("2019-03-10T12:45:10Z")), millis("2019-03-10T12:30:00Z"));
+        assert_eq!(date_histogram.next_bucket_key(millis("2019-03-10T12:30:00Z")), millis("2019-03-10T13:00:00Z"));
+
+        // Dates before the epoch are rounded down too
+        assert_eq!(date_histogram.bucket_key(millis("1969-12-31T23:59:59Z")), millis("1969-12-31T23:30:00Z"));
+    }
+
+    #[test]
+    fn test_calendar_bucket_key() {
+        let month = date_histogram(DateInterval::Calendar(CalendarUnit::Month), "UTC");
+        assert_eq!(month.bucket_key(millis("2019-03-10T12:45:10Z")), millis("2019-03-01T00:00:00Z"));
+        assert_eq!(month.next_bucket_key(millis("2019-12-01T00:00:00Z")), millis("2020-01-01T00:00:00Z"));
+
+        let week = date_histogram(DateInterval::Calendar(CalendarUnit::Week), "UTC");
+        assert_eq!(week.bucket_key(millis("2019-03-10T12:45:10Z")), millis("2019-03-04T00:00:00Z"));
+
+        let quarter = date_histogram(DateInterval::Calendar(CalendarUnit::Quarter), "UTC");
+        assert_eq!(quarter.bucket_key(millis("2019-06-30T23:00:00Z")), millis("2019-04-01T00:00:00Z"));
+        assert_eq!(quarter.next_bucket_key(millis("2019-10-01T00:00:00Z")), millis("2020-01-01T00:00:00Z"));
+    }
+
+    #[test]
+    fn test_time_zone() {
+        // This is in March in UTC, but April in the aggregation's time zone
+        let month = date_histogram(DateInterval::Calendar(CalendarUnit::Month), "+02:00");
+        let key = month.bucket_key(millis("2019-03-31T23:00:00Z"));
+
+        assert_eq!(key, millis("2019-04-01T00:00:00+02:00"));
+        assert_eq!(month.format_key(key), "2019-04-01T00:00:00.000+02:00");
+        assert_eq!(month.next_bucket_key(key), millis("2019-05-01T00:00:00+02:00"));
+    }
+
+    #[test]
+    fn test_filled_keys() {
+        let mut month = date_histogram(DateInterval::Calendar(CalendarUnit::Month), "UTC");
+        month.extended_bounds = Some((millis("2019-01-15T00:00:00Z"), millis("2019-02-15T00:00:00Z")));
+
+        let keys = month.filled_keys(Some((millis("2019-02-01T00:00:00Z"), millis("2019-04-01T00:00:00Z"))));
+        assert_eq!(keys, Some(vec![millis("2019-01-01T00:00:00Z"), millis("2019-02-01T00:00:00Z"), millis("2019-03-01T00:00:00Z"), millis("2019-04-01T00:00:00Z")]));
+
+        month.min_doc_count = 1;
+        assert_eq!(month.filled_keys(Some((millis("2019-02-01T00:00:00Z"), millis("2019-04-01T00:00:00Z")))), None);
+    }
+}
@@ -0,0 +1,305 @@
+//! Geo bucket aggregations
+//!
+//! These bucket documents by the points in one of their geo_point fields,
+//! either by their distance from an origin or by the grid cell they are in.
+//! Points are read from the stored GeoJSON of the field. A document is counted
+//! once in each bucket that any of its points fall into.
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+use serde_json::Value as Json;
+
+use search::query::geo_shape::{Geometry, Coordinate, CoordinateSystem};
+use search::query::geo_distance::{DistanceUnit, haversine_distance};
+
+
+/// Tiles can't go further from the equator than this, as Web Mercator maps
+/// the poles to infinity
+const MAX_TILE_LATITUDE: f64 = 85.05112878;
+
+pub const MAX_GEOHASH_PRECISION: u32 = 12;
+pub const MAX_GEOTILE_PRECISION: u32 = 29;
+
+const GEOHASH_ALPHABET: &'static [u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+
+/// Encodes a point as a geohash with the given number of characters
+pub fn geohash(point: Coordinate, precision: u32) -> String {
+    let (mut min_lon, mut max_lon) = (-180.0, 180.0);
+    let (mut min_lat, mut max_lat) = (-90.0, 90.0);
+    let mut hash = String::with_capacity(precision as usize);
+
+    // Each character holds five bits, which alternate between halving the
+    // longitude range and halving the latitude range
+    let mut is_lon = true;
+    for _ in 0..precision {
+        let mut index = 0;
+
+        for _ in 0..5 {
+            let (value, min, max) = if is_lon {
+                (point.0, &mut min_lon, &mut max_lon)
+            } else {
+                (point.1, &mut min_lat, &mut max_lat)
+            };
+
+            let mid = (*min + *max) / 2.0;
+            index <<= 1;
+            if value >= mid {
+                index |= 1;
+                *min = mid;
+            } else {
+                *max = mid;
+            }
+
+            is_lon = !is_lon;
+        }
+
+        hash.push(GEOHASH_ALPHABET[index] as char);
+    }
+
+    hash
+}
+
+
+/// Finds the Web Mercator map tile that contains a point, as "zoom/x/y"
+pub fn geotile(point: Coordinate, zoom: u32) -> String {
+    let tiles = (1u64 << zoom) as f64;
+    let lat = point.1.max(-MAX_TILE_LATITUDE).min(MAX_TILE_LATITUDE).to_radians();
+
+    let x = ((point.0 + 180.0) / 360.0 * tiles).floor();
+    let y = ((1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0 * tiles).floor();
+
+    // Points on the far edges belong to the last tile
+    let clamp = |value: f64| value.max(0.0).min(tiles - 1.0) as u64;
+    format!("{}/{}/{}", zoom, clamp(x), clamp(y))
+}
+
+
+/// Reads the points out of the stored value of a geo_point field
+pub fn stored_points(json: &Json) -> Vec<Coordinate> {
+    match Geometry::from_json(json, CoordinateSystem::Geographic) {
+        Some(Geometry::Point(point)) => vec![point],
+        Some(Geometry::MultiPoint(points)) => points,
+        _ => Vec::new(),
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DistanceRange {
+    pub key: Option<String>,
+
+    /// Inclusive lower bound
+    pub from: Option<f64>,
+
+    /// Exclusive upper bound
+    pub to: Option<f64>,
+}
+
+
+impl DistanceRange {
+    fn contains(&self, distance: f64) -> bool {
+        self.from.map_or(true, |from| distance >= from) && self.to.map_or(true, |to| distance < to)
+    }
+
+    /// Ranges without a key are named after their bounds, eg "100.0-300.0"
+    fn key(&self) -> String {
+        match self.key {
+            Some(ref key) => key.clone(),
+            None => {
+                let bound = |bound: Option<f64>| bound.map_or("*".to_string(), |bound| format!("{:?}", bound));
+                format!("{}-{}", bound(self.from), bound(self.to))
+            }
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GeoGrid {
+    /// Cells are geohashes of this many characters
+    Geohash(u32),
+
+    /// Cells are map tiles at this zoom level
+    Geotile(u32),
+}
+
+
+impl GeoGrid {
+    pub fn cell(&self, point: Coordinate) -> String {
+        match *self {
+            GeoGrid::Geohash(precision) => geohash(point, precision),
+            GeoGrid::Geotile(zoom) => geotile(point, zoom),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeoAggregation {
+    /// Buckets documents into rings around the origin
+    Distance {
+        field: String,
+        origin: Coordinate,
+        unit: DistanceUnit,
+        ranges: Vec<DistanceRange>,
+    },
+
+    /// Buckets documents into grid cells, keeping the cells with the most
+    /// documents
+    Grid {
+        field: String,
+        grid: GeoGrid,
+        size: usize,
+    },
+}
+
+
+impl GeoAggregation {
+    /// The name of the geo_point field that points are read from
+    pub fn field(&self) -> &str {
+        match *self {
+            GeoAggregation::Distance{ref field, ..} | GeoAggregation::Grid{ref field, ..} => field,
+        }
+    }
+
+    /// Buckets the documents, given as the points of each one
+    pub fn collect<'a, I: IntoIterator<Item = &'a [Coordinate]>>(&self, documents: I) -> Vec<Json> {
+        match *self {
+            GeoAggregation::Distance{origin, unit, ref ranges, ..} => {
+                let mut doc_counts = vec![0u64; ranges.len()];
+
+                for points in documents {
+                    let distances = points.iter().map(|point| haversine_distance(origin, *point) / unit.metres()).collect::<Vec<_>>();
+
+                    for (range, doc_count) in ranges.iter().zip(doc_counts.iter_mut()) {
+                        if distances.iter().any(|distance| range.contains(*distance)) {
+                            *doc_count += 1;
+                        }
+                    }
+                }
+
+                ranges.iter().zip(doc_counts.into_iter()).map(|(range, doc_count)| {
+                    let mut bucket = json!({"key": range.key(), "doc_count": doc_count});
+                    if let Some(from) = range.from {
+                        bucket["from"] = json!(from);
+                    }
+
+                    if let Some(to) = range.to {
+                        bucket["to"] = json!(to);
+                    }
+
+                    bucket
+                }).collect()
+            }
+            GeoAggregation::Grid{grid, size, ..} => {
+                let mut doc_counts: HashMap<String, u64> = HashMap::new();
+
+                for points in documents {
+                    let mut cells = points.iter().map(|point| grid.cell(*point)).collect::<Vec<_>>();
+                    cells.sort();
+                    cells.dedup();
+
+                    for cell in cells {
+                        *doc_counts.entry(cell).or_insert(0) += 1;
+                    }
+                }
+
+                // Busiest cells first
+                let mut doc_counts = doc_counts.into_iter().collect::<Vec<_>>();
+                doc_counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+                doc_counts.truncate(size);
+
+                doc_counts.into_iter().map(|(cell, doc_count)| json!({"key": cell, "doc_count": doc_count})).collect()
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use search::query::geo_shape::Coordinate;
+
+    use super::{GeoAggregation, GeoGrid, DistanceRange, DistanceUnit, haversine_distance, geohash, geotile, stored_points};
+
+    const AMSTERDAM: Coordinate = (4.9, 52.37);
+    const ROTTERDAM: Coordinate = (4.48, 51.92);
+    const PARIS: Coordinate = (2.35, 48.86);
+
+    #[test]
+    fn test_haversine_distance() {
+        let distance = haversine_distance(AMSTERDAM, PARIS);
+        assert!((distance - 430000.0).abs() < 5000.0, "{}", distance);
+        assert_eq!(haversine_distance(PARIS, PARIS), 0.0);
+    }
+
+    #[test]
+    fn test_geohash() {
+        assert_eq!(geohash((-5.6, 42.6), 5), "ezs42");
+        assert_eq!(geohash(AMSTERDAM, 3), "u17");
+        assert_eq!(geohash(AMSTERDAM, 0), "");
+    }
+
+    #[test]
+    fn test_geotile() {
+        assert_eq!(geotile(AMSTERDAM, 0), "0/0/0");
+        assert_eq!(geotile(AMSTERDAM, 8), "8/131/84");
+        assert_eq!(geotile((180.0, -90.0), 2), "2/3/3");
+    }
+
+    #[test]
+    fn test_stored_points() {
+        assert_eq!(stored_points(&json!({"type": "point", "coordinates": [4.9, 52.37]})), vec![AMSTERDAM]);
+        assert_eq!(stored_points(&json!({"type": "multipoint", "coordinates": [[4.9, 52.37], [2.35, 48.86]]})), vec![AMSTERDAM, PARIS]);
+        assert_eq!(stored_points(&json!("foo")), vec![]);
+    }
+
+    #[test]
+    fn test_distance_aggregation() {
+        let aggregation = GeoAggregation::Distance {
+            field: "location".to_string(),
+            origin: AMSTERDAM,
+            unit: DistanceUnit::Kilometres,
+            ranges: vec![
+                DistanceRange { key: None, from: None, to: Some(100.0) },
+                DistanceRange { key: None, from: Some(100.0), to: Some(500.0) },
+                DistanceRange { key: Some("far".to_string()), from: Some(500.0), to: None },
+            ],
+        };
+
+        let documents: Vec<&[Coordinate]> = vec![&[AMSTERDAM], &[ROTTERDAM, PARIS], &[]];
+        assert_eq!(aggregation.collect(documents), vec![
+            json!({"key": "*-100.0", "to": 100.0, "doc_count": 2}),
+            json!({"key": "100.0-500.0", "from": 100.0, "to": 500.0, "doc_count": 1}),
+            json!({"key": "far", "from": 500.0, "doc_count": 0}),
+        ]);
+    }
+
+    #[test]
+    fn test_grid_aggregation() {
+        let aggregation = GeoAggregation::Grid {
+            field: "location".to_string(),
+            grid: GeoGrid::Geohash(2),
+            size: 10,
+        };
+
+        // A document with two points in the same cell is only counted once
+        let documents: Vec<&[Coordinate]> = vec![&[AMSTERDAM, ROTTERDAM], &[ROTTERDAM], &[PARIS]];
+        assert_eq!(aggregation.collect(documents.clone()), vec![
+            json!({"key": "u1", "doc_count": 2}),
+            json!({"key": "u0", "doc_count": 1}),
+        ]);
+
+        let aggregation = GeoAggregation::Grid {
+            field: "location".to_string(),
+            grid: GeoGrid::Geotile(4),
+            size: 1,
+        };
+
+        assert_eq!(aggregation.collect(documents), vec![
+            json!({"key": "4/8/5", "doc_count": 3}),
+        ]);
+    }
+}
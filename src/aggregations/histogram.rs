@@ -0,0 +1,98 @@
+//! Histogram aggregations
+//!
+//! Buckets documents by the values of a numeric field, with a bucket for each
+//! interval. Buckets are given in order of their keys, and the key of each
+//! bucket is the lowest value that falls into it.
+
+
+/// Empty buckets aren't filled in if there would be more than this many
+/// buckets, so a small interval over a wide range can't exhaust memory
+pub const MAX_FILLED_BUCKETS: i64 = 10000;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistogramAggregation {
+    pub field: String,
+    pub interval: f64,
+
+    /// Buckets with fewer documents are left out. If this is 0, empty buckets
+    /// are given for the gaps between buckets
+    pub min_doc_count: u64,
+
+    /// The minimum and maximum values that empty buckets are given for, when
+    /// min_doc_count is 0
+    pub extended_bounds: Option<(f64, f64)>,
+}
+
+
+impl HistogramAggregation {
+    /// Finds the bucket that a value falls into. Buckets are numbered from the
+    /// one that starts at 0
+    pub fn bucket_index(&self, value: f64) -> i64 {
+        (value / self.interval).floor() as i64
+    }
+
+    pub fn bucket_key(&self, index: i64) -> f64 {
+        index as f64 * self.interval
+    }
+
+    /// Finds the buckets to give when empty buckets are filled in, from the first
+    /// and last buckets that have documents. Returns None if empty buckets
+    /// shouldn't be given
+    pub fn filled_range(&self, collected: Option<(i64, i64)>) -> Option<(i64, i64)> {
+        if self.min_doc_count > 0 {
+            return None;
+        }
+
+        let bounds = self.extended_bounds.map(|(min, max)| (self.bucket_index(min), self.bucket_index(max)));
+        let (first, last) = match (collected, bounds) {
+            (Some((first, last)), Some((min, max))) => (first.min(min), last.max(max)),
+            (Some(range), None) | (None, Some(range)) => range,
+            (None, None) => return None,
+        };
+
+        if last - first >= MAX_FILLED_BUCKETS {
+            return None;
+        }
+
+        Some((first, last))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{HistogramAggregation, MAX_FILLED_BUCKETS};
+
+    fn histogram(interval: f64, min_doc_count: u64, extended_bounds: Option<(f64, f64)>) -> HistogramAggregation {
+        HistogramAggregation {
+            field: "price".to_string(),
+            interval: interval,
+            min_doc_count: min_doc_count,
+            extended_bounds: extended_bounds,
+        }
+    }
+
+    #[test]
+    fn test_bucket_index() {
+        let histogram = histogram(5.0, 0, None);
+
+        assert_eq!(histogram.bucket_index(0.0), 0);
+        assert_eq!(histogram.bucket_index(4.9), 0);
+        assert_eq!(histogram.bucket_index(5.0), 1);
+        assert_eq!(histogram.bucket_index(-0.1), -1);
+        assert_eq!(histogram.bucket_key(-1), -5.0);
+    }
+
+    #[test]
+    fn test_filled_range() {
+        assert_eq!(histogram(5.0, 0, None).filled_range(Some((1, 3))), Some((1, 3)));
+        assert_eq!(histogram(5.0, 1, None).filled_range(Some((1, 3))), None);
+
+        // Extended bounds can only widen the range
+        assert_eq!(histogram(5.0, 0, Some((0.0, 12.0))).filled_range(Some((1, 3))), Some((0, 3)));
+        assert_eq!(histogram(5.0, 0, Some((0.0, 12.0))).filled_range(None), Some((0, 2)));
+
+        assert_eq!(histogram(1.0, 0, None).filled_range(Some((0, MAX_FILLED_BUCKETS))), None);
+    }
+}
@@ -0,0 +1,22 @@
+//! Aggregations
+//!
+//! The aggregations of a search request are parsed into a tree (see `tree`).
+//! Collectors for each aggregation are run alongside the search, and render
+//! their results into the response once it has finished (see `collector`).
+//!
+//! Bucket aggregations group the matching documents by their values. Pipeline
+//! aggregations are run over the rendered buckets of their parent aggregation
+//! once collection has finished. Metric aggregations compute statistics over
+//! the values of fields.
+
+pub mod pipeline;
+pub mod geo;
+pub mod adjacency_matrix;
+pub mod stats;
+pub mod terms;
+pub mod histogram;
+pub mod date_histogram;
+pub mod nested;
+pub mod tree;
+pub mod collector;
+pub mod parse;
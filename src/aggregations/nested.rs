@@ -0,0 +1,20 @@
+//! Nested aggregations
+//!
+//! A nested aggregation runs its sub-aggregations over the objects of a nested
+//! field rather than over the documents that they're in, so facets over nested
+//! objects count each object once. A reverse nested aggregation goes back from
+//! the nested objects to the documents that they're in, counting each of those
+//! once.
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NestedAggregation {
+    pub path: String,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReverseNestedAggregation {
+    /// The nested path to go back to. The top level documents if there isn't one
+    pub path: Option<String>,
+}
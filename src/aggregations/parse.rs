@@ -0,0 +1,1163 @@
+use std::fmt;
+
+use chrono::{DateTime, FixedOffset};
+
+use serde_json;
+
+use search::query::geo_distance::DistanceUnit;
+use script::{Expression, parse_script, ScriptParseError};
+use mapping::parse_geo_point;
+use query_parser::{parse as parse_query, QueryParseError};
+use aggregations::pipeline::{PipelineAggregation, MovingFunction, GapPolicy};
+use aggregations::geo::{GeoAggregation, GeoGrid, DistanceRange, MAX_GEOHASH_PRECISION, MAX_GEOTILE_PRECISION};
+use aggregations::adjacency_matrix::{AdjacencyMatrixAggregation, AdjacencyFilter};
+use aggregations::stats::{Metric, MetricAggregation, ExtendedStatsAggregation, MatrixStatsAggregation};
+use aggregations::terms::{TermsAggregation, TermsOrder, TermsOrderKey};
+use aggregations::histogram::HistogramAggregation;
+use aggregations::date_histogram::{DateHistogramAggregation, DateInterval, CalendarUnit, parse_time_zone};
+use aggregations::nested::{NestedAggregation, ReverseNestedAggregation};
+use aggregations::tree::{Aggregations, Aggregation, AggregationType};
+
+
+#[derive(Debug, PartialEq)]
+pub enum AggregationParseError {
+    ExpectedObject,
+    ExpectedArray,
+    ExpectedString,
+    ExpectedNumber,
+    ExpectedPositiveInteger,
+    ExpectedKey(String),
+    UnrecognisedType(String),
+    InvalidGapPolicy(String),
+    InvalidModel(String),
+    UnrecognisedMovingFunction(String),
+    InvalidPoint,
+    InvalidUnit(String),
+    InvalidPrecision,
+    ScriptParseError(ScriptParseError),
+    QueryParseError(QueryParseError),
+
+    /// The named aggregation doesn't have exactly one type
+    ExpectedAggregationType(String),
+
+    /// The named aggregation can't have sub-aggregations
+    UnexpectedSubAggregations(String),
+
+    /// The named pipeline aggregation doesn't have a parent with buckets
+    UnexpectedPipeline(String),
+
+    /// The named reverse nested aggregation isn't inside a nested aggregation
+    UnexpectedReverseNested(String),
+
+    /// Buckets can't be ordered by this
+    InvalidOrder(String),
+
+    InvalidInterval(String),
+    InvalidTimeZone(String),
+    InvalidDate(String),
+}
+
+
+impl From<ScriptParseError> for AggregationParseError {
+    fn from(e: ScriptParseError) -> AggregationParseError {
+        AggregationParseError::ScriptParseError(e)
+    }
+}
+
+
+impl From<QueryParseError> for AggregationParseError {
+    fn from(e: QueryParseError) -> AggregationParseError {
+        AggregationParseError::QueryParseError(e)
+    }
+}
+
+
+impl fmt::Display for AggregationParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AggregationParseError::ExpectedObject => write!(f, "expected an object"),
+            AggregationParseError::ExpectedArray => write!(f, "expected an array"),
+            AggregationParseError::ExpectedString => write!(f, "expected a string"),
+            AggregationParseError::ExpectedNumber => write!(f, "expected a number"),
+            AggregationParseError::ExpectedPositiveInteger => write!(f, "expected a positive integer"),
+            AggregationParseError::ExpectedKey(ref key) => write!(f, "expected key {:?}", key),
+            AggregationParseError::UnrecognisedType(ref aggregation_type) => write!(f, "unrecognised aggregation type {:?}", aggregation_type),
+            AggregationParseError::InvalidGapPolicy(ref gap_policy) => write!(f, "invalid gap policy {:?}", gap_policy),
+            AggregationParseError::InvalidModel(ref model) => write!(f, "invalid model {:?}", model),
+            AggregationParseError::UnrecognisedMovingFunction(ref script) => write!(f, "unrecognised moving function {:?}", script),
+            AggregationParseError::InvalidPoint => write!(f, "invalid point"),
+            AggregationParseError::InvalidUnit(ref unit) => write!(f, "invalid unit {:?}", unit),
+            AggregationParseError::InvalidPrecision => write!(f, "invalid precision"),
+            AggregationParseError::ScriptParseError(ref error) => write!(f, "invalid script: {:?}", error),
+            AggregationParseError::QueryParseError(ref error) => write!(f, "invalid filter: {}", error),
+            AggregationParseError::ExpectedAggregationType(ref name) => write!(f, "aggregation {:?} must have exactly one type", name),
+            AggregationParseError::UnexpectedSubAggregations(ref name) => write!(f, "aggregation {:?} can't have sub-aggregations", name),
+            AggregationParseError::UnexpectedPipeline(ref name) => write!(f, "pipeline aggregation {:?} must be inside a bucket aggregation", name),
+            AggregationParseError::UnexpectedReverseNested(ref name) => write!(f, "reverse nested aggregation {:?} must be inside a nested aggregation", name),
+            AggregationParseError::InvalidOrder(ref order) => write!(f, "invalid order {:?}", order),
+            AggregationParseError::InvalidInterval(ref interval) => write!(f, "invalid interval {:?}", interval),
+            AggregationParseError::InvalidTimeZone(ref time_zone) => write!(f, "invalid time zone {:?}", time_zone),
+            AggregationParseError::InvalidDate(ref date) => write!(f, "invalid date {}", date),
+        }
+    }
+}
+
+
+fn parse_buckets_path(data: &serde_json::Map<String, serde_json::Value>) -> Result<String, AggregationParseError> {
+    let buckets_path_json = data.get("buckets_path").ok_or(AggregationParseError::ExpectedKey("buckets_path".to_string()))?;
+    Ok(buckets_path_json.as_str().ok_or(AggregationParseError::ExpectedString)?.to_string())
+}
+
+
+/// Parses a "buckets_path" that maps script variables to paths. A single path
+/// is given the variable name "_value"
+fn parse_buckets_path_map(data: &serde_json::Map<String, serde_json::Value>) -> Result<Vec<(String, String)>, AggregationParseError> {
+    let buckets_path_json = data.get("buckets_path").ok_or(AggregationParseError::ExpectedKey("buckets_path".to_string()))?;
+
+    match *buckets_path_json {
+        serde_json::Value::String(ref path) => Ok(vec![("_value".to_string(), path.clone())]),
+        serde_json::Value::Object(ref paths) => {
+            let mut buckets_path = Vec::new();
+            for (variable, path_json) in paths.iter() {
+                let path = path_json.as_str().ok_or(AggregationParseError::ExpectedString)?;
+                buckets_path.push((variable.clone(), path.to_string()));
+            }
+
+            Ok(buckets_path)
+        }
+        _ => Err(AggregationParseError::ExpectedObject),
+    }
+}
+
+
+fn parse_gap_policy(data: &serde_json::Map<String, serde_json::Value>) -> Result<GapPolicy, AggregationParseError> {
+    match data.get("gap_policy") {
+        Some(gap_policy_json) => {
+            let gap_policy = gap_policy_json.as_str().ok_or(AggregationParseError::ExpectedString)?;
+
+            match gap_policy {
+                "skip" => Ok(GapPolicy::Skip),
+                "insert_zeros" => Ok(GapPolicy::InsertZeros),
+                "keep_values" => Ok(GapPolicy::KeepValues),
+                _ => Err(AggregationParseError::InvalidGapPolicy(gap_policy.to_string())),
+            }
+        }
+        None => Ok(GapPolicy::Skip),
+    }
+}
+
+
+fn parse_window(data: &serde_json::Map<String, serde_json::Value>, default: Option<usize>) -> Result<usize, AggregationParseError> {
+    match data.get("window") {
+        Some(window_json) => {
+            match window_json.as_u64() {
+                Some(window) if window > 0 => Ok(window as usize),
+                _ => Err(AggregationParseError::ExpectedPositiveInteger),
+            }
+        }
+        None => default.ok_or(AggregationParseError::ExpectedKey("window".to_string())),
+    }
+}
+
+
+/// Parses the script of a "moving_fn" aggregation, which must call one of the
+/// built in functions on the window, eg "MovingFunctions.max(values)"
+fn parse_moving_function_script(script: &str) -> Result<MovingFunction, AggregationParseError> {
+    let unrecognised = || AggregationParseError::UnrecognisedMovingFunction(script.to_string());
+
+    let call = script.trim().trim_end_matches(';').trim_end();
+    let call = if call.starts_with("MovingFunctions.") { &call["MovingFunctions.".len()..] } else { call };
+
+    let open = call.find('(').ok_or_else(unrecognised)?;
+    if !call.ends_with(')') {
+        return Err(unrecognised());
+    }
+
+    let name = call[..open].trim();
+    let mut arguments = call[open + 1..call.len() - 1].split(',').map(|argument| argument.trim());
+
+    if arguments.next() != Some("values") {
+        return Err(unrecognised());
+    }
+
+    let function = match name {
+        "max" => MovingFunction::Max,
+        "min" => MovingFunction::Min,
+        "sum" => MovingFunction::Sum,
+        "unweightedAvg" => MovingFunction::UnweightedAvg,
+        "linearWeightedAvg" => MovingFunction::LinearWeightedAvg,
+        "stdDev" => MovingFunction::StdDev,
+        "ewma" => {
+            let alpha = arguments.next().and_then(|alpha| alpha.parse::<f64>().ok()).ok_or_else(unrecognised)?;
+            MovingFunction::Ewma(alpha)
+        }
+        _ => return Err(unrecognised()),
+    };
+
+    if arguments.next().is_some() {
+        return Err(unrecognised());
+    }
+
+    Ok(function)
+}
+
+
+fn parse_moving_fn(data: &serde_json::Map<String, serde_json::Value>) -> Result<PipelineAggregation, AggregationParseError> {
+    let script_json = data.get("script").ok_or(AggregationParseError::ExpectedKey("script".to_string()))?;
+    let script = script_json.as_str().ok_or(AggregationParseError::ExpectedString)?;
+
+    let shift = match data.get("shift") {
+        Some(shift_json) => shift_json.as_i64().ok_or(AggregationParseError::ExpectedNumber)?,
+        None => 0,
+    };
+
+    Ok(PipelineAggregation::MovingFunction {
+        buckets_path: parse_buckets_path(data)?,
+        window: parse_window(data, None)?,
+        shift: shift,
+        function: parse_moving_function_script(script)?,
+        gap_policy: parse_gap_policy(data)?,
+    })
+}
+
+
+/// Parses the older "moving_avg" aggregation, which picks its function with a
+/// "model" instead of a script
+fn parse_moving_avg(data: &serde_json::Map<String, serde_json::Value>) -> Result<PipelineAggregation, AggregationParseError> {
+    let function = match data.get("model") {
+        Some(model_json) => {
+            let model = model_json.as_str().ok_or(AggregationParseError::ExpectedString)?;
+
+            match model {
+                "simple" => MovingFunction::UnweightedAvg,
+                "linear" => MovingFunction::LinearWeightedAvg,
+                "ewma" => {
+                    let alpha = match data.get("settings").and_then(|settings| settings.get("alpha")) {
+                        Some(alpha_json) => alpha_json.as_f64().ok_or(AggregationParseError::ExpectedNumber)?,
+                        None => 0.3,
+                    };
+
+                    MovingFunction::Ewma(alpha)
+                }
+                _ => return Err(AggregationParseError::InvalidModel(model.to_string())),
+            }
+        }
+        None => MovingFunction::UnweightedAvg,
+    };
+
+    Ok(PipelineAggregation::MovingFunction {
+        buckets_path: parse_buckets_path(data)?,
+        window: parse_window(data, Some(5))?,
+        shift: 0,
+        function: function,
+        gap_policy: parse_gap_policy(data)?,
+    })
+}
+
+
+fn parse_script_key(data: &serde_json::Map<String, serde_json::Value>) -> Result<Expression, AggregationParseError> {
+    let script_json = data.get("script").ok_or(AggregationParseError::ExpectedKey("script".to_string()))?;
+    Ok(parse_script(script_json)?)
+}
+
+
+/// Parses a pipeline aggregation of the given type, eg "derivative"
+pub fn parse_pipeline_aggregation(aggregation_type: &str, json: &serde_json::Value) -> Result<PipelineAggregation, AggregationParseError> {
+    let data = json.as_object().ok_or(AggregationParseError::ExpectedObject)?;
+
+    match aggregation_type {
+        "derivative" => {
+            Ok(PipelineAggregation::Derivative {
+                buckets_path: parse_buckets_path(data)?,
+                gap_policy: parse_gap_policy(data)?,
+            })
+        }
+        "cumulative_sum" => {
+            Ok(PipelineAggregation::CumulativeSum {
+                buckets_path: parse_buckets_path(data)?,
+            })
+        }
+        "moving_fn" => parse_moving_fn(data),
+        "moving_avg" => parse_moving_avg(data),
+        "bucket_script" => {
+            Ok(PipelineAggregation::BucketScript {
+                buckets_path: parse_buckets_path_map(data)?,
+                script: parse_script_key(data)?,
+                gap_policy: parse_gap_policy(data)?,
+            })
+        }
+        "bucket_selector" => {
+            Ok(PipelineAggregation::BucketSelector {
+                buckets_path: parse_buckets_path_map(data)?,
+                script: parse_script_key(data)?,
+                gap_policy: parse_gap_policy(data)?,
+            })
+        }
+        _ => Err(AggregationParseError::UnrecognisedType(aggregation_type.to_string())),
+    }
+}
+
+
+fn parse_field(data: &serde_json::Map<String, serde_json::Value>) -> Result<String, AggregationParseError> {
+    let field_json = data.get("field").ok_or(AggregationParseError::ExpectedKey("field".to_string()))?;
+    Ok(field_json.as_str().ok_or(AggregationParseError::ExpectedString)?.to_string())
+}
+
+
+fn parse_distance_range(json: &serde_json::Value) -> Result<DistanceRange, AggregationParseError> {
+    let data = json.as_object().ok_or(AggregationParseError::ExpectedObject)?;
+
+    let parse_bound = |name| {
+        match data.get(name) {
+            Some(bound_json) => bound_json.as_f64().map(Some).ok_or(AggregationParseError::ExpectedNumber),
+            None => Ok(None),
+        }
+    };
+
+    let key = match data.get("key") {
+        Some(key_json) => Some(key_json.as_str().ok_or(AggregationParseError::ExpectedString)?.to_string()),
+        None => None,
+    };
+
+    Ok(DistanceRange {
+        key: key,
+        from: parse_bound("from")?,
+        to: parse_bound("to")?,
+    })
+}
+
+
+fn parse_geo_distance(data: &serde_json::Map<String, serde_json::Value>) -> Result<GeoAggregation, AggregationParseError> {
+    let origin_json = data.get("origin").ok_or(AggregationParseError::ExpectedKey("origin".to_string()))?;
+    let origin = parse_geo_point(origin_json).ok_or(AggregationParseError::InvalidPoint)?;
+
+    let unit = match data.get("unit") {
+        Some(unit_json) => {
+            let unit = unit_json.as_str().ok_or(AggregationParseError::ExpectedString)?;
+            DistanceUnit::from_str(unit).ok_or_else(|| AggregationParseError::InvalidUnit(unit.to_string()))?
+        }
+        None => DistanceUnit::default(),
+    };
+
+    let ranges_json = data.get("ranges").ok_or(AggregationParseError::ExpectedKey("ranges".to_string()))?;
+    let ranges = ranges_json.as_array().ok_or(AggregationParseError::ExpectedArray)?
+        .iter().map(parse_distance_range).collect::<Result<Vec<_>, _>>()?;
+
+    Ok(GeoAggregation::Distance {
+        field: parse_field(data)?,
+        origin: origin,
+        unit: unit,
+        ranges: ranges,
+    })
+}
+
+
+fn parse_geo_grid<F: Fn(u32) -> GeoGrid>(data: &serde_json::Map<String, serde_json::Value>, default_precision: u32, max_precision: u32, grid: F) -> Result<GeoAggregation, AggregationParseError> {
+    let precision = match data.get("precision") {
+        Some(precision_json) => {
+            match precision_json.as_u64() {
+                Some(precision) if precision <= max_precision as u64 => precision as u32,
+                _ => return Err(AggregationParseError::InvalidPrecision),
+            }
+        }
+        None => default_precision,
+    };
+
+    let size = match data.get("size") {
+        Some(size_json) => {
+            match size_json.as_u64() {
+                Some(size) if size > 0 => size as usize,
+                _ => return Err(AggregationParseError::ExpectedPositiveInteger),
+            }
+        }
+        None => 10000,
+    };
+
+    Ok(GeoAggregation::Grid {
+        field: parse_field(data)?,
+        grid: grid(precision),
+        size: size,
+    })
+}
+
+
+/// Parses a geo bucket aggregation of the given type, eg "geohash_grid"
+pub fn parse_geo_aggregation(aggregation_type: &str, json: &serde_json::Value) -> Result<GeoAggregation, AggregationParseError> {
+    let data = json.as_object().ok_or(AggregationParseError::ExpectedObject)?;
+
+    match aggregation_type {
+        "geo_distance" => parse_geo_distance(data),
+        "geohash_grid" => {
+            // Geohashes have at least one character
+            if data.get("precision").and_then(|precision| precision.as_u64()) == Some(0) {
+                return Err(AggregationParseError::InvalidPrecision);
+            }
+
+            parse_geo_grid(data, 5, MAX_GEOHASH_PRECISION, GeoGrid::Geohash)
+        }
+        "geotile_grid" => parse_geo_grid(data, 7, MAX_GEOTILE_PRECISION, GeoGrid::Geotile),
+        _ => Err(AggregationParseError::UnrecognisedType(aggregation_type.to_string())),
+    }
+}
+
+
+pub fn parse_adjacency_matrix(json: &serde_json::Value) -> Result<AdjacencyMatrixAggregation, AggregationParseError> {
+    let data = json.as_object().ok_or(AggregationParseError::ExpectedObject)?;
+
+    let filters_json = data.get("filters").ok_or(AggregationParseError::ExpectedKey("filters".to_string()))?;
+    let mut filters = Vec::new();
+    for (name, filter_json) in filters_json.as_object().ok_or(AggregationParseError::ExpectedObject)?.iter() {
+        filters.push((name.clone(), AdjacencyFilter::new(parse_query(filter_json)?)));
+    }
+
+    // Keys of pairs are ordered by the names of the filters
+    filters.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let separator = match data.get("separator") {
+        Some(separator_json) => separator_json.as_str().ok_or(AggregationParseError::ExpectedString)?.to_string(),
+        None => "&".to_string(),
+    };
+
+    Ok(AdjacencyMatrixAggregation {
+        filters: filters,
+        separator: separator,
+    })
+}
+
+
+pub fn parse_metric(metric: Metric, json: &serde_json::Value) -> Result<MetricAggregation, AggregationParseError> {
+    let data = json.as_object().ok_or(AggregationParseError::ExpectedObject)?;
+
+    Ok(MetricAggregation {
+        metric: metric,
+        field: parse_field(data)?,
+    })
+}
+
+
+pub fn parse_extended_stats(json: &serde_json::Value) -> Result<ExtendedStatsAggregation, AggregationParseError> {
+    let data = json.as_object().ok_or(AggregationParseError::ExpectedObject)?;
+
+    let sigma = match data.get("sigma") {
+        Some(sigma_json) => {
+            match sigma_json.as_f64() {
+                Some(sigma) if sigma >= 0.0 => sigma,
+                _ => return Err(AggregationParseError::ExpectedNumber),
+            }
+        }
+        None => 2.0,
+    };
+
+    Ok(ExtendedStatsAggregation {
+        field: parse_field(data)?,
+        sigma: sigma,
+    })
+}
+
+
+pub fn parse_matrix_stats(json: &serde_json::Value) -> Result<MatrixStatsAggregation, AggregationParseError> {
+    let data = json.as_object().ok_or(AggregationParseError::ExpectedObject)?;
+
+    let fields_json = data.get("fields").ok_or(AggregationParseError::ExpectedKey("fields".to_string()))?;
+    let fields = fields_json.as_array().ok_or(AggregationParseError::ExpectedArray)?
+        .iter()
+        .map(|field_json| field_json.as_str().map(|field| field.to_string()).ok_or(AggregationParseError::ExpectedString))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(MatrixStatsAggregation {
+        fields: fields,
+    })
+}
+
+
+fn parse_terms_order(json: &serde_json::Value) -> Result<TermsOrder, AggregationParseError> {
+    let data = json.as_object().ok_or(AggregationParseError::ExpectedObject)?;
+    if data.len() != 1 {
+        return Err(AggregationParseError::InvalidOrder(json.to_string()));
+    }
+
+    let (key, direction_json) = data.iter().next().unwrap();
+    let reverse = match direction_json.as_str() {
+        Some("asc") => false,
+        Some("desc") => true,
+        _ => return Err(AggregationParseError::InvalidOrder(json.to_string())),
+    };
+
+    let key = match key.as_str() {
+        "_count" => TermsOrderKey::Count,
+        "_key" | "_term" => TermsOrderKey::Key,
+        path => TermsOrderKey::SubAggregation(path.to_string()),
+    };
+
+    Ok(TermsOrder {
+        key: key,
+        reverse: reverse,
+    })
+}
+
+
+pub fn parse_terms(json: &serde_json::Value) -> Result<TermsAggregation, AggregationParseError> {
+    let data = json.as_object().ok_or(AggregationParseError::ExpectedObject)?;
+
+    let size = match data.get("size") {
+        Some(size_json) => {
+            match size_json.as_u64() {
+                Some(size) if size > 0 => size as usize,
+                _ => return Err(AggregationParseError::ExpectedPositiveInteger),
+            }
+        }
+        None => 10,
+    };
+
+    // Orders can be given on their own, or as a list that's compared in turn
+    let order = match data.get("order") {
+        Some(&serde_json::Value::Array(ref orders)) => orders.iter().map(parse_terms_order).collect::<Result<Vec<_>, _>>()?,
+        Some(order_json) => vec![parse_terms_order(order_json)?],
+        None => TermsAggregation::default_order(),
+    };
+
+    let min_doc_count = match data.get("min_doc_count") {
+        Some(min_doc_count_json) => min_doc_count_json.as_u64().ok_or(AggregationParseError::ExpectedPositiveInteger)?,
+        None => 1,
+    };
+
+    Ok(TermsAggregation {
+        field: parse_field(data)?,
+        size: size,
+        order: order,
+        min_doc_count: min_doc_count,
+    })
+}
+
+
+/// Checks that the sub-aggregations that buckets are ordered by exist
+fn check_terms_order(terms: &TermsAggregation, sub_aggregations: &Aggregations) -> Result<(), AggregationParseError> {
+    for order in terms.order.iter() {
+        if let TermsOrderKey::SubAggregation(ref path) = order.key {
+            let name = path.split(|c| c == '>' || c == '.').next().unwrap_or("");
+
+            if !sub_aggregations.aggregations.iter().any(|aggregation| aggregation.name == name) {
+                return Err(AggregationParseError::InvalidOrder(path.clone()));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+
+pub fn parse_histogram(json: &serde_json::Value) -> Result<HistogramAggregation, AggregationParseError> {
+    let data = json.as_object().ok_or(AggregationParseError::ExpectedObject)?;
+
+    let interval_json = data.get("interval").ok_or(AggregationParseError::ExpectedKey("interval".to_string()))?;
+    let interval = match interval_json.as_f64() {
+        Some(interval) if interval > 0.0 => interval,
+        _ => return Err(AggregationParseError::ExpectedNumber),
+    };
+
+    let min_doc_count = match data.get("min_doc_count") {
+        Some(min_doc_count_json) => min_doc_count_json.as_u64().ok_or(AggregationParseError::ExpectedPositiveInteger)?,
+        None => 0,
+    };
+
+    let extended_bounds = match data.get("extended_bounds") {
+        Some(extended_bounds_json) => {
+            let extended_bounds = extended_bounds_json.as_object().ok_or(AggregationParseError::ExpectedObject)?;
+            let parse_bound = |name: &str| {
+                let bound_json = extended_bounds.get(name).ok_or(AggregationParseError::ExpectedKey(name.to_string()))?;
+                bound_json.as_f64().ok_or(AggregationParseError::ExpectedNumber)
+            };
+
+            let (min, max) = (parse_bound("min")?, parse_bound("max")?);
+            if min > max {
+                return Err(AggregationParseError::ExpectedNumber);
+            }
+
+            Some((min, max))
+        }
+        None => None,
+    };
+
+    Ok(HistogramAggregation {
+        field: parse_field(data)?,
+        interval: interval,
+        min_doc_count: min_doc_count,
+        extended_bounds: extended_bounds,
+    })
+}
+
+
+/// Reads a date, given in milliseconds since the epoch or as an RFC 3339 string
+fn parse_date_millis(json: &serde_json::Value) -> Result<i64, AggregationParseError> {
+    match *json {
+        serde_json::Value::Number(ref millis) => millis.as_i64().ok_or_else(|| AggregationParseError::InvalidDate(json.to_string())),
+        serde_json::Value::String(ref date) => {
+            match DateTime::parse_from_rfc3339(date) {
+                Ok(date) => Ok(date.timestamp() * 1000 + date.timestamp_subsec_millis() as i64),
+                Err(_) => Err(AggregationParseError::InvalidDate(json.to_string())),
+            }
+        }
+        _ => Err(AggregationParseError::InvalidDate(json.to_string())),
+    }
+}
+
+
+pub fn parse_date_histogram(json: &serde_json::Value) -> Result<DateHistogramAggregation, AggregationParseError> {
+    let data = json.as_object().ok_or(AggregationParseError::ExpectedObject)?;
+
+    // "interval" can be either kind, calendar units are checked first
+    let interval = match (data.get("calendar_interval"), data.get("fixed_interval"), data.get("interval")) {
+        (Some(interval_json), None, None) => {
+            let interval = interval_json.as_str().ok_or(AggregationParseError::ExpectedString)?;
+            CalendarUnit::parse(interval).map(DateInterval::Calendar).ok_or_else(|| AggregationParseError::InvalidInterval(interval.to_string()))?
+        }
+        (None, Some(interval_json), None) => {
+            let interval = interval_json.as_str().ok_or(AggregationParseError::ExpectedString)?;
+            DateInterval::parse_fixed(interval).ok_or_else(|| AggregationParseError::InvalidInterval(interval.to_string()))?
+        }
+        (None, None, Some(interval_json)) => {
+            let interval = interval_json.as_str().ok_or(AggregationParseError::ExpectedString)?;
+            CalendarUnit::parse(interval).map(DateInterval::Calendar)
+                .or_else(|| DateInterval::parse_fixed(interval))
+                .ok_or_else(|| AggregationParseError::InvalidInterval(interval.to_string()))?
+        }
+        (None, None, None) => return Err(AggregationParseError::ExpectedKey("calendar_interval".to_string())),
+        _ => return Err(AggregationParseError::InvalidInterval("only one interval can be given".to_string())),
+    };
+
+    let time_zone = match data.get("time_zone") {
+        Some(time_zone_json) => {
+            let time_zone = time_zone_json.as_str().ok_or(AggregationParseError::ExpectedString)?;
+            parse_time_zone(time_zone).ok_or_else(|| AggregationParseError::InvalidTimeZone(time_zone.to_string()))?
+        }
+        None => FixedOffset::east(0),
+    };
+
+    let min_doc_count = match data.get("min_doc_count") {
+        Some(min_doc_count_json) => min_doc_count_json.as_u64().ok_or(AggregationParseError::ExpectedPositiveInteger)?,
+        None => 0,
+    };
+
+    let extended_bounds = match data.get("extended_bounds") {
+        Some(extended_bounds_json) => {
+            let extended_bounds = extended_bounds_json.as_object().ok_or(AggregationParseError::ExpectedObject)?;
+            let parse_bound = |name: &str| {
+                let bound_json = extended_bounds.get(name).ok_or(AggregationParseError::ExpectedKey(name.to_string()))?;
+                parse_date_millis(bound_json)
+            };
+
+            let (min, max) = (parse_bound("min")?, parse_bound("max")?);
+            if min > max {
+                return Err(AggregationParseError::InvalidDate(extended_bounds_json.to_string()));
+            }
+
+            Some((min, max))
+        }
+        None => None,
+    };
+
+    Ok(DateHistogramAggregation {
+        field: parse_field(data)?,
+        interval: interval,
+        time_zone: time_zone,
+        min_doc_count: min_doc_count,
+        extended_bounds: extended_bounds,
+    })
+}
+
+
+pub fn parse_nested(json: &serde_json::Value) -> Result<NestedAggregation, AggregationParseError> {
+    let data = json.as_object().ok_or(AggregationParseError::ExpectedObject)?;
+    let path = data.get("path").ok_or(AggregationParseError::ExpectedKey("path".to_string()))?;
+
+    Ok(NestedAggregation {
+        path: path.as_str().ok_or(AggregationParseError::ExpectedString)?.to_string(),
+    })
+}
+
+
+pub fn parse_reverse_nested(json: &serde_json::Value) -> Result<ReverseNestedAggregation, AggregationParseError> {
+    let data = json.as_object().ok_or(AggregationParseError::ExpectedObject)?;
+
+    let path = match data.get("path") {
+        Some(path) => Some(path.as_str().ok_or(AggregationParseError::ExpectedString)?.to_string()),
+        None => None,
+    };
+
+    Ok(ReverseNestedAggregation {
+        path: path,
+    })
+}
+
+
+fn is_pipeline_type(aggregation_type: &str) -> bool {
+    match aggregation_type {
+        "derivative" | "cumulative_sum" | "moving_fn" | "moving_avg" | "bucket_script" | "bucket_selector" => true,
+        _ => false,
+    }
+}
+
+
+fn parse_aggregation_type(aggregation_type: &str, json: &serde_json::Value) -> Result<AggregationType, AggregationParseError> {
+    match aggregation_type {
+        "min" => Ok(AggregationType::Metric(parse_metric(Metric::Min, json)?)),
+        "max" => Ok(AggregationType::Metric(parse_metric(Metric::Max, json)?)),
+        "avg" => Ok(AggregationType::Metric(parse_metric(Metric::Avg, json)?)),
+        "sum" => Ok(AggregationType::Metric(parse_metric(Metric::Sum, json)?)),
+        "stats" => Ok(AggregationType::Metric(parse_metric(Metric::Stats, json)?)),
+        "extended_stats" => Ok(AggregationType::ExtendedStats(parse_extended_stats(json)?)),
+        "matrix_stats" => Ok(AggregationType::MatrixStats(parse_matrix_stats(json)?)),
+        "geo_distance" | "geohash_grid" | "geotile_grid" => Ok(AggregationType::Geo(parse_geo_aggregation(aggregation_type, json)?)),
+        "terms" => Ok(AggregationType::Terms(parse_terms(json)?)),
+        "histogram" => Ok(AggregationType::Histogram(parse_histogram(json)?)),
+        "date_histogram" => Ok(AggregationType::DateHistogram(parse_date_histogram(json)?)),
+        "adjacency_matrix" => Ok(AggregationType::AdjacencyMatrix(parse_adjacency_matrix(json)?)),
+        "nested" => Ok(AggregationType::Nested(parse_nested(json)?)),
+        "reverse_nested" => Ok(AggregationType::ReverseNested(parse_reverse_nested(json)?)),
+        _ => Err(AggregationParseError::UnrecognisedType(aggregation_type.to_string())),
+    }
+}
+
+
+/// Parses the aggregations inside an aggregation, or at the top level of the
+/// request if there's no parent. `in_nested` is set inside nested aggregations
+fn parse_aggregations_level(json: &serde_json::Value, parent: Option<&AggregationType>, in_nested: bool) -> Result<Aggregations, AggregationParseError> {
+    let mut aggregations = Aggregations::new();
+
+    for (name, aggregation_json) in json.as_object().ok_or(AggregationParseError::ExpectedObject)?.iter() {
+        let data = aggregation_json.as_object().ok_or(AggregationParseError::ExpectedObject)?;
+
+        // Everything other than the sub-aggregations is the type
+        let mut aggregation_type = None;
+        let mut sub_aggregations_json = None;
+        for (key, value) in data.iter() {
+            match key.as_str() {
+                "aggs" | "aggregations" if sub_aggregations_json.is_none() => sub_aggregations_json = Some(value),
+                _ if aggregation_type.is_none() => aggregation_type = Some((key.as_str(), value)),
+                _ => return Err(AggregationParseError::ExpectedAggregationType(name.clone())),
+            }
+        }
+
+        let (aggregation_type, type_json) = aggregation_type.ok_or_else(|| AggregationParseError::ExpectedAggregationType(name.clone()))?;
+
+        if is_pipeline_type(aggregation_type) {
+            if !parent.map_or(false, |parent| parent.has_buckets()) {
+                return Err(AggregationParseError::UnexpectedPipeline(name.clone()));
+            }
+
+            if sub_aggregations_json.is_some() {
+                return Err(AggregationParseError::UnexpectedSubAggregations(name.clone()));
+            }
+
+            aggregations.pipelines.push((name.clone(), parse_pipeline_aggregation(aggregation_type, type_json)?));
+            continue;
+        }
+
+        let aggregation_type = parse_aggregation_type(aggregation_type, type_json)?;
+
+        // Going back to the top level goes out of all nested aggregations, going
+        // back to a nested path stays inside one
+        let in_nested = match aggregation_type {
+            AggregationType::Nested(_) => true,
+            AggregationType::ReverseNested(ref reverse_nested) => {
+                if !in_nested {
+                    return Err(AggregationParseError::UnexpectedReverseNested(name.clone()));
+                }
+
+                reverse_nested.path.is_some()
+            }
+            _ => in_nested,
+        };
+
+        let sub_aggregations = match sub_aggregations_json {
+            Some(sub_aggregations_json) => parse_aggregations_level(sub_aggregations_json, Some(&aggregation_type), in_nested)?,
+            None => Aggregations::new(),
+        };
+
+        // Geo buckets are counted all at once, so they can only have pipelines
+        let sub_aggregations_allowed = match aggregation_type {
+            AggregationType::Geo(_) => sub_aggregations.aggregations.is_empty(),
+            AggregationType::Terms(ref terms) => {
+                check_terms_order(terms, &sub_aggregations)?;
+                true
+            }
+            AggregationType::Histogram(_) | AggregationType::DateHistogram(_) | AggregationType::AdjacencyMatrix(_) => true,
+            AggregationType::Nested(_) | AggregationType::ReverseNested(_) => true,
+            _ => sub_aggregations.is_empty(),
+        };
+
+        if !sub_aggregations_allowed {
+            return Err(AggregationParseError::UnexpectedSubAggregations(name.clone()));
+        }
+
+        aggregations.aggregations.push(Aggregation {
+            name: name.clone(),
+            aggregation_type: aggregation_type,
+            sub_aggregations: sub_aggregations,
+        });
+    }
+
+    Ok(aggregations)
+}
+
+
+/// Parses the "aggs" (or "aggregations") section of a search request into a
+/// tree of aggregations
+pub fn parse_aggregations(json: &serde_json::Value) -> Result<Aggregations, AggregationParseError> {
+    parse_aggregations_level(json, None, false)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::FixedOffset;
+
+    use script::Expression;
+    use aggregations::pipeline::{PipelineAggregation, MovingFunction, GapPolicy};
+    use search::query::geo_distance::DistanceUnit;
+    use aggregations::geo::{GeoAggregation, GeoGrid, DistanceRange};
+    use aggregations::stats::{Metric, MetricAggregation, ExtendedStatsAggregation, MatrixStatsAggregation};
+
+    use query_parser::QueryParseError;
+
+    use aggregations::terms::{TermsAggregation, TermsOrder, TermsOrderKey};
+    use aggregations::histogram::HistogramAggregation;
+    use aggregations::date_histogram::{DateHistogramAggregation, DateInterval, CalendarUnit};
+    use aggregations::nested::{NestedAggregation, ReverseNestedAggregation};
+    use aggregations::tree::{Aggregations, Aggregation, AggregationType};
+
+    use super::{parse_pipeline_aggregation, parse_geo_aggregation, parse_adjacency_matrix, parse_metric, parse_extended_stats, parse_matrix_stats, parse_terms, parse_histogram, parse_date_histogram, parse_aggregations, AggregationParseError};
+
+    #[test]
+    fn test_parse_derivative() {
+        assert_eq!(parse_pipeline_aggregation("derivative", &json!({"buckets_path": "sales", "gap_policy": "insert_zeros"})), Ok(PipelineAggregation::Derivative {
+            buckets_path: "sales".to_string(),
+            gap_policy: GapPolicy::InsertZeros,
+        }));
+
+        assert_eq!(parse_pipeline_aggregation("derivative", &json!({"buckets_path": "sales", "gap_policy": "foo"})), Err(AggregationParseError::InvalidGapPolicy("foo".to_string())));
+        assert_eq!(parse_pipeline_aggregation("derivative", &json!({})), Err(AggregationParseError::ExpectedKey("buckets_path".to_string())));
+    }
+
+    #[test]
+    fn test_parse_moving_fn() {
+        assert_eq!(parse_pipeline_aggregation("moving_fn", &json!({"buckets_path": "sales", "window": 10, "script": "MovingFunctions.ewma(values, 0.5)"})), Ok(PipelineAggregation::MovingFunction {
+            buckets_path: "sales".to_string(),
+            window: 10,
+            shift: 0,
+            function: MovingFunction::Ewma(0.5),
+            gap_policy: GapPolicy::Skip,
+        }));
+
+        assert_eq!(parse_pipeline_aggregation("moving_fn", &json!({"buckets_path": "sales", "window": 10, "script": "values[0]"})), Err(AggregationParseError::UnrecognisedMovingFunction("values[0]".to_string())));
+        assert_eq!(parse_pipeline_aggregation("moving_fn", &json!({"buckets_path": "sales", "window": 0, "script": "MovingFunctions.max(values)"})), Err(AggregationParseError::ExpectedPositiveInteger));
+    }
+
+    #[test]
+    fn test_parse_moving_avg() {
+        assert_eq!(parse_pipeline_aggregation("moving_avg", &json!({"buckets_path": "sales", "model": "linear"})), Ok(PipelineAggregation::MovingFunction {
+            buckets_path: "sales".to_string(),
+            window: 5,
+            shift: 0,
+            function: MovingFunction::LinearWeightedAvg,
+            gap_policy: GapPolicy::Skip,
+        }));
+    }
+
+    #[test]
+    fn test_parse_bucket_script() {
+        assert_eq!(parse_pipeline_aggregation("bucket_script", &json!({"buckets_path": {"sales": "sales", "count": "_count"}, "script": "params.sales / params.count"})), Ok(PipelineAggregation::BucketScript {
+            buckets_path: vec![("count".to_string(), "_count".to_string()), ("sales".to_string(), "sales".to_string())],
+            script: Expression::parse("params.sales / params.count").unwrap(),
+            gap_policy: GapPolicy::Skip,
+        }));
+
+        assert_eq!(parse_pipeline_aggregation("bucket_sort", &json!({})), Err(AggregationParseError::UnrecognisedType("bucket_sort".to_string())));
+    }
+
+    #[test]
+    fn test_parse_geo_distance() {
+        assert_eq!(parse_geo_aggregation("geo_distance", &json!({
+            "field": "location",
+            "origin": "52.37, 4.9",
+            "unit": "km",
+            "ranges": [{"to": 100}, {"from": 100, "key": "far"}]
+        })), Ok(GeoAggregation::Distance {
+            field: "location".to_string(),
+            origin: (4.9, 52.37),
+            unit: DistanceUnit::Kilometres,
+            ranges: vec![
+                DistanceRange { key: None, from: None, to: Some(100.0) },
+                DistanceRange { key: Some("far".to_string()), from: Some(100.0), to: None },
+            ],
+        }));
+
+        assert_eq!(parse_geo_aggregation("geo_distance", &json!({"field": "location", "origin": "foo", "ranges": []})), Err(AggregationParseError::InvalidPoint));
+        assert_eq!(parse_geo_aggregation("geo_distance", &json!({"field": "location", "origin": [0, 0], "unit": "furlongs", "ranges": []})), Err(AggregationParseError::InvalidUnit("furlongs".to_string())));
+    }
+
+    #[test]
+    fn test_parse_geo_grid() {
+        assert_eq!(parse_geo_aggregation("geohash_grid", &json!({"field": "location", "precision": 3})), Ok(GeoAggregation::Grid {
+            field: "location".to_string(),
+            grid: GeoGrid::Geohash(3),
+            size: 10000,
+        }));
+
+        assert_eq!(parse_geo_aggregation("geotile_grid", &json!({"field": "location", "size": 5})), Ok(GeoAggregation::Grid {
+            field: "location".to_string(),
+            grid: GeoGrid::Geotile(7),
+            size: 5,
+        }));
+
+        assert_eq!(parse_geo_aggregation("geohash_grid", &json!({"field": "location", "precision": 13})), Err(AggregationParseError::InvalidPrecision));
+        assert_eq!(parse_geo_aggregation("geohash_grid", &json!({"field": "location", "precision": 0})), Err(AggregationParseError::InvalidPrecision));
+        assert_eq!(parse_geo_aggregation("geotile_grid", &json!({"precision": 0})), Err(AggregationParseError::ExpectedKey("field".to_string())));
+    }
+
+    #[test]
+    fn test_parse_adjacency_matrix() {
+        let aggregation = parse_adjacency_matrix(&json!({
+            "filters": {
+                "grpB": {"terms": {"accounts": ["c", "d"]}},
+                "grpA": {"terms": {"accounts": ["a", "b"]}}
+            },
+            "separator": "|"
+        })).unwrap();
+
+        let names = aggregation.filters.iter().map(|&(ref name, _)| name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["grpA", "grpB"]);
+        assert_eq!(aggregation.separator, "|");
+
+        assert_eq!(parse_adjacency_matrix(&json!({"filters": {"grpA": {"foo": {}}}})).err(), Some(AggregationParseError::QueryParseError(QueryParseError::UnrecognisedQueryType("foo".to_string()))));
+        assert_eq!(parse_adjacency_matrix(&json!({})).err(), Some(AggregationParseError::ExpectedKey("filters".to_string())));
+    }
+
+    #[test]
+    fn test_parse_extended_stats() {
+        assert_eq!(parse_extended_stats(&json!({"field": "price"})), Ok(ExtendedStatsAggregation {
+            field: "price".to_string(),
+            sigma: 2.0,
+        }));
+
+        assert_eq!(parse_extended_stats(&json!({"field": "price", "sigma": 3})), Ok(ExtendedStatsAggregation {
+            field: "price".to_string(),
+            sigma: 3.0,
+        }));
+
+        assert_eq!(parse_extended_stats(&json!({"field": "price", "sigma": -1})), Err(AggregationParseError::ExpectedNumber));
+    }
+
+    #[test]
+    fn test_parse_matrix_stats() {
+        assert_eq!(parse_matrix_stats(&json!({"fields": ["poverty", "income"]})), Ok(MatrixStatsAggregation {
+            fields: vec!["poverty".to_string(), "income".to_string()],
+        }));
+
+        assert_eq!(parse_matrix_stats(&json!({"fields": "poverty"})), Err(AggregationParseError::ExpectedArray));
+        assert_eq!(parse_matrix_stats(&json!({})), Err(AggregationParseError::ExpectedKey("fields".to_string())));
+    }
+
+    #[test]
+    fn test_parse_aggregations() {
+        assert_eq!(parse_aggregations(&json!({
+            "price_stats": {"extended_stats": {"field": "price"}},
+            "cells": {
+                "geohash_grid": {"field": "location"},
+                "aggregations": {
+                    "total": {"cumulative_sum": {"buckets_path": "_count"}}
+                }
+            }
+        })), Ok(Aggregations {
+            aggregations: vec![
+                Aggregation {
+                    name: "cells".to_string(),
+                    aggregation_type: AggregationType::Geo(GeoAggregation::Grid {
+                        field: "location".to_string(),
+                        grid: GeoGrid::Geohash(5),
+                        size: 10000,
+                    }),
+                    sub_aggregations: Aggregations {
+                        aggregations: vec![],
+                        pipelines: vec![("total".to_string(), PipelineAggregation::CumulativeSum {
+                            buckets_path: "_count".to_string(),
+                        })],
+                    },
+                },
+                Aggregation {
+                    name: "price_stats".to_string(),
+                    aggregation_type: AggregationType::ExtendedStats(ExtendedStatsAggregation {
+                        field: "price".to_string(),
+                        sigma: 2.0,
+                    }),
+                    sub_aggregations: Aggregations::new(),
+                },
+            ],
+            pipelines: vec![],
+        }));
+
+        assert_eq!(parse_aggregations(&json!({})), Ok(Aggregations::new()));
+    }
+
+    #[test]
+    fn test_parse_invalid_aggregations() {
+        assert_eq!(parse_aggregations(&json!([])), Err(AggregationParseError::ExpectedObject));
+        assert_eq!(parse_aggregations(&json!({"foo": {"bar": {}}})), Err(AggregationParseError::UnrecognisedType("bar".to_string())));
+        assert_eq!(parse_aggregations(&json!({"foo": {}})), Err(AggregationParseError::ExpectedAggregationType("foo".to_string())));
+        assert_eq!(parse_aggregations(&json!({"foo": {"extended_stats": {"field": "price"}, "matrix_stats": {"fields": ["price"]}}})), Err(AggregationParseError::ExpectedAggregationType("foo".to_string())));
+
+        // Pipelines need buckets to run over
+        assert_eq!(parse_aggregations(&json!({"foo": {"cumulative_sum": {"buckets_path": "_count"}}})), Err(AggregationParseError::UnexpectedPipeline("foo".to_string())));
+
+        // Metrics don't have buckets for sub-aggregations to run in
+        assert_eq!(parse_aggregations(&json!({
+            "foo": {
+                "extended_stats": {"field": "price"},
+                "aggs": {"bar": {"extended_stats": {"field": "price"}}}
+            }
+        })), Err(AggregationParseError::UnexpectedSubAggregations("foo".to_string())));
+    }
+
+    #[test]
+    fn test_parse_terms() {
+        assert_eq!(parse_terms(&json!({"field": "colour"})), Ok(TermsAggregation {
+            field: "colour".to_string(),
+            size: 10,
+            order: vec![TermsOrder { key: TermsOrderKey::Count, reverse: true }],
+            min_doc_count: 1,
+        }));
+
+        assert_eq!(parse_terms(&json!({"field": "colour", "size": 3, "order": [{"avg_price": "asc"}, {"_key": "desc"}], "min_doc_count": 0})), Ok(TermsAggregation {
+            field: "colour".to_string(),
+            size: 3,
+            order: vec![
+                TermsOrder { key: TermsOrderKey::SubAggregation("avg_price".to_string()), reverse: false },
+                TermsOrder { key: TermsOrderKey::Key, reverse: true },
+            ],
+            min_doc_count: 0,
+        }));
+
+        assert_eq!(parse_terms(&json!({"field": "colour", "size": 0})), Err(AggregationParseError::ExpectedPositiveInteger));
+        assert_eq!(parse_terms(&json!({"field": "colour", "order": {"_count": "up"}})), Err(AggregationParseError::InvalidOrder("{\"_count\":\"up\"}".to_string())));
+        assert_eq!(parse_terms(&json!({})), Err(AggregationParseError::ExpectedKey("field".to_string())));
+    }
+
+    #[test]
+    fn test_parse_terms_order_by_sub_aggregation() {
+        assert!(parse_aggregations(&json!({
+            "colours": {
+                "terms": {"field": "colour", "order": {"price_stats.avg": "desc"}},
+                "aggs": {"price_stats": {"extended_stats": {"field": "price"}}}
+            }
+        })).is_ok());
+
+        // The sub-aggregation must exist
+        assert_eq!(parse_aggregations(&json!({
+            "colours": {
+                "terms": {"field": "colour", "order": {"avg_price": "desc"}}
+            }
+        })), Err(AggregationParseError::InvalidOrder("avg_price".to_string())));
+    }
+
+    #[test]
+    fn test_parse_histogram() {
+        assert_eq!(parse_histogram(&json!({"field": "price", "interval": 50})), Ok(HistogramAggregation {
+            field: "price".to_string(),
+            interval: 50.0,
+            min_doc_count: 0,
+            extended_bounds: None,
+        }));
+
+        assert_eq!(parse_histogram(&json!({"field": "price", "interval": 2.5, "min_doc_count": 1, "extended_bounds": {"min": 0, "max": 100}})), Ok(HistogramAggregation {
+            field: "price".to_string(),
+            interval: 2.5,
+            min_doc_count: 1,
+            extended_bounds: Some((0.0, 100.0)),
+        }));
+
+        assert_eq!(parse_histogram(&json!({"field": "price"})), Err(AggregationParseError::ExpectedKey("interval".to_string())));
+        assert_eq!(parse_histogram(&json!({"field": "price", "interval": 0})), Err(AggregationParseError::ExpectedNumber));
+        assert_eq!(parse_histogram(&json!({"field": "price", "interval": 10, "extended_bounds": {"min": 0}})), Err(AggregationParseError::ExpectedKey("max".to_string())));
+    }
+
+    #[test]
+    fn test_parse_date_histogram() {
+        assert_eq!(parse_date_histogram(&json!({"field": "date", "calendar_interval": "month", "time_zone": "-05:00"})), Ok(DateHistogramAggregation {
+            field: "date".to_string(),
+            interval: DateInterval::Calendar(CalendarUnit::Month),
+            time_zone: FixedOffset::west(5 * 60 * 60),
+            min_doc_count: 0,
+            extended_bounds: None,
+        }));
+
+        assert_eq!(parse_date_histogram(&json!({"field": "date", "fixed_interval": "30m", "extended_bounds": {"min": 0, "max": "1970-01-02T00:00:00Z"}})), Ok(DateHistogramAggregation {
+            field: "date".to_string(),
+            interval: DateInterval::Fixed(30 * 60 * 1000),
+            time_zone: FixedOffset::east(0),
+            min_doc_count: 0,
+            extended_bounds: Some((0, 24 * 60 * 60 * 1000)),
+        }));
+
+        // The legacy "interval" accepts either kind
+        assert_eq!(parse_date_histogram(&json!({"field": "date", "interval": "1h"})).map(|date_histogram| date_histogram.interval), Ok(DateInterval::Calendar(CalendarUnit::Hour)));
+        assert_eq!(parse_date_histogram(&json!({"field": "date", "interval": "2h"})).map(|date_histogram| date_histogram.interval), Ok(DateInterval::Fixed(2 * 60 * 60 * 1000)));
+
+        assert_eq!(parse_date_histogram(&json!({"field": "date", "calendar_interval": "2M"})), Err(AggregationParseError::InvalidInterval("2M".to_string())));
+        assert_eq!(parse_date_histogram(&json!({"field": "date", "calendar_interval": "day", "time_zone": "Europe/Paris"})), Err(AggregationParseError::InvalidTimeZone("Europe/Paris".to_string())));
+        assert_eq!(parse_date_histogram(&json!({"field": "date"})), Err(AggregationParseError::ExpectedKey("calendar_interval".to_string())));
+    }
+
+    #[test]
+    fn test_parse_metric() {
+        assert_eq!(parse_aggregations(&json!({"avg_price": {"avg": {"field": "price"}}})).map(|aggregations| aggregations.aggregations[0].aggregation_type.clone()), Ok(AggregationType::Metric(MetricAggregation {
+            metric: Metric::Avg,
+            field: "price".to_string(),
+        })));
+
+        assert_eq!(parse_metric(Metric::Stats, &json!({})), Err(AggregationParseError::ExpectedKey("field".to_string())));
+
+        // Metrics don't have buckets
+        assert_eq!(parse_aggregations(&json!({
+            "max_price": {
+                "max": {"field": "price"},
+                "aggs": {"min_price": {"min": {"field": "price"}}}
+            }
+        })), Err(AggregationParseError::UnexpectedSubAggregations("max_price".to_string())));
+    }
+
+    #[test]
+    fn test_parse_nested() {
+        let aggregations = parse_aggregations(&json!({
+            "comments": {
+                "nested": {"path": "comments"},
+                "aggs": {
+                    "posts": {"reverse_nested": {}}
+                }
+            }
+        })).unwrap();
+
+        assert_eq!(aggregations.aggregations[0].aggregation_type, AggregationType::Nested(NestedAggregation {
+            path: "comments".to_string(),
+        }));
+        assert_eq!(aggregations.aggregations[0].sub_aggregations.aggregations[0].aggregation_type, AggregationType::ReverseNested(ReverseNestedAggregation {
+            path: None,
+        }));
+
+        assert_eq!(parse_aggregations(&json!({"comments": {"nested": {}}})), Err(AggregationParseError::ExpectedKey("path".to_string())));
+
+        // Reverse nested aggregations must be inside a nested aggregation, and
+        // going back to the top level leaves it
+        assert_eq!(parse_aggregations(&json!({"posts": {"reverse_nested": {}}})), Err(AggregationParseError::UnexpectedReverseNested("posts".to_string())));
+        assert_eq!(parse_aggregations(&json!({
+            "comments": {
+                "nested": {"path": "comments"},
+                "aggs": {
+                    "posts": {
+                        "reverse_nested": {},
+                        "aggs": {"again": {"reverse_nested": {}}}
+                    }
+                }
+            }
+        })), Err(AggregationParseError::UnexpectedReverseNested("again".to_string())));
+    }
+}
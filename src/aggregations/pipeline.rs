@@ -0,0 +1,411 @@
+//! Pipeline aggregations
+//!
+//! These run once the buckets of their parent aggregation have been rendered,
+//! reading the values of sibling aggregations from each bucket and writing a
+//! new value into it (or, for the bucket selector, removing it).
+
+use std::fmt;
+
+use serde_json::Value as Json;
+
+use script::{Expression, ScriptValue, ScriptError};
+
+
+/// What to do with buckets that have no documents or no value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GapPolicy {
+    /// Leave the bucket out of the calculation
+    Skip,
+
+    /// Use zero in place of the value
+    InsertZeros,
+
+    /// Use the value if there is one, even if the bucket has no documents
+    KeepValues,
+}
+
+
+impl Default for GapPolicy {
+    fn default() -> GapPolicy {
+        GapPolicy::Skip
+    }
+}
+
+
+/// Functions that a moving window of values can be reduced with
+#[derive(Debug, Clone, PartialEq)]
+pub enum MovingFunction {
+    Max,
+    Min,
+    Sum,
+    UnweightedAvg,
+
+    /// Weights each value by its place in the window, so later values count for more
+    LinearWeightedAvg,
+
+    /// Exponentially weighted average, alpha is between 0 and 1
+    Ewma(f64),
+
+    StdDev,
+}
+
+
+impl MovingFunction {
+    pub fn apply(&self, values: &[f64]) -> Option<f64> {
+        if values.is_empty() {
+            return None;
+        }
+
+        let count = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / count;
+
+        Some(match *self {
+            MovingFunction::Max => values.iter().cloned().fold(::std::f64::NEG_INFINITY, f64::max),
+            MovingFunction::Min => values.iter().cloned().fold(::std::f64::INFINITY, f64::min),
+            MovingFunction::Sum => values.iter().sum(),
+            MovingFunction::UnweightedAvg => mean,
+            MovingFunction::LinearWeightedAvg => {
+                let weighted_total = values.iter().enumerate().map(|(i, value)| value * (i + 1) as f64).sum::<f64>();
+                weighted_total / (count * (count + 1.0) / 2.0)
+            }
+            MovingFunction::Ewma(alpha) => {
+                let mut average = values[0];
+                for value in values[1..].iter() {
+                    average = alpha * value + (1.0 - alpha) * average;
+                }
+
+                average
+            }
+            MovingFunction::StdDev => {
+                let variance = values.iter().map(|value| (value - mean) * (value - mean)).sum::<f64>() / count;
+                variance.sqrt()
+            }
+        })
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PipelineAggregation {
+    /// The difference between the value of each bucket and the one before it
+    Derivative {
+        buckets_path: String,
+        gap_policy: GapPolicy,
+    },
+
+    /// The total of the values of each bucket and all of those before it
+    CumulativeSum {
+        buckets_path: String,
+    },
+
+    /// Reduces the values of the buckets in a window before each bucket. The
+    /// window is moved forward by "shift" buckets, so a shift of 1 takes in
+    /// the current bucket
+    MovingFunction {
+        buckets_path: String,
+        window: usize,
+        shift: i64,
+        function: MovingFunction,
+        gap_policy: GapPolicy,
+    },
+
+    /// Runs a script over named values of each bucket
+    BucketScript {
+        buckets_path: Vec<(String, String)>,
+        script: Expression,
+        gap_policy: GapPolicy,
+    },
+
+    /// Removes the buckets that the script returns false for
+    BucketSelector {
+        buckets_path: Vec<(String, String)>,
+        script: Expression,
+        gap_policy: GapPolicy,
+    },
+}
+
+
+#[derive(Debug, PartialEq)]
+pub enum PipelineError {
+    ScriptError(ScriptError),
+}
+
+
+impl From<ScriptError> for PipelineError {
+    fn from(e: ScriptError) -> PipelineError {
+        PipelineError::ScriptError(e)
+    }
+}
+
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PipelineError::ScriptError(ScriptError::UnknownVariable(ref name)) => write!(f, "unknown variable {:?} in script", name),
+            PipelineError::ScriptError(ScriptError::TypeMismatch) => write!(f, "type mismatch in script"),
+        }
+    }
+}
+
+
+/// Reads a value out of a bucket
+///
+/// Paths name sibling aggregations, which are separated by ">" to step into
+/// single bucket aggregations. The last aggregation can be followed by "." and
+/// the name of a value, otherwise its "value" is read. "_count" reads the
+/// number of documents and "_key" reads the key.
+pub fn resolve_buckets_path(bucket: &Json, buckets_path: &str) -> Option<f64> {
+    let mut current = bucket;
+    let mut names = buckets_path.split('>').peekable();
+
+    while let Some(name) = names.next() {
+        if names.peek().is_some() {
+            current = current.get(name)?;
+            continue;
+        }
+
+        let mut split = name.splitn(2, '.');
+        let (aggregation_name, value_name) = (split.next()?, split.next());
+
+        let value = match (aggregation_name, value_name) {
+            ("_count", None) => current.get("doc_count"),
+            ("_key", None) => current.get("key"),
+            (aggregation_name, Some(value_name)) => current.get(aggregation_name)?.get(value_name),
+            (aggregation_name, None) => current.get(aggregation_name)?.get("value"),
+        };
+
+        return value.and_then(|value| value.as_f64());
+    }
+
+    None
+}
+
+
+/// Reads a value out of a bucket, applying the gap policy
+fn bucket_value(bucket: &Json, buckets_path: &str, gap_policy: GapPolicy) -> Option<f64> {
+    let doc_count = bucket.get("doc_count").and_then(|doc_count| doc_count.as_u64()).unwrap_or(0);
+    let value = resolve_buckets_path(bucket, buckets_path).filter(|value| value.is_finite());
+
+    if value.is_some() && doc_count > 0 {
+        return value;
+    }
+
+    match gap_policy {
+        GapPolicy::Skip => None,
+        GapPolicy::InsertZeros => Some(0.0),
+        GapPolicy::KeepValues => value,
+    }
+}
+
+
+/// Reads the values of a script's variables out of a bucket. Returns None
+/// if any of them is missing
+fn script_variables(bucket: &Json, buckets_path: &[(String, String)], gap_policy: GapPolicy) -> Option<Vec<(String, f64)>> {
+    buckets_path.iter()
+        .map(|&(ref variable, ref path)| bucket_value(bucket, path, gap_policy).map(|value| (variable.clone(), value)))
+        .collect()
+}
+
+
+fn run_script(script: &Expression, variables: &[(String, f64)]) -> Result<ScriptValue, ScriptError> {
+    script.evaluate(&|name: &str| {
+        variables.iter().find(|&&(ref variable, _)| variable == name).map(|&(_, value)| ScriptValue::Number(value))
+    })
+}
+
+
+fn set_value(bucket: &mut Json, name: &str, value: Option<f64>) {
+    if let Some(bucket) = bucket.as_object_mut() {
+        bucket.insert(name.to_string(), json!({"value": value}));
+    }
+}
+
+
+impl PipelineAggregation {
+    /// Runs the pipeline over the buckets of its parent aggregation, storing
+    /// its results under the name it was given in the request
+    pub fn apply(&self, name: &str, buckets: &mut Vec<Json>) -> Result<(), PipelineError> {
+        match *self {
+            PipelineAggregation::Derivative{ref buckets_path, gap_policy} => {
+                let mut last_value = None;
+
+                for bucket in buckets.iter_mut() {
+                    let value = match bucket_value(bucket, buckets_path, gap_policy) {
+                        Some(value) => value,
+                        None => continue,
+                    };
+
+                    // The first bucket doesn't have anything to compare with
+                    if let Some(last_value) = last_value {
+                        set_value(bucket, name, Some(value - last_value));
+                    }
+
+                    last_value = Some(value);
+                }
+            }
+            PipelineAggregation::CumulativeSum{ref buckets_path} => {
+                let mut total = 0.0;
+
+                for bucket in buckets.iter_mut() {
+                    total += bucket_value(bucket, buckets_path, GapPolicy::InsertZeros).unwrap_or(0.0);
+                    set_value(bucket, name, Some(total));
+                }
+            }
+            PipelineAggregation::MovingFunction{ref buckets_path, window, shift, ref function, gap_policy} => {
+                let values = buckets.iter().map(|bucket| bucket_value(bucket, buckets_path, gap_policy)).collect::<Vec<_>>();
+
+                for (i, bucket) in buckets.iter_mut().enumerate() {
+                    if values[i].is_none() {
+                        continue;
+                    }
+
+                    let end = (i as i64 + shift).max(0).min(values.len() as i64) as usize;
+                    let start = end.saturating_sub(window);
+                    let window_values = values[start..end].iter().filter_map(|value| *value).collect::<Vec<_>>();
+
+                    set_value(bucket, name, function.apply(&window_values));
+                }
+            }
+            PipelineAggregation::BucketScript{ref buckets_path, ref script, gap_policy} => {
+                for bucket in buckets.iter_mut() {
+                    let variables = match script_variables(bucket, buckets_path, gap_policy) {
+                        Some(variables) => variables,
+                        None => continue,
+                    };
+
+                    let value = run_script(script, &variables)?.as_number()?;
+                    set_value(bucket, name, Some(value));
+                }
+            }
+            PipelineAggregation::BucketSelector{ref buckets_path, ref script, gap_policy} => {
+                let mut selected = Vec::with_capacity(buckets.len());
+
+                for bucket in buckets.drain(..) {
+                    // Buckets that are missing a value are kept
+                    let keep = match script_variables(&bucket, buckets_path, gap_policy) {
+                        Some(variables) => run_script(script, &variables)?.as_boolean()?,
+                        None => true,
+                    };
+
+                    if keep {
+                        selected.push(bucket);
+                    }
+                }
+
+                *buckets = selected;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value as Json;
+
+    use script::Expression;
+
+    use super::{PipelineAggregation, MovingFunction, GapPolicy, resolve_buckets_path};
+
+    fn make_buckets() -> Vec<Json> {
+        vec![
+            json!({"key": 1, "doc_count": 2, "sales": {"value": 100.0}, "stats": {"max": 70.0}}),
+            json!({"key": 2, "doc_count": 0, "sales": {"value": null}, "stats": {"max": null}}),
+            json!({"key": 3, "doc_count": 3, "sales": {"value": 160.0}, "stats": {"max": 90.0}}),
+            json!({"key": 4, "doc_count": 1, "sales": {"value": 130.0}, "stats": {"max": 130.0}}),
+        ]
+    }
+
+    fn values(buckets: &[Json], name: &str) -> Vec<Option<f64>> {
+        buckets.iter().map(|bucket| bucket.get(name).and_then(|result| result.get("value")).and_then(|value| value.as_f64())).collect()
+    }
+
+    #[test]
+    fn test_resolve_buckets_path() {
+        let bucket = json!({"key": 1, "doc_count": 2, "sales": {"value": 100.0}, "stats": {"max": 70.0}, "sale_type": {"doc_count": 1, "sales": {"value": 40.0}}});
+
+        assert_eq!(resolve_buckets_path(&bucket, "_count"), Some(2.0));
+        assert_eq!(resolve_buckets_path(&bucket, "_key"), Some(1.0));
+        assert_eq!(resolve_buckets_path(&bucket, "sales"), Some(100.0));
+        assert_eq!(resolve_buckets_path(&bucket, "stats.max"), Some(70.0));
+        assert_eq!(resolve_buckets_path(&bucket, "sale_type>sales"), Some(40.0));
+        assert_eq!(resolve_buckets_path(&bucket, "sale_type>_count"), Some(1.0));
+        assert_eq!(resolve_buckets_path(&bucket, "missing"), None);
+    }
+
+    #[test]
+    fn test_derivative() {
+        let mut buckets = make_buckets();
+        PipelineAggregation::Derivative {
+            buckets_path: "sales".to_string(),
+            gap_policy: GapPolicy::Skip,
+        }.apply("sales_deriv", &mut buckets).unwrap();
+
+        assert_eq!(values(&buckets, "sales_deriv"), vec![None, None, Some(60.0), Some(-30.0)]);
+
+        let mut buckets = make_buckets();
+        PipelineAggregation::Derivative {
+            buckets_path: "sales".to_string(),
+            gap_policy: GapPolicy::InsertZeros,
+        }.apply("sales_deriv", &mut buckets).unwrap();
+
+        assert_eq!(values(&buckets, "sales_deriv"), vec![None, Some(-100.0), Some(160.0), Some(-30.0)]);
+    }
+
+    #[test]
+    fn test_cumulative_sum() {
+        let mut buckets = make_buckets();
+        PipelineAggregation::CumulativeSum {
+            buckets_path: "sales".to_string(),
+        }.apply("total_sales", &mut buckets).unwrap();
+
+        assert_eq!(values(&buckets, "total_sales"), vec![Some(100.0), Some(100.0), Some(260.0), Some(390.0)]);
+    }
+
+    #[test]
+    fn test_moving_function() {
+        let mut buckets = make_buckets();
+        PipelineAggregation::MovingFunction {
+            buckets_path: "stats.max".to_string(),
+            window: 2,
+            shift: 0,
+            function: MovingFunction::UnweightedAvg,
+            gap_policy: GapPolicy::Skip,
+        }.apply("moving_max", &mut buckets).unwrap();
+
+        // The window holds the buckets before the current one
+        assert_eq!(values(&buckets, "moving_max"), vec![None, None, Some(70.0), Some(90.0)]);
+
+        assert_eq!(MovingFunction::LinearWeightedAvg.apply(&[1.0, 2.0, 3.0]), Some(14.0 / 6.0));
+        assert_eq!(MovingFunction::Ewma(0.5).apply(&[1.0, 3.0]), Some(2.0));
+        assert_eq!(MovingFunction::StdDev.apply(&[1.0, 3.0]), Some(1.0));
+    }
+
+    #[test]
+    fn test_bucket_script() {
+        let mut buckets = make_buckets();
+        PipelineAggregation::BucketScript {
+            buckets_path: vec![("sales".to_string(), "sales".to_string()), ("count".to_string(), "_count".to_string())],
+            script: Expression::parse("params.sales / params.count").unwrap(),
+            gap_policy: GapPolicy::Skip,
+        }.apply("sales_per_doc", &mut buckets).unwrap();
+
+        assert_eq!(values(&buckets, "sales_per_doc"), vec![Some(50.0), None, Some(160.0 / 3.0), Some(130.0)]);
+    }
+
+    #[test]
+    fn test_bucket_selector() {
+        let mut buckets = make_buckets();
+        PipelineAggregation::BucketSelector {
+            buckets_path: vec![("sales".to_string(), "sales".to_string())],
+            script: Expression::parse("params.sales > 120").unwrap(),
+            gap_policy: GapPolicy::Skip,
+        }.apply("sales_filter", &mut buckets).unwrap();
+
+        // Buckets without a value are kept
+        let keys = buckets.iter().map(|bucket| bucket["key"].as_u64().unwrap()).collect::<Vec<_>>();
+        assert_eq!(keys, vec![2, 3, 4]);
+    }
+}
@@ -0,0 +1,327 @@
+//! Statistics aggregations
+//!
+//! Values are collected one at a time, keeping running totals rather than the
+//! values themselves. Matrix stats use Welford's method to update the moments
+//! and co-moments of the fields, which doesn't lose precision when the values
+//! are large compared to their spread.
+
+use serde_json::Value as Json;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtendedStatsAggregation {
+    pub field: String,
+
+    /// The number of standard deviations from the mean to put the bounds at
+    pub sigma: f64,
+}
+
+
+/// The statistics given by a metric aggregation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metric {
+    Min,
+    Max,
+    Avg,
+    Sum,
+
+    /// The count, min, max, avg and sum together
+    Stats,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricAggregation {
+    pub metric: Metric,
+    pub field: String,
+}
+
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExtendedStats {
+    count: u64,
+    sum: f64,
+    sum_of_squares: f64,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+
+impl ExtendedStats {
+    pub fn new() -> ExtendedStats {
+        ExtendedStats::default()
+    }
+
+    pub fn collect(&mut self, value: f64) {
+        self.collect_weighted(value, 1);
+    }
+
+    /// Collects a value as if it had been seen "count" times
+    pub fn collect_weighted(&mut self, value: f64, count: u64) {
+        if count == 0 {
+            return;
+        }
+
+        self.count += count;
+        self.sum += value * count as f64;
+        self.sum_of_squares += value * value * count as f64;
+        self.min = Some(self.min.map_or(value, |min| min.min(value)));
+        self.max = Some(self.max.map_or(value, |max| max.max(value)));
+    }
+
+    /// Renders the results of a metric aggregation. Single metrics are given
+    /// as "value", so they can be used in buckets paths
+    pub fn render_metric(&self, metric: Metric) -> Json {
+        let avg = if self.count > 0 { Some(self.sum / self.count as f64) } else { None };
+
+        match metric {
+            Metric::Min => json!({"value": self.min}),
+            Metric::Max => json!({"value": self.max}),
+            Metric::Avg => json!({"value": avg}),
+            Metric::Sum => json!({"value": self.sum}),
+            Metric::Stats => {
+                json!({
+                    "count": self.count,
+                    "min": self.min,
+                    "max": self.max,
+                    "avg": avg,
+                    "sum": self.sum,
+                })
+            }
+        }
+    }
+
+    pub fn render(&self, sigma: f64) -> Json {
+        if self.count == 0 {
+            return json!({
+                "count": 0,
+                "min": null,
+                "max": null,
+                "avg": null,
+                "sum": 0.0,
+                "sum_of_squares": null,
+                "variance": null,
+                "variance_population": null,
+                "variance_sampling": null,
+                "std_deviation": null,
+                "std_deviation_population": null,
+                "std_deviation_sampling": null,
+                "std_deviation_bounds": {
+                    "upper": null,
+                    "lower": null,
+                    "upper_population": null,
+                    "lower_population": null,
+                    "upper_sampling": null,
+                    "lower_sampling": null,
+                },
+            });
+        }
+
+        let count = self.count as f64;
+        let avg = self.sum / count;
+
+        // Rounding can make the variance of equal values slightly negative
+        let variance_population = (self.sum_of_squares / count - avg * avg).max(0.0);
+        let variance_sampling = if self.count > 1 { variance_population * count / (count - 1.0) } else { ::std::f64::NAN };
+        let (std_deviation_population, std_deviation_sampling) = (variance_population.sqrt(), variance_sampling.sqrt());
+
+        // NaN isn't valid JSON
+        let number = |value: f64| if value.is_finite() { json!(value) } else { Json::Null };
+
+        json!({
+            "count": self.count,
+            "min": self.min,
+            "max": self.max,
+            "avg": avg,
+            "sum": self.sum,
+            "sum_of_squares": self.sum_of_squares,
+            "variance": variance_population,
+            "variance_population": variance_population,
+            "variance_sampling": number(variance_sampling),
+            "std_deviation": std_deviation_population,
+            "std_deviation_population": std_deviation_population,
+            "std_deviation_sampling": number(std_deviation_sampling),
+            "std_deviation_bounds": {
+                "upper": avg + sigma * std_deviation_population,
+                "lower": avg - sigma * std_deviation_population,
+                "upper_population": avg + sigma * std_deviation_population,
+                "lower_population": avg - sigma * std_deviation_population,
+                "upper_sampling": number(avg + sigma * std_deviation_sampling),
+                "lower_sampling": number(avg - sigma * std_deviation_sampling),
+            },
+        })
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatrixStatsAggregation {
+    pub fields: Vec<String>,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatrixStats {
+    count: u64,
+    means: Vec<f64>,
+
+    /// The sums of the second, third and fourth powers of the distances from the mean
+    m2: Vec<f64>,
+    m3: Vec<f64>,
+    m4: Vec<f64>,
+
+    /// The sums of the products of the distances of each pair of fields from their means
+    comoments: Vec<Vec<f64>>,
+}
+
+
+impl MatrixStats {
+    pub fn new(num_fields: usize) -> MatrixStats {
+        MatrixStats {
+            count: 0,
+            means: vec![0.0; num_fields],
+            m2: vec![0.0; num_fields],
+            m3: vec![0.0; num_fields],
+            m4: vec![0.0; num_fields],
+            comoments: vec![vec![0.0; num_fields]; num_fields],
+        }
+    }
+
+    /// Collects a document's value for each of the fields. Documents that are
+    /// missing any of the fields should be left out
+    pub fn collect(&mut self, values: &[f64]) {
+        self.count += 1;
+        let n = self.count as f64;
+
+        let deltas = values.iter().zip(self.means.iter()).map(|(value, mean)| value - mean).collect::<Vec<_>>();
+
+        for i in 0..values.len() {
+            for j in 0..values.len() {
+                self.comoments[i][j] += deltas[i] * deltas[j] * (n - 1.0) / n;
+            }
+        }
+
+        for (i, delta) in deltas.iter().enumerate() {
+            let delta_n = delta / n;
+            let delta_n2 = delta_n * delta_n;
+            let term = delta * delta_n * (n - 1.0);
+
+            self.means[i] += delta_n;
+            self.m4[i] += term * delta_n2 * (n * n - 3.0 * n + 3.0) + 6.0 * delta_n2 * self.m2[i] - 4.0 * delta_n * self.m3[i];
+            self.m3[i] += term * delta_n * (n - 2.0) - 3.0 * delta_n * self.m2[i];
+            self.m2[i] += term;
+        }
+    }
+
+    pub fn render(&self, fields: &[String]) -> Json {
+        let n = self.count as f64;
+        let number = |value: f64| if value.is_finite() { json!(value) } else { Json::Null };
+
+        let field_stats = fields.iter().enumerate().map(|(i, name)| {
+            let mut covariance = json!({});
+            let mut correlation = json!({});
+
+            for (j, other_name) in fields.iter().enumerate() {
+                covariance[other_name] = number(self.comoments[i][j] / (n - 1.0));
+                correlation[other_name] = number(self.comoments[i][j] / (self.comoments[i][i] * self.comoments[j][j]).sqrt());
+            }
+
+            json!({
+                "name": name,
+                "count": self.count,
+                "mean": number(self.means[i]),
+                "variance": number(self.m2[i] / (n - 1.0)),
+                "skewness": number(n.sqrt() * self.m3[i] / self.m2[i].powf(1.5)),
+                "kurtosis": number(n * self.m4[i] / (self.m2[i] * self.m2[i])),
+                "covariance": covariance,
+                "correlation": correlation,
+            })
+        }).collect::<Vec<_>>();
+
+        json!({
+            "doc_count": self.count,
+            "fields": field_stats,
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{ExtendedStats, Metric, MatrixStats};
+
+    #[test]
+    fn test_extended_stats() {
+        let mut stats = ExtendedStats::new();
+        for value in [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0].iter() {
+            stats.collect(*value);
+        }
+
+        let rendered = stats.render(2.0);
+        assert_eq!(rendered["count"], json!(8));
+        assert_eq!(rendered["min"], json!(2.0));
+        assert_eq!(rendered["max"], json!(9.0));
+        assert_eq!(rendered["avg"], json!(5.0));
+        assert_eq!(rendered["sum_of_squares"], json!(232.0));
+        assert_eq!(rendered["variance"], json!(4.0));
+        assert_eq!(rendered["variance_sampling"], json!(32.0 / 7.0));
+        assert_eq!(rendered["std_deviation"], json!(2.0));
+        assert_eq!(rendered["std_deviation_bounds"]["upper"], json!(9.0));
+        assert_eq!(rendered["std_deviation_bounds"]["lower"], json!(1.0));
+    }
+
+    #[test]
+    fn test_render_metric() {
+        let mut stats = ExtendedStats::new();
+        for value in [2.0, 4.0, 9.0].iter() {
+            stats.collect(*value);
+        }
+
+        assert_eq!(stats.render_metric(Metric::Min), json!({"value": 2.0}));
+        assert_eq!(stats.render_metric(Metric::Max), json!({"value": 9.0}));
+        assert_eq!(stats.render_metric(Metric::Avg), json!({"value": 5.0}));
+        assert_eq!(stats.render_metric(Metric::Sum), json!({"value": 15.0}));
+        assert_eq!(stats.render_metric(Metric::Stats), json!({"count": 3, "min": 2.0, "max": 9.0, "avg": 5.0, "sum": 15.0}));
+
+        // Sums of no values are 0, the others have no value
+        let stats = ExtendedStats::new();
+        assert_eq!(stats.render_metric(Metric::Avg), json!({"value": null}));
+        assert_eq!(stats.render_metric(Metric::Sum), json!({"value": 0.0}));
+    }
+
+    #[test]
+    fn test_extended_stats_without_values() {
+        let rendered = ExtendedStats::new().render(2.0);
+        assert_eq!(rendered["count"], json!(0));
+        assert_eq!(rendered["avg"], json!(null));
+
+        // The sample variance of one value is undefined
+        let mut stats = ExtendedStats::new();
+        stats.collect(3.0);
+        let rendered = stats.render(2.0);
+        assert_eq!(rendered["variance"], json!(0.0));
+        assert_eq!(rendered["variance_sampling"], json!(null));
+    }
+
+    #[test]
+    fn test_matrix_stats() {
+        let mut stats = MatrixStats::new(2);
+        for &(x, y) in [(1.0, 2.0), (2.0, 4.0), (3.0, 6.0), (4.0, 8.0)].iter() {
+            stats.collect(&[x, y]);
+        }
+
+        let rendered = stats.render(&["x".to_string(), "y".to_string()]);
+        let field = |i: usize, key: &str| rendered["fields"][i][key].as_f64().unwrap();
+        let approx_eq = |a: f64, b: f64| (a - b).abs() < 1e-9;
+
+        assert_eq!(rendered["doc_count"], json!(4));
+        assert!(approx_eq(field(0, "mean"), 2.5));
+        assert!(approx_eq(field(1, "mean"), 5.0));
+        assert!(approx_eq(field(0, "variance"), 5.0 / 3.0));
+        assert!(approx_eq(field(0, "skewness"), 0.0));
+        assert!(approx_eq(field(0, "kurtosis"), 1.64));
+        assert!(approx_eq(rendered["fields"][0]["covariance"]["y"].as_f64().unwrap(), 10.0 / 3.0));
+        assert!(approx_eq(rendered["fields"][0]["correlation"]["y"].as_f64().unwrap(), 1.0));
+        assert!(approx_eq(rendered["fields"][1]["correlation"]["x"].as_f64().unwrap(), 1.0));
+    }
+}
@@ -0,0 +1,189 @@
+//! Terms aggregations
+//!
+//! Buckets documents by the values of a keyword, integer or boolean field.
+//! Values are read from the stored values of the matching documents, so the
+//! field must be stored. The buckets with the most documents are kept, unless
+//! another order is given.
+
+use std::cmp::Ordering;
+
+use serde_json::Value as Json;
+
+use aggregations::pipeline::resolve_buckets_path;
+
+
+/// A value of a field that documents are bucketed by
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum TermValue {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+}
+
+
+impl TermValue {
+    /// Adds the key of the bucket. Booleans are given as 1 or 0, with their
+    /// name in "key_as_string"
+    pub fn render_key(&self, bucket: &mut Json) {
+        match *self {
+            TermValue::String(ref value) => {
+                bucket["key"] = json!(value);
+            }
+            TermValue::Integer(value) => {
+                bucket["key"] = json!(value);
+            }
+            TermValue::Boolean(value) => {
+                bucket["key"] = json!(if value { 1 } else { 0 });
+                bucket["key_as_string"] = json!(if value { "true" } else { "false" });
+            }
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TermsOrderKey {
+    /// The number of documents in the bucket
+    Count,
+
+    /// The value of the field
+    Key,
+
+    /// A value of a sub-aggregation, eg "avg_price" or "price_stats.max"
+    SubAggregation(String),
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TermsOrder {
+    pub key: TermsOrderKey,
+    pub reverse: bool,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TermsAggregation {
+    pub field: String,
+    pub size: usize,
+
+    /// Buckets are compared by each of these in turn. Ties are broken by key
+    pub order: Vec<TermsOrder>,
+
+    /// Buckets with fewer documents are left out
+    pub min_doc_count: u64,
+}
+
+
+impl TermsAggregation {
+    /// The default order, with the most documents first
+    pub fn default_order() -> Vec<TermsOrder> {
+        vec![TermsOrder { key: TermsOrderKey::Count, reverse: true }]
+    }
+
+    /// Sorts the rendered buckets, keeping the first "size" of them. The keys
+    /// of the buckets are given in the same order as the buckets
+    pub fn select_buckets(&self, buckets: Vec<(TermValue, Json)>) -> Vec<Json> {
+        let mut buckets = buckets.into_iter()
+            .filter(|&(_, ref bucket)| bucket["doc_count"].as_u64().unwrap_or(0) >= self.min_doc_count)
+            .collect::<Vec<_>>();
+
+        buckets.sort_by(|a, b| {
+            for order in self.order.iter() {
+                let ordering = match order.key {
+                    TermsOrderKey::Count => a.1["doc_count"].as_u64().cmp(&b.1["doc_count"].as_u64()),
+                    TermsOrderKey::Key => a.0.cmp(&b.0),
+                    TermsOrderKey::SubAggregation(ref path) => {
+                        // Buckets without a value go last, whichever way they're sorted
+                        match (resolve_buckets_path(&a.1, path), resolve_buckets_path(&b.1, path)) {
+                            (Some(a), Some(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+                            (Some(_), None) => if order.reverse { Ordering::Greater } else { Ordering::Less },
+                            (None, Some(_)) => if order.reverse { Ordering::Less } else { Ordering::Greater },
+                            (None, None) => Ordering::Equal,
+                        }
+                    }
+                };
+
+                let ordering = if order.reverse { ordering.reverse() } else { ordering };
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+
+            a.0.cmp(&b.0)
+        });
+
+        buckets.truncate(self.size);
+        buckets.into_iter().map(|(_, bucket)| bucket).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value as Json;
+
+    use super::{TermsAggregation, TermsOrder, TermsOrderKey, TermValue};
+
+    fn bucket(key: &str, doc_count: u64, avg_price: Option<f64>) -> (TermValue, Json) {
+        let mut bucket = json!({"doc_count": doc_count, "avg_price": {"value": avg_price}});
+        let key = TermValue::String(key.to_string());
+        key.render_key(&mut bucket);
+        (key, bucket)
+    }
+
+    fn keys(buckets: &[Json]) -> Vec<&str> {
+        buckets.iter().map(|bucket| bucket["key"].as_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_render_key() {
+        let mut bucket = json!({});
+        TermValue::Boolean(true).render_key(&mut bucket);
+        assert_eq!(bucket, json!({"key": 1, "key_as_string": "true"}));
+
+        let mut bucket = json!({});
+        TermValue::Integer(-5).render_key(&mut bucket);
+        assert_eq!(bucket, json!({"key": -5}));
+    }
+
+    #[test]
+    fn test_select_buckets_by_count() {
+        let aggregation = TermsAggregation {
+            field: "colour".to_string(),
+            size: 2,
+            order: TermsAggregation::default_order(),
+            min_doc_count: 1,
+        };
+
+        // Ties are broken by key
+        let buckets = aggregation.select_buckets(vec![bucket("red", 1, None), bucket("green", 3, None), bucket("blue", 1, None)]);
+        assert_eq!(keys(&buckets), vec!["green", "blue"]);
+    }
+
+    #[test]
+    fn test_select_buckets_by_key() {
+        let aggregation = TermsAggregation {
+            field: "colour".to_string(),
+            size: 10,
+            order: vec![TermsOrder { key: TermsOrderKey::Key, reverse: false }],
+            min_doc_count: 2,
+        };
+
+        let buckets = aggregation.select_buckets(vec![bucket("red", 2, None), bucket("green", 3, None), bucket("blue", 1, None)]);
+        assert_eq!(keys(&buckets), vec!["green", "red"]);
+    }
+
+    #[test]
+    fn test_select_buckets_by_sub_aggregation() {
+        let aggregation = TermsAggregation {
+            field: "colour".to_string(),
+            size: 10,
+            order: vec![TermsOrder { key: TermsOrderKey::SubAggregation("avg_price".to_string()), reverse: true }],
+            min_doc_count: 1,
+        };
+
+        // Buckets without a value go last
+        let buckets = aggregation.select_buckets(vec![bucket("red", 1, Some(5.0)), bucket("green", 1, None), bucket("blue", 1, Some(10.0))]);
+        assert_eq!(keys(&buckets), vec!["blue", "red", "green"]);
+    }
+}
@@ -0,0 +1,108 @@
+//! Aggregation trees
+//!
+//! The "aggs" section of a search request is parsed into a tree. Each
+//! aggregation has a name, which its results are rendered under, and the
+//! aggregations that run inside of it. Bucket aggregations run their
+//! sub-aggregations once for each bucket. Pipeline aggregations are kept apart
+//! from the others, as they're run over the buckets of their parent once it
+//! has been rendered.
+
+use serde_json::Value as Json;
+
+use aggregations::pipeline::{PipelineAggregation, PipelineError};
+use aggregations::geo::GeoAggregation;
+use aggregations::stats::{MetricAggregation, ExtendedStatsAggregation, MatrixStatsAggregation};
+use aggregations::terms::TermsAggregation;
+use aggregations::histogram::HistogramAggregation;
+use aggregations::date_histogram::DateHistogramAggregation;
+use aggregations::adjacency_matrix::{AdjacencyMatrixAggregation, AdjacencyFilter};
+use aggregations::nested::{NestedAggregation, ReverseNestedAggregation};
+use aggregations::collector::AggregationsCollector;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AggregationType {
+    Metric(MetricAggregation),
+    ExtendedStats(ExtendedStatsAggregation),
+    MatrixStats(MatrixStatsAggregation),
+    Geo(GeoAggregation),
+    Terms(TermsAggregation),
+    Histogram(HistogramAggregation),
+    DateHistogram(DateHistogramAggregation),
+    AdjacencyMatrix(AdjacencyMatrixAggregation),
+    Nested(NestedAggregation),
+    ReverseNested(ReverseNestedAggregation),
+}
+
+
+impl AggregationType {
+    /// Checks if the aggregation puts documents into buckets, so it can have
+    /// pipeline sub-aggregations. Nested aggregations have sub-aggregations, but
+    /// only the one bucket
+    pub fn has_buckets(&self) -> bool {
+        match *self {
+            AggregationType::Metric(_) | AggregationType::ExtendedStats(_) | AggregationType::MatrixStats(_) => false,
+            AggregationType::Nested(_) | AggregationType::ReverseNested(_) => false,
+            AggregationType::Geo(_) | AggregationType::Terms(_) | AggregationType::Histogram(_) | AggregationType::DateHistogram(_) | AggregationType::AdjacencyMatrix(_) => true,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Aggregation {
+    pub name: String,
+    pub aggregation_type: AggregationType,
+    pub sub_aggregations: Aggregations,
+}
+
+
+/// The aggregations at one level of the tree
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Aggregations {
+    pub aggregations: Vec<Aggregation>,
+
+    /// Run over the buckets of the parent aggregation, in the order they were given
+    pub pipelines: Vec<(String, PipelineAggregation)>,
+}
+
+
+impl Aggregations {
+    pub fn new() -> Aggregations {
+        Aggregations::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.aggregations.is_empty() && self.pipelines.is_empty()
+    }
+
+    /// Finds the filters of the adjacency matrices in the tree, so they can be
+    /// run before the search
+    pub fn adjacency_filters(&self) -> Vec<AdjacencyFilter> {
+        let mut filters = Vec::new();
+
+        for aggregation in self.aggregations.iter() {
+            if let AggregationType::AdjacencyMatrix(ref adjacency_matrix) = aggregation.aggregation_type {
+                filters.extend(adjacency_matrix.filters.iter().map(|&(_, ref filter)| filter.clone()));
+            }
+
+            filters.extend(aggregation.sub_aggregations.adjacency_filters());
+        }
+
+        filters
+    }
+
+    /// Creates the collectors for a search, or for one bucket of the parent
+    pub fn collector(&self) -> AggregationsCollector {
+        AggregationsCollector::new(self)
+    }
+
+    /// Runs the pipeline aggregations over the rendered buckets of the parent
+    pub fn apply_pipelines(&self, buckets: &mut Vec<Json>) -> Result<(), PipelineError> {
+        for &(ref name, ref pipeline) in self.pipelines.iter() {
+            pipeline.apply(name, buckets)?;
+        }
+
+        Ok(())
+    }
+}
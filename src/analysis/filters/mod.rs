@@ -1,6 +1,7 @@
 pub mod lowercase;
 pub mod ngram;
 pub mod asciifolding;
+pub mod shingle;
 
 use serde::{Serialize, Serializer};
 use search::Token;
@@ -9,6 +10,7 @@ use analysis::ngram_generator::Edge;
 use analysis::filters::lowercase::LowercaseFilter;
 use analysis::filters::ngram::NGramFilter;
 use analysis::filters::asciifolding::ASCIIFoldingFilter;
+use analysis::filters::shingle::ShingleFilter;
 
 
 /// Defines a token filter
@@ -45,6 +47,11 @@ pub enum FilterSpec {
         edge: Edge,
     },
     ASCIIFolding,
+    Shingle {
+        min_size: usize,
+        max_size: usize,
+        output_unigrams: bool,
+    },
 }
 
 
@@ -60,6 +67,9 @@ impl FilterSpec {
             FilterSpec::ASCIIFolding => {
                 Box::new(ASCIIFoldingFilter::new(input))
             }
+            FilterSpec::Shingle{min_size, max_size, output_unigrams} => {
+                Box::new(ShingleFilter::new(input, min_size, max_size, output_unigrams))
+            }
         }
     }
 }
@@ -105,6 +115,14 @@ impl Serialize for FilterSpec {
                     "type": "asciifolding",
                 })
             }
+            FilterSpec::Shingle{min_size, max_size, output_unigrams} => {
+                json!({
+                    "type": "shingle",
+                    "min_shingle_size": min_size,
+                    "max_shingle_size": max_size,
+                    "output_unigrams": output_unigrams,
+                })
+            }
         };
 
         json.serialize(serializer)
@@ -0,0 +1,120 @@
+//! Generates "shingles" (runs of consecutive tokens) from a token stream
+
+use std::collections::VecDeque;
+use std::str;
+
+use search::{Term, Token};
+
+
+pub struct ShingleFilter<'a> {
+    tokens: Box<Iterator<Item=Token> + 'a>,
+    min_size: usize,
+    max_size: usize,
+    output_unigrams: bool,
+    window: VecDeque<Token>,
+    output_buffer: VecDeque<Token>,
+}
+
+
+impl<'a> ShingleFilter<'a> {
+    pub fn new(tokens: Box<Iterator<Item=Token> +'a >, min_size: usize, max_size: usize, output_unigrams: bool) -> ShingleFilter<'a> {
+        ShingleFilter {
+            tokens: tokens,
+            min_size: min_size,
+            max_size: max_size,
+            output_unigrams: output_unigrams,
+            window: VecDeque::new(),
+            output_buffer: VecDeque::new(),
+        }
+    }
+}
+
+
+impl<'a> Iterator for ShingleFilter<'a> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        while self.output_buffer.is_empty() {
+            // Fill the window so it has enough tokens to make the largest shingle
+            while self.window.len() < self.max_size {
+                match self.tokens.next() {
+                    Some(token) => self.window.push_back(token),
+                    None => break,
+                }
+            }
+
+            // Generate shingles that start at the first token in the window
+            let first_token = match self.window.pop_front() {
+                Some(token) => token,
+                None => return None,
+            };
+
+            if self.output_unigrams {
+                self.output_buffer.push_back(first_token.clone());
+            }
+
+            let mut shingle = String::from_utf8_lossy(first_token.term.as_bytes()).into_owned();
+            for (i, token) in self.window.iter().enumerate() {
+                if let Ok(word) = str::from_utf8(token.term.as_bytes()) {
+                    shingle.push(' ');
+                    shingle.push_str(word);
+                }
+
+                if i + 2 >= self.min_size {
+                    self.output_buffer.push_back(Token {
+                        term: Term::from_string(&shingle),
+                        position: first_token.position,
+                    });
+                }
+            }
+        }
+
+        self.output_buffer.pop_front()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use search::{Term, Token};
+
+    use super::ShingleFilter;
+
+    #[test]
+    fn test_shingle_filter() {
+        let mut tokens: Vec<Token> = vec![
+            Token { term: Term::from_string("quick"), position: 1 },
+            Token { term: Term::from_string("brown"), position: 2 },
+            Token { term: Term::from_string("fox"), position: 3 },
+        ];
+
+        let token_filter = ShingleFilter::new(Box::new(tokens.drain(..)), 2, 2, false);
+        let tokens = token_filter.collect::<Vec<Token>>();
+
+        assert_eq!(tokens, vec![
+            Token { term: Term::from_string("quick brown"), position: 1 },
+            Token { term: Term::from_string("brown fox"), position: 2 },
+        ]);
+    }
+
+    #[test]
+    fn test_shingle_filter_with_unigrams() {
+        let mut tokens: Vec<Token> = vec![
+            Token { term: Term::from_string("quick"), position: 1 },
+            Token { term: Term::from_string("brown"), position: 2 },
+            Token { term: Term::from_string("fox"), position: 3 },
+        ];
+
+        let token_filter = ShingleFilter::new(Box::new(tokens.drain(..)), 2, 3, true);
+        let tokens = token_filter.collect::<Vec<Token>>();
+
+        assert_eq!(tokens, vec![
+            Token { term: Term::from_string("quick"), position: 1 },
+            Token { term: Term::from_string("quick brown"), position: 1 },
+            Token { term: Term::from_string("quick brown fox"), position: 1 },
+            Token { term: Term::from_string("brown"), position: 2 },
+            Token { term: Term::from_string("brown fox"), position: 2 },
+            Token { term: Term::from_string("fox"), position: 3 },
+        ]);
+    }
+}
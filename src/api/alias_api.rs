@@ -4,7 +4,8 @@ use api::persistent;
 use api::iron::prelude::*;
 use api::iron::status;
 use api::router::Router;
-use api::utils::json_response;
+use api::utils::{json_response, audit};
+use audit::AuditEventType;
 
 
 pub fn view_get_global_alias(req: &mut Request) -> IronResult<Response> {
@@ -93,5 +94,7 @@ pub fn view_put_alias(req: &mut Request) -> IronResult<Response> {
         }
     }
 
+    audit(system, req, AuditEventType::PrivilegedApiAccess, None);
+
     Ok(json_response(status::Ok, json!({"acknowledged": true})))
 }
@@ -0,0 +1,148 @@
+use std::io::Read;
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+use serde_json;
+use search::collectors::top_score::TopScoreCollector;
+
+use bench::{Timings, load_fixture, merge_all_segments, duration_millis};
+use document::DocumentSource;
+use query_parser::{QueryBuildContext, parse as parse_query};
+
+use api::persistent;
+use api::iron::prelude::*;
+use api::iron::status;
+use api::router::Router;
+use api::utils::{json_response, audit};
+use audit::AuditEventType;
+
+
+/// Runs benchmarks against an index
+///
+/// This is for developers, to compare the performance of different builds on
+/// the same data. The fixture is indexed into the given mapping first.
+pub fn view_post_bench(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+
+    // Get index
+    let cluster_metadata = system.metadata.read().unwrap();
+    let index = get_index_or_404!(cluster_metadata, *index_name);
+    let index_metadata = index.metadata.read().unwrap();
+
+    let data = match json_from_request_body!(req) {
+        Some(data) => data,
+        None => return Ok(json_response(status::BadRequest, json!({"message": "No data"}))),
+    };
+
+    let iterations = match data.get("iterations") {
+        Some(iterations) => match iterations.as_u64() {
+            Some(iterations) if iterations > 0 => iterations,
+            _ => return Ok(json_response(status::BadRequest, json!({"message": "iterations must be a positive integer"}))),
+        },
+        None => 10,
+    };
+
+    let mut response = serde_json::Map::new();
+
+    // Indexing
+    if let Some(fixture_path) = data.get("fixture") {
+        let fixture_path = match fixture_path.as_str() {
+            Some(fixture_path) => fixture_path,
+            None => return Ok(json_response(status::BadRequest, json!({"message": "fixture must be a string"}))),
+        };
+
+        let mapping = match data.get("mapping").and_then(|mapping| mapping.as_str()).and_then(|mapping| index_metadata.mappings.get(mapping)) {
+            Some(mapping) => mapping,
+            None => return Ok(json_response(status::BadRequest, json!({"message": "mapping must be the name of a mapping in the index"}))),
+        };
+
+        let documents = match load_fixture(fixture_path) {
+            Ok(documents) => documents,
+            Err(error) => return Ok(json_response(status::BadRequest, json!({"message": format!("{}", error)}))),
+        };
+
+        audit(system, req, AuditEventType::PrivilegedApiAccess, Some(*index_name));
+
+        let start = Instant::now();
+        for document in documents.iter() {
+            let document_source = DocumentSource {
+                key: &document.key,
+                data: &document.data,
+            };
+
+            let doc = match document_source.prepare(mapping) {
+                Ok(doc) => doc,
+                Err(error) => return Ok(json_response(status::BadRequest, json!({"message": format!("Couldn't index document {:?}: {:?}", document.key, error)}))),
+            };
+
+            index.insert_document(index_metadata.mode, &doc).unwrap();
+        }
+        index.sync_after_request(index_metadata.translog_durability).unwrap();
+        let took = duration_millis(start.elapsed());
+
+        response.insert("indexing".to_string(), json!({
+            "docs": documents.len(),
+            "took_ms": took,
+            "docs_per_second": if took > 0.0 { documents.len() as f64 * 1000.0 / took } else { 0.0 },
+        }));
+    }
+
+    // Queries, grouped by the type of query
+    if let Some(queries) = data.get("queries") {
+        let queries = match queries.as_array() {
+            Some(queries) => queries,
+            None => return Ok(json_response(status::BadRequest, json!({"message": "queries must be an array"}))),
+        };
+
+        let index_reader = index.store.reader();
+        let mut timings_by_type = BTreeMap::new();
+
+        for query_json in queries.iter() {
+            let query_type = match query_json.as_object().and_then(|object| object.keys().next()) {
+                Some(query_type) => query_type.clone(),
+                None => return Ok(json_response(status::BadRequest, json!({"message": "Query error"}))),
+            };
+
+            let context = QueryBuildContext::new().set_index(index).set_index_metadata(&index_metadata).set_cluster_metadata(&cluster_metadata);
+            let query = match parse_query(query_json).and_then(|query| query.check(&context).map(|_| query)) {
+                Ok(query) => query.build(&context, &index_reader.schema()),
+                Err(_) => return Ok(json_response(status::BadRequest, json!({"message": "Query error"}))),
+            };
+
+            let timings = timings_by_type.entry(query_type).or_insert_with(Timings::new);
+            for _ in 0..iterations {
+                timings.time(|| {
+                    let mut collector = TopScoreCollector::new(10);
+                    index_reader.search(&mut collector, &query).unwrap();
+                });
+            }
+        }
+
+        let mut queries_json = serde_json::Map::new();
+        for (query_type, timings) in timings_by_type {
+            queries_json.insert(query_type, timings.as_json());
+        }
+
+        response.insert("queries".to_string(), serde_json::Value::Object(queries_json));
+    }
+
+    // Segment merges
+    // Merges aren't safe to run alongside each other, so this takes the merge pool's
+    // only slot and the maintenance task skips merging until it's done
+    if data.get("merge").and_then(|merge| merge.as_bool()).unwrap_or(false) {
+        let _permit = acquire_thread_pool!(system.thread_pools.merge);
+        let start = Instant::now();
+        let segments = match merge_all_segments(index) {
+            Ok(segments) => segments,
+            Err(error) => return Ok(json_response(status::InternalServerError, json!({"message": format!("Couldn't merge segments: {}", error)}))),
+        };
+
+        response.insert("merge".to_string(), json!({
+            "segments": segments,
+            "took_ms": duration_millis(start.elapsed()),
+        }));
+    }
+
+    Ok(json_response(status::Ok, serde_json::Value::Object(response)))
+}
@@ -2,28 +2,114 @@ use std::io::Read;
 use std::collections::HashMap;
 
 use serde_json;
+use uuid::Uuid;
 
 use document::DocumentSource;
+use ingest::{IngestDocument, run_pipelines};
+use index::Index;
+use index::metadata::{IndexMode, TranslogDurability};
 
 use api::persistent;
 use api::iron::prelude::*;
 use api::iron::status;
-use api::utils::{json_response};
+use api::utils::{json_response, read_query_parameter};
 use api::router::Router;
 
 
+/// Gives documents in append-only indices an ID. These aren't looked up, so any
+/// unique value will do
+///
+/// Append-only indices can't check that an ID given in the request is unique,
+/// so those are rejected rather than risk indexing the same ID twice
+fn generate_doc_id(doc_id: Option<&str>, mode: IndexMode) -> Result<String, String> {
+    match (doc_id, mode) {
+        (Some(doc_id), IndexMode::Standard) => Ok(doc_id.to_string()),
+        (Some(_), IndexMode::AppendOnly) => Err("Documents can't be given an _id in an append only index".to_string()),
+        (None, IndexMode::AppendOnly) => Ok(Uuid::new_v4().simple().to_string()),
+        (None, IndexMode::Standard) => Err("Documents must have an _id unless the index is append only".to_string()),
+    }
+}
+
+
+/// Works out how to insert a document given its key before and after the ingest
+/// pipelines ran
+///
+/// Append-only indices skip the key lookup as the keys they give documents are
+/// unique. A key given by a pipeline, such as a fingerprint, repeats when the same
+/// document is sent again, so it's looked up like in any other index.
+fn insert_mode(mode: IndexMode, generated_key: &str, doc_key: &str) -> IndexMode {
+    if generated_key == doc_key {
+        mode
+    } else {
+        IndexMode::Standard
+    }
+}
+
+
+/// What happened to the document of an action
+#[derive(Debug)]
+enum BulkItemResult {
+    Created,
+
+    /// An ingest pipeline dropped the document
+    Noop,
+
+    /// The action failed, the rest of the request carries on
+    Failed(String),
+}
+
+
+/// Builds the entry for an action in the "items" array of the response
+fn bulk_item(action_params: &serde_json::Map<String, serde_json::Value>, doc_id: Option<&str>, result: BulkItemResult) -> HashMap<&'static str, serde_json::Map<String, serde_json::Value>> {
+    let mut item_params = action_params.clone();
+    if let Some(doc_id) = doc_id {
+        item_params.insert("_id".to_string(), json!(doc_id));
+    }
+
+    match result {
+        BulkItemResult::Created => {
+            item_params.insert("result".to_string(), json!("created"));
+            item_params.insert("status".to_string(), json!(201));
+        }
+        BulkItemResult::Noop => {
+            item_params.insert("result".to_string(), json!("noop"));
+            item_params.insert("status".to_string(), json!(200));
+        }
+        BulkItemResult::Failed(error) => {
+            item_params.insert("status".to_string(), json!(400));
+            item_params.insert("error".to_string(), json!({"reason": error}));
+        }
+    }
+
+    let mut item = HashMap::new();
+    // TODO: "create" may not always be right
+    item.insert("create", item_params);
+    item
+}
+
+
 pub fn view_post_bulk(req: &mut Request) -> IronResult<Response> {
     let ref system = get_system!(req);
+    let _permit = acquire_thread_pool!(system.thread_pools.index);
 
     // Lock cluster metedata
     let cluster_metadata = system.metadata.read().unwrap();
 
+    // Make sure enough copies are active before writing anything
+    wait_for_active_shards!(req);
+
+    // Pipeline to run documents through, unless overridden by the action
+    let url_pipeline_name = read_query_parameter(req, "pipeline");
+
     // Load data from body
     let mut payload = String::new();
     req.body.read_to_string(&mut payload).unwrap();
 
     let mut items = Vec::new();
 
+    // The indices that have been written to, these are synced once all actions are done
+    let mut written_indices: Vec<(&Index, TranslogDurability)> = Vec::new();
+
     // Iterate
     let mut payload_lines = payload.split('\n');
     loop {
@@ -48,7 +134,7 @@ pub fn view_post_bulk(req: &mut Request) -> IronResult<Response> {
                                        .as_object()
                                        .unwrap();
 
-        let doc_id = action_params.get("_id").unwrap().as_str().unwrap();
+        let doc_id = action_params.get("_id").and_then(|doc_id| doc_id.as_str());
         let doc_type = action_params.get("_type").unwrap().as_str().unwrap();
         let doc_index = action_params.get("_index").unwrap().as_str().unwrap();
 
@@ -60,6 +146,13 @@ pub fn view_post_bulk(req: &mut Request) -> IronResult<Response> {
                 // Find index
                 let index = get_index_or_404!(cluster_metadata, doc_index);
                 let index_metadata = index.metadata.read().unwrap();
+                let doc_id = match generate_doc_id(doc_id, index_metadata.mode) {
+                    Ok(doc_id) => doc_id,
+                    Err(error) => {
+                        items.push(bulk_item(action_params, doc_id, BulkItemResult::Failed(error)));
+                        continue;
+                    }
+                };
 
                 let doc = {
                     // Find mapping
@@ -70,21 +163,52 @@ pub fn view_post_bulk(req: &mut Request) -> IronResult<Response> {
                         }
                     };
 
-                    // Create document
-                    let document_source = DocumentSource {
-                        key: doc_id,
-                        data: doc_json.as_object().unwrap(),
+                    let ingest_doc = IngestDocument {
+                        key: doc_id.clone(),
+                        data: doc_json.as_object().unwrap().clone(),
+                    };
+
+                    // Run the document through the ingest pipelines
+                    // A pipeline set on the action takes precedence over the one in the URL
+                    let requested_pipeline = match action_params.get("pipeline").and_then(|name| name.as_str()) {
+                        Some(name) => Some(name.to_string()),
+                        None => url_pipeline_name.clone(),
+                    };
+
+                    // A failed pipeline only fails this item
+                    let pipeline_names = index_metadata.get_ingest_pipelines(requested_pipeline.as_ref().map(|name| name.as_str()));
+                    let ingest_doc = match run_pipelines(&cluster_metadata.pipelines, &pipeline_names, ingest_doc) {
+                        Ok(ingest_doc) => ingest_doc,
+                        Err(error) => {
+                            items.push(bulk_item(action_params, Some(&doc_id), BulkItemResult::Failed(error)));
+                            continue;
+                        }
                     };
-                    document_source.prepare(mapping).unwrap()
+
+                    // Create document
+                    ingest_doc.map(|ingest_doc| {
+                        let document_source = DocumentSource {
+                            key: &ingest_doc.key,
+                            data: &ingest_doc.data,
+                        };
+                        document_source.prepare(mapping).unwrap()
+                    })
                 };
 
-                index.store.insert_or_update_document(&doc).unwrap();
+                // The document may have been dropped by the pipeline, or given a
+                // new key by it. The item gives the key it was indexed under
+                match doc {
+                    Some(doc) => {
+                        index.insert_document(insert_mode(index_metadata.mode, &doc_id, &doc.key), &doc).unwrap();
 
-                // Insert into "items" array
-                let mut item = HashMap::new();
-                // TODO: "create" may not always be right
-                item.insert("create", action_params.clone());
-                items.push(item);
+                        if !written_indices.iter().any(|&(written_index, _)| written_index.id() == index.id()) {
+                            written_indices.push((index, index_metadata.translog_durability));
+                        }
+
+                        items.push(bulk_item(action_params, Some(&doc.key), BulkItemResult::Created));
+                    }
+                    None => items.push(bulk_item(action_params, Some(&doc_id), BulkItemResult::Noop)),
+                }
             }
             _ => {
                 warn!(system.log, "unrecognised action! {}", action_name);
@@ -92,9 +216,16 @@ pub fn view_post_bulk(req: &mut Request) -> IronResult<Response> {
         }
     }
 
+    for (index, durability) in written_indices {
+        index.sync_after_request(durability).unwrap();
+    }
+
+    let errors = items.iter().any(|item| item.values().any(|item_params| item_params.contains_key("error")));
+
     return Ok(json_response(status::Ok,
                             json!({
                                 "took": items.len(),
+                                "errors": errors,
                                 "items": items,
                             })));
 }
@@ -103,6 +234,7 @@ pub fn view_post_bulk(req: &mut Request) -> IronResult<Response> {
 pub fn view_post_index_bulk(req: &mut Request) -> IronResult<Response> {
     let ref system = get_system!(req);
     let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+    let _permit = acquire_thread_pool!(system.thread_pools.index);
 
     // Lock cluster metedata
     let cluster_metadata = system.metadata.read().unwrap();
@@ -111,6 +243,12 @@ pub fn view_post_index_bulk(req: &mut Request) -> IronResult<Response> {
     let index = get_index_or_404!(cluster_metadata, *index_name);
     let index_metadata = index.metadata.read().unwrap();
 
+    // Make sure enough copies are active before writing anything
+    wait_for_active_shards!(req);
+
+    // Pipeline to run documents through, unless overridden by the action
+    let url_pipeline_name = read_query_parameter(req, "pipeline");
+
     // Load data from body
     let mut payload = String::new();
     req.body.read_to_string(&mut payload).unwrap();
@@ -141,7 +279,7 @@ pub fn view_post_index_bulk(req: &mut Request) -> IronResult<Response> {
                                        .as_object()
                                        .unwrap();
 
-        let doc_id = action_params.get("_id").unwrap().as_str().unwrap();
+        let doc_id = action_params.get("_id").and_then(|doc_id| doc_id.as_str());
         let doc_type = action_params.get("_type").unwrap().as_str().unwrap();
 
         match action_name.as_ref() {
@@ -149,6 +287,14 @@ pub fn view_post_index_bulk(req: &mut Request) -> IronResult<Response> {
                 let doc_line = payload_lines.next();
                 let doc_json = parse_json!(&doc_line.unwrap());;
 
+                let doc_id = match generate_doc_id(doc_id, index_metadata.mode) {
+                    Ok(doc_id) => doc_id,
+                    Err(error) => {
+                        items.push(bulk_item(action_params, doc_id, BulkItemResult::Failed(error)));
+                        continue;
+                    }
+                };
+
                 let doc = {
                     // Find mapping
                     let mapping = match index_metadata.mappings.get(doc_type) {
@@ -158,21 +304,47 @@ pub fn view_post_index_bulk(req: &mut Request) -> IronResult<Response> {
                         }
                     };
 
-                    // Create document
-                    let document_source = DocumentSource {
-                        key: doc_id,
-                        data: doc_json.as_object().unwrap(),
+                    let ingest_doc = IngestDocument {
+                        key: doc_id.clone(),
+                        data: doc_json.as_object().unwrap().clone(),
                     };
-                    document_source.prepare(mapping).unwrap()
-                };
 
-                index.store.insert_or_update_document(&doc).unwrap();
+                    // Run the document through the ingest pipelines
+                    // A pipeline set on the action takes precedence over the one in the URL
+                    let requested_pipeline = match action_params.get("pipeline").and_then(|name| name.as_str()) {
+                        Some(name) => Some(name.to_string()),
+                        None => url_pipeline_name.clone(),
+                    };
+
+                    // A failed pipeline only fails this item
+                    let pipeline_names = index_metadata.get_ingest_pipelines(requested_pipeline.as_ref().map(|name| name.as_str()));
+                    let ingest_doc = match run_pipelines(&cluster_metadata.pipelines, &pipeline_names, ingest_doc) {
+                        Ok(ingest_doc) => ingest_doc,
+                        Err(error) => {
+                            items.push(bulk_item(action_params, Some(&doc_id), BulkItemResult::Failed(error)));
+                            continue;
+                        }
+                    };
+
+                    // Create document
+                    ingest_doc.map(|ingest_doc| {
+                        let document_source = DocumentSource {
+                            key: &ingest_doc.key,
+                            data: &ingest_doc.data,
+                        };
+                        document_source.prepare(mapping).unwrap()
+                    })
+                };
 
-                // Insert into "items" array
-                let mut item = HashMap::new();
-                // TODO: "create" may not always be right
-                item.insert("create", action_params.clone());
-                items.push(item);
+                // The document may have been dropped by the pipeline, or given a
+                // new key by it. The item gives the key it was indexed under
+                match doc {
+                    Some(doc) => {
+                        index.insert_document(insert_mode(index_metadata.mode, &doc_id, &doc.key), &doc).unwrap();
+                        items.push(bulk_item(action_params, Some(&doc.key), BulkItemResult::Created));
+                    }
+                    None => items.push(bulk_item(action_params, Some(&doc_id), BulkItemResult::Noop)),
+                }
             }
             _ => {
                 warn!(system.log, "unrecognised action! {}", action_name);
@@ -180,9 +352,57 @@ pub fn view_post_index_bulk(req: &mut Request) -> IronResult<Response> {
         }
     }
 
+    index.sync_after_request(index_metadata.translog_durability).unwrap();
+
+    let errors = items.iter().any(|item| item.values().any(|item_params| item_params.contains_key("error")));
+
     return Ok(json_response(status::Ok,
                             json!({
                                 "took": items.len(),
+                                "errors": errors,
                                 "items": items,
                             })));
 }
+
+
+#[cfg(test)]
+mod tests {
+    use index::metadata::IndexMode;
+
+    use super::{generate_doc_id, insert_mode, bulk_item, BulkItemResult};
+
+    #[test]
+    fn test_generate_doc_id() {
+        assert_eq!(generate_doc_id(Some("a"), IndexMode::Standard), Ok("a".to_string()));
+        assert!(generate_doc_id(None, IndexMode::Standard).is_err());
+
+        // Append-only indices always give documents their own ID
+        assert_eq!(generate_doc_id(None, IndexMode::AppendOnly).unwrap().len(), 32);
+        assert!(generate_doc_id(Some("a"), IndexMode::AppendOnly).is_err());
+    }
+
+    #[test]
+    fn test_insert_mode() {
+        assert_eq!(insert_mode(IndexMode::AppendOnly, "a", "a"), IndexMode::AppendOnly);
+        assert_eq!(insert_mode(IndexMode::Standard, "a", "a"), IndexMode::Standard);
+
+        // Keys set by a pipeline are looked up even in append-only indices
+        assert_eq!(insert_mode(IndexMode::AppendOnly, "a", "b"), IndexMode::Standard);
+    }
+
+    #[test]
+    fn test_bulk_item() {
+        let action_params = json!({"_index": "test", "_type": "doc", "_id": "a"});
+        let action_params = action_params.as_object().unwrap();
+
+        // The key the document was indexed under replaces the one in the action
+        let item = bulk_item(action_params, Some("b"), BulkItemResult::Created);
+        assert_eq!(json!(item), json!({"create": {"_index": "test", "_type": "doc", "_id": "b", "result": "created", "status": 201}}));
+
+        let item = bulk_item(action_params, Some("a"), BulkItemResult::Noop);
+        assert_eq!(json!(item), json!({"create": {"_index": "test", "_type": "doc", "_id": "a", "result": "noop", "status": 200}}));
+
+        let item = bulk_item(action_params, Some("a"), BulkItemResult::Failed("no".to_string()));
+        assert_eq!(json!(item), json!({"create": {"_index": "test", "_type": "doc", "_id": "a", "status": 400, "error": {"reason": "no"}}}));
+    }
+}
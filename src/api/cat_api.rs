@@ -0,0 +1,99 @@
+use serde_json;
+
+use api::persistent;
+use api::iron::prelude::*;
+use api::iron::status;
+use api::utils::{json_response, read_query_parameter};
+
+
+const INDICES_COLUMNS: [&'static str; 6] = ["health", "status", "index", "uuid", "creation.date", "version.created"];
+
+
+/// Lays out rows as a table with the columns lined up
+fn format_table(columns: &[&str], rows: &[Vec<String>], with_header: bool) -> String {
+    let mut widths = columns.iter().map(|column| if with_header { column.len() } else { 0 }).collect::<Vec<_>>();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let mut lines = Vec::new();
+    if with_header {
+        lines.push(columns.iter().map(|column| column.to_string()).collect::<Vec<_>>());
+    }
+    lines.extend(rows.iter().cloned());
+
+    let mut table = String::new();
+    for line in lines {
+        let cells = line.iter().zip(widths.iter()).map(|(cell, width)| format!("{:1$}", cell, width)).collect::<Vec<_>>();
+        table.push_str(cells.join(" ").trim_right());
+        table.push('\n');
+    }
+
+    table
+}
+
+
+pub fn view_get_cat_indices(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let cluster_metadata = system.metadata.read().unwrap();
+
+    let mut rows = cluster_metadata.indices.values().map(|index| {
+        let metadata = index.metadata.read().unwrap();
+
+        vec![
+            "green".to_string(),
+            "open".to_string(),
+            index.canonical_name().to_string(),
+            metadata.uuid.to_string(),
+            metadata.creation_date_millis().to_string(),
+            metadata.version_created.clone(),
+        ]
+    }).collect::<Vec<_>>();
+    rows.sort_by(|a, b| a[2].cmp(&b[2]));
+
+    if read_query_parameter(req, "format").as_ref().map(|format| format.as_ref()) == Some("json") {
+        let json = rows.iter().map(|row| {
+            let mut object = serde_json::Map::new();
+            for (column, cell) in INDICES_COLUMNS.iter().zip(row.iter()) {
+                object.insert(column.to_string(), serde_json::Value::String(cell.clone()));
+            }
+
+            serde_json::Value::Object(object)
+        }).collect::<Vec<_>>();
+
+        return Ok(json_response(status::Ok, serde_json::Value::Array(json)));
+    }
+
+    let with_header = read_query_parameter(req, "v").map_or(false, |v| v != "false");
+    let mut response = Response::with((status::Ok, format_table(&INDICES_COLUMNS, &rows, with_header)));
+    response.headers.set_raw("Content-Type", vec![b"text/plain; charset=UTF-8".to_vec()]);
+
+    Ok(response)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::format_table;
+
+    #[test]
+    fn test_format_table() {
+        let rows = vec![
+            vec!["green".to_string(), "logs".to_string(), "1".to_string()],
+            vec!["green".to_string(), "metrics-2019".to_string(), "22".to_string()],
+        ];
+
+        assert_eq!(format_table(&["health", "index", "n"], &rows, true), "\
+health index        n
+green  logs         1
+green  metrics-2019 22
+");
+
+        assert_eq!(format_table(&["health", "index", "n"], &rows, false), "\
+green logs         1
+green metrics-2019 22
+");
+    }
+}
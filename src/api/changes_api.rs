@@ -0,0 +1,82 @@
+use search::backends::rocksdb::change_log::ChangeOperation;
+
+use api::persistent;
+use api::iron::prelude::*;
+use api::iron::status;
+use api::router::Router;
+use api::utils::{json_response, read_query_parameter};
+
+
+/// The maximum number of changes returned by default
+const DEFAULT_SIZE: usize = 1000;
+
+
+pub fn view_get_changes(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+
+    // Get index
+    let cluster_metadata = system.metadata.read().unwrap();
+    let index = get_index_or_404!(cluster_metadata, *index_name);
+
+    // Changes are returned starting from the one after "since"
+    let since = match read_query_parameter(req, "since") {
+        Some(since) => {
+            match since.parse::<u64>() {
+                Ok(since) => since,
+                Err(_) => {
+                    return Ok(json_response(status::BadRequest, json!({"message": "since must be a positive integer"})));
+                }
+            }
+        }
+        None => 0,
+    };
+
+    let size = match read_query_parameter(req, "size") {
+        Some(size) => {
+            match size.parse::<usize>() {
+                Ok(size) => size,
+                Err(_) => {
+                    return Ok(json_response(status::BadRequest, json!({"message": "size must be a positive integer"})));
+                }
+            }
+        }
+        None => DEFAULT_SIZE,
+    };
+
+    let index_reader = index.store.reader();
+
+    // If some of the requested history was trimmed, the caller will have to resync
+    let min_retained_seq_no = index_reader.min_retained_seq_no();
+    if since + 1 < min_retained_seq_no {
+        return Ok(json_response(status::Gone, json!({
+            "message": "changes are no longer retained",
+            "min_retained_seq_no": min_retained_seq_no,
+        })));
+    }
+
+    let changes = match index_reader.changes_since(since, size) {
+        Ok(changes) => changes,
+        Err(e) => {
+            return Ok(json_response(status::InternalServerError, json!({"message": format!("unable to read changes: {}", e)})));
+        }
+    };
+
+    let changes_json = changes.iter().map(|change| {
+        json!({
+            "_seq_no": change.seq_no,
+            "_id": change.key,
+            "op": match change.operation {
+                ChangeOperation::Index => "index",
+                ChangeOperation::Delete => "delete",
+            },
+            "time": change.time,
+        })
+    }).collect::<Vec<_>>();
+
+    return Ok(json_response(status::Ok, json!({
+        "max_seq_no": index_reader.max_seq_no(),
+        "min_retained_seq_no": min_retained_seq_no,
+        "changes": changes_json,
+    })));
+}
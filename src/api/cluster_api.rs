@@ -0,0 +1,136 @@
+use std::io::Read;
+use std::collections::BTreeMap;
+
+use serde_json;
+
+use cluster::remote::parse as parse_remote_cluster;
+
+use api::persistent;
+use api::iron::prelude::*;
+use api::iron::status;
+use api::utils::{json_response, audit};
+use audit::AuditEventType;
+
+
+pub fn view_put_cluster_settings(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+
+    // Load data from body
+    let data = match json_from_request_body!(req) {
+        Some(data) => data,
+        None => {
+            return Ok(json_response(status::BadRequest, json!({"message": "No data"})));
+        }
+    };
+
+    // The maximum number of searches that can run at once
+    for scope in &["persistent", "transient"] {
+        if let Some(max_concurrent_searches_json) = data.pointer(&format!("/{}/search/max_concurrent_searches", scope)) {
+            match max_concurrent_searches_json.as_u64() {
+                Some(max_concurrent_searches) if max_concurrent_searches > 0 => {
+                    let queue_size = system.thread_pools.search.stats().queue_size;
+                    system.thread_pools.search.resize(max_concurrent_searches as usize, queue_size);
+                    info!(system.log, "changed max concurrent searches"; "max_concurrent_searches" => max_concurrent_searches);
+                }
+                _ => {
+                    return Ok(json_response(status::BadRequest, json!({"message": "search.max_concurrent_searches must be a positive integer"})));
+                }
+            }
+        }
+    }
+
+    // Thread pools
+    for scope in &["persistent", "transient"] {
+        let thread_pools = match data.pointer(&format!("/{}/thread_pool", scope)).and_then(|thread_pools| thread_pools.as_object()) {
+            Some(thread_pools) => thread_pools,
+            None => continue,
+        };
+
+        for (pool_name, pool_json) in thread_pools.iter() {
+            let thread_pool = match system.thread_pools.get(pool_name) {
+                Some(thread_pool) => thread_pool,
+                None => {
+                    return Ok(json_response(status::BadRequest, json!({"message": format!("Unrecognised thread pool: {}", pool_name)})));
+                }
+            };
+
+            // Either setting can be left out to keep its current value
+            let stats = thread_pool.stats();
+            let size = pool_json.get("size").map(|size| size.as_u64()).unwrap_or(Some(stats.size as u64));
+            let queue_size = pool_json.get("queue_size").map(|queue_size| queue_size.as_u64()).unwrap_or(Some(stats.queue_size as u64));
+
+            match (size, queue_size) {
+                (Some(size), Some(queue_size)) if size > 0 => {
+                    thread_pool.resize(size as usize, queue_size as usize);
+                    info!(system.log, "resized thread pool"; "pool" => pool_name.as_str(), "size" => size, "queue_size" => queue_size);
+                }
+                _ => {
+                    return Ok(json_response(status::BadRequest, json!({"message": format!("Thread pool {} needs a positive size and a queue_size of 0 or more", pool_name)})));
+                }
+            }
+        }
+    }
+
+    // Lock cluster metadata
+    let mut cluster_metadata = system.metadata.write().unwrap();
+
+    // Settings aren't saved to disk yet so persistent and transient settings are treated the same
+    for scope in &["persistent", "transient"] {
+        let remotes = match data.pointer(&format!("/{}/cluster/remote", scope)).and_then(|remotes| remotes.as_object()) {
+            Some(remotes) => remotes,
+            None => continue,
+        };
+
+        for (cluster_name, remote_json) in remotes.iter() {
+            // Setting a remote to null removes it
+            if remote_json.is_null() {
+                cluster_metadata.remote_clusters.remove(cluster_name);
+                info!(system.log, "removed remote cluster"; "cluster" => cluster_name.as_str());
+                continue;
+            }
+
+            let remote_cluster = match parse_remote_cluster(remote_json) {
+                Ok(remote_cluster) => remote_cluster,
+                Err(error) => {
+                    return Ok(json_response(status::BadRequest, json!({"message": format!("Couldn't parse remote cluster {}: {:?}", cluster_name, error)})));
+                }
+            };
+
+            cluster_metadata.remote_clusters.insert(cluster_name.clone(), remote_cluster);
+            info!(system.log, "registered remote cluster"; "cluster" => cluster_name.as_str());
+        }
+    }
+
+    audit(system, req, AuditEventType::PrivilegedApiAccess, None);
+
+    return Ok(json_response(status::Ok, json!({"acknowledged": true})));
+}
+
+
+pub fn view_get_remote_info(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+
+    // Lock cluster metadata
+    let cluster_metadata = system.metadata.read().unwrap();
+
+    let mut remotes_json = BTreeMap::new();
+    for (cluster_name, remote_cluster) in cluster_metadata.remote_clusters.iter() {
+        remotes_json.insert(cluster_name.clone(), json!({
+            "seeds": remote_cluster.seeds,
+        }));
+    }
+
+    return Ok(json_response(status::Ok, json!(remotes_json)));
+}
+
+
+pub fn view_get_thread_pool_stats(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+
+    let mut thread_pools_json = BTreeMap::new();
+    for thread_pool in system.thread_pools.iter() {
+        thread_pools_json.insert(thread_pool.name(), thread_pool.stats().as_json());
+    }
+
+    return Ok(json_response(status::Ok, json!({"thread_pool": thread_pools_json})));
+}
@@ -3,12 +3,14 @@ use std::io::Read;
 use serde_json;
 
 use document::DocumentSource;
+use ingest::{IngestDocument, run_pipelines};
+use index::metadata::IndexMode;
 
 use api::persistent;
 use api::iron::prelude::*;
 use api::iron::status;
 use api::router::Router;
-use api::utils::json_response;
+use api::utils::{json_response, read_query_parameter};
 
 
 pub fn view_get_doc(req: &mut Request) -> IronResult<Response> {
@@ -27,6 +29,10 @@ pub fn view_get_doc(req: &mut Request) -> IronResult<Response> {
         return Ok(json_response(status::NotFound, json!({"message": "Mapping not found"})));
     }
 
+    if index_metadata.mode == IndexMode::AppendOnly {
+        return Ok(json_response(status::BadRequest, json!({"message": "Documents in an append only index can't be fetched by _id"})));
+    }
+
     // Find document
     /*
     let index_reader = index.store.reader();
@@ -55,12 +61,22 @@ pub fn view_put_doc(req: &mut Request) -> IronResult<Response> {
     let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
     let ref mapping_name = read_path_parameter!(req, "mapping").unwrap_or("");
     let ref doc_key = read_path_parameter!(req, "doc").unwrap_or("");
+    let _permit = acquire_thread_pool!(system.thread_pools.index);
 
     // Get index
     let cluster_metadata = system.metadata.read().unwrap();
     let index = get_index_or_404!(cluster_metadata, *index_name);
     let index_metadata = index.metadata.read().unwrap();
 
+    // Append-only indices can't check that the ID is unique, so they give
+    // documents their own IDs. Use the bulk API without an _id instead
+    if index_metadata.mode == IndexMode::AppendOnly {
+        return Ok(json_response(status::BadRequest, json!({"message": "Documents can't be given an _id in an append only index"})));
+    }
+
+    // Make sure enough copies are active before writing
+    wait_for_active_shards!(req);
+
     let doc = {
         // Find mapping
         let mapping = match index_metadata.mappings.get(*mapping_name) {
@@ -72,9 +88,28 @@ pub fn view_put_doc(req: &mut Request) -> IronResult<Response> {
 
         // Create document
         if let Some(data) = json_from_request_body!(req) {
+            let ingest_doc = IngestDocument {
+                key: doc_key.to_string(),
+                data: data.as_object().unwrap().clone(),
+            };
+
+            // Run the document through the ingest pipelines
+            let requested_pipeline = read_query_parameter(req, "pipeline");
+            let pipeline_names = index_metadata.get_ingest_pipelines(requested_pipeline.as_ref().map(|name| name.as_str()));
+            let ingest_doc = match run_pipelines(&cluster_metadata.pipelines, &pipeline_names, ingest_doc) {
+                Ok(Some(doc)) => doc,
+                Ok(None) => {
+                    // The pipeline dropped the document
+                    return Ok(json_response(status::Ok, json!({"result": "noop"})));
+                }
+                Err(error) => {
+                    return Ok(json_response(status::BadRequest, json!({"message": error})));
+                }
+            };
+
             let document_source = DocumentSource {
-                key: doc_key,
-                data: data.as_object().unwrap(),
+                key: &ingest_doc.key,
+                data: &ingest_doc.data,
             };
             document_source.prepare(mapping).unwrap()
         } else {
@@ -82,10 +117,11 @@ pub fn view_put_doc(req: &mut Request) -> IronResult<Response> {
         }
     };
 
-    index.store.insert_or_update_document(&doc).unwrap();
+    let seq_no = index.insert_document(index_metadata.mode, &doc).unwrap();
+    index.sync_after_request(index_metadata.translog_durability).unwrap();
 
     // TODO: {"_index":"wagtail","_type":"searchtests_searchtest","_id":"searchtests_searchtest:5378","_version":1,"created":true}
-    return Ok(json_response(status::Ok, json!({})));
+    return Ok(json_response(status::Ok, json!({"_seq_no": seq_no})));
 }
 
 
@@ -94,6 +130,7 @@ pub fn view_delete_doc(req: &mut Request) -> IronResult<Response> {
     let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
     let ref mapping_name = read_path_parameter!(req, "mapping").unwrap_or("");
     let ref doc_key = read_path_parameter!(req, "doc").unwrap_or("");
+    let _permit = acquire_thread_pool!(system.thread_pools.index);
 
     // Get index
     let cluster_metadata = system.metadata.read().unwrap();
@@ -105,13 +142,22 @@ pub fn view_delete_doc(req: &mut Request) -> IronResult<Response> {
         return Ok(json_response(status::NotFound, json!({"message": "Mapping not found"})));
     }
 
+    // Documents in append-only indices aren't indexed by their keys
+    if index_metadata.mode == IndexMode::AppendOnly {
+        return Ok(json_response(status::BadRequest, json!({"message": "Documents in an append only index can't be deleted by _id"})));
+    }
+
     // Make sure the document exists
     if !index.store.reader().contains_document_key(doc_key) {
         return Ok(json_response(status::NotFound, json!({"message": "Document not found"})));
     }
 
+    // Make sure enough copies are active before writing
+    wait_for_active_shards!(req);
+
     // Delete document
     index.store.remove_document_by_key(doc_key).unwrap();
+    index.sync_after_request(index_metadata.translog_durability).unwrap();
 
     return Ok(json_response(status::Ok, json!({})));
 }
@@ -0,0 +1,211 @@
+//! Response filtering with the "filter_path" parameter
+//!
+//! Any endpoint can be given a comma separated list of paths, such as
+//! "items.*.error,took", and only the parts of the response that are on one of
+//! these paths are sent back. Segments of a path can contain wildcards, and a
+//! "**" segment matches any number of keys. Arrays don't have a segment of their
+//! own, the path carries on into each of their items.
+
+use serde_json;
+use search::query::wildcard::WildcardPattern;
+
+use api::iron::prelude::*;
+use api::iron::typemap::Key;
+use api::iron::AfterMiddleware;
+use api::utils::read_query_parameter;
+
+
+/// The JSON of a response, kept so the response can be filtered after the view has run
+pub struct JsonBody;
+
+
+impl Key for JsonBody {
+    type Value = serde_json::Value;
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathSegment {
+    Key(WildcardPattern),
+    AnyDepth,
+}
+
+
+pub fn parse_filter_path(value: &str) -> Vec<Vec<PathSegment>> {
+    value.split(',')
+        .map(|path| path.trim())
+        .filter(|path| !path.is_empty())
+        .map(|path| {
+            path.split('.').map(|segment| {
+                match segment {
+                    "**" => PathSegment::AnyDepth,
+                    _ => PathSegment::Key(WildcardPattern::new(segment)),
+                }
+            }).collect()
+        })
+        .collect()
+}
+
+
+/// Finds what is left of each path once it has gone through the key
+fn follow_key<'a>(path: &'a [PathSegment], key: &str, remaining: &mut Vec<&'a [PathSegment]>) {
+    match path.first() {
+        Some(&PathSegment::AnyDepth) => {
+            if path.len() == 1 {
+                // A "**" at the end takes everything under it
+                remaining.push(&path[1..]);
+            } else {
+                remaining.push(path);
+                follow_key(&path[1..], key, remaining);
+            }
+        }
+        Some(&PathSegment::Key(ref pattern)) => {
+            if pattern.matches(key) {
+                remaining.push(&path[1..]);
+            }
+        }
+        None => {}
+    }
+}
+
+
+/// Removes the parts of the JSON that aren't on any of the paths. Returns None if nothing is left
+fn filter_value(value: &serde_json::Value, paths: &[&[PathSegment]]) -> Option<serde_json::Value> {
+    // Paths that have been followed to the end include everything under them
+    if paths.iter().any(|path| path.is_empty()) {
+        return Some(value.clone());
+    }
+
+    match *value {
+        serde_json::Value::Object(ref object) => {
+            let mut filtered = serde_json::Map::new();
+
+            for (key, child) in object.iter() {
+                let mut remaining = Vec::new();
+                for path in paths.iter() {
+                    follow_key(path, key, &mut remaining);
+                }
+
+                if remaining.is_empty() {
+                    continue;
+                }
+
+                if let Some(child) = filter_value(child, &remaining) {
+                    filtered.insert(key.clone(), child);
+                }
+            }
+
+            if filtered.is_empty() {
+                None
+            } else {
+                Some(serde_json::Value::Object(filtered))
+            }
+        }
+        serde_json::Value::Array(ref array) => {
+            let filtered = array.iter().filter_map(|item| filter_value(item, paths)).collect::<Vec<_>>();
+
+            if filtered.is_empty() {
+                None
+            } else {
+                Some(serde_json::Value::Array(filtered))
+            }
+        }
+        _ => None,
+    }
+}
+
+
+pub fn filter_json(value: &serde_json::Value, paths: &[Vec<PathSegment>]) -> serde_json::Value {
+    let paths = paths.iter().map(|path| &path[..]).collect::<Vec<_>>();
+    filter_value(value, &paths).unwrap_or_else(|| json!({}))
+}
+
+
+/// Filters the JSON responses of all views by the "filter_path" parameter
+pub struct FilterPath;
+
+
+impl AfterMiddleware for FilterPath {
+    fn after(&self, req: &mut Request, mut response: Response) -> IronResult<Response> {
+        let paths = match read_query_parameter(req, "filter_path") {
+            Some(filter_path) => parse_filter_path(&filter_path),
+            None => return Ok(response),
+        };
+
+        if let Some(json) = response.extensions.remove::<JsonBody>() {
+            let filtered = filter_json(&json, &paths);
+            response.set_mut(format!("{}", filtered));
+            response.extensions.insert::<JsonBody>(filtered);
+        }
+
+        Ok(response)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_filter_path, filter_json};
+
+    #[test]
+    fn test_filter_bulk_errors() {
+        let response = json!({
+            "took": 3,
+            "errors": true,
+            "items": [
+                {"index": {"_id": "1", "status": 201}},
+                {"index": {"_id": "2", "status": 400, "error": {"type": "mapper_parsing_exception"}}},
+            ],
+        });
+
+        assert_eq!(filter_json(&response, &parse_filter_path("items.*.error")), json!({
+            "items": [
+                {"index": {"error": {"type": "mapper_parsing_exception"}}},
+            ],
+        }));
+
+        assert_eq!(filter_json(&response, &parse_filter_path("took,items.*._id")), json!({
+            "took": 3,
+            "items": [
+                {"index": {"_id": "1"}},
+                {"index": {"_id": "2"}},
+            ],
+        }));
+    }
+
+    #[test]
+    fn test_filter_any_depth() {
+        let response = json!({
+            "hits": {
+                "total": {"value": 1, "relation": "eq"},
+                "hits": [{"_index": "logs", "_score": 1.0}],
+            },
+            "_shards": {"total": 1},
+        });
+
+        assert_eq!(filter_json(&response, &parse_filter_path("**.total")), json!({
+            "hits": {
+                "total": {"value": 1, "relation": "eq"},
+            },
+            "_shards": {"total": 1},
+        }));
+
+        assert_eq!(filter_json(&response, &parse_filter_path("hits.**")), json!({
+            "hits": {
+                "total": {"value": 1, "relation": "eq"},
+                "hits": [{"_index": "logs", "_score": 1.0}],
+            },
+        }));
+
+        assert_eq!(filter_json(&response, &parse_filter_path("h*.hits._index")), json!({
+            "hits": {
+                "hits": [{"_index": "logs"}],
+            },
+        }));
+    }
+
+    #[test]
+    fn test_filter_nothing_matches() {
+        assert_eq!(filter_json(&json!({"took": 3}), &parse_filter_path("items.*.error")), json!({}));
+    }
+}
@@ -2,18 +2,22 @@ use std::fs;
 use std::io::Read;
 
 use serde_json;
+use chrono::Utc;
 use search::backends::rocksdb::RocksDBStore;
+use search::schema::Schema;
 use uuid::Uuid;
 
 use index::Index;
 use index::metadata::IndexMetadata;
-use index::metadata::parse::parse as parse_index_metadata;
+use index::metadata::parse::{IndexMetadataParseError, parse as parse_index_metadata, parse_lenient as parse_index_metadata_lenient};
 
 use api::persistent;
 use api::iron::prelude::*;
 use api::iron::status;
 use api::router::Router;
-use api::utils::json_response;
+use api::utils::{json_response, read_query_parameter, audit};
+use audit::AuditEventType;
+use VERSION;
 
 
 pub fn view_get_index(req: &mut Request) -> IronResult<Response> {
@@ -40,6 +44,30 @@ pub fn view_get_index(req: &mut Request) -> IronResult<Response> {
 }
 
 
+pub fn view_get_index_settings(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+
+    // Get index
+    let cluster_metadata = system.metadata.read().unwrap();
+    let index = get_index_or_404!(cluster_metadata, *index_name);
+
+    let settings = match serde_json::to_value(&*index.metadata.read().unwrap()) {
+        Ok(json) => json.get("settings").cloned().unwrap_or_else(|| json!({})),
+        Err(_) => {
+            return Ok(json_response(status::InternalServerError, json!({
+                "message": "unable to serialise index metadata"
+            })));
+        }
+    };
+
+    let mut response = serde_json::Map::new();
+    response.insert(index.canonical_name().to_string(), json!({"settings": settings}));
+
+    return Ok(json_response(status::Ok, serde_json::Value::Object(response)));
+}
+
+
 pub fn view_put_index(req: &mut Request) -> IronResult<Response> {
     let ref system = get_system!(req);
     let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
@@ -59,19 +87,39 @@ pub fn view_put_index(req: &mut Request) -> IronResult<Response> {
         }
         None => {
             // Load metadata
+            // Unrecognised settings are rejected unless "strict=false" is given
+            let strict = read_query_parameter(req, "strict").map_or(true, |strict| strict != "false");
             let mut metadata = IndexMetadata::default();
-            match json_from_request_body!(req).map(|data| parse_index_metadata(&mut metadata, data)) {
+            let result = json_from_request_body!(req).map(|data| {
+                if strict {
+                    parse_index_metadata(&mut metadata, data)
+                } else {
+                    parse_index_metadata_lenient(&mut metadata, data)
+                }
+            });
+
+            match result {
                 Some(Ok(())) | None => {}
+                Some(Err(IndexMetadataParseError::UnrecognisedSetting(path))) => {
+                    return Ok(json_response(status::BadRequest, json!({"message": format!("Unrecognised setting: {}", path)})));
+                }
                 Some(Err(_)) => {
                     // TODO: better error
                     return Ok(json_response(status::BadRequest, json!({"message": "Couldn't parse index settings"})));
                 }
             }
 
-            // Create index
+            // These are always assigned here, even if the request gave them
+            metadata.uuid = Uuid::new_v4();
+            metadata.provided_name = index_name.to_string();
+            metadata.creation_date = Utc::now();
+            metadata.version_created = VERSION.to_string();
+
+            // Create index. It's stored under its UUID so that the data of a deleted
+            // index with the same name can never be picked up by this one
             let mut indices_dir = system.get_indices_dir();
-            indices_dir.push(index_name);
-            let index = Index::new(Uuid::new_v4(), index_name.clone().to_owned(), metadata, RocksDBStore::create(indices_dir).unwrap());
+            indices_dir.push(metadata.uuid.to_string());
+            let index = Index::new(metadata.uuid, index_name.clone().to_owned(), metadata, RocksDBStore::create_with_encryption(indices_dir, Schema::new(), system.store_encryption.clone()).unwrap());
             index.metadata.read().unwrap().save(index.metadata_path()).unwrap();
             let index_ref = cluster_metadata.insert_index(index);
 
@@ -85,6 +133,7 @@ pub fn view_put_index(req: &mut Request) -> IronResult<Response> {
             cluster_metadata.names.insert_canonical(index_name.clone().to_owned(), index_ref).unwrap();
 
             info!(system.log, "created index"; "index" => *index_name);
+            audit(system, req, AuditEventType::IndexCreated, Some(*index_name));
         }
     }
 
@@ -104,32 +153,28 @@ pub fn view_delete_index(req: &mut Request) -> IronResult<Response> {
 
     // Remove indices
     for index_ref in cluster_metadata.names.find(*index_selector) {
-        // Get the index name
-        let index_name = {
-            if let Some(index) = cluster_metadata.indices.get(&index_ref) {
-                index.canonical_name().to_string()
+        // Remove index from array, this closes its store
+        let (index_name, index_path) = {
+            if let Some(index) = cluster_metadata.indices.remove(&index_ref) {
+                (index.canonical_name().to_string(), index.store.path().to_path_buf())
             } else {
                 // Index doesn't exist
                 continue;
             }
         };
 
-        // Remove index from array
-        cluster_metadata.indices.remove(&index_ref);
-
         // Delete canonical name
         cluster_metadata.names.delete_canonical(&index_name, index_ref).unwrap();
 
         // Delete file
-        let mut indices_dir = system.get_indices_dir();
-        indices_dir.push(&index_name);
-        match fs::remove_dir_all(&indices_dir) {
+        match fs::remove_dir_all(&index_path) {
             Ok(()) => {},
             Err(e) => {
                 warn!(system.log, "failed to delete index data"; "index" => format!("{}", index_name), "error" => format!("{}", e));
             }
         }
 
+        audit(system, req, AuditEventType::IndexDeleted, Some(&index_name));
         info!(system.log, "deleted index"; "index" => index_name);
 
         // Delete aliases
@@ -0,0 +1,108 @@
+use std::io::Read;
+
+use serde_json;
+
+use ingest::parse::parse as parse_pipeline;
+use ingest::simulate::{parse as parse_simulate_request, simulate};
+
+use api::persistent;
+use api::iron::prelude::*;
+use api::iron::status;
+use api::router::Router;
+use api::utils::{json_response, audit, read_query_parameter};
+use audit::AuditEventType;
+
+
+pub fn view_put_pipeline(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref pipeline_name = read_path_parameter!(req, "pipeline").unwrap_or("");
+
+    // Load data from body
+    let data = match json_from_request_body!(req) {
+        Some(data) => data,
+        None => {
+            return Ok(json_response(status::BadRequest, json!({"message": "No data"})));
+        }
+    };
+
+    // Parse pipeline
+    let pipeline = match parse_pipeline(&data) {
+        Ok(pipeline) => pipeline,
+        Err(error) => {
+            return Ok(json_response(status::BadRequest, json!({"message": format!("Couldn't parse pipeline: {:?}", error)})));
+        }
+    };
+
+    // Insert pipeline
+    let mut cluster_metadata = system.metadata.write().unwrap();
+    let previous_pipeline = cluster_metadata.pipelines.insert(pipeline_name.to_string(), pipeline);
+
+    // If the pipelines couldn't be saved, go back to the ones that are on disk
+    if let Err(error) = system.save_pipelines(&cluster_metadata) {
+        match previous_pipeline {
+            Some(previous_pipeline) => cluster_metadata.pipelines.insert(pipeline_name.to_string(), previous_pipeline),
+            None => cluster_metadata.pipelines.remove(*pipeline_name),
+        };
+
+        error!(system.log, "failed to save pipelines"; "error" => &error);
+        return Ok(json_response(status::InternalServerError, json!({"message": error})));
+    }
+
+    info!(system.log, "created pipeline"; "pipeline" => *pipeline_name);
+    audit(system, req, AuditEventType::PrivilegedApiAccess, None);
+
+    return Ok(json_response(status::Ok, json!({"acknowledged": true})));
+}
+
+
+pub fn view_delete_pipeline(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref pipeline_name = read_path_parameter!(req, "pipeline").unwrap_or("");
+
+    // Lock cluster metadata
+    let mut cluster_metadata = system.metadata.write().unwrap();
+
+    let pipeline = match cluster_metadata.pipelines.remove(*pipeline_name) {
+        Some(pipeline) => pipeline,
+        None => {
+            return Ok(json_response(status::NotFound, json!({"message": "Pipeline not found"})));
+        }
+    };
+
+    // If the pipelines couldn't be saved, go back to the ones that are on disk
+    if let Err(error) = system.save_pipelines(&cluster_metadata) {
+        cluster_metadata.pipelines.insert(pipeline_name.to_string(), pipeline);
+
+        error!(system.log, "failed to save pipelines"; "error" => &error);
+        return Ok(json_response(status::InternalServerError, json!({"message": error})));
+    }
+
+    info!(system.log, "deleted pipeline"; "pipeline" => *pipeline_name);
+    audit(system, req, AuditEventType::PrivilegedApiAccess, None);
+
+    return Ok(json_response(status::Ok, json!({"acknowledged": true})));
+}
+
+
+/// Runs sample documents through a pipeline given in the request, without indexing them
+pub fn view_simulate_pipeline(req: &mut Request) -> IronResult<Response> {
+    // Load data from body
+    let data = match json_from_request_body!(req) {
+        Some(data) => data,
+        None => {
+            return Ok(json_response(status::BadRequest, json!({"message": "No data"})));
+        }
+    };
+
+    let request = match parse_simulate_request(&data) {
+        Ok(request) => request,
+        Err(error) => {
+            return Ok(json_response(status::BadRequest, json!({"message": format!("Couldn't parse simulate request: {:?}", error)})));
+        }
+    };
+
+    // "?verbose" on its own turns it on too
+    let verbose = read_query_parameter(req, "verbose").map_or(false, |verbose| verbose.is_empty() || verbose == "true");
+
+    Ok(json_response(status::Ok, simulate(&request, verbose)))
+}
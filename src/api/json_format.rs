@@ -0,0 +1,193 @@
+//! The "pretty" and "human" parameters
+//!
+//! "pretty" indents the JSON of a response. "human" adds a readable version of
+//! each size and duration next to its raw value, so "size_in_bytes": 1288490189
+//! is joined by "size": "1.2gb" and "time_in_millis": 3400 by "time": "3.4s".
+
+use serde_json;
+
+use api::iron::prelude::*;
+use api::iron::AfterMiddleware;
+use api::utils::read_query_parameter;
+use api::filter_path::JsonBody;
+
+
+const BYTE_UNITS: [&'static str; 5] = ["kb", "mb", "gb", "tb", "pb"];
+
+
+/// Checks a flag parameter, which is switched on by being given without a value
+fn read_flag_parameter(req: &Request, name: &str) -> bool {
+    match read_query_parameter(req, name) {
+        Some(value) => value != "false",
+        None => false,
+    }
+}
+
+
+/// Formats a number to one decimal place, leaving off the decimal if it's zero
+fn format_one_decimal(value: f64) -> String {
+    let formatted = format!("{:.1}", value);
+
+    if formatted.ends_with(".0") {
+        formatted[..formatted.len() - 2].to_string()
+    } else {
+        formatted
+    }
+}
+
+
+pub fn format_bytes(bytes: u64) -> String {
+    if bytes < 1024 {
+        return format!("{}b", bytes);
+    }
+
+    let mut value = bytes as f64 / 1024.0;
+    for unit in BYTE_UNITS.iter() {
+        if value < 1024.0 || *unit == "pb" {
+            return format!("{}{}", format_one_decimal(value), unit);
+        }
+
+        value /= 1024.0;
+    }
+
+    unreachable!()
+}
+
+
+pub fn format_millis(millis: u64) -> String {
+    let millis = millis as f64;
+
+    if millis < 1000.0 {
+        format!("{}ms", millis)
+    } else if millis < 60.0 * 1000.0 {
+        format!("{}s", format_one_decimal(millis / 1000.0))
+    } else if millis < 60.0 * 60.0 * 1000.0 {
+        format!("{}m", format_one_decimal(millis / (60.0 * 1000.0)))
+    } else if millis < 24.0 * 60.0 * 60.0 * 1000.0 {
+        format!("{}h", format_one_decimal(millis / (60.0 * 60.0 * 1000.0)))
+    } else {
+        format!("{}d", format_one_decimal(millis / (24.0 * 60.0 * 60.0 * 1000.0)))
+    }
+}
+
+
+/// Adds a readable version of each "_in_bytes" and "_in_millis" value in the JSON
+pub fn add_human_readable(value: &mut serde_json::Value) {
+    match *value {
+        serde_json::Value::Object(ref mut object) => {
+            let mut readable = Vec::new();
+
+            for (key, child) in object.iter_mut() {
+                if let Some(raw) = child.as_u64() {
+                    if key.ends_with("_in_bytes") {
+                        readable.push((key[..key.len() - "_in_bytes".len()].to_string(), format_bytes(raw)));
+                    } else if key.ends_with("_in_millis") {
+                        readable.push((key[..key.len() - "_in_millis".len()].to_string(), format_millis(raw)));
+                    }
+                } else {
+                    add_human_readable(child);
+                }
+            }
+
+            for (key, value) in readable {
+                // Don't replace a value that the response already has
+                if !object.contains_key(&key) {
+                    object.insert(key, serde_json::Value::String(value));
+                }
+            }
+        }
+        serde_json::Value::Array(ref mut array) => {
+            for item in array.iter_mut() {
+                add_human_readable(item);
+            }
+        }
+        _ => {}
+    }
+}
+
+
+/// Adds readable sizes and durations to the JSON responses of all views if "human" is set
+pub struct HumanReadable;
+
+
+impl AfterMiddleware for HumanReadable {
+    fn after(&self, req: &mut Request, mut response: Response) -> IronResult<Response> {
+        if !read_flag_parameter(req, "human") {
+            return Ok(response);
+        }
+
+        if let Some(mut json) = response.extensions.remove::<JsonBody>() {
+            add_human_readable(&mut json);
+            response.set_mut(format!("{}", json));
+            response.extensions.insert::<JsonBody>(json);
+        }
+
+        Ok(response)
+    }
+}
+
+
+/// Indents the JSON responses of all views if "pretty" is set
+pub struct Pretty;
+
+
+impl AfterMiddleware for Pretty {
+    fn after(&self, req: &mut Request, mut response: Response) -> IronResult<Response> {
+        if !read_flag_parameter(req, "pretty") {
+            return Ok(response);
+        }
+
+        if let Some(json) = response.extensions.get::<JsonBody>() {
+            if let Ok(pretty) = serde_json::to_string_pretty(json) {
+                response.set_mut(pretty + "\n");
+            }
+        }
+
+        Ok(response)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{format_bytes, format_millis, add_human_readable};
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(512), "512b");
+        assert_eq!(format_bytes(10 * 1024), "10kb");
+        assert_eq!(format_bytes(1536 * 1024), "1.5mb");
+        assert_eq!(format_bytes(1288490189), "1.2gb");
+        assert_eq!(format_bytes(2048 * 1024 * 1024 * 1024 * 1024 * 1024), "2048pb");
+    }
+
+    #[test]
+    fn test_format_millis() {
+        assert_eq!(format_millis(150), "150ms");
+        assert_eq!(format_millis(3400), "3.4s");
+        assert_eq!(format_millis(120 * 1000), "2m");
+        assert_eq!(format_millis(90 * 60 * 1000), "1.5h");
+        assert_eq!(format_millis(4 * 24 * 60 * 60 * 1000), "4d");
+    }
+
+    #[test]
+    fn test_add_human_readable() {
+        let mut json = json!({
+            "indices": [
+                {"store": {"size_in_bytes": 1288490189}},
+            ],
+            "search": {"query_time_in_millis": 3400, "query_total": 12},
+            "dump": {"size_in_bytes": 10, "size": "small"},
+        });
+
+        add_human_readable(&mut json);
+
+        assert_eq!(json, json!({
+            "indices": [
+                {"store": {"size_in_bytes": 1288490189, "size": "1.2gb"}},
+            ],
+            "search": {"query_time_in_millis": 3400, "query_time": "3.4s", "query_total": 12},
+            "dump": {"size_in_bytes": 10, "size": "small"},
+        }));
+    }
+}
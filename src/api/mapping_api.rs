@@ -2,7 +2,7 @@ use std::io::Read;
 use std::collections::HashMap;
 
 use serde_json;
-use search::schema::{FieldType, FieldFlags, FIELD_INDEXED, FIELD_STORED};
+use search::schema::{Schema, FieldType, FieldFlags, FIELD_INDEXED, FIELD_STORED};
 
 use mapping::{self, MappingProperty};
 use mapping::parse::parse as parse_mapping;
@@ -11,7 +11,77 @@ use api::persistent;
 use api::iron::prelude::*;
 use api::iron::status;
 use api::router::Router;
-use api::utils::json_response;
+use api::utils::{json_response, audit};
+use audit::AuditEventType;
+
+
+/// Finds the fields that the properties need in the store. Properties in
+/// nested mappings are named by their full path, and each nested mapping has a
+/// field for its path too
+fn collect_store_fields(properties: &HashMap<String, MappingProperty>, path: &str, store_fields: &mut Vec<(String, FieldType, FieldFlags)>) {
+    for (name, property) in properties.iter() {
+        let name = format!("{}{}", path, name);
+
+        match *property {
+            MappingProperty::Field(ref field_mapping) => {
+                let field_type = match field_mapping.data_type {
+                    mapping::FieldType::String => FieldType::Text,
+                    mapping::FieldType::Integer => FieldType::I64,
+                    mapping::FieldType::Boolean => FieldType::Boolean,
+                    mapping::FieldType::Date => FieldType::DateTime,
+                    mapping::FieldType::Wildcard => FieldType::Wildcard,
+                    mapping::FieldType::SearchAsYouType => FieldType::Text,
+                    mapping::FieldType::Flattened => FieldType::Flattened,
+                    mapping::FieldType::Join => FieldType::PlainString,
+                    mapping::FieldType::Percolator => FieldType::PlainString,
+                    mapping::FieldType::RankFeature => FieldType::PlainString,
+                    mapping::FieldType::RankFeatures => FieldType::PlainString,
+                    mapping::FieldType::GeoShape => FieldType::PlainString,
+                    mapping::FieldType::Shape => FieldType::PlainString,
+                    mapping::FieldType::Point => FieldType::PlainString,
+                    mapping::FieldType::GeoPoint => FieldType::PlainString,
+                    mapping::FieldType::Histogram => FieldType::PlainString,
+                    mapping::FieldType::Completion => FieldType::PlainString,
+                };
+
+                // Flags
+                let mut field_flags = FieldFlags::empty();
+
+                if field_mapping.is_indexed {
+                    field_flags |= FIELD_INDEXED;
+                }
+
+                if field_mapping.is_stored {
+                    field_flags |= FIELD_STORED;
+                }
+
+                store_fields.push((name, field_type, field_flags));
+            }
+            MappingProperty::NestedMapping(ref nested_mapping) => {
+                collect_store_fields(&nested_mapping.properties, &format!("{}.", name), store_fields);
+                store_fields.push((name, FieldType::Nested, FIELD_INDEXED));
+            }
+        }
+    }
+}
+
+
+/// Points the properties at their fields in the store
+fn link_store_fields(properties: &mut HashMap<String, MappingProperty>, path: &str, schema: &Schema) {
+    for (name, property) in properties.iter_mut() {
+        let name = format!("{}{}", path, name);
+
+        match *property {
+            MappingProperty::Field(ref mut field_mapping) => {
+                field_mapping.index_ref = schema.get_field_by_name(&name)
+            }
+            MappingProperty::NestedMapping(ref mut nested_mapping) => {
+                nested_mapping.index_ref = schema.get_field_by_name(&name);
+                link_store_fields(&mut nested_mapping.properties, &format!("{}.", name), schema);
+            }
+        }
+    }
+}
 
 
 pub fn view_put_mapping(req: &mut Request) -> IronResult<Response> {
@@ -51,47 +121,35 @@ pub fn view_put_mapping(req: &mut Request) -> IronResult<Response> {
     //debug!("{:#?}", mapping);
     let is_updating = index_metadata.mappings.contains_key(*mapping_name);
 
+    // Fields with the same name in other mappings must be defined the same way
+    if let Err(conflict) = index_metadata.check_mapping_conflicts(*mapping_name, &mapping) {
+        return Ok(json_response(status::BadRequest, json!({"message": format!("{}", conflict)})));
+    }
+
     // Find list of new fields that need to be added to the store
     let new_fields = {
         let index_reader = index.store.reader();
         let schema = index_reader.schema();
         let mut new_fields: HashMap<String, (FieldType, FieldFlags)>  = HashMap::new();
-        for (name, property) in mapping.properties.iter() {
-            if let MappingProperty::Field(ref field_mapping) = *property {
-                let field_type = match field_mapping.data_type {
-                    mapping::FieldType::String => FieldType::Text,
-                    mapping::FieldType::Integer => FieldType::I64,
-                    mapping::FieldType::Boolean => FieldType::Boolean,
-                    mapping::FieldType::Date => FieldType::DateTime,
-                };
-
-                // Flags
-                let mut field_flags = FieldFlags::empty();
-
-                if field_mapping.is_indexed {
-                    field_flags |= FIELD_INDEXED;
-                }
-
-                if field_mapping.is_stored {
-                    field_flags |= FIELD_STORED;
-                }
-
-                // Check if this field already exists
-                if let Some(field_ref) = schema.get_field_by_name(&name) {
-                    let field_info = schema.get(&field_ref).expect("get_field_by_name returned an invalid FieldId");
-
-                    // Field already exists. Check for conflicting type or flags, otherwise ignore.
-                    if field_info.field_type == field_type && field_info.field_flags == field_flags {
-                        continue;
-                    } else {
-                        // Conflict!
-                        // TODO: Better error
-                        return Ok(json_response(status::BadRequest, json!({"acknowledged": false})));
-                    }
+        let mut store_fields = Vec::new();
+        collect_store_fields(&mapping.properties, "", &mut store_fields);
+
+        for (name, field_type, field_flags) in store_fields {
+            // Check if this field already exists
+            if let Some(field_ref) = schema.get_field_by_name(&name) {
+                let field_info = schema.get(&field_ref).expect("get_field_by_name returned an invalid FieldId");
+
+                // Field already exists. Check for conflicting type or flags, otherwise ignore.
+                if field_info.field_type == field_type && field_info.field_flags == field_flags {
+                    continue;
+                } else {
+                    // Conflict!
+                    // TODO: Better error
+                    return Ok(json_response(status::BadRequest, json!({"acknowledged": false})));
                 }
-
-                new_fields.insert(name.clone(), (field_type, field_flags));
             }
+
+            new_fields.insert(name.clone(), (field_type, field_flags));
         }
 
         new_fields
@@ -111,16 +169,14 @@ pub fn view_put_mapping(req: &mut Request) -> IronResult<Response> {
         let index_reader = index.store.reader();
         let schema = index_reader.schema();
 
-        for (name, property) in mapping.properties.iter_mut() {
-            if let MappingProperty::Field(ref mut field_mapping) = *property {
-                field_mapping.index_ref = schema.get_field_by_name(&name)
-            }
-        }
+        link_store_fields(&mut mapping.properties, "", schema);
     }
 
     index_metadata.mappings.insert(mapping_name.clone().to_owned(), mapping);
     index_metadata.save(index.metadata_path()).unwrap();
 
+    audit(system, req, AuditEventType::MappingChanged, Some(*index_name));
+
     if is_updating {
         // TODO: New mapping should be merged with existing one
         info!(system.log, "updated mapping"; "index" => *index_name, "mapping" => *mapping_name);
@@ -130,3 +186,37 @@ pub fn view_put_mapping(req: &mut Request) -> IronResult<Response> {
 
     return Ok(json_response(status::Ok, json!({"acknowledged": true})));
 }
+
+
+/// Shows how a field is mapped across all of the mappings in an index
+pub fn view_get_field_mapping(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+    let ref field_name = read_path_parameter!(req, "field").unwrap_or("");
+
+    // Get index
+    let cluster_metadata = system.metadata.read().unwrap();
+    let index = get_index_or_404!(cluster_metadata, *index_name);
+    let index_metadata = index.metadata.read().unwrap();
+
+    let (field_mapping, mapping_names) = match index_metadata.get_merged_field_mapping(*field_name) {
+        Ok(Some(field_mapping)) => field_mapping,
+        Ok(None) => return Ok(json_response(status::NotFound, json!({"message": "Field not found"}))),
+        Err(conflict) => {
+            // Indices created before conflicts were checked may still have them
+            warn!(system.log, "conflicting field mappings"; "index" => *index_name, "field" => *field_name, "conflict" => format!("{}", conflict));
+            return Ok(json_response(status::BadRequest, json!({"message": format!("{}", conflict)})));
+        }
+    };
+
+    let mut response = serde_json::Map::new();
+    response.insert(index.canonical_name().to_string(), json!({
+        "field": {
+            "full_name": *field_name,
+            "mapping": field_mapping,
+            "mappings": mapping_names,
+        }
+    }));
+
+    Ok(json_response(status::Ok, serde_json::Value::Object(response)))
+}
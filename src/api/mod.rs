@@ -4,12 +4,20 @@ extern crate persistent;
 
 #[macro_use]
 mod utils;
-mod search_api;
+mod filter_path;
+mod json_format;
+pub mod search_api;
 mod alias_api;
 mod document_api;
 mod index_api;
 mod mapping_api;
 mod bulk_api;
+mod ingest_api;
+mod watcher_api;
+mod changes_api;
+mod cluster_api;
+mod cat_api;
+mod bench_api;
 
 use std::sync::Arc;
 
@@ -18,6 +26,8 @@ use api::iron::status;
 use api::iron::typemap::Key;
 use api::router::Router;
 use api::utils::json_response;
+use api::filter_path::FilterPath;
+use api::json_format::{HumanReadable, Pretty};
 
 use system::System;
 use VERSION;
@@ -35,10 +45,16 @@ fn view_home(_: &mut Request) -> IronResult<Response> {
 
 fn get_router() -> Router {
     router!(get "/" => view_home,
+            put "/_cluster/settings" => cluster_api::view_put_cluster_settings,
+            get "/_remote/info" => cluster_api::view_get_remote_info,
+            get "/_nodes/stats/thread_pool" => cluster_api::view_get_thread_pool_stats,
+            get "/_cat/indices" => cat_api::view_get_cat_indices,
             get "/:index/_count" => search_api::view_count,
             post "/:index/_count" => search_api::view_count,
             get "/:index/_search" => search_api::view_search,
             post "/:index/_search" => search_api::view_search,
+            get "/:index/_validate/query" => search_api::view_validate_query,
+            post "/:index/_validate/query" => search_api::view_validate_query,
             get "/_alias/:alias" => alias_api::view_get_global_alias,
             get "/:index/_alias" => alias_api::view_get_alias_list,
             get "/:index/_alias/:alias" => alias_api::view_get_alias,
@@ -46,13 +62,24 @@ fn get_router() -> Router {
             get "/:index/:mapping/:doc" => document_api::view_get_doc,
             put "/:index/:mapping/:doc" => document_api::view_put_doc,
             delete "/:index/:mapping/:doc" => document_api::view_delete_doc,
+            get "/:index/_settings" => index_api::view_get_index_settings,
             get "/:index" => index_api::view_get_index,
             put "/:index" => index_api::view_put_index,
             delete "/:index" => index_api::view_delete_index,
             post "/:index/_refresh" => index_api::view_post_refresh_index,
+            post "/:index/_bench" => bench_api::view_post_bench,
+            get "/:index/_changes" => changes_api::view_get_changes,
             put "/:index/_mapping/:mapping" => mapping_api::view_put_mapping,
+            get "/:index/_mapping/field/:field" => mapping_api::view_get_field_mapping,
             post "/_bulk" => bulk_api::view_post_bulk,
-            post "/:index/_bulk" => bulk_api::view_post_index_bulk)
+            post "/:index/_bulk" => bulk_api::view_post_index_bulk,
+            get "/_ingest/pipeline/_simulate" => ingest_api::view_simulate_pipeline,
+            post "/_ingest/pipeline/_simulate" => ingest_api::view_simulate_pipeline,
+            put "/_ingest/pipeline/:pipeline" => ingest_api::view_put_pipeline,
+            delete "/_ingest/pipeline/:pipeline" => ingest_api::view_delete_pipeline,
+            get "/_watcher/watch/:watch" => watcher_api::view_get_watch,
+            put "/_watcher/watch/:watch" => watcher_api::view_put_watch,
+            delete "/_watcher/watch/:watch" => watcher_api::view_delete_watch)
 }
 
 
@@ -82,6 +109,11 @@ pub fn api_main(system: Arc<System>) {
     let router = get_router();
     let mut chain = Chain::new(router);
     chain.link(persistent::Read::<Context>::both(Context::new(system.clone())));
+
+    // Readable values are added first so they can be picked out by "filter_path"
+    chain.link_after(HumanReadable);
+    chain.link_after(FilterPath);
+    chain.link_after(Pretty);
     info!(system.log, "listening"; "scheme" => "http", "address" => "localhost", "port" => 9200);
 
     if let Err(error) = Iron::new(chain).http("localhost:9200") {
@@ -1,14 +1,38 @@
 use std::io::Read;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
+use std::cmp::Ordering;
 
 use serde_json;
+use slog::Logger;
 use url::form_urlencoded;
-use search::document::DocId;
+use search::document::{DocId, FieldValue};
+use search::schema::FieldId;
 use search::query::Query;
+use search::query::geo_shape::Coordinate;
+use search::query::geo_distance::{DistanceUnit, haversine_distance};
+use search::segment::SegmentFailure;
+use search::backends::rocksdb::RocksDBReader;
+use search::collectors::Collector;
 use search::collectors::top_score::TopScoreCollector;
 use search::collectors::total_count::TotalCountCollector;
+use search::collectors::index_order::IndexOrderCollector;
+use search::collectors::sort_value::SortValueCollector;
+use search::collectors::doc_id_set::DocIdSetCollector;
 
-use query_parser::{QueryBuildContext, parse as parse_query};
+use query_parser::{QueryBuilder, QueryBuildContext, QueryParseError, parse as parse_query};
+use mapping::parse_geo_point;
+use mapping::histogram::Histogram;
+use script::{Expression, ScriptValue, parse_script, parse_script_params};
+use highlight::{Highlight, QueryTerms, parse_highlight, highlight_document};
+use suggest::{SuggestOption, parse_suggest, run_completion, merge_options, render_suggestions};
+use aggregations::geo::stored_points;
+use aggregations::collector::{DocumentValues, AggregationsCollector, AggregatingCollector};
+use aggregations::terms::TermValue;
+use aggregations::adjacency_matrix::AdjacencyFilter;
+use aggregations::parse::parse_aggregations;
+use index::Index;
+use cluster::metadata::ClusterMetadata;
+use cluster::remote::split_search_target;
 
 use api::persistent;
 use api::iron::prelude::*;
@@ -20,6 +44,7 @@ use api::utils::json_response;
 pub fn view_count(req: &mut Request) -> IronResult<Response> {
     let ref system = get_system!(req);
     let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+    let _permit = acquire_thread_pool!(system.thread_pools.search);
 
     // Get index
     let cluster_metadata = system.metadata.read().unwrap();
@@ -30,49 +55,1118 @@ pub fn view_count(req: &mut Request) -> IronResult<Response> {
     let count = match json_from_request_body!(req) {
         Some(query_json) => {
             // Parse query
-            let query = parse_query(query_json.as_object().unwrap().get("query").unwrap());
+            // Requests that only ask for suggestions don't need a query
+            let match_all_json = json!({"match_all": {}});
+            let query_clause_json = query_json.as_object().unwrap().get("query").unwrap_or(&match_all_json);
+            let query = parse_query(query_clause_json);
             //debug!("{:#?}", query);
 
             match query {
                 Ok(query) => {
+                    // Documents the query looks up must exist
+                    if let Err(error) = query.check(&QueryBuildContext::new().set_index(index).set_index_metadata(&index_metadata).set_cluster_metadata(&cluster_metadata)) {
+                        return Ok(json_response(status::BadRequest, json!({"message": format!("Query error: {}", error)})));
+                    }
+
                     let mut collector = TotalCountCollector::new();
-                    index_reader.search(&mut collector, &query.build(&QueryBuildContext::new().set_index_metadata(&index_metadata).no_score(), &index_reader.schema())).unwrap();
+                    index_reader.search(&mut collector, &query.build(&QueryBuildContext::new().set_index(index).set_index_metadata(&index_metadata).set_cluster_metadata(&cluster_metadata).no_score(), &index_reader.schema())).unwrap();
                     collector.get_total_count()
                 }
-                Err(_) => {
-                    // TODO: What specifically is bad about the Query?
-                    let mut response = Response::with((status::BadRequest,
-                                                       "{\"message\": \"Query error\"}"));
-                    response.headers.set_raw("Content-Type", vec![b"application/json".to_vec()]);
-                    return Ok(response);
+                Err(error) => {
+                    return Ok(json_response(status::BadRequest, json!({"message": format!("Query error: {}", describe_query_error(&error))})));
+                }
+            }
+        }
+        None => {
+            let mut collector = TotalCountCollector::new();
+            index_reader.search(&mut collector, &Query::all()).unwrap();
+            collector.get_total_count()
+        }
+    };
+
+    return Ok(json_response(status::Ok, json!({"count": count})));
+}
+
+
+/// Checks that a query parses and can be run against the index, without running it
+pub fn view_validate_query(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+
+    // Get index
+    let cluster_metadata = system.metadata.read().unwrap();
+    let index = get_index_or_404!(cluster_metadata, *index_name);
+    let index_metadata = index.metadata.read().unwrap();
+
+    let query_json = match json_from_request_body!(req) {
+        Some(data) => {
+            match data.as_object() {
+                Some(data) => data.get("query").cloned(),
+                None => return Ok(json_response(status::Ok, json!({"valid": false, "error": "request body must be an object"}))),
+            }
+        }
+        None => None,
+    };
+
+    // Requests without a query match everything
+    let query_json = query_json.unwrap_or_else(|| json!({"match_all": {}}));
+
+    let query = match parse_query(&query_json) {
+        Ok(query) => query,
+        Err(error) => return Ok(json_response(status::Ok, json!({"valid": false, "error": describe_query_error(&error)}))),
+    };
+
+    // Documents the query looks up must exist
+    match query.check(&QueryBuildContext::new().set_index(index).set_index_metadata(&index_metadata).set_cluster_metadata(&cluster_metadata)) {
+        Ok(()) => Ok(json_response(status::Ok, json!({"valid": true}))),
+        Err(error) => Ok(json_response(status::Ok, json!({"valid": false, "error": describe_query_error(&error)}))),
+    }
+}
+
+
+/// Describes why a query failed to parse, with the path to the part of the
+/// query that failed, such as "[query.bool.must[1].match] unrecognised key"
+fn describe_query_error(error: &QueryParseError) -> String {
+    match error.path() {
+        Some(path) => format!("[query.{}] {}", path, error),
+        None => format!("[query] {}", error),
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SortOrder {
+    Asc,
+    Desc,
+}
+
+
+/// How the values of a document are reduced to one, for documents with many
+/// values or many nested objects
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SortMode {
+    Min,
+    Max,
+    Sum,
+    Avg,
+    Median,
+}
+
+
+impl SortMode {
+    fn combine(&self, values: &[f64]) -> Option<f64> {
+        if values.is_empty() {
+            return None;
+        }
+
+        Some(match *self {
+            SortMode::Min => values.iter().cloned().fold(f64::INFINITY, f64::min),
+            SortMode::Max => values.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+            SortMode::Sum => values.iter().sum::<f64>(),
+            SortMode::Avg => values.iter().sum::<f64>() / values.len() as f64,
+            SortMode::Median => {
+                let mut values = values.to_vec();
+                values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+
+                // Even numbers of values have two in the middle
+                let middle = values.len() / 2;
+                if values.len() % 2 == 0 {
+                    (values[middle - 1] + values[middle]) / 2.0
+                } else {
+                    values[middle]
+                }
+            }
+        })
+    }
+}
+
+
+/// Sorts on fields inside nested objects take their values from the nested
+/// documents in the path that match the filter
+#[derive(Debug, Clone, PartialEq)]
+struct NestedSort {
+    path: String,
+    filter: Option<serde_json::Value>,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum SearchSort {
+    Score,
+    IndexOrder,
+
+    /// Sorts by the number that a script gives for each document
+    Script {
+        script: Expression,
+        params: HashMap<String, ScriptValue>,
+        order: SortOrder,
+    },
+
+    /// Sorts by the distance from the origins to the points in a geo_point field
+    GeoDistance {
+        field: String,
+        origins: Vec<Coordinate>,
+        unit: DistanceUnit,
+        mode: SortMode,
+        order: SortOrder,
+    },
+
+    /// Sorts by the stored value of a numeric, date or boolean field
+    Field {
+        field: String,
+        mode: SortMode,
+        nested: Option<NestedSort>,
+        order: SortOrder,
+    },
+}
+
+
+impl SearchSort {
+    /// The order of sorts that give each hit a sort value
+    fn value_order(&self) -> Option<SortOrder> {
+        match *self {
+            SearchSort::Script{order, ..} | SearchSort::GeoDistance{order, ..} | SearchSort::Field{order, ..} => Some(order),
+            SearchSort::Score | SearchSort::IndexOrder => None,
+        }
+    }
+}
+
+
+/// Why a sort couldn't be parsed
+#[derive(Debug, PartialEq)]
+enum SortParseError {
+    /// The sort isn't supported. The search carries on with the default sort
+    Unsupported,
+
+    /// The filter of a nested sort isn't a valid query
+    InvalidNestedFilter(QueryParseError),
+}
+
+
+fn parse_sort_field(name: &str, order: Option<&str>) -> Result<SearchSort, SortParseError> {
+    match (name, order) {
+        ("_score", None) | ("_score", Some("desc")) => Ok(SearchSort::Score),
+        ("_doc", None) | ("_doc", Some("asc")) => Ok(SearchSort::IndexOrder),
+        _ if name.starts_with('_') => Err(SortParseError::Unsupported),
+        (_, None) => parse_field_sort(name, &serde_json::Map::new()),
+        (_, Some(order)) => {
+            let mut options = serde_json::Map::new();
+            options.insert("order".to_string(), json!(order));
+            parse_field_sort(name, &options)
+        }
+    }
+}
+
+
+fn parse_sort_order(order: &str) -> Option<SortOrder> {
+    match order {
+        "asc" => Some(SortOrder::Asc),
+        "desc" => Some(SortOrder::Desc),
+        _ => None,
+    }
+}
+
+
+fn parse_sort_mode(mode: &str) -> Option<SortMode> {
+    match mode {
+        "min" => Some(SortMode::Min),
+        "max" => Some(SortMode::Max),
+        "sum" => Some(SortMode::Sum),
+        "avg" => Some(SortMode::Avg),
+        "median" => Some(SortMode::Median),
+        _ => None,
+    }
+}
+
+
+/// Checks the filter of a nested sort. It's only built once the index to search is known
+fn parse_nested_sort_filter(json: &serde_json::Value) -> Result<serde_json::Value, SortParseError> {
+    parse_query(json).map_err(SortParseError::InvalidNestedFilter)?;
+    Ok(json.clone())
+}
+
+
+/// Parses the "nested" option of a field sort
+fn parse_nested_sort(json: &serde_json::Value) -> Result<NestedSort, SortParseError> {
+    let options = json.as_object().ok_or(SortParseError::Unsupported)?;
+    let mut path = None;
+    let mut filter = None;
+
+    for (key, value) in options.iter() {
+        match key.as_ref() {
+            "path" => {
+                path = Some(value.as_str().ok_or(SortParseError::Unsupported)?.to_string());
+            }
+            "filter" => {
+                filter = Some(parse_nested_sort_filter(value)?);
+            }
+            _ => return Err(SortParseError::Unsupported),
+        }
+    }
+
+    Ok(NestedSort {
+        path: path.ok_or(SortParseError::Unsupported)?,
+        filter: filter,
+    })
+}
+
+
+/// Parses a sort on a field, such as {"price": {"order": "desc", "mode": "avg"}}
+fn parse_field_sort(name: &str, options: &serde_json::Map<String, serde_json::Value>) -> Result<SearchSort, SortParseError> {
+    let mut order = SortOrder::Asc;
+    let mut mode = None;
+    let mut nested = None;
+    let mut nested_path = None;
+    let mut nested_filter = None;
+
+    for (key, value) in options.iter() {
+        match key.as_ref() {
+            "order" => {
+                order = value.as_str().and_then(parse_sort_order).ok_or(SortParseError::Unsupported)?;
+            }
+            "mode" => {
+                mode = Some(value.as_str().and_then(parse_sort_mode).ok_or(SortParseError::Unsupported)?);
+            }
+            "nested" => {
+                nested = Some(parse_nested_sort(value)?);
+            }
+            "nested_path" => {
+                nested_path = Some(value.as_str().ok_or(SortParseError::Unsupported)?.to_string());
+            }
+            "nested_filter" => {
+                nested_filter = Some(parse_nested_sort_filter(value)?);
+            }
+            "missing" => {
+                // Documents without a value always go last
+                if value.as_str() != Some("_last") {
+                    return Err(SortParseError::Unsupported);
+                }
+            }
+            "unmapped_type" => {
+                // Unmapped fields don't give any values, whatever their type
+                value.as_str().ok_or(SortParseError::Unsupported)?;
+            }
+            _ => return Err(SortParseError::Unsupported),
+        }
+    }
+
+    // Older versions of Elasticsearch took the nested path and filter as separate options
+    if nested.is_none() {
+        if let Some(path) = nested_path {
+            nested = Some(NestedSort {
+                path: path,
+                filter: nested_filter,
+            });
+        }
+    }
+
+    // Multiple values sort by the lowest in ascending order and the highest in descending order
+    let mode = mode.unwrap_or(match order {
+        SortOrder::Asc => SortMode::Min,
+        SortOrder::Desc => SortMode::Max,
+    });
+
+    Ok(SearchSort::Field {
+        field: name.to_string(),
+        mode: mode,
+        nested: nested,
+        order: order,
+    })
+}
+
+
+/// Parses a "_script" sort. Scripts must give numbers
+fn parse_script_sort(json: &serde_json::Value) -> Option<SearchSort> {
+    let options = json.as_object()?;
+    let mut script = None;
+    let mut order = SortOrder::Asc;
+
+    for (key, value) in options.iter() {
+        match key.as_ref() {
+            "type" => {
+                if value.as_str() != Some("number") {
+                    return None;
+                }
+            }
+            "script" => {
+                script = Some((parse_script(value).ok()?, parse_script_params(value)));
+            }
+            "order" => {
+                order = parse_sort_order(value.as_str()?)?;
+            }
+            _ => return None,
+        }
+    }
+
+    let (script, params) = script?;
+    Some(SearchSort::Script {
+        script: script,
+        params: params,
+        order: order,
+    })
+}
+
+
+/// Parses the origins of a "_geo_distance" sort, which is either a point or an array of points
+fn parse_sort_origins(json: &serde_json::Value) -> Option<Vec<Coordinate>> {
+    if let Some(origin) = parse_geo_point(json) {
+        return Some(vec![origin]);
+    }
+
+    match *json {
+        serde_json::Value::Array(ref origins) if !origins.is_empty() => origins.iter().map(parse_geo_point).collect(),
+        _ => None,
+    }
+}
+
+
+/// Parses a "_geo_distance" sort. The field is the only key that isn't an option
+fn parse_geo_distance_sort(json: &serde_json::Value) -> Option<SearchSort> {
+    let options = json.as_object()?;
+    let mut field = None;
+    let mut unit = DistanceUnit::default();
+    let mut mode = SortMode::Min;
+    let mut order = SortOrder::Asc;
+
+    for (key, value) in options.iter() {
+        match key.as_ref() {
+            "unit" => {
+                unit = DistanceUnit::from_str(value.as_str()?)?;
+            }
+            "mode" => {
+                // Adding up distances doesn't mean anything
+                mode = match parse_sort_mode(value.as_str()?)? {
+                    SortMode::Sum => return None,
+                    mode => mode,
+                };
+            }
+            "order" => {
+                order = parse_sort_order(value.as_str()?)?;
+            }
+            "distance_type" => {
+                // Distances are always worked out along the surface of the earth,
+                // which is at least as accurate as "plane"
+                match value.as_str()? {
+                    "arc" | "plane" => {}
+                    _ => return None,
+                }
+            }
+            "ignore_unmapped" => {
+                // Documents without points are sorted last either way
+                value.as_bool()?;
+            }
+            _ => {
+                if field.is_some() {
+                    return None;
+                }
+
+                field = Some((key.clone(), parse_sort_origins(value)?));
+            }
+        }
+    }
+
+    let (field, origins) = field?;
+    Some(SearchSort::GeoDistance {
+        field: field,
+        origins: origins,
+        unit: unit,
+        mode: mode,
+        order: order,
+    })
+}
+
+
+/// Parses the "sort" section of a search request
+///
+/// Only the first sort is used as the others would only break ties
+fn parse_sort(json: &serde_json::Value) -> Result<SearchSort, SortParseError> {
+    match *json {
+        serde_json::Value::String(ref name) => parse_sort_field(name, None),
+        serde_json::Value::Array(ref sorts) => {
+            match sorts.first() {
+                Some(sort) => parse_sort(sort),
+                None => Ok(SearchSort::Score),
+            }
+        }
+        serde_json::Value::Object(ref object) => {
+            if object.len() != 1 {
+                return Err(SortParseError::Unsupported);
+            }
+
+            let (name, order_json) = object.iter().next().unwrap();
+            match (name.as_ref(), order_json) {
+                ("_script", _) => return parse_script_sort(order_json).ok_or(SortParseError::Unsupported),
+                ("_geo_distance", _) => return parse_geo_distance_sort(order_json).ok_or(SortParseError::Unsupported),
+                ("_score", _) | ("_doc", _) => {}
+                (_, &serde_json::Value::Object(ref options)) => return parse_field_sort(name, options),
+                _ => {}
+            }
+
+            let order = match *order_json {
+                serde_json::Value::String(ref order) => order.as_str(),
+                serde_json::Value::Object(ref options) => {
+                    match options.get("order").and_then(|order| order.as_str()) {
+                        Some(order) => order,
+                        None => return Err(SortParseError::Unsupported),
+                    }
+                }
+                _ => return Err(SortParseError::Unsupported),
+            };
+
+            parse_sort_field(name, Some(order))
+        }
+        _ => Err(SortParseError::Unsupported),
+    }
+}
+
+
+/// Reads the stored values of a field as numbers. Dates are given in milliseconds since the epoch
+fn read_numeric_field(index_reader: &RocksDBReader, field: FieldId, doc_id: DocId) -> Vec<f64> {
+    match index_reader.read_stored_field(field, doc_id) {
+        Ok(Some(value)) => value.as_numbers(),
+        _ => Vec::new(),
+    }
+}
+
+
+/// Reads the values of a matching document for aggregations, from its stored fields
+#[derive(Clone)]
+struct StoredDocumentValues<'a, 'b: 'a> {
+    index_reader: &'a RocksDBReader<'b>,
+    doc_id: DocId,
+
+    /// The documents that match each adjacency matrix filter, by filter id
+    filter_matches: &'a HashMap<usize, DocIdSetCollector>,
+
+    /// The path of the nested field this document is in, and the document it's in
+    parent: Option<(String, Box<StoredDocumentValues<'a, 'b>>)>,
+}
+
+
+impl<'a, 'b> DocumentValues for StoredDocumentValues<'a, 'b> {
+    fn numeric_values(&self, field: &str) -> Vec<f64> {
+        match self.index_reader.schema().get_field_by_name(field) {
+            Some(field) => read_numeric_field(self.index_reader, field, self.doc_id),
+            None => Vec::new(),
+        }
+    }
+
+    fn geo_points(&self, field: &str) -> Vec<Coordinate> {
+        let field = match self.index_reader.schema().get_field_by_name(field) {
+            Some(field) => field,
+            None => return Vec::new(),
+        };
+
+        match self.index_reader.read_stored_field(field, self.doc_id) {
+            Ok(Some(FieldValue::String(value))) => serde_json::from_str(&value).map(|value| stored_points(&value)).unwrap_or_else(|_| Vec::new()),
+            _ => Vec::new(),
+        }
+    }
+
+    fn term_values(&self, field: &str) -> Vec<TermValue> {
+        let field = match self.index_reader.schema().get_field_by_name(field) {
+            Some(field) => field,
+            None => return Vec::new(),
+        };
+
+        match self.index_reader.read_stored_field(field, self.doc_id) {
+            Ok(Some(FieldValue::String(value))) => vec![TermValue::String(value)],
+            Ok(Some(FieldValue::StringArray(values))) => values.into_iter().map(TermValue::String).collect(),
+            Ok(Some(FieldValue::Integer(value))) => vec![TermValue::Integer(value)],
+            Ok(Some(FieldValue::IntegerArray(values))) => values.into_iter().map(TermValue::Integer).collect(),
+            Ok(Some(FieldValue::Boolean(value))) => vec![TermValue::Boolean(value)],
+            _ => Vec::new(),
+        }
+    }
+
+    fn histograms(&self, field: &str) -> Vec<Histogram> {
+        let field = match self.index_reader.schema().get_field_by_name(field) {
+            Some(field) => field,
+            None => return Vec::new(),
+        };
+
+        // Histograms are stored as they were given in the document
+        match self.index_reader.read_stored_field(field, self.doc_id) {
+            Ok(Some(FieldValue::String(value))) => serde_json::from_str(&value).ok().and_then(|value| Histogram::from_json(&value)).into_iter().collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn matches_filter(&self, filter: &AdjacencyFilter) -> bool {
+        self.filter_matches.get(&filter.id()).map_or(false, |matches| matches.contains(self.doc_id))
+    }
+
+    fn nested_documents<'s>(&'s self, path: &str) -> Vec<Box<DocumentValues + 's>> {
+        let path_field = match self.index_reader.schema().get_field_by_name(path) {
+            Some(path_field) => path_field,
+            None => return Vec::new(),
+        };
+
+        match self.index_reader.get_nested_documents(self.doc_id, path_field) {
+            Ok(nested_doc_ids) => nested_doc_ids.into_iter().map(|nested_doc_id| {
+                Box::new(StoredDocumentValues {
+                    index_reader: self.index_reader,
+                    doc_id: nested_doc_id,
+                    filter_matches: self.filter_matches,
+                    parent: Some((path.to_string(), Box::new(self.clone()))),
+                }) as Box<DocumentValues + 's>
+            }).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    fn nested_path(&self) -> Option<&str> {
+        self.parent.as_ref().map(|&(ref path, _)| path.as_str())
+    }
+
+    fn parent(&self) -> Option<&DocumentValues> {
+        self.parent.as_ref().map(|&(_, ref parent)| &**parent as &DocumentValues)
+    }
+
+    fn id(&self) -> u64 {
+        self.doc_id.as_u64()
+    }
+}
+
+
+/// Runs the search, passing the matches to the aggregations as well as the collector
+fn search_with_aggregations<C: Collector>(index_reader: &RocksDBReader, collector: &mut C, query: &Query, aggregations: Option<&mut AggregationsCollector>, filter_matches: &HashMap<usize, DocIdSetCollector>) -> (usize, Vec<SegmentFailure>) {
+    match aggregations {
+        Some(aggregations) => {
+            let mut collector = AggregatingCollector::new(collector, aggregations, |doc_id| {
+                StoredDocumentValues {
+                    index_reader: index_reader,
+                    doc_id: DocId::from_u64(doc_id),
+                    filter_matches: filter_matches,
+                    parent: None,
+                }
+            });
+
+            index_reader.search_allow_partial(&mut collector, query)
+        }
+        None => index_reader.search_allow_partial(collector, query),
+    }
+}
+
+
+/// Runs the filter of each adjacency matrix in the aggregations, returning the
+/// documents that match them by filter id and the errors of those that failed
+fn find_adjacency_filter_matches(index_reader: &RocksDBReader, context: &QueryBuildContext, aggregations: &AggregationsCollector) -> (HashMap<usize, DocIdSetCollector>, Vec<String>) {
+    let mut filter_matches = HashMap::new();
+    let mut errors = Vec::new();
+
+    for filter in aggregations.adjacency_filters() {
+        let mut collector = DocIdSetCollector::new();
+
+        match index_reader.search(&mut collector, &filter.query().build(context, &index_reader.schema())) {
+            Ok(()) => {
+                filter_matches.insert(filter.id(), collector);
+            }
+            Err(error) => errors.push(error),
+        }
+    }
+
+    (filter_matches, errors)
+}
+
+
+/// Counts the documents in an index that match the query, passing them to the
+/// aggregations as well
+///
+/// Unlike searches, this fails if any of the segments or adjacency matrix
+/// filters couldn't be run, as the count and the aggregations would be wrong
+pub fn count_with_aggregations(index: &Index, cluster_metadata: &ClusterMetadata, query: &QueryBuilder, aggregations: &mut AggregationsCollector) -> Result<u64, String> {
+    let index_reader = index.store.reader();
+    let index_metadata = index.metadata.read().unwrap();
+    let context = QueryBuildContext::new().set_index(index).set_index_metadata(&index_metadata).set_cluster_metadata(cluster_metadata).no_score();
+
+    let (filter_matches, errors) = find_adjacency_filter_matches(&index_reader, &context, aggregations);
+    if let Some(error) = errors.into_iter().next() {
+        return Err(format!("adjacency matrix filter failed: {}", error));
+    }
+
+    let mut collector = TotalCountCollector::new();
+    let (_, failures) = search_with_aggregations(&index_reader, &mut collector, &query.build(&context, &index_reader.schema()), Some(aggregations), &filter_matches);
+    if let Some(failure) = failures.into_iter().next() {
+        return Err(format!("segment {} failed: {}", (failure.segment).0, failure.reason));
+    }
+
+    Ok(collector.get_total_count())
+}
+
+
+/// Works out the sort value of a document, for sorts that have one. Field sorts with
+/// a nested filter are given the nested documents that match it
+fn sort_value(index_reader: &RocksDBReader, sort: &SearchSort, nested_matches: Option<&DocIdSetCollector>, doc_id: DocId, score: Option<f32>) -> Option<f64> {
+    match *sort {
+        SearchSort::Script{ref script, ref params, ..} => {
+            // Scripts can read the score, the stored values of fields and their params
+            let variables = |name: &str| {
+                if name == "_score" {
+                    return score.map(|score| ScriptValue::Number(score as f64));
+                }
+
+                if name.starts_with("doc.") {
+                    let field = index_reader.schema().get_field_by_name(&name["doc.".len()..])?;
+
+                    return match index_reader.read_stored_field(field, doc_id) {
+                        Ok(Some(FieldValue::Boolean(value))) => Some(ScriptValue::Boolean(value)),
+                        // Scripts see the first value of fields with many
+                        _ => read_numeric_field(index_reader, field, doc_id).into_iter().next().map(ScriptValue::Number),
+                    };
+                }
+
+                params.get(name).cloned()
+            };
+
+            script.evaluate(&variables).ok().and_then(|value| value.as_number().ok())
+        }
+        SearchSort::GeoDistance{ref field, ref origins, unit, mode, ..} => {
+            let field = index_reader.schema().get_field_by_name(field)?;
+            let points = match index_reader.read_stored_field(field, doc_id) {
+                Ok(Some(FieldValue::String(value))) => stored_points(&serde_json::from_str(&value).ok()?),
+                _ => return None,
+            };
+
+            let mut distances = Vec::with_capacity(origins.len() * points.len());
+            for origin in origins.iter() {
+                for point in points.iter() {
+                    distances.push(haversine_distance(*origin, *point) / unit.metres());
+                }
+            }
+
+            mode.combine(&distances)
+        }
+        SearchSort::Field{ref field, mode, ref nested, ..} => {
+            let field = index_reader.schema().get_field_by_name(field)?;
+
+            let values = match *nested {
+                Some(ref nested) => {
+                    let path = index_reader.schema().get_field_by_name(&nested.path)?;
+
+                    index_reader.get_nested_documents(doc_id, path).ok()?.into_iter()
+                        .filter(|nested_doc_id| nested_matches.map_or(true, |nested_matches| nested_matches.contains(*nested_doc_id)))
+                        .flat_map(|nested_doc_id| read_numeric_field(index_reader, field, nested_doc_id))
+                        .collect::<Vec<_>>()
+                }
+                None => read_numeric_field(index_reader, field, doc_id),
+            };
+
+            mode.combine(&values)
+        }
+        SearchSort::Score | SearchSort::IndexOrder => None,
+    }
+}
+
+
+/// Controls how accurately the total number of hits is counted
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TrackTotalHits {
+    /// Don't return the total
+    Disabled,
+
+    /// Count accurately up to this many hits, beyond that the total is a lower bound
+    UpTo(u64),
+
+    /// Always return the exact total
+    Accurate,
+}
+
+
+impl TrackTotalHits {
+    /// Collectors can stop counting once there are more hits than this
+    fn total_limit(&self) -> Option<u64> {
+        match *self {
+            TrackTotalHits::Disabled => Some(0),
+            TrackTotalHits::UpTo(limit) => Some(limit),
+            TrackTotalHits::Accurate => None,
+        }
+    }
+}
+
+
+fn parse_track_total_hits(json: &serde_json::Value) -> Option<TrackTotalHits> {
+    match *json {
+        serde_json::Value::Bool(true) => Some(TrackTotalHits::Accurate),
+        serde_json::Value::Bool(false) => Some(TrackTotalHits::Disabled),
+        serde_json::Value::Number(ref number) => number.as_u64().map(TrackTotalHits::UpTo),
+        serde_json::Value::String(ref string) => {
+            match string.as_ref() {
+                "true" => Some(TrackTotalHits::Accurate),
+                "false" => Some(TrackTotalHits::Disabled),
+                _ => string.parse().ok().map(TrackTotalHits::UpTo),
+            }
+        }
+        _ => None,
+    }
+}
+
+
+/// Renders the "hits.total" value of a search response
+fn render_total_hits(total: u64, track_total_hits: TrackTotalHits, as_int: bool) -> Option<serde_json::Value> {
+    let (value, relation) = match track_total_hits {
+        TrackTotalHits::Disabled => return None,
+        TrackTotalHits::UpTo(limit) if total > limit => (limit, "gte"),
+        TrackTotalHits::UpTo(_) | TrackTotalHits::Accurate => (total, "eq"),
+    };
+
+    if as_int {
+        Some(json!(value))
+    } else {
+        Some(json!({
+            "value": value,
+            "relation": relation,
+        }))
+    }
+}
+
+
+/// Sorts hits that were collected from several indices
+///
+/// Hits from each index are already in order. When sorting by score or by a sort
+/// value, these are merged. Otherwise, the hits of each index are kept together.
+fn merge_hits(mut hits: Vec<serde_json::Value>, sort: &SearchSort) -> Vec<serde_json::Value> {
+    if let Some(order) = sort.value_order() {
+        // Hits without a sort value go last, whatever the order
+        hits.sort_by(|a, b| {
+            let a_value = a.get("sort").and_then(|sort| sort.get(0)).and_then(|value| value.as_f64());
+            let b_value = b.get("sort").and_then(|sort| sort.get(0)).and_then(|value| value.as_f64());
+
+            match (a_value, b_value) {
+                (Some(a_value), Some(b_value)) => {
+                    let ordering = a_value.partial_cmp(&b_value).unwrap_or(Ordering::Equal);
+
+                    match order {
+                        SortOrder::Asc => ordering,
+                        SortOrder::Desc => ordering.reverse(),
+                    }
+                }
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            }
+        });
+    } else if *sort == SearchSort::Score {
+        hits.sort_by(|a, b| {
+            let a_score = a.get("_score").and_then(|score| score.as_f64()).unwrap_or(0.0);
+            let b_score = b.get("_score").and_then(|score| score.as_f64()).unwrap_or(0.0);
+            b_score.partial_cmp(&a_score).unwrap_or(Ordering::Equal)
+        });
+    }
+
+    hits
+}
+
+
+/// The segments that a search ran on, along with the ones that failed
+///
+/// Segments are reported as the shards of the response, these are the parts of
+/// an index that can fail to be searched independently.
+#[derive(Debug, Default, PartialEq)]
+struct SearchShards {
+    total: u64,
+    failures: Vec<serde_json::Value>,
+}
+
+
+impl SearchShards {
+    fn add_segment_failures(&mut self, index_name: &str, num_segments: usize, failures: Vec<SegmentFailure>) {
+        self.total += num_segments as u64;
+
+        for failure in failures {
+            self.failures.push(json!({
+                "shard": (failure.segment).0,
+                "index": index_name,
+                "reason": {
+                    "type": "search_exception",
+                    "reason": failure.reason,
+                },
+            }));
+        }
+    }
+
+    /// Adds the shards of a response from a remote cluster
+    fn add_remote_shards(&mut self, cluster_name: &str, response: &serde_json::Value) {
+        let shards = match response.get("_shards") {
+            Some(shards) => shards,
+            None => return,
+        };
+
+        self.total += shards.get("total").and_then(|total| total.as_u64()).unwrap_or(0);
+
+        if let Some(failures) = shards.get("failures").and_then(|failures| failures.as_array()) {
+            for failure in failures.iter() {
+                let mut failure = failure.clone();
+                if let Some(failure) = failure.as_object_mut() {
+                    let remote_index_name = failure.get("index").and_then(|index| index.as_str()).unwrap_or("").to_string();
+                    failure.insert("index".to_string(), json!(format!("{}:{}", cluster_name, remote_index_name)));
                 }
+
+                self.failures.push(failure);
             }
         }
-        None => {
-            let mut collector = TotalCountCollector::new();
-            index_reader.search(&mut collector, &Query::all()).unwrap();
-            collector.get_total_count()
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let failed = self.failures.len() as u64;
+        let mut shards_json = json!({
+            "total": self.total,
+            "successful": self.total.saturating_sub(failed),
+            "skipped": 0,
+            "failed": failed,
+        });
+
+        if !self.failures.is_empty() {
+            shards_json.as_object_mut().unwrap().insert("failures".to_string(), json!(self.failures));
+        }
+
+        shards_json
+    }
+}
+
+
+/// Reads the total number of hits from a search response
+fn read_total_hits(response: &serde_json::Value) -> u64 {
+    match response.get("hits").and_then(|hits| hits.get("total")) {
+        Some(&serde_json::Value::Number(ref total)) => total.as_u64().unwrap_or(0),
+        Some(&serde_json::Value::Object(ref total)) => total.get("value").and_then(|value| value.as_u64()).unwrap_or(0),
+        _ => 0,
+    }
+}
+
+
+/// Searches an index in this cluster and returns the top hits along with the total number of matches
+fn search_local_index(log: &Logger, index: &Index, cluster_metadata: &ClusterMetadata, query: &Box<QueryBuilder>, sort: &SearchSort, size: usize, total_limit: Option<u64>, field_names: &[String], highlight: Option<&Highlight>, aggregations: Option<&mut AggregationsCollector>) -> (Vec<serde_json::Value>, u64, SearchShards) {
+    let index_reader = index.store.reader();
+    let index_metadata = index.metadata.read().unwrap();
+
+    let mut fields = Vec::new();
+    for field_name in field_names.iter() {
+        let field_ref = match index_reader.schema().get_field_by_name(field_name) {
+            Some(field_ref) => field_ref,
+            None => {
+                warn!(log, "unknown field {:?}", field_name);
+                continue;
+            }
+        };
+
+        fields.push((field_name.to_owned(), field_ref));
+    }
+
+    // Find the documents that match each adjacency matrix filter up front, rather
+    // than running the filters for each document
+    let mut filter_matches = HashMap::new();
+    if let Some(ref aggregations) = aggregations {
+        let context = QueryBuildContext::new().set_index(index).set_index_metadata(&index_metadata).set_cluster_metadata(cluster_metadata).no_score();
+        let (matches, errors) = find_adjacency_filter_matches(&index_reader, &context, aggregations);
+        for error in errors {
+            warn!(log, "failed to run adjacency matrix filter: {}", error);
+        }
+        filter_matches = matches;
+    }
+
+    // Do the search
+    // Segments that fail are reported rather than failing the whole search
+    let segment_results;
+
+    // Segments that failed while finding the nested documents for a sort
+    let mut nested_failures = Vec::new();
+    let (doc_matches, sort_values, total) = match *sort {
+        SearchSort::Score => {
+            let mut collector = match total_limit {
+                Some(limit) => TopScoreCollector::new(size).with_total_limit(limit),
+                None => TopScoreCollector::new(size),
+            };
+            segment_results = search_with_aggregations(&index_reader, &mut collector, &query.build(&QueryBuildContext::new().set_index(index).set_index_metadata(&index_metadata).set_cluster_metadata(cluster_metadata), &index_reader.schema()), aggregations, &filter_matches);
+
+            let total = collector.get_total_count();
+            (collector.into_sorted_vec(), Vec::new(), total)
+        }
+        SearchSort::IndexOrder => {
+            // Documents don't need to be scored, the collector stops early in each segment
+            // (and skips the remaining segments once it's counted enough) unless there are
+            // aggregations, which need all of the matches
+            let mut collector = match total_limit {
+                Some(limit) => IndexOrderCollector::new(size).with_total_limit(limit),
+                None => IndexOrderCollector::new(size),
+            };
+            segment_results = search_with_aggregations(&index_reader, &mut collector, &query.build(&QueryBuildContext::new().set_index(index).set_index_metadata(&index_metadata).set_cluster_metadata(cluster_metadata).no_score(), &index_reader.schema()), aggregations, &filter_matches);
+
+            let total = collector.get_total_count();
+            (collector.into_sorted_vec(), Vec::new(), total)
+        }
+        SearchSort::Script{order, ..} | SearchSort::GeoDistance{order, ..} | SearchSort::Field{order, ..} => {
+            // Only scripts can read the score
+            let needs_score = match *sort {
+                SearchSort::Script{..} => true,
+                _ => false,
+            };
+
+            let mut context = QueryBuildContext::new().set_index(index).set_index_metadata(&index_metadata).set_cluster_metadata(cluster_metadata);
+            if !needs_score {
+                context = context.no_score();
+            }
+
+            // Find the nested documents that pass the nested filter up front, rather than
+            // running the filter for each document
+            let nested_matches = match *sort {
+                SearchSort::Field{nested: Some(NestedSort{ref path, filter: Some(ref filter)}), ..} => {
+                    let mut collector = DocIdSetCollector::new();
+
+                    // The filter was checked when the sort was parsed. Without the path in the
+                    // schema, there are no nested documents for it to match
+                    if let Some(path) = index_reader.schema().get_field_by_name(path) {
+                        let filter = parse_query(filter).expect("nested sort filter was checked when parsed");
+                        nested_failures = index_reader.search_nested_allow_partial(&mut collector, path, &filter.build(&context.clone().no_score(), &index_reader.schema()));
+                    }
+
+                    Some(collector)
+                }
+                _ => None,
+            };
+
+            let mut collector = SortValueCollector::new(size, order == SortOrder::Desc, needs_score, |doc_id, score| {
+                sort_value(&index_reader, sort, nested_matches.as_ref(), DocId::from_u64(doc_id), score)
+            });
+
+            // Segments are already in this order when the index is sorted the same way
+            if let (&SearchSort::Field{ref field, mode, nested: None, ..}, Some(ref index_sort)) = (sort, index_metadata.sort.as_ref()) {
+                let index_sort_mode = if index_sort.descending { SortMode::Max } else { SortMode::Min };
+                if *field == index_sort.field && (order == SortOrder::Desc) == index_sort.descending && mode == index_sort_mode {
+                    collector = collector.with_sorted_segments();
+                }
+            }
+            segment_results = search_with_aggregations(&index_reader, &mut collector, &query.build(&context, &index_reader.schema()), aggregations, &filter_matches);
+
+            let total = collector.get_total_count();
+            let (doc_matches, sort_values) = collector.into_sorted_vec().into_iter().unzip();
+            (doc_matches, sort_values, total)
         }
     };
 
-    return Ok(json_response(status::Ok, json!({"count": count})));
+    // The hits are highlighted with the terms of the query
+    let query_terms = highlight.map(|_| {
+        QueryTerms::from_query(&query.build(&QueryBuildContext::new().set_index(index).set_index_metadata(&index_metadata).set_cluster_metadata(cluster_metadata).no_score(), &index_reader.schema()))
+    });
+
+    // Find which of the named queries match each hit. Each named query is only run
+    // against the hits
+    let mut matched_queries = Vec::new();
+    if !doc_matches.is_empty() {
+        let query = query.build(&QueryBuildContext::new().set_index(index).set_index_metadata(&index_metadata).set_cluster_metadata(cluster_metadata).no_score(), &index_reader.schema());
+        let hit_doc_ids = doc_matches.iter().map(|doc_match| DocId::from_u64(doc_match.doc_id())).collect::<Vec<_>>();
+
+        for (name, named_query) in query.named_queries() {
+            let mut collector = DocIdSetCollector::new();
+            let named_query = named_query.clone().filter(Query::DocIds { doc_ids: hit_doc_ids.clone(), score: 1.0f32 });
+
+            match index_reader.search(&mut collector, &named_query) {
+                Ok(()) => matched_queries.push((name.to_string(), collector)),
+                Err(error) => warn!(log, "failed to run named query {:?}: {}", name, error),
+            }
+        }
+    }
+
+    // Convert hits into JSON
+    let mut hits = Vec::new();
+    for (i, doc_match) in doc_matches.iter().enumerate() {
+        let mut field_values = BTreeMap::new();
+
+        for &(ref field_name, field_ref) in fields.iter() {
+            let value = match index_reader.read_stored_field(field_ref, DocId::from_u64(doc_match.doc_id())) {
+                Ok(Some(value)) => vec![value],
+                Ok(None) => vec![],
+                Err(_) => vec![],
+            };
+
+            field_values.insert(field_name.clone(), value);
+        }
+
+        let mut hit = json!({
+            "_index": index.canonical_name(),
+            "_score": doc_match.score(),
+            "fields": "FIXME",
+        });
+
+        // Hits are only known by their internal ids until now
+        match index_reader.read_document_key(DocId::from_u64(doc_match.doc_id())) {
+            Ok(Some(key)) => {
+                hit.as_object_mut().unwrap().insert("_id".to_string(), json!(key));
+            }
+            Ok(None) => {}
+            Err(error) => warn!(log, "failed to read document key: {:?}", error),
+        }
+
+        if sort.value_order().is_some() {
+            hit.as_object_mut().unwrap().insert("sort".to_string(), json!([sort_values[i]]));
+        }
+
+        if let (Some(highlight), Some(query_terms)) = (highlight, query_terms.as_ref()) {
+            let highlight_json = highlight_document(highlight, query_terms, &index_reader, &index_metadata, DocId::from_u64(doc_match.doc_id()));
+            hit.as_object_mut().unwrap().insert("highlight".to_string(), serde_json::Value::Object(highlight_json));
+        }
+
+        let hit_matched_queries = matched_queries.iter()
+            .filter(|&&(_, ref collector)| collector.contains(DocId::from_u64(doc_match.doc_id())))
+            .map(|&(ref name, _)| name.clone())
+            .collect::<Vec<_>>();
+
+        if !hit_matched_queries.is_empty() {
+            hit.as_object_mut().unwrap().insert("matched_queries".to_string(), json!(hit_matched_queries));
+        }
+
+        hits.push(hit);
+    }
+
+    // A segment that failed in both searches is only reported once
+    let (num_segments, mut failures) = segment_results;
+    for nested_failure in nested_failures {
+        if !failures.iter().any(|failure: &SegmentFailure| failure.segment == nested_failure.segment) {
+            failures.push(nested_failure);
+        }
+    }
+
+    let mut shards = SearchShards::default();
+    shards.add_segment_failures(index.canonical_name(), num_segments, failures);
+
+    (hits, total, shards)
+}
+
+
+/// The state of an index that a search saw
+///
+/// This changes with every write to the index. Deleting the index and creating it
+/// again gives a different one too, as the new index has a different id.
+fn index_generation(index: &Index) -> String {
+    format!("{}:{}", index.id(), index.store.reader().max_seq_no())
+}
+
+
+/// Combines the generations of the indices that were searched into a token for the response
+///
+/// Clients pass this back when fetching the next page, so we can tell them if the
+/// results have changed since the previous page. Remote indices aren't included.
+fn generation_token(mut generations: Vec<String>) -> String {
+    generations.sort();
+    generations.join(",")
 }
 
 
 pub fn view_search(req: &mut Request) -> IronResult<Response> {
     let ref system = get_system!(req);
     let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+    let _permit = acquire_thread_pool!(system.thread_pools.search);
 
-    // Get index
-    let cluster_metadata = system.metadata.read().unwrap();
-    let index = get_index_or_404!(cluster_metadata, *index_name);
-    let index_reader = index.store.reader();
-    let index_metadata = index.metadata.read().unwrap();
+    // Indices in remote clusters are prefixed with the cluster name, eg: "eu:logs"
+    let targets = index_name.split(',').map(split_search_target).collect::<Vec<_>>();
 
     match json_from_request_body!(req) {
         Some(query_json) => {
             // Parse query
-            let query = parse_query(query_json.as_object().unwrap().get("query").unwrap());
+            // Requests that only ask for suggestions don't need a query
+            let match_all_json = json!({"match_all": {}});
+            let query_clause_json = query_json.as_object().unwrap().get("query").unwrap_or(&match_all_json);
+            let query = parse_query(query_clause_json);
             //debug!("{:#?}", query);
 
             match query {
@@ -80,6 +1174,70 @@ pub fn view_search(req: &mut Request) -> IronResult<Response> {
                     let mut from = 0;
                     let mut size = 10;
                     let mut fields = Vec::new();
+                    let mut sort = SearchSort::Score;
+                    let mut track_total_hits = None;
+                    let mut total_hits_as_int = false;
+                    let mut allow_partial_search_results = true;
+                    let mut highlight = None;
+                    let mut suggesters = Vec::new();
+                    let mut aggregations = None;
+                    let mut previous_generation = None;
+
+                    if let Some(sort_json) = query_json.as_object().unwrap().get("sort") {
+                        match parse_sort(sort_json) {
+                            Ok(parsed_sort) => sort = parsed_sort,
+                            Err(SortParseError::Unsupported) => warn!(system.log, "unsupported sort {}", sort_json),
+                            Err(SortParseError::InvalidNestedFilter(error)) => {
+                                return Ok(json_response(status::BadRequest, json!({"message": format!("Invalid nested sort filter: {}", error)})));
+                            }
+                        }
+                    }
+
+                    if let Some(track_total_hits_json) = query_json.as_object().unwrap().get("track_total_hits") {
+                        match parse_track_total_hits(track_total_hits_json) {
+                            Some(parsed_track_total_hits) => track_total_hits = Some(parsed_track_total_hits),
+                            None => {
+                                return Ok(json_response(status::BadRequest, json!({"message": "track_total_hits must be a boolean or a positive integer"})));
+                            }
+                        }
+                    }
+
+                    if let Some(highlight_json) = query_json.as_object().unwrap().get("highlight") {
+                        match parse_highlight(highlight_json) {
+                            Ok(parsed_highlight) => highlight = Some(parsed_highlight),
+                            Err(e) => {
+                                return Ok(json_response(status::BadRequest, json!({"message": format!("Invalid highlight: {}", e)})));
+                            }
+                        }
+                    }
+
+                    if let Some(generation_json) = query_json.as_object().unwrap().get("generation") {
+                        match generation_json.as_str() {
+                            Some(generation) => previous_generation = Some(generation.to_string()),
+                            None => {
+                                return Ok(json_response(status::BadRequest, json!({"message": "generation must be a string"})));
+                            }
+                        }
+                    }
+
+                    if let Some(suggest_json) = query_json.as_object().unwrap().get("suggest") {
+                        match parse_suggest(suggest_json) {
+                            Ok(parsed_suggesters) => suggesters = parsed_suggesters,
+                            Err(e) => {
+                                return Ok(json_response(status::BadRequest, json!({"message": format!("Invalid suggest: {}", e)})));
+                            }
+                        }
+                    }
+
+                    let aggregations_json = query_json.as_object().unwrap().get("aggs").or_else(|| query_json.as_object().unwrap().get("aggregations"));
+                    if let Some(aggregations_json) = aggregations_json {
+                        match parse_aggregations(aggregations_json) {
+                            Ok(parsed_aggregations) => aggregations = Some(parsed_aggregations),
+                            Err(e) => {
+                                return Ok(json_response(status::BadRequest, json!({"message": format!("Invalid aggregations: {}", e)})));
+                            }
+                        }
+                    }
 
                     // TODO: Rewrite this
                     if let Some(ref url_query) = req.url.query() {
@@ -91,17 +1249,33 @@ pub fn view_search(req: &mut Request) -> IronResult<Response> {
                                 "size" => {
                                     size = value.as_ref().parse().expect("need a number");
                                 }
+                                "sort" => {
+                                    // Sorts can be given with an order, eg "price:desc"
+                                    let mut split = value.splitn(2, ':');
+                                    let name = split.next().unwrap();
+
+                                    match parse_sort_field(name, split.next()) {
+                                        Ok(parsed_sort) => sort = parsed_sort,
+                                        Err(_) => warn!(system.log, "unsupported sort {:?}", value),
+                                    }
+                                }
+                                "track_total_hits" => {
+                                    match parse_track_total_hits(&serde_json::Value::String(value.to_string())) {
+                                        Some(parsed_track_total_hits) => track_total_hits = Some(parsed_track_total_hits),
+                                        None => {
+                                            return Ok(json_response(status::BadRequest, json!({"message": "track_total_hits must be a boolean or a positive integer"})));
+                                        }
+                                    }
+                                }
+                                "rest_total_hits_as_int" => {
+                                    total_hits_as_int = value == "true";
+                                }
+                                "allow_partial_search_results" => {
+                                    allow_partial_search_results = value != "false";
+                                }
                                 "fields" => {
                                     for field_name in value.split(",") {
-                                        let field_ref = match index_reader.schema().get_field_by_name(field_name) {
-                                            Some(field_ref) => field_ref,
-                                            None => {
-                                                warn!(system.log, "unknown field {:?}", field_name);
-                                                continue;
-                                            }
-                                        };
-
-                                        fields.push((field_name.to_owned(), field_ref));
+                                        fields.push(field_name.to_owned());
                                     }
                                 }
                                 // terminate_after
@@ -117,48 +1291,587 @@ pub fn view_search(req: &mut Request) -> IronResult<Response> {
                         }
                     }
 
-                    // Do the search
-                    let mut collector = TopScoreCollector::new(from + size);
-                    index_reader.search(&mut collector, &query.build(&QueryBuildContext::new().set_index_metadata(&index_metadata), &index_reader.schema())).unwrap();
-
-                    // Convert hits into JSON
+                    // Search each target
+                    // Every target needs to return enough hits to fill the requested page
                     let mut hits = Vec::new();
-                    for doc_match in collector.into_sorted_vec().iter().skip(from) {
-                        let mut field_values = BTreeMap::new();
+                    let mut total = 0;
+                    let mut remote_clusters_searched = 0;
+                    let mut shards = SearchShards::default();
+                    let mut suggestions = suggesters.iter().map(|_| Vec::new()).collect::<Vec<_>>();
+                    let mut generations = Vec::new();
+
+                    // One set of collectors is shared by all of the indices, so the
+                    // results cover all of them
+                    let mut aggregations_collector = aggregations.as_ref().map(|aggregations| aggregations.collector());
+
+                    for &(cluster_name, target_index_name) in targets.iter() {
+                        match cluster_name {
+                            Some(cluster_name) => {
+                                // The rendered results of a remote cluster can't be merged with ours
+                                if aggregations.is_some() {
+                                    return Ok(json_response(status::BadRequest, json!({"message": "Aggregations aren't supported on remote clusters"})));
+                                }
+
+                                // Don't keep the cluster metadata locked while waiting for the remote cluster
+                                let remote_cluster = match system.metadata.read().unwrap().remote_clusters.get(cluster_name) {
+                                    Some(remote_cluster) => remote_cluster.clone(),
+                                    None => {
+                                        return Ok(json_response(status::NotFound, json!({"message": format!("Remote cluster not found: {}", cluster_name)})));
+                                    }
+                                };
+
+                                let mut remote_query_json = query_json.clone();
+                                {
+                                    let remote_query = remote_query_json.as_object_mut().unwrap();
+                                    remote_query.insert("from".to_string(), json!(0));
+                                    remote_query.insert("size".to_string(), json!(from + size));
+                                    remote_query.insert("track_total_hits".to_string(), json!(true));
+
+                                    if sort == SearchSort::IndexOrder {
+                                        remote_query.insert("sort".to_string(), json!("_doc"));
+                                    }
+                                }
+
+                                let response = match remote_cluster.search(target_index_name, &remote_query_json) {
+                                    Ok(response) => response,
+                                    Err(e) => {
+                                        return Ok(json_response(status::BadGateway, json!({"message": format!("Search on remote cluster {} failed: {}", cluster_name, e)})));
+                                    }
+                                };
+
+                                total += read_total_hits(&response);
+                                shards.add_remote_shards(cluster_name, &response);
+
+                                if let Some(remote_hits) = response.get("hits").and_then(|hits| hits.get("hits")).and_then(|hits| hits.as_array()) {
+                                    for hit in remote_hits.iter() {
+                                        let mut hit = hit.clone();
+                                        if let Some(hit) = hit.as_object_mut() {
+                                            let remote_index_name = hit.get("_index").and_then(|index| index.as_str()).unwrap_or(target_index_name).to_string();
+                                            hit.insert("_index".to_string(), json!(format!("{}:{}", cluster_name, remote_index_name)));
+                                        }
+
+                                        hits.push(hit);
+                                    }
+                                }
+
+                                for (suggester, options) in suggesters.iter().zip(suggestions.iter_mut()) {
+                                    let remote_options = response.get("suggest").and_then(|suggest| suggest.get(&suggester.name)).and_then(|entries| entries.get(0)).and_then(|entry| entry.get("options")).and_then(|options| options.as_array());
+
+                                    for option in remote_options.into_iter().flat_map(|options| options.iter()) {
+                                        let remote_index_name = option.get("_index").and_then(|index| index.as_str()).unwrap_or(target_index_name);
+                                        options.push(SuggestOption {
+                                            text: option.get("text").and_then(|text| text.as_str()).unwrap_or("").to_string(),
+                                            index: format!("{}:{}", cluster_name, remote_index_name),
+                                            score: option.get("_score").and_then(|score| score.as_f64()).unwrap_or(0.0),
+                                        });
+                                    }
+                                }
+
+                                remote_clusters_searched += 1;
+                            }
+                            None => {
+                                let cluster_metadata = system.metadata.read().unwrap();
+                                let index = get_index_or_404!(cluster_metadata, target_index_name);
+
+                                // Documents the query looks up must exist
+                                if let Err(error) = query.check(&QueryBuildContext::new().set_index(index).set_index_metadata(&index.metadata.read().unwrap()).set_cluster_metadata(&cluster_metadata)) {
+                                    return Ok(json_response(status::BadRequest, json!({"message": format!("Query error: {}", error)})));
+                                }
+
+                                // Taken before searching so that a write made during the search
+                                // shows up as a change on the next page
+                                generations.push(index_generation(index));
 
-                        for &(ref field_name, field_ref) in fields.iter() {
-                            let value = match index_reader.read_stored_field(field_ref, DocId::from_u64(doc_match.doc_id())) {
-                                Ok(Some(value)) => vec![value],
-                                Ok(None) => vec![],
-                                Err(_) => vec![],
-                            };
+                                let (index_hits, index_total, index_shards) = search_local_index(&system.log, index, &cluster_metadata, &query, &sort, from + size, track_total_hits.and_then(|track_total_hits| track_total_hits.total_limit()), &fields, highlight.as_ref(), aggregations_collector.as_mut());
+                                hits.extend(index_hits);
+                                total += index_total;
+                                shards.total += index_shards.total;
+                                shards.failures.extend(index_shards.failures);
 
-                            field_values.insert(field_name.clone(), value);
+                                for (suggester, options) in suggesters.iter().zip(suggestions.iter_mut()) {
+                                    let index_reader = index.store.reader();
+                                    let index_metadata = index.metadata.read().unwrap();
+
+                                    match run_completion(suggester, &index_reader, &index_metadata, index.canonical_name()) {
+                                        Ok(index_options) => options.extend(index_options),
+                                        Err(e) => {
+                                            return Ok(json_response(status::BadRequest, json!({"message": format!("Invalid suggest: {}", e)})));
+                                        }
+                                    }
+                                }
+                            }
                         }
+                    }
 
-                        hits.push(json!({
-                            "_score": doc_match.score().unwrap(),
-                            "fields": "FIXME",
-                        }));
+                    if !allow_partial_search_results && !shards.failures.is_empty() {
+                        return Ok(json_response(status::InternalServerError, json!({
+                            "message": format!("Search failed on {} of {} shards", shards.failures.len(), shards.total),
+                            "_shards": shards.to_json(),
+                        })));
                     }
 
+                    let hits = merge_hits(hits, &sort).into_iter().skip(from).take(size).collect::<Vec<_>>();
+
                     // TODO: {"took":5,"timed_out":false,"_shards":{"total":5,"successful":5,"failed":0},"hits":{"total":4,"max_score":1.0,"hits":[{"_index":"wagtail","_type":"searchtests_searchtest_searchtests_searchtestchild","_id":"searchtests_searchtest:5380","_score":1.0,"fields":{"pk":["5380"]}},{"_index":"wagtail","_type":"searchtests_searchtest","_id":"searchtests_searchtest:5379","_score":1.0,"fields":{"pk":["5379"]}}]}}
-                    Ok(json_response(status::Ok,
-                                     json!({
-                                         "hits": {
-                                             "total": hits.len(),
-                                             "hits": hits
-                                        }})))
+                    let mut hits_json = serde_json::Map::new();
+                    // The total is only given as an object when track_total_hits is set, so
+                    // responses to clients that don't know about it keep the same shape
+                    let total_json = match track_total_hits {
+                        Some(track_total_hits) => render_total_hits(total, track_total_hits, total_hits_as_int),
+                        None => Some(json!(total)),
+                    };
+
+                    if let Some(total_json) = total_json {
+                        hits_json.insert("total".to_string(), total_json);
+                    }
+                    hits_json.insert("hits".to_string(), serde_json::Value::Array(hits));
+
+                    let generation = generation_token(generations);
+                    let mut response_json = json!({
+                        "_shards": shards.to_json(),
+                        "hits": hits_json,
+                        "generation": generation,
+                    });
+
+                    // The indices have changed since the previous page was fetched, so hits may
+                    // have moved between pages
+                    if let Some(previous_generation) = previous_generation {
+                        response_json.as_object_mut().unwrap().insert("generation_changed".to_string(), json!(previous_generation != generation));
+                    }
+
+                    if !suggesters.is_empty() {
+                        let mut suggest_json = serde_json::Map::new();
+                        for (suggester, options) in suggesters.iter().zip(suggestions.into_iter()) {
+                            suggest_json.insert(suggester.name.clone(), render_suggestions(suggester, &merge_options(options, suggester)));
+                        }
+
+                        response_json.as_object_mut().unwrap().insert("suggest".to_string(), serde_json::Value::Object(suggest_json));
+                    }
+
+                    if let Some(aggregations_collector) = aggregations_collector {
+                        match aggregations_collector.render_map() {
+                            Ok(aggregations_json) => {
+                                response_json.as_object_mut().unwrap().insert("aggregations".to_string(), serde_json::Value::Object(aggregations_json));
+                            }
+                            Err(e) => {
+                                return Ok(json_response(status::BadRequest, json!({"message": format!("Invalid aggregations: {}", e)})));
+                            }
+                        }
+                    }
+
+                    if remote_clusters_searched > 0 {
+                        response_json.as_object_mut().unwrap().insert("_clusters".to_string(), json!({
+                            "total": remote_clusters_searched,
+                            "successful": remote_clusters_searched,
+                            "skipped": 0,
+                        }));
+                    }
+
+                    Ok(json_response(status::Ok, response_json))
                 }
-                Err(_) => {
-                    // TODO: What specifically is bad about the Query?
-                    let mut response = Response::with((status::BadRequest,
-                                                       "{\"message\": \"Query error\"}"));
-                    response.headers.set_raw("Content-Type", vec![b"application/json".to_vec()]);
-                    Ok(response)
+                Err(error) => {
+                    Ok(json_response(status::BadRequest, json!({"message": format!("Query error: {}", describe_query_error(&error))})))
                 }
             }
         }
         None => Ok(json_response(status::BadRequest, json!({"message": "Missing query"}))),
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use search::query::geo_distance::DistanceUnit;
+    use search::segment::{SegmentId, SegmentFailure};
+    use script::{Expression, ScriptValue};
+
+    use std::fs::remove_dir_all;
+
+    use slog::{Logger, Discard};
+    use fnv::FnvHashMap;
+    use uuid::Uuid;
+    use search::{Term, Token, Document};
+    use search::schema::{FieldType, FIELD_INDEXED, FIELD_STORED};
+    use search::backends::rocksdb::RocksDBStore;
+    use query_parser::parse as parse_query;
+    use aggregations::parse::parse_aggregations;
+    use index::Index;
+    use index::metadata::IndexMetadata;
+    use cluster::metadata::ClusterMetadata;
+
+    use super::{SearchShards, parse_sort, SortParseError, SearchSort, SortOrder, SortMode, NestedSort, parse_track_total_hits, render_total_hits, TrackTotalHits, merge_hits, read_total_hits, generation_token, search_local_index, describe_query_error};
+
+    #[test]
+    fn test_describe_query_error() {
+        let query_json = json!({"bool": {"must": [{"match_all": {}}, {"match": {"title": {"fiel": "foo"}}}]}});
+        let error = parse_query(&query_json).err().unwrap();
+        assert_eq!(describe_query_error(&error), "[query.bool.must[1].match] unrecognised key \"fiel\"");
+
+        let query_json = json!({"foo": {}});
+        let error = parse_query(&query_json).err().unwrap();
+        assert_eq!(describe_query_error(&error), "[query] unrecognised query type \"foo\"");
+    }
+
+    #[test]
+    fn test_parse_sort() {
+        assert_eq!(parse_sort(&json!("_doc")), Ok(SearchSort::IndexOrder));
+        assert_eq!(parse_sort(&json!(["_doc", "_score"])), Ok(SearchSort::IndexOrder));
+        assert_eq!(parse_sort(&json!([{"_doc": "asc"}])), Ok(SearchSort::IndexOrder));
+        assert_eq!(parse_sort(&json!([{"_doc": {"order": "asc"}}])), Ok(SearchSort::IndexOrder));
+        assert_eq!(parse_sort(&json!(["_score"])), Ok(SearchSort::Score));
+        assert_eq!(parse_sort(&json!([])), Ok(SearchSort::Score));
+    }
+
+    #[test]
+    fn test_parse_script_sort() {
+        let mut params = HashMap::new();
+        params.insert("factor".to_string(), ScriptValue::Number(1.1));
+
+        assert_eq!(parse_sort(&json!({
+            "_script": {
+                "type": "number",
+                "script": {
+                    "source": "doc['price'].value * params.factor",
+                    "params": {"factor": 1.1}
+                },
+                "order": "desc"
+            }
+        })), Ok(SearchSort::Script {
+            script: Expression::parse("doc['price'].value * params.factor").unwrap(),
+            params: params,
+            order: SortOrder::Desc,
+        }));
+
+        // Only numbers can be sorted by
+        assert_eq!(parse_sort(&json!({"_script": {"type": "string", "script": "doc['name'].value"}})), Err(SortParseError::Unsupported));
+    }
+
+    #[test]
+    fn test_parse_geo_distance_sort() {
+        assert_eq!(parse_sort(&json!([{
+            "_geo_distance": {
+                "location": {"lat": 40.0, "lon": -70.0},
+                "order": "asc",
+                "unit": "km",
+                "mode": "avg",
+                "distance_type": "arc"
+            }
+        }])), Ok(SearchSort::GeoDistance {
+            field: "location".to_string(),
+            origins: vec![(-70.0, 40.0)],
+            unit: DistanceUnit::Kilometres,
+            mode: SortMode::Avg,
+            order: SortOrder::Asc,
+        }));
+
+        assert_eq!(parse_sort(&json!({
+            "_geo_distance": {
+                "location": [[-70.0, 40.0], "41.0,-71.0"]
+            }
+        })), Ok(SearchSort::GeoDistance {
+            field: "location".to_string(),
+            origins: vec![(-70.0, 40.0), (-71.0, 41.0)],
+            unit: DistanceUnit::Metres,
+            mode: SortMode::Min,
+            order: SortOrder::Asc,
+        }));
+
+        assert_eq!(parse_sort(&json!({"_geo_distance": {"location": [-70.0, 40.0], "mode": "median"}})), Ok(SearchSort::GeoDistance {
+            field: "location".to_string(),
+            origins: vec![(-70.0, 40.0)],
+            unit: DistanceUnit::Metres,
+            mode: SortMode::Median,
+            order: SortOrder::Asc,
+        }));
+        assert_eq!(parse_sort(&json!({"_geo_distance": {"location": [-70.0, 40.0], "mode": "sum"}})), Err(SortParseError::Unsupported));
+        assert_eq!(parse_sort(&json!({"_geo_distance": {"order": "asc"}})), Err(SortParseError::Unsupported));
+    }
+
+    #[test]
+    fn test_parse_field_sort() {
+        assert_eq!(parse_sort(&json!(["price"])), Ok(SearchSort::Field {
+            field: "price".to_string(),
+            mode: SortMode::Min,
+            nested: None,
+            order: SortOrder::Asc,
+        }));
+
+        // Descending sorts use the highest value by default
+        assert_eq!(parse_sort(&json!([{"price": "desc"}])), Ok(SearchSort::Field {
+            field: "price".to_string(),
+            mode: SortMode::Max,
+            nested: None,
+            order: SortOrder::Desc,
+        }));
+
+        assert_eq!(parse_sort(&json!([{"price": {"order": "desc", "mode": "avg"}}])), Ok(SearchSort::Field {
+            field: "price".to_string(),
+            mode: SortMode::Avg,
+            nested: None,
+            order: SortOrder::Desc,
+        }));
+
+        assert_eq!(parse_sort(&json!([{"price": {"mode": "median"}}])), Ok(SearchSort::Field {
+            field: "price".to_string(),
+            mode: SortMode::Median,
+            nested: None,
+            order: SortOrder::Asc,
+        }));
+    }
+
+    #[test]
+    fn test_sort_mode_combine() {
+        let values = [30.0, 10.0, 20.0, 60.0];
+
+        assert_eq!(SortMode::Min.combine(&values), Some(10.0));
+        assert_eq!(SortMode::Max.combine(&values), Some(60.0));
+        assert_eq!(SortMode::Sum.combine(&values), Some(120.0));
+        assert_eq!(SortMode::Avg.combine(&values), Some(30.0));
+        assert_eq!(SortMode::Median.combine(&values), Some(25.0));
+        assert_eq!(SortMode::Median.combine(&values[..3]), Some(20.0));
+        assert_eq!(SortMode::Sum.combine(&[]), None);
+    }
+
+    #[test]
+    fn test_parse_nested_field_sort() {
+        let expected = Ok(SearchSort::Field {
+            field: "offers.price".to_string(),
+            mode: SortMode::Min,
+            nested: Some(NestedSort {
+                path: "offers".to_string(),
+                filter: Some(json!({"term": {"offers.color": "blue"}})),
+            }),
+            order: SortOrder::Asc,
+        });
+
+        assert_eq!(parse_sort(&json!({
+            "offers.price": {
+                "mode": "min",
+                "nested": {
+                    "path": "offers",
+                    "filter": {"term": {"offers.color": "blue"}}
+                }
+            }
+        })), expected);
+
+        assert_eq!(parse_sort(&json!({
+            "offers.price": {
+                "nested_path": "offers",
+                "nested_filter": {"term": {"offers.color": "blue"}}
+            }
+        })), expected);
+
+        // Filters must be valid queries
+        let filter_error = || parse_query(&json!({"colour": {}})).err().unwrap();
+        assert_eq!(parse_sort(&json!({
+            "offers.price": {
+                "nested": {"path": "offers", "filter": {"colour": {}}}
+            }
+        })), Err(SortParseError::InvalidNestedFilter(filter_error())));
+
+        assert_eq!(parse_sort(&json!({
+            "offers.price": {
+                "nested_path": "offers",
+                "nested_filter": {"colour": {}}
+            }
+        })), Err(SortParseError::InvalidNestedFilter(filter_error())));
+    }
+
+    #[test]
+    fn test_parse_unsupported_sort() {
+        assert_eq!(parse_sort(&json!([{"_doc": "desc"}])), Err(SortParseError::Unsupported));
+        assert_eq!(parse_sort(&json!(["_id"])), Err(SortParseError::Unsupported));
+        assert_eq!(parse_sort(&json!([{"title": "sideways"}])), Err(SortParseError::Unsupported));
+        assert_eq!(parse_sort(&json!(123)), Err(SortParseError::Unsupported));
+    }
+
+    #[test]
+    fn test_parse_track_total_hits() {
+        assert_eq!(parse_track_total_hits(&json!(true)), Some(TrackTotalHits::Accurate));
+        assert_eq!(parse_track_total_hits(&json!(false)), Some(TrackTotalHits::Disabled));
+        assert_eq!(parse_track_total_hits(&json!(100)), Some(TrackTotalHits::UpTo(100)));
+        assert_eq!(parse_track_total_hits(&json!("100")), Some(TrackTotalHits::UpTo(100)));
+        assert_eq!(parse_track_total_hits(&json!(-1)), None);
+        assert_eq!(parse_track_total_hits(&json!("foo")), None);
+    }
+
+    #[test]
+    fn test_render_total_hits() {
+        assert_eq!(render_total_hits(50, TrackTotalHits::UpTo(100), false), Some(json!({"value": 50, "relation": "eq"})));
+        assert_eq!(render_total_hits(150, TrackTotalHits::UpTo(100), false), Some(json!({"value": 100, "relation": "gte"})));
+        assert_eq!(render_total_hits(150, TrackTotalHits::Accurate, false), Some(json!({"value": 150, "relation": "eq"})));
+        assert_eq!(render_total_hits(150, TrackTotalHits::UpTo(100), true), Some(json!(100)));
+        assert_eq!(render_total_hits(150, TrackTotalHits::Disabled, false), None);
+    }
+
+    #[test]
+    fn test_merge_hits() {
+        let hits = merge_hits(vec![
+            json!({"_index": "logs", "_score": 2.0}),
+            json!({"_index": "logs", "_score": 1.0}),
+            json!({"_index": "eu:logs", "_score": 3.0}),
+        ], &SearchSort::Score);
+
+        assert_eq!(hits, vec![
+            json!({"_index": "eu:logs", "_score": 3.0}),
+            json!({"_index": "logs", "_score": 2.0}),
+            json!({"_index": "logs", "_score": 1.0}),
+        ]);
+
+        // Hits sorted in index order are kept in the order of the targets
+        let hits = merge_hits(vec![
+            json!({"_index": "logs", "_score": null}),
+            json!({"_index": "eu:logs", "_score": null}),
+        ], &SearchSort::IndexOrder);
+
+        assert_eq!(hits, vec![
+            json!({"_index": "logs", "_score": null}),
+            json!({"_index": "eu:logs", "_score": null}),
+        ]);
+    }
+
+    #[test]
+    fn test_merge_hits_by_sort_value() {
+        let sort = SearchSort::GeoDistance {
+            field: "location".to_string(),
+            origins: vec![(-70.0, 40.0)],
+            unit: DistanceUnit::Kilometres,
+            mode: SortMode::Min,
+            order: SortOrder::Asc,
+        };
+
+        let hits = merge_hits(vec![
+            json!({"_index": "shops", "sort": [1.5]}),
+            json!({"_index": "shops", "sort": [null]}),
+            json!({"_index": "eu:shops", "sort": [0.5]}),
+        ], &sort);
+
+        assert_eq!(hits, vec![
+            json!({"_index": "eu:shops", "sort": [0.5]}),
+            json!({"_index": "shops", "sort": [1.5]}),
+            json!({"_index": "shops", "sort": [null]}),
+        ]);
+    }
+
+    #[test]
+    fn test_read_total_hits() {
+        assert_eq!(read_total_hits(&json!({"hits": {"total": 5, "hits": []}})), 5);
+        assert_eq!(read_total_hits(&json!({"hits": {"total": {"value": 5, "relation": "eq"}, "hits": []}})), 5);
+        assert_eq!(read_total_hits(&json!({"hits": {"hits": []}})), 0);
+    }
+
+    #[test]
+    fn test_search_shards() {
+        let mut shards = SearchShards::default();
+        assert_eq!(shards.to_json(), json!({"total": 0, "successful": 0, "skipped": 0, "failed": 0}));
+
+        shards.add_segment_failures("logs", 3, vec![SegmentFailure {
+            segment: SegmentId(2),
+            reason: "corrupt document id set".to_string(),
+        }]);
+        shards.add_remote_shards("eu", &json!({"_shards": {
+            "total": 2,
+            "successful": 1,
+            "skipped": 0,
+            "failed": 1,
+            "failures": [{"shard": 5, "index": "logs", "reason": {"type": "search_exception", "reason": "timed out"}}]
+        }}));
+
+        assert_eq!(shards.to_json(), json!({
+            "total": 5,
+            "successful": 3,
+            "skipped": 0,
+            "failed": 2,
+            "failures": [
+                {"shard": 2, "index": "logs", "reason": {"type": "search_exception", "reason": "corrupt document id set"}},
+                {"shard": 5, "index": "eu:logs", "reason": {"type": "search_exception", "reason": "timed out"}},
+            ],
+        }));
+    }
+
+    fn make_accounts_index(path: &str) -> Index {
+        let _ = remove_dir_all(path);
+
+        let mut store = RocksDBStore::create(path).unwrap();
+        let accounts_field = store.add_field("accounts".to_string(), FieldType::PlainString, FIELD_INDEXED | FIELD_STORED).unwrap();
+
+        for (key, accounts) in vec![("1", vec!["a", "c"]), ("2", vec!["a", "b"]), ("3", vec!["c"]), ("4", vec!["d"])] {
+            let mut indexed_fields = FnvHashMap::default();
+            indexed_fields.insert(accounts_field, accounts.iter().enumerate().map(|(position, account)| Token { term: Term::from_string(account), position: position as u32 + 1 }).collect::<Vec<_>>().into());
+
+            store.insert_or_update_document(&Document {
+                key: key.to_string(),
+                indexed_fields: indexed_fields,
+                stored_fields: FnvHashMap::default(),
+                nested_documents: Vec::new(),
+            }).unwrap();
+        }
+
+        Index::new(Uuid::new_v4(), "emails".to_string(), IndexMetadata::default(), store)
+    }
+
+    #[test]
+    fn test_search_adjacency_matrix() {
+        let index = make_accounts_index("test_indices/test_search_adjacency_matrix");
+        let log = Logger::root(Discard, o!());
+
+        let aggregations = parse_aggregations(&json!({
+            "interactions": {
+                "adjacency_matrix": {
+                    "filters": {
+                        "grpA": {"terms": {"accounts": ["a"]}},
+                        "grpB": {"terms": {"accounts": ["b"]}},
+                        "grpC": {"terms": {"accounts": ["c"]}}
+                    }
+                },
+                "aggs": {
+                    "total": {"cumulative_sum": {"buckets_path": "_count"}}
+                }
+            }
+        })).unwrap();
+
+        let mut collector = aggregations.collector();
+        let (_, total, _) = search_local_index(&log, &index, &ClusterMetadata::new(), &parse_query(&json!({"match_all": {}})).unwrap(), &SearchSort::Score, 10, None, &[], None, Some(&mut collector));
+        assert_eq!(total, 4);
+
+        // The document that matches none of the filters isn't in any bucket
+        let results = collector.render_map().unwrap();
+        assert_eq!(results["interactions"], json!({"buckets": [
+            {"key": "grpA", "doc_count": 2, "total": {"value": 2.0}},
+            {"key": "grpA&grpB", "doc_count": 1, "total": {"value": 3.0}},
+            {"key": "grpA&grpC", "doc_count": 1, "total": {"value": 4.0}},
+            {"key": "grpB", "doc_count": 1, "total": {"value": 5.0}},
+            {"key": "grpC", "doc_count": 2, "total": {"value": 7.0}},
+        ]}));
+    }
+
+    #[test]
+    fn test_search_total_limit() {
+        let index = make_accounts_index("test_indices/test_search_total_limit");
+        let log = Logger::root(Discard, o!());
+        let query = parse_query(&json!({"match_all": {}})).unwrap();
+
+        // Each document is in its own segment. Searching stops once one more than
+        // the limit has been counted, so the total is reported as a lower bound
+        let (hits, total, _) = search_local_index(&log, &index, &ClusterMetadata::new(), &query, &SearchSort::IndexOrder, 1, Some(2), &[], None, None);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(total, 3);
+        assert_eq!(render_total_hits(total, TrackTotalHits::UpTo(2), false), Some(json!({"value": 2, "relation": "gte"})));
+
+        let (_, total, _) = search_local_index(&log, &index, &ClusterMetadata::new(), &query, &SearchSort::Score, 0, Some(2), &[], None, None);
+        assert_eq!(total, 3);
+
+        let (_, total, _) = search_local_index(&log, &index, &ClusterMetadata::new(), &query, &SearchSort::IndexOrder, 1, None, &[], None, None);
+        assert_eq!(total, 4);
+    }
+
+    #[test]
+    fn test_generation_token() {
+        // The order the indices were searched in doesn't matter
+        assert_eq!(generation_token(vec!["b:3".to_string(), "a:7".to_string()]), "a:7,b:3");
+        assert_eq!(generation_token(vec!["a:7".to_string(), "b:3".to_string()]), "a:7,b:3");
+        assert_eq!(generation_token(vec![]), "");
+    }
+}
@@ -1,7 +1,15 @@
 use serde_json;
+use url::form_urlencoded;
+
+use chrono::Utc;
+
+use thread_pool::RejectedExecution;
+use system::System;
+use audit::{AuditEvent, AuditEventType, ANONYMOUS_PRINCIPAL};
 
 use api::iron::prelude::*;
 use api::iron::status;
+use api::filter_path::JsonBody;
 
 
 macro_rules! get_system {
@@ -23,10 +31,159 @@ macro_rules! read_path_parameter {
 pub fn json_response(status: status::Status, content: serde_json::Value) -> Response {
     let mut response = Response::with((status, format!("{}", content)));
     response.headers.set_raw("Content-Type", vec![b"application/json".to_vec()]);
+
+    // Keep the JSON so that middleware can change the response
+    response.extensions.insert::<JsonBody>(content);
     response
 }
 
 
+/// Records a security relevant request in the audit log
+pub fn audit(system: &System, req: &Request, event_type: AuditEventType, index: Option<&str>) {
+    let event = AuditEvent {
+        event_type: event_type,
+        timestamp: Utc::now(),
+        principal: ANONYMOUS_PRINCIPAL.to_string(),
+        source_ip: req.remote_addr.ip(),
+        method: req.method.to_string(),
+        path: format!("/{}", req.url.path().join("/")),
+        index: index.map(|index| index.to_string()),
+    };
+
+    if let Err(error) = system.audit_log.record(&event) {
+        error!(system.log, "failed to write to audit log"; "path" => format!("{:?}", system.audit_log.path()), "error" => format!("{}", error));
+    }
+}
+
+
+pub fn read_query_parameter(req: &Request, name: &str) -> Option<String> {
+    let url_query = match req.url.query() {
+        Some(url_query) => url_query,
+        None => return None,
+    };
+
+    for (key, value) in form_urlencoded::parse(url_query.as_bytes()) {
+        if key == name {
+            return Some(value.into_owned());
+        }
+    }
+
+    None
+}
+
+
+/// The number of copies of each index. Indices aren't replicated yet
+const ACTIVE_SHARD_COPIES: u64 = 1;
+
+
+/// The number of copies that must be active before a write is performed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ActiveShardCount {
+    All,
+    Count(u64),
+}
+
+
+impl ActiveShardCount {
+    pub fn is_satisfied_by(&self, active_copies: u64) -> bool {
+        match *self {
+            ActiveShardCount::All => true,
+            ActiveShardCount::Count(count) => count <= active_copies,
+        }
+    }
+}
+
+
+pub fn parse_wait_for_active_shards(value: &str) -> Option<ActiveShardCount> {
+    match value {
+        "all" => Some(ActiveShardCount::All),
+        _ => value.parse().ok().map(ActiveShardCount::Count),
+    }
+}
+
+
+/// Checks the "wait_for_active_shards" parameter of a write request
+///
+/// Indices aren't replicated, so there's only ever one active copy and there's
+/// nothing to wait for. Values above that can never be met, so they're rejected
+/// as bad requests rather than waiting for a timeout.
+pub fn check_wait_for_active_shards(req: &Request) -> Result<(), Response> {
+    let value = match read_query_parameter(req, "wait_for_active_shards") {
+        Some(value) => value,
+        None => return Ok(()),
+    };
+
+    match parse_wait_for_active_shards(&value) {
+        Some(active_shard_count) => {
+            if active_shard_count.is_satisfied_by(ACTIVE_SHARD_COPIES) {
+                Ok(())
+            } else {
+                Err(json_response(status::BadRequest, json!({
+                    "message": format!("wait_for_active_shards [{}] can't be met, indices aren't replicated so it can't be more than {}", value, ACTIVE_SHARD_COPIES),
+                })))
+            }
+        }
+        None => {
+            Err(json_response(status::BadRequest, json!({
+                "message": "wait_for_active_shards must be \"all\" or a positive integer",
+            })))
+        }
+    }
+}
+
+
+macro_rules! wait_for_active_shards {
+    ($req: expr) => {{
+        use api::utils::check_wait_for_active_shards;
+
+        if let Err(response) = check_wait_for_active_shards($req) {
+            return Ok(response);
+        }
+    }}
+}
+
+
+/// The body of a 429 response. The stats of the pool, including the length of its
+/// queue and how many requests it has rejected, are given with the error
+fn rejected_execution_json(error: &RejectedExecution) -> serde_json::Value {
+    json!({
+        "message": format!("Rejected by the {} thread pool, its queue is full ({} of {} queued, {} rejected)", error.pool, error.stats.queue, error.stats.queue_size, error.stats.rejected),
+        "error": {
+            "type": "rejected_execution_exception",
+            "thread_pool": error.pool,
+            "stats": error.stats.as_json(),
+        },
+        "status": 429,
+    })
+}
+
+
+pub fn rejected_execution_response(error: &RejectedExecution) -> Response {
+    let mut response = json_response(status::TooManyRequests, rejected_execution_json(error));
+
+    // The pool was busy when the request arrived so it's likely to free up soon
+    response.headers.set_raw("Retry-After", vec![b"1".to_vec()]);
+    response
+}
+
+
+/// Takes a slot in a thread pool, responding with 429 if the pool's queue is full
+///
+/// The slot is held until the returned permit goes out of scope
+macro_rules! acquire_thread_pool {
+    ($thread_pool: expr) => {{
+        use api::utils::rejected_execution_response;
+
+        match $thread_pool.acquire() {
+            Ok(permit) => permit,
+            Err(error) => {
+                return Ok(rejected_execution_response(&error));
+            }
+        }
+    }}
+}
+
+
 pub fn index_not_found_response() -> Response {
     json_response(status::NotFound, json!({"message": "Index not found"}))
 }
@@ -103,3 +260,59 @@ macro_rules! json_from_request_body {
         }
     }}
 }
+
+
+#[cfg(test)]
+mod tests {
+    use thread_pool::{RejectedExecution, ThreadPoolStats};
+
+    use super::{parse_wait_for_active_shards, ActiveShardCount, rejected_execution_json};
+
+    #[test]
+    fn test_parse_wait_for_active_shards() {
+        assert_eq!(parse_wait_for_active_shards("all"), Some(ActiveShardCount::All));
+        assert_eq!(parse_wait_for_active_shards("2"), Some(ActiveShardCount::Count(2)));
+        assert_eq!(parse_wait_for_active_shards("-1"), None);
+        assert_eq!(parse_wait_for_active_shards("some"), None);
+    }
+
+    #[test]
+    fn test_active_shard_count_is_satisfied_by() {
+        assert_eq!(ActiveShardCount::All.is_satisfied_by(1), true);
+        assert_eq!(ActiveShardCount::Count(0).is_satisfied_by(1), true);
+        assert_eq!(ActiveShardCount::Count(1).is_satisfied_by(1), true);
+        assert_eq!(ActiveShardCount::Count(2).is_satisfied_by(1), false);
+    }
+
+    #[test]
+    fn test_rejected_execution_json() {
+        let error = RejectedExecution {
+            pool: "search",
+            stats: ThreadPoolStats {
+                size: 16,
+                queue_size: 16,
+                active: 16,
+                queue: 16,
+                completed: 12345,
+                rejected: 3,
+            },
+        };
+
+        assert_eq!(rejected_execution_json(&error), json!({
+            "message": "Rejected by the search thread pool, its queue is full (16 of 16 queued, 3 rejected)",
+            "error": {
+                "type": "rejected_execution_exception",
+                "thread_pool": "search",
+                "stats": {
+                    "size": 16,
+                    "queue_size": 16,
+                    "active": 16,
+                    "queue": 16,
+                    "completed": 12345,
+                    "rejected": 3,
+                },
+            },
+            "status": 429,
+        }));
+    }
+}
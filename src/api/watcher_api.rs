@@ -0,0 +1,112 @@
+use std::io::Read;
+use std::sync::Arc;
+
+use serde_json;
+
+use watcher::parse::parse as parse_watch;
+
+use api::persistent;
+use api::iron::prelude::*;
+use api::iron::status;
+use api::router::Router;
+use api::utils::{json_response, audit};
+use audit::AuditEventType;
+
+
+pub fn view_get_watch(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref watch_id = read_path_parameter!(req, "watch").unwrap_or("");
+
+    // Lock cluster metadata
+    let cluster_metadata = system.metadata.read().unwrap();
+
+    let watch = match cluster_metadata.watches.get(*watch_id) {
+        Some(watch) => watch,
+        None => {
+            return Ok(json_response(status::NotFound, json!({"_id": *watch_id, "found": false})));
+        }
+    };
+
+    return Ok(json_response(status::Ok, json!({
+        "_id": *watch_id,
+        "found": true,
+        "watch": {
+            "index": watch.index,
+            "query": watch.query,
+            "interval_in_seconds": watch.interval.as_secs(),
+        },
+        "history": watch.history(),
+    })));
+}
+
+
+pub fn view_put_watch(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref watch_id = read_path_parameter!(req, "watch").unwrap_or("");
+
+    // Load data from body
+    let data = match json_from_request_body!(req) {
+        Some(data) => data,
+        None => {
+            return Ok(json_response(status::BadRequest, json!({"message": "No data"})));
+        }
+    };
+
+    // Parse watch
+    let watch = match parse_watch(&data) {
+        Ok(watch) => watch,
+        Err(error) => {
+            return Ok(json_response(status::BadRequest, json!({"message": format!("Couldn't parse watch: {:?}", error)})));
+        }
+    };
+
+    // Insert watch
+    let mut cluster_metadata = system.metadata.write().unwrap();
+    let previous_watch = cluster_metadata.watches.insert(watch_id.to_string(), Arc::new(watch));
+    let created = previous_watch.is_none();
+
+    // If the watches couldn't be saved, go back to the ones that are on disk
+    if let Err(error) = system.save_watches(&cluster_metadata) {
+        match previous_watch {
+            Some(previous_watch) => cluster_metadata.watches.insert(watch_id.to_string(), previous_watch),
+            None => cluster_metadata.watches.remove(*watch_id),
+        };
+
+        error!(system.log, "failed to save watches"; "error" => &error);
+        return Ok(json_response(status::InternalServerError, json!({"message": error})));
+    }
+
+    info!(system.log, "registered watch"; "watch" => *watch_id);
+    audit(system, req, AuditEventType::PrivilegedApiAccess, None);
+
+    return Ok(json_response(status::Ok, json!({"_id": *watch_id, "created": created})));
+}
+
+
+pub fn view_delete_watch(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref watch_id = read_path_parameter!(req, "watch").unwrap_or("");
+
+    // Lock cluster metadata
+    let mut cluster_metadata = system.metadata.write().unwrap();
+
+    let watch = match cluster_metadata.watches.remove(*watch_id) {
+        Some(watch) => watch,
+        None => {
+            return Ok(json_response(status::NotFound, json!({"_id": *watch_id, "found": false})));
+        }
+    };
+
+    // If the watches couldn't be saved, go back to the ones that are on disk
+    if let Err(error) = system.save_watches(&cluster_metadata) {
+        cluster_metadata.watches.insert(watch_id.to_string(), watch);
+
+        error!(system.log, "failed to save watches"; "error" => &error);
+        return Ok(json_response(status::InternalServerError, json!({"message": error})));
+    }
+
+    info!(system.log, "deleted watch"; "watch" => *watch_id);
+    audit(system, req, AuditEventType::PrivilegedApiAccess, None);
+
+    return Ok(json_response(status::Ok, json!({"_id": *watch_id, "found": true})));
+}
@@ -0,0 +1,170 @@
+//! Audit log
+//!
+//! Security relevant events, such as indices being deleted or mappings being
+//! changed, are recorded in their own file separately from the server log. Each
+//! event is written as a line of JSON.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use serde_json;
+
+
+/// Requests aren't authenticated yet, so they're all made by this principal
+pub const ANONYMOUS_PRINCIPAL: &'static str = "_anonymous";
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuditEventType {
+    IndexCreated,
+    IndexDeleted,
+    MappingChanged,
+
+    /// An API that changes the configuration of the cluster was used
+    PrivilegedApiAccess,
+}
+
+
+impl AuditEventType {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            AuditEventType::IndexCreated => "index_created",
+            AuditEventType::IndexDeleted => "index_deleted",
+            AuditEventType::MappingChanged => "mapping_changed",
+            AuditEventType::PrivilegedApiAccess => "privileged_api_access",
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEvent {
+    pub event_type: AuditEventType,
+    pub timestamp: DateTime<Utc>,
+    pub principal: String,
+    pub source_ip: IpAddr,
+    pub method: String,
+    pub path: String,
+
+    /// The index the event happened to, if there is one
+    pub index: Option<String>,
+}
+
+
+impl AuditEvent {
+    pub fn as_json(&self) -> serde_json::Value {
+        let mut json = json!({
+            "@timestamp": self.timestamp.to_rfc3339(),
+            "event": self.event_type.name(),
+            "principal": self.principal,
+            "source_ip": self.source_ip.to_string(),
+            "request": {
+                "method": self.method,
+                "path": self.path,
+            },
+        });
+
+        if let Some(ref index) = self.index {
+            json.as_object_mut().unwrap().insert("index".to_string(), json!(index));
+        }
+
+        json
+    }
+}
+
+
+#[derive(Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+
+    /// The file is opened when the first event is recorded
+    file: Mutex<Option<File>>,
+}
+
+
+impl AuditLog {
+    pub fn new<P: AsRef<Path>>(path: P) -> AuditLog {
+        AuditLog {
+            path: path.as_ref().to_path_buf(),
+            file: Mutex::new(None),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends an event to the log. The event is flushed before this returns
+    pub fn record(&self, event: &AuditEvent) -> io::Result<()> {
+        let mut file = self.file.lock().unwrap();
+
+        if file.is_none() {
+            *file = Some(OpenOptions::new().create(true).append(true).open(&self.path)?);
+        }
+
+        let file = file.as_mut().unwrap();
+        writeln!(file, "{}", event.as_json())?;
+        file.flush()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Read;
+
+    use chrono::{TimeZone, Utc};
+    use serde_json;
+    use uuid::Uuid;
+
+    use super::{AuditLog, AuditEvent, AuditEventType, ANONYMOUS_PRINCIPAL};
+
+    fn make_event(event_type: AuditEventType, index: Option<&str>) -> AuditEvent {
+        AuditEvent {
+            event_type: event_type,
+            timestamp: Utc.ymd(2017, 12, 1).and_hms(10, 30, 0),
+            principal: ANONYMOUS_PRINCIPAL.to_string(),
+            source_ip: "127.0.0.1".parse().unwrap(),
+            method: "DELETE".to_string(),
+            path: "/test".to_string(),
+            index: index.map(|index| index.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_event_as_json() {
+        assert_eq!(make_event(AuditEventType::IndexDeleted, Some("test")).as_json(), json!({
+            "@timestamp": "2017-12-01T10:30:00+00:00",
+            "event": "index_deleted",
+            "principal": "_anonymous",
+            "source_ip": "127.0.0.1",
+            "request": {
+                "method": "DELETE",
+                "path": "/test",
+            },
+            "index": "test",
+        }));
+    }
+
+    #[test]
+    fn test_record() {
+        let path = env::temp_dir().join(format!("rusticsearch-audit-{}.log", Uuid::new_v4()));
+        let audit_log = AuditLog::new(&path);
+
+        audit_log.record(&make_event(AuditEventType::IndexDeleted, Some("test"))).unwrap();
+        audit_log.record(&make_event(AuditEventType::PrivilegedApiAccess, None)).unwrap();
+
+        let mut contents = String::new();
+        File::open(&path).unwrap().read_to_string(&mut contents).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let events = contents.lines().map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["event"].clone()).collect::<Vec<_>>();
+        assert_eq!(events, vec![json!("index_deleted"), json!("privileged_api_access")]);
+    }
+}
@@ -0,0 +1,243 @@
+//! Benchmark harness
+//!
+//! Measures indexing throughput, query latency and segment merge performance,
+//! so that changes that affect performance can be compared on the same data.
+//!
+//! Documents are loaded from fixture files. These are newline delimited JSON
+//! with one document per line, for example a subset of Wikipedia articles. The
+//! key of each document is taken from its "_id" field, or its line number if it
+//! doesn't have one.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use serde_json;
+
+use index::Index;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FixtureDocument {
+    pub key: String,
+    pub data: serde_json::Map<String, serde_json::Value>,
+}
+
+
+#[derive(Debug)]
+pub enum FixtureError {
+    Io(io::Error),
+
+    /// The line isn't a JSON object. Lines are counted from 1
+    InvalidDocument(usize),
+}
+
+
+impl From<io::Error> for FixtureError {
+    fn from(e: io::Error) -> FixtureError {
+        FixtureError::Io(e)
+    }
+}
+
+
+impl fmt::Display for FixtureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FixtureError::Io(ref e) => write!(f, "unable to read fixture: {}", e),
+            FixtureError::InvalidDocument(line) => write!(f, "invalid document on line {} of fixture", line),
+        }
+    }
+}
+
+
+pub fn parse_fixture<R: BufRead>(reader: R) -> Result<Vec<FixtureDocument>, FixtureError> {
+    let mut documents = Vec::new();
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let mut data = match serde_json::from_str::<serde_json::Value>(&line) {
+            Ok(serde_json::Value::Object(data)) => data,
+            _ => return Err(FixtureError::InvalidDocument(i + 1)),
+        };
+
+        let key = match data.remove("_id") {
+            Some(serde_json::Value::String(key)) => key,
+            Some(key @ serde_json::Value::Number(_)) => key.to_string(),
+            Some(_) => return Err(FixtureError::InvalidDocument(i + 1)),
+            None => (i + 1).to_string(),
+        };
+
+        documents.push(FixtureDocument {
+            key: key,
+            data: data,
+        });
+    }
+
+    Ok(documents)
+}
+
+
+pub fn load_fixture<P: AsRef<Path>>(path: P) -> Result<Vec<FixtureDocument>, FixtureError> {
+    parse_fixture(BufReader::new(File::open(path)?))
+}
+
+
+pub fn duration_millis(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1000.0 + duration.subsec_nanos() as f64 / 1_000_000.0
+}
+
+
+/// The durations of each run of an operation
+#[derive(Debug, Clone, Default)]
+pub struct Timings {
+    durations: Vec<Duration>,
+}
+
+
+impl Timings {
+    pub fn new() -> Timings {
+        Timings::default()
+    }
+
+    pub fn record(&mut self, duration: Duration) {
+        self.durations.push(duration);
+    }
+
+    /// Runs the operation and records how long it took
+    pub fn time<T, F: FnOnce() -> T>(&mut self, operation: F) -> T {
+        let start = Instant::now();
+        let result = operation();
+        self.record(start.elapsed());
+        result
+    }
+
+    pub fn count(&self) -> usize {
+        self.durations.len()
+    }
+
+    pub fn total(&self) -> Duration {
+        self.durations.iter().fold(Duration::new(0, 0), |total, duration| total + *duration)
+    }
+
+    /// Returns the duration that the given fraction of runs finished within
+    pub fn percentile(&self, fraction: f64) -> Duration {
+        if self.durations.is_empty() {
+            return Duration::new(0, 0);
+        }
+
+        let mut durations = self.durations.clone();
+        durations.sort();
+
+        let rank = (fraction * durations.len() as f64).ceil() as usize;
+        durations[rank.max(1).min(durations.len()) - 1]
+    }
+
+    pub fn as_json(&self) -> serde_json::Value {
+        let mean = if self.durations.is_empty() {
+            0.0
+        } else {
+            duration_millis(self.total()) / self.durations.len() as f64
+        };
+
+        json!({
+            "count": self.count(),
+            "mean_ms": mean,
+            "min_ms": duration_millis(self.durations.iter().min().cloned().unwrap_or(Duration::new(0, 0))),
+            "max_ms": duration_millis(self.durations.iter().max().cloned().unwrap_or(Duration::new(0, 0))),
+            "p50_ms": duration_millis(self.percentile(0.5)),
+            "p95_ms": duration_millis(self.percentile(0.95)),
+        })
+    }
+}
+
+
+/// Merges the index's segments together, as far as the maximum segment size
+/// allows. Returns the number of segments that were merged
+pub fn merge_all_segments(index: &Index) -> Result<usize, String> {
+    let mut segment_stats = index.store.get_segment_statistics()?;
+    segment_stats.sort_by_key(|&(_, ref stats)| stats.total_docs());
+
+    let mut merged = 0;
+    let mut segment_ids = Vec::new();
+    let mut current_doc_count = 0;
+
+    for (segment, stats) in segment_stats {
+        if current_doc_count + stats.total_docs() > 65536 {
+            if segment_ids.len() > 1 {
+                index.store.merge_segments_sorted(&segment_ids, index.segment_sort())?;
+                index.store.purge_segments(&segment_ids)?;
+                merged += segment_ids.len();
+            }
+
+            segment_ids.clear();
+            current_doc_count = 0;
+        }
+
+        segment_ids.push(segment);
+        current_doc_count += stats.total_docs();
+    }
+
+    if segment_ids.len() > 1 {
+        index.store.merge_segments_sorted(&segment_ids, index.segment_sort())?;
+        index.store.purge_segments(&segment_ids)?;
+        merged += segment_ids.len();
+    }
+
+    Ok(merged)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{parse_fixture, Timings, FixtureError};
+
+    #[test]
+    fn test_parse_fixture() {
+        let fixture = "{\"_id\": \"Rust\", \"title\": \"Rust (programming language)\"}\n\n{\"title\": \"Elasticsearch\"}\n{\"_id\": 3, \"title\": \"Lucene\"}\n";
+        let documents = parse_fixture(fixture.as_bytes()).unwrap();
+
+        assert_eq!(documents.iter().map(|document| document.key.as_str()).collect::<Vec<_>>(), vec!["Rust", "3", "3"]);
+        assert_eq!(documents[0].data.get("title"), Some(&json!("Rust (programming language)")));
+        assert!(!documents[0].data.contains_key("_id"));
+    }
+
+    #[test]
+    fn test_parse_fixture_invalid_document() {
+        let fixture = "{\"title\": \"Rust\"}\n[1, 2, 3]\n";
+
+        match parse_fixture(fixture.as_bytes()) {
+            Err(FixtureError::InvalidDocument(2)) => {}
+            result => panic!("expected an invalid document error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_timings() {
+        let mut timings = Timings::new();
+        for millis in 1..101 {
+            timings.record(Duration::from_millis(millis));
+        }
+
+        assert_eq!(timings.count(), 100);
+        assert_eq!(timings.percentile(0.5), Duration::from_millis(50));
+        assert_eq!(timings.percentile(0.95), Duration::from_millis(95));
+        assert_eq!(timings.as_json()["mean_ms"], json!(50.5));
+        assert_eq!(timings.as_json()["max_ms"], json!(100.0));
+    }
+
+    #[test]
+    fn test_empty_timings() {
+        let timings = Timings::new();
+
+        assert_eq!(timings.percentile(0.5), Duration::new(0, 0));
+        assert_eq!(timings.as_json()["mean_ms"], json!(0.0));
+    }
+}
@@ -1,10 +1,14 @@
 pub mod name_registry;
 
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use uuid::Uuid;
 
 use index::Index;
+use ingest::Pipeline;
+use watcher::Watch;
+use cluster::remote::RemoteCluster;
 
 use self::name_registry::NameRegistry;
 
@@ -24,6 +28,9 @@ impl IndexRef {
 pub struct ClusterMetadata {
     pub indices: HashMap<IndexRef, Index>,
     pub names: NameRegistry,
+    pub pipelines: HashMap<String, Pipeline>,
+    pub watches: HashMap<String, Arc<Watch>>,
+    pub remote_clusters: HashMap<String, RemoteCluster>,
 }
 
 
@@ -32,6 +39,9 @@ impl ClusterMetadata {
         ClusterMetadata {
             indices: HashMap::new(),
             names: NameRegistry::new(),
+            pipelines: HashMap::new(),
+            watches: HashMap::new(),
+            remote_clusters: HashMap::new(),
         }
     }
 
@@ -0,0 +1,246 @@
+//! Remote clusters
+//!
+//! Other clusters can be registered by name so their indices can be searched
+//! alongside local ones using the "cluster:index" syntax.
+//!
+//! Requests are sent to the seed that is expected to respond quickest, based on
+//! how long its recent responses took and how many requests are waiting on it.
+
+use std::io::Read;
+use std::cmp::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde_json;
+use hyper::Client;
+use hyper::header::ContentType;
+
+
+#[derive(Debug, PartialEq)]
+pub enum RemoteClusterParseError {
+    ExpectedObject,
+    ExpectedArray,
+    ExpectedString,
+    ExpectedKey(String),
+    NoSeeds,
+}
+
+
+/// How much weight the latest response time has in a seed's moving average
+const RESPONSE_TIME_EWMA_ALPHA: f64 = 0.3;
+
+
+/// The response time that is recorded against a seed when a request to it fails
+const FAILED_RESPONSE_TIME: Duration = Duration::from_secs(30);
+
+
+#[derive(Debug, Default)]
+struct SeedStats {
+    /// Exponentially weighted moving average of response times in milliseconds
+    response_time_ewma: Option<f64>,
+
+    /// The number of requests that are currently waiting for a response
+    outstanding_requests: u64,
+}
+
+
+impl SeedStats {
+    fn record_response_time(&mut self, response_time: Duration) {
+        let response_time = response_time.as_secs() as f64 * 1000.0 + response_time.subsec_nanos() as f64 / 1000000.0;
+
+        self.response_time_ewma = Some(match self.response_time_ewma {
+            Some(ewma) => RESPONSE_TIME_EWMA_ALPHA * response_time + (1.0 - RESPONSE_TIME_EWMA_ALPHA) * ewma,
+            None => response_time,
+        });
+    }
+
+    /// Lower is better. Seeds that haven't responded yet rank first so they get measured
+    fn rank(&self) -> f64 {
+        match self.response_time_ewma {
+            Some(ewma) => ewma * (self.outstanding_requests + 1) as f64,
+            None => 0.0,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct RemoteCluster {
+    /// Addresses ("host:port") of the nodes to send requests to
+    pub seeds: Vec<String>,
+
+    /// Stats for each seed, shared between clones
+    seed_stats: Arc<Mutex<Vec<SeedStats>>>,
+}
+
+
+impl PartialEq for RemoteCluster {
+    fn eq(&self, other: &RemoteCluster) -> bool {
+        self.seeds == other.seeds
+    }
+}
+
+
+impl RemoteCluster {
+    pub fn new(seeds: Vec<String>) -> RemoteCluster {
+        let seed_stats = seeds.iter().map(|_| SeedStats::default()).collect();
+
+        RemoteCluster {
+            seeds: seeds,
+            seed_stats: Arc::new(Mutex::new(seed_stats)),
+        }
+    }
+
+    /// Returns the seeds in the order they should be tried
+    fn ranked_seeds(&self) -> Vec<usize> {
+        let seed_stats = self.seed_stats.lock().unwrap();
+        let mut seeds = (0..self.seeds.len()).collect::<Vec<_>>();
+        seeds.sort_by(|a, b| seed_stats[*a].rank().partial_cmp(&seed_stats[*b].rank()).unwrap_or(Ordering::Equal));
+        seeds
+    }
+
+    fn search_seed(&self, seed: &str, index: &str, body: &serde_json::Value) -> Result<serde_json::Value, String> {
+        let mut client = Client::new();
+        client.set_read_timeout(Some(Duration::from_secs(30)));
+        client.set_write_timeout(Some(Duration::from_secs(10)));
+
+        let url = format!("http://{}/{}/_search?rest_total_hits_as_int=true", seed, index);
+        let body = format!("{}", body);
+        let mut response = client.post(url.as_str())
+                                 .header(ContentType::json())
+                                 .body(body.as_str())
+                                 .send()
+                                 .map_err(|e| format!("request to {} failed: {}", seed, e))?;
+
+        let mut payload = String::new();
+        response.read_to_string(&mut payload).map_err(|e| format!("request to {} failed: {}", seed, e))?;
+
+        if !response.status.is_success() {
+            return Err(format!("{} returned {}: {}", seed, response.status, payload));
+        }
+
+        serde_json::from_str(&payload).map_err(|e| format!("{} returned invalid JSON: {}", seed, e))
+    }
+
+    /// Runs a search on an index in this cluster
+    ///
+    /// Seeds are tried in order of rank until one of them responds
+    pub fn search(&self, index: &str, body: &serde_json::Value) -> Result<serde_json::Value, String> {
+        let mut errors = Vec::new();
+
+        for seed in self.ranked_seeds() {
+            self.seed_stats.lock().unwrap()[seed].outstanding_requests += 1;
+            let start = Instant::now();
+
+            let result = self.search_seed(&self.seeds[seed], index, body);
+
+            {
+                let mut seed_stats = self.seed_stats.lock().unwrap();
+                seed_stats[seed].outstanding_requests -= 1;
+                seed_stats[seed].record_response_time(if result.is_ok() { start.elapsed() } else { FAILED_RESPONSE_TIME });
+            }
+
+            match result {
+                Ok(response) => return Ok(response),
+                Err(e) => errors.push(e),
+            }
+        }
+
+        Err(errors.join(", "))
+    }
+}
+
+
+pub fn parse(json: &serde_json::Value) -> Result<RemoteCluster, RemoteClusterParseError> {
+    let data = json.as_object().ok_or(RemoteClusterParseError::ExpectedObject)?;
+    let seeds_json = data.get("seeds").ok_or(RemoteClusterParseError::ExpectedKey("seeds".to_string()))?;
+    let seeds_array = seeds_json.as_array().ok_or(RemoteClusterParseError::ExpectedArray)?;
+
+    let mut seeds = Vec::new();
+    for seed_json in seeds_array.iter() {
+        let seed = seed_json.as_str().ok_or(RemoteClusterParseError::ExpectedString)?;
+        seeds.push(seed.to_string());
+    }
+
+    if seeds.is_empty() {
+        return Err(RemoteClusterParseError::NoSeeds);
+    }
+
+    Ok(RemoteCluster::new(seeds))
+}
+
+
+/// Splits a search target such as "eu:logs" into the cluster name and the index name
+///
+/// Targets without a cluster name refer to the local cluster
+pub fn split_search_target(target: &str) -> (Option<&str>, &str) {
+    match target.find(':') {
+        Some(position) => (Some(&target[..position]), &target[position + 1..]),
+        None => (None, target),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{parse, split_search_target, RemoteCluster, RemoteClusterParseError, SeedStats};
+
+    #[test]
+    fn test_parse() {
+        let remote = parse(&json!({
+            "seeds": ["eu1.example.com:9200", "eu2.example.com:9200"]
+        })).expect("parse() returned an error");
+
+        assert_eq!(remote, RemoteCluster::new(vec!["eu1.example.com:9200".to_string(), "eu2.example.com:9200".to_string()]));
+    }
+
+    #[test]
+    fn test_parse_no_seeds() {
+        let error = parse(&json!({
+            "seeds": []
+        })).err().expect("parse() was supposed to return an error, but didn't");
+
+        assert_eq!(error, RemoteClusterParseError::NoSeeds);
+    }
+
+    #[test]
+    fn test_ranked_seeds() {
+        let remote = RemoteCluster::new(vec!["a:9200".to_string(), "b:9200".to_string(), "c:9200".to_string()]);
+
+        {
+            let mut seed_stats = remote.seed_stats.lock().unwrap();
+            seed_stats[0].record_response_time(Duration::from_millis(50));
+            seed_stats[1].record_response_time(Duration::from_millis(20));
+        }
+
+        // Seeds without any responses are tried first
+        assert_eq!(remote.ranked_seeds(), vec![2, 1, 0]);
+
+        // A busy seed loses its place
+        {
+            let mut seed_stats = remote.seed_stats.lock().unwrap();
+            seed_stats[2].record_response_time(Duration::from_millis(10));
+            seed_stats[2].outstanding_requests = 5;
+        }
+
+        assert_eq!(remote.ranked_seeds(), vec![1, 0, 2]);
+    }
+
+    #[test]
+    fn test_response_time_ewma() {
+        let mut seed_stats = SeedStats::default();
+        seed_stats.record_response_time(Duration::from_millis(100));
+        assert_eq!(seed_stats.response_time_ewma, Some(100.0));
+
+        seed_stats.record_response_time(Duration::from_millis(200));
+        assert_eq!(seed_stats.response_time_ewma, Some(130.0));
+    }
+
+    #[test]
+    fn test_split_search_target() {
+        assert_eq!(split_search_target("logs"), (None, "logs"));
+        assert_eq!(split_search_target("eu:logs"), (Some("eu"), "logs"));
+    }
+}
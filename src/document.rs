@@ -1,8 +1,11 @@
+use std::collections::HashMap;
+
 use serde_json;
 use search::Document;
+use search::query::nested::nested_document_marker;
 use fnv::FnvHashMap;
 
-use mapping::{Mapping, MappingProperty, FieldValueError};
+use mapping::{Mapping, MappingProperty, FieldType, FieldValueError};
 
 
 #[derive(Debug)]
@@ -27,18 +30,63 @@ pub enum PrepareDocumentError {
 
 impl<'a> DocumentSource<'a> {
     pub fn prepare(&self, mapping: &Mapping) -> Result<Document, PrepareDocumentError> {
-        let mut indexed_fields = FnvHashMap::default();
-        let mut stored_fields = FnvHashMap::default();
+        let mut document = Document {
+            key: self.key.to_string(),
+            indexed_fields: FnvHashMap::default(),
+            stored_fields: FnvHashMap::default(),
+            nested_documents: Vec::new(),
+        };
         let mut all_field_strings: Vec<String> = Vec::new();
 
-        for (field_name, field_value) in self.data {
+        self.prepare_object(mapping, &mapping.properties, "", self.data, &mut document, &mut all_field_strings)?;
+
+        // Insert _all field
+        if let Some(property) = mapping.properties.get("_all") {
+            if let MappingProperty::Field(ref field_mapping) = *property {
+                let strings_json = serde_json::Value::String(all_field_strings.join(" "));
+                let value = field_mapping.process_value_for_index(&strings_json);
+
+                match value {
+                    Ok(Some(value)) => {
+                        document.indexed_fields.insert(field_mapping.index_ref.unwrap(), value);
+                    }
+                    Ok(None) => {}
+                    Err(error) => {
+                        return Err(PrepareDocumentError::FieldValueError {
+                            field_name: "_all".to_string(),
+                            value: strings_json.clone(),
+                            error: error,
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(document)
+    }
+
+    /// Prepares the fields of the document or of one of its nested objects. Field names are
+    /// prefixed with the path of the object in errors
+    fn prepare_object(&self, mapping: &Mapping, properties: &HashMap<String, MappingProperty>, path: &str, data: &serde_json::Map<String, serde_json::Value>, document: &mut Document, all_field_strings: &mut Vec<String>) -> Result<(), PrepareDocumentError> {
+        for (field_name, field_value) in data {
             if *field_value == serde_json::Value::Null {
                 // Treat null like a missing field
                 continue;
             }
 
-            match mapping.properties.get(field_name) {
+            match properties.get(field_name) {
                 Some(&MappingProperty::Field(ref field_mapping)) => {
+                    // Queries in percolator fields must be able to run against this mapping
+                    if field_mapping.data_type == FieldType::Percolator {
+                        if let Err(error) = mapping.validate_percolator_query(field_value) {
+                            return Err(PrepareDocumentError::FieldValueError {
+                                field_name: format!("{}{}", path, field_name),
+                                value: field_value.clone(),
+                                error: error,
+                            });
+                        }
+                    }
+
                     if field_mapping.is_indexed {
                         let value = field_mapping.process_value_for_index(field_value);
 
@@ -52,12 +100,21 @@ impl<'a> DocumentSource<'a> {
                                 }
 
                                 // Insert the field
-                                indexed_fields.insert(field_mapping.index_ref.unwrap(), value);
+                                document.indexed_fields.insert(field_mapping.index_ref.unwrap(), value);
+
+                                // Index the value into the field's subfields as well
+                                for subfield_name in field_mapping.subfields.iter() {
+                                    if let Some(&MappingProperty::Field(ref subfield_mapping)) = properties.get(subfield_name) {
+                                        if let Ok(Some(value)) = subfield_mapping.process_value_for_index(field_value) {
+                                            document.indexed_fields.insert(subfield_mapping.index_ref.unwrap(), value);
+                                        }
+                                    }
+                                }
                             }
                             Ok(None) => {}
                             Err(error) => {
                                 return Err(PrepareDocumentError::FieldValueError {
-                                    field_name: field_name.clone(),
+                                    field_name: format!("{}{}", path, field_name),
                                     value: field_value.clone(),
                                     error: error,
                                 });
@@ -71,12 +128,12 @@ impl<'a> DocumentSource<'a> {
                         match value {
                             Ok(Some(value)) => {
                                 // Insert the field
-                                stored_fields.insert(field_mapping.index_ref.unwrap(), value);
+                                document.stored_fields.insert(field_mapping.index_ref.unwrap(), value);
                             }
                             Ok(None) => {}
                             Err(error) => {
                                 return Err(PrepareDocumentError::FieldValueError {
-                                    field_name: field_name.clone(),
+                                    field_name: format!("{}{}", path, field_name),
                                     value: field_value.clone(),
                                     error: error,
                                 });
@@ -84,44 +141,55 @@ impl<'a> DocumentSource<'a> {
                         }
                     }
                 }
-                Some(&MappingProperty::NestedMapping(ref _nested_mapping)) => {
-                    // TODO
+                Some(&MappingProperty::NestedMapping(ref nested_mapping)) => {
+                    let nested_path = format!("{}{}", path, field_name);
+
+                    // Each object is indexed as a separate document
+                    let objects = match *field_value {
+                        serde_json::Value::Array(ref array) => array.iter().collect(),
+                        _ => vec![field_value],
+                    };
+
+                    for object in objects {
+                        let object_data = match *object {
+                            serde_json::Value::Object(ref object_data) => object_data,
+                            serde_json::Value::Null => continue,
+                            _ => {
+                                return Err(PrepareDocumentError::FieldValueError {
+                                    field_name: nested_path,
+                                    value: object.clone(),
+                                    error: FieldValueError,
+                                });
+                            }
+                        };
+
+                        let mut nested_document = Document {
+                            key: self.key.to_string(),
+                            indexed_fields: FnvHashMap::default(),
+                            stored_fields: FnvHashMap::default(),
+                            nested_documents: Vec::new(),
+                        };
+
+                        // Mark the document as being nested in the path
+                        if let Some(field_ref) = nested_mapping.index_ref {
+                            nested_document.indexed_fields.insert(field_ref, nested_document_marker());
+                        }
+
+                        // Values in nested objects aren't copied into the _all field of the parent
+                        self.prepare_object(mapping, &nested_mapping.properties, &format!("{}.", nested_path), object_data, &mut nested_document, &mut Vec::new())?;
+
+                        document.nested_documents.push(nested_document);
+                    }
                 }
                 None => {
                     // No mapping found
                     return Err(PrepareDocumentError::FieldDoesntExist {
-                        field_name: field_name.clone(),
+                        field_name: format!("{}{}", path, field_name),
                     });
                 }
             }
         }
 
-        // Insert _all field
-        if let Some(property) = mapping.properties.get("_all") {
-            if let MappingProperty::Field(ref field_mapping) = *property {
-                let strings_json = serde_json::Value::String(all_field_strings.join(" "));
-                let value = field_mapping.process_value_for_index(&strings_json);
-
-                match value {
-                    Ok(Some(value)) => {
-                        indexed_fields.insert(field_mapping.index_ref.unwrap(), value);
-                    }
-                    Ok(None) => {}
-                    Err(error) => {
-                        return Err(PrepareDocumentError::FieldValueError {
-                            field_name: "_all".to_string(),
-                            value: strings_json.clone(),
-                            error: error,
-                        });
-                    }
-                }
-            }
-        }
-
-        Ok(Document {
-            key: self.key.to_string(),
-            indexed_fields: indexed_fields,
-            stored_fields: stored_fields,
-        })
+        Ok(())
     }
 }
@@ -0,0 +1,716 @@
+//! Highlighting
+//!
+//! Highlights the words that a query matched in the stored text of each hit.
+//! Word offsets aren't indexed, so the stored text is split into words again
+//! and each word is run through the analyzers of the fields it's matched
+//! against. "matched_fields" lets one field be highlighted with the matches of
+//! its subfields, eg. a stemmed field along with an exact one.
+//!
+//! Words are given the positions that the standard tokenizer would give them.
+//! Phrase and span queries are turned into intervals, these only highlight
+//! their terms where the intervals match rather than every occurrence.
+
+use std::iter;
+
+use fnv::{FnvHashMap, FnvHashSet};
+use serde_json::{Map, Value as Json};
+use unicode_segmentation::UnicodeSegmentation;
+
+use analysis::AnalyzerSpec;
+use search::{Term, DocId};
+use search::document::FieldValue;
+use search::schema::FieldId;
+use search::query::Query;
+use search::query::multi_term_selector::MultiTermSelector;
+use search::query::intervals::IntervalsSource;
+use search::backends::rocksdb::RocksDBReader;
+
+use index::metadata::IndexMetadata;
+
+
+/// Options of a highlighted field
+///
+/// These can be given for all fields and then overridden for each field.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HighlightOptions {
+    pub pre_tag: String,
+    pub post_tag: String,
+
+    /// Only highlights the terms of the query that are in the field, rather than
+    /// those in any field
+    pub require_field_match: bool,
+
+    /// Other fields that the text is also matched against
+    pub matched_fields: Vec<String>,
+
+    /// The most characters in a fragment
+    pub fragment_size: usize,
+
+    /// The most fragments to return. If 0, the whole text is returned
+    pub number_of_fragments: usize,
+}
+
+
+impl Default for HighlightOptions {
+    fn default() -> HighlightOptions {
+        HighlightOptions {
+            pre_tag: "<em>".to_string(),
+            post_tag: "</em>".to_string(),
+            require_field_match: true,
+            matched_fields: Vec::new(),
+            fragment_size: 100,
+            number_of_fragments: 5,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Highlight {
+    /// The fields to highlight, in the order they were given
+    pub fields: Vec<(String, HighlightOptions)>,
+}
+
+
+fn parse_tag(json: &Json) -> Result<String, String> {
+    // Elasticsearch picks from many tags by term importance, we always use the first
+    match *json {
+        Json::String(ref tag) => Ok(tag.clone()),
+        Json::Array(ref tags) => {
+            match tags.first() {
+                Some(&Json::String(ref tag)) => Ok(tag.clone()),
+                _ => Err("tags must be a non-empty array of strings".to_string()),
+            }
+        }
+        _ => Err("tags must be a non-empty array of strings".to_string()),
+    }
+}
+
+
+fn parse_options(json: &Json, options: &mut HighlightOptions, allow_fields: bool) -> Result<(), String> {
+    let object = json.as_object().ok_or_else(|| "highlight options must be an object".to_string())?;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "pre_tags" => {
+                options.pre_tag = parse_tag(value)?;
+            }
+            "post_tags" => {
+                options.post_tag = parse_tag(value)?;
+            }
+            "require_field_match" => {
+                options.require_field_match = value.as_bool().ok_or_else(|| "require_field_match must be a boolean".to_string())?;
+            }
+            "matched_fields" => {
+                let array = value.as_array().ok_or_else(|| "matched_fields must be an array of strings".to_string())?;
+                options.matched_fields = array.iter()
+                    .map(|name| name.as_str().map(|name| name.to_string()).ok_or_else(|| "matched_fields must be an array of strings".to_string()))
+                    .collect::<Result<Vec<_>, _>>()?;
+            }
+            "fragment_size" => {
+                options.fragment_size = value.as_u64().ok_or_else(|| "fragment_size must be a positive integer".to_string())? as usize;
+            }
+            "number_of_fragments" => {
+                options.number_of_fragments = value.as_u64().ok_or_else(|| "number_of_fragments must be a positive integer".to_string())? as usize;
+            }
+            "type" => {
+                // Only the unified highlighter is implemented
+            }
+            "fields" if allow_fields => {}
+            _ => return Err(format!("unrecognised highlight option {:?}", key)),
+        }
+    }
+
+    Ok(())
+}
+
+
+/// Parses the "highlight" section of a search request
+///
+/// Fields can be given as an object or as an array of single key objects.
+pub fn parse_highlight(json: &Json) -> Result<Highlight, String> {
+    let mut defaults = HighlightOptions::default();
+    parse_options(json, &mut defaults, true)?;
+
+    let mut field_jsons = Vec::new();
+    match json.get("fields") {
+        Some(&Json::Object(ref object)) => {
+            field_jsons.extend(object.iter());
+        }
+        Some(&Json::Array(ref array)) => {
+            for item in array.iter() {
+                match item.as_object() {
+                    Some(object) if object.len() == 1 => field_jsons.extend(object.iter()),
+                    _ => return Err("each item of the fields array must be an object with a single key".to_string()),
+                }
+            }
+        }
+        Some(_) => return Err("fields must be an object or an array".to_string()),
+        None => return Err("highlight must have fields".to_string()),
+    }
+
+    let mut fields = Vec::with_capacity(field_jsons.len());
+    for (field_name, field_json) in field_jsons {
+        let mut options = defaults.clone();
+        parse_options(field_json, &mut options, false)?;
+        fields.push((field_name.clone(), options));
+    }
+
+    Ok(Highlight {
+        fields: fields,
+    })
+}
+
+
+/// A term of a phrase or span query, the last term of a phrase prefix query selects many terms
+#[derive(Debug, Clone, PartialEq)]
+pub enum TermMatcher {
+    Term(Term),
+    Selector(MultiTermSelector),
+}
+
+
+impl TermMatcher {
+    fn matches(&self, term: &Term) -> bool {
+        match *self {
+            TermMatcher::Term(ref matcher_term) => matcher_term == term,
+            TermMatcher::Selector(ref selector) => selector.matches(term),
+        }
+    }
+}
+
+
+/// Finds the terms that are highlighted in the intervals of a source. The
+/// terms of filters only decide which intervals match so aren't included
+fn span_terms(source: &IntervalsSource<TermMatcher>) -> Vec<&TermMatcher> {
+    match *source {
+        IntervalsSource::Term(ref term) => vec![term],
+        IntervalsSource::AllOf{ref sources, ..} | IntervalsSource::AnyOf{ref sources} => {
+            sources.iter().flat_map(span_terms).collect()
+        }
+        IntervalsSource::Filtered{ref source, ..} | IntervalsSource::First{ref source, ..} => span_terms(source),
+    }
+}
+
+
+/// Builds the intervals of a phrase. Phrases with a slop can have their terms
+/// in any order, with up to that many other terms between them
+fn phrase_source(terms: Vec<TermMatcher>, slop: u32) -> IntervalsSource<TermMatcher> {
+    IntervalsSource::AllOf {
+        sources: terms.into_iter().map(IntervalsSource::Term).collect(),
+        ordered: slop == 0,
+        max_gaps: Some(slop),
+    }
+}
+
+
+/// The terms of a query in one field
+#[derive(Debug, Default)]
+pub struct FieldTerms {
+    terms: FnvHashSet<Term>,
+    selectors: Vec<MultiTermSelector>,
+
+    /// Terms that only match in the intervals of a phrase or span query
+    spans: Vec<IntervalsSource<TermMatcher>>,
+}
+
+
+impl FieldTerms {
+    /// Checks if the term matches wherever it is
+    pub fn matches(&self, term: &Term) -> bool {
+        self.terms.contains(term) || self.selectors.iter().any(|selector| selector.matches(term))
+    }
+}
+
+
+/// The terms of a query in each field
+#[derive(Debug, Default)]
+pub struct QueryTerms {
+    fields: FnvHashMap<FieldId, FieldTerms>,
+}
+
+
+impl QueryTerms {
+    /// Collects the terms that the query matches. Terms in excluded queries aren't included
+    pub fn from_query(query: &Query) -> QueryTerms {
+        let mut query_terms = QueryTerms::default();
+        query_terms.collect(query);
+        query_terms
+    }
+
+    fn add_terms<'a, I: IntoIterator<Item=&'a Term>>(&mut self, field: FieldId, terms: I) {
+        self.fields.entry(field).or_insert_with(FieldTerms::default).terms.extend(terms.into_iter().cloned());
+    }
+
+    fn add_selector(&mut self, field: FieldId, selector: &MultiTermSelector) {
+        self.fields.entry(field).or_insert_with(FieldTerms::default).selectors.push(selector.clone());
+    }
+
+    fn add_span(&mut self, field: FieldId, source: IntervalsSource<TermMatcher>) {
+        self.fields.entry(field).or_insert_with(FieldTerms::default).spans.push(source);
+    }
+
+    fn collect(&mut self, query: &Query) {
+        match *query {
+            Query::Term{field, ref term, ..} => self.add_terms(field, iter::once(term)),
+            Query::MultiTerm{field, ref term_selector, ..} => self.add_selector(field, term_selector),
+            Query::CommonTerms{field, ref terms, ..} |
+            Query::TermSet{field, ref terms, ..} => self.add_terms(field, terms),
+            Query::BlendedTerm{ref fields, ref term, ..} => {
+                for &(field, _) in fields.iter() {
+                    self.add_terms(field, iter::once(term));
+                }
+            }
+            Query::Phrase{field, ref terms, slop, ..} => {
+                let terms = terms.iter().cloned().map(TermMatcher::Term).collect();
+                self.add_span(field, phrase_source(terms, slop));
+            }
+            Query::PhrasePrefix{field, ref terms, ref last_term_selector, slop, ..} => {
+                let mut terms = terms.iter().cloned().map(TermMatcher::Term).collect::<Vec<_>>();
+                terms.push(TermMatcher::Selector(last_term_selector.clone()));
+                self.add_span(field, phrase_source(terms, slop));
+            }
+            Query::Intervals{field, ref source, ..} => {
+                self.add_span(field, source.map_terms(&|term: &Term| TermMatcher::Term(term.clone())));
+            }
+            Query::Wildcard{field, ref pattern, ..} => self.add_selector(field, &MultiTermSelector::Wildcard(pattern.clone())),
+            Query::FunctionScore{ref query, ..} |
+            Query::Nested{ref query, ..} |
+            Query::Exclude{ref query, ..} |
+            Query::Named{ref query, ..} => self.collect(query),
+            Query::Filter{ref query, ref filter} => {
+                self.collect(query);
+                self.collect(filter);
+            }
+            Query::Conjunction{ref queries} |
+            Query::Disjunction{ref queries} |
+            Query::DisjunctionMax{ref queries, ..} |
+            Query::MinimumShouldMatch{ref queries, ..} => {
+                for query in queries.iter() {
+                    self.collect(query);
+                }
+            }
+            Query::All{..} |
+            Query::None |
+            Query::Ids{..} |
+            Query::DocIds{..} |
+            Query::Exists{..} |
+            Query::GeoShape{..} |
+            Query::GeoDistance{..} |
+            Query::RankFeature{..} |
+            Query::DistanceFeature{..} |
+            Query::Script{..} => {}
+        }
+    }
+
+    pub fn get(&self, field: FieldId) -> Option<&FieldTerms> {
+        self.fields.get(&field)
+    }
+
+    /// The terms of every field
+    pub fn all(&self) -> Vec<&FieldTerms> {
+        self.fields.values().collect()
+    }
+}
+
+
+/// A field that the highlighted text is matched against
+#[derive(Debug)]
+pub struct MatchedField<'a> {
+    /// The analyzer of the field. Words of fields without one aren't split,
+    /// so these only match the whole text
+    pub analyzer: Option<&'a AnalyzerSpec>,
+
+    /// The terms that words must match
+    pub terms: Vec<&'a FieldTerms>,
+}
+
+
+impl<'a> MatchedField<'a> {
+    fn matches(&self, term: &Term) -> bool {
+        self.terms.iter().any(|terms| terms.matches(term))
+    }
+
+    fn spans(&self) -> Vec<&'a IntervalsSource<TermMatcher>> {
+        self.terms.iter().flat_map(|terms| terms.spans.iter()).collect()
+    }
+}
+
+
+/// A word of the text, with its byte offset and if it was matched
+type Word<'t> = (usize, &'t str, bool);
+
+
+fn render_fragment(text: &str, start: usize, end: usize, words: &[Word], options: &HighlightOptions) -> String {
+    let mut fragment = String::new();
+    let mut offset = start;
+
+    for &(word_start, word, matched) in words.iter() {
+        if matched {
+            fragment.push_str(&text[offset..word_start]);
+            fragment.push_str(&options.pre_tag);
+            fragment.push_str(word);
+            fragment.push_str(&options.post_tag);
+            offset = word_start + word.len();
+        }
+    }
+
+    fragment.push_str(&text[offset..end]);
+    fragment
+}
+
+
+/// Highlights the words of the text that any of the fields match
+///
+/// Returns the fragments of the text that have a match in them, in the order they
+/// appear. Returns nothing if no words matched.
+pub fn highlight_text(text: &str, matched_fields: &[MatchedField], options: &HighlightOptions) -> Vec<String> {
+    // Split the text the same way as the standard tokenizer
+    let mut words = text.split_word_bound_indices()
+        .filter(|&(_, word)| word.chars().any(|c| c.is_alphanumeric()))
+        .map(|(offset, word)| (offset, word, false))
+        .collect::<Vec<Word>>();
+
+    for matched_field in matched_fields.iter() {
+        let analyzer = match matched_field.analyzer {
+            Some(analyzer) => analyzer,
+            None => {
+                let term = Term::from_string(text);
+                let span_matches = matched_field.spans().iter().any(|span| span_terms(span).iter().any(|matcher| matcher.matches(&term)));
+                if span_matches || matched_field.matches(&term) {
+                    for word in words.iter_mut() {
+                        word.2 = true;
+                    }
+                }
+
+                continue;
+            }
+        };
+
+        // Words are at the position after their index
+        let word_terms = words.iter().map(|word| analyzer.initialise(word.1).map(|token| token.term).collect::<Vec<_>>()).collect::<Vec<_>>();
+
+        for (word, terms) in words.iter_mut().zip(word_terms.iter()) {
+            if !word.2 {
+                word.2 = terms.iter().any(|term| matched_field.matches(term));
+            }
+        }
+
+        for span in matched_field.spans() {
+            let intervals = span.intervals(&|matcher: &TermMatcher| {
+                word_terms.iter().enumerate()
+                    .filter(|&(_, terms)| terms.iter().any(|term| matcher.matches(term)))
+                    .map(|(index, _)| index as u32 + 1)
+                    .collect()
+            });
+
+            // Words between the terms of a span aren't highlighted
+            let matchers = span_terms(span);
+            for (start, end) in intervals {
+                for index in (start as usize - 1)..(end as usize) {
+                    if word_terms[index].iter().any(|term| matchers.iter().any(|matcher| matcher.matches(term))) {
+                        words[index].2 = true;
+                    }
+                }
+            }
+        }
+    }
+
+    if !words.iter().any(|word| word.2) {
+        return Vec::new();
+    }
+
+    if options.number_of_fragments == 0 {
+        return vec![render_fragment(text, 0, text.len(), &words, options)];
+    }
+
+    // Break the text into fragments at word boundaries and keep those with a match
+    let mut fragments = Vec::new();
+    let mut first_word = 0;
+    while first_word < words.len() && fragments.len() < options.number_of_fragments {
+        let start = words[first_word].0;
+        let mut end_word = first_word + 1;
+        while end_word < words.len() && words[end_word].0 + words[end_word].1.len() - start <= options.fragment_size {
+            end_word += 1;
+        }
+
+        let fragment_words = &words[first_word..end_word];
+        if fragment_words.iter().any(|word| word.2) {
+            // Fragments run up to the next word, so keep any punctuation after the last one
+            let end = words.get(end_word).map_or(text.len(), |word| word.0);
+            fragments.push(render_fragment(text, start, end, fragment_words, options).trim_end().to_string());
+        }
+
+        first_word = end_word;
+    }
+
+    fragments
+}
+
+
+/// Highlights the stored text of each field of a document
+///
+/// Returns the fragments of each field, fields without any matches aren't included.
+pub fn highlight_document(highlight: &Highlight, query_terms: &QueryTerms, index_reader: &RocksDBReader, index_metadata: &IndexMetadata, doc_id: DocId) -> Map<String, Json> {
+    let schema = index_reader.schema();
+    let mut fields_json = Map::new();
+
+    for &(ref field_name, ref options) in highlight.fields.iter() {
+        let text = match schema.get_field_by_name(field_name).map(|field_id| index_reader.read_stored_field(field_id, doc_id)) {
+            Some(Ok(Some(FieldValue::String(text)))) => text,
+            Some(Ok(Some(FieldValue::StringArray(texts)))) => texts.join(" "),
+            _ => continue,
+        };
+
+        let mut matched_fields = Vec::new();
+        let matched_field_names = iter::once(field_name).chain(options.matched_fields.iter().filter(|name| *name != field_name));
+        for name in matched_field_names {
+            let (field_id, field_mapping) = match (schema.get_field_by_name(name), index_metadata.get_field_mapping(name)) {
+                (Some(field_id), Some(field_mapping)) => (field_id, field_mapping),
+                _ => continue,
+            };
+
+            matched_fields.push(MatchedField {
+                analyzer: field_mapping.index_analyzer(),
+                terms: if options.require_field_match {
+                    query_terms.get(field_id).into_iter().collect()
+                } else {
+                    query_terms.all()
+                },
+            });
+        }
+
+        let fragments = highlight_text(&text, &matched_fields, options);
+        if !fragments.is_empty() {
+            fields_json.insert(field_name.clone(), json!(fragments));
+        }
+    }
+
+    fields_json
+}
+
+
+#[cfg(test)]
+mod tests {
+    use search::{Term, Query};
+    use search::schema::FieldId;
+    use search::query::multi_term_selector::MultiTermSelector;
+    use search::query::intervals::IntervalsSource;
+    use analysis::AnalyzerSpec;
+    use analysis::tokenizers::TokenizerSpec;
+    use analysis::filters::FilterSpec;
+
+    use super::{parse_highlight, Highlight, HighlightOptions, QueryTerms, MatchedField, highlight_text};
+
+    fn folded_analyzer() -> AnalyzerSpec {
+        AnalyzerSpec {
+            tokenizer: TokenizerSpec::Standard,
+            filters: vec![FilterSpec::ASCIIFolding, FilterSpec::Lowercase],
+        }
+    }
+
+    fn exact_analyzer() -> AnalyzerSpec {
+        AnalyzerSpec {
+            tokenizer: TokenizerSpec::Standard,
+            filters: vec![],
+        }
+    }
+
+    #[test]
+    fn test_parse_highlight() {
+        let highlight = parse_highlight(&json!({
+            "pre_tags": ["<b>"],
+            "post_tags": ["</b>"],
+            "require_field_match": false,
+            "fields": [
+                {"title": {"matched_fields": ["title", "title.exact"], "number_of_fragments": 0}},
+                {"body": {"pre_tags": ["<i>"], "post_tags": ["</i>"], "fragment_size": 50}}
+            ]
+        }));
+
+        let defaults = HighlightOptions {
+            pre_tag: "<b>".to_string(),
+            post_tag: "</b>".to_string(),
+            require_field_match: false,
+            ..HighlightOptions::default()
+        };
+
+        assert_eq!(highlight, Ok(Highlight {
+            fields: vec![
+                ("title".to_string(), HighlightOptions {
+                    matched_fields: vec!["title".to_string(), "title.exact".to_string()],
+                    number_of_fragments: 0,
+                    ..defaults.clone()
+                }),
+                ("body".to_string(), HighlightOptions {
+                    pre_tag: "<i>".to_string(),
+                    post_tag: "</i>".to_string(),
+                    fragment_size: 50,
+                    ..defaults.clone()
+                }),
+            ],
+        }));
+
+        assert!(parse_highlight(&json!({"fields": {"title": {"fields": {}}}})).is_err());
+        assert!(parse_highlight(&json!({"fields": {"title": {"order": "score"}}})).is_err());
+        assert!(parse_highlight(&json!({"pre_tags": ["<b>"]})).is_err());
+    }
+
+    #[test]
+    fn test_query_terms() {
+        let query = Query::Disjunction {
+            queries: vec![
+                Query::term(FieldId(1), Term::from_string("hello")),
+                Query::MultiTerm {
+                    field: FieldId(2),
+                    term_selector: MultiTermSelector::Prefix("wor".to_string()),
+                    scorer: Default::default(),
+                },
+                Query::term(FieldId(1), Term::from_string("world")).exclude(Query::term(FieldId(1), Term::from_string("foo"))),
+            ],
+        };
+
+        let query_terms = QueryTerms::from_query(&query);
+        let terms = query_terms.get(FieldId(1)).unwrap();
+        assert!(terms.matches(&Term::from_string("hello")));
+        assert!(terms.matches(&Term::from_string("world")));
+        assert!(!terms.matches(&Term::from_string("foo")));
+        assert!(query_terms.get(FieldId(2)).unwrap().matches(&Term::from_string("worlds")));
+        assert!(query_terms.get(FieldId(3)).is_none());
+    }
+
+    #[test]
+    fn test_highlight_text() {
+        let query_terms = QueryTerms::from_query(&Query::term(FieldId(1), Term::from_string("fox")));
+        let analyzer = folded_analyzer();
+        let matched_fields = vec![MatchedField {
+            analyzer: Some(&analyzer),
+            terms: query_terms.all(),
+        }];
+
+        let text = "The quick brown Fox jumps over the lazy dog. A fox is quick.";
+        assert_eq!(highlight_text(text, &matched_fields, &HighlightOptions::default()), vec![
+            "The quick brown <em>Fox</em> jumps over the lazy dog. A <em>fox</em> is quick.".to_string(),
+        ]);
+
+        // Fragments without a match are left out
+        let options = HighlightOptions {
+            fragment_size: 20,
+            ..HighlightOptions::default()
+        };
+        assert_eq!(highlight_text(text, &matched_fields, &options), vec![
+            "The quick brown <em>Fox</em>".to_string(),
+            "dog. A <em>fox</em> is quick.".to_string(),
+        ]);
+
+        let options = HighlightOptions {
+            fragment_size: 20,
+            number_of_fragments: 1,
+            ..HighlightOptions::default()
+        };
+        assert_eq!(highlight_text(text, &matched_fields, &options), vec![
+            "The quick brown <em>Fox</em>".to_string(),
+        ]);
+
+        assert!(highlight_text("Nothing to see here", &matched_fields, &HighlightOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn test_highlight_matched_fields() {
+        // "title" is folded to lowercase ascii, "title.exact" isn't
+        let query = Query::Conjunction {
+            queries: vec![
+                Query::term(FieldId(1), Term::from_string("hello")),
+                Query::term(FieldId(2), Term::from_string("Café")),
+            ],
+        };
+        let query_terms = QueryTerms::from_query(&query);
+        let folded = folded_analyzer();
+        let exact = exact_analyzer();
+        let text = "Hello from the Café and the cafe";
+
+        let title = MatchedField {
+            analyzer: Some(&folded),
+            terms: query_terms.get(FieldId(1)).into_iter().collect(),
+        };
+        let title_exact = MatchedField {
+            analyzer: Some(&exact),
+            terms: query_terms.get(FieldId(2)).into_iter().collect(),
+        };
+        assert_eq!(highlight_text(text, &[title, title_exact], &HighlightOptions::default()), vec![
+            "<em>Hello</em> from the <em>Café</em> and the cafe".to_string(),
+        ]);
+
+        // Without require_field_match, the terms of every field are matched
+        let title = MatchedField {
+            analyzer: Some(&folded),
+            terms: query_terms.all(),
+        };
+        assert_eq!(highlight_text(text, &[title], &HighlightOptions::default()), vec![
+            "<em>Hello</em> from the Café and the cafe".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_highlight_phrases() {
+        let analyzer = folded_analyzer();
+        let text = "A quick brown fox, a brown dog and a quick red fox";
+        let highlight = |query: Query| {
+            let query_terms = QueryTerms::from_query(&query);
+            let matched_fields = vec![MatchedField {
+                analyzer: Some(&analyzer),
+                terms: query_terms.all(),
+            }];
+
+            highlight_text(text, &matched_fields, &HighlightOptions::default())
+        };
+
+        // Only the terms where the phrase matches are highlighted
+        let phrase = |terms: &[&str], slop| {
+            Query::Phrase {
+                field: FieldId(1),
+                terms: terms.iter().map(|term| Term::from_string(term)).collect(),
+                slop: slop,
+                scorer: Default::default(),
+            }
+        };
+        assert_eq!(highlight(phrase(&["brown", "fox"], 0)), vec![
+            "A quick <em>brown</em> <em>fox</em>, a brown dog and a quick red fox".to_string(),
+        ]);
+        assert_eq!(highlight(phrase(&["quick", "fox"], 1)), vec![
+            "A <em>quick</em> brown <em>fox</em>, a brown dog and a <em>quick</em> red <em>fox</em>".to_string(),
+        ]);
+        assert!(highlight(phrase(&["fox", "dog"], 0)).is_empty());
+
+        let query = Query::PhrasePrefix {
+            field: FieldId(1),
+            terms: vec![Term::from_string("brown")],
+            last_term_selector: MultiTermSelector::Prefix("d".to_string()),
+            slop: 0,
+            scorer: Default::default(),
+        };
+        assert_eq!(highlight(query), vec![
+            "A quick brown fox, a <em>brown</em> <em>dog</em> and a quick red fox".to_string(),
+        ]);
+
+        // Spans
+        let query = Query::Intervals {
+            field: FieldId(1),
+            source: IntervalsSource::First {
+                source: Box::new(IntervalsSource::AllOf {
+                    sources: vec![
+                        IntervalsSource::Term(Term::from_string("quick")),
+                        IntervalsSource::Term(Term::from_string("fox")),
+                    ],
+                    ordered: true,
+                    max_gaps: Some(1),
+                }),
+                end: 5,
+            },
+            scorer: Default::default(),
+        };
+        assert_eq!(highlight(query), vec![
+            "A <em>quick</em> brown <em>fox</em>, a brown dog and a quick red fox".to_string(),
+        ]);
+    }
+}
@@ -1,10 +1,24 @@
+use chrono::{self, Utc};
+
 use index::Index;
+use thread_pool::ThreadPool;
 
 
 impl Index {
     /// Run a maintenance task on the index
     /// This must be run periodically by a background thread. It is not currently thread-safe
-    pub fn run_maintenance_task(&self) -> Result<(), String> {
+    ///
+    /// Segments are only merged if a slot in the merge pool is free
+    pub fn run_maintenance_task(&self, merge_pool: &ThreadPool) -> Result<(), String> {
+        // Sync writes to indices with async durability
+        let sync_interval = self.metadata.read().unwrap().translog_sync_interval;
+        self.sync_if_due(sync_interval).map_err(|e| format!("failed to sync: {}", e))?;
+
+        // Trim the change log
+        let retention_period = self.metadata.read().unwrap().soft_deletes_retention_period;
+        let older_than = Utc::now() - chrono::Duration::from_std(retention_period).map_err(|e| format!("{}", e))?;
+        self.store.trim_change_log(older_than).map_err(|e| format!("failed to trim change log: {}", e))?;
+
         let segment_stats = self.store.get_segment_statistics()?;
 
         // TODO: Deactivate segments with 100% deletions
@@ -75,7 +89,12 @@ impl Index {
         }
 
         // Merge segments
-        self.store.merge_segments(&segment_ids)?;
+        let _permit = match merge_pool.acquire() {
+            Ok(permit) => permit,
+            Err(_) => return Ok(()),
+        };
+
+        self.store.merge_segments_sorted(&segment_ids, self.segment_sort())?;
         self.store.purge_segments(&segment_ids)?;
 
         Ok(())
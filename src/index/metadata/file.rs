@@ -6,7 +6,7 @@ use serde_json;
 use atomicwrites::{self, AtomicFile, AllowOverwrite};
 
 use index::metadata::IndexMetadata;
-use index::metadata::parse::{parse, IndexMetadataParseError};
+use index::metadata::parse::{parse_lenient, IndexMetadataParseError};
 
 
 #[derive(Debug)]
@@ -99,7 +99,7 @@ impl IndexMetadata {
         file.read_to_string(&mut s)?;
 
         let mut metadata = IndexMetadata::default();
-        parse(&mut metadata, serde_json::from_str(&s)?)?;
+        parse_lenient(&mut metadata, serde_json::from_str(&s)?)?;
 
         Ok(metadata)
     }
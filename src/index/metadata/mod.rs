@@ -2,32 +2,158 @@ pub mod parse;
 pub mod file;
 
 use std::collections::{HashMap, BTreeMap};
+use std::fmt;
+use std::time::Duration;
 
 use serde::{Serialize, Serializer};
 use serde_json;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
 
 use analysis::AnalyzerSpec;
 use analysis::tokenizers::TokenizerSpec;
 use analysis::filters::FilterSpec;
-use mapping::{Mapping, MappingProperty, FieldMapping};
+use mapping::{Mapping, FieldMapping};
+use VERSION;
+
+
+/// How documents are written to the index
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IndexMode {
+    /// Documents are replaced when a document with the same ID is indexed
+    Standard,
+
+    /// Documents are only ever added, such as for logs and metrics. These can't
+    /// be replaced or deleted by ID, so indexing skips looking up the ID. Each
+    /// document is given a new ID, as IDs given in requests can't be checked.
+    /// IDs set by an ingest pipeline are still looked up, as they can repeat
+    AppendOnly,
+}
+
+
+impl IndexMode {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            IndexMode::Standard => "standard",
+            IndexMode::AppendOnly => "append_only",
+        }
+    }
+}
+
+
+/// When writes are synced to disk
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TranslogDurability {
+    /// Writes are synced before each request returns, so acknowledged writes are never lost
+    Request,
+
+    /// Writes are synced in the background at the sync interval. Writes made
+    /// since the last sync can be lost if the server crashes
+    Async,
+}
+
+
+impl TranslogDurability {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            TranslogDurability::Request => "request",
+            TranslogDurability::Async => "async",
+        }
+    }
+}
+
+
+/// The order documents are kept in, within each segment
+///
+/// This can only be set when the index is created. Searches sorted by the same
+/// field in the same order only collect the first hits of each segment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexSort {
+    pub field: String,
+
+    /// Documents are sorted by their highest value, highest first. Otherwise
+    /// they're sorted by their lowest value, lowest first
+    pub descending: bool,
+}
+
+
+/// Two mappings in an index define a field with the same name differently
+#[derive(Debug, Clone, PartialEq)]
+pub struct MappingConflict {
+    pub field: String,
+
+    /// The names of the two mappings, in the order they were compared
+    pub mappings: (String, String),
+
+    /// The setting that differs, eg "type" or "analyzer"
+    pub setting: &'static str,
+}
+
+
+impl fmt::Display for MappingConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "field [{}] in mapping [{}] conflicts with mapping [{}]: different [{}]", self.field, self.mappings.0, self.mappings.1, self.setting)
+    }
+}
 
 
 #[derive(Debug)]
 pub struct IndexMetadata {
+    /// Identifies this index, so it isn't mixed up with an index that used to have the same name
+    pub uuid: Uuid,
+
+    /// The name the index was created with, used to register it again on startup
+    pub provided_name: String,
+
+    pub creation_date: DateTime<Utc>,
+
+    /// The version of rusticsearch that created the index
+    pub version_created: String,
+
     analyzers: HashMap<String, AnalyzerSpec>,
     tokenizers: HashMap<String, TokenizerSpec>,
     filters: HashMap<String, FilterSpec>,
     pub mappings: HashMap<String, Mapping>,
+
+    /// How long deleted and replaced documents are kept in the change log
+    pub soft_deletes_retention_period: Duration,
+
+    pub mode: IndexMode,
+
+    pub translog_durability: TranslogDurability,
+
+    /// How often writes are synced when the durability is async
+    pub translog_sync_interval: Duration,
+
+    /// The ingest pipeline that documents are run through when the request
+    /// doesn't give one
+    pub default_pipeline: Option<String>,
+
+    /// The ingest pipeline that documents are always run through last
+    pub final_pipeline: Option<String>,
+
+    pub sort: Option<IndexSort>,
 }
 
 
 impl Default for IndexMetadata {
     fn default() -> IndexMetadata {
         let mut metadata = IndexMetadata {
+            uuid: Uuid::new_v4(),
+            provided_name: String::new(),
+            creation_date: Utc::now(),
+            version_created: VERSION.to_string(),
             analyzers: HashMap::new(),
             tokenizers: HashMap::new(),
             filters: HashMap::new(),
             mappings: HashMap::new(),
+            soft_deletes_retention_period: Duration::from_secs(12 * 60 * 60),
+            mode: IndexMode::Standard,
+            translog_durability: TranslogDurability::Request,
+            translog_sync_interval: Duration::from_secs(5),
+            default_pipeline: None,
+            final_pipeline: None,
+            sort: None,
         };
 
         // Builtin tokenizers
@@ -53,6 +179,11 @@ impl Default for IndexMetadata {
 
 
 impl IndexMetadata {
+    /// The creation date in milliseconds since the epoch
+    pub fn creation_date_millis(&self) -> i64 {
+        self.creation_date.timestamp() * 1000 + self.creation_date.timestamp_subsec_millis() as i64
+    }
+
     // Tokenizer helpers
 
     pub fn insert_tokenizer(&mut self, name: String, tokenizer: TokenizerSpec) -> Option<TokenizerSpec> {
@@ -107,19 +238,101 @@ impl IndexMetadata {
         })
     }
 
+    // Ingest helpers
+
+    /// Finds the ingest pipelines that a document is run through, in order
+    ///
+    /// The pipeline given with the request replaces the default pipeline, and
+    /// "_none" skips it. The final pipeline can't be skipped, so enrichment
+    /// that it does always happens
+    pub fn get_ingest_pipelines(&self, requested_pipeline: Option<&str>) -> Vec<String> {
+        let mut pipelines = Vec::new();
+
+        match requested_pipeline {
+            Some("_none") => {}
+            Some(pipeline) => pipelines.push(pipeline.to_string()),
+            None => pipelines.extend(self.default_pipeline.clone()),
+        }
+
+        pipelines.extend(self.final_pipeline.clone());
+        pipelines
+    }
+
     // Mapping helpers
 
+    /// Finds a field in any of the mappings
+    ///
+    /// Mappings are checked for conflicts when they are put, so it doesn't matter
+    /// which mapping the field is found in. Use get_merged_field_mapping to check
+    /// this for indices that were created before conflicts were rejected
     pub fn get_field_mapping(&self, name: &str) -> Option<&FieldMapping> {
         for mapping in self.mappings.values() {
-            if let Some(property) = mapping.properties.get(name) {
-                if let MappingProperty::Field(ref field_mapping) = *property {
-                    return Some(field_mapping);
-                }
+            if let Some(field_mapping) = mapping.get_field(name) {
+                return Some(field_mapping);
             }
         }
 
         None
     }
+
+    /// Finds a field in all of the mappings, returning the names of the mappings
+    /// that define it. The definitions must all agree
+    pub fn get_merged_field_mapping(&self, name: &str) -> Result<Option<(&FieldMapping, Vec<&str>)>, MappingConflict> {
+        let mut mapping_names = self.mappings.keys().collect::<Vec<_>>();
+        mapping_names.sort();
+
+        let mut merged: Option<(&str, &FieldMapping)> = None;
+        let mut defined_in = Vec::new();
+        for mapping_name in mapping_names {
+            let field_mapping = match self.mappings[mapping_name].get_field(name) {
+                Some(field_mapping) => field_mapping,
+                None => continue,
+            };
+
+            if let Some((first_mapping_name, first_field_mapping)) = merged {
+                if let Some(setting) = first_field_mapping.find_conflict(field_mapping) {
+                    return Err(MappingConflict {
+                        field: name.to_string(),
+                        mappings: (mapping_name.to_string(), first_mapping_name.to_string()),
+                        setting: setting,
+                    });
+                }
+            } else {
+                merged = Some((mapping_name, field_mapping));
+            }
+
+            defined_in.push(mapping_name.as_str());
+        }
+
+        Ok(merged.map(|(_, field_mapping)| (field_mapping, defined_in)))
+    }
+
+    /// Checks that a mapping doesn't define any fields differently to the other
+    /// mappings in the index. The mapping with the same name is skipped, as it
+    /// is being replaced
+    pub fn check_mapping_conflicts(&self, mapping_name: &str, mapping: &Mapping) -> Result<(), MappingConflict> {
+        let mut fields = mapping.fields();
+        fields.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut other_mapping_names = self.mappings.keys().filter(|name| *name != mapping_name).collect::<Vec<_>>();
+        other_mapping_names.sort();
+
+        for (field_name, field_mapping) in fields {
+            for other_mapping_name in other_mapping_names.iter() {
+                if let Some(other_field_mapping) = self.mappings[*other_mapping_name].get_field(&field_name) {
+                    if let Some(setting) = field_mapping.find_conflict(other_field_mapping) {
+                        return Err(MappingConflict {
+                            field: field_name,
+                            mappings: (mapping_name.to_string(), other_mapping_name.to_string()),
+                            setting: setting,
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
 }
 
 
@@ -145,11 +358,31 @@ impl Serialize for IndexMetadata {
 
         let json = json!({
             "settings": {
+                "uuid": self.uuid.to_string(),
+                "provided_name": self.provided_name,
+                "creation_date": self.creation_date_millis().to_string(),
+                "version": {
+                    "created": self.version_created,
+                },
                 "analysis": {
                     "tokenizers": tokenizers_json,
                     "filters": filters_json,
                     "analyzers": {},  // TODO
                 },
+                "soft_deletes": {
+                    "retention_period": format!("{}s", self.soft_deletes_retention_period.as_secs()),
+                },
+                "mode": self.mode.name(),
+                "translog": {
+                    "durability": self.translog_durability.name(),
+                    "sync_interval": format!("{}s", self.translog_sync_interval.as_secs()),
+                },
+                "default_pipeline": self.default_pipeline,
+                "final_pipeline": self.final_pipeline,
+                "sort": self.sort.as_ref().map(|sort| json!({
+                    "field": sort.field,
+                    "order": if sort.descending { "desc" } else { "asc" },
+                })),
             },
             "mappings": mappings_json,
         });
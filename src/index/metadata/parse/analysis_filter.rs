@@ -9,6 +9,7 @@ pub enum FilterParseError {
     ExpectedObject,
     ExpectedString,
     ExpectedPositiveInteger,
+    ExpectedBoolean,
     ExpectedKey(String),
     UnrecognisedType(String),
     InvalidSideValue,
@@ -104,6 +105,43 @@ pub fn parse(json: &serde_json::Value) -> Result<FilterSpec, FilterParseError> {
                 edge: edge,
             })
         }
+        "shingle" => {
+            let min_shingle_size = match data.get("min_shingle_size") {
+                Some(min_shingle_size_json) => {
+                    match min_shingle_size_json.as_u64() {
+                        Some(min_shingle_size) => min_shingle_size as usize,
+                        None => return Err(FilterParseError::ExpectedPositiveInteger),
+                    }
+                }
+                None => 2 as usize,
+            };
+
+            let max_shingle_size = match data.get("max_shingle_size") {
+                Some(max_shingle_size_json) => {
+                    match max_shingle_size_json.as_u64() {
+                        Some(max_shingle_size) => max_shingle_size as usize,
+                        None => return Err(FilterParseError::ExpectedPositiveInteger),
+                    }
+                }
+                None => 2 as usize,
+            };
+
+            let output_unigrams = match data.get("output_unigrams") {
+                Some(output_unigrams_json) => {
+                    match output_unigrams_json.as_bool() {
+                        Some(output_unigrams) => output_unigrams,
+                        None => return Err(FilterParseError::ExpectedBoolean),
+                    }
+                }
+                None => true,
+            };
+
+            Ok(FilterSpec::Shingle {
+                min_size: min_shingle_size,
+                max_size: max_shingle_size,
+                output_unigrams: output_unigrams,
+            })
+        }
         // TODO
         // stop
         // reverse
@@ -112,7 +150,6 @@ pub fn parse(json: &serde_json::Value) -> Result<FilterSpec, FilterParseError> {
         // porter_stem
         // kstem
         // standard
-        // shingle
         // unique
         // truncate
         // trim
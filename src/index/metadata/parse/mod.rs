@@ -3,9 +3,13 @@ pub mod analysis_filter;
 pub mod analysis_analyzer;
 
 use serde_json;
+use chrono::{TimeZone, Utc};
+use uuid::Uuid;
 
-use index::metadata::IndexMetadata;
+use index::metadata::{IndexMetadata, IndexMode, TranslogDurability, IndexSort};
+use mapping::FieldType;
 use mapping::parse::{MappingParseError, parse as parse_mapping};
+use watcher::parse::parse_interval;
 
 use self::analysis_tokenizer::{TokenizerParseError, parse as parse_tokenizer};
 use self::analysis_filter::{FilterParseError, parse as parse_filter};
@@ -19,10 +23,145 @@ pub enum IndexMetadataParseError {
     FilterParseError(String, FilterParseError),
     AnalyzerParseError(String, AnalyzerParseError),
     MappingParseError(String, MappingParseError),
+    InvalidRetentionPeriod(String),
+    InvalidIndexMode(String),
+    InvalidUuid(String),
+    InvalidCreationDate(String),
+    InvalidTranslogDurability(String),
+    InvalidTranslogSyncInterval(String),
+    InvalidPipeline(String),
+    InvalidIndexSort(String),
+
+    /// A key that isn't a known setting was given. Contains the path of the key
+    UnrecognisedSetting(String),
 }
 
 
+/// Makes sure all of the keys in an object are known when parsing strictly
+fn check_keys(object: &serde_json::Map<String, serde_json::Value>, path: &str, known_keys: &[&str], strict: bool) -> Result<(), IndexMetadataParseError> {
+    if !strict {
+        return Ok(());
+    }
+
+    for key in object.keys() {
+        if !known_keys.contains(&key.as_str()) {
+            let key_path = if path.is_empty() { key.to_string() } else { format!("{}.{}", path, key) };
+            return Err(IndexMetadataParseError::UnrecognisedSetting(key_path));
+        }
+    }
+
+    Ok(())
+}
+
+
+/// Elasticsearch settings that only tune its own internals. These are accepted,
+/// so that settings written for Elasticsearch can be used as they are, but
+/// they have no effect
+const IGNORED_SETTINGS: &'static [&'static str] = &[
+    "refresh_interval", "max_result_window", "max_inner_result_window", "max_rescore_window",
+    "auto_expand_replicas", "number_of_routing_shards", "codec", "routing", "blocks", "hidden",
+    "priority", "lifecycle", "queries", "store", "merge", "unassigned", "write", "shard",
+    "search", "indexing", "mapping", "highlight", "max_ngram_diff", "max_shingle_diff",
+    "max_terms_count", "max_regex_length", "gc_deletes",
+];
+
+
+/// Inserts a value at a path of keys, creating objects along the way. Objects
+/// that are already there are merged with the new value
+fn insert_setting(settings: &mut serde_json::Map<String, serde_json::Value>, path: &[&str], value: serde_json::Value) {
+    if path.len() > 1 {
+        let child = settings.entry(path[0].to_string()).or_insert_with(|| json!({}));
+        if !child.is_object() {
+            *child = json!({});
+        }
+
+        insert_setting(child.as_object_mut().unwrap(), &path[1..], value);
+        return;
+    }
+
+    match (settings.get_mut(path[0]), value) {
+        (Some(&mut serde_json::Value::Object(ref mut existing)), serde_json::Value::Object(value)) => {
+            for (key, value) in value {
+                insert_setting(existing, &[&key], value);
+            }
+        }
+        (_, value) => {
+            settings.insert(path[0].to_string(), value);
+        }
+    }
+}
+
+
+/// Puts settings in the form that they're parsed in
+///
+/// Like Elasticsearch, settings can be given inside an "index" object, prefixed
+/// with "index.", or with dotted names such as "translog.durability". These are
+/// all turned into nested objects without the "index" part.
+fn normalize_settings(settings: &serde_json::Map<String, serde_json::Value>) -> serde_json::Map<String, serde_json::Value> {
+    let mut normalized = serde_json::Map::new();
+
+    for (key, value) in settings.iter() {
+        let key = if key.starts_with("index.") { &key["index.".len()..] } else { key.as_str() };
+
+        if key == "index" {
+            if let Some(index_settings) = value.as_object() {
+                for (key, value) in normalize_settings(index_settings) {
+                    insert_setting(&mut normalized, &[&key], value);
+                }
+                continue;
+            }
+        }
+
+        let path = key.split('.').collect::<Vec<_>>();
+        let value = match value.as_object() {
+            Some(object) => serde_json::Value::Object(normalize_settings(object)),
+            None => value.clone(),
+        };
+        insert_setting(&mut normalized, &path, value);
+    }
+
+    normalized
+}
+
+
+/// Reads a creation date, given in milliseconds since the epoch as either a string or a number
+fn parse_creation_date_millis(value: &serde_json::Value) -> Option<i64> {
+    match *value {
+        serde_json::Value::String(ref string) => string.parse().ok(),
+        serde_json::Value::Number(ref number) => number.as_i64(),
+        _ => None,
+    }
+}
+
+
+/// Parses index metadata. Unrecognised keys are rejected, so typos in settings
+/// names don't go unnoticed
 pub fn parse(metadata: &mut IndexMetadata, data: serde_json::Value) -> Result<(), IndexMetadataParseError> {
+    parse_with_strictness(metadata, data, true)
+}
+
+
+/// Parses index metadata, ignoring any unrecognised keys
+///
+/// This is used for loading saved metadata, which may have been written by a
+/// different version.
+pub fn parse_lenient(metadata: &mut IndexMetadata, data: serde_json::Value) -> Result<(), IndexMetadataParseError> {
+    parse_with_strictness(metadata, data, false)
+}
+
+
+/// Reads the name of an ingest pipeline. Null and "_none" mean no pipeline
+fn parse_pipeline_name(value: &serde_json::Value) -> Result<Option<String>, IndexMetadataParseError> {
+    match *value {
+        serde_json::Value::Null => Ok(None),
+        serde_json::Value::String(ref name) if name == "_none" => Ok(None),
+        serde_json::Value::String(ref name) if !name.is_empty() => Ok(Some(name.clone())),
+        _ => Err(IndexMetadataParseError::InvalidPipeline(value.to_string())),
+    }
+}
+
+
+fn parse_with_strictness(metadata: &mut IndexMetadata, data: serde_json::Value, strict: bool) -> Result<(), IndexMetadataParseError> {
     let data = match data.as_object() {
         Some(object) => object,
         None => {
@@ -30,11 +169,48 @@ pub fn parse(metadata: &mut IndexMetadata, data: serde_json::Value) -> Result<()
         }
     };
 
+    check_keys(data, "", &["settings", "mappings"], strict)?;
+
     if let Some(settings) = data.get("settings") {
         let settings = match settings.as_object() {
-            Some(object) => object,
+            Some(object) => normalize_settings(object),
             None => return Err(IndexMetadataParseError::ExpectedObject),
         };
+        let settings = &settings;
+
+        // Shards and replicas are accepted for compatibility, but indices are
+        // always stored in a single unreplicated shard
+        let mut known_settings = vec!["uuid", "provided_name", "creation_date", "version", "analysis", "soft_deletes", "mode", "translog", "default_pipeline", "final_pipeline", "sort", "number_of_shards", "number_of_replicas"];
+        known_settings.extend(IGNORED_SETTINGS);
+        check_keys(settings, "settings", &known_settings, strict)?;
+
+        if let Some(uuid) = settings.get("uuid") {
+            metadata.uuid = match uuid.as_str().map(Uuid::parse_str) {
+                Some(Ok(uuid)) => uuid,
+                _ => return Err(IndexMetadataParseError::InvalidUuid(uuid.to_string())),
+            };
+        }
+
+        if let Some(provided_name) = settings.get("provided_name").and_then(|provided_name| provided_name.as_str()) {
+            metadata.provided_name = provided_name.to_string();
+        }
+
+        if let Some(creation_date) = settings.get("creation_date") {
+            metadata.creation_date = match parse_creation_date_millis(creation_date) {
+                Some(millis) if millis >= 0 => Utc.timestamp(millis / 1000, (millis % 1000) as u32 * 1_000_000),
+                _ => return Err(IndexMetadataParseError::InvalidCreationDate(creation_date.to_string())),
+            };
+        }
+
+        if let Some(version) = settings.get("version") {
+            if let Some(version) = version.as_object() {
+                check_keys(version, "settings.version", &["created"], strict)?;
+            }
+
+            if let Some(version_created) = version.get("created").and_then(|created| created.as_str()) {
+                metadata.version_created = version_created.to_string();
+            }
+        }
 
         if let Some(analysis) = settings.get("analysis") {
             let analysis = match analysis.as_object() {
@@ -42,6 +218,8 @@ pub fn parse(metadata: &mut IndexMetadata, data: serde_json::Value) -> Result<()
                 None => return Err(IndexMetadataParseError::ExpectedObject),
             };
 
+            check_keys(analysis, "settings.analysis", &["tokenizer", "filter", "analyzer"], strict)?;
+
             // Tokenisers
             if let Some(tokenizer_data) = analysis.get("tokenizer") {
                 let tokenizer_data = match tokenizer_data.as_object() {
@@ -93,6 +271,93 @@ pub fn parse(metadata: &mut IndexMetadata, data: serde_json::Value) -> Result<()
                 }
             }
         }
+
+        if let Some(soft_deletes) = settings.get("soft_deletes") {
+            let soft_deletes = match soft_deletes.as_object() {
+                Some(object) => object,
+                None => return Err(IndexMetadataParseError::ExpectedObject),
+            };
+
+            check_keys(soft_deletes, "settings.soft_deletes", &["retention_period"], strict)?;
+
+            if let Some(retention_period) = soft_deletes.get("retention_period") {
+                let retention_period = match retention_period.as_str() {
+                    Some(retention_period) => retention_period,
+                    None => return Err(IndexMetadataParseError::InvalidRetentionPeriod(retention_period.to_string())),
+                };
+
+                metadata.soft_deletes_retention_period = match parse_interval(retention_period) {
+                    Ok(retention_period) => retention_period,
+                    Err(_) => return Err(IndexMetadataParseError::InvalidRetentionPeriod(retention_period.to_string())),
+                };
+            }
+        }
+
+        if let Some(mode) = settings.get("mode") {
+            metadata.mode = match mode.as_str() {
+                Some("standard") => IndexMode::Standard,
+                Some("append_only") => IndexMode::AppendOnly,
+                _ => return Err(IndexMetadataParseError::InvalidIndexMode(mode.to_string())),
+            };
+        }
+
+        if let Some(default_pipeline) = settings.get("default_pipeline") {
+            metadata.default_pipeline = parse_pipeline_name(default_pipeline)?;
+        }
+
+        if let Some(final_pipeline) = settings.get("final_pipeline") {
+            metadata.final_pipeline = parse_pipeline_name(final_pipeline)?;
+        }
+
+        if let Some(sort) = settings.get("sort") {
+            metadata.sort = match *sort {
+                serde_json::Value::Null => None,
+                serde_json::Value::Object(ref sort) => {
+                    check_keys(sort, "settings.sort", &["field", "order"], strict)?;
+
+                    let field = match sort.get("field").and_then(|field| field.as_str()) {
+                        Some(field) => field.to_string(),
+                        None => return Err(IndexMetadataParseError::InvalidIndexSort("sort needs a field".to_string())),
+                    };
+
+                    let descending = match sort.get("order").map(|order| order.as_str()) {
+                        None | Some(Some("asc")) => false,
+                        Some(Some("desc")) => true,
+                        Some(_) => return Err(IndexMetadataParseError::InvalidIndexSort("order must be asc or desc".to_string())),
+                    };
+
+                    Some(IndexSort {
+                        field: field,
+                        descending: descending,
+                    })
+                }
+                _ => return Err(IndexMetadataParseError::ExpectedObject),
+            };
+        }
+
+        if let Some(translog) = settings.get("translog") {
+            let translog = match translog.as_object() {
+                Some(object) => object,
+                None => return Err(IndexMetadataParseError::ExpectedObject),
+            };
+
+            check_keys(translog, "settings.translog", &["durability", "sync_interval"], strict)?;
+
+            if let Some(durability) = translog.get("durability") {
+                metadata.translog_durability = match durability.as_str() {
+                    Some("request") => TranslogDurability::Request,
+                    Some("async") => TranslogDurability::Async,
+                    _ => return Err(IndexMetadataParseError::InvalidTranslogDurability(durability.to_string())),
+                };
+            }
+
+            if let Some(sync_interval) = translog.get("sync_interval") {
+                metadata.translog_sync_interval = match sync_interval.as_str().map(parse_interval) {
+                    Some(Ok(sync_interval)) => sync_interval,
+                    _ => return Err(IndexMetadataParseError::InvalidTranslogSyncInterval(sync_interval.to_string())),
+                };
+            }
+        }
     }
 
     if let Some(mappings) = data.get("mappings") {
@@ -111,12 +376,31 @@ pub fn parse(metadata: &mut IndexMetadata, data: serde_json::Value) -> Result<()
         }
     }
 
+    // Documents are sorted by the stored values of the field, so it must be mapped
+    // when the index is created
+    if let Some(ref sort) = metadata.sort {
+        match metadata.get_field_mapping(&sort.field) {
+            Some(field_mapping) if !field_mapping.is_stored => {
+                return Err(IndexMetadataParseError::InvalidIndexSort(format!("sort field [{}] must be stored", sort.field)));
+            }
+            Some(field_mapping) => {
+                match field_mapping.data_type {
+                    FieldType::Integer | FieldType::Date | FieldType::Boolean => {}
+                    _ => return Err(IndexMetadataParseError::InvalidIndexSort(format!("sort field [{}] must be an integer, date or boolean", sort.field))),
+                }
+            }
+            None => return Err(IndexMetadataParseError::InvalidIndexSort(format!("sort field [{}] isn't mapped", sort.field))),
+        }
+    }
+
     Ok(())
 }
 
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use serde_json;
 
     use analysis::ngram_generator::Edge;
@@ -124,9 +408,9 @@ mod tests {
     use analysis::filters::FilterSpec;
     use analysis::AnalyzerSpec;
     use mapping::parse::MappingParseError;
-    use index::metadata::IndexMetadata;
+    use index::metadata::{IndexMetadata, IndexMode, TranslogDurability, IndexSort};
 
-    use super::{parse, IndexMetadataParseError};
+    use super::{parse, parse_lenient, IndexMetadataParseError};
     use super::analysis_tokenizer::TokenizerParseError;
     use super::analysis_filter::FilterParseError;
 
@@ -329,6 +613,196 @@ mod tests {
         assert_eq!(error, IndexMetadataParseError::FilterParseError("bad_filter".to_string(), FilterParseError::UnrecognisedType("foo".to_string())));
     }
 
+    #[test]
+    fn test_soft_deletes_retention_period() {
+        let mut metadata = IndexMetadata::default();
+        assert_eq!(metadata.soft_deletes_retention_period, Duration::from_secs(43200));
+
+        parse(&mut metadata, json!({
+            "settings": {
+                "soft_deletes": {
+                    "retention_period": "1d"
+                }
+            }
+        })).expect("parse() returned an error");
+
+        assert_eq!(metadata.soft_deletes_retention_period, Duration::from_secs(86400));
+
+        let error = parse(&mut metadata, json!({
+            "settings": {
+                "soft_deletes": {
+                    "retention_period": "forever"
+                }
+            }
+        })).err().expect("parse() was supposed to return an error, but didn't");
+
+        assert_eq!(error, IndexMetadataParseError::InvalidRetentionPeriod("forever".to_string()));
+    }
+
+    #[test]
+    fn test_index_mode() {
+        let mut metadata = IndexMetadata::default();
+        assert_eq!(metadata.mode, IndexMode::Standard);
+
+        parse(&mut metadata, json!({
+            "settings": {
+                "mode": "append_only"
+            }
+        })).expect("parse() returned an error");
+
+        assert_eq!(metadata.mode, IndexMode::AppendOnly);
+
+        let error = parse(&mut metadata, json!({
+            "settings": {
+                "mode": "time_series"
+            }
+        })).err().expect("parse() was supposed to return an error, but didn't");
+
+        assert_eq!(error, IndexMetadataParseError::InvalidIndexMode("\"time_series\"".to_string()));
+    }
+
+    #[test]
+    fn test_index_sort() {
+        let mut metadata = IndexMetadata::default();
+        assert_eq!(metadata.sort, None);
+
+        parse(&mut metadata, json!({
+            "settings": {
+                "sort": {"field": "timestamp", "order": "desc"}
+            },
+            "mappings": {
+                "event": {
+                    "properties": {
+                        "timestamp": {"type": "date", "store": true}
+                    }
+                }
+            }
+        })).expect("parse() returned an error");
+
+        assert_eq!(metadata.sort, Some(IndexSort {
+            field: "timestamp".to_string(),
+            descending: true,
+        }));
+
+        // The field must be mapped in the same request
+        let error = parse(&mut IndexMetadata::default(), json!({
+            "settings": {
+                "sort": {"field": "timestamp"}
+            }
+        })).err().expect("parse() was supposed to return an error, but didn't");
+
+        assert_eq!(error, IndexMetadataParseError::InvalidIndexSort("sort field [timestamp] isn't mapped".to_string()));
+
+        let error = parse(&mut IndexMetadata::default(), json!({
+            "settings": {
+                "sort": {"field": "timestamp", "order": "newest"}
+            }
+        })).err().expect("parse() was supposed to return an error, but didn't");
+
+        assert_eq!(error, IndexMetadataParseError::InvalidIndexSort("order must be asc or desc".to_string()));
+    }
+
+    #[test]
+    fn test_translog() {
+        let mut metadata = IndexMetadata::default();
+        assert_eq!(metadata.translog_durability, TranslogDurability::Request);
+        assert_eq!(metadata.translog_sync_interval, Duration::from_secs(5));
+
+        parse(&mut metadata, json!({
+            "settings": {
+                "translog": {
+                    "durability": "async",
+                    "sync_interval": "30s"
+                }
+            }
+        })).expect("parse() returned an error");
+
+        assert_eq!(metadata.translog_durability, TranslogDurability::Async);
+        assert_eq!(metadata.translog_sync_interval, Duration::from_secs(30));
+
+        let error = parse(&mut metadata, json!({
+            "settings": {
+                "translog": {
+                    "durability": "never"
+                }
+            }
+        })).err().expect("parse() was supposed to return an error, but didn't");
+
+        assert_eq!(error, IndexMetadataParseError::InvalidTranslogDurability("\"never\"".to_string()));
+
+        let error = parse(&mut metadata, json!({
+            "settings": {
+                "translog": {
+                    "sync_interval": "0s"
+                }
+            }
+        })).err().expect("parse() was supposed to return an error, but didn't");
+
+        assert_eq!(error, IndexMetadataParseError::InvalidTranslogSyncInterval("\"0s\"".to_string()));
+    }
+
+    #[test]
+    fn test_pipelines() {
+        let mut metadata = IndexMetadata::default();
+        parse(&mut metadata, json!({
+            "settings": {
+                "default_pipeline": "enrich",
+                "final_pipeline": "_none"
+            }
+        })).expect("parse() returned an error");
+
+        assert_eq!(metadata.default_pipeline, Some("enrich".to_string()));
+        assert_eq!(metadata.final_pipeline, None);
+
+        // Saved metadata can be loaded again
+        let mut reloaded = IndexMetadata::default();
+        parse_lenient(&mut reloaded, serde_json::to_value(&metadata).unwrap()).expect("parse() returned an error");
+        assert_eq!(reloaded.default_pipeline, Some("enrich".to_string()));
+        assert_eq!(reloaded.final_pipeline, None);
+
+        let error = parse(&mut metadata, json!({
+            "settings": {
+                "final_pipeline": 1
+            }
+        })).err().expect("parse() was supposed to return an error, but didn't");
+
+        assert_eq!(error, IndexMetadataParseError::InvalidPipeline("1".to_string()));
+    }
+
+    #[test]
+    fn test_uuid_and_creation_date() {
+        let mut metadata = IndexMetadata::default();
+        parse(&mut metadata, json!({
+            "settings": {
+                "uuid": "6cd0d5e0-1a5b-4c6b-9a73-4e9f2f0c1d2e",
+                "provided_name": "logs",
+                "creation_date": "1554382862123",
+                "version": {
+                    "created": "0.0.1"
+                }
+            }
+        })).expect("parse() returned an error");
+
+        assert_eq!(metadata.uuid.to_string(), "6cd0d5e0-1a5b-4c6b-9a73-4e9f2f0c1d2e");
+        assert_eq!(metadata.provided_name, "logs");
+        assert_eq!(metadata.creation_date_millis(), 1554382862123);
+        assert_eq!(metadata.version_created, "0.0.1");
+
+        // Saved metadata can be loaded again
+        let mut reloaded = IndexMetadata::default();
+        parse_lenient(&mut reloaded, serde_json::to_value(&metadata).unwrap()).expect("parse() returned an error");
+        assert_eq!(reloaded.uuid, metadata.uuid);
+        assert_eq!(reloaded.creation_date, metadata.creation_date);
+
+        let error = parse(&mut metadata, json!({
+            "settings": {
+                "uuid": "not-a-uuid"
+            }
+        })).err().expect("parse() was supposed to return an error, but didn't");
+
+        assert_eq!(error, IndexMetadataParseError::InvalidUuid("\"not-a-uuid\"".to_string()));
+    }
+
     #[test]
     fn test_mapping() {
         let mut metadata = IndexMetadata::default();
@@ -360,4 +834,72 @@ mod tests {
 
         assert_eq!(error, IndexMetadataParseError::MappingParseError("test_mapping".to_string(), MappingParseError::UnrecognisedKeys(vec!["foo".to_string()])));
     }
+
+    #[test]
+    fn test_unrecognised_setting() {
+        let mut metadata = IndexMetadata::default();
+        let error = parse(&mut metadata, json!({
+            "settings": {
+                "translog": {
+                    "durabilty": "async"
+                }
+            }
+        })).err().expect("parse() was supposed to return an error, but didn't");
+
+        assert_eq!(error, IndexMetadataParseError::UnrecognisedSetting("settings.translog.durabilty".to_string()));
+
+        let error = parse(&mut metadata, json!({
+            "setings": {}
+        })).err().expect("parse() was supposed to return an error, but didn't");
+
+        assert_eq!(error, IndexMetadataParseError::UnrecognisedSetting("setings".to_string()));
+    }
+
+    #[test]
+    fn test_index_settings_forms() {
+        // Settings can be wrapped in "index", prefixed with "index." or dotted
+        let mut metadata = IndexMetadata::default();
+        parse(&mut metadata, json!({
+            "settings": {
+                "index": {
+                    "number_of_shards": 1,
+                    "translog": {
+                        "sync_interval": "10s"
+                    }
+                },
+                "index.mode": "append_only",
+                "translog.durability": "async",
+                "refresh_interval": "1s"
+            }
+        })).expect("parse() returned an error");
+
+        assert_eq!(metadata.mode, IndexMode::AppendOnly);
+        assert_eq!(metadata.translog_durability, TranslogDurability::Async);
+        assert_eq!(metadata.translog_sync_interval, Duration::from_secs(10));
+
+        // Typos are found in any of the forms
+        let error = parse(&mut metadata, json!({
+            "settings": {
+                "index.translog.durabilty": "async"
+            }
+        })).err().expect("parse() was supposed to return an error, but didn't");
+
+        assert_eq!(error, IndexMetadataParseError::UnrecognisedSetting("settings.translog.durabilty".to_string()));
+    }
+
+    #[test]
+    fn test_unrecognised_setting_lenient() {
+        let mut metadata = IndexMetadata::default();
+        parse_lenient(&mut metadata, json!({
+            "settings": {
+                "mode": "append_only",
+                "translog": {
+                    "durabilty": "async"
+                }
+            }
+        })).expect("parse_lenient() returned an error");
+
+        assert_eq!(metadata.mode, IndexMode::AppendOnly);
+        assert_eq!(metadata.translog_durability, TranslogDurability::Request);
+    }
 }
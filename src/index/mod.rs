@@ -1,13 +1,20 @@
 pub mod maintenance;
 pub mod metadata;
+pub mod terms_lookup;
+pub mod percolate;
 
-use std::sync::RwLock;
+use std::sync::{RwLock, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
-use search::backends::rocksdb::RocksDBStore;
+use rocksdb;
+use search::Document;
+use search::backends::rocksdb::{RocksDBStore, DocumentInsertError, SegmentSort};
 use uuid::Uuid;
 
-use index::metadata::IndexMetadata;
+use index::metadata::{IndexMetadata, IndexMode, TranslogDurability};
+use index::terms_lookup::TermsLookupCache;
 
 
 #[derive(Debug)]
@@ -16,6 +23,11 @@ pub struct Index {
     canonical_name: String,
     pub metadata: RwLock<IndexMetadata>,
     pub store: RocksDBStore,
+    terms_lookup_cache: TermsLookupCache,
+
+    /// Set when there are writes that haven't been synced to disk yet
+    unsynced_writes: AtomicBool,
+    last_sync: Mutex<Instant>,
 }
 
 
@@ -26,6 +38,9 @@ impl Index {
             canonical_name: canonical_name,
             metadata: RwLock::new(metadata),
             store: store,
+            terms_lookup_cache: TermsLookupCache::default(),
+            unsynced_writes: AtomicBool::new(false),
+            last_sync: Mutex::new(Instant::now()),
         }
     }
 
@@ -37,6 +52,67 @@ impl Index {
         &self.canonical_name
     }
 
+    /// Writes a document to the store and returns its sequence number
+    ///
+    /// The mode is passed in, rather than read from the metadata, as callers
+    /// already have the metadata locked.
+    pub fn insert_document(&self, mode: IndexMode, doc: &Document) -> Result<u64, DocumentInsertError> {
+        match mode {
+            IndexMode::Standard => self.store.insert_or_update_document(doc),
+            IndexMode::AppendOnly => self.store.insert_document_append_only(doc),
+        }
+    }
+
+    /// Must be called at the end of each request that writes to the index
+    ///
+    /// With request durability, writes are synced to disk before the request is
+    /// acknowledged. Otherwise, they are left for the maintenance task to sync.
+    pub fn sync_after_request(&self, durability: TranslogDurability) -> Result<(), rocksdb::Error> {
+        match durability {
+            TranslogDurability::Request => {
+                self.store.sync()?;
+                *self.last_sync.lock().unwrap() = Instant::now();
+            }
+            TranslogDurability::Async => {
+                self.unsynced_writes.store(true, Ordering::SeqCst);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Syncs writes to disk if there are any and the interval has passed since the last sync
+    pub fn sync_if_due(&self, interval: Duration) -> Result<(), rocksdb::Error> {
+        let mut last_sync = self.last_sync.lock().unwrap();
+
+        if last_sync.elapsed() < interval || !self.unsynced_writes.swap(false, Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        if let Err(error) = self.store.sync() {
+            // Try again next time
+            self.unsynced_writes.store(true, Ordering::SeqCst);
+            return Err(error);
+        }
+
+        *last_sync = Instant::now();
+        Ok(())
+    }
+
+    /// The order to put documents in when segments are merged, from the index sort
+    ///
+    /// Until a document has a value for the field, it isn't in the store's schema.
+    /// There's nothing to sort by then, so segments are merged in their own order.
+    pub fn segment_sort(&self) -> Option<SegmentSort> {
+        let metadata = self.metadata.read().unwrap();
+        let sort = metadata.sort.as_ref()?;
+
+        Some(SegmentSort {
+            field: self.store.reader().schema().get_field_by_name(&sort.field)?,
+            descending: sort.descending,
+        })
+    }
+
     pub fn metadata_path(&self) -> PathBuf {
         let mut path = self.store.path().to_path_buf();
         path.push("metadata.json");
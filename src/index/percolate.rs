@@ -0,0 +1,215 @@
+use std::env;
+use std::fs::remove_dir_all;
+use std::path::Path;
+
+use serde_json::{self, Map, Value as Json};
+use search::{Document, DocId, Query};
+use search::document::FieldValue;
+use search::schema::Schema;
+use search::backends::rocksdb::RocksDBStore;
+use search::collectors::doc_id_set::DocIdSetCollector;
+use search::collectors::total_count::TotalCountCollector;
+use uuid::Uuid;
+
+use document::DocumentSource;
+use mapping::FieldType;
+use query_parser::{QueryBuilder, QueryBuildContext, parse as parse_query};
+
+use index::Index;
+use index::metadata::IndexMetadata;
+
+
+/// Indexes the documents into a new store at the path and runs each of the queries against them
+///
+/// Returns the ids of the documents that the matching queries were stored in.
+fn run_stored_queries(path: &Path, schema: Schema, documents: &[Document], queries: &[(DocId, Box<QueryBuilder>)], index_metadata: &IndexMetadata) -> Result<Vec<DocId>, String> {
+    let store = RocksDBStore::create_with_schema(path, schema)?;
+
+    for document in documents.iter() {
+        if let Err(e) = store.insert_or_update_document(document) {
+            return Err(format!("failed to index document: {:?}", e));
+        }
+    }
+
+    // Stored queries are built without the index, so they can't percolate themselves
+    let index_reader = store.reader();
+    let context = QueryBuildContext::new().set_index_metadata(index_metadata).no_score();
+
+    let mut matches = Vec::new();
+    for &(doc_id, ref query) in queries.iter() {
+        let mut collector = TotalCountCollector::new();
+        index_reader.search(&mut collector, &query.build(&context, index_reader.schema()))?;
+
+        if collector.get_total_count() > 0 {
+            matches.push(doc_id);
+        }
+    }
+
+    Ok(matches)
+}
+
+
+impl Index {
+    /// Finds the documents that have a query in the percolator field that matches any of the given documents
+    ///
+    /// This is used by the percolate query. The documents are indexed into a temporary store with
+    /// the same schema, using the mapping that the percolator field is in. Returns an empty list if
+    /// the field isn't a percolator field.
+    pub fn percolate(&self, index_metadata: &IndexMetadata, field_name: &str, documents: &[Map<String, Json>]) -> Result<Vec<DocId>, String> {
+        let mapping = index_metadata.mappings.values().find(|mapping| {
+            mapping.get_field(field_name).map_or(false, |field_mapping| field_mapping.data_type == FieldType::Percolator)
+        });
+
+        let mapping = match mapping {
+            Some(mapping) => mapping,
+            None => return Ok(Vec::new()),
+        };
+
+        let index_reader = self.store.reader();
+
+        let field_id = match index_reader.schema().get_field_by_name(field_name) {
+            Some(field_id) => field_id,
+            None => return Ok(Vec::new()),
+        };
+
+        // Read the stored queries. These were checked against the mapping when they were
+        // indexed, queries that no longer parse are skipped
+        let mut collector = DocIdSetCollector::new();
+        index_reader.search(&mut collector, &Query::all())?;
+
+        let mut queries = Vec::new();
+        for doc_id in collector.doc_ids() {
+            let query_json = match index_reader.read_stored_field(field_id, doc_id) {
+                Ok(Some(FieldValue::String(query_json))) => query_json,
+                Ok(_) => continue,
+                Err(e) => return Err(format!("failed to read stored field: {:?}", e)),
+            };
+
+            if let Some(query) = serde_json::from_str(&query_json).ok().and_then(|query_json| parse_query(&query_json).ok()) {
+                queries.push((doc_id, query));
+            }
+        }
+
+        if queries.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut prepared_documents = Vec::with_capacity(documents.len());
+        for (slot, data) in documents.iter().enumerate() {
+            let key = slot.to_string();
+            let document_source = DocumentSource {
+                key: &key,
+                data: data,
+            };
+
+            match document_source.prepare(mapping) {
+                Ok(document) => prepared_documents.push(document),
+                Err(e) => return Err(format!("failed to prepare document: {:?}", e)),
+            }
+        }
+
+        let path = env::temp_dir().join(format!("rusticsearch-percolate-{}", Uuid::new_v4()));
+        let matches = run_stored_queries(&path, index_reader.schema().clone(), &prepared_documents, &queries, index_metadata);
+        let _ = remove_dir_all(&path);
+
+        matches
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::remove_dir_all;
+
+    use fnv::FnvHashMap;
+    use uuid::Uuid;
+    use search::{Document, DocId};
+    use search::document::FieldValue;
+    use search::schema::{FieldType, FIELD_INDEXED, FIELD_STORED};
+    use search::backends::rocksdb::RocksDBStore;
+
+    use mapping::{self, Mapping, MappingProperty, FieldMapping};
+    use index::Index;
+    use index::metadata::IndexMetadata;
+
+    fn make_test_index(path: &str) -> (Index, IndexMetadata) {
+        let _ = remove_dir_all(path);
+
+        let mut store = RocksDBStore::create(path).unwrap();
+        let colour_field = store.add_field("colour".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let query_field = store.add_field("query".to_string(), FieldType::PlainString, FIELD_STORED).unwrap();
+
+        let queries = [
+            ("red", json!({"term": {"colour": "red"}})),
+            ("blue", json!({"term": {"colour": "blue"}})),
+            ("red_or_green", json!({"bool": {"should": [{"term": {"colour": "red"}}, {"term": {"colour": "green"}}]}})),
+        ];
+
+        for &(key, ref query) in queries.iter() {
+            let mut stored_fields = FnvHashMap::default();
+            stored_fields.insert(query_field, FieldValue::String(query.to_string()));
+
+            store.insert_or_update_document(&Document {
+                key: key.to_string(),
+                indexed_fields: FnvHashMap::default(),
+                stored_fields: stored_fields,
+                nested_documents: Vec::new(),
+            }).unwrap();
+        }
+
+        let mut colour_mapping = FieldMapping::default();
+        colour_mapping.index_ref = Some(colour_field);
+
+        let mut query_mapping = FieldMapping::default();
+        query_mapping.data_type = mapping::FieldType::Percolator;
+        query_mapping.index_ref = Some(query_field);
+        query_mapping.is_indexed = false;
+        query_mapping.is_stored = true;
+
+        let mut mapping = Mapping {
+            properties: Default::default(),
+        };
+        mapping.properties.insert("colour".to_string(), MappingProperty::Field(colour_mapping));
+        mapping.properties.insert("query".to_string(), MappingProperty::Field(query_mapping));
+
+        let mut index_metadata = IndexMetadata::default();
+        index_metadata.mappings.insert("alerts".to_string(), mapping);
+
+        (Index::new(Uuid::new_v4(), "alerts".to_string(), IndexMetadata::default(), store), index_metadata)
+    }
+
+    fn percolated_keys(index: &Index, doc_ids: Vec<DocId>) -> Vec<&'static str> {
+        let index_reader = index.store.reader();
+
+        ["red", "blue", "red_or_green"].iter()
+            .filter(|key| index_reader.get_document_id_by_key(key).map_or(false, |doc_id| doc_ids.contains(&doc_id)))
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn test_percolate() {
+        let (index, index_metadata) = make_test_index("test_indices/test_percolate");
+
+        let documents = vec![json!({"colour": "red"}).as_object().unwrap().clone()];
+        let doc_ids = index.percolate(&index_metadata, "query", &documents).unwrap();
+        assert_eq!(percolated_keys(&index, doc_ids), vec!["red", "red_or_green"]);
+
+        // Queries only need to match one of the documents
+        let documents = vec![
+            json!({"colour": "green"}).as_object().unwrap().clone(),
+            json!({"colour": "blue"}).as_object().unwrap().clone(),
+        ];
+        let doc_ids = index.percolate(&index_metadata, "query", &documents).unwrap();
+        assert_eq!(percolated_keys(&index, doc_ids), vec!["blue", "red_or_green"]);
+    }
+
+    #[test]
+    fn test_percolate_non_percolator_field() {
+        let (index, index_metadata) = make_test_index("test_indices/test_percolate_non_percolator_field");
+
+        let documents = vec![json!({"colour": "red"}).as_object().unwrap().clone()];
+        assert_eq!(index.percolate(&index_metadata, "colour", &documents), Ok(vec![]));
+        assert_eq!(index.percolate(&index_metadata, "missing", &documents), Ok(vec![]));
+    }
+}
@@ -0,0 +1,228 @@
+use std::sync::Mutex;
+
+use serde_json::Value as Json;
+use search::{Term, Token, DocId};
+use search::document::FieldValue;
+use search::schema::FieldId;
+use fnv::FnvHashMap;
+
+use index::Index;
+use index::metadata::IndexMetadata;
+use mapping::FieldMapping;
+
+
+/// The maximum number of lookups to keep in an index's terms lookup cache
+const MAX_CACHED_LOOKUPS: usize = 1000;
+
+
+/// Caches the terms that have been looked up from documents in this index
+///
+/// Entries are keyed by internal document id. As a document gets a new id each time it's
+/// updated, entries never go stale, they just stop being hit.
+#[derive(Debug, Default)]
+pub struct TermsLookupCache {
+    entries: Mutex<FnvHashMap<(DocId, FieldId), Vec<Term>>>,
+}
+
+
+impl TermsLookupCache {
+    fn get(&self, doc_id: DocId, field_id: FieldId) -> Option<Vec<Term>> {
+        self.entries.lock().unwrap().get(&(doc_id, field_id)).cloned()
+    }
+
+    fn insert(&self, doc_id: DocId, field_id: FieldId, terms: Vec<Term>) {
+        let mut entries = self.entries.lock().unwrap();
+
+        if entries.len() >= MAX_CACHED_LOOKUPS {
+            entries.clear();
+        }
+
+        entries.insert((doc_id, field_id), terms);
+    }
+}
+
+
+/// Turns a stored value back into the terms it was indexed as
+///
+/// Strings are analyzed the same way they were when they were indexed, so a
+/// not_analyzed string is a single term. Each value of an array is analyzed on
+/// its own, so an array of not_analyzed strings gives a term for each value.
+fn field_value_to_terms(value: FieldValue, field_mapping: Option<&FieldMapping>) -> Vec<Term> {
+    match value {
+        FieldValue::String(string) => {
+            let value = Json::String(string);
+            match field_mapping.map(|field_mapping| field_mapping.process_value_for_index(&value)) {
+                Some(Ok(Some(term_vector))) => {
+                    let mut tokens: Vec<Token> = term_vector.into();
+                    tokens.sort_by_key(|token| token.position);
+
+                    let mut terms: Vec<Term> = Vec::with_capacity(tokens.len());
+                    for token in tokens {
+                        if !terms.contains(&token.term) {
+                            terms.push(token.term);
+                        }
+                    }
+                    terms
+                }
+                Some(_) => Vec::new(),
+                None => vec![Term::from_string(value.as_str().unwrap())],
+            }
+        }
+        FieldValue::Integer(value) => vec![Term::from_integer(value)],
+        FieldValue::IntegerArray(values) => values.into_iter().map(Term::from_integer).collect(),
+        FieldValue::StringArray(strings) => {
+            let mut terms: Vec<Term> = Vec::new();
+            for string in strings {
+                for term in field_value_to_terms(FieldValue::String(string), field_mapping) {
+                    if !terms.contains(&term) {
+                        terms.push(term);
+                    }
+                }
+            }
+            terms
+        }
+        FieldValue::Boolean(value) => vec![Term::from_boolean(value)],
+        FieldValue::DateTime(value) => vec![Term::from_datetime(&value)],
+    }
+}
+
+
+impl Index {
+    /// Reads the list of terms stored in a field of a document
+    ///
+    /// This is used by the terms lookup form of the terms query. The field must be stored.
+    /// Returns an error if the document doesn't exist, and an empty list if the field doesn't.
+    pub fn lookup_terms(&self, index_metadata: &IndexMetadata, doc_key: &str, field_name: &str) -> Result<Vec<Term>, String> {
+        let index_reader = self.store.reader();
+
+        let doc_id = match index_reader.get_document_id_by_key(doc_key) {
+            Some(doc_id) => doc_id,
+            None => return Err(format!("document {:?} not found in index {:?}", doc_key, self.canonical_name())),
+        };
+
+        let field_id = match index_reader.schema().get_field_by_name(field_name) {
+            Some(field_id) => field_id,
+            None => return Ok(Vec::new()),
+        };
+
+        if let Some(terms) = self.terms_lookup_cache.get(doc_id, field_id) {
+            return Ok(terms);
+        }
+
+        let terms = match index_reader.read_stored_field(field_id, doc_id) {
+            Ok(Some(value)) => field_value_to_terms(value, index_metadata.get_field_mapping(field_name)),
+            Ok(None) => Vec::new(),
+            Err(e) => return Err(format!("failed to read stored field: {:?}", e)),
+        };
+
+        self.terms_lookup_cache.insert(doc_id, field_id, terms.clone());
+        Ok(terms)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::remove_dir_all;
+
+    use fnv::FnvHashMap;
+    use uuid::Uuid;
+    use search::{Term, Document};
+    use search::document::FieldValue;
+    use search::schema::{FieldType, FIELD_STORED};
+    use search::backends::rocksdb::RocksDBStore;
+    use mapping::Mapping;
+    use mapping::parse::parse as parse_mapping;
+
+    use index::Index;
+    use index::metadata::IndexMetadata;
+
+    use super::field_value_to_terms;
+
+    fn make_test_mapping() -> Mapping {
+        parse_mapping(&json!({
+            "properties": {
+                "friends": {"type": "string", "index": "not_analyzed"},
+                "bio": {"type": "string"}
+            }
+        })).unwrap().build(&IndexMetadata::default())
+    }
+
+    fn make_test_index(path: &str) -> Index {
+        let _ = remove_dir_all(path);
+
+        let mut store = RocksDBStore::create(path).unwrap();
+        let friends_field = store.add_field("friends".to_string(), FieldType::PlainString, FIELD_STORED).unwrap();
+
+        let mapping = make_test_mapping();
+        for &(key, ref friends) in [("u1", json!("alice")), ("u3", json!(["bob smith", "carol", "bob smith"]))].iter() {
+            let mut stored_fields = FnvHashMap::default();
+            stored_fields.insert(friends_field, mapping.get_field("friends").unwrap().process_value_for_store(friends).unwrap().unwrap());
+
+            store.insert_or_update_document(&Document {
+                key: key.to_string(),
+                indexed_fields: FnvHashMap::default(),
+                stored_fields: stored_fields,
+                nested_documents: Vec::new(),
+            }).unwrap();
+        }
+
+        let mut metadata = IndexMetadata::default();
+        metadata.mappings.insert("user".to_string(), mapping);
+
+        Index::new(Uuid::new_v4(), "users".to_string(), metadata, store)
+    }
+
+    #[test]
+    fn test_lookup_terms() {
+        let index = make_test_index("test_indices/test_lookup_terms");
+        let metadata = index.metadata.read().unwrap();
+
+        assert_eq!(index.lookup_terms(&metadata, "u1", "friends"), Ok(vec![
+            Term::from_string("alice"),
+        ]));
+
+        // Second lookup is served from the cache
+        assert_eq!(index.lookup_terms(&metadata, "u1", "friends"), Ok(vec![
+            Term::from_string("alice"),
+        ]));
+
+        // Each value of an array is a term of its own
+        assert_eq!(index.lookup_terms(&metadata, "u3", "friends"), Ok(vec![
+            Term::from_string("bob smith"),
+            Term::from_string("carol"),
+        ]));
+    }
+
+    #[test]
+    fn test_lookup_terms_missing() {
+        let index = make_test_index("test_indices/test_lookup_terms_missing");
+        let metadata = index.metadata.read().unwrap();
+
+        assert_eq!(index.lookup_terms(&metadata, "u2", "friends"), Err("document \"u2\" not found in index \"users\"".to_string()));
+        assert_eq!(index.lookup_terms(&metadata, "u1", "enemies"), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_string_value_to_terms() {
+        let mapping = make_test_mapping();
+
+        // Not analyzed strings are a single term
+        assert_eq!(field_value_to_terms(FieldValue::String("Alice Smith".to_string()), mapping.get_field("friends")), vec![
+            Term::from_string("Alice Smith"),
+        ]);
+
+        // Analyzed strings are analyzed the same way as when they were indexed
+        assert_eq!(field_value_to_terms(FieldValue::String("Alice Smith".to_string()), mapping.get_field("bio")), vec![
+            Term::from_string("alice"),
+            Term::from_string("smith"),
+        ]);
+    }
+
+    #[test]
+    fn test_integer_value_to_terms() {
+        assert_eq!(field_value_to_terms(FieldValue::Integer(123), None), vec![
+            Term::from_integer(123),
+        ]);
+    }
+}
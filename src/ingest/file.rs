@@ -0,0 +1,84 @@
+//! Saving pipelines
+//!
+//! Pipelines are kept in a single file in the data directory, as the JSON they
+//! were created with, keyed by their names. The file is rewritten whenever a
+//! pipeline is put or deleted.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::io::{Read, Write};
+use std::fs::File;
+
+use serde_json;
+use atomicwrites::{AtomicFile, AllowOverwrite};
+
+use ingest::Pipeline;
+use ingest::parse::parse as parse_pipeline;
+
+
+pub fn save_pipelines<P: AsRef<Path>>(pipelines: &HashMap<String, Pipeline>, path: P) -> Result<(), String> {
+    let mut json = serde_json::Map::new();
+    for (name, pipeline) in pipelines.iter() {
+        json.insert(name.clone(), pipeline.source.clone());
+    }
+    let s = format!("{}", serde_json::Value::Object(json));
+
+    let file = AtomicFile::new(path, AllowOverwrite);
+    file.write(|f| {
+        f.write_all(s.as_bytes())
+    }).map_err(|e| format!("failed to save pipelines: {}", e))
+}
+
+
+pub fn load_pipelines<P: AsRef<Path>>(path: P) -> Result<HashMap<String, Pipeline>, String> {
+    let mut file = File::open(path).map_err(|e| format!("failed to load pipelines: {}", e))?;
+    let mut s = String::new();
+    file.read_to_string(&mut s).map_err(|e| format!("failed to load pipelines: {}", e))?;
+
+    let json: serde_json::Value = serde_json::from_str(&s).map_err(|e| format!("failed to load pipelines: {}", e))?;
+    let json = json.as_object().ok_or("failed to load pipelines: expected an object".to_string())?;
+
+    let mut pipelines = HashMap::new();
+    for (name, pipeline_json) in json.iter() {
+        let pipeline = parse_pipeline(pipeline_json).map_err(|e| format!("failed to load pipeline {}: {:?}", name, e))?;
+        pipelines.insert(name.clone(), pipeline);
+    }
+
+    Ok(pipelines)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs;
+
+    use uuid::Uuid;
+
+    use ingest::parse::parse as parse_pipeline;
+
+    use super::{save_pipelines, load_pipelines};
+
+    #[test]
+    fn test_save_and_load_pipelines() {
+        let path = env::temp_dir().join(format!("rusticsearch-pipelines-{}.json", Uuid::new_v4()));
+
+        let mut pipelines = HashMap::new();
+        pipelines.insert("events".to_string(), parse_pipeline(&json!({
+            "description": "Sets the id of events",
+            "processors": [
+                {"fingerprint": {"fields": ["user"], "mode": "set_id"}}
+            ]
+        })).unwrap());
+        save_pipelines(&pipelines, &path).unwrap();
+
+        let loaded = load_pipelines(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded["events"].description, Some("Sets the id of events".to_string()));
+        assert_eq!(loaded["events"].processors.len(), 1);
+        assert_eq!(loaded["events"].source, pipelines["events"].source);
+    }
+}
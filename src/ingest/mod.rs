@@ -0,0 +1,107 @@
+//! The ingest module
+//!
+//! Pipelines are ordered lists of processors that documents are passed through
+//! before they are indexed. Each processor can modify the document or drop it
+//! altogether.
+
+pub mod processors;
+pub mod parse;
+pub mod simulate;
+pub mod file;
+
+use std::collections::HashMap;
+
+use serde_json;
+
+use ingest::processors::{Processor, ProcessorOutcome, ProcessorError};
+
+
+/// A document that is being passed through an ingest pipeline
+#[derive(Debug, Clone, PartialEq)]
+pub struct IngestDocument {
+    pub key: String,
+    pub data: serde_json::Map<String, serde_json::Value>,
+}
+
+
+#[derive(Debug)]
+pub struct Pipeline {
+    pub description: Option<String>,
+    pub processors: Vec<Processor>,
+
+    /// The JSON the pipeline was created with, so it can be saved
+    pub source: serde_json::Value,
+}
+
+
+impl Pipeline {
+    /// Runs the document through each processor in turn
+    ///
+    /// Returns `None` if one of the processors dropped the document
+    pub fn process(&self, mut doc: IngestDocument) -> Result<Option<IngestDocument>, ProcessorError> {
+        for processor in self.processors.iter() {
+            match processor.process(&mut doc)? {
+                ProcessorOutcome::Continue => {}
+                ProcessorOutcome::Drop => return Ok(None),
+            }
+        }
+
+        Ok(Some(doc))
+    }
+}
+
+
+/// Runs the document through each of the named pipelines in turn
+///
+/// Returns `None` if one of the pipelines dropped the document, or an error if
+/// a pipeline doesn't exist or failed
+pub fn run_pipelines(pipelines: &HashMap<String, Pipeline>, pipeline_names: &[String], doc: IngestDocument) -> Result<Option<IngestDocument>, String> {
+    let mut doc = doc;
+
+    for pipeline_name in pipeline_names.iter() {
+        let pipeline = match pipelines.get(pipeline_name) {
+            Some(pipeline) => pipeline,
+            None => return Err(format!("Pipeline not found: {}", pipeline_name)),
+        };
+
+        doc = match pipeline.process(doc) {
+            Ok(Some(doc)) => doc,
+            Ok(None) => return Ok(None),
+            Err(error) => return Err(format!("Pipeline failed: {:?}", error)),
+        };
+    }
+
+    Ok(Some(doc))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use ingest::IngestDocument;
+    use ingest::parse::parse as parse_pipeline;
+
+    use super::run_pipelines;
+
+    #[test]
+    fn test_run_pipelines() {
+        let mut pipelines = HashMap::new();
+        pipelines.insert("events".to_string(), parse_pipeline(&json!({
+            "processors": [
+                {"fingerprint": {"fields": ["user"], "mode": "set_id"}}
+            ]
+        })).unwrap());
+
+        let doc = |data| IngestDocument {
+            key: "a".to_string(),
+            data: json!(data).as_object().unwrap().clone(),
+        };
+
+        let processed = run_pipelines(&pipelines, &["events".to_string()], doc(json!({"user": "alice"}))).unwrap().unwrap();
+        assert_eq!(processed.key.len(), 16);
+
+        assert!(run_pipelines(&pipelines, &["events".to_string()], doc(json!({}))).is_err());
+        assert_eq!(run_pipelines(&pipelines, &["missing".to_string()], doc(json!({}))).err(), Some("Pipeline not found: missing".to_string()));
+    }
+}
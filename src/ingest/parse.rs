@@ -0,0 +1,206 @@
+use serde_json;
+
+use ingest::Pipeline;
+use ingest::processors::Processor;
+use ingest::processors::fingerprint::{FingerprintProcessor, FingerprintAction};
+
+
+#[derive(Debug, PartialEq)]
+pub enum ProcessorParseError {
+    ExpectedObject,
+    ExpectedArray,
+    ExpectedString,
+    ExpectedBoolean,
+    ExpectedPositiveInteger,
+    ExpectedKey(String),
+    ExpectedSingleKey,
+    UnrecognisedType(String),
+    InvalidModeValue(String),
+}
+
+
+#[derive(Debug, PartialEq)]
+pub enum PipelineParseError {
+    ExpectedObject,
+    ExpectedArray,
+    ExpectedString,
+    ExpectedKey(String),
+    ProcessorParseError(usize, ProcessorParseError),
+}
+
+
+fn parse_fingerprint(data: &serde_json::Map<String, serde_json::Value>) -> Result<Processor, ProcessorParseError> {
+    let fields_json = data.get("fields").ok_or(ProcessorParseError::ExpectedKey("fields".to_string()))?;
+    let fields_array = fields_json.as_array().ok_or(ProcessorParseError::ExpectedArray)?;
+
+    let mut fields = Vec::new();
+    for field_json in fields_array.iter() {
+        let field = field_json.as_str().ok_or(ProcessorParseError::ExpectedString)?;
+        fields.push(field.to_string());
+    }
+
+    if fields.is_empty() {
+        return Err(ProcessorParseError::ExpectedKey("fields".to_string()));
+    }
+
+    let ignore_missing = match data.get("ignore_missing") {
+        Some(ignore_missing_json) => {
+            ignore_missing_json.as_bool().ok_or(ProcessorParseError::ExpectedBoolean)?
+        }
+        None => false,
+    };
+
+    let window = match data.get("window") {
+        Some(window_json) => {
+            match window_json.as_u64() {
+                Some(window) if window > 0 => window as usize,
+                _ => return Err(ProcessorParseError::ExpectedPositiveInteger),
+            }
+        }
+        None => 10000,
+    };
+
+    let action = match data.get("mode") {
+        Some(mode_json) => {
+            let mode = mode_json.as_str().ok_or(ProcessorParseError::ExpectedString)?;
+
+            match mode {
+                "set_id" => FingerprintAction::SetKey,
+                "drop_duplicates" => FingerprintAction::DropDuplicates { window: window },
+                _ => return Err(ProcessorParseError::InvalidModeValue(mode.to_string())),
+            }
+        }
+        None => FingerprintAction::SetKey,
+    };
+
+    Ok(Processor::Fingerprint(FingerprintProcessor::new(fields, action, ignore_missing)))
+}
+
+
+pub fn parse_processor(json: &serde_json::Value) -> Result<Processor, ProcessorParseError> {
+    // Processors are objects with a single key, the key being the processor type
+    let object = json.as_object().ok_or(ProcessorParseError::ExpectedObject)?;
+    if object.len() != 1 {
+        return Err(ProcessorParseError::ExpectedSingleKey);
+    }
+
+    let (processor_type, data_json) = object.iter().next().unwrap();
+    let data = data_json.as_object().ok_or(ProcessorParseError::ExpectedObject)?;
+
+    match processor_type.as_ref() {
+        "fingerprint" => parse_fingerprint(data),
+        _ => Err(ProcessorParseError::UnrecognisedType(processor_type.to_string())),
+    }
+}
+
+
+pub fn parse(json: &serde_json::Value) -> Result<Pipeline, PipelineParseError> {
+    let data = json.as_object().ok_or(PipelineParseError::ExpectedObject)?;
+
+    let description = match data.get("description") {
+        Some(description_json) => {
+            Some(description_json.as_str().ok_or(PipelineParseError::ExpectedString)?.to_string())
+        }
+        None => None,
+    };
+
+    let processors_json = data.get("processors").ok_or(PipelineParseError::ExpectedKey("processors".to_string()))?;
+    let processors_array = processors_json.as_array().ok_or(PipelineParseError::ExpectedArray)?;
+
+    let mut processors = Vec::new();
+    for (i, processor_json) in processors_array.iter().enumerate() {
+        match parse_processor(processor_json) {
+            Ok(processor) => processors.push(processor),
+            Err(e) => return Err(PipelineParseError::ProcessorParseError(i, e)),
+        }
+    }
+
+    Ok(Pipeline {
+        description: description,
+        processors: processors,
+        source: json.clone(),
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use ingest::processors::Processor;
+    use ingest::processors::fingerprint::FingerprintAction;
+
+    use super::{parse, PipelineParseError, ProcessorParseError};
+
+    #[test]
+    fn test_fingerprint() {
+        let pipeline = parse(&json!({
+            "description": "dedup replayed events",
+            "processors": [
+                {
+                    "fingerprint": {
+                        "fields": ["user", "event"],
+                        "mode": "drop_duplicates",
+                        "window": 100
+                    }
+                }
+            ]
+        })).expect("parse() returned an error");
+
+        assert_eq!(pipeline.description, Some("dedup replayed events".to_string()));
+        assert_eq!(pipeline.processors.len(), 1);
+
+        match pipeline.processors[0] {
+            Processor::Fingerprint(ref processor) => {
+                assert_eq!(processor.fields(), &["event".to_string(), "user".to_string()]);
+                assert_eq!(processor.action(), FingerprintAction::DropDuplicates { window: 100 });
+            }
+        }
+    }
+
+    #[test]
+    fn test_fingerprint_default_mode() {
+        let pipeline = parse(&json!({
+            "processors": [
+                {
+                    "fingerprint": {
+                        "fields": ["user"]
+                    }
+                }
+            ]
+        })).expect("parse() returned an error");
+
+        match pipeline.processors[0] {
+            Processor::Fingerprint(ref processor) => {
+                assert_eq!(processor.action(), FingerprintAction::SetKey);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fingerprint_bad_mode() {
+        let error = parse(&json!({
+            "processors": [
+                {
+                    "fingerprint": {
+                        "fields": ["user"],
+                        "mode": "foo"
+                    }
+                }
+            ]
+        })).err().expect("parse() was supposed to return an error, but didn't");
+
+        assert_eq!(error, PipelineParseError::ProcessorParseError(0, ProcessorParseError::InvalidModeValue("foo".to_string())));
+    }
+
+    #[test]
+    fn test_unrecognised_processor() {
+        let error = parse(&json!({
+            "processors": [
+                {
+                    "foo": {}
+                }
+            ]
+        })).err().expect("parse() was supposed to return an error, but didn't");
+
+        assert_eq!(error, PipelineParseError::ProcessorParseError(0, ProcessorParseError::UnrecognisedType("foo".to_string())));
+    }
+}
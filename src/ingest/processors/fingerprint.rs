@@ -0,0 +1,202 @@
+use std::collections::VecDeque;
+use std::hash::Hasher;
+use std::sync::Mutex;
+
+use fnv::{FnvHasher, FnvHashSet};
+
+use ingest::IngestDocument;
+use ingest::processors::{ProcessorOutcome, ProcessorError};
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FingerprintAction {
+    /// Use the fingerprint as the documents key, so replayed documents overwrite the original
+    SetKey,
+
+    /// Drop documents whose fingerprint was seen within the last `window` documents
+    DropDuplicates {
+        window: usize,
+    },
+}
+
+
+/// Remembers the most recently seen fingerprints
+#[derive(Debug, Default)]
+struct FingerprintWindow {
+    order: VecDeque<u64>,
+    seen: FnvHashSet<u64>,
+}
+
+
+impl FingerprintWindow {
+    /// Records the fingerprint, returns false if it was already in the window
+    fn insert(&mut self, fingerprint: u64, size: usize) -> bool {
+        if self.seen.contains(&fingerprint) {
+            return false;
+        }
+
+        self.order.push_back(fingerprint);
+        self.seen.insert(fingerprint);
+
+        while self.order.len() > size {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+
+        true
+    }
+}
+
+
+#[derive(Debug)]
+pub struct FingerprintProcessor {
+    fields: Vec<String>,
+    action: FingerprintAction,
+    ignore_missing: bool,
+    window: Mutex<FingerprintWindow>,
+}
+
+
+impl FingerprintProcessor {
+    pub fn new(mut fields: Vec<String>, action: FingerprintAction, ignore_missing: bool) -> FingerprintProcessor {
+        // Sort the field names so the fingerprint doesn't depend on the order they were given in
+        fields.sort();
+        fields.dedup();
+
+        FingerprintProcessor {
+            fields: fields,
+            action: action,
+            ignore_missing: ignore_missing,
+            window: Mutex::new(FingerprintWindow::default()),
+        }
+    }
+
+    pub fn fields(&self) -> &[String] {
+        &self.fields
+    }
+
+    pub fn action(&self) -> FingerprintAction {
+        self.action
+    }
+
+    /// Computes a hash of the selected fields
+    ///
+    /// This is stable across restarts as the values are hashed in their canonical JSON form
+    pub fn fingerprint(&self, doc: &IngestDocument) -> Result<u64, ProcessorError> {
+        let mut hasher = FnvHasher::default();
+
+        for field_name in self.fields.iter() {
+            hasher.write(field_name.as_bytes());
+            hasher.write_u8(0);
+
+            match doc.data.get(field_name) {
+                Some(value) => hasher.write(value.to_string().as_bytes()),
+                None if self.ignore_missing => {}
+                None => return Err(ProcessorError::FieldNotFound(field_name.clone())),
+            }
+
+            hasher.write_u8(0);
+        }
+
+        Ok(hasher.finish())
+    }
+
+    pub fn process(&self, doc: &mut IngestDocument) -> Result<ProcessorOutcome, ProcessorError> {
+        let fingerprint = self.fingerprint(doc)?;
+
+        match self.action {
+            FingerprintAction::SetKey => {
+                doc.key = format!("{:016x}", fingerprint);
+                Ok(ProcessorOutcome::Continue)
+            }
+            FingerprintAction::DropDuplicates{window} => {
+                let mut seen = self.window.lock().unwrap();
+
+                if seen.insert(fingerprint, window) {
+                    Ok(ProcessorOutcome::Continue)
+                } else {
+                    Ok(ProcessorOutcome::Drop)
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use ingest::IngestDocument;
+    use ingest::processors::{ProcessorOutcome, ProcessorError};
+
+    use super::{FingerprintProcessor, FingerprintAction};
+
+    fn make_doc(key: &str, data: ::serde_json::Value) -> IngestDocument {
+        IngestDocument {
+            key: key.to_string(),
+            data: data.as_object().unwrap().clone(),
+        }
+    }
+
+    #[test]
+    fn test_set_key() {
+        let processor = FingerprintProcessor::new(vec!["user".to_string(), "event".to_string()], FingerprintAction::SetKey, false);
+
+        let mut doc = make_doc("1", json!({"user": "alice", "event": "login", "time": 1}));
+        let outcome = processor.process(&mut doc);
+        assert_eq!(outcome, Ok(ProcessorOutcome::Continue));
+        assert_eq!(doc.key.len(), 16);
+
+        // A replay of the same event with different unselected fields should get the same key
+        let mut replayed_doc = make_doc("2", json!({"event": "login", "user": "alice", "time": 2}));
+        processor.process(&mut replayed_doc).unwrap();
+        assert_eq!(replayed_doc.key, doc.key);
+
+        let mut other_doc = make_doc("3", json!({"user": "bob", "event": "login", "time": 1}));
+        processor.process(&mut other_doc).unwrap();
+        assert!(other_doc.key != doc.key);
+    }
+
+    #[test]
+    fn test_field_order_doesnt_matter() {
+        let processor_a = FingerprintProcessor::new(vec!["a".to_string(), "b".to_string()], FingerprintAction::SetKey, false);
+        let processor_b = FingerprintProcessor::new(vec!["b".to_string(), "a".to_string()], FingerprintAction::SetKey, false);
+        let doc = make_doc("1", json!({"a": 1, "b": {"y": true, "x": [1, 2]}}));
+
+        assert_eq!(processor_a.fingerprint(&doc), processor_b.fingerprint(&doc));
+    }
+
+    #[test]
+    fn test_missing_field() {
+        let processor = FingerprintProcessor::new(vec!["user".to_string()], FingerprintAction::SetKey, false);
+        let mut doc = make_doc("1", json!({"event": "login"}));
+
+        assert_eq!(processor.process(&mut doc), Err(ProcessorError::FieldNotFound("user".to_string())));
+        assert_eq!(doc.key, "1");
+    }
+
+    #[test]
+    fn test_missing_field_ignored() {
+        let processor = FingerprintProcessor::new(vec!["user".to_string()], FingerprintAction::SetKey, true);
+        let missing_doc = make_doc("1", json!({"event": "login"}));
+        let null_doc = make_doc("1", json!({"user": null}));
+
+        // Missing fields and nulls must not collide
+        assert!(processor.fingerprint(&missing_doc).is_ok());
+        assert!(processor.fingerprint(&missing_doc) != processor.fingerprint(&null_doc));
+    }
+
+    #[test]
+    fn test_drop_duplicates() {
+        let processor = FingerprintProcessor::new(vec!["event_id".to_string()], FingerprintAction::DropDuplicates { window: 2 }, false);
+
+        assert_eq!(processor.process(&mut make_doc("1", json!({"event_id": 1}))), Ok(ProcessorOutcome::Continue));
+        assert_eq!(processor.process(&mut make_doc("2", json!({"event_id": 1}))), Ok(ProcessorOutcome::Drop));
+        assert_eq!(processor.process(&mut make_doc("3", json!({"event_id": 2}))), Ok(ProcessorOutcome::Continue));
+        assert_eq!(processor.process(&mut make_doc("4", json!({"event_id": 3}))), Ok(ProcessorOutcome::Continue));
+
+        // event 1 has now fallen out of the window
+        assert_eq!(processor.process(&mut make_doc("5", json!({"event_id": 1}))), Ok(ProcessorOutcome::Continue));
+        assert_eq!(processor.process(&mut make_doc("6", json!({"event_id": 3}))), Ok(ProcessorOutcome::Drop));
+    }
+}
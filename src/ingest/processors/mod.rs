@@ -0,0 +1,39 @@
+pub mod fingerprint;
+
+use ingest::IngestDocument;
+use ingest::processors::fingerprint::FingerprintProcessor;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProcessorOutcome {
+    Continue,
+    Drop,
+}
+
+
+#[derive(Debug, PartialEq)]
+pub enum ProcessorError {
+    FieldNotFound(String),
+}
+
+
+#[derive(Debug)]
+pub enum Processor {
+    Fingerprint(FingerprintProcessor),
+}
+
+
+impl Processor {
+    /// The name that the processor is given in pipeline definitions
+    pub fn processor_type(&self) -> &'static str {
+        match *self {
+            Processor::Fingerprint(_) => "fingerprint",
+        }
+    }
+
+    pub fn process(&self, doc: &mut IngestDocument) -> Result<ProcessorOutcome, ProcessorError> {
+        match *self {
+            Processor::Fingerprint(ref processor) => processor.process(doc),
+        }
+    }
+}
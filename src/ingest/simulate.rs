@@ -0,0 +1,237 @@
+//! Simulating pipelines
+//!
+//! Runs sample documents through a pipeline and returns what the pipeline did
+//! to them, without indexing anything. In verbose mode, the document is given
+//! as it was after each processor, so pipelines can be debugged one step at a
+//! time.
+//!
+//! The pipeline is given in the request, so it has its own state. Documents
+//! dropped by a "drop_duplicates" fingerprint processor here won't affect
+//! pipelines that are in use.
+
+use serde_json::Value as Json;
+
+use ingest::{Pipeline, IngestDocument};
+use ingest::processors::{ProcessorOutcome, ProcessorError};
+use ingest::parse::{PipelineParseError, parse as parse_pipeline};
+
+
+#[derive(Debug, PartialEq)]
+pub enum SimulateParseError {
+    ExpectedObject,
+    ExpectedArray,
+    ExpectedString,
+    ExpectedKey(String),
+    PipelineParseError(PipelineParseError),
+}
+
+
+impl From<PipelineParseError> for SimulateParseError {
+    fn from(e: PipelineParseError) -> SimulateParseError {
+        SimulateParseError::PipelineParseError(e)
+    }
+}
+
+
+#[derive(Debug)]
+pub struct SimulateRequest {
+    pub pipeline: Pipeline,
+    pub docs: Vec<IngestDocument>,
+}
+
+
+/// What happened to a document in one processor
+#[derive(Debug, PartialEq)]
+pub enum ProcessorResult {
+    /// The document as the processor left it
+    Success(IngestDocument),
+    Dropped,
+    Error(ProcessorError),
+}
+
+
+impl ProcessorResult {
+    fn to_json(&self, processor_type: &str) -> Json {
+        match *self {
+            ProcessorResult::Success(ref doc) => json!({"processor_type": processor_type, "status": "success", "doc": doc_json(doc)}),
+            ProcessorResult::Dropped => json!({"processor_type": processor_type, "status": "dropped"}),
+            ProcessorResult::Error(ref error) => json!({"processor_type": processor_type, "status": "error", "error": error_json(error)}),
+        }
+    }
+}
+
+
+fn doc_json(doc: &IngestDocument) -> Json {
+    json!({
+        "_id": doc.key,
+        "_source": doc.data,
+    })
+}
+
+
+fn error_json(error: &ProcessorError) -> Json {
+    json!({"reason": format!("{:?}", error)})
+}
+
+
+/// Parses the body of a simulate request. Documents are given as objects with
+/// their data in "_source" and, optionally, their key in "_id"
+pub fn parse(json: &Json) -> Result<SimulateRequest, SimulateParseError> {
+    let data = json.as_object().ok_or(SimulateParseError::ExpectedObject)?;
+
+    let pipeline_json = data.get("pipeline").ok_or(SimulateParseError::ExpectedKey("pipeline".to_string()))?;
+    let pipeline = parse_pipeline(pipeline_json)?;
+
+    let docs_json = data.get("docs").ok_or(SimulateParseError::ExpectedKey("docs".to_string()))?;
+    let docs_array = docs_json.as_array().ok_or(SimulateParseError::ExpectedArray)?;
+
+    let mut docs = Vec::with_capacity(docs_array.len());
+    for doc_json in docs_array.iter() {
+        let doc = doc_json.as_object().ok_or(SimulateParseError::ExpectedObject)?;
+
+        // Like Elasticsearch, documents without a key are given "_id"
+        let key = match doc.get("_id") {
+            Some(&Json::String(ref key)) => key.clone(),
+            Some(&Json::Number(ref key)) => key.to_string(),
+            Some(_) => return Err(SimulateParseError::ExpectedString),
+            None => "_id".to_string(),
+        };
+
+        let source = doc.get("_source").ok_or(SimulateParseError::ExpectedKey("_source".to_string()))?;
+        let source = source.as_object().ok_or(SimulateParseError::ExpectedObject)?;
+
+        docs.push(IngestDocument {
+            key: key,
+            data: source.clone(),
+        });
+    }
+
+    Ok(SimulateRequest {
+        pipeline: pipeline,
+        docs: docs,
+    })
+}
+
+
+/// Runs the document through each processor in turn, keeping the result of
+/// each one. Stops after a processor drops the document or fails
+pub fn process_verbose(pipeline: &Pipeline, mut doc: IngestDocument) -> Vec<ProcessorResult> {
+    let mut results = Vec::with_capacity(pipeline.processors.len());
+
+    for processor in pipeline.processors.iter() {
+        match processor.process(&mut doc) {
+            Ok(ProcessorOutcome::Continue) => results.push(ProcessorResult::Success(doc.clone())),
+            Ok(ProcessorOutcome::Drop) => {
+                results.push(ProcessorResult::Dropped);
+                break;
+            }
+            Err(error) => {
+                results.push(ProcessorResult::Error(error));
+                break;
+            }
+        }
+    }
+
+    results
+}
+
+
+/// Runs the documents through the pipeline, in the order they were given
+///
+/// Without verbose, each document is given as it came out of the pipeline, or
+/// null if it was dropped. With verbose, the results of each processor are given
+pub fn simulate(request: &SimulateRequest, verbose: bool) -> Json {
+    let mut docs = Vec::with_capacity(request.docs.len());
+
+    for doc in request.docs.iter() {
+        if verbose {
+            let processor_results = process_verbose(&request.pipeline, doc.clone()).iter()
+                .zip(request.pipeline.processors.iter())
+                .map(|(result, processor)| result.to_json(processor.processor_type()))
+                .collect::<Vec<_>>();
+
+            docs.push(json!({"processor_results": processor_results}));
+        } else {
+            docs.push(match request.pipeline.process(doc.clone()) {
+                Ok(Some(doc)) => json!({"doc": doc_json(&doc)}),
+                Ok(None) => Json::Null,
+                Err(error) => json!({"error": error_json(&error)}),
+            });
+        }
+    }
+
+    json!({"docs": docs})
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value as Json;
+
+    use ingest::processors::ProcessorError;
+
+    use super::{parse, simulate, SimulateParseError};
+
+    fn request_json() -> Json {
+        json!({
+            "pipeline": {
+                "processors": [
+                    {"fingerprint": {"fields": ["user"], "mode": "set_id"}},
+                    {"fingerprint": {"fields": ["event_id"], "mode": "drop_duplicates"}}
+                ]
+            },
+            "docs": [
+                {"_id": "a", "_source": {"user": "alice", "event_id": 1}},
+                {"_source": {"user": "bob", "event_id": 1}},
+                {"_source": {"event_id": 2}}
+            ]
+        })
+    }
+
+    #[test]
+    fn test_parse() {
+        let request = parse(&request_json()).unwrap();
+
+        assert_eq!(request.pipeline.processors.len(), 2);
+        assert_eq!(request.docs.iter().map(|doc| doc.key.as_str()).collect::<Vec<_>>(), vec!["a", "_id", "_id"]);
+
+        assert_eq!(parse(&json!({"pipeline": {"processors": []}})).err(), Some(SimulateParseError::ExpectedKey("docs".to_string())));
+        assert_eq!(parse(&json!({"pipeline": {"processors": []}, "docs": [{"_id": "a"}]})).err(), Some(SimulateParseError::ExpectedKey("_source".to_string())));
+    }
+
+    #[test]
+    fn test_simulate() {
+        let request = parse(&request_json()).unwrap();
+        let results = simulate(&request, false);
+        let docs = results["docs"].as_array().unwrap();
+
+        assert_eq!(docs[0]["doc"]["_id"].as_str().unwrap().len(), 16);
+        assert_eq!(docs[0]["doc"]["_source"], json!({"user": "alice", "event_id": 1}));
+
+        // The second document has the same event id, so it's dropped
+        assert_eq!(docs[1], Json::Null);
+
+        assert_eq!(docs[2]["error"]["reason"], json!(format!("{:?}", ProcessorError::FieldNotFound("user".to_string()))));
+    }
+
+    #[test]
+    fn test_simulate_verbose() {
+        let request = parse(&request_json()).unwrap();
+        let results = simulate(&request, true);
+        let docs = results["docs"].as_array().unwrap();
+
+        let first = docs[0]["processor_results"].as_array().unwrap();
+        assert_eq!(first.len(), 2);
+        assert_eq!(first[0]["processor_type"], json!("fingerprint"));
+        assert_eq!(first[0]["status"], json!("success"));
+        assert_eq!(first[0]["doc"]["_id"], first[1]["doc"]["_id"]);
+
+        // Processors after the one that dropped the document aren't run
+        let second = docs[1]["processor_results"].as_array().unwrap();
+        assert_eq!(second[1]["status"], json!("dropped"));
+
+        let third = docs[2]["processor_results"].as_array().unwrap();
+        assert_eq!(third.len(), 1);
+        assert_eq!(third[0]["status"], json!("error"));
+    }
+}
@@ -22,26 +22,39 @@ extern crate bitflags;
 extern crate roaring;
 extern crate byteorder;
 extern crate rocksdb;
+extern crate ring;
+extern crate hyper;
 
 pub mod search;
 pub mod analysis;
 pub mod query_parser;
 pub mod mapping;
 pub mod document;
+pub mod ingest;
+pub mod watcher;
 pub mod index;
 pub mod cluster;
 pub mod system;
+pub mod script;
+pub mod highlight;
+pub mod suggest;
+pub mod aggregations;
+pub mod thread_pool;
+pub mod audit;
+pub mod bench;
 mod api;
 
+use std::env;
 use std::path::Path;
 use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::panic;
 
 use slog::Drain;
 
 use system::System;
+use search::backends::rocksdb::encryption::{KeyFile, StoreEncryption};
 
 
 const VERSION: &'static str = env!("CARGO_PKG_VERSION");
@@ -56,11 +69,33 @@ fn main() {
 
     info!(log, "starting rusticsearch"; "version" => VERSION);
 
-    let system = Arc::new(System::new(log, Path::new("data/").to_path_buf()));
+    let mut system = System::new(log, Path::new("data/").to_path_buf());
+
+    // Stored values are encrypted at rest if there's a key
+    if let Ok(key_file) = env::var("RUSTICSEARCH_ENCRYPTION_KEY_FILE") {
+        match StoreEncryption::from_provider(&KeyFile::new(&key_file)) {
+            Ok(encryption) => {
+                info!(system.log, "encryption at rest enabled"; "key_file" => key_file);
+                system.store_encryption = Some(Arc::new(encryption));
+            }
+            Err(e) => {
+                crit!(system.log, "unable to load encryption key"; "error" => e);
+                return;
+            }
+        }
+    }
+
+    let system = Arc::new(system);
+
+    info!(system.log, "loading pipelines");
+    system.load_pipelines();
 
     info!(system.log, "loading indices");
     system.load_indices();
 
+    info!(system.log, "loading watches");
+    system.load_watches();
+
     {
         let system = system.clone();
         thread::spawn(move || {
@@ -69,7 +104,7 @@ fn main() {
                     let cluster_metadata = system.metadata.read().unwrap();
                     for index in cluster_metadata.indices.values() {
                         let result = panic::catch_unwind(|| {
-                            index.run_maintenance_task().unwrap();
+                            index.run_maintenance_task(&system.thread_pools.merge).unwrap();
                         });
 
                         if let Err(error) = result {
@@ -83,6 +118,41 @@ fn main() {
         });
     }
 
+    {
+        let system = system.clone();
+        thread::spawn(move || {
+            loop {
+                // Run the queries while holding the metadata lock, but release it
+                // before executing actions so a slow webhook can't block writers
+                let due_watches = {
+                    let cluster_metadata = system.metadata.read().unwrap();
+                    let now = Instant::now();
+                    cluster_metadata.watches.iter()
+                        .filter(|&(_, watch)| watch.check_due(now))
+                        .map(|(watch_id, watch)| (watch_id.clone(), watch.clone(), watch.search(&cluster_metadata)))
+                        .collect::<Vec<_>>()
+                };
+
+                let ran_watches = !due_watches.is_empty();
+                for (watch_id, watch, payload) in due_watches {
+                    let record = watch.execute(&watch_id, payload);
+                    for error in record.errors.iter() {
+                        warn!(system.log, "watch failed"; "watch" => &watch_id, "error" => error);
+                    }
+                }
+
+                // Save the records the watches added to their history
+                if ran_watches {
+                    if let Err(error) = system.save_watches(&system.metadata.read().unwrap()) {
+                        error!(system.log, "failed to save watches"; "error" => &error);
+                    }
+                }
+
+                thread::sleep(Duration::new(1, 0));
+            }
+        });
+    }
+
     info!(system.log, "starting api server");
     api::api_main(system);
 }
@@ -1,7 +1,14 @@
 use std::collections::HashMap;
 
 use mapping::{Mapping, MappingProperty, FieldMapping, NestedMapping, FieldType, get_standard_analyzer};
+use mapping::completion::CompletionContext;
 use index::metadata::IndexMetadata;
+use analysis::filters::FilterSpec;
+use analysis::ngram_generator::Edge;
+
+
+/// The longest prefix of each word that is indexed into "_index_prefix" subfields
+const MAX_INDEXED_PREFIX_SIZE: usize = 20;
 
 
 #[derive(Debug, PartialEq)]
@@ -14,7 +21,17 @@ pub struct FieldMappingBuilder {
     pub boost: f64,
     pub base_analyzer: Option<String>,
     pub index_analyzer: Option<String>,
-    pub search_analyzer: Option<String>
+    pub search_analyzer: Option<String>,
+
+    /// Index shingles of this many words instead of single words
+    pub shingle_size: Option<usize>,
+
+    /// Index the prefixes of each word
+    pub index_prefixes: bool,
+
+    pub subfields: Vec<String>,
+    pub relations: HashMap<String, Vec<String>>,
+    pub contexts: Vec<CompletionContext>,
 }
 
 
@@ -30,6 +47,11 @@ impl Default for FieldMappingBuilder {
             base_analyzer: None,
             index_analyzer: None,
             search_analyzer: None,
+            shingle_size: None,
+            index_prefixes: false,
+            subfields: Vec::new(),
+            relations: HashMap::new(),
+            contexts: Vec::new(),
         }
     }
 }
@@ -81,15 +103,49 @@ impl FieldMappingBuilder {
             None
         };
 
+        // Add shingles and prefixes
+        let shingle_filter = self.shingle_size.map(|shingle_size| {
+            FilterSpec::Shingle {
+                min_size: shingle_size,
+                max_size: shingle_size,
+                output_unigrams: false,
+            }
+        });
+
+        let index_analyzer = index_analyzer.map(|mut analyzer| {
+            analyzer.filters.extend(shingle_filter.clone());
+
+            if self.index_prefixes {
+                analyzer.filters.push(FilterSpec::NGram {
+                    min_size: 1,
+                    max_size: MAX_INDEXED_PREFIX_SIZE,
+                    edge: Edge::Left,
+                });
+            }
+
+            analyzer
+        });
+
+        // Prefixes are only needed at index time, searches look up the whole word
+        let search_analyzer = search_analyzer.map(|mut analyzer| {
+            analyzer.filters.extend(shingle_filter.clone());
+            analyzer
+        });
+
         FieldMapping {
             data_type: self.field_type,
             index_ref: None,
             is_indexed: self.is_indexed,
-            is_stored: self.is_stored,
+            // Wildcard and shape queries check candidates against the stored
+            // value. Percolator queries and histograms are read back from the store
+            is_stored: self.is_stored || self.field_type.is_always_stored(),
             is_in_all: self.is_in_all,
             boost: self.boost,
             index_analyzer: index_analyzer,
             search_analyzer: search_analyzer,
+            subfields: self.subfields.clone(),
+            relations: self.relations.clone(),
+            contexts: self.contexts.clone(),
         }
     }
 }
@@ -126,6 +182,7 @@ impl NestedMappingBuilder {
         }
 
         NestedMapping {
+            index_ref: None,
             properties: properties,
         }
     }
@@ -0,0 +1,368 @@
+//! Completion fields
+//!
+//! Completion fields hold the suggestions of the completion suggester. Each
+//! input is indexed whole and lowercased, so suggestions are found by looking
+//! up the terms that start with what has been typed so far. Inputs are also
+//! indexed once for each of their context values, with the context in front of
+//! the input, so suggestions can be restricted to a category or an area without
+//! a separate field for each one.
+
+use std::collections::BTreeMap;
+
+use serde_json::Value as Json;
+use search::Term;
+
+use aggregations::geo::{geohash, MAX_GEOHASH_PRECISION};
+use mapping::parse_geo_point;
+
+
+/// The length of the geohashes that geo contexts are indexed with, unless the mapping gives one
+pub const DEFAULT_GEO_CONTEXT_PRECISION: u32 = 6;
+
+/// Separates the name of a context from its value
+const CONTEXT_SEPARATOR: u8 = 0x1F;
+
+/// Marks the start of the input. Terms without a context start with this
+const INPUT_MARKER: u8 = 0x1E;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompletionContextType {
+    /// Values are names, such as a tenant or a category
+    Category,
+
+    /// Values are geo points, which are indexed as geohashes of every length up to the precision
+    Geo {
+        precision: u32,
+    },
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompletionContext {
+    pub name: String,
+    pub context_type: CompletionContextType,
+}
+
+
+impl CompletionContext {
+    /// Reads the definition of a context from the "contexts" setting of a completion field
+    pub fn from_json(json: &Json) -> Option<CompletionContext> {
+        let object = json.as_object()?;
+        let mut name = None;
+        let mut context_type = None;
+        let mut precision = None;
+
+        for (key, value) in object.iter() {
+            match key.as_ref() {
+                "name" => name = Some(value.as_str()?.to_string()),
+                "type" => context_type = Some(value.as_str()?),
+                "precision" => {
+                    let value = value.as_u64()? as u32;
+                    if value == 0 || value > MAX_GEOHASH_PRECISION {
+                        return None;
+                    }
+
+                    precision = Some(value);
+                }
+                _ => return None,
+            }
+        }
+
+        let context_type = match (context_type?, precision) {
+            ("category", None) => CompletionContextType::Category,
+            ("geo", precision) => CompletionContextType::Geo {
+                precision: precision.unwrap_or(DEFAULT_GEO_CONTEXT_PRECISION),
+            },
+            _ => return None,
+        };
+
+        Some(CompletionContext {
+            name: name?,
+            context_type: context_type,
+        })
+    }
+
+    pub fn to_json(&self) -> Json {
+        match self.context_type {
+            CompletionContextType::Category => json!({"name": self.name, "type": "category"}),
+            CompletionContextType::Geo{precision} => json!({"name": self.name, "type": "geo", "precision": precision}),
+        }
+    }
+
+    /// Reads one value of the context. Geo points are given as the geohash of the
+    /// cell they are in, which can be shortened to search a larger area
+    pub fn parse_value(&self, json: &Json, precision: Option<u32>) -> Option<String> {
+        match self.context_type {
+            CompletionContextType::Category => {
+                match *json {
+                    Json::String(ref value) => Some(value.clone()),
+                    Json::Number(ref value) => Some(value.to_string()),
+                    Json::Bool(value) => Some(value.to_string()),
+                    _ => None,
+                }
+            }
+            CompletionContextType::Geo{precision: max_precision} => {
+                let point = parse_geo_point(json)?;
+                Some(geohash(point, precision.unwrap_or(max_precision).min(max_precision)))
+            }
+        }
+    }
+
+    /// Reads the values of the context in a document, which can be one value or an array of them
+    fn parse_values(&self, json: &Json) -> Option<Vec<String>> {
+        match *json {
+            // Arrays of numbers are a single geo point
+            Json::Array(ref array) if !array.first().map_or(false, |item| item.is_number()) => {
+                array.iter().map(|value| self.parse_value(value, None)).collect()
+            }
+            _ => self.parse_value(json, None).map(|value| vec![value]),
+        }
+    }
+
+    /// Checks if a value of this context in a document matches a value from a suggest request
+    pub fn matches(&self, value: &str, query_value: &str) -> bool {
+        match self.context_type {
+            CompletionContextType::Category => value == query_value,
+            CompletionContextType::Geo{..} => value.starts_with(query_value),
+        }
+    }
+}
+
+
+/// The suggestions that a document gives in a completion field
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompletionEntry {
+    pub inputs: Vec<String>,
+    pub weight: i64,
+
+    /// The values of each context
+    pub contexts: BTreeMap<String, Vec<String>>,
+}
+
+
+impl CompletionEntry {
+    fn from_json(json: &Json, contexts: &[CompletionContext]) -> Option<CompletionEntry> {
+        let mut entry = CompletionEntry {
+            inputs: Vec::new(),
+            weight: 1,
+            contexts: BTreeMap::new(),
+        };
+
+        match *json {
+            Json::String(ref input) => entry.inputs.push(input.clone()),
+            Json::Object(ref object) => {
+                for (key, value) in object.iter() {
+                    match key.as_ref() {
+                        "input" => {
+                            entry.inputs = match *value {
+                                Json::String(ref input) => vec![input.clone()],
+                                Json::Array(ref array) => array.iter().map(|input| input.as_str().map(|input| input.to_string())).collect::<Option<Vec<_>>>()?,
+                                _ => return None,
+                            };
+                        }
+                        "weight" => {
+                            entry.weight = value.as_i64().filter(|weight| *weight >= 0)?;
+                        }
+                        "contexts" => {
+                            for (name, values) in value.as_object()?.iter() {
+                                let context = contexts.iter().find(|context| context.name == *name)?;
+                                entry.contexts.insert(name.clone(), context.parse_values(values)?);
+                            }
+                        }
+                        _ => return None,
+                    }
+                }
+            }
+            _ => return None,
+        }
+
+        Some(entry)
+    }
+
+    pub fn to_json(&self) -> Json {
+        json!({
+            "input": self.inputs,
+            "weight": self.weight,
+            "contexts": self.contexts,
+        })
+    }
+
+    /// Reads the entries from the stored value of a completion field
+    pub fn from_stored_json(json: &Json) -> Vec<CompletionEntry> {
+        let read_entry = |json: &Json| {
+            Some(CompletionEntry {
+                inputs: json.get("input")?.as_array()?.iter().map(|input| input.as_str().map(|input| input.to_string())).collect::<Option<Vec<_>>>()?,
+                weight: json.get("weight")?.as_i64()?,
+                contexts: json.get("contexts")?.as_object()?.iter().map(|(name, values)| {
+                    let values = values.as_array()?.iter().map(|value| value.as_str().map(|value| value.to_string())).collect::<Option<Vec<_>>>()?;
+                    Some((name.clone(), values))
+                }).collect::<Option<BTreeMap<_, _>>>()?,
+            })
+        };
+
+        json.as_array().map_or_else(Vec::new, |array| array.iter().filter_map(read_entry).collect())
+    }
+}
+
+
+/// Reads the value of a completion field
+///
+/// Values are an input, an object with "input", "weight" and "contexts" keys or
+/// an array of either of these.
+pub fn parse_completion_value(json: &Json, contexts: &[CompletionContext]) -> Option<Vec<CompletionEntry>> {
+    match *json {
+        Json::Array(ref array) => {
+            // Arrays of strings are the inputs of one entry
+            if array.iter().all(|item| item.is_string()) {
+                CompletionEntry::from_json(&json!({"input": json}), contexts).map(|entry| vec![entry])
+            } else {
+                array.iter().map(|item| CompletionEntry::from_json(item, contexts)).collect()
+            }
+        }
+        _ => CompletionEntry::from_json(json, contexts).map(|entry| vec![entry]),
+    }
+}
+
+
+/// Builds the term that a completion input, or the start of one, is indexed as
+pub fn completion_term(context: Option<(&str, &str)>, input: &str) -> Term {
+    let mut bytes = Vec::new();
+
+    if let Some((name, value)) = context {
+        bytes.extend(name.as_bytes());
+        bytes.push(CONTEXT_SEPARATOR);
+        bytes.extend(value.as_bytes());
+    }
+
+    bytes.push(INPUT_MARKER);
+    bytes.extend(input.to_lowercase().as_bytes());
+    Term::from_bytes(&bytes)
+}
+
+
+/// Builds the terms that an entry is indexed as
+pub fn completion_index_terms(entry: &CompletionEntry, contexts: &[CompletionContext]) -> Vec<Term> {
+    let mut terms = Vec::new();
+
+    for input in entry.inputs.iter() {
+        terms.push(completion_term(None, input));
+
+        for (name, values) in entry.contexts.iter() {
+            let is_geo = contexts.iter().any(|context| context.name == *name && context.context_type != CompletionContextType::Category);
+
+            for value in values.iter() {
+                if is_geo {
+                    // Shorter geohashes cover larger areas
+                    for length in 1..(value.len() + 1) {
+                        terms.push(completion_term(Some((name, &value[..length])), input));
+                    }
+                } else {
+                    terms.push(completion_term(Some((name, value)), input));
+                }
+            }
+        }
+    }
+
+    terms
+}
+
+
+#[cfg(test)]
+mod tests {
+    use search::Term;
+
+    use super::{CompletionContext, CompletionContextType, CompletionEntry, parse_completion_value, completion_term, completion_index_terms};
+
+    fn contexts() -> Vec<CompletionContext> {
+        vec![
+            CompletionContext {
+                name: "tenant".to_string(),
+                context_type: CompletionContextType::Category,
+            },
+            CompletionContext {
+                name: "location".to_string(),
+                context_type: CompletionContextType::Geo {
+                    precision: 3,
+                },
+            },
+        ]
+    }
+
+    #[test]
+    fn test_completion_context_from_json() {
+        assert_eq!(CompletionContext::from_json(&json!({"name": "location", "type": "geo"})), Some(CompletionContext {
+            name: "location".to_string(),
+            context_type: CompletionContextType::Geo {
+                precision: 6,
+            },
+        }));
+
+        assert_eq!(CompletionContext::from_json(&json!({"name": "tenant", "type": "category", "precision": 2})), None);
+        assert_eq!(CompletionContext::from_json(&json!({"name": "tenant", "type": "range"})), None);
+        assert_eq!(CompletionContext::from_json(&json!({"type": "category"})), None);
+    }
+
+    #[test]
+    fn test_parse_completion_value() {
+        assert_eq!(parse_completion_value(&json!(["Nevermind", "Nirvana"]), &contexts()), Some(vec![
+            CompletionEntry {
+                inputs: vec!["Nevermind".to_string(), "Nirvana".to_string()],
+                weight: 1,
+                contexts: btreemap! {},
+            },
+        ]));
+
+        assert_eq!(parse_completion_value(&json!({
+            "input": "Nirvana",
+            "weight": 34,
+            "contexts": {
+                "tenant": ["acme", "globex"],
+                "location": {"lat": 52.5, "lon": 13.4}
+            }
+        }), &contexts()), Some(vec![
+            CompletionEntry {
+                inputs: vec!["Nirvana".to_string()],
+                weight: 34,
+                contexts: btreemap! {
+                    "location".to_string() => vec!["u33".to_string()],
+                    "tenant".to_string() => vec!["acme".to_string(), "globex".to_string()],
+                },
+            },
+        ]));
+
+        // Contexts must be in the mapping
+        assert_eq!(parse_completion_value(&json!({"input": "Nirvana", "contexts": {"genre": "grunge"}}), &contexts()), None);
+        assert_eq!(parse_completion_value(&json!({"input": "Nirvana", "weight": -1}), &contexts()), None);
+    }
+
+    #[test]
+    fn test_completion_index_terms() {
+        let entry = CompletionEntry {
+            inputs: vec!["Nirvana".to_string()],
+            weight: 1,
+            contexts: btreemap! {
+                "location".to_string() => vec!["u33".to_string()],
+                "tenant".to_string() => vec!["acme".to_string()],
+            },
+        };
+
+        assert_eq!(completion_index_terms(&entry, &contexts()), vec![
+            completion_term(None, "nirvana"),
+            completion_term(Some(("location", "u")), "nirvana"),
+            completion_term(Some(("location", "u3")), "nirvana"),
+            completion_term(Some(("location", "u33")), "nirvana"),
+            completion_term(Some(("tenant", "acme")), "nirvana"),
+        ]);
+
+        assert_eq!(completion_term(Some(("tenant", "acme")), "Nir"), Term::from_bytes(b"tenant\x1facme\x1enir"));
+    }
+
+    #[test]
+    fn test_completion_entries_round_trip() {
+        let entries = parse_completion_value(&json!({"input": ["Nirvana"], "weight": 3, "contexts": {"tenant": "acme"}}), &contexts()).unwrap();
+        let stored = json!(entries.iter().map(|entry| entry.to_json()).collect::<Vec<_>>());
+
+        assert_eq!(CompletionEntry::from_stored_json(&stored), entries);
+    }
+}
@@ -0,0 +1,108 @@
+//! Histogram fields
+//!
+//! Histogram fields hold data that has already been aggregated, such as
+//! downsampled metrics, as a list of values and the number of times each one
+//! was seen. Aggregations read them from the store and weight each value by its
+//! count, so metrics and histogram buckets come out the same as they would over
+//! the raw data.
+
+use serde_json::Value as Json;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    /// Values in increasing order
+    pub values: Vec<f64>,
+
+    /// The number of times each value was seen
+    pub counts: Vec<u64>,
+}
+
+
+impl Histogram {
+    /// Reads a histogram from an object with "values" and "counts" arrays of
+    /// the same length. Values must be in increasing order
+    pub fn from_json(json: &Json) -> Option<Histogram> {
+        let object = json.as_object()?;
+
+        let mut values = Vec::new();
+        let mut counts = Vec::new();
+
+        for (key, value) in object.iter() {
+            match key.as_ref() {
+                "values" => {
+                    values = value.as_array()?.iter().map(|value| value.as_f64()).collect::<Option<Vec<_>>>()?;
+                }
+                "counts" => {
+                    counts = value.as_array()?.iter().map(|count| count.as_u64()).collect::<Option<Vec<_>>>()?;
+                }
+                _ => return None,
+            }
+        }
+
+        if values.len() != counts.len() || values.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return None;
+        }
+
+        Some(Histogram {
+            values: values,
+            counts: counts,
+        })
+    }
+
+    pub fn total_count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Adds up the counts of each bucket of a histogram aggregation. Buckets
+    /// are numbered from the one that starts at 0
+    pub fn bucket_counts<F: Fn(f64) -> i64>(&self, bucket_index: F) -> Vec<(i64, u64)> {
+        let mut buckets: Vec<(i64, u64)> = Vec::new();
+
+        // Values are in order, so buckets are filled one after another
+        for (value, count) in self.values.iter().zip(self.counts.iter()) {
+            let index = bucket_index(*value);
+
+            match buckets.last_mut() {
+                Some(&mut (last_index, ref mut last_count)) if last_index == index => *last_count += *count,
+                _ => buckets.push((index, *count)),
+            }
+        }
+
+        buckets
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::Histogram;
+
+    #[test]
+    fn test_from_json() {
+        assert_eq!(Histogram::from_json(&json!({"values": [0.1, 0.2, 0.3], "counts": [3, 7, 23]})), Some(Histogram {
+            values: vec![0.1, 0.2, 0.3],
+            counts: vec![3, 7, 23],
+        }));
+
+        // Lengths must match
+        assert_eq!(Histogram::from_json(&json!({"values": [0.1, 0.2], "counts": [3]})), None);
+
+        // Values must be in order
+        assert_eq!(Histogram::from_json(&json!({"values": [0.2, 0.1], "counts": [3, 7]})), None);
+
+        // Counts can't be negative
+        assert_eq!(Histogram::from_json(&json!({"values": [0.1], "counts": [-3]})), None);
+    }
+
+    #[test]
+    fn test_bucket_counts() {
+        let histogram = Histogram {
+            values: vec![1.0, 4.0, 6.0, 12.0],
+            counts: vec![2, 3, 4, 5],
+        };
+
+        assert_eq!(histogram.bucket_counts(|value| (value / 5.0).floor() as i64), vec![(0, 5), (1, 4), (2, 5)]);
+        assert_eq!(histogram.bucket_counts(|value| ((value - 2.0) / 5.0).floor() as i64), vec![(-1, 2), (0, 7), (2, 5)]);
+    }
+}
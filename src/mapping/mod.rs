@@ -1,5 +1,8 @@
 pub mod build;
 pub mod parse;
+pub mod percolator;
+pub mod histogram;
+pub mod completion;
 
 use std::collections::{HashMap, BTreeMap};
 
@@ -11,8 +14,14 @@ use search::{Term, Token};
 use search::term_vector::TermVector;
 use search::document::FieldValue;
 use search::similarity::SimilarityModel;
+use search::query::wildcard::{trigrams_of, trigram_term, VALUE_SEPARATOR};
+use search::query::rank_feature::rank_feature_term;
+use search::query::geo_shape::{Geometry, CoordinateSystem, parse_coordinate, geo_shape_index_terms};
 use search::schema::FieldId;
 
+use self::histogram::Histogram;
+use self::completion::{CompletionContext, parse_completion_value, completion_index_terms};
+
 use analysis::AnalyzerSpec;
 use analysis::tokenizers::TokenizerSpec;
 use analysis::filters::FilterSpec;
@@ -36,6 +45,38 @@ pub enum FieldType {
     Integer,
     Boolean,
     Date,
+    Wildcard,
+    SearchAsYouType,
+    Flattened,
+    Join,
+    Percolator,
+    RankFeature,
+    RankFeatures,
+    GeoShape,
+    Shape,
+    Point,
+    GeoPoint,
+    Histogram,
+    Completion,
+}
+
+
+impl FieldType {
+    /// Checks if values of this type are text that can be analyzed
+    pub fn is_text(&self) -> bool {
+        match *self {
+            FieldType::String | FieldType::SearchAsYouType => true,
+            _ => false,
+        }
+    }
+
+    /// Checks if values of this type must always be stored
+    pub fn is_always_stored(&self) -> bool {
+        match *self {
+            FieldType::Wildcard | FieldType::GeoShape | FieldType::Shape | FieldType::Point | FieldType::GeoPoint | FieldType::Percolator | FieldType::Histogram | FieldType::Completion => true,
+            _ => false,
+        }
+    }
 }
 
 
@@ -53,6 +94,19 @@ impl ToString for FieldType {
             FieldType::Integer => "integer".to_string(),
             FieldType::Boolean => "boolean".to_string(),
             FieldType::Date => "date".to_string(),
+            FieldType::Wildcard => "wildcard".to_string(),
+            FieldType::SearchAsYouType => "search_as_you_type".to_string(),
+            FieldType::Flattened => "flattened".to_string(),
+            FieldType::Join => "join".to_string(),
+            FieldType::Percolator => "percolator".to_string(),
+            FieldType::RankFeature => "rank_feature".to_string(),
+            FieldType::RankFeatures => "rank_features".to_string(),
+            FieldType::GeoShape => "geo_shape".to_string(),
+            FieldType::Shape => "shape".to_string(),
+            FieldType::Point => "point".to_string(),
+            FieldType::GeoPoint => "geo_point".to_string(),
+            FieldType::Histogram => "histogram".to_string(),
+            FieldType::Completion => "completion".to_string(),
         }
     }
 }
@@ -92,6 +146,15 @@ pub struct FieldMapping {
     boost: f64,
     index_analyzer: Option<AnalyzerSpec>,
     search_analyzer: Option<AnalyzerSpec>,
+
+    /// Names of other fields that values of this field are also indexed into
+    pub subfields: Vec<String>,
+
+    /// For join fields, the names of the child relations of each parent relation
+    pub relations: HashMap<String, Vec<String>>,
+
+    /// For completion fields, the contexts that suggestions can be restricted to
+    pub contexts: Vec<CompletionContext>,
 }
 
 
@@ -106,6 +169,9 @@ impl Default for FieldMapping {
             boost: 1.0f64,
             index_analyzer: None,
             search_analyzer: None,
+            subfields: Vec::new(),
+            relations: HashMap::new(),
+            contexts: Vec::new(),
         }
     }
 }
@@ -117,7 +183,7 @@ impl Serialize for FieldMapping {
             (false, &None) => "no",
             (true, &None) => "not_analyzed",
             _ => {
-                if self.data_type == FieldType::String {
+                if self.data_type.is_text() {
                     "analyzed"
                 } else {
                     "not_analyzed"
@@ -125,7 +191,7 @@ impl Serialize for FieldMapping {
             }
         };
 
-        let json = json!({
+        let mut json = json!({
             "type": self.data_type.to_string(),
             "index": index,
             "store": self.is_stored,
@@ -136,6 +202,24 @@ impl Serialize for FieldMapping {
             "include_in_all": self.is_in_all
         });
 
+        if self.data_type == FieldType::Join {
+            let relations_json = self.relations.iter().map(|(parent, children)| {
+                let children_json = if children.len() == 1 {
+                    json!(children[0])
+                } else {
+                    json!(children)
+                };
+
+                (parent.clone(), children_json)
+            }).collect::<BTreeMap<_, _>>();
+
+            json["relations"] = json!(relations_json);
+        }
+
+        if self.data_type == FieldType::Completion && !self.contexts.is_empty() {
+            json["contexts"] = json!(self.contexts.iter().map(|context| context.to_json()).collect::<Vec<_>>());
+        }
+
         json.serialize(serializer)
     }
 }
@@ -158,6 +242,23 @@ impl FieldMapping {
         }
     }
 
+    /// Finds the parent relation of a relation in a join field
+    ///
+    /// Returns Some(None) for relations that don't have a parent and None for relations that don't exist
+    pub fn get_parent_relation(&self, relation: &str) -> Option<Option<&str>> {
+        for (parent, children) in self.relations.iter() {
+            if children.iter().any(|child| child == relation) {
+                return Some(Some(parent));
+            }
+        }
+
+        if self.relations.contains_key(relation) {
+            Some(None)
+        } else {
+            None
+        }
+    }
+
     pub fn get_search_options(&self) -> FieldSearchOptions {
         FieldSearchOptions {
             analyzer: self.search_analyzer().cloned(),
@@ -165,13 +266,90 @@ impl FieldMapping {
         }
     }
 
+    /// Finds the first setting that this field and a field with the same name
+    /// in another mapping disagree on. Fields with the same name share a field
+    /// in the store, so they must be indexed and searched in the same way
+    pub fn find_conflict(&self, other: &FieldMapping) -> Option<&'static str> {
+        if self.data_type != other.data_type {
+            Some("type")
+        } else if self.is_indexed != other.is_indexed {
+            Some("index")
+        } else if self.is_stored != other.is_stored {
+            Some("store")
+        } else if self.index_analyzer != other.index_analyzer {
+            Some("analyzer")
+        } else if self.search_analyzer != other.search_analyzer {
+            Some("search_analyzer")
+        } else {
+            None
+        }
+    }
+
+    /// Reads the value of a geo_shape, shape, point or geo_point field
+    fn parse_shape_value(&self, value: &serde_json::Value) -> Result<(Geometry, CoordinateSystem), FieldValueError> {
+        let shape = match self.data_type {
+            FieldType::GeoShape => Geometry::from_json(value, CoordinateSystem::Geographic),
+            FieldType::Shape => Geometry::from_json(value, CoordinateSystem::Cartesian),
+            FieldType::Point => {
+                match *value {
+                    // Arrays of numbers are a single point, otherwise they hold many points
+                    serde_json::Value::Array(ref array) if !array.first().map(|item| item.is_number()).unwrap_or(false) => {
+                        array.iter().map(parse_cartesian_point).collect::<Option<Vec<_>>>().map(Geometry::MultiPoint)
+                    }
+                    _ => parse_cartesian_point(value).map(Geometry::Point),
+                }
+            }
+            FieldType::GeoPoint => {
+                match *value {
+                    serde_json::Value::Array(ref array) if !array.first().map(|item| item.is_number()).unwrap_or(false) => {
+                        array.iter().map(parse_geo_point).collect::<Option<Vec<_>>>().map(Geometry::MultiPoint)
+                    }
+                    _ => parse_geo_point(value).map(Geometry::Point),
+                }
+            }
+            _ => None,
+        };
+
+        match (shape, self.data_type) {
+            (Some(shape), FieldType::GeoShape) | (Some(shape), FieldType::GeoPoint) => Ok((shape, CoordinateSystem::Geographic)),
+            (Some(shape), _) => Ok((shape, CoordinateSystem::Cartesian)),
+            (None, _) => Err(FieldValueError),
+        }
+    }
+
+    /// Reads the relation and parent id from the value of a join field
+    ///
+    /// Values are either the name of the relation or an object with "name" and
+    /// "parent" keys. Children must give the id of their parent.
+    fn parse_join_value<'a>(&self, value: &'a serde_json::Value) -> Result<(&'a str, Option<&'a str>), FieldValueError> {
+        let (relation, parent_id) = match *value {
+            serde_json::Value::String(ref relation) => (relation.as_str(), None),
+            serde_json::Value::Object(ref object) => {
+                let relation = object.get("name").and_then(|name| name.as_str()).ok_or(FieldValueError)?;
+                let parent_id = match object.get("parent") {
+                    Some(parent_id) => Some(parent_id.as_str().ok_or(FieldValueError)?),
+                    None => None,
+                };
+
+                (relation, parent_id)
+            }
+            _ => return Err(FieldValueError),
+        };
+
+        match self.get_parent_relation(relation) {
+            Some(Some(_)) if parent_id.is_some() => Ok((relation, parent_id)),
+            Some(None) if parent_id.is_none() => Ok((relation, None)),
+            _ => Err(FieldValueError),
+        }
+    }
+
     pub fn process_value_for_index(&self, value: &serde_json::Value) -> Result<Option<TermVector>, FieldValueError> {
         if *value == serde_json::Value::Null {
             return Ok(None);
         }
 
         match self.data_type {
-            FieldType::String => {
+            FieldType::String | FieldType::SearchAsYouType => {
                 match *value {
                     serde_json::Value::String(ref string) => {
                         // Analyze string
@@ -229,6 +407,91 @@ impl FieldMapping {
                     _ => Err(FieldValueError),
                 }
             }
+            FieldType::Wildcard => {
+                let strings = wildcard_values(value)?;
+                let mut tokens = Vec::new();
+
+                // Each value is indexed whole, followed by its trigrams
+                for (i, string) in strings.iter().enumerate() {
+                    let position = i as u32 + 1;
+                    tokens.push(Token {term: Term::from_string(string), position: position});
+
+                    for trigram in trigrams_of(string) {
+                        tokens.push(Token {term: trigram_term(&trigram), position: position});
+                    }
+                }
+
+                Ok(Some(tokens.into()))
+            }
+            FieldType::Flattened => {
+                let mut leaves = Vec::new();
+                flatten_object(None, value, &mut leaves)?;
+
+                // Each leaf value is indexed on its own and along with its key
+                let mut tokens = Vec::new();
+                for (i, (key, value)) in leaves.iter().enumerate() {
+                    let position = i as u32 + 1;
+                    tokens.push(Token {term: Term::from_string(value), position: position});
+                    tokens.push(Token {term: flattened_keyed_term(key, value.as_bytes()), position: position});
+                }
+
+                Ok(Some(tokens.into()))
+            }
+            FieldType::Join => {
+                let (relation, parent_id) = self.parse_join_value(value)?;
+
+                // Children are also indexed with the id of their parent
+                let mut tokens = vec![Token {term: Term::from_string(relation), position: 1}];
+                if let Some(parent_id) = parent_id {
+                    tokens.push(Token {term: join_parent_term(parent_id), position: 1});
+                }
+
+                Ok(Some(tokens.into()))
+            }
+            FieldType::GeoShape | FieldType::Shape | FieldType::Point | FieldType::GeoPoint => {
+                let (shape, system) = self.parse_shape_value(value)?;
+                let tokens = geo_shape_index_terms(&shape, system).into_iter().map(|term| Token{term: term, position: 1}).collect::<Vec<_>>();
+                Ok(Some(tokens.into()))
+            }
+            FieldType::RankFeature => {
+                let value = parse_rank_feature_value(value)?;
+                Ok(Some(vec![Token{term: rank_feature_term("", value), position: 1}].into()))
+            }
+            FieldType::RankFeatures => {
+                match *value {
+                    serde_json::Value::Object(ref object) => {
+                        let mut tokens = Vec::new();
+                        for (feature, value) in object.iter() {
+                            let value = parse_rank_feature_value(value)?;
+                            tokens.push(Token{term: rank_feature_term(feature, value), position: 1});
+                        }
+
+                        Ok(Some(tokens.into()))
+                    }
+                    _ => Err(FieldValueError),
+                }
+            }
+            FieldType::Completion => {
+                let entries = parse_completion_value(value, &self.contexts).ok_or(FieldValueError)?;
+                let tokens = entries.iter().enumerate()
+                    .flat_map(|(i, entry)| completion_index_terms(entry, &self.contexts).into_iter().map(move |term| Token{term: term, position: i as u32 + 1}))
+                    .collect::<Vec<_>>();
+
+                Ok(Some(tokens.into()))
+            }
+            FieldType::Histogram => {
+                // Histograms are only read by aggregations, from the store
+                Histogram::from_json(value).ok_or(FieldValueError)?;
+                Ok(None)
+            }
+            FieldType::Percolator => {
+                // Queries are checked against the mapping when the document is
+                // prepared. They aren't searchable so there is nothing to index
+                match *value {
+                    serde_json::Value::Object(_) => Ok(None),
+                    _ => Err(FieldValueError),
+                }
+            }
             FieldType::Integer => {
                 match *value {
                     serde_json::Value::Number(ref num) => {
@@ -237,6 +500,18 @@ impl FieldMapping {
                             None => Err(FieldValueError),
                         }
                     }
+                    serde_json::Value::Array(_) => {
+                        // Each value is given its own position
+                        let tokens = integer_values(value)?.into_iter().enumerate()
+                            .map(|(i, num)| Token{term: Term::from_integer(num), position: i as u32 + 1})
+                            .collect::<Vec<_>>();
+
+                        if tokens.is_empty() {
+                            Ok(None)
+                        } else {
+                            Ok(Some(tokens.into()))
+                        }
+                    }
                     _ => Err(FieldValueError),
                 }
             }
@@ -271,7 +546,7 @@ impl FieldMapping {
         }
 
         match self.data_type {
-            FieldType::String => {
+            FieldType::String | FieldType::SearchAsYouType => {
                 match *value {
                     serde_json::Value::String(ref string) => {
                         Ok(Some(FieldValue::String(string.clone())))
@@ -291,11 +566,78 @@ impl FieldMapping {
                             }
                         }
 
-                        self.process_value_for_store(&serde_json::Value::String(strings.join(" ")))
+                        if strings.is_empty() {
+                            Ok(None)
+                        } else {
+                            Ok(Some(FieldValue::StringArray(strings)))
+                        }
                     }
                     _ => Err(FieldValueError),
                 }
             }
+            FieldType::Wildcard => {
+                let strings = wildcard_values(value)?;
+                Ok(Some(FieldValue::String(strings.join(&VALUE_SEPARATOR.to_string()))))
+            }
+            FieldType::Flattened => {
+                match *value {
+                    serde_json::Value::Object(_) => Ok(Some(FieldValue::String(value.to_string()))),
+                    _ => Err(FieldValueError),
+                }
+            }
+            FieldType::Percolator => {
+                match *value {
+                    serde_json::Value::Object(_) => Ok(Some(FieldValue::String(value.to_string()))),
+                    _ => Err(FieldValueError),
+                }
+            }
+            FieldType::Histogram => {
+                Histogram::from_json(value).ok_or(FieldValueError)?;
+                Ok(Some(FieldValue::String(value.to_string())))
+            }
+            FieldType::Completion => {
+                // Geo context values are stored as geohashes
+                let entries = parse_completion_value(value, &self.contexts).ok_or(FieldValueError)?;
+                let entries_json = entries.iter().map(|entry| entry.to_json()).collect::<Vec<_>>();
+                Ok(Some(FieldValue::String(serde_json::Value::Array(entries_json).to_string())))
+            }
+            FieldType::GeoShape | FieldType::Shape => {
+                self.parse_shape_value(value)?;
+                Ok(Some(FieldValue::String(value.to_string())))
+            }
+            FieldType::Point | FieldType::GeoPoint => {
+                // Points can be given in a few formats, so are stored as GeoJSON
+                let shape_json = match self.parse_shape_value(value)? {
+                    (Geometry::Point((x, y)), _) => json!({"type": "point", "coordinates": [x, y]}),
+                    (Geometry::MultiPoint(points), _) => {
+                        let coordinates = points.iter().map(|&(x, y)| json!([x, y])).collect::<Vec<_>>();
+                        json!({"type": "multipoint", "coordinates": coordinates})
+                    }
+                    _ => return Err(FieldValueError),
+                };
+
+                Ok(Some(FieldValue::String(shape_json.to_string())))
+            }
+            FieldType::RankFeature => {
+                parse_rank_feature_value(value)?;
+                Ok(Some(FieldValue::String(value.to_string())))
+            }
+            FieldType::RankFeatures => {
+                match *value {
+                    serde_json::Value::Object(ref object) => {
+                        for value in object.values() {
+                            parse_rank_feature_value(value)?;
+                        }
+
+                        Ok(Some(FieldValue::String(value.to_string())))
+                    }
+                    _ => Err(FieldValueError),
+                }
+            }
+            FieldType::Join => {
+                let (relation, parent_id) = self.parse_join_value(value)?;
+                Ok(Some(FieldValue::String(json!({"name": relation, "parent": parent_id}).to_string())))
+            }
             FieldType::Integer => {
                 match *value {
                     serde_json::Value::Number(ref num) => {
@@ -304,6 +646,15 @@ impl FieldMapping {
                             None => Err(FieldValueError)
                         }
                     }
+                    serde_json::Value::Array(_) => {
+                        let values = integer_values(value)?;
+
+                        if values.is_empty() {
+                            Ok(None)
+                        } else {
+                            Ok(Some(FieldValue::IntegerArray(values)))
+                        }
+                    }
                     _ => Err(FieldValueError),
                 }
             }
@@ -336,6 +687,8 @@ impl FieldMapping {
 
 #[derive(Debug, PartialEq)]
 pub struct NestedMapping {
+    /// The field that marks the documents of the nested objects
+    pub index_ref: Option<FieldId>,
     pub properties: HashMap<String, MappingProperty>,
 }
 
@@ -382,12 +735,78 @@ pub struct Mapping {
 }
 
 
+impl Mapping {
+    /// Finds a property by its full name. Properties inside nested mappings
+    /// are referenced as "path.name"
+    pub fn get_property(&self, name: &str) -> Option<&MappingProperty> {
+        find_property(&self.properties, name)
+    }
+
+    pub fn get_field(&self, name: &str) -> Option<&FieldMapping> {
+        match self.get_property(name) {
+            Some(&MappingProperty::Field(ref field_mapping)) => Some(field_mapping),
+            _ => None,
+        }
+    }
+
+    /// Lists all of the fields by their full name, including those inside
+    /// nested mappings
+    pub fn fields(&self) -> Vec<(String, &FieldMapping)> {
+        let mut fields = Vec::new();
+        collect_fields(&self.properties, "", &mut fields);
+        fields
+    }
+}
+
+
+fn collect_fields<'a>(properties: &'a HashMap<String, MappingProperty>, path: &str, fields: &mut Vec<(String, &'a FieldMapping)>) {
+    for (name, property) in properties.iter() {
+        let name = format!("{}{}", path, name);
+
+        match *property {
+            MappingProperty::Field(ref field_mapping) => fields.push((name, field_mapping)),
+            MappingProperty::NestedMapping(ref nested_mapping) => collect_fields(&nested_mapping.properties, &format!("{}.", name), fields),
+        }
+    }
+}
+
+
+fn find_property<'a>(properties: &'a HashMap<String, MappingProperty>, name: &str) -> Option<&'a MappingProperty> {
+    // Subfields have dots in their names too, so these are checked first
+    if let Some(property) = properties.get(name) {
+        return Some(property);
+    }
+
+    for (split_at, _) in name.match_indices('.') {
+        if let Some(&MappingProperty::NestedMapping(ref nested_mapping)) = properties.get(&name[..split_at]) {
+            if let Some(property) = find_property(&nested_mapping.properties, &name[split_at + 1..]) {
+                return Some(property);
+            }
+        }
+    }
+
+    None
+}
+
+
 impl Serialize for Mapping {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
         let mut properties_json = BTreeMap::new();
 
+        // Subfields are created along with their parent field so aren't listed
+        let mut subfields = Vec::new();
+        for prop in self.properties.values() {
+            if let MappingProperty::Field(ref field_mapping) = *prop {
+                subfields.extend(field_mapping.subfields.iter());
+            }
+        }
+
         // TODO: Exclude "_all" field
         for (name, prop) in self.properties.iter() {
+            if subfields.contains(&name) {
+                continue;
+            }
+
             properties_json.insert(name.to_string(), serde_json::to_value(&prop).unwrap());
         }
 
@@ -400,6 +819,179 @@ impl Serialize for Mapping {
 }
 
 
+/// Reads the values of a wildcard field. Numbers are treated as strings and nulls are ignored
+fn wildcard_values(value: &serde_json::Value) -> Result<Vec<String>, FieldValueError> {
+    match *value {
+        serde_json::Value::String(ref string) => Ok(vec![string.clone()]),
+        serde_json::Value::Number(ref num) => Ok(vec![num.to_string()]),
+        serde_json::Value::Array(ref array) => {
+            let mut strings = Vec::new();
+
+            for item in array {
+                match *item {
+                    serde_json::Value::String(ref string) => strings.push(string.clone()),
+                    serde_json::Value::Number(ref num) => strings.push(num.to_string()),
+                    serde_json::Value::Null => {}
+                    _ => {
+                        return Err(FieldValueError);
+                    }
+                }
+            }
+
+            Ok(strings)
+        }
+        _ => Err(FieldValueError),
+    }
+}
+
+
+/// Reads the values of an integer field given as an array. Nulls are ignored
+fn integer_values(value: &serde_json::Value) -> Result<Vec<i64>, FieldValueError> {
+    let array = value.as_array().ok_or(FieldValueError)?;
+    let mut values = Vec::with_capacity(array.len());
+
+    for item in array {
+        match *item {
+            serde_json::Value::Number(ref num) => values.push(num.as_i64().ok_or(FieldValueError)?),
+            serde_json::Value::Null => {}
+            _ => {
+                return Err(FieldValueError);
+            }
+        }
+    }
+
+    Ok(values)
+}
+
+
+/// Collects the leaf values of an object for a flattened field, along with their keys
+///
+/// Keys of nested objects are joined with dots. Leaf values are indexed as strings
+/// and the items of arrays are indexed under the same key.
+fn flatten_object(key: Option<&str>, value: &serde_json::Value, leaves: &mut Vec<(String, String)>) -> Result<(), FieldValueError> {
+    match *value {
+        serde_json::Value::Object(ref object) => {
+            for (child_key, child_value) in object.iter() {
+                let child_key = match key {
+                    Some(key) => format!("{}.{}", key, child_key),
+                    None => child_key.clone(),
+                };
+
+                flatten_object(Some(&child_key), child_value, leaves)?;
+            }
+        }
+        serde_json::Value::Array(ref array) => {
+            for item in array.iter() {
+                if item.is_object() {
+                    return Err(FieldValueError);
+                }
+
+                flatten_object(key, item, leaves)?;
+            }
+        }
+        serde_json::Value::Null => {}
+        ref value => {
+            // Values must be in an object
+            let key = key.ok_or(FieldValueError)?;
+
+            let value = match *value {
+                serde_json::Value::String(ref string) => string.clone(),
+                ref value => value.to_string(),
+            };
+
+            leaves.push((key.to_string(), value));
+        }
+    }
+
+    Ok(())
+}
+
+
+/// Builds the term that a value is indexed as under a key of a flattened field
+pub fn flattened_keyed_term(key: &str, value: &[u8]) -> Term {
+    let mut bytes = Vec::with_capacity(key.len() + 1 + value.len());
+    bytes.extend(key.as_bytes());
+    bytes.push(0);
+    bytes.extend(value);
+    Term::from_bytes(&bytes)
+}
+
+
+/// Builds the term that child documents are indexed with in a join field
+///
+/// Parent ids start with a zero byte so they can't be confused with relation names
+pub fn join_parent_term(parent_id: &str) -> Term {
+    let mut bytes = Vec::with_capacity(parent_id.len() + 1);
+    bytes.push(0);
+    bytes.extend(parent_id.as_bytes());
+    Term::from_bytes(&bytes)
+}
+
+
+/// Parses a cartesian point given as [x, y], {"x": x, "y": y}, "x,y" or a GeoJSON point
+fn parse_cartesian_point(json: &serde_json::Value) -> Option<(f64, f64)> {
+    let system = CoordinateSystem::Cartesian;
+
+    match *json {
+        serde_json::Value::Array(_) => parse_coordinate(json, system),
+        serde_json::Value::String(ref string) => {
+            let mut split = string.splitn(2, ',');
+            let x = split.next()?.trim().parse::<f64>().ok()?;
+            let y = split.next()?.trim().parse::<f64>().ok()?;
+            parse_coordinate(&json!([x, y]), system)
+        }
+        serde_json::Value::Object(ref object) => {
+            if object.contains_key("type") {
+                match Geometry::from_json(json, system)? {
+                    Geometry::Point(point) => Some(point),
+                    _ => None,
+                }
+            } else {
+                parse_coordinate(&json!([object.get("x")?, object.get("y")?]), system)
+            }
+        }
+        _ => None,
+    }
+}
+
+
+/// Parses a geographic point given as [lon, lat], {"lat": lat, "lon": lon},
+/// "lat,lon" or a GeoJSON point
+pub fn parse_geo_point(json: &serde_json::Value) -> Option<(f64, f64)> {
+    let system = CoordinateSystem::Geographic;
+
+    match *json {
+        serde_json::Value::Array(_) => parse_coordinate(json, system),
+        serde_json::Value::String(ref string) => {
+            let mut split = string.splitn(2, ',');
+            let lat = split.next()?.trim().parse::<f64>().ok()?;
+            let lon = split.next()?.trim().parse::<f64>().ok()?;
+            parse_coordinate(&json!([lon, lat]), system)
+        }
+        serde_json::Value::Object(ref object) => {
+            if object.contains_key("type") {
+                match Geometry::from_json(json, system)? {
+                    Geometry::Point(point) => Some(point),
+                    _ => None,
+                }
+            } else {
+                parse_coordinate(&json!([object.get("lon")?, object.get("lat")?]), system)
+            }
+        }
+        _ => None,
+    }
+}
+
+
+/// Rank features must be positive numbers
+fn parse_rank_feature_value(json: &serde_json::Value) -> Result<f32, FieldValueError> {
+    match json.as_f64() {
+        Some(value) if value > 0.0 && (value as f32).is_finite() => Ok(value as f32),
+        _ => Err(FieldValueError),
+    }
+}
+
+
 fn parse_boolean(json: &serde_json::Value) -> bool {
     match *json {
         serde_json::Value::Bool(val) => val,
@@ -420,3 +1012,207 @@ fn parse_boolean(json: &serde_json::Value) -> bool {
         }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use search::{Term, Token};
+    use search::document::FieldValue;
+
+    use search::query::rank_feature::rank_feature_term;
+
+    use super::{Mapping, MappingProperty, NestedMapping, FieldMapping, FieldType, flattened_keyed_term, join_parent_term};
+
+    #[test]
+    fn test_process_flattened_value_for_index() {
+        let field_mapping = FieldMapping {
+            data_type: FieldType::Flattened,
+            ..FieldMapping::default()
+        };
+
+        let tokens: Vec<Token> = field_mapping.process_value_for_index(&json!({
+            "app": {
+                "tier": "frontend"
+            },
+            "replicas": 3
+        })).unwrap().unwrap().into();
+
+        let terms = tokens.into_iter().map(|token| token.term).collect::<Vec<Term>>();
+        assert_eq!(terms.len(), 4);
+        assert!(terms.contains(&Term::from_string("frontend")));
+        assert!(terms.contains(&flattened_keyed_term("app.tier", b"frontend")));
+        assert!(terms.contains(&Term::from_string("3")));
+        assert!(terms.contains(&flattened_keyed_term("replicas", b"3")));
+
+        // Values must be in an object
+        assert!(field_mapping.process_value_for_index(&json!("frontend")).is_err());
+    }
+
+    #[test]
+    fn test_process_join_value_for_index() {
+        let field_mapping = FieldMapping {
+            data_type: FieldType::Join,
+            relations: hashmap! {
+                "question".to_string() => vec!["answer".to_string()],
+            },
+            ..FieldMapping::default()
+        };
+
+        let tokens: Vec<Token> = field_mapping.process_value_for_index(&json!("question")).unwrap().unwrap().into();
+        assert_eq!(tokens, vec![
+            Token { term: Term::from_string("question"), position: 1 },
+        ]);
+
+        let tokens: Vec<Token> = field_mapping.process_value_for_index(&json!({"name": "answer", "parent": "1"})).unwrap().unwrap().into();
+        assert_eq!(tokens.len(), 2);
+        assert!(tokens.contains(&Token { term: Term::from_string("answer"), position: 1 }));
+        assert!(tokens.contains(&Token { term: join_parent_term("1"), position: 1 }));
+
+        // Children must have a parent and parents can't
+        assert!(field_mapping.process_value_for_index(&json!("answer")).is_err());
+        assert!(field_mapping.process_value_for_index(&json!({"name": "question", "parent": "1"})).is_err());
+
+        // Unknown relation
+        assert!(field_mapping.process_value_for_index(&json!("comment")).is_err());
+    }
+
+    #[test]
+    fn test_process_rank_features_value_for_index() {
+        let field_mapping = FieldMapping {
+            data_type: FieldType::RankFeatures,
+            ..FieldMapping::default()
+        };
+
+        let tokens: Vec<Token> = field_mapping.process_value_for_index(&json!({
+            "politics": 20,
+            "economics": 50.5
+        })).unwrap().unwrap().into();
+
+        assert_eq!(tokens.len(), 2);
+        assert!(tokens.contains(&Token { term: rank_feature_term("politics", 20.0f32), position: 1 }));
+        assert!(tokens.contains(&Token { term: rank_feature_term("economics", 50.5f32), position: 1 }));
+
+        // Values must be positive
+        assert!(field_mapping.process_value_for_index(&json!({"politics": 0})).is_err());
+        assert!(field_mapping.process_value_for_index(&json!({"politics": "high"})).is_err());
+    }
+
+    #[test]
+    fn test_process_integer_array_value() {
+        let field_mapping = FieldMapping {
+            data_type: FieldType::Integer,
+            ..FieldMapping::default()
+        };
+
+        let tokens: Vec<Token> = field_mapping.process_value_for_index(&json!([10, null, 25])).unwrap().unwrap().into();
+        assert_eq!(tokens, vec![
+            Token { term: Term::from_integer(10), position: 1 },
+            Token { term: Term::from_integer(25), position: 2 },
+        ]);
+
+        match field_mapping.process_value_for_store(&json!([10, null, 25])) {
+            Ok(Some(FieldValue::IntegerArray(values))) => assert_eq!(values, vec![10, 25]),
+            value => panic!("unexpected stored value: {:?}", value),
+        }
+
+        // All of the values must be integers
+        assert!(field_mapping.process_value_for_index(&json!([10, "25"])).is_err());
+        assert!(field_mapping.process_value_for_store(&json!([10, 2.5])).is_err());
+    }
+
+    #[test]
+    fn test_process_string_array_value_for_store() {
+        let field_mapping = FieldMapping {
+            data_type: FieldType::String,
+            ..FieldMapping::default()
+        };
+
+        match field_mapping.process_value_for_store(&json!(["bob smith", null, "carol"])) {
+            Ok(Some(FieldValue::StringArray(values))) => assert_eq!(values, vec!["bob smith".to_string(), "carol".to_string()]),
+            value => panic!("unexpected stored value: {:?}", value),
+        }
+
+        assert!(field_mapping.process_value_for_store(&json!([])).unwrap().is_none());
+        assert!(field_mapping.process_value_for_store(&json!(["carol", 1])).is_err());
+    }
+
+    #[test]
+    fn test_process_point_value_for_store() {
+        let field_mapping = FieldMapping {
+            data_type: FieldType::Point,
+            ..FieldMapping::default()
+        };
+
+        let stored_json = |value| {
+            match field_mapping.process_value_for_store(&value) {
+                Ok(Some(FieldValue::String(string))) => Some(string),
+                _ => None,
+            }
+        };
+
+        // Points can be given in many formats, but are all stored as GeoJSON
+        let expected = Some(json!({"type": "point", "coordinates": [1200.5, -300.0]}).to_string());
+        assert_eq!(stored_json(json!([1200.5, -300.0])), expected);
+        assert_eq!(stored_json(json!({"x": 1200.5, "y": -300.0})), expected);
+        assert_eq!(stored_json(json!("1200.5, -300")), expected);
+        assert_eq!(stored_json(json!({"type": "point", "coordinates": [1200.5, -300.0]})), expected);
+
+        assert_eq!(stored_json(json!("1200.5")), None);
+        assert_eq!(stored_json(json!({"x": 1200.5})), None);
+    }
+
+    #[test]
+    fn test_process_geo_point_value_for_store() {
+        let field_mapping = FieldMapping {
+            data_type: FieldType::GeoPoint,
+            ..FieldMapping::default()
+        };
+
+        let stored_json = |value| {
+            match field_mapping.process_value_for_store(&value) {
+                Ok(Some(FieldValue::String(string))) => Some(string),
+                _ => None,
+            }
+        };
+
+        // Strings give the latitude first, unlike arrays and GeoJSON
+        let expected = Some(json!({"type": "point", "coordinates": [-71.34, 41.12]}).to_string());
+        assert_eq!(stored_json(json!([-71.34, 41.12])), expected);
+        assert_eq!(stored_json(json!({"lat": 41.12, "lon": -71.34})), expected);
+        assert_eq!(stored_json(json!("41.12,-71.34")), expected);
+        assert_eq!(stored_json(json!({"type": "Point", "coordinates": [-71.34, 41.12]})), expected);
+
+        assert_eq!(stored_json(json!({"lat": 91.0, "lon": 0.0})), None);
+    }
+
+    #[test]
+    fn test_find_conflict() {
+        let title_mapping = FieldMapping {
+            data_type: FieldType::String,
+            ..FieldMapping::default()
+        };
+
+        assert_eq!(title_mapping.find_conflict(&FieldMapping::default()), None);
+        assert_eq!(title_mapping.find_conflict(&FieldMapping { data_type: FieldType::Integer, ..FieldMapping::default() }), Some("type"));
+        assert_eq!(title_mapping.find_conflict(&FieldMapping { is_stored: true, ..FieldMapping::default() }), Some("store"));
+    }
+
+    #[test]
+    fn test_mapping_fields() {
+        let mut nested_mapping = NestedMapping {
+            index_ref: None,
+            properties: Default::default(),
+        };
+        nested_mapping.properties.insert("name".to_string(), MappingProperty::Field(FieldMapping::default()));
+
+        let mut mapping = Mapping {
+            properties: Default::default(),
+        };
+        mapping.properties.insert("title".to_string(), MappingProperty::Field(FieldMapping::default()));
+        mapping.properties.insert("authors".to_string(), MappingProperty::NestedMapping(Box::new(nested_mapping)));
+
+        let mut names = mapping.fields().into_iter().map(|(name, _)| name).collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["authors.name", "title"]);
+    }
+}
@@ -3,6 +3,7 @@ use std::collections::{HashMap, BTreeSet};
 use serde_json;
 
 use mapping::FieldType;
+use mapping::completion::CompletionContext;
 use mapping::build::{MappingBuilder, MappingPropertyBuilder, FieldMappingBuilder, NestedMappingBuilder};
 
 
@@ -12,6 +13,7 @@ pub enum FieldMappingParseError {
     ExpectedString,
     ExpectedBoolean,
     ExpectedNumber,
+    ExpectedArray,
     ExpectedKey(String),
     UnrecognisedKeys(Vec<String>),
 
@@ -29,6 +31,13 @@ pub enum FieldMappingParseError {
     // "boost" setting
     BoostOnlyAllowedOnIndexedFields,
     BoostMustBePositive,
+
+    // "relations" setting
+    RelationsOnlyAllowedOnJoinType,
+
+    // "contexts" setting
+    ContextsOnlyAllowedOnCompletionType,
+    InvalidContext,
 }
 
 
@@ -38,10 +47,12 @@ pub enum MappingParseError {
     ExpectedString,
     ExpectedBoolean,
     ExpectedNumber,
+    ExpectedArray,
     ExpectedKey(String),
     UnrecognisedKeys(Vec<String>),
     FieldMappingParseError(String, FieldMappingParseError),
     NestedMappingParseError(String, Box<MappingParseError>),
+    MultipleJoinFields,
 }
 
 
@@ -79,6 +90,19 @@ fn parse_field_type(field_type_str: &str) -> Result<FieldType, FieldMappingParse
         "integer" => Ok(FieldType::Integer),
         "boolean" => Ok(FieldType::Boolean),
         "date" => Ok(FieldType::Date),
+        "wildcard" => Ok(FieldType::Wildcard),
+        "search_as_you_type" => Ok(FieldType::SearchAsYouType),
+        "flattened" => Ok(FieldType::Flattened),
+        "join" => Ok(FieldType::Join),
+        "percolator" => Ok(FieldType::Percolator),
+        "rank_feature" => Ok(FieldType::RankFeature),
+        "rank_features" => Ok(FieldType::RankFeatures),
+        "geo_shape" => Ok(FieldType::GeoShape),
+        "shape" => Ok(FieldType::Shape),
+        "point" => Ok(FieldType::Point),
+        "geo_point" => Ok(FieldType::GeoPoint),
+        "histogram" => Ok(FieldType::Histogram),
+        "completion" => Ok(FieldType::Completion),
         _ => Err(FieldMappingParseError::UnrecognisedFieldType(field_type_str.to_string())),
     }
 }
@@ -99,6 +123,8 @@ fn parse_field(json: &serde_json::Value) -> Result<FieldMappingBuilder, FieldMap
         "search_analyzer".to_string(),
         "boost".to_string(),
         "include_in_all".to_string(),
+        "relations".to_string(),
+        "contexts".to_string(),
     ];
     let unrecognised_keys = provided_keys.difference(&allowed_keys).cloned().collect::<Vec<String>>();
 
@@ -112,7 +138,7 @@ fn parse_field(json: &serde_json::Value) -> Result<FieldMappingBuilder, FieldMap
     mapping_builder.field_type = parse_field_type(field_type_str)?;
 
     // Non-string fields cannot be analyzed
-    if mapping_builder.field_type != FieldType::String {
+    if !mapping_builder.field_type.is_text() {
         mapping_builder.is_analyzed = false;
     }
 
@@ -134,7 +160,7 @@ fn parse_field(json: &serde_json::Value) -> Result<FieldMappingBuilder, FieldMap
                 mapping_builder.is_analyzed = true;
 
                 // Not valid for non-string fields
-                if mapping_builder.field_type != FieldType::String {
+                if !mapping_builder.field_type.is_text() {
                     return Err(FieldMappingParseError::IndexAnalyzedOnlyAllowedOnStringType);
                 }
             }
@@ -154,7 +180,7 @@ fn parse_field(json: &serde_json::Value) -> Result<FieldMappingBuilder, FieldMap
         let analyzer_str = analyzer_json.as_str().ok_or(FieldMappingParseError::ExpectedString)?;
         mapping_builder.base_analyzer = Some(analyzer_str.to_string());
 
-        if mapping_builder.field_type != FieldType::String {
+        if !mapping_builder.field_type.is_text() {
             return Err(FieldMappingParseError::AnalyzersOnlyAllowedOnStringType);
         }
 
@@ -167,7 +193,7 @@ fn parse_field(json: &serde_json::Value) -> Result<FieldMappingBuilder, FieldMap
         let index_analyzer_str = index_analyzer_json.as_str().ok_or(FieldMappingParseError::ExpectedString)?;
         mapping_builder.index_analyzer = Some(index_analyzer_str.to_string());
 
-        if mapping_builder.field_type != FieldType::String {
+        if !mapping_builder.field_type.is_text() {
             return Err(FieldMappingParseError::AnalyzersOnlyAllowedOnStringType);
         }
 
@@ -180,7 +206,7 @@ fn parse_field(json: &serde_json::Value) -> Result<FieldMappingBuilder, FieldMap
         let search_analyzer_str = search_analyzer_json.as_str().ok_or(FieldMappingParseError::ExpectedString)?;
         mapping_builder.search_analyzer = Some(search_analyzer_str.to_string());
 
-        if mapping_builder.field_type != FieldType::String {
+        if !mapping_builder.field_type.is_text() {
             return Err(FieldMappingParseError::AnalyzersOnlyAllowedOnStringType);
         }
 
@@ -209,10 +235,92 @@ fn parse_field(json: &serde_json::Value) -> Result<FieldMappingBuilder, FieldMap
         mapping_builder.is_in_all = include_in_all;
     }
 
+    // "relations" setting
+    // Maps each parent relation to either one child relation or an array of them
+    if let Some(relations_json) = field_object.get("relations") {
+        if mapping_builder.field_type != FieldType::Join {
+            return Err(FieldMappingParseError::RelationsOnlyAllowedOnJoinType);
+        }
+
+        let relations_object = relations_json.as_object().ok_or(FieldMappingParseError::ExpectedObject)?;
+        for (parent, children_json) in relations_object.iter() {
+            let children = match *children_json {
+                serde_json::Value::String(ref child) => vec![child.clone()],
+                serde_json::Value::Array(ref array) => {
+                    let mut children = Vec::new();
+                    for child_json in array.iter() {
+                        children.push(child_json.as_str().ok_or(FieldMappingParseError::ExpectedString)?.to_string());
+                    }
+
+                    children
+                }
+                _ => return Err(FieldMappingParseError::ExpectedString),
+            };
+
+            mapping_builder.relations.insert(parent.clone(), children);
+        }
+    } else if mapping_builder.field_type == FieldType::Join {
+        return Err(FieldMappingParseError::ExpectedKey("relations".to_string()));
+    }
+
+    // "contexts" setting
+    if let Some(contexts_json) = field_object.get("contexts") {
+        if mapping_builder.field_type != FieldType::Completion {
+            return Err(FieldMappingParseError::ContextsOnlyAllowedOnCompletionType);
+        }
+
+        let contexts_array = contexts_json.as_array().ok_or(FieldMappingParseError::ExpectedArray)?;
+        for context_json in contexts_array.iter() {
+            let context = CompletionContext::from_json(context_json).ok_or(FieldMappingParseError::InvalidContext)?;
+            if mapping_builder.contexts.iter().any(|existing| existing.name == context.name) {
+                return Err(FieldMappingParseError::InvalidContext);
+            }
+
+            mapping_builder.contexts.push(context);
+        }
+    }
+
     Ok(mapping_builder)
 }
 
 
+/// Adds the subfields of a search_as_you_type field
+///
+/// These are indexed from the same value. "_2gram" and "_3gram" hold shingles of two
+/// and three words and "_index_prefix" holds the prefixes of each word, so the last
+/// word being typed can be looked up with a term query.
+fn add_search_as_you_type_subfields(prop_name: &str, field: &mut FieldMappingBuilder, properties: &mut HashMap<String, MappingPropertyBuilder>) {
+    let subfields = vec![
+        ("_2gram", Some(2), false),
+        ("_3gram", Some(3), false),
+        ("_index_prefix", None, true),
+    ];
+
+    for (suffix, shingle_size, index_prefixes) in subfields {
+        let subfield_name = format!("{}.{}", prop_name, suffix);
+
+        properties.insert(subfield_name.clone(), MappingPropertyBuilder::Field(FieldMappingBuilder {
+            field_type: FieldType::SearchAsYouType,
+            is_indexed: field.is_indexed,
+            is_analyzed: field.is_analyzed,
+            is_stored: false,
+            is_in_all: false,
+            boost: field.boost,
+            base_analyzer: field.base_analyzer.clone(),
+            index_analyzer: field.index_analyzer.clone(),
+            search_analyzer: field.search_analyzer.clone(),
+            shingle_size: shingle_size,
+            index_prefixes: index_prefixes,
+            subfields: Vec::new(),
+            relations: HashMap::new(),
+            contexts: Vec::new(),
+        }));
+
+        field.subfields.push(subfield_name);
+    }
+}
+
+
 fn parse_nested_mapping(json: &serde_json::Value) -> Result<NestedMappingBuilder, MappingParseError> {
     let mapping_object = json.as_object().ok_or(MappingParseError::ExpectedObject)?;
 
@@ -249,7 +357,11 @@ fn parse_nested_mapping(json: &serde_json::Value) -> Result<NestedMappingBuilder
         } else {
             // Property is a field (or maybe invalid, which is handled by parse_field)
             match parse_field(prop_json) {
-                Ok(field) => {
+                Ok(mut field) => {
+                    if field.field_type == FieldType::SearchAsYouType {
+                        add_search_as_you_type_subfields(prop_name, &mut field, &mut properties);
+                    }
+
                     properties.insert(prop_name.to_string(), MappingPropertyBuilder::Field(field));
                 }
                 Err(e) => {
@@ -300,7 +412,11 @@ pub fn parse(json: &serde_json::Value) -> Result<MappingBuilder, MappingParseErr
         } else {
             // Property is a field (or maybe invalid, which is handled by parse_field)
             match parse_field(prop_json) {
-                Ok(field) => {
+                Ok(mut field) => {
+                    if field.field_type == FieldType::SearchAsYouType {
+                        add_search_as_you_type_subfields(prop_name, &mut field, &mut properties);
+                    }
+
                     properties.insert(prop_name.to_string(), MappingPropertyBuilder::Field(field));
                 }
                 Err(e) => {
@@ -310,6 +426,18 @@ pub fn parse(json: &serde_json::Value) -> Result<MappingBuilder, MappingParseErr
         }
     }
 
+    // Documents can only have one relation, so there can only be one join field
+    let join_fields = properties.values().filter(|property| {
+        match **property {
+            MappingPropertyBuilder::Field(ref field) => field.field_type == FieldType::Join,
+            _ => false,
+        }
+    }).count();
+
+    if join_fields > 1 {
+        return Err(MappingParseError::MultipleJoinFields);
+    }
+
     Ok(MappingBuilder {
         properties: properties,
     })
@@ -319,6 +447,7 @@ pub fn parse(json: &serde_json::Value) -> Result<MappingBuilder, MappingParseErr
 #[cfg(test)]
 mod tests {
     use mapping::FieldType;
+    use mapping::completion::{CompletionContext, CompletionContextType};
     use mapping::build::{FieldMappingBuilder, NestedMappingBuilder, MappingPropertyBuilder, MappingBuilder};
 
     use super::{MappingParseError, FieldMappingParseError, parse, parse_field};
@@ -347,6 +476,165 @@ mod tests {
         }));
     }
 
+    #[test]
+    fn test_parse_search_as_you_type() {
+        let mapping = parse(&json!(
+            {
+                "properties": {
+                    "title": {
+                        "type": "search_as_you_type"
+                    }
+                }
+            }
+        ));
+
+        let subfield = || FieldMappingBuilder {
+            field_type: FieldType::SearchAsYouType,
+            is_in_all: false,
+            ..FieldMappingBuilder::default()
+        };
+
+        assert_eq!(mapping, Ok(MappingBuilder {
+            properties: hashmap! {
+                "title".to_string() => MappingPropertyBuilder::Field(
+                    FieldMappingBuilder {
+                        field_type: FieldType::SearchAsYouType,
+                        subfields: vec![
+                            "title._2gram".to_string(),
+                            "title._3gram".to_string(),
+                            "title._index_prefix".to_string(),
+                        ],
+                        ..FieldMappingBuilder::default()
+                    }
+                ),
+                "title._2gram".to_string() => MappingPropertyBuilder::Field(
+                    FieldMappingBuilder {
+                        shingle_size: Some(2),
+                        ..subfield()
+                    }
+                ),
+                "title._3gram".to_string() => MappingPropertyBuilder::Field(
+                    FieldMappingBuilder {
+                        shingle_size: Some(3),
+                        ..subfield()
+                    }
+                ),
+                "title._index_prefix".to_string() => MappingPropertyBuilder::Field(
+                    FieldMappingBuilder {
+                        index_prefixes: true,
+                        ..subfield()
+                    }
+                )
+            }
+        }));
+    }
+
+    #[test]
+    fn test_parse_join() {
+        let mapping = parse_field(&json!(
+            {
+                "type": "join",
+                "relations": {
+                    "question": ["answer", "comment"],
+                    "answer": "vote"
+                }
+            }
+        ));
+
+        assert_eq!(mapping, Ok(FieldMappingBuilder {
+            field_type: FieldType::Join,
+            is_analyzed: false,
+            relations: hashmap! {
+                "question".to_string() => vec!["answer".to_string(), "comment".to_string()],
+                "answer".to_string() => vec!["vote".to_string()],
+            },
+            ..FieldMappingBuilder::default()
+        }));
+
+        // Relations are required
+        let mapping = parse_field(&json!(
+            {
+                "type": "join"
+            }
+        ));
+
+        assert_eq!(mapping, Err(FieldMappingParseError::ExpectedKey("relations".to_string())));
+    }
+
+    #[test]
+    fn test_parse_completion_contexts() {
+        let mapping = parse_field(&json!(
+            {
+                "type": "completion",
+                "contexts": [
+                    {"name": "tenant", "type": "category"},
+                    {"name": "location", "type": "geo", "precision": 4}
+                ]
+            }
+        ));
+
+        assert_eq!(mapping, Ok(FieldMappingBuilder {
+            field_type: FieldType::Completion,
+            is_analyzed: false,
+            contexts: vec![
+                CompletionContext {
+                    name: "tenant".to_string(),
+                    context_type: CompletionContextType::Category,
+                },
+                CompletionContext {
+                    name: "location".to_string(),
+                    context_type: CompletionContextType::Geo {
+                        precision: 4,
+                    },
+                },
+            ],
+            ..FieldMappingBuilder::default()
+        }));
+
+        // Context names must be unique
+        let mapping = parse_field(&json!(
+            {
+                "type": "completion",
+                "contexts": [
+                    {"name": "tenant", "type": "category"},
+                    {"name": "tenant", "type": "geo"}
+                ]
+            }
+        ));
+
+        assert_eq!(mapping, Err(FieldMappingParseError::InvalidContext));
+
+        // Contexts are only allowed on completion fields
+        let mapping = parse_field(&json!(
+            {
+                "type": "string",
+                "contexts": [{"name": "tenant", "type": "category"}]
+            }
+        ));
+
+        assert_eq!(mapping, Err(FieldMappingParseError::ContextsOnlyAllowedOnCompletionType));
+    }
+
+    #[test]
+    fn test_parse_multiple_join_fields() {
+        let mapping = parse(&json!(
+            {
+                "properties": {
+                    "first": {
+                        "type": "join",
+                        "relations": {"question": "answer"}
+                    },
+                    "second": {
+                        "type": "join",
+                        "relations": {"question": "answer"}
+                    }
+                }
+            }
+        ));
+
+        assert_eq!(mapping, Err(MappingParseError::MultipleJoinFields));
+    }
+
     #[test]
     fn test_parse_nested() {
         let mapping = parse(&json!(
@@ -575,6 +863,58 @@ mod tests {
             is_analyzed: false,
             ..FieldMappingBuilder::default()
         }));
+
+        // Wildcard
+        let mapping = parse_field(&json!(
+            {
+                "type": "wildcard"
+            }
+        ));
+
+        assert_eq!(mapping, Ok(FieldMappingBuilder {
+            field_type: FieldType::Wildcard,
+            is_analyzed: false,
+            ..FieldMappingBuilder::default()
+        }));
+
+        // Flattened
+        let mapping = parse_field(&json!(
+            {
+                "type": "flattened"
+            }
+        ));
+
+        assert_eq!(mapping, Ok(FieldMappingBuilder {
+            field_type: FieldType::Flattened,
+            is_analyzed: false,
+            ..FieldMappingBuilder::default()
+        }));
+
+        // Percolator
+        let mapping = parse_field(&json!(
+            {
+                "type": "percolator"
+            }
+        ));
+
+        assert_eq!(mapping, Ok(FieldMappingBuilder {
+            field_type: FieldType::Percolator,
+            is_analyzed: false,
+            ..FieldMappingBuilder::default()
+        }));
+
+        // Shape
+        let mapping = parse_field(&json!(
+            {
+                "type": "shape"
+            }
+        ));
+
+        assert_eq!(mapping, Ok(FieldMappingBuilder {
+            field_type: FieldType::Shape,
+            is_analyzed: false,
+            ..FieldMappingBuilder::default()
+        }));
     }
 
     #[test]
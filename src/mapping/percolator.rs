@@ -0,0 +1,295 @@
+//! Percolator fields
+//!
+//! Percolator fields store queries rather than values. These are checked when
+//! the document is indexed so the percolate API doesn't have to deal with
+//! queries that can't be run against the index.
+
+use serde_json::Value as Json;
+
+use search::query::script::script_fields;
+use script::parse_script;
+use query_parser::parse as parse_query;
+use mapping::{Mapping, MappingProperty, FieldType, FieldValueError};
+
+
+/// Query types that take an object with a single key naming the field
+const SINGLE_FIELD_QUERY_TYPES: &'static [&'static str] = &["match", "match_phrase", "match_phrase_prefix", "term", "terms", "in", "prefix", "wildcard", "fuzzy", "range", "intervals", "span_term"];
+
+
+/// Collects the names of the fields that a query searches, including those
+/// of any queries nested inside it
+fn collect_query_fields(json: &Json, fields: &mut Vec<String>) {
+    let object = match *json {
+        Json::Object(ref object) => object,
+        Json::Array(ref array) => {
+            for query in array.iter() {
+                collect_query_fields(query, fields);
+            }
+
+            return;
+        }
+        _ => return,
+    };
+
+    for (query_type, inner) in object.iter() {
+        if SINGLE_FIELD_QUERY_TYPES.contains(&query_type.as_ref()) {
+            if let Some(inner_object) = inner.as_object() {
+                fields.extend(inner_object.keys().cloned());
+            }
+
+            continue;
+        }
+
+        match query_type.as_ref() {
+            "multi_match" => {
+                if let Some(field_names) = inner.get("fields").and_then(|field_names| field_names.as_array()) {
+                    for field_name in field_names.iter().filter_map(|field_name| field_name.as_str()) {
+                        // Remove the boost
+                        let field_name = field_name.split('^').next().unwrap();
+                        fields.push(field_name.to_string());
+                    }
+                }
+            }
+            "geo_shape" | "shape" => {
+                if let Some(inner_object) = inner.as_object() {
+                    fields.extend(inner_object.keys().filter(|key| *key != "boost").cloned());
+                }
+            }
+            "geo_distance" => {
+                if let Some(inner_object) = inner.as_object() {
+                    fields.extend(inner_object.keys().filter(|key| !["distance", "distance_type", "boost"].contains(&key.as_ref())).cloned());
+                }
+            }
+            "rank_feature" | "exists" | "missing" | "distance_feature" | "percolate" => {
+                if let Some(field_name) = inner.get("field").and_then(|field_name| field_name.as_str()) {
+                    fields.push(field_name.to_string());
+                }
+            }
+            "script" => {
+                if let Some(script) = inner.get("script").and_then(|script| parse_script(script).ok()) {
+                    fields.extend(script_fields(&script));
+                }
+            }
+            "and" | "or" | "not" => collect_query_fields(inner, fields),
+            "span_near" | "span_or" | "span_first" => {
+                for key in ["clauses", "match"].iter() {
+                    if let Some(clause) = inner.get(key) {
+                        collect_query_fields(clause, fields);
+                    }
+                }
+            }
+            "filtered" | "constant_score" | "bool" | "function_score" | "boosting" | "nested" | "pinned" => {
+                if query_type == "function_score" {
+                    collect_score_function_fields(inner, fields);
+                }
+
+                if let Some(inner_object) = inner.as_object() {
+                    for (key, clause) in inner_object.iter() {
+                        match key.as_ref() {
+                            "query" | "filter" | "must" | "should" | "must_not" | "positive" | "negative" | "organic" => collect_query_fields(clause, fields),
+                            "path" => fields.extend(clause.as_str().map(|path| path.to_string())),
+                            "functions" => {
+                                for function in clause.as_array().into_iter().flat_map(|functions| functions.iter()) {
+                                    if let Some(filter) = function.get("filter") {
+                                        collect_query_fields(filter, fields);
+                                    }
+
+                                    collect_score_function_fields(function, fields);
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+
+/// Collects the names of the fields that the score function of a function
+/// score query reads
+fn collect_score_function_fields(function: &Json, fields: &mut Vec<String>) {
+    if let Some(field_name) = function.pointer("/field_value_factor/field").and_then(|field_name| field_name.as_str()) {
+        fields.push(field_name.to_string());
+    }
+
+    for decay_function in ["gauss", "linear", "exp"].iter() {
+        if let Some(decay_object) = function.get(decay_function).and_then(|decay_object| decay_object.as_object()) {
+            fields.extend(decay_object.keys().cloned());
+        }
+    }
+}
+
+
+/// Checks if a field can be searched. Keys inside flattened fields are
+/// referenced as "field.key"
+fn field_exists(mapping: &Mapping, field_name: &str) -> bool {
+    if mapping.get_property(field_name).is_some() {
+        return true;
+    }
+
+    let mut split = field_name.splitn(2, '.');
+    match (split.next(), split.next()) {
+        (Some(parent_name), Some(_)) => {
+            match mapping.properties.get(parent_name) {
+                Some(&MappingProperty::Field(ref field_mapping)) => field_mapping.data_type == FieldType::Flattened,
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+
+impl Mapping {
+    /// Checks that a query can be stored in a percolator field of this mapping
+    pub fn validate_percolator_query(&self, query: &Json) -> Result<(), FieldValueError> {
+        if parse_query(query).is_err() {
+            return Err(FieldValueError);
+        }
+
+        let mut fields = Vec::new();
+        collect_query_fields(query, &mut fields);
+
+        if fields.iter().all(|field_name| field_exists(self, field_name)) {
+            Ok(())
+        } else {
+            Err(FieldValueError)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use mapping::{Mapping, MappingProperty, FieldMapping, NestedMapping, FieldType};
+
+    fn make_mapping() -> Mapping {
+        let mut mapping = Mapping {
+            properties: HashMap::new(),
+        };
+        mapping.properties.insert("title".to_string(), MappingProperty::Field(FieldMapping::default()));
+        mapping.properties.insert("labels".to_string(), MappingProperty::Field(FieldMapping {
+            data_type: FieldType::Flattened,
+            ..FieldMapping::default()
+        }));
+
+        let mut comments_mapping = NestedMapping {
+            index_ref: None,
+            properties: HashMap::new(),
+        };
+        comments_mapping.properties.insert("author".to_string(), MappingProperty::Field(FieldMapping::default()));
+        mapping.properties.insert("comments".to_string(), MappingProperty::NestedMapping(Box::new(comments_mapping)));
+
+        mapping
+    }
+
+    #[test]
+    fn test_validate_percolator_query() {
+        let mapping = make_mapping();
+
+        assert!(mapping.validate_percolator_query(&json!({
+            "bool": {
+                "must": {
+                    "match": {
+                        "title": "hello"
+                    }
+                },
+                "filter": [
+                    {
+                        "term": {
+                            "labels.env": "production"
+                        }
+                    }
+                ]
+            }
+        })).is_ok());
+    }
+
+    #[test]
+    fn test_validate_percolator_query_with_missing_field() {
+        let mapping = make_mapping();
+
+        assert!(mapping.validate_percolator_query(&json!({
+            "multi_match": {
+                "query": "hello",
+                "fields": ["title^2", "body"]
+            }
+        })).is_err());
+    }
+
+    #[test]
+    fn test_validate_percolator_query_with_function_score() {
+        let mapping = make_mapping();
+
+        assert!(mapping.validate_percolator_query(&json!({
+            "function_score": {
+                "query": {"match": {"title": "hello"}},
+                "functions": [{"filter": {"term": {"body": "foo"}}, "weight": 2}]
+            }
+        })).is_err());
+
+        assert!(mapping.validate_percolator_query(&json!({
+            "function_score": {
+                "query": {"match": {"title": "hello"}},
+                "gauss": {"published": {"origin": "now", "scale": "10d"}}
+            }
+        })).is_err());
+    }
+
+    #[test]
+    fn test_validate_percolator_query_with_span_query() {
+        let mapping = make_mapping();
+
+        assert!(mapping.validate_percolator_query(&json!({
+            "span_near": {
+                "clauses": [{"span_term": {"title": "hello"}}, {"span_first": {"match": {"span_term": {"title": "world"}}, "end": 3}}]
+            }
+        })).is_ok());
+
+        assert!(mapping.validate_percolator_query(&json!({
+            "span_or": {
+                "clauses": [{"span_term": {"title": "hello"}}, {"span_term": {"body": "world"}}]
+            }
+        })).is_err());
+    }
+
+    #[test]
+    fn test_validate_percolator_query_with_nested_query() {
+        let mapping = make_mapping();
+
+        assert!(mapping.validate_percolator_query(&json!({
+            "nested": {
+                "path": "comments",
+                "query": {"term": {"comments.author": "alice"}}
+            }
+        })).is_ok());
+
+        assert!(mapping.validate_percolator_query(&json!({
+            "nested": {
+                "path": "replies",
+                "query": {"match_all": {}}
+            }
+        })).is_err());
+
+        assert!(mapping.validate_percolator_query(&json!({
+            "nested": {
+                "path": "comments",
+                "query": {"term": {"comments.body": "hello"}}
+            }
+        })).is_err());
+    }
+
+    #[test]
+    fn test_validate_percolator_query_with_invalid_query() {
+        let mapping = make_mapping();
+
+        assert!(mapping.validate_percolator_query(&json!({
+            "foo": {}
+        })).is_err());
+    }
+}
@@ -4,7 +4,7 @@ use serde_json::Value as Json;
 use search::Query;
 use search::schema::Schema;
 
-use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, parse as parse_query};
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, parse_inner};
 
 
 #[derive(Debug)]
@@ -14,6 +14,14 @@ struct AndQueryBuilder {
 
 
 impl QueryBuilder for AndQueryBuilder {
+    fn check(&self, context: &QueryBuildContext) -> Result<(), QueryParseError> {
+        for query in self.queries.iter() {
+            query.check(context)?;
+        }
+
+        Ok(())
+    }
+
     fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
         let mut queries = Vec::new();
 
@@ -27,11 +35,23 @@ impl QueryBuilder for AndQueryBuilder {
 
 
 pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
-    let filters = json.as_array().ok_or(QueryParseError::ExpectedArray)?;
+    // The filters can be given directly, or in the "filters" key of an object
+    let (filters, path) = match *json {
+        Json::Array(ref filters) => (filters, ""),
+        Json::Object(ref object) => {
+            if let Some(key) = object.keys().find(|key| *key != "filters") {
+                return Err(QueryParseError::UnrecognisedKey(key.clone()));
+            }
+
+            let filters = object.get("filters").ok_or(QueryParseError::ExpectedKey("filters"))?;
+            (filters.as_array().ok_or(QueryParseError::ExpectedArray)?, "filters")
+        }
+        _ => return Err(QueryParseError::ExpectedArray),
+    };
 
     let mut queries = Vec::new();
-    for filter in filters.iter() {
-        queries.push(parse_query(filter)?);
+    for (i, filter) in filters.iter().enumerate() {
+        queries.push(parse_inner(filter, &format!("{}[{}]", path, i))?);
     }
 
     Ok(Box::new(AndQueryBuilder {
@@ -96,14 +116,14 @@ mod tests {
 
         assert_eq!(query.err(), Some(QueryParseError::ExpectedArray));
 
-        // Object
+        // Object without filters
         let query = parse(&serde_json::from_str("
         {
             \"foo\": \"bar\"
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedArray));
+        assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("foo".to_string())));
 
         // Integer
         let query = parse(&serde_json::from_str("
@@ -119,4 +139,38 @@ mod tests {
 
         assert_eq!(query.err(), Some(QueryParseError::ExpectedArray));
     }
+
+    #[test]
+    fn test_and_query_filters_object() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "filters": [
+                {"term": {"test": "foo"}}
+            ]
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Conjunction {
+            queries: vec![
+                Query::Term {
+                    field: test_field,
+                    term: Term::from_string("foo"),
+                    scorer: TermScorer::default(),
+                },
+            ],
+        }))
+    }
+
+    #[test]
+    fn test_gives_error_for_unrecognised_key() {
+        let query = parse(&json!({
+            "filters": [
+                {"term": {"test": "foo"}}
+            ],
+            "foo": "bar"
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("foo".to_string())));
+    }
 }
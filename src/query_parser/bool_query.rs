@@ -0,0 +1,434 @@
+//! Parses "bool" queries
+
+use serde_json::Value as Json;
+use search::Query;
+use search::schema::Schema;
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, parse_inner};
+use query_parser::utils::{parse_float, MinimumShouldMatch, parse_minimum_should_match};
+
+
+#[derive(Debug)]
+struct BoolQueryBuilder {
+    must: Vec<Box<QueryBuilder>>,
+    should: Vec<Box<QueryBuilder>>,
+    must_not: Vec<Box<QueryBuilder>>,
+    filter: Vec<Box<QueryBuilder>>,
+    minimum_should_match: Option<MinimumShouldMatch>,
+    boost: f32,
+}
+
+
+fn build_conjunction(mut queries: Vec<Query>) -> Query {
+    match queries.len() {
+        1 => queries.pop().unwrap(),
+        _ => Query::Conjunction { queries: queries },
+    }
+}
+
+
+fn build_disjunction(mut queries: Vec<Query>) -> Query {
+    match queries.len() {
+        1 => queries.pop().unwrap(),
+        _ => Query::Disjunction { queries: queries },
+    }
+}
+
+
+impl QueryBuilder for BoolQueryBuilder {
+    fn check(&self, context: &QueryBuildContext) -> Result<(), QueryParseError> {
+        for query in self.must.iter().chain(self.should.iter()).chain(self.must_not.iter()).chain(self.filter.iter()) {
+            query.check(context)?;
+        }
+
+        Ok(())
+    }
+
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+        let no_score_context = context.clone().no_score();
+        let must = self.must.iter().map(|query| query.build(context, schema)).collect::<Vec<_>>();
+        let should = self.should.iter().map(|query| query.build(context, schema)).collect::<Vec<_>>();
+        let must_not = self.must_not.iter().map(|query| query.build(&no_score_context, schema)).collect::<Vec<_>>();
+        let filter = self.filter.iter().map(|query| query.build(&no_score_context, schema)).collect::<Vec<_>>();
+
+        // "should" clauses are only required if there are no "must" or "filter" clauses,
+        // otherwise they just add to the score of the documents they match. Setting
+        // "minimum_should_match" makes that many of them required either way
+        let minimum_should_match = self.minimum_should_match.as_ref().map_or(0, |minimum_should_match| minimum_should_match.resolve(should.len()));
+
+        let query = match (must.is_empty(), should.is_empty()) {
+            (true, false) if minimum_should_match > 0 => Query::minimum_should_match(should, minimum_should_match),
+            (false, false) if minimum_should_match > 0 => {
+                let mut queries = vec![build_conjunction(must.clone())];
+                queries.extend(should.clone());
+
+                let mut required = must;
+                required.push(Query::minimum_should_match(should, minimum_should_match));
+                Query::Disjunction { queries: queries }.filter(Query::Conjunction { queries: required })
+            }
+            (true, true) => {
+                if filter.is_empty() {
+                    Query::all()
+                } else {
+                    // Only filters, these don't affect the score
+                    Query::All { score: 0.0f32 }
+                }
+            }
+            (false, true) => build_conjunction(must),
+            (true, false) => {
+                if filter.is_empty() {
+                    build_disjunction(should)
+                } else {
+                    // Matches everything, the filter decides which documents remain
+                    let mut queries = vec![Query::All { score: 0.0f32 }];
+                    queries.extend(should);
+                    Query::Disjunction { queries: queries }
+                }
+            }
+            (false, false) => {
+                // Score documents that match the "must" clauses along with any "should"
+                // clauses they match, but only keep those that match every "must" clause
+                let mut queries = vec![build_conjunction(must.clone())];
+                queries.extend(should);
+                Query::Disjunction { queries: queries }.filter(build_conjunction(must))
+            }
+        };
+
+        let query = if filter.is_empty() {
+            query
+        } else {
+            query.filter(build_conjunction(filter))
+        };
+
+        let query = if must_not.is_empty() {
+            query
+        } else {
+            query.exclude(build_disjunction(must_not))
+        };
+
+        // Add boost
+        query.boost(self.boost)
+    }
+}
+
+
+/// Parses a clause, which can be either a single query or an array of queries
+fn parse_clause(json: &Json, key: &str) -> Result<Vec<Box<QueryBuilder>>, QueryParseError> {
+    match *json {
+        Json::Array(ref array) => {
+            let mut queries = Vec::new();
+            for (i, query) in array.iter().enumerate() {
+                queries.push(parse_inner(query, &format!("{}[{}]", key, i))?);
+            }
+
+            Ok(queries)
+        }
+        Json::Object(_) => Ok(vec![parse_inner(json, key)?]),
+        _ => Err(QueryParseError::ExpectedObjectOrArray),
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut must = Vec::new();
+    let mut should = Vec::new();
+    let mut must_not = Vec::new();
+    let mut filter = Vec::new();
+    let mut minimum_should_match = None;
+    let mut boost = 1.0f32;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "must" => {
+                must = parse_clause(value, key)?;
+            }
+            "should" => {
+                should = parse_clause(value, key)?;
+            }
+            "must_not" => {
+                must_not = parse_clause(value, key)?;
+            }
+            "filter" => {
+                filter = parse_clause(value, key)?;
+            }
+            "minimum_should_match" => {
+                minimum_should_match = Some(parse_minimum_should_match(value)?);
+            }
+            "boost" => {
+                boost = parse_float(value)?;
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(Box::new(BoolQueryBuilder {
+        must: must,
+        should: should,
+        must_not: must_not,
+        filter: filter,
+        minimum_should_match: minimum_should_match,
+        boost: boost,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use search::{Term, Query, TermScorer};
+    use search::schema::{Schema, FieldType, FIELD_INDEXED};
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    #[test]
+    fn test_bool_query() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"must\": {
+                \"term\": {
+                    \"test\":  \"foo\"
+                }
+            },
+            \"should\": [
+                {
+                    \"term\": {
+                        \"test\":  \"bar\"
+                    }
+                }
+            ],
+            \"must_not\": {
+                \"term\": {
+                    \"test\":  \"baz\"
+                }
+            },
+            \"filter\": {
+                \"term\": {
+                    \"test\":  \"quux\"
+                }
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        let term = |value| Query::Term {
+            field: test_field,
+            term: Term::from_string(value),
+            scorer: TermScorer::default(),
+        };
+
+        assert_eq!(query, Ok(Query::Exclude {
+            query: Box::new(Query::Filter {
+                query: Box::new(Query::Filter {
+                    query: Box::new(Query::Disjunction {
+                        queries: vec![term("foo"), term("bar")],
+                    }),
+                    filter: Box::new(term("foo")),
+                }),
+                filter: Box::new(term("quux")),
+            }),
+            exclude: Box::new(term("baz")),
+        }));
+    }
+
+    #[test]
+    fn test_cacheable_filters() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "filter": [
+                {"term": {"test": "foo"}},
+                {"ids": {"values": ["1", "2"]}}
+            ],
+            "must_not": {"term": {"test": "bar"}}
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new().no_score(), &schema))).unwrap();
+
+        let term = |value| Query::Term {
+            field: test_field,
+            term: Term::from_string(value),
+            scorer: TermScorer::default(),
+        };
+
+        // Ids queries depend on the current version of each document, so can't be cached
+        assert!(!query.is_cacheable());
+        assert_eq!(query.cacheable_filters(), vec![&term("foo"), &term("bar")]);
+    }
+
+    #[test]
+    fn test_should_only() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"should\": [
+                {
+                    \"term\": {
+                        \"test\":  \"foo\"
+                    }
+                },
+                {
+                    \"term\": {
+                        \"test\":  \"bar\"
+                    }
+                }
+            ]
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Disjunction {
+            queries: vec![
+                Query::Term {
+                    field: test_field,
+                    term: Term::from_string("foo"),
+                    scorer: TermScorer::default(),
+                },
+                Query::Term {
+                    field: test_field,
+                    term: Term::from_string("bar"),
+                    scorer: TermScorer::default(),
+                }
+            ],
+        }));
+    }
+
+    #[test]
+    fn test_minimum_should_match() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "should": [
+                {"term": {"test": "foo"}},
+                {"term": {"test": "bar"}},
+                {"term": {"test": "baz"}}
+            ],
+            "minimum_should_match": "-1"
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        let term = |value| Query::Term {
+            field: test_field,
+            term: Term::from_string(value),
+            scorer: TermScorer::default(),
+        };
+
+        assert_eq!(query, Ok(Query::MinimumShouldMatch {
+            queries: vec![term("foo"), term("bar"), term("baz")],
+            minimum: 2,
+        }));
+
+        // With "must" clauses, the "should" clauses become required rather than being scored on their own
+        let query = parse(&json!({
+            "must": {"term": {"test": "foo"}},
+            "should": [
+                {"term": {"test": "bar"}},
+                {"term": {"test": "baz"}}
+            ],
+            "minimum_should_match": 1
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Filter {
+            query: Box::new(Query::Disjunction {
+                queries: vec![term("foo"), term("bar"), term("baz")],
+            }),
+            filter: Box::new(Query::Conjunction {
+                queries: vec![
+                    term("foo"),
+                    Query::Disjunction {
+                        queries: vec![term("bar"), term("baz")],
+                    },
+                ],
+            }),
+        }));
+    }
+
+    #[test]
+    fn test_filter_only() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"filter\": {
+                \"term\": {
+                    \"test\":  \"foo\"
+                }
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Filter {
+            query: Box::new(Query::All { score: 0.0f32 }),
+            filter: Box::new(Query::Term {
+                field: test_field,
+                term: Term::from_string("foo"),
+                scorer: TermScorer::default(),
+            }),
+        }));
+    }
+
+    #[test]
+    fn test_empty() {
+        let schema = Schema::new();
+
+        let query = parse(&serde_json::from_str("
+        {
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::all()));
+    }
+
+    #[test]
+    fn test_with_boost() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"must\": {
+                \"term\": {
+                    \"test\":  \"foo\"
+                }
+            },
+            \"boost\": 2.0
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Term {
+            field: test_field,
+            term: Term::from_string("foo"),
+            scorer: TermScorer::default_with_boost(2.0f32),
+        }));
+    }
+
+    #[test]
+    fn test_gives_error_for_incorrect_clause_type() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"must\": \"foo\"
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedObjectOrArray));
+    }
+
+    #[test]
+    fn test_gives_error_for_extra_key() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"must\": [],
+            \"hello\": \"world\"
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("hello".to_string())));
+    }
+}
@@ -0,0 +1,148 @@
+//! Parses "boosting" queries
+
+use std::f32;
+
+use serde_json::Value as Json;
+use search::Query;
+use search::schema::Schema;
+use search::query::function_score::{ScoreFunction, FilteredScoreFunction, ScoreMode, BoostMode};
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, parse_inner};
+use query_parser::utils::parse_float;
+
+
+#[derive(Debug)]
+struct BoostingQueryBuilder {
+    positive: Box<QueryBuilder>,
+    negative: Box<QueryBuilder>,
+    negative_boost: f32,
+    boost: f32,
+}
+
+
+impl QueryBuilder for BoostingQueryBuilder {
+    fn check(&self, context: &QueryBuildContext) -> Result<(), QueryParseError> {
+        self.positive.check(context)?;
+        self.negative.check(context)
+    }
+
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+        // Documents that match the negative query have their score multiplied
+        // by the negative boost, the same as a weight function with a filter
+        Query::FunctionScore {
+            query: Box::new(self.positive.build(context, schema)),
+            functions: vec![
+                FilteredScoreFunction {
+                    filter: Some(self.negative.build(&context.clone().no_score(), schema)),
+                    function: ScoreFunction::Weight,
+                    weight: self.negative_boost,
+                },
+            ],
+            score_mode: ScoreMode::Multiply,
+            boost_mode: BoostMode::Multiply,
+            max_boost: f32::MAX,
+            boost: self.boost,
+        }
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    // Get configuration
+    let mut positive = None;
+    let mut negative = None;
+    let mut negative_boost = None;
+    let mut boost = 1.0f32;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "positive" => {
+                positive = Some(parse_inner(value, key)?);
+            }
+            "negative" => {
+                negative = Some(parse_inner(value, key)?);
+            }
+            "negative_boost" => {
+                let value = parse_float(value)?;
+
+                if value < 0.0 {
+                    return Err(QueryParseError::InvalidValue);
+                }
+
+                negative_boost = Some(value);
+            }
+            "boost" => {
+                boost = parse_float(value)?;
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(Box::new(BoostingQueryBuilder {
+        positive: positive.ok_or(QueryParseError::ExpectedKey("positive"))?,
+        negative: negative.ok_or(QueryParseError::ExpectedKey("negative"))?,
+        negative_boost: negative_boost.ok_or(QueryParseError::ExpectedKey("negative_boost"))?,
+        boost: boost,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::f32;
+
+    use search::{Term, Query};
+    use search::schema::{Schema, FieldType, FIELD_INDEXED};
+    use search::query::function_score::{ScoreFunction, FilteredScoreFunction, ScoreMode, BoostMode};
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    #[test]
+    fn test_boosting_query() {
+        let mut schema = Schema::new();
+        let text_field = schema.add_field("text".to_string(), FieldType::PlainString, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "positive": {"term": {"text": "apple"}},
+            "negative": {"term": {"text": "pie"}},
+            "negative_boost": 0.5
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::FunctionScore {
+            query: Box::new(Query::term(text_field, Term::from_string("apple"))),
+            functions: vec![
+                FilteredScoreFunction {
+                    filter: Some(Query::term(text_field, Term::from_string("pie"))),
+                    function: ScoreFunction::Weight,
+                    weight: 0.5f32,
+                },
+            ],
+            score_mode: ScoreMode::Multiply,
+            boost_mode: BoostMode::Multiply,
+            max_boost: f32::MAX,
+            boost: 1.0f32,
+        }));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_negative_boost() {
+        let query = parse(&json!({
+            "positive": {"match_all": {}},
+            "negative": {"match_all": {}}
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("negative_boost")));
+
+        let query = parse(&json!({
+            "positive": {"match_all": {}},
+            "negative": {"match_all": {}},
+            "negative_boost": -1
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+    }
+}
@@ -4,7 +4,7 @@ use serde_json::Value as Json;
 use search::Query;
 use search::schema::Schema;
 
-use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, parse as parse_query};
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, parse_inner};
 use query_parser::utils::parse_float;
 
 #[derive(Debug)]
@@ -14,6 +14,10 @@ struct ConstantScoreQueryBuilder {
 }
 
 impl QueryBuilder for ConstantScoreQueryBuilder {
+    fn check(&self, context: &QueryBuildContext) -> Result<(), QueryParseError> {
+        self.filter.check(context)
+    }
+
     fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
         Query::Filter {
             query: Box::new(Query::All{ score: self.score }),
@@ -26,7 +30,7 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
     let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
 
     let filter = match object.get("filter") {
-        Some(inner) => parse_query(inner)?,
+        Some(inner) => parse_inner(inner, "filter")?,
         None => return Err(QueryParseError::ExpectedKey("filter")),
     };
 
@@ -138,7 +142,7 @@ mod tests {
             "boost": 2.0
         })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
 
-       assert_eq!(query, Err(QueryParseError::ExpectedObject));
+       assert_eq!(query, Err(QueryParseError::InQuery("filter".to_string(), Box::new(QueryParseError::ExpectedObject))));
     }
 
     #[test]
@@ -0,0 +1,265 @@
+//! Date math expressions
+//!
+//! These give a date relative to now, or to another date. For example, "now-7d/d"
+//! is seven days ago rounded to the day and "2016-01-01||+1M" is a month after
+//! the first of January 2016.
+//!
+//! The date is followed by any number of operations:
+//!
+//!  - "+1d" adds to the date
+//!  - "-1d" subtracts from the date
+//!  - "/d" rounds the date to the unit
+//!
+//! The units are "y" (years), "M" (months), "w" (weeks), "d" (days), "h" or "H"
+//! (hours), "m" (minutes) and "s" (seconds).
+
+use chrono::{DateTime, Utc, NaiveDate, NaiveDateTime, Duration, Datelike, Timelike};
+
+
+/// Which way dates are rounded by "/" operations
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rounding {
+    /// To the start of the unit
+    Down,
+
+    /// To the last microsecond of the unit
+    Up,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Unit {
+    Year,
+    Month,
+    Week,
+    Day,
+    Hour,
+    Minute,
+    Second,
+}
+
+
+impl Unit {
+    fn from_char(c: char) -> Option<Unit> {
+        match c {
+            'y' => Some(Unit::Year),
+            'M' => Some(Unit::Month),
+            'w' => Some(Unit::Week),
+            'd' => Some(Unit::Day),
+            'h' | 'H' => Some(Unit::Hour),
+            'm' => Some(Unit::Minute),
+            's' => Some(Unit::Second),
+            _ => None,
+        }
+    }
+
+    /// The length of the unit in seconds. Years and months vary in length
+    fn seconds(&self) -> Option<i64> {
+        match *self {
+            Unit::Year | Unit::Month => None,
+            Unit::Week => Some(7 * 24 * 60 * 60),
+            Unit::Day => Some(24 * 60 * 60),
+            Unit::Hour => Some(60 * 60),
+            Unit::Minute => Some(60),
+            Unit::Second => Some(1),
+        }
+    }
+}
+
+
+/// Parses an RFC 3339 date or a "yyyy-mm-dd" date
+pub fn parse_date_string(string: &str) -> Option<DateTime<Utc>> {
+    match string.parse::<DateTime<Utc>>() {
+        Ok(date) => Some(date),
+        Err(_) => {
+            let date = NaiveDate::parse_from_str(string, "%Y-%m-%d").ok()?;
+            Some(DateTime::<Utc>::from_utc(date.and_hms(0, 0, 0), Utc))
+        }
+    }
+}
+
+
+/// Checks if the string is a date math expression, rather than just a date
+pub fn is_date_math(string: &str) -> bool {
+    string.starts_with("now") || string.contains("||")
+}
+
+
+/// Adds a number of months to the date. Days past the end of the new month are
+/// moved back to its last day
+fn add_months(date: NaiveDateTime, months: i64) -> Option<NaiveDateTime> {
+    let total_months = (date.year() as i64 * 12 + date.month0() as i64).checked_add(months)?;
+    let year = if total_months >= 0 { total_months / 12 } else { (total_months - 11) / 12 };
+    let month0 = total_months - year * 12;
+
+    if year < i32::min_value() as i64 || year > i32::max_value() as i64 {
+        return None;
+    }
+
+    let mut day = date.day();
+    loop {
+        if let Some(new_date) = NaiveDate::from_ymd_opt(year as i32, month0 as u32 + 1, day) {
+            return Some(new_date.and_time(date.time()));
+        }
+
+        if day <= 28 {
+            return None;
+        }
+
+        day -= 1;
+    }
+}
+
+
+fn add(date: NaiveDateTime, amount: i64, unit: Unit) -> Option<NaiveDateTime> {
+    match unit.seconds() {
+        Some(unit_seconds) => {
+            let seconds = amount.checked_mul(unit_seconds)?;
+
+            // Durations are stored in milliseconds
+            if seconds.abs() > i64::max_value() / 1000 {
+                return None;
+            }
+
+            date.checked_add_signed(Duration::seconds(seconds))
+        }
+        None => {
+            let months = match unit {
+                Unit::Year => amount.checked_mul(12)?,
+                _ => amount,
+            };
+
+            add_months(date, months)
+        }
+    }
+}
+
+
+fn round_down(date: NaiveDateTime, unit: Unit) -> NaiveDateTime {
+    match unit {
+        Unit::Year => NaiveDate::from_ymd(date.year(), 1, 1).and_hms(0, 0, 0),
+        Unit::Month => NaiveDate::from_ymd(date.year(), date.month(), 1).and_hms(0, 0, 0),
+        Unit::Week => {
+            // Weeks start on Monday
+            let days_since_monday = date.weekday().num_days_from_monday() as i64;
+            (date.date() - Duration::days(days_since_monday)).and_hms(0, 0, 0)
+        }
+        Unit::Day => date.date().and_hms(0, 0, 0),
+        Unit::Hour => date.date().and_hms(date.hour(), 0, 0),
+        Unit::Minute => date.date().and_hms(date.hour(), date.minute(), 0),
+        Unit::Second => date.date().and_hms(date.hour(), date.minute(), date.second()),
+    }
+}
+
+
+/// Works out the date of an expression. Returns None if the expression is invalid
+pub fn evaluate(expression: &str, now: DateTime<Utc>, rounding: Rounding) -> Option<DateTime<Utc>> {
+    let (mut date, operations) = if expression.starts_with("now") {
+        (now.naive_utc(), &expression[3..])
+    } else {
+        let position = expression.find("||")?;
+        (parse_date_string(&expression[..position])?.naive_utc(), &expression[position + 2..])
+    };
+
+    let mut chars = operations.chars().peekable();
+    while let Some(operator) = chars.next() {
+        match operator {
+            '+' | '-' => {
+                let mut amount = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !c.is_digit(10) {
+                        break;
+                    }
+
+                    amount.push(c);
+                    chars.next();
+                }
+
+                let amount = amount.parse::<i64>().ok()?;
+                let unit = chars.next().and_then(Unit::from_char)?;
+
+                date = add(date, if operator == '-' { -amount } else { amount }, unit)?;
+            }
+            '/' => {
+                let unit = chars.next().and_then(Unit::from_char)?;
+
+                date = match rounding {
+                    Rounding::Down => round_down(date, unit),
+                    Rounding::Up => add(round_down(date, unit), 1, unit)? - Duration::microseconds(1),
+                };
+            }
+            _ => return None,
+        }
+    }
+
+    Some(DateTime::from_utc(date, Utc))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Utc, TimeZone};
+
+    use super::{evaluate, is_date_math, Rounding};
+
+    fn now() -> DateTime<Utc> {
+        Utc.ymd(2017, 12, 6).and_hms(15, 30, 45)
+    }
+
+    #[test]
+    fn test_now() {
+        assert_eq!(evaluate("now", now(), Rounding::Down), Some(now()));
+    }
+
+    #[test]
+    fn test_add_and_subtract() {
+        assert_eq!(evaluate("now-7d", now(), Rounding::Down), Some(Utc.ymd(2017, 11, 29).and_hms(15, 30, 45)));
+        assert_eq!(evaluate("now+1h-30m", now(), Rounding::Down), Some(Utc.ymd(2017, 12, 6).and_hms(16, 0, 45)));
+        assert_eq!(evaluate("now+2w", now(), Rounding::Down), Some(Utc.ymd(2017, 12, 20).and_hms(15, 30, 45)));
+        assert_eq!(evaluate("now+1y", now(), Rounding::Down), Some(Utc.ymd(2018, 12, 6).and_hms(15, 30, 45)));
+    }
+
+    #[test]
+    fn test_add_months() {
+        assert_eq!(evaluate("2016-01-01||+1M", now(), Rounding::Down), Some(Utc.ymd(2016, 2, 1).and_hms(0, 0, 0)));
+        assert_eq!(evaluate("2016-11-15||+3M", now(), Rounding::Down), Some(Utc.ymd(2017, 2, 15).and_hms(0, 0, 0)));
+        assert_eq!(evaluate("2016-01-15||-1M", now(), Rounding::Down), Some(Utc.ymd(2015, 12, 15).and_hms(0, 0, 0)));
+
+        // There's no 31st of February, so the last day of the month is used
+        assert_eq!(evaluate("2016-01-31||+1M", now(), Rounding::Down), Some(Utc.ymd(2016, 2, 29).and_hms(0, 0, 0)));
+    }
+
+    #[test]
+    fn test_rounding() {
+        assert_eq!(evaluate("now/d", now(), Rounding::Down), Some(Utc.ymd(2017, 12, 6).and_hms(0, 0, 0)));
+        assert_eq!(evaluate("now/d", now(), Rounding::Up), Some(Utc.ymd(2017, 12, 6).and_hms_micro(23, 59, 59, 999999)));
+        assert_eq!(evaluate("now/M", now(), Rounding::Down), Some(Utc.ymd(2017, 12, 1).and_hms(0, 0, 0)));
+        assert_eq!(evaluate("now/y", now(), Rounding::Up), Some(Utc.ymd(2017, 12, 31).and_hms_micro(23, 59, 59, 999999)));
+        assert_eq!(evaluate("now-7d/d", now(), Rounding::Down), Some(Utc.ymd(2017, 11, 29).and_hms(0, 0, 0)));
+
+        // The 6th of December 2017 is a Wednesday
+        assert_eq!(evaluate("now/w", now(), Rounding::Down), Some(Utc.ymd(2017, 12, 4).and_hms(0, 0, 0)));
+    }
+
+    #[test]
+    fn test_anchor_date() {
+        assert_eq!(evaluate("2017-01-01T12:00:00Z||+1d/d", now(), Rounding::Down), Some(Utc.ymd(2017, 1, 2).and_hms(0, 0, 0)));
+    }
+
+    #[test]
+    fn test_invalid_expressions() {
+        assert_eq!(evaluate("now-7x", now(), Rounding::Down), None);
+        assert_eq!(evaluate("now-d", now(), Rounding::Down), None);
+        assert_eq!(evaluate("now/", now(), Rounding::Down), None);
+        assert_eq!(evaluate("now*2d", now(), Rounding::Down), None);
+        assert_eq!(evaluate("yesterday||+1d", now(), Rounding::Down), None);
+        assert_eq!(evaluate("now+99999999999999999y", now(), Rounding::Down), None);
+    }
+
+    #[test]
+    fn test_is_date_math() {
+        assert!(is_date_math("now-1d"));
+        assert!(is_date_math("2016-01-01||+1M"));
+        assert!(!is_date_math("2016-01-01"));
+    }
+}
@@ -0,0 +1,211 @@
+//! Parses "distance_feature" queries
+
+use chrono::{Utc, Timelike};
+use serde_json::Value as Json;
+use search::Query;
+use search::schema::{Schema, FieldType};
+use search::query::geo_distance::parse_distance;
+use search::query::distance_feature::DistanceFeatureOrigin;
+
+use mapping::parse_geo_point;
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::utils::{parse_string, parse_float};
+use query_parser::range_query::parse_date;
+
+
+#[derive(Debug)]
+struct DistanceFeatureQueryBuilder {
+    field: String,
+    origin: Json,
+    pivot: Json,
+    boost: f32,
+}
+
+
+impl QueryBuilder for DistanceFeatureQueryBuilder {
+    fn build(&self, _context: &QueryBuildContext, schema: &Schema) -> Query {
+        let field = match schema.get_field_by_name(&self.field) {
+            Some(field) => field,
+            None => return Query::None,
+        };
+
+        // The field type decides how the origin and pivot are interpreted
+        let field_type = schema.get(&field).map(|field_info| &field_info.field_type);
+
+        match (parse_origin(field_type, &self.origin), parse_origin_distance(field_type, &self.pivot)) {
+            (Some(origin), Some(pivot)) if pivot > 0.0 => {
+                Query::DistanceFeature {
+                    field: field,
+                    origin: origin,
+                    pivot: pivot,
+                    boost: self.boost,
+                }
+            }
+            _ => Query::None,
+        }
+    }
+}
+
+
+/// Reads an origin as a value of the field type. Anything that isn't an
+/// integer or a date field must be a geo point
+pub fn parse_origin(field_type: Option<&FieldType>, origin: &Json) -> Option<DistanceFeatureOrigin> {
+    match field_type {
+        Some(&FieldType::I64) => origin.as_i64().map(DistanceFeatureOrigin::Integer),
+        Some(&FieldType::DateTime) => {
+            let origin = match origin.as_str() {
+                Some("now") => {
+                    let now = Utc::now();
+                    Some(now.timestamp() * 1000000 + (now.nanosecond() / 1000) as i64)
+                }
+                _ => parse_date(origin),
+            };
+
+            origin.map(DistanceFeatureOrigin::Date)
+        }
+        _ => parse_geo_point(origin).map(DistanceFeatureOrigin::GeoPoint),
+    }
+}
+
+
+/// Reads a distance from an origin in units that suit the field type, such
+/// as "7d" for dates or "1km" for geo points
+pub fn parse_origin_distance(field_type: Option<&FieldType>, distance: &Json) -> Option<f64> {
+    match field_type {
+        Some(&FieldType::I64) => distance.as_f64(),
+        Some(&FieldType::DateTime) => distance.as_str().and_then(parse_time),
+        _ => {
+            match *distance {
+                Json::String(ref distance) => parse_distance(distance),
+                Json::Number(ref distance) => distance.as_f64(),
+                _ => None,
+            }
+        }
+    }
+}
+
+
+/// Parses a length of time such as "7d" or "12h" into microseconds
+fn parse_time(time: &str) -> Option<f64> {
+    let unit_start = time.find(|c: char| c.is_alphabetic()).unwrap_or(time.len());
+    let (value, unit) = time.split_at(unit_start);
+
+    let micros = match unit {
+        "d" => 86400000000.0,
+        "h" => 3600000000.0,
+        "m" => 60000000.0,
+        "s" => 1000000.0,
+        "ms" => 1000.0,
+        "micros" => 1.0,
+        _ => return None,
+    };
+
+    value.trim().parse::<f64>().ok().map(|value| value * micros)
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    // Get configuration
+    let mut field = None;
+    let mut origin = None;
+    let mut pivot = None;
+    let mut boost = 1.0f32;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "field" => {
+                field = Some(parse_string(value)?);
+            }
+            "origin" => {
+                origin = Some(value.clone());
+            }
+            "pivot" => {
+                match *value {
+                    Json::Number(_) | Json::String(_) => {},
+                    _ => return Err(QueryParseError::InvalidValue),
+                }
+
+                pivot = Some(value.clone());
+            }
+            "boost" => {
+                boost = parse_float(value)?;
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(Box::new(DistanceFeatureQueryBuilder {
+        field: field.ok_or(QueryParseError::ExpectedKey("field"))?,
+        origin: origin.ok_or(QueryParseError::ExpectedKey("origin"))?,
+        pivot: pivot.ok_or(QueryParseError::ExpectedKey("pivot"))?,
+        boost: boost,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use search::Query;
+    use search::schema::{Schema, FieldType, FIELD_INDEXED, FIELD_STORED};
+    use search::query::distance_feature::DistanceFeatureOrigin;
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    #[test]
+    fn test_distance_feature_query() {
+        let mut schema = Schema::new();
+        let published_field = schema.add_field("published".to_string(), FieldType::DateTime, FIELD_INDEXED | FIELD_STORED).unwrap();
+        let location_field = schema.add_field("location".to_string(), FieldType::PlainString, FIELD_INDEXED | FIELD_STORED).unwrap();
+
+        let query = parse(&json!({
+            "field": "published",
+            "origin": "2020-01-01",
+            "pivot": "7d",
+            "boost": 2
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::DistanceFeature {
+            field: published_field,
+            origin: DistanceFeatureOrigin::Date(1577836800000000),
+            pivot: 604800000000.0,
+            boost: 2.0f32,
+        }));
+
+        let query = parse(&json!({
+            "field": "location",
+            "origin": [-71.3, 41.15],
+            "pivot": "1km"
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::DistanceFeature {
+            field: location_field,
+            origin: DistanceFeatureOrigin::GeoPoint((-71.3, 41.15)),
+            pivot: 1000.0,
+            boost: 1.0f32,
+        }));
+
+        // Pivots must be given in units that suit the field
+        let query = parse(&json!({
+            "field": "published",
+            "origin": "2020-01-01",
+            "pivot": "1km"
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::None));
+    }
+
+    #[test]
+    fn test_gives_error_for_missing_pivot() {
+        let query = parse(&json!({
+            "field": "published",
+            "origin": "now"
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("pivot")));
+    }
+}
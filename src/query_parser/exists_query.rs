@@ -0,0 +1,110 @@
+//! Parses "exists" queries
+
+use serde_json::Value as Json;
+use search::Query;
+use search::schema::Schema;
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::utils::{parse_string, parse_float};
+
+
+#[derive(Debug)]
+struct ExistsQueryBuilder {
+    field: String,
+    boost: f32,
+}
+
+
+impl QueryBuilder for ExistsQueryBuilder {
+    fn build(&self, _context: &QueryBuildContext, schema: &Schema) -> Query {
+        match schema.get_field_by_name(&self.field) {
+            Some(field) => {
+                Query::Exists {
+                    field: field,
+                    score: self.boost,
+                }
+            }
+            None => Query::None,
+        }
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut field = None;
+    let mut boost = 1.0f32;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "field" => {
+                field = Some(parse_string(value)?);
+            }
+            "boost" => {
+                boost = parse_float(value)?;
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(Box::new(ExistsQueryBuilder {
+        field: field.ok_or(QueryParseError::ExpectedKey("field"))?,
+        boost: boost,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use search::Query;
+    use search::schema::{Schema, FieldType, FIELD_INDEXED};
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    #[test]
+    fn test_exists_query() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"field\": \"foo\",
+            \"boost\": 2.0
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Exists {
+            field: foo_field,
+            score: 2.0f32,
+        }));
+    }
+
+    #[test]
+    fn test_missing_field() {
+        let schema = Schema::new();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"field\": \"foo\"
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::None));
+    }
+
+    #[test]
+    fn test_gives_error_for_missing_field_key() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"boost\": 2.0
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("field")));
+    }
+}
@@ -4,7 +4,7 @@ use serde_json::Value as Json;
 use search::Query;
 use search::schema::Schema;
 
-use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, parse as parse_query};
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, parse_inner};
 
 
 #[derive(Debug)]
@@ -15,6 +15,14 @@ struct FilteredQueryBuilder {
 
 
 impl QueryBuilder for FilteredQueryBuilder {
+    fn check(&self, context: &QueryBuildContext) -> Result<(), QueryParseError> {
+        if let Some(ref query) = self.query {
+            query.check(context)?;
+        }
+
+        self.filter.check(context)
+    }
+
     fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
         let query = match self.query {
             Some(ref query) => query.build(context, schema),
@@ -40,11 +48,11 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
     for (key, value) in object.iter() {
         match key.as_ref() {
             "query" => {
-                query = Some(parse_query(value)?);
+                query = Some(parse_inner(value, key)?);
             }
             "filter" => {
                 has_filter_key = true;
-                filter = Some(parse_query(value)?);
+                filter = Some(parse_inner(value, key)?);
             }
             _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
         }
@@ -177,7 +185,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedObject));
+        assert_eq!(query.err(), Some(QueryParseError::InQuery("query".to_string(), Box::new(QueryParseError::ExpectedObject))));
     }
 
     #[test]
@@ -208,7 +216,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedObject));
+        assert_eq!(query.err(), Some(QueryParseError::InQuery("filter".to_string(), Box::new(QueryParseError::ExpectedObject))));
     }
 
     #[test]
@@ -0,0 +1,587 @@
+//! Parses "function_score" queries
+
+use std::f32;
+use std::hash::Hasher;
+
+use fnv::FnvHasher;
+use serde_json::Value as Json;
+use search::Query;
+use search::schema::Schema;
+use search::query::function_score::{ScoreFunction, FilteredScoreFunction, ScoreMode, BoostMode, FieldValueModifier, DecayFunction};
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, parse_inner, join_path};
+use query_parser::utils::{parse_string, parse_float};
+use query_parser::distance_feature_query::{parse_origin, parse_origin_distance};
+
+
+#[derive(Debug)]
+enum ScoreFunctionBuilder {
+    Weight,
+    RandomScore {
+        seed: u64,
+        field: Option<String>,
+    },
+    FieldValueFactor {
+        field: String,
+        factor: f64,
+        modifier: FieldValueModifier,
+        missing: Option<f64>,
+    },
+    Decay {
+        function: DecayFunction,
+        field: String,
+        origin: Json,
+        scale: Json,
+        offset: Option<Json>,
+        decay: f64,
+    },
+}
+
+
+impl ScoreFunctionBuilder {
+    /// Builds the function for the schema. Functions of fields that don't
+    /// exist, or with values that don't suit the field, are left out
+    fn build(&self, schema: &Schema) -> Option<ScoreFunction> {
+        match *self {
+            ScoreFunctionBuilder::Weight => Some(ScoreFunction::Weight),
+            ScoreFunctionBuilder::RandomScore{seed, ref field} => {
+                Some(ScoreFunction::RandomScore {
+                    seed: seed,
+                    field: field.as_ref().and_then(|field_name| schema.get_field_by_name(field_name)),
+                })
+            }
+            ScoreFunctionBuilder::FieldValueFactor{ref field, factor, modifier, missing} => {
+                Some(ScoreFunction::FieldValueFactor {
+                    field: schema.get_field_by_name(field)?,
+                    factor: factor,
+                    modifier: modifier,
+                    missing: missing,
+                })
+            }
+            ScoreFunctionBuilder::Decay{function, ref field, ref origin, ref scale, ref offset, decay} => {
+                let field = schema.get_field_by_name(field)?;
+                let field_type = schema.get(&field).map(|field_info| &field_info.field_type);
+
+                let scale = parse_origin_distance(field_type, scale)?;
+                let offset = match *offset {
+                    Some(ref offset) => parse_origin_distance(field_type, offset)?,
+                    None => 0.0,
+                };
+
+                if scale <= 0.0 || offset < 0.0 {
+                    return None;
+                }
+
+                Some(ScoreFunction::Decay {
+                    field: field,
+                    function: function,
+                    origin: parse_origin(field_type, origin)?,
+                    scale: scale,
+                    offset: offset,
+                    decay: decay,
+                })
+            }
+        }
+    }
+}
+
+
+#[derive(Debug)]
+struct FunctionBuilder {
+    filter: Option<Box<QueryBuilder>>,
+    function: ScoreFunctionBuilder,
+    weight: f32,
+}
+
+
+#[derive(Debug)]
+struct FunctionScoreQueryBuilder {
+    query: Option<Box<QueryBuilder>>,
+    functions: Vec<FunctionBuilder>,
+    score_mode: ScoreMode,
+    boost_mode: BoostMode,
+    max_boost: f32,
+    boost: f32,
+}
+
+
+impl QueryBuilder for FunctionScoreQueryBuilder {
+    fn check(&self, context: &QueryBuildContext) -> Result<(), QueryParseError> {
+        if let Some(ref query) = self.query {
+            query.check(context)?;
+        }
+
+        for function in self.functions.iter() {
+            if let Some(ref filter) = function.filter {
+                filter.check(context)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+        let query = match self.query {
+            Some(ref query) => query.build(context, schema),
+            None => Query::all(),
+        };
+
+        let functions = self.functions.iter().filter_map(|function| {
+            Some(FilteredScoreFunction {
+                filter: function.filter.as_ref().map(|filter| filter.build(&context.clone().no_score(), schema)),
+                function: function.function.build(schema)?,
+                weight: function.weight,
+            })
+        }).collect();
+
+        Query::FunctionScore {
+            query: Box::new(query),
+            functions: functions,
+            score_mode: self.score_mode,
+            boost_mode: self.boost_mode,
+            max_boost: self.max_boost,
+            boost: self.boost,
+        }
+    }
+}
+
+
+/// Parses a seed, which can be a number or a string such as a user ID
+fn parse_seed(json: &Json) -> Result<u64, QueryParseError> {
+    match *json {
+        Json::Number(ref number) => {
+            number.as_u64().or_else(|| number.as_i64().map(|seed| seed as u64)).ok_or(QueryParseError::InvalidValue)
+        }
+        Json::String(ref string) => {
+            let mut hasher = FnvHasher::default();
+            hasher.write(string.as_bytes());
+            Ok(hasher.finish())
+        }
+        _ => Err(QueryParseError::InvalidValue),
+    }
+}
+
+
+fn parse_random_score(json: &Json) -> Result<ScoreFunctionBuilder, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut seed = 0;
+    let mut field = None;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "seed" => {
+                seed = parse_seed(value)?;
+            }
+            "field" => {
+                field = Some(parse_string(value)?);
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(ScoreFunctionBuilder::RandomScore {
+        seed: seed,
+        field: field,
+    })
+}
+
+
+fn parse_modifier(json: &Json) -> Result<FieldValueModifier, QueryParseError> {
+    match parse_string(json)?.as_ref() {
+        "none" => Ok(FieldValueModifier::None),
+        "log" => Ok(FieldValueModifier::Log),
+        "log1p" => Ok(FieldValueModifier::Log1p),
+        "log2p" => Ok(FieldValueModifier::Log2p),
+        "ln" => Ok(FieldValueModifier::Ln),
+        "ln1p" => Ok(FieldValueModifier::Ln1p),
+        "ln2p" => Ok(FieldValueModifier::Ln2p),
+        "square" => Ok(FieldValueModifier::Square),
+        "sqrt" => Ok(FieldValueModifier::Sqrt),
+        "reciprocal" => Ok(FieldValueModifier::Reciprocal),
+        _ => Err(QueryParseError::InvalidValue),
+    }
+}
+
+
+fn parse_field_value_factor(json: &Json) -> Result<ScoreFunctionBuilder, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut field = None;
+    let mut factor = 1.0f64;
+    let mut modifier = FieldValueModifier::None;
+    let mut missing = None;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "field" => {
+                field = Some(parse_string(value)?);
+            }
+            "factor" => {
+                factor = value.as_f64().ok_or(QueryParseError::ExpectedFloat)?;
+            }
+            "modifier" => {
+                modifier = parse_modifier(value)?;
+            }
+            "missing" => {
+                missing = Some(value.as_f64().ok_or(QueryParseError::ExpectedFloat)?);
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(ScoreFunctionBuilder::FieldValueFactor {
+        field: field.ok_or(QueryParseError::ExpectedKey("field"))?,
+        factor: factor,
+        modifier: modifier,
+        missing: missing,
+    })
+}
+
+
+/// Checks a scale or offset of a decay function. These are read in the units
+/// of the field once its type is known
+fn parse_decay_distance(json: &Json) -> Result<Json, QueryParseError> {
+    match *json {
+        Json::Number(_) | Json::String(_) => Ok(json.clone()),
+        _ => Err(QueryParseError::InvalidValue),
+    }
+}
+
+
+/// Parses a decay function, which has a single key naming the field
+fn parse_decay(function: DecayFunction, json: &Json) -> Result<ScoreFunctionBuilder, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let field_name = if object.len() == 1 {
+        object.keys().collect::<Vec<_>>()[0]
+    } else {
+        return Err(QueryParseError::ExpectedSingleKey)
+    };
+
+    let object = object.get(field_name).unwrap().as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut origin = None;
+    let mut scale = None;
+    let mut offset = None;
+    let mut decay = 0.5f64;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "origin" => {
+                origin = Some(value.clone());
+            }
+            "scale" => {
+                scale = Some(parse_decay_distance(value)?);
+            }
+            "offset" => {
+                offset = Some(parse_decay_distance(value)?);
+            }
+            "decay" => {
+                decay = value.as_f64().ok_or(QueryParseError::ExpectedFloat)?;
+
+                if decay <= 0.0 || decay >= 1.0 {
+                    return Err(QueryParseError::InvalidValue);
+                }
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(ScoreFunctionBuilder::Decay {
+        function: function,
+        field: field_name.clone(),
+        origin: origin.ok_or(QueryParseError::ExpectedKey("origin"))?,
+        scale: scale.ok_or(QueryParseError::ExpectedKey("scale"))?,
+        offset: offset,
+        decay: decay,
+    })
+}
+
+
+/// Parses a function along with its filter and weight. The keys of the
+/// function can also be given directly to the query, in which case the path
+/// of the function is empty
+fn parse_function<'a, I: Iterator<Item = (&'a String, &'a Json)>>(keys: I, path: &str) -> Result<Option<FunctionBuilder>, QueryParseError> {
+    let mut filter = None;
+    let mut function = None;
+    let mut weight = None;
+
+    for (key, value) in keys {
+        match key.as_ref() {
+            "filter" => {
+                filter = Some(parse_inner(value, &join_path(path, "filter"))?);
+            }
+            "weight" => {
+                weight = Some(parse_float(value)?);
+            }
+            "random_score" | "field_value_factor" | "gauss" | "linear" | "exp" => {
+                // Only one function can be used
+                if function.is_some() {
+                    return Err(QueryParseError::InvalidValue);
+                }
+
+                function = Some(match key.as_ref() {
+                    "random_score" => parse_random_score(value)?,
+                    "field_value_factor" => parse_field_value_factor(value)?,
+                    "gauss" => parse_decay(DecayFunction::Gauss, value)?,
+                    "linear" => parse_decay(DecayFunction::Linear, value)?,
+                    _ => parse_decay(DecayFunction::Exp, value)?,
+                });
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    let function = match (function, weight) {
+        (Some(function), _) => function,
+        (None, Some(_)) => ScoreFunctionBuilder::Weight,
+        (None, None) => {
+            if filter.is_some() {
+                return Err(QueryParseError::ExpectedKey("weight"));
+            }
+
+            return Ok(None);
+        }
+    };
+
+    Ok(Some(FunctionBuilder {
+        filter: filter,
+        function: function,
+        weight: weight.unwrap_or(1.0f32),
+    }))
+}
+
+
+fn parse_score_mode(json: &Json) -> Result<ScoreMode, QueryParseError> {
+    match parse_string(json)?.as_ref() {
+        "multiply" => Ok(ScoreMode::Multiply),
+        "sum" => Ok(ScoreMode::Sum),
+        "avg" => Ok(ScoreMode::Avg),
+        "first" => Ok(ScoreMode::First),
+        "max" => Ok(ScoreMode::Max),
+        "min" => Ok(ScoreMode::Min),
+        _ => Err(QueryParseError::InvalidValue),
+    }
+}
+
+
+fn parse_boost_mode(json: &Json) -> Result<BoostMode, QueryParseError> {
+    match parse_string(json)?.as_ref() {
+        "multiply" => Ok(BoostMode::Multiply),
+        "replace" => Ok(BoostMode::Replace),
+        "sum" => Ok(BoostMode::Sum),
+        "avg" => Ok(BoostMode::Avg),
+        "max" => Ok(BoostMode::Max),
+        "min" => Ok(BoostMode::Min),
+        _ => Err(QueryParseError::InvalidValue),
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    // Get configuration
+    let mut query = None;
+    let mut functions = None;
+    let mut score_mode = ScoreMode::Multiply;
+    let mut boost_mode = BoostMode::Multiply;
+    let mut max_boost = f32::MAX;
+    let mut boost = 1.0f32;
+    let mut function_keys = Vec::new();
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "query" => {
+                query = Some(parse_inner(value, key)?);
+            }
+            "functions" => {
+                let array = value.as_array().ok_or(QueryParseError::ExpectedArray)?;
+
+                let mut parsed_functions = Vec::with_capacity(array.len());
+                for (i, function_json) in array.iter().enumerate() {
+                    let function_object = function_json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+                    parsed_functions.push(parse_function(function_object.iter(), &format!("functions[{}]", i))?.ok_or(QueryParseError::ExpectedKey("weight"))?);
+                }
+
+                functions = Some(parsed_functions);
+            }
+            "score_mode" => {
+                score_mode = parse_score_mode(value)?;
+            }
+            "boost_mode" => {
+                boost_mode = parse_boost_mode(value)?;
+            }
+            "max_boost" => {
+                max_boost = parse_float(value)?;
+            }
+            "boost" => {
+                boost = parse_float(value)?;
+            }
+            "weight" | "random_score" | "field_value_factor" | "gauss" | "linear" | "exp" => {
+                function_keys.push((key, value));
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    // A single function can be given without a "functions" array
+    let function = parse_function(function_keys.into_iter(), "")?;
+    let functions = match (functions, function) {
+        (Some(_), Some(_)) => return Err(QueryParseError::InvalidValue),
+        (Some(functions), None) => functions,
+        (None, function) => function.into_iter().collect(),
+    };
+
+    Ok(Box::new(FunctionScoreQueryBuilder {
+        query: query,
+        functions: functions,
+        score_mode: score_mode,
+        boost_mode: boost_mode,
+        max_boost: max_boost,
+        boost: boost,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::f32;
+
+    use search::{Term, Query};
+    use search::schema::{Schema, FieldType, FIELD_INDEXED, FIELD_STORED};
+    use search::query::function_score::{ScoreFunction, FilteredScoreFunction, ScoreMode, BoostMode, FieldValueModifier, DecayFunction};
+    use search::query::distance_feature::DistanceFeatureOrigin;
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    #[test]
+    fn test_function_score_query() {
+        let mut schema = Schema::new();
+        let user_field = schema.add_field("user".to_string(), FieldType::PlainString, FIELD_INDEXED | FIELD_STORED).unwrap();
+
+        let query = parse(&json!({
+            "query": {"term": {"user": "kimchy"}},
+            "functions": [
+                {"random_score": {"seed": 10, "field": "user"}},
+                {"filter": {"term": {"user": "foo"}}, "weight": 2}
+            ],
+            "score_mode": "sum",
+            "boost_mode": "replace",
+            "max_boost": 5
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::FunctionScore {
+            query: Box::new(Query::term(user_field, Term::from_string("kimchy"))),
+            functions: vec![
+                FilteredScoreFunction {
+                    filter: None,
+                    function: ScoreFunction::RandomScore { seed: 10, field: Some(user_field) },
+                    weight: 1.0f32,
+                },
+                FilteredScoreFunction {
+                    filter: Some(Query::term(user_field, Term::from_string("foo"))),
+                    function: ScoreFunction::Weight,
+                    weight: 2.0f32,
+                },
+            ],
+            score_mode: ScoreMode::Sum,
+            boost_mode: BoostMode::Replace,
+            max_boost: 5.0f32,
+            boost: 1.0f32,
+        }));
+    }
+
+    #[test]
+    fn test_single_function() {
+        let schema = Schema::new();
+
+        let query = parse(&json!({
+            "random_score": {"seed": "user-42"},
+            "boost": 2
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        // String seeds are hashed, so the same user always gets the same order
+        let other_query = parse(&json!({
+            "random_score": {"seed": "user-42"},
+            "boost": 2
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, other_query);
+
+        match query {
+            Ok(Query::FunctionScore{ref query, ref functions, score_mode, boost_mode, max_boost, boost}) => {
+                assert_eq!(**query, Query::all());
+                assert_eq!(functions.len(), 1);
+                assert_eq!(score_mode, ScoreMode::Multiply);
+                assert_eq!(boost_mode, BoostMode::Multiply);
+                assert_eq!(max_boost, f32::MAX);
+                assert_eq!(boost, 2.0f32);
+            }
+            _ => panic!("expected a function score query, got {:?}", query),
+        }
+    }
+
+    #[test]
+    fn test_field_value_factor_and_decay_functions() {
+        let mut schema = Schema::new();
+        let likes_field = schema.add_field("likes".to_string(), FieldType::I64, FIELD_INDEXED | FIELD_STORED).unwrap();
+        let published_field = schema.add_field("published".to_string(), FieldType::DateTime, FIELD_INDEXED | FIELD_STORED).unwrap();
+
+        let query = parse(&json!({
+            "functions": [
+                {"field_value_factor": {"field": "likes", "factor": 1.2, "modifier": "log1p", "missing": 1}},
+                {"gauss": {"published": {"origin": "2020-01-01", "scale": "10d", "offset": "1d", "decay": 0.25}}},
+                {"exp": {"missing_field": {"origin": 0, "scale": 1}}}
+            ]
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        // Functions of fields that don't exist are left out
+        match query {
+            Ok(Query::FunctionScore{ref functions, ..}) => {
+                assert_eq!(functions.iter().map(|function| function.function.clone()).collect::<Vec<_>>(), vec![
+                    ScoreFunction::FieldValueFactor {
+                        field: likes_field,
+                        factor: 1.2,
+                        modifier: FieldValueModifier::Log1p,
+                        missing: Some(1.0),
+                    },
+                    ScoreFunction::Decay {
+                        field: published_field,
+                        function: DecayFunction::Gauss,
+                        origin: DistanceFeatureOrigin::Date(1577836800000000),
+                        scale: 864000000000.0,
+                        offset: 86400000000.0,
+                        decay: 0.25,
+                    },
+                ]);
+            }
+            _ => panic!("expected a function score query, got {:?}", query),
+        }
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_modes() {
+        let query = parse(&json!({
+            "random_score": {},
+            "score_mode": "foo"
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+
+        let query = parse(&json!({
+            "functions": [{"filter": {"match_all": {}}}]
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("weight")));
+
+        let query = parse(&json!({
+            "linear": {"published": {"origin": "now", "scale": "10d", "decay": 1.5}}
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+    }
+}
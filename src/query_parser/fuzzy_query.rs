@@ -0,0 +1,282 @@
+//! Parses "fuzzy" queries
+
+use serde_json::Value as Json;
+use search::{Query, MultiTermSelector, TermScorer};
+use search::schema::Schema;
+use search::query::levenshtein::LevenshteinAutomaton;
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::utils::{parse_string, parse_float, parse_fuzziness, Fuzziness, parse_rewrite, Rewrite};
+
+
+/// The number of terms that a fuzzy query expands to by default
+const DEFAULT_MAX_EXPANSIONS: usize = 50;
+
+
+#[derive(Debug)]
+struct FuzzyQueryBuilder {
+    field: String,
+    value: String,
+    fuzziness: Fuzziness,
+    prefix_length: usize,
+    max_expansions: usize,
+    transpositions: bool,
+    rewrite: Rewrite,
+    boost: f32,
+}
+
+
+impl QueryBuilder for FuzzyQueryBuilder {
+    fn build(&self, _context: &QueryBuildContext, schema: &Schema) -> Query {
+        let field = match schema.get_field_by_name(&self.field) {
+            Some(field) => field,
+            None => return Query::None,
+        };
+
+        let max_distance = self.fuzziness.max_distance(&self.value);
+        let max_expansions = match self.rewrite {
+            Rewrite::TopTerms(size) => size,
+            _ => self.max_expansions,
+        };
+
+        let query = Query::MultiTerm {
+            field: field,
+            term_selector: MultiTermSelector::Fuzzy {
+                automaton: LevenshteinAutomaton::new(&self.value, max_distance, self.prefix_length).with_transpositions(self.transpositions),
+                max_expansions: max_expansions,
+            },
+            scorer: TermScorer::default_with_boost(self.boost),
+        };
+
+        match self.rewrite {
+            Rewrite::ConstantScore => {
+                Query::Filter {
+                    query: Box::new(Query::All{ score: self.boost }),
+                    filter: Box::new(query),
+                }
+            }
+            _ => query,
+        }
+    }
+}
+
+
+fn parse_positive_integer(json: &Json) -> Result<usize, QueryParseError> {
+    match json.as_u64() {
+        Some(value) => Ok(value as usize),
+        None => Err(QueryParseError::InvalidValue),
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let field_name = if object.len() == 1 {
+        object.keys().collect::<Vec<_>>()[0]
+    } else {
+        return Err(QueryParseError::ExpectedSingleKey)
+    };
+
+    let object = object.get(field_name).unwrap();
+
+    // Get configuration
+    let mut value = None;
+    let mut fuzziness = Fuzziness::default();
+    let mut prefix_length = 0;
+    let mut max_expansions = DEFAULT_MAX_EXPANSIONS;
+    let mut transpositions = true;
+    let mut rewrite = Rewrite::ScoringBoolean;
+    let mut boost = 1.0f32;
+
+    match *object {
+        Json::String(ref string) => value = Some(string.clone()),
+        Json::Object(ref inner_object) => {
+            for (key, val) in inner_object.iter() {
+                match key.as_ref() {
+                    "value" => {
+                        value = Some(parse_string(val)?);
+                    }
+                    "fuzziness" => {
+                        fuzziness = parse_fuzziness(val)?;
+                    }
+                    "prefix_length" => {
+                        prefix_length = parse_positive_integer(val)?;
+                    }
+                    "max_expansions" => {
+                        max_expansions = parse_positive_integer(val)?;
+
+                        if max_expansions == 0 {
+                            return Err(QueryParseError::InvalidValue);
+                        }
+                    }
+                    "transpositions" => {
+                        transpositions = val.as_bool().ok_or(QueryParseError::InvalidValue)?;
+                    }
+                    "rewrite" => {
+                        rewrite = parse_rewrite(val)?;
+                    }
+                    "boost" => {
+                        boost = parse_float(val)?;
+                    }
+                    _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+                }
+            }
+        }
+        _ => return Err(QueryParseError::ExpectedObjectOrString),
+    }
+
+    Ok(Box::new(FuzzyQueryBuilder {
+        field: field_name.clone(),
+        value: value.ok_or(QueryParseError::ExpectedKey("value"))?,
+        fuzziness: fuzziness,
+        prefix_length: prefix_length,
+        max_expansions: max_expansions,
+        transpositions: transpositions,
+        rewrite: rewrite,
+        boost: boost,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use search::{Query, MultiTermSelector, TermScorer};
+    use search::schema::{Schema, FieldType, FIELD_INDEXED};
+    use search::query::levenshtein::LevenshteinAutomaton;
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    #[test]
+    fn test_fuzzy_query() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"value\": \"kitten\",
+                \"fuzziness\": 1,
+                \"prefix_length\": 2,
+                \"max_expansions\": 10,
+                \"boost\": 2.0
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::MultiTerm {
+            field: foo_field,
+            term_selector: MultiTermSelector::Fuzzy {
+                automaton: LevenshteinAutomaton::new("kitten", 1, 2).with_transpositions(true),
+                max_expansions: 10,
+            },
+            scorer: TermScorer::default_with_boost(2.0f32),
+        }));
+    }
+
+    #[test]
+    fn test_fuzzy_query_with_rewrite() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"value\": \"kitten\",
+                \"transpositions\": false,
+                \"rewrite\": \"constant_score\",
+                \"boost\": 2.0
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        // Documents score the boost however many of the terms they have
+        assert_eq!(query, Ok(Query::Filter {
+            query: Box::new(Query::All{ score: 2.0f32 }),
+            filter: Box::new(Query::MultiTerm {
+                field: foo_field,
+                term_selector: MultiTermSelector::Fuzzy {
+                    automaton: LevenshteinAutomaton::new("kitten", 2, 0),
+                    max_expansions: 50,
+                },
+                scorer: TermScorer::default_with_boost(2.0f32),
+            }),
+        }));
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"value\": \"kitten\",
+                \"rewrite\": \"top_terms_5\"
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        match query {
+            Ok(Query::MultiTerm{term_selector: MultiTermSelector::Fuzzy{max_expansions, ..}, ..}) => assert_eq!(max_expansions, 5),
+            _ => panic!("expected a fuzzy query, got {:?}", query),
+        }
+    }
+
+    #[test]
+    fn test_auto_fuzziness() {
+        let mut schema = Schema::new();
+        schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let max_distance = |json: &str| {
+            match parse(&serde_json::from_str(json).unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema))) {
+                Ok(Query::MultiTerm{term_selector: MultiTermSelector::Fuzzy{automaton, ..}, ..}) => Some(automaton.max_distance()),
+                _ => None,
+            }
+        };
+
+        // The default is "AUTO", which allows more edits in longer values
+        assert_eq!(max_distance("{\"foo\": \"ki\"}"), Some(0));
+        assert_eq!(max_distance("{\"foo\": \"kit\"}"), Some(1));
+        assert_eq!(max_distance("{\"foo\": \"kitten\"}"), Some(2));
+        assert_eq!(max_distance("{\"foo\": {\"value\": \"kitten\", \"fuzziness\": \"AUTO:2,10\"}}"), Some(1));
+        assert_eq!(max_distance("{\"foo\": {\"value\": \"kitten\", \"fuzziness\": \"0\"}}"), Some(0));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_fuzziness() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"value\": \"kitten\",
+                \"fuzziness\": 3
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"value\": \"kitten\",
+                \"fuzziness\": \"AUTO:6\"
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+    }
+
+    #[test]
+    fn test_gives_error_for_missing_value() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"fuzziness\": 1
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("value")));
+    }
+}
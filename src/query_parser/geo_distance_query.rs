@@ -0,0 +1,166 @@
+//! Parses "geo_distance" queries
+
+use serde_json::Value as Json;
+use search::Query;
+use search::schema::Schema;
+use search::query::geo_shape::{Coordinate, SpatialRelation, CoordinateSystem, geo_shape_query_terms};
+use search::query::geo_distance::{parse_distance, bounding_box};
+
+use mapping::parse_geo_point;
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::utils::{parse_string, parse_float};
+
+
+#[derive(Debug)]
+struct GeoDistanceQueryBuilder {
+    field: String,
+    origin: Coordinate,
+    distance: f64,
+    boost: f32,
+}
+
+
+impl QueryBuilder for GeoDistanceQueryBuilder {
+    fn build(&self, _context: &QueryBuildContext, schema: &Schema) -> Query {
+        let field = match schema.get_field_by_name(&self.field) {
+            Some(field) => field,
+            None => return Query::None,
+        };
+
+        let query = Query::GeoDistance {
+            field: field,
+            tiles: geo_shape_query_terms(&bounding_box(self.origin, self.distance), SpatialRelation::Intersects, CoordinateSystem::Geographic),
+            origin: self.origin,
+            distance: self.distance,
+            score: 1.0f32,
+        };
+
+        // Add boost
+        query.boost(self.boost)
+    }
+}
+
+
+fn parse_query_distance(json: &Json) -> Result<f64, QueryParseError> {
+    let distance = match *json {
+        Json::String(ref distance) => parse_distance(distance),
+        Json::Number(ref distance) => distance.as_f64(),
+        _ => return Err(QueryParseError::ExpectedString),
+    };
+
+    match distance {
+        Some(distance) if distance >= 0.0 => Ok(distance),
+        _ => Err(QueryParseError::InvalidValue),
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    // Get configuration
+    let mut field = None;
+    let mut distance = None;
+    let mut boost = 1.0f32;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "distance" => {
+                distance = Some(parse_query_distance(value)?);
+            }
+            "distance_type" => {
+                // Distances are always worked out along the surface of the earth
+                match parse_string(value)?.as_ref() {
+                    "arc" | "plane" => {}
+                    _ => return Err(QueryParseError::InvalidValue),
+                }
+            }
+            "boost" => {
+                boost = parse_float(value)?;
+            }
+            _ => {
+                // Any other key is the field
+                if field.is_some() {
+                    return Err(QueryParseError::ExpectedSingleKey);
+                }
+
+                field = Some((key.clone(), parse_geo_point(value).ok_or(QueryParseError::InvalidValue)?));
+            }
+        }
+    }
+
+    let (field, origin) = field.ok_or(QueryParseError::ExpectedSingleKey)?;
+    Ok(Box::new(GeoDistanceQueryBuilder {
+        field: field,
+        origin: origin,
+        distance: distance.ok_or(QueryParseError::ExpectedKey("distance"))?,
+        boost: boost,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use search::Query;
+    use search::schema::{Schema, FieldType, FIELD_INDEXED, FIELD_STORED};
+    use search::query::geo_shape::{SpatialRelation, CoordinateSystem, geo_shape_query_terms};
+    use search::query::geo_distance::bounding_box;
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    #[test]
+    fn test_geo_distance_query() {
+        let mut schema = Schema::new();
+        let location_field = schema.add_field("location".to_string(), FieldType::PlainString, FIELD_INDEXED | FIELD_STORED).unwrap();
+
+        let query = parse(&json!({
+            "distance": "12km",
+            "location": {"lat": 52.5, "lon": 13.4},
+            "boost": 2
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        let tiles = geo_shape_query_terms(&bounding_box((13.4, 52.5), 12000.0), SpatialRelation::Intersects, CoordinateSystem::Geographic);
+        assert_eq!(query, Ok(Query::GeoDistance {
+            field: location_field,
+            tiles: tiles,
+            origin: (13.4, 52.5),
+            distance: 12000.0,
+            score: 2.0f32,
+        }));
+
+        // Points can be given in any of the forms that geo_point fields accept
+        let query = parse(&json!({
+            "distance": "2mi",
+            "location": "52.5,13.4"
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        match query {
+            Ok(Query::GeoDistance{origin, distance, ..}) => assert_eq!((origin, distance), ((13.4, 52.5), 3218.688)),
+            query => panic!("expected a geo distance query, got {:?}", query),
+        }
+    }
+
+    #[test]
+    fn test_geo_distance_query_gives_errors() {
+        let query = parse(&json!({
+            "distance": "12 furlongs",
+            "location": [13.4, 52.5]
+        }));
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+
+        let query = parse(&json!({
+            "location": [13.4, 52.5]
+        }));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("distance")));
+
+        let query = parse(&json!({
+            "distance": "12km",
+            "location": [13.4, 52.5],
+            "other_location": [2.35, 48.85]
+        }));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedSingleKey));
+    }
+}
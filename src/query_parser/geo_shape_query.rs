@@ -0,0 +1,245 @@
+//! Parses "geo_shape" and "shape" queries
+
+use serde_json::Value as Json;
+use search::Query;
+use search::schema::Schema;
+use search::query::geo_shape::{Geometry, SpatialRelation, CoordinateSystem, geo_shape_query_terms};
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::utils::{parse_string, parse_float};
+
+
+#[derive(Debug)]
+struct GeoShapeQueryBuilder {
+    field: String,
+    shape: Geometry,
+    relation: SpatialRelation,
+    system: CoordinateSystem,
+    boost: f32,
+}
+
+
+impl QueryBuilder for GeoShapeQueryBuilder {
+    fn build(&self, _context: &QueryBuildContext, schema: &Schema) -> Query {
+        let field = match schema.get_field_by_name(&self.field) {
+            Some(field) => field,
+            None => return Query::None,
+        };
+
+        let query = Query::GeoShape {
+            field: field,
+            tiles: geo_shape_query_terms(&self.shape, self.relation, self.system),
+            shape: self.shape.clone(),
+            relation: self.relation,
+            system: self.system,
+            score: 1.0f32,
+        };
+
+        // Add boost
+        query.boost(self.boost)
+    }
+}
+
+
+fn parse_relation(json: &Json) -> Result<SpatialRelation, QueryParseError> {
+    match parse_string(json)?.as_ref() {
+        "intersects" => Ok(SpatialRelation::Intersects),
+        "within" => Ok(SpatialRelation::Within),
+        "disjoint" => Ok(SpatialRelation::Disjoint),
+        _ => Err(QueryParseError::InvalidValue),
+    }
+}
+
+
+fn parse_shape_query(json: &Json, system: CoordinateSystem) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    // Get configuration
+    let mut field = None;
+    let mut boost = 1.0f32;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "boost" => {
+                boost = parse_float(value)?;
+            }
+            _ => {
+                // Any other key is the field
+                if field.is_some() {
+                    return Err(QueryParseError::ExpectedSingleKey);
+                }
+
+                field = Some((key, value));
+            }
+        }
+    }
+
+    let (field_name, field_object) = field.ok_or(QueryParseError::ExpectedSingleKey)?;
+    let field_object = field_object.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut shape = None;
+    let mut relation = SpatialRelation::Intersects;
+
+    for (key, value) in field_object.iter() {
+        match key.as_ref() {
+            "shape" => {
+                shape = Some(Geometry::from_json(value, system).ok_or(QueryParseError::InvalidValue)?);
+            }
+            "relation" => {
+                relation = parse_relation(value)?;
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(Box::new(GeoShapeQueryBuilder {
+        field: field_name.clone(),
+        shape: shape.ok_or(QueryParseError::ExpectedKey("shape"))?,
+        relation: relation,
+        system: system,
+        boost: boost,
+    }))
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    parse_shape_query(json, CoordinateSystem::Geographic)
+}
+
+
+/// Parses "shape" queries, which search cartesian shape and point fields
+pub fn parse_cartesian(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    parse_shape_query(json, CoordinateSystem::Cartesian)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use search::Query;
+    use search::schema::{Schema, FieldType, FIELD_INDEXED, FIELD_STORED};
+    use search::query::geo_shape::{Geometry, SpatialRelation, CoordinateSystem, geo_shape_query_terms};
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::{parse, parse_cartesian};
+
+    #[test]
+    fn test_geo_shape_query() {
+        let mut schema = Schema::new();
+        let location_field = schema.add_field("location".to_string(), FieldType::PlainString, FIELD_INDEXED | FIELD_STORED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"location\": {
+                \"shape\": {
+                    \"type\": \"envelope\",
+                    \"coordinates\": [[13.0, 53.0], [14.0, 52.0]]
+                },
+                \"relation\": \"within\"
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        let shape = Geometry::Envelope((13.0, 53.0), (14.0, 52.0));
+        assert_eq!(query, Ok(Query::GeoShape {
+            field: location_field,
+            tiles: geo_shape_query_terms(&shape, SpatialRelation::Within, CoordinateSystem::Geographic),
+            shape: shape,
+            relation: SpatialRelation::Within,
+            system: CoordinateSystem::Geographic,
+            score: 1.0f32,
+        }));
+    }
+
+    #[test]
+    fn test_shape_query() {
+        let mut schema = Schema::new();
+        let outline_field = schema.add_field("outline".to_string(), FieldType::PlainString, FIELD_INDEXED | FIELD_STORED).unwrap();
+
+        // Cartesian coordinates aren't limited to latitudes and longitudes
+        let query = parse_cartesian(&serde_json::from_str("
+        {
+            \"outline\": {
+                \"shape\": {
+                    \"type\": \"envelope\",
+                    \"coordinates\": [[1000.0, 2500.0], [1500.0, 2000.0]]
+                }
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        let shape = Geometry::Envelope((1000.0, 2500.0), (1500.0, 2000.0));
+        assert_eq!(query, Ok(Query::GeoShape {
+            field: outline_field,
+            tiles: geo_shape_query_terms(&shape, SpatialRelation::Intersects, CoordinateSystem::Cartesian),
+            shape: shape,
+            relation: SpatialRelation::Intersects,
+            system: CoordinateSystem::Cartesian,
+            score: 1.0f32,
+        }));
+    }
+
+    #[test]
+    fn test_geo_shape_query_gives_error_for_cartesian_coordinates() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"outline\": {
+                \"shape\": {
+                    \"type\": \"point\",
+                    \"coordinates\": [1000.0, 2500.0]
+                }
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_shape() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"location\": {
+                \"shape\": {
+                    \"type\": \"triangle\",
+                    \"coordinates\": [[13.0, 53.0], [14.0, 52.0]]
+                }
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_relation() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"location\": {
+                \"shape\": {
+                    \"type\": \"point\",
+                    \"coordinates\": [13.0, 53.0]
+                },
+                \"relation\": \"contains\"
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+    }
+
+    #[test]
+    fn test_gives_error_for_missing_shape() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"location\": {
+                \"relation\": \"within\"
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("shape")));
+    }
+}
@@ -0,0 +1,101 @@
+//! Parses "ids" queries
+
+use serde_json::Value as Json;
+use search::Query;
+use search::schema::Schema;
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::utils::{parse_string, parse_float};
+
+
+#[derive(Debug)]
+struct IdsQueryBuilder {
+    keys: Vec<String>,
+    boost: f32,
+}
+
+
+impl QueryBuilder for IdsQueryBuilder {
+    fn build(&self, _context: &QueryBuildContext, _schema: &Schema) -> Query {
+        Query::Ids {
+            keys: self.keys.clone(),
+            score: self.boost,
+        }
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut keys = None;
+    let mut boost = 1.0f32;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "values" => {
+                let values = value.as_array().ok_or(QueryParseError::ExpectedArray)?;
+                keys = Some(values.iter().map(parse_string).collect::<Result<Vec<_>, _>>()?);
+            }
+            "boost" => {
+                boost = parse_float(value)?;
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(Box::new(IdsQueryBuilder {
+        keys: keys.ok_or(QueryParseError::ExpectedKey("values"))?,
+        boost: boost,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use search::Query;
+    use search::schema::Schema;
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    #[test]
+    fn test_ids_query() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"values\": [\"1\", \"4\", \"100\"],
+            \"boost\": 2.0
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &Schema::new())));
+
+        assert_eq!(query, Ok(Query::Ids {
+            keys: vec!["1".to_string(), "4".to_string(), "100".to_string()],
+            score: 2.0f32,
+        }));
+    }
+
+    #[test]
+    fn test_gives_error_for_non_string_value() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"values\": [1]
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedString));
+    }
+
+    #[test]
+    fn test_gives_error_for_missing_values() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"boost\": 2.0
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("values")));
+    }
+}
@@ -0,0 +1,352 @@
+//! Parses "intervals" queries
+
+use serde_json::Value as Json;
+use search::{Term, Query, TermScorer};
+use search::schema::Schema;
+use search::query::intervals::{IntervalsSource, IntervalFilter};
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::utils::{parse_string, parse_float};
+use query_parser::query_string_query::analyze;
+
+
+#[derive(Debug)]
+enum IntervalsRule {
+    Match {
+        query: String,
+        ordered: bool,
+        max_gaps: Option<u32>,
+        filter: Option<(IntervalFilter, Box<IntervalsRule>)>,
+    },
+    AllOf {
+        rules: Vec<IntervalsRule>,
+        ordered: bool,
+        max_gaps: Option<u32>,
+        filter: Option<(IntervalFilter, Box<IntervalsRule>)>,
+    },
+    AnyOf {
+        rules: Vec<IntervalsRule>,
+        filter: Option<(IntervalFilter, Box<IntervalsRule>)>,
+    },
+}
+
+
+impl IntervalsRule {
+    fn build(&self, context: &QueryBuildContext, field_name: &str) -> IntervalsSource<Term> {
+        let (source, filter) = match *self {
+            IntervalsRule::Match{ref query, ordered, max_gaps, ref filter} => {
+                let mut terms = analyze(context, field_name, query);
+
+                let source = match terms.len() {
+                    1 => IntervalsSource::Term(terms.pop().unwrap()),
+                    _ => IntervalsSource::AllOf {
+                        sources: terms.into_iter().map(IntervalsSource::Term).collect(),
+                        ordered: ordered,
+                        max_gaps: max_gaps,
+                    },
+                };
+
+                (source, filter)
+            }
+            IntervalsRule::AllOf{ref rules, ordered, max_gaps, ref filter} => {
+                let source = IntervalsSource::AllOf {
+                    sources: rules.iter().map(|rule| rule.build(context, field_name)).collect(),
+                    ordered: ordered,
+                    max_gaps: max_gaps,
+                };
+
+                (source, filter)
+            }
+            IntervalsRule::AnyOf{ref rules, ref filter} => {
+                let source = IntervalsSource::AnyOf {
+                    sources: rules.iter().map(|rule| rule.build(context, field_name)).collect(),
+                };
+
+                (source, filter)
+            }
+        };
+
+        match *filter {
+            Some((filter, ref filter_rule)) => {
+                IntervalsSource::Filtered {
+                    source: Box::new(source),
+                    filter: filter,
+                    filter_source: Box::new(filter_rule.build(context, field_name)),
+                }
+            }
+            None => source,
+        }
+    }
+}
+
+
+#[derive(Debug)]
+struct IntervalsQueryBuilder {
+    field: String,
+    rule: IntervalsRule,
+    boost: f32,
+}
+
+
+impl QueryBuilder for IntervalsQueryBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+        let field = match schema.get_field_by_name(&self.field) {
+            Some(field) => field,
+            None => return Query::None,
+        };
+
+        Query::Intervals {
+            field: field,
+            source: self.rule.build(context, &self.field),
+            scorer: TermScorer::default_with_boost(self.boost),
+        }
+    }
+}
+
+
+fn parse_ordered(json: &Json) -> Result<bool, QueryParseError> {
+    json.as_bool().ok_or(QueryParseError::InvalidValue)
+}
+
+
+/// Parses "max_gaps", where -1 means there is no limit
+fn parse_max_gaps(json: &Json) -> Result<Option<u32>, QueryParseError> {
+    match json.as_i64() {
+        Some(-1) => Ok(None),
+        Some(max_gaps) if max_gaps >= 0 => Ok(Some(max_gaps as u32)),
+        _ => Err(QueryParseError::InvalidValue),
+    }
+}
+
+
+fn parse_filter(json: &Json) -> Result<(IntervalFilter, Box<IntervalsRule>), QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    if object.len() != 1 {
+        return Err(QueryParseError::ExpectedSingleKey);
+    }
+
+    let (filter_name, rule_json) = object.iter().next().unwrap();
+    let filter = match filter_name.as_ref() {
+        "containing" => IntervalFilter::Containing,
+        "contained_by" => IntervalFilter::ContainedBy,
+        "not_containing" => IntervalFilter::NotContaining,
+        "not_contained_by" => IntervalFilter::NotContainedBy,
+        "overlapping" => IntervalFilter::Overlapping,
+        "not_overlapping" => IntervalFilter::NotOverlapping,
+        "before" => IntervalFilter::Before,
+        "after" => IntervalFilter::After,
+        _ => return Err(QueryParseError::UnrecognisedKey(filter_name.clone())),
+    };
+
+    Ok((filter, Box::new(parse_rule(rule_json)?)))
+}
+
+
+fn parse_rules(json: &Json) -> Result<Vec<IntervalsRule>, QueryParseError> {
+    let array = json.as_array().ok_or(QueryParseError::ExpectedArray)?;
+    array.iter().map(parse_rule).collect()
+}
+
+
+/// Parses an object with a single key naming the type of rule
+fn parse_rule(json: &Json) -> Result<IntervalsRule, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    if object.len() != 1 {
+        return Err(QueryParseError::ExpectedSingleKey);
+    }
+
+    let (rule_type, rule_json) = object.iter().next().unwrap();
+    parse_rule_of_type(rule_type, rule_json)
+}
+
+
+fn parse_rule_of_type(rule_type: &str, rule_json: &Json) -> Result<IntervalsRule, QueryParseError> {
+    let rule_object = rule_json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut query = None;
+    let mut rules = None;
+    let mut ordered = false;
+    let mut max_gaps = None;
+    let mut filter = None;
+
+    for (key, value) in rule_object.iter() {
+        match (rule_type, key.as_ref()) {
+            ("match", "query") => {
+                query = Some(parse_string(value)?);
+            }
+            ("all_of", "intervals") | ("any_of", "intervals") => {
+                rules = Some(parse_rules(value)?);
+            }
+            ("match", "ordered") | ("all_of", "ordered") => {
+                ordered = parse_ordered(value)?;
+            }
+            ("match", "max_gaps") | ("all_of", "max_gaps") => {
+                max_gaps = parse_max_gaps(value)?;
+            }
+            ("match", "filter") | ("all_of", "filter") | ("any_of", "filter") => {
+                filter = Some(parse_filter(value)?);
+            }
+            ("match", _) | ("all_of", _) | ("any_of", _) => return Err(QueryParseError::UnrecognisedKey(key.clone())),
+            _ => return Err(QueryParseError::UnrecognisedQueryType(rule_type.to_string())),
+        }
+    }
+
+    match rule_type {
+        "match" => Ok(IntervalsRule::Match {
+            query: query.ok_or(QueryParseError::ExpectedKey("query"))?,
+            ordered: ordered,
+            max_gaps: max_gaps,
+            filter: filter,
+        }),
+        "all_of" => Ok(IntervalsRule::AllOf {
+            rules: rules.ok_or(QueryParseError::ExpectedKey("intervals"))?,
+            ordered: ordered,
+            max_gaps: max_gaps,
+            filter: filter,
+        }),
+        "any_of" => Ok(IntervalsRule::AnyOf {
+            rules: rules.ok_or(QueryParseError::ExpectedKey("intervals"))?,
+            filter: filter,
+        }),
+        _ => Err(QueryParseError::UnrecognisedQueryType(rule_type.to_string())),
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let field_name = if object.len() == 1 {
+        object.keys().collect::<Vec<_>>()[0]
+    } else {
+        return Err(QueryParseError::ExpectedSingleKey)
+    };
+
+    let object = object.get(field_name).unwrap().as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    // Get configuration. The rule is the only key other than "boost"
+    let mut rule = None;
+    let mut boost = 1.0f32;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "boost" => {
+                boost = parse_float(value)?;
+            }
+            "match" | "all_of" | "any_of" => {
+                if rule.is_some() {
+                    return Err(QueryParseError::ExpectedSingleKey);
+                }
+
+                rule = Some(parse_rule_of_type(key, value)?);
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(Box::new(IntervalsQueryBuilder {
+        field: field_name.clone(),
+        rule: rule.ok_or(QueryParseError::ExpectedKey("match"))?,
+        boost: boost,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use search::{Term, Query, TermScorer};
+    use search::schema::{Schema, FieldType, FIELD_INDEXED};
+    use search::query::intervals::{IntervalsSource, IntervalFilter};
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    #[test]
+    fn test_intervals_query() {
+        let mut schema = Schema::new();
+        let text_field = schema.add_field("my_text".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "my_text": {
+                "all_of": {
+                    "ordered": true,
+                    "intervals": [
+                        {"match": {"query": "my food", "max_gaps": 1, "ordered": true}},
+                        {"any_of": {"intervals": [{"match": {"query": "water"}}, {"match": {"query": "porridge"}}]}}
+                    ]
+                },
+                "boost": 2
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Intervals {
+            field: text_field,
+            source: IntervalsSource::AllOf {
+                sources: vec![
+                    IntervalsSource::AllOf {
+                        sources: vec![
+                            IntervalsSource::Term(Term::from_string("my")),
+                            IntervalsSource::Term(Term::from_string("food")),
+                        ],
+                        ordered: true,
+                        max_gaps: Some(1),
+                    },
+                    IntervalsSource::AnyOf {
+                        sources: vec![
+                            IntervalsSource::Term(Term::from_string("water")),
+                            IntervalsSource::Term(Term::from_string("porridge")),
+                        ],
+                    },
+                ],
+                ordered: true,
+                max_gaps: None,
+            },
+            scorer: TermScorer::default_with_boost(2.0f32),
+        }));
+    }
+
+    #[test]
+    fn test_intervals_query_with_filter() {
+        let mut schema = Schema::new();
+        let text_field = schema.add_field("my_text".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "my_text": {
+                "match": {
+                    "query": "hot",
+                    "filter": {
+                        "not_containing": {"match": {"query": "salty"}}
+                    }
+                }
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Intervals {
+            field: text_field,
+            source: IntervalsSource::Filtered {
+                source: Box::new(IntervalsSource::Term(Term::from_string("hot"))),
+                filter: IntervalFilter::NotContaining,
+                filter_source: Box::new(IntervalsSource::Term(Term::from_string("salty"))),
+            },
+            scorer: TermScorer::default(),
+        }));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_rules() {
+        let query = parse(&json!({"my_text": {"match": {"query": "hot", "max_gaps": -2}}}));
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+
+        let query = parse(&json!({"my_text": {"any_of": {"intervals": [], "ordered": true}}}));
+        assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("ordered".to_string())));
+
+        let query = parse(&json!({"my_text": {"all_of": {"intervals": [{"span_near": {}}]}}}));
+        assert_eq!(query.err(), Some(QueryParseError::UnrecognisedQueryType("span_near".to_string())));
+
+        let query = parse(&json!({"my_text": {"match": {"query": "hot", "filter": {"near": {"match": {"query": "cold"}}}}}}));
+        assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("near".to_string())));
+    }
+}
@@ -5,6 +5,7 @@ use search::Query;
 use search::schema::Schema;
 
 use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::utils::parse_float;
 
 
 #[derive(Debug)]
@@ -22,8 +23,12 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
     let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
 
     // Get configuration
-    for (key, _value) in object.iter() {
+    for (key, value) in object.iter() {
         match &key[..] {
+            "boost" => {
+                // Accepted for consistency with "match_all", though nothing is matched to boost
+                parse_float(value)?;
+            }
             _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
         }
     }
@@ -55,6 +60,27 @@ mod tests {
         assert_eq!(query, Ok(Query::None))
     }
 
+    #[test]
+    fn test_with_boost() {
+        let schema = Schema::new();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"boost\": 2.0
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::None));
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"boost\": \"2\"
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedFloat));
+    }
+
     #[test]
     fn test_gives_error_for_incorrect_type() {
         // Array
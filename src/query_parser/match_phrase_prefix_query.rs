@@ -0,0 +1,214 @@
+//! Parses "match_phrase_prefix" queries
+
+use serde_json::Value as Json;
+use search::{Term, Token, Query, TermScorer};
+use search::schema::Schema;
+use search::query::multi_term_selector::MultiTermSelector;
+
+use mapping::FieldSearchOptions;
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::utils::{parse_string, parse_float};
+
+
+#[derive(Debug)]
+struct MatchPhrasePrefixQueryBuilder {
+    field: String,
+    query: String,
+    slop: u32,
+    max_expansions: usize,
+    boost: f32,
+}
+
+
+impl QueryBuilder for MatchPhrasePrefixQueryBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+        let field = match schema.get_field_by_name(&self.field) {
+            Some(field) => field,
+            None => return Query::None,
+        };
+
+        // Get search options for field
+        let field_search_options = match context.index_metadata {
+            Some(index_metadata) => {
+                match index_metadata.get_field_mapping(&self.field) {
+                    Some(field_mapping) => field_mapping.get_search_options(),
+                    None => FieldSearchOptions::default(),  // TODO: error?
+                }
+            }
+            None => FieldSearchOptions::default(),  // TODO: error?
+        };
+
+        // Tokenise query string
+        let tokens = match field_search_options.analyzer {
+            Some(ref analyzer) => {
+                let token_stream = analyzer.initialise(&self.query);
+                token_stream.collect::<Vec<Token>>()
+            }
+            None => {
+                vec![Token {term: Term::from_string(&self.query), position: 1}]
+            }
+        };
+
+        let mut terms = tokens.into_iter().map(|token| token.term).collect::<Vec<_>>();
+
+        // The last term is expanded into the first terms that start with it
+        let last_term_selector = match terms.pop() {
+            Some(last_term) => {
+                MultiTermSelector::Limit {
+                    selector: Box::new(MultiTermSelector::Prefix(String::from_utf8_lossy(last_term.as_bytes()).into_owned())),
+                    max_terms: self.max_expansions,
+                }
+            }
+            None => return Query::None,
+        };
+
+        // A phrase of one term is the same as a prefix query
+        let query = if terms.is_empty() {
+            Query::MultiTerm {
+                field: field,
+                term_selector: last_term_selector,
+                scorer: TermScorer::default(),
+            }
+        } else {
+            Query::PhrasePrefix {
+                field: field,
+                terms: terms,
+                last_term_selector: last_term_selector,
+                slop: self.slop,
+                scorer: TermScorer::default(),
+            }
+        };
+
+        // Add boost
+        query.boost(self.boost)
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let field_name = if object.len() == 1 {
+        object.keys().collect::<Vec<_>>()[0]
+    } else {
+        return Err(QueryParseError::ExpectedSingleKey)
+    };
+
+    // Get configuration
+    let mut query = None;
+    let mut slop = 0;
+    let mut max_expansions = 50;
+    let mut boost = 1.0f32;
+
+    match object.get(field_name).unwrap() {
+        s @ &Json::String(_) => query = Some(parse_string(s)?),
+        &Json::Object(ref inner_object) => {
+            for (key, value) in inner_object.iter() {
+                match key.as_ref() {
+                    "query" => {
+                        query = Some(parse_string(value)?);
+                    }
+                    "slop" => {
+                        slop = value.as_u64().ok_or(QueryParseError::InvalidValue)? as u32;
+                    }
+                    "max_expansions" => {
+                        max_expansions = match value.as_u64() {
+                            Some(max_expansions) if max_expansions > 0 => max_expansions as usize,
+                            _ => return Err(QueryParseError::InvalidValue),
+                        };
+                    }
+                    "boost" => {
+                        boost = parse_float(value)?;
+                    }
+                    _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+                }
+            }
+        }
+        _ => return Err(QueryParseError::ExpectedObjectOrString),
+    }
+
+    Ok(Box::new(MatchPhrasePrefixQueryBuilder {
+        field: field_name.clone(),
+        query: query.ok_or(QueryParseError::ExpectedKey("query"))?,
+        slop: slop,
+        max_expansions: max_expansions,
+        boost: boost,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use search::{Term, Query, TermScorer};
+    use search::schema::{Schema, FieldType, FIELD_INDEXED};
+    use search::query::multi_term_selector::MultiTermSelector;
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    #[test]
+    fn test_match_phrase_prefix_query() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"query\": \"Quick brown f\",
+                \"slop\": 1,
+                \"max_expansions\": 10
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::PhrasePrefix {
+            field: foo_field,
+            terms: vec![Term::from_string("quick"), Term::from_string("brown")],
+            last_term_selector: MultiTermSelector::Limit {
+                selector: Box::new(MultiTermSelector::Prefix("f".to_string())),
+                max_terms: 10,
+            },
+            slop: 1,
+            scorer: TermScorer::default(),
+        }));
+    }
+
+    #[test]
+    fn test_single_term_match_phrase_prefix_query() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": \"ba\"
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::MultiTerm {
+            field: foo_field,
+            term_selector: MultiTermSelector::Limit {
+                selector: Box::new(MultiTermSelector::Prefix("ba".to_string())),
+                max_terms: 50,
+            },
+            scorer: TermScorer::default(),
+        }));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_max_expansions() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"query\": \"bar baz\",
+                \"max_expansions\": 0
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+    }
+}
@@ -0,0 +1,190 @@
+//! Parses "match_phrase" queries
+
+use serde_json::Value as Json;
+use search::{Term, Token, Query, TermScorer};
+use search::schema::Schema;
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::utils::{parse_string, parse_float, search_analyzer};
+
+
+#[derive(Debug)]
+struct MatchPhraseQueryBuilder {
+    field: String,
+    query: String,
+    slop: u32,
+    analyzer: Option<String>,
+    boost: f32,
+}
+
+
+impl QueryBuilder for MatchPhraseQueryBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+        let field = match schema.get_field_by_name(&self.field) {
+            Some(field) => field,
+            None => return Query::None,
+        };
+
+        // Tokenise query string
+        let tokens = match search_analyzer(context.index_metadata, &self.field, self.analyzer.as_ref().map(|analyzer| &analyzer[..])) {
+            Some(ref analyzer) => {
+                let token_stream = analyzer.initialise(&self.query);
+                token_stream.collect::<Vec<Token>>()
+            }
+            None => {
+                vec![Token {term: Term::from_string(&self.query), position: 1}]
+            }
+        };
+
+        let mut terms = tokens.into_iter().map(|token| token.term).collect::<Vec<_>>();
+
+        // A phrase of one term is the same as a term query
+        let query = match terms.len() {
+            0 => Query::None,
+            1 => Query::Term {
+                field: field,
+                term: terms.pop().unwrap(),
+                scorer: TermScorer::default(),
+            },
+            _ => Query::Phrase {
+                field: field,
+                terms: terms,
+                slop: self.slop,
+                scorer: TermScorer::default(),
+            },
+        };
+
+        // Add boost
+        query.boost(self.boost)
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let field_name = if object.len() == 1 {
+        object.keys().collect::<Vec<_>>()[0]
+    } else {
+        return Err(QueryParseError::ExpectedSingleKey)
+    };
+
+    // Get configuration
+    let mut query = None;
+    let mut slop = 0;
+    let mut analyzer = None;
+    let mut boost = 1.0f32;
+
+    match object.get(field_name).unwrap() {
+        s @ &Json::String(_) => query = Some(parse_string(s)?),
+        &Json::Object(ref inner_object) => {
+            for (key, value) in inner_object.iter() {
+                match key.as_ref() {
+                    "query" => {
+                        query = Some(parse_string(value)?);
+                    }
+                    "slop" => {
+                        slop = value.as_u64().ok_or(QueryParseError::InvalidValue)? as u32;
+                    }
+                    "analyzer" => {
+                        analyzer = Some(parse_string(value)?);
+                    }
+                    "boost" => {
+                        boost = parse_float(value)?;
+                    }
+                    _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+                }
+            }
+        }
+        _ => return Err(QueryParseError::ExpectedObjectOrString),
+    }
+
+    Ok(Box::new(MatchPhraseQueryBuilder {
+        field: field_name.clone(),
+        query: query.ok_or(QueryParseError::ExpectedKey("query"))?,
+        slop: slop,
+        analyzer: analyzer,
+        boost: boost,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use search::{Term, Query, TermScorer};
+    use search::schema::{Schema, FieldType, FIELD_INDEXED};
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    #[test]
+    fn test_match_phrase_query() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"query\": \"Quick brown fox\",
+                \"slop\": 2,
+                \"boost\": 2.0
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Phrase {
+            field: foo_field,
+            terms: vec![Term::from_string("quick"), Term::from_string("brown"), Term::from_string("fox")],
+            slop: 2,
+            scorer: TermScorer::default_with_boost(2.0f32),
+        }));
+    }
+
+    #[test]
+    fn test_single_term_match_phrase_query() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": \"bar\"
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Term {
+            field: foo_field,
+            term: Term::from_string("bar"),
+            scorer: TermScorer::default(),
+        }));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_slop() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"query\": \"bar baz\",
+                \"slop\": -1
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+    }
+
+    #[test]
+    fn test_gives_error_for_missing_query() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"slop\": 2
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("query")));
+    }
+}
@@ -1,13 +1,16 @@
 //! Parses "match" queries
 
 use serde_json::Value as Json;
-use search::{Term, Token, Query, TermScorer};
-use search::schema::Schema;
-
-use mapping::FieldSearchOptions;
+use search::{Term, Token, Query, TermScorer, MultiTermSelector};
+use search::schema::{Schema, FieldId};
+use search::query::levenshtein::LevenshteinAutomaton;
 
 use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
-use query_parser::utils::{parse_string, parse_float, Operator, parse_operator};
+use query_parser::utils::{parse_string, parse_float, Operator, parse_operator, MinimumShouldMatch, parse_minimum_should_match, Fuzziness, parse_fuzziness, search_analyzer};
+
+
+/// The number of terms that each fuzzy term of the query expands to
+const FUZZY_MAX_EXPANSIONS: usize = 50;
 
 
 #[derive(Debug)]
@@ -15,25 +18,45 @@ struct MatchQueryBuilder {
     field: String,
     query: String,
     operator: Operator,
+    minimum_should_match: Option<MinimumShouldMatch>,
+    cutoff_frequency: Option<f64>,
+    fuzziness: Option<Fuzziness>,
+    analyzer: Option<String>,
     boost: f32,
 }
 
 
-impl QueryBuilder for MatchQueryBuilder {
-    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
-        // Get search options for field
-        let field_search_options = match context.index_metadata {
-            Some(index_metadata) => {
-                match index_metadata.get_field_mapping(&self.field) {
-                    Some(field_mapping) => field_mapping.get_search_options(),
-                    None => FieldSearchOptions::default(),  // TODO: error?
-                }
+impl MatchQueryBuilder {
+    fn build_term_query(&self, field: FieldId, term: Term) -> Query {
+        if let Some(ref fuzziness) = self.fuzziness {
+            let value = String::from_utf8_lossy(term.as_bytes()).into_owned();
+            let max_distance = fuzziness.max_distance(&value);
+
+            if max_distance > 0 {
+                return Query::MultiTerm {
+                    field: field,
+                    term_selector: MultiTermSelector::Fuzzy {
+                        automaton: LevenshteinAutomaton::new(&value, max_distance, 0).with_transpositions(true),
+                        max_expansions: FUZZY_MAX_EXPANSIONS,
+                    },
+                    scorer: TermScorer::default(),
+                };
             }
-            None => FieldSearchOptions::default(),  // TODO: error?
-        };
+        }
 
+        Query::Term {
+            field: field,
+            term: term,
+            scorer: TermScorer::default(),
+        }
+    }
+}
+
+
+impl QueryBuilder for MatchQueryBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
         // Tokenise query string
-        let tokens = match field_search_options.analyzer {
+        let tokens = match search_analyzer(context.index_metadata, &self.field, self.analyzer.as_ref().map(|analyzer| &analyzer[..])) {
             Some(ref analyzer) => {
                 let token_stream = analyzer.initialise(&self.query);
                 token_stream.collect::<Vec<Token>>()
@@ -43,18 +66,38 @@ impl QueryBuilder for MatchQueryBuilder {
             }
         };
 
+        let field = schema.get_field_by_name(&self.field).unwrap();
+        let terms = tokens.into_iter().map(|token| token.term).collect::<Vec<_>>();
+
+        // Which terms are high frequency isn't known until the query is run
+        // Fuzzy terms have no single frequency so they don't use a cutoff
+        if let (Some(cutoff_frequency), None) = (self.cutoff_frequency, self.fuzziness.as_ref()) {
+            if terms.len() > 1 {
+                let query = Query::CommonTerms {
+                    field: field,
+                    terms: terms,
+                    cutoff_frequency: cutoff_frequency,
+                    require_all: self.operator == Operator::And,
+                    scorer: TermScorer::default(),
+                };
+
+                return query.boost(self.boost);
+            }
+        }
+
         // Create a term query for each token
         let mut sub_queries = Vec::new();
-        for token in tokens {
-            sub_queries.push(Query::Term {
-                field: schema.get_field_by_name(&self.field).unwrap(),
-                term: token.term,
-                scorer: TermScorer::default(),
-            });
+        for term in terms {
+            sub_queries.push(self.build_term_query(field, term));
         }
 
         // Combine the term queries
+        let minimum_should_match = self.minimum_should_match.as_ref().map_or(0, |minimum_should_match| minimum_should_match.resolve(sub_queries.len()));
+
         let query = match sub_queries.len() {
+            _ if self.operator == Operator::Or && minimum_should_match > 0 => {
+                Query::minimum_should_match(sub_queries, minimum_should_match)
+            }
             0 => Query::None,
             1 => sub_queries.pop().unwrap(),
             _ => {
@@ -88,6 +131,10 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
     let mut query = String::new();
     let mut boost = 1.0f32;
     let mut operator = Operator::Or;
+    let mut minimum_should_match = None;
+    let mut cutoff_frequency = None;
+    let mut fuzziness = None;
+    let mut analyzer = None;
 
     match object.get(field_name).unwrap() {
         s @ &Json::String(_) => query = parse_string(s)?,
@@ -106,6 +153,24 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
                     "operator" => {
                         operator = parse_operator(value)?;
                     }
+                    "minimum_should_match" => {
+                        minimum_should_match = Some(parse_minimum_should_match(value)?);
+                    }
+                    "cutoff_frequency" => {
+                        let value = parse_float(value)?;
+
+                        if value <= 0.0 {
+                            return Err(QueryParseError::InvalidValue);
+                        }
+
+                        cutoff_frequency = Some(value as f64);
+                    }
+                    "fuzziness" => {
+                        fuzziness = Some(parse_fuzziness(value)?);
+                    }
+                    "analyzer" => {
+                        analyzer = Some(parse_string(value)?);
+                    }
                     _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
                 }
             }
@@ -121,6 +186,10 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
         field: field_name.clone(),
         query: query,
         operator: operator,
+        minimum_should_match: minimum_should_match,
+        cutoff_frequency: cutoff_frequency,
+        fuzziness: fuzziness,
+        analyzer: analyzer,
         boost: boost,
     }))
 }
@@ -130,8 +199,12 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
 mod tests {
     use serde_json;
 
-    use search::{Term, Query, TermScorer};
+    use search::{Term, Query, TermScorer, MultiTermSelector};
     use search::schema::{Schema, FieldType, FIELD_INDEXED};
+    use search::query::levenshtein::LevenshteinAutomaton;
+    use analysis::AnalyzerSpec;
+    use analysis::tokenizers::TokenizerSpec;
+    use index::metadata::IndexMetadata;
 
     use query_parser::{QueryBuildContext, QueryParseError};
 
@@ -186,6 +259,40 @@ mod tests {
         }))
     }
 
+    #[test]
+    fn test_minimum_should_match() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let term = |value| Query::Term {
+            field: foo_field,
+            term: Term::from_string(value),
+            scorer: TermScorer::default(),
+        };
+
+        let query = parse(&json!({
+            "foo": {
+                "query": "bar baz quux",
+                "minimum_should_match": "2<75%"
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::MinimumShouldMatch {
+            queries: vec![term("bar"), term("baz"), term("quux")],
+            minimum: 2,
+        }));
+
+        // More than the number of terms can't match anything
+        let query = parse(&json!({
+            "foo": {
+                "query": "bar",
+                "minimum_should_match": 2
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::None));
+    }
+
     #[test]
     fn test_simple_multi_term_match_query() {
         let mut schema = Schema::new();
@@ -285,6 +392,108 @@ mod tests {
         }))
     }
 
+    #[test]
+    fn test_with_fuzziness() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "foo": {
+                "query": "an kitten",
+                "operator": "and",
+                "fuzziness": "AUTO",
+                "boost": 2.0
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        // Terms that are too short to have any edits are matched exactly
+        assert_eq!(query, Ok(Query::Conjunction {
+            queries: vec![
+                Query::Term {
+                    field: foo_field,
+                    term: Term::from_string("an"),
+                    scorer: TermScorer::default_with_boost(2.0f32),
+                },
+                Query::MultiTerm {
+                    field: foo_field,
+                    term_selector: MultiTermSelector::Fuzzy {
+                        automaton: LevenshteinAutomaton::new("kitten", 2, 0).with_transpositions(true),
+                        max_expansions: 50,
+                    },
+                    scorer: TermScorer::default_with_boost(2.0f32),
+                },
+            ],
+        }))
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_fuzziness() {
+        let query = parse(&json!({
+            "foo": {
+                "query": "bar",
+                "fuzziness": 3
+            }
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+    }
+
+    #[test]
+    fn test_with_analyzer() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let mut index_metadata = IndexMetadata::default();
+        index_metadata.insert_analyzer("no_lowercase".to_string(), AnalyzerSpec {
+            tokenizer: TokenizerSpec::Standard,
+            filters: vec![],
+        });
+
+        let query = parse(&json!({
+            "foo": {
+                "query": "Bar",
+                "analyzer": "no_lowercase"
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new().set_index_metadata(&index_metadata), &schema)));
+
+        assert_eq!(query, Ok(Query::Term {
+            field: foo_field,
+            term: Term::from_string("Bar"),
+            scorer: TermScorer::default(),
+        }))
+    }
+
+    #[test]
+    fn test_with_cutoff_frequency() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"query\": \"to be or not to be\",
+                \"cutoff_frequency\": 0.01,
+                \"operator\": \"and\"
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::CommonTerms {
+            field: foo_field,
+            terms: vec![
+                Term::from_string("to"),
+                Term::from_string("be"),
+                Term::from_string("or"),
+                Term::from_string("not"),
+                Term::from_string("to"),
+                Term::from_string("be"),
+            ],
+            cutoff_frequency: 0.01f32 as f64,
+            require_all: true,
+            scorer: TermScorer::default(),
+        }))
+    }
+
     #[test]
     fn test_gives_error_for_incorrect_type() {
         // Array
@@ -0,0 +1,129 @@
+//! Parses "missing" queries
+
+use serde_json::Value as Json;
+use search::Query;
+use search::schema::Schema;
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::utils::{parse_string, parse_float};
+
+
+#[derive(Debug)]
+struct MissingQueryBuilder {
+    field: String,
+    boost: f32,
+}
+
+
+impl QueryBuilder for MissingQueryBuilder {
+    fn build(&self, _context: &QueryBuildContext, schema: &Schema) -> Query {
+        match schema.get_field_by_name(&self.field) {
+            Some(field) => {
+                // Nulls and empty arrays aren't indexed, so documents that only
+                // have those in the field aren't matched by the exists query
+                Query::Exclude {
+                    query: Box::new(Query::All {
+                        score: self.boost,
+                    }),
+                    exclude: Box::new(Query::Exists {
+                        field: field,
+                        score: 1.0f32,
+                    }),
+                }
+            }
+            None => {
+                // No document has a value in a field that isn't in the schema
+                Query::All {
+                    score: self.boost,
+                }
+            }
+        }
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut field = None;
+    let mut boost = 1.0f32;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "field" => {
+                field = Some(parse_string(value)?);
+            }
+            "boost" => {
+                boost = parse_float(value)?;
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(Box::new(MissingQueryBuilder {
+        field: field.ok_or(QueryParseError::ExpectedKey("field"))?,
+        boost: boost,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use search::Query;
+    use search::schema::{Schema, FieldType, FIELD_INDEXED};
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    #[test]
+    fn test_missing_query() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"field\": \"foo\",
+            \"boost\": 2.0
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Exclude {
+            query: Box::new(Query::All {
+                score: 2.0f32,
+            }),
+            exclude: Box::new(Query::Exists {
+                field: foo_field,
+                score: 1.0f32,
+            }),
+        }));
+    }
+
+    #[test]
+    fn test_missing_field() {
+        let schema = Schema::new();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"field\": \"foo\"
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::All {
+            score: 1.0f32,
+        }));
+    }
+
+    #[test]
+    fn test_gives_error_for_missing_field_key() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"boost\": 2.0
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("field")));
+    }
+}
@@ -1,7 +1,10 @@
 //! Parses Elasticsearch Query DSL
 
 pub mod utils;
+pub mod date_math;
 pub mod match_query;
+pub mod match_phrase_query;
+pub mod match_phrase_prefix_query;
 pub mod multi_match_query;
 pub mod match_all_query;
 pub mod match_none_query;
@@ -9,23 +12,51 @@ pub mod filtered_query;
 pub mod terms_query;
 pub mod term_query;
 pub mod prefix_query;
+pub mod wildcard_query;
+pub mod fuzzy_query;
+pub mod exists_query;
+pub mod missing_query;
+pub mod ids_query;
+pub mod range_query;
+pub mod rank_feature_query;
+pub mod geo_shape_query;
+pub mod geo_distance_query;
 pub mod and_query;
 pub mod or_query;
 pub mod not_query;
 pub mod constant_score_query;
+pub mod bool_query;
+pub mod query_string_syntax;
+pub mod query_string_query;
+pub mod simple_query_string_query;
+pub mod function_score_query;
+pub mod distance_feature_query;
+pub mod intervals_query;
+pub mod boosting_query;
+pub mod pinned_query;
+pub mod nested_query;
+pub mod span_query;
+pub mod percolate_query;
+pub mod script_query;
 
-use std::fmt::Debug;
+use std::fmt::{self, Debug};
 
 use serde_json::Value as Json;
 use search::Query;
 use search::schema::Schema;
 
+use index::Index;
 use index::metadata::IndexMetadata;
+use cluster::metadata::ClusterMetadata;
+
+use self::query_string_syntax::QueryStringSyntaxError;
 
 
 #[derive(Debug, Clone)]
 pub struct QueryBuildContext<'a> {
+    pub index: Option<&'a Index>,
     pub index_metadata: Option<&'a IndexMetadata>,
+    pub cluster_metadata: Option<&'a ClusterMetadata>,
     score_required: bool,
 }
 
@@ -33,22 +64,42 @@ pub struct QueryBuildContext<'a> {
 impl<'a> QueryBuildContext<'a> {
     pub fn new() -> QueryBuildContext<'a> {
         QueryBuildContext {
+            index: None,
             index_metadata: None,
+            cluster_metadata: None,
             score_required: true
         }
     }
 
+    #[inline]
+    pub fn set_index(mut self, index: &'a Index) -> QueryBuildContext<'a> {
+        self.index = Some(index);
+        self
+    }
+
     #[inline]
     pub fn set_index_metadata(mut self, index_metadata: &'a IndexMetadata) -> QueryBuildContext<'a> {
         self.index_metadata = Some(index_metadata);
         self
     }
 
+    #[inline]
+    pub fn set_cluster_metadata(mut self, cluster_metadata: &'a ClusterMetadata) -> QueryBuildContext<'a> {
+        self.cluster_metadata = Some(cluster_metadata);
+        self
+    }
+
     #[inline]
     pub fn no_score(mut self) -> QueryBuildContext<'a> {
         self.score_required = false;
         self
     }
+
+    /// Returns false if the query is being built as a filter
+    #[inline]
+    pub fn score_required(&self) -> bool {
+        self.score_required
+    }
 }
 
 
@@ -63,13 +114,79 @@ pub enum QueryParseError {
     ExpectedString,
     ExpectedFloat,
     ExpectedObjectOrString,
+    ExpectedObjectOrArray,
     InvalidValue,
     ExpectedSingleKey,
     InvalidOperator,
+    InvalidQueryString(QueryStringSyntaxError),
+    TermsLookupFailed(String),
+
+    /// An error in the body of a query, or in a query inside it. The path leads
+    /// from the type of the query to the one that failed, such as "bool.must[1].match"
+    InQuery(String, Box<QueryParseError>),
+}
+
+
+impl QueryParseError {
+    /// Records that the error happened at `path` in the body of a query
+    fn within(self, path: &str) -> QueryParseError {
+        match self {
+            QueryParseError::InQuery(inner_path, error) => QueryParseError::InQuery(join_path(path, &inner_path), error),
+            error => QueryParseError::InQuery(path.to_string(), Box::new(error)),
+        }
+    }
+
+    /// The path to the query that failed, such as "bool.must[1].match". None if
+    /// the error isn't in a query (eg, the query itself isn't an object)
+    pub fn path(&self) -> Option<&str> {
+        match *self {
+            QueryParseError::InQuery(ref path, _) => Some(path),
+            _ => None,
+        }
+    }
+
+    /// The error, without where it happened
+    pub fn innermost(&self) -> &QueryParseError {
+        match *self {
+            QueryParseError::InQuery(_, ref error) => error,
+            ref error => error,
+        }
+    }
+}
+
+
+impl fmt::Display for QueryParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            QueryParseError::UnrecognisedQueryType(ref query_type) => write!(f, "unrecognised query type {:?}", query_type),
+            QueryParseError::FieldDoesntExist(ref field) => write!(f, "field {:?} doesn't exist", field),
+            QueryParseError::UnrecognisedKey(ref key) => write!(f, "unrecognised key {:?}", key),
+            QueryParseError::ExpectedKey(key) => write!(f, "expected key {:?}", key),
+            QueryParseError::ExpectedObject => write!(f, "expected an object"),
+            QueryParseError::ExpectedArray => write!(f, "expected an array"),
+            QueryParseError::ExpectedString => write!(f, "expected a string"),
+            QueryParseError::ExpectedFloat => write!(f, "expected a number"),
+            QueryParseError::ExpectedObjectOrString => write!(f, "expected an object or a string"),
+            QueryParseError::ExpectedObjectOrArray => write!(f, "expected an object or an array"),
+            QueryParseError::InvalidValue => write!(f, "invalid value"),
+            QueryParseError::ExpectedSingleKey => write!(f, "expected an object with a single key"),
+            QueryParseError::InvalidOperator => write!(f, "invalid operator, expected \"and\" or \"or\""),
+            QueryParseError::InvalidQueryString(ref error) => write!(f, "invalid query string: {}", error),
+            QueryParseError::TermsLookupFailed(ref error) => write!(f, "terms lookup failed: {}", error),
+            QueryParseError::InQuery(_, ref error) => write!(f, "{}", error),
+        }
+    }
 }
 
 
 pub trait QueryBuilder: Debug {
+    /// Checks anything the query needs that can only be found when searching,
+    /// such as documents that it looks up in other indices. This is called
+    /// before building, so these can be reported as query errors
+    fn check(&self, _context: &QueryBuildContext) -> Result<(), QueryParseError> {
+        Ok(())
+    }
+
     fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query;
 }
 
@@ -77,6 +194,9 @@ pub trait QueryBuilder: Debug {
 fn get_query_parser(query_name: &str) -> Option<fn(&Json) -> Result<Box<QueryBuilder>, QueryParseError>> {
     match query_name {
         "match" => Some(match_query::parse),
+        "match_phrase" => Some(match_phrase_query::parse),
+        "match_phrase_prefix" => Some(match_phrase_prefix_query::parse),
+        "intervals" => Some(intervals_query::parse),
         "multi_match" => Some(multi_match_query::parse),
         "match_all" => Some(match_all_query::parse),
         "match_none" => Some(match_none_query::parse),
@@ -85,15 +205,94 @@ fn get_query_parser(query_name: &str) -> Option<fn(&Json) -> Result<Box<QueryBui
         "in" => Some(terms_query::parse),
         "term" => Some(term_query::parse),
         "prefix" => Some(prefix_query::parse),
+        "wildcard" => Some(wildcard_query::parse),
+        "fuzzy" => Some(fuzzy_query::parse),
+        "exists" => Some(exists_query::parse),
+        "missing" => Some(missing_query::parse),
+        "ids" => Some(ids_query::parse),
+        "range" => Some(range_query::parse),
+        "rank_feature" => Some(rank_feature_query::parse),
+        "distance_feature" => Some(distance_feature_query::parse),
+        "geo_shape" => Some(geo_shape_query::parse),
+        "shape" => Some(geo_shape_query::parse_cartesian),
+        "geo_distance" => Some(geo_distance_query::parse),
         "and" => Some(and_query::parse),
         "or" => Some(or_query::parse),
         "not" => Some(not_query::parse),
         "constant_score" => Some(constant_score_query::parse),
+        "bool" => Some(bool_query::parse),
+        "query_string" => Some(query_string_query::parse),
+        "simple_query_string" => Some(simple_query_string_query::parse),
+        "function_score" => Some(function_score_query::parse),
+        "boosting" => Some(boosting_query::parse),
+        "pinned" => Some(pinned_query::parse),
+        "nested" => Some(nested_query::parse),
+        "span_term" => Some(span_query::parse_span_term),
+        "span_near" => Some(span_query::parse_span_near),
+        "span_first" => Some(span_query::parse_span_first),
+        "span_or" => Some(span_query::parse_span_or),
+        "percolate" => Some(percolate_query::parse),
+        "script" => Some(script_query::parse),
         _ => None
     }
 }
 
 
+#[derive(Debug)]
+struct NamedQueryBuilder {
+    name: String,
+    query: Box<QueryBuilder>,
+}
+
+
+impl QueryBuilder for NamedQueryBuilder {
+    fn check(&self, context: &QueryBuildContext) -> Result<(), QueryParseError> {
+        self.query.check(context)
+    }
+
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+        Query::Named {
+            name: self.name.clone(),
+            query: Box::new(self.query.build(context, schema)),
+        }
+    }
+}
+
+
+/// Takes the "_name" out of the body of a query. This is either given with the
+/// rest of the query's options or, for queries on a single field, with the options
+/// of the field
+fn take_query_name(body: &Json) -> Result<Option<(String, Json)>, QueryParseError> {
+    let object = match body.as_object() {
+        Some(object) => object,
+        None => return Ok(None),
+    };
+
+    if let Some(name) = object.get("_name") {
+        let name = name.as_str().ok_or(QueryParseError::ExpectedString)?.to_string();
+        let mut object = object.clone();
+        object.remove("_name");
+        return Ok(Some((name, Json::Object(object))));
+    }
+
+    if object.len() == 1 {
+        let (field_name, field_options) = object.iter().next().unwrap();
+
+        if let Some(name) = field_options.as_object().and_then(|field_options| field_options.get("_name")) {
+            let name = name.as_str().ok_or(QueryParseError::ExpectedString)?.to_string();
+            let mut field_options = field_options.as_object().unwrap().clone();
+            field_options.remove("_name");
+
+            let mut object = object.clone();
+            object.insert(field_name.clone(), Json::Object(field_options));
+            return Ok(Some((name, Json::Object(object))));
+        }
+    }
+
+    Ok(None)
+}
+
+
 pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
     let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
 
@@ -103,8 +302,106 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
         return Err(QueryParseError::ExpectedSingleKey)
     };
 
-    match get_query_parser(&query_type) {
-        Some(parse) => parse(object.get(query_type).unwrap()),
-        None => Err(QueryParseError::UnrecognisedQueryType(query_type.clone())),
+    let parse = match get_query_parser(&query_type) {
+        Some(parse) => parse,
+        None => return Err(QueryParseError::UnrecognisedQueryType(query_type.clone())),
+    };
+
+    let body = object.get(query_type).unwrap();
+    let query = match take_query_name(body).map_err(|error| error.within(query_type))? {
+        Some((name, body)) => {
+            Box::new(NamedQueryBuilder {
+                name: name,
+                query: parse(&body).map_err(|error| error.within(query_type))?,
+            })
+        }
+        None => parse(body).map_err(|error| error.within(query_type))?,
+    };
+
+    Ok(query)
+}
+
+
+/// Parses a query in the body of another, at the given path within the body
+/// (such as "must[1]"). Errors record the path so they can be located
+fn parse_inner(json: &Json, path: &str) -> Result<Box<QueryBuilder>, QueryParseError> {
+    parse(json).map_err(|error| error.within(path))
+}
+
+
+fn join_path(path: &str, key: &str) -> String {
+    if path.is_empty() || key.starts_with('[') {
+        format!("{}{}", path, key)
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use search::{Term, Query};
+    use search::schema::{Schema, FieldType, FIELD_INDEXED};
+
+    use super::{parse, QueryBuildContext, QueryParseError};
+
+    #[test]
+    fn test_named_query() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "bool": {
+                "should": [
+                    {"term": {"foo": {"value": "bar", "_name": "first"}}},
+                    {"match": {"foo": {"query": "baz", "_name": "second"}}},
+                    {"match_all": {}}
+                ],
+                "_name": "outer"
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema))).unwrap();
+
+        let named_queries = query.named_queries();
+        assert_eq!(named_queries.iter().map(|&(name, _)| name).collect::<Vec<_>>(), vec!["outer", "first", "second"]);
+        assert_eq!(*named_queries[1].1, Query::term(foo_field, Term::from_string("bar")));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_query_name() {
+        let query = parse(&json!({
+            "match_all": {"_name": 123}
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::InQuery("match_all".to_string(), Box::new(QueryParseError::ExpectedString))));
+    }
+
+    #[test]
+    fn test_locate_error() {
+        let query = json!({
+            "bool": {
+                "must": [
+                    {"match": {"title": "hello"}},
+                    {"match": {"title": {"query": "world", "fuzzyness": 1}}}
+                ],
+                "filter": {"term": {"status": "published"}}
+            }
+        });
+
+        let error = parse(&query).err().unwrap();
+        assert_eq!(error.path(), Some("bool.must[1].match"));
+        assert_eq!(*error.innermost(), QueryParseError::UnrecognisedKey("fuzzyness".to_string()));
+        assert_eq!(error.to_string(), "unrecognised key \"fuzzyness\"");
+
+        assert_eq!(parse(&json!({"bool": {"must": {"foo": {}}}})).err().unwrap().path(), Some("bool.must"));
+        assert_eq!(parse(&json!({"and": [{"match_all": {}}, {"term": {}}]})).err().unwrap().path(), Some("and[1].term"));
+        assert_eq!(parse(&json!({"not": {"filter": {"match_all": {"boost": "high"}}}})).err().unwrap().path(), Some("not.filter.match_all"));
+
+        // Errors in the outermost query itself have no path
+        assert_eq!(parse(&json!({"foo": {}})).err().unwrap().path(), None);
+    }
+
+    #[test]
+    fn test_error_message() {
+        assert_eq!(QueryParseError::UnrecognisedKey("fiel".to_string()).to_string(), "unrecognised key \"fiel\"");
     }
 }
@@ -1,87 +1,191 @@
 //! Parses "multi_match" queries
 
 use serde_json::Value as Json;
-use search::{Term, Token, Query, TermScorer};
-use search::schema::Schema;
-
-use mapping::FieldSearchOptions;
+use search::{Term, Token, Query, TermScorer, MultiTermSelector};
+use search::schema::{Schema, FieldId};
 
 use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
-use query_parser::utils::{parse_string, parse_float, Operator, parse_operator, parse_field_and_boost};
+use query_parser::utils::{parse_string, parse_float, Operator, parse_operator, parse_field_and_boost, MinimumShouldMatch, parse_minimum_should_match, search_analyzer};
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MultiMatchType {
+    /// Scores each document by the field that matched best
+    BestFields,
+
+    /// Adds together the scores of all fields that matched
+    MostFields,
+
+    /// Treats the fields as if they were one big field. Each term can match in
+    /// any of the fields and is scored with the statistics of the fields blended
+    CrossFields,
+
+    /// Matches the last word as a prefix, for searching as the user types.
+    /// Scores are combined from all fields that matched
+    BoolPrefix,
+}
 
 
 #[derive(Debug)]
 struct MultiMatchQueryBuilder {
     fields: Vec<(String, f32)>,
     query: String,
+    match_type: MultiMatchType,
     operator: Operator,
+    minimum_should_match: Option<MinimumShouldMatch>,
+    tie_breaker: f32,
+    analyzer: Option<String>,
     boost: f32,
 }
 
 
-impl QueryBuilder for MultiMatchQueryBuilder {
-    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+impl MultiMatchQueryBuilder {
+    /// Tokenises the query string with the analyzer of the field, or the analyzer given in the query
+    fn analyze(&self, context: &QueryBuildContext, field_name: &str) -> Vec<Token> {
+        match search_analyzer(context.index_metadata, field_name, self.analyzer.as_ref().map(|analyzer| &analyzer[..])) {
+            Some(ref analyzer) => {
+                let token_stream = analyzer.initialise(&self.query);
+                token_stream.collect::<Vec<Token>>()
+            }
+            None => {
+                vec![Token {term: Term::from_string(&self.query), position: 1}]
+            }
+        }
+    }
+
+    /// Combines the queries of each term by the operator. The minimum number that should match applies to each combination
+    fn combine_term_queries(&self, mut term_queries: Vec<Query>) -> Query {
+        let minimum_should_match = self.minimum_should_match.as_ref().map_or(0, |minimum_should_match| minimum_should_match.resolve(term_queries.len()));
+
+        match term_queries.len() {
+            _ if self.operator == Operator::Or && minimum_should_match > 0 => {
+                Query::minimum_should_match(term_queries, minimum_should_match)
+            }
+            0 => Query::None,
+            1 => term_queries.pop().unwrap(),
+            _ => {
+                match self.operator {
+                    Operator::Or => {
+                        Query::Disjunction { queries: term_queries }
+                    }
+                    Operator::And => {
+                        Query::Conjunction { queries: term_queries }
+                    }
+                }
+            }
+        }
+    }
+
+    fn build_field_queries(&self, context: &QueryBuildContext, schema: &Schema) -> Vec<Query> {
         // Convert query string into term query objects
         let mut field_queries = Vec::new();
         for &(ref field_name, field_boost) in self.fields.iter() {
-            // Get search options for field
-            let field_search_options = match context.index_metadata {
-                Some(index_metadata) => {
-                    match index_metadata.get_field_mapping(&field_name) {
-                        Some(field_mapping) => field_mapping.get_search_options(),
-                        None => FieldSearchOptions::default(),  // TODO: error?
-                    }
-                }
-                None => FieldSearchOptions::default(),  // TODO: error?
-            };
+            let field = schema.get_field_by_name(field_name).unwrap();
+            let field_mapping = context.index_metadata.and_then(|index_metadata| index_metadata.get_field_mapping(&field_name));
 
             // Tokenise query string
-            let tokens = match field_search_options.analyzer {
-                Some(ref analyzer) => {
-                    let token_stream = analyzer.initialise(&self.query);
-                    token_stream.collect::<Vec<Token>>()
-                }
-                None => {
-                    vec![Token {term: Term::from_string(&self.query), position: 1}]
-                }
+            let mut tokens = self.analyze(context, field_name);
+
+            // The last word might not have been finished yet
+            let prefix_token = match self.match_type {
+                MultiMatchType::BoolPrefix => tokens.pop(),
+                _ => None,
             };
 
             let mut term_queries = Vec::new();
             for token in tokens {
                 term_queries.push(Query::Term {
-                    field: schema.get_field_by_name(field_name).unwrap(),
+                    field: field,
                     term: token.term,
                     scorer: TermScorer::default(),
                 });
             }
 
-            // Combine the term queries
-            let field_query = match term_queries.len() {
-                0 => Query::None,
-                1 => term_queries.pop().unwrap(),
-                _ => {
-                    match self.operator {
-                        Operator::Or => {
-                            Query::Disjunction { queries: term_queries }
+            if let Some(token) = prefix_token {
+                // If the field has an "_index_prefix" subfield, prefixes of each word have
+                // been indexed into it so we don't need to look through the term dictionary
+                let index_prefix_field = field_mapping
+                    .and_then(|field_mapping| field_mapping.subfields.iter().find(|subfield| subfield.ends_with("._index_prefix")))
+                    .and_then(|subfield| schema.get_field_by_name(subfield));
+
+                term_queries.push(match index_prefix_field {
+                    Some(index_prefix_field) => {
+                        Query::Term {
+                            field: index_prefix_field,
+                            term: token.term,
+                            scorer: TermScorer::default(),
                         }
-                        Operator::And => {
-                            Query::Conjunction { queries: term_queries }
+                    }
+                    None => {
+                        Query::MultiTerm {
+                            field: field,
+                            term_selector: MultiTermSelector::Prefix(String::from_utf8_lossy(token.term.as_bytes()).into_owned()),
+                            scorer: TermScorer::default(),
                         }
                     }
-                }
-            };
+                });
+            }
 
             // Add boost
-            let field_query = field_query.boost(field_boost);
+            let field_query = self.combine_term_queries(term_queries).boost(field_boost);
 
             field_queries.push(field_query);
         }
 
+        field_queries
+    }
+
+    /// Builds a query for each group of fields that analyze the query string into the same terms. Each term
+    /// is matched against all fields of its group at once, so the terms can be spread across the fields
+    fn build_cross_field_queries(&self, context: &QueryBuildContext, schema: &Schema) -> Vec<Query> {
+        let mut groups: Vec<(Vec<Term>, Vec<(FieldId, f32)>)> = Vec::new();
+        for &(ref field_name, field_boost) in self.fields.iter() {
+            let field = schema.get_field_by_name(field_name).unwrap();
+            let terms = self.analyze(context, field_name).into_iter().map(|token| token.term).collect::<Vec<_>>();
+
+            match groups.iter().position(|&(ref group_terms, _)| *group_terms == terms) {
+                Some(position) => groups[position].1.push((field, field_boost)),
+                None => groups.push((terms, vec![(field, field_boost)])),
+            }
+        }
+
+        groups.into_iter().map(|(terms, fields)| {
+            let term_queries = terms.into_iter().map(|term| {
+                Query::BlendedTerm {
+                    fields: fields.clone(),
+                    term: term,
+                    tie_breaker: self.tie_breaker,
+                    scorer: TermScorer::default(),
+                }
+            }).collect();
+
+            self.combine_term_queries(term_queries)
+        }).collect()
+    }
+}
+
+
+impl QueryBuilder for MultiMatchQueryBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+        let mut field_queries = match self.match_type {
+            MultiMatchType::CrossFields => self.build_cross_field_queries(context, schema),
+            _ => self.build_field_queries(context, schema),
+        };
+
         let query = match field_queries.len() {
             0 => Query::None,
             1 => field_queries.pop().unwrap(),
             _ => {
-                Query::DisjunctionMax { queries: field_queries }
+                match self.match_type {
+                    MultiMatchType::BestFields | MultiMatchType::CrossFields => {
+                        Query::DisjunctionMax { queries: field_queries, tie_breaker: self.tie_breaker }
+                    }
+                    MultiMatchType::MostFields => {
+                        // Taking the best score and all of the others is the same as a sum
+                        Query::DisjunctionMax { queries: field_queries, tie_breaker: 1.0 }
+                    }
+                    MultiMatchType::BoolPrefix => Query::Disjunction { queries: field_queries },
+                }
             }
         };
 
@@ -99,6 +203,10 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
     let mut query = String::new();
     let mut boost = 1.0f32;
     let mut operator = Operator::Or;
+    let mut minimum_should_match = None;
+    let mut match_type = MultiMatchType::BestFields;
+    let mut tie_breaker = 0.0f32;
+    let mut analyzer = None;
 
     let mut has_fields_key = false;
     let mut has_query_key = false;
@@ -127,6 +235,28 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
             "operator" => {
                 operator = parse_operator(val)?;
             }
+            "minimum_should_match" => {
+                minimum_should_match = Some(parse_minimum_should_match(val)?);
+            }
+            "type" => {
+                match_type = match parse_string(val)?.as_ref() {
+                    "best_fields" => MultiMatchType::BestFields,
+                    "most_fields" => MultiMatchType::MostFields,
+                    "cross_fields" => MultiMatchType::CrossFields,
+                    "bool_prefix" => MultiMatchType::BoolPrefix,
+                    _ => return Err(QueryParseError::InvalidValue),
+                };
+            }
+            "tie_breaker" => {
+                tie_breaker = parse_float(val)?;
+
+                if tie_breaker < 0.0 || tie_breaker > 1.0 {
+                    return Err(QueryParseError::InvalidValue);
+                }
+            }
+            "analyzer" => {
+                analyzer = Some(parse_string(val)?);
+            }
             _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
         }
     }
@@ -142,7 +272,11 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
     Ok(Box::new(MultiMatchQueryBuilder {
         fields: fields_with_boosts,
         query: query,
+        match_type: match_type,
         operator: operator,
+        minimum_should_match: minimum_should_match,
+        tie_breaker: tie_breaker,
+        analyzer: analyzer,
         boost: boost,
     }))
 }
@@ -152,7 +286,7 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
 mod tests {
     use serde_json;
 
-    use search::{Term, Query, TermScorer};
+    use search::{Term, Query, TermScorer, MultiTermSelector};
     use search::schema::{Schema, FieldType, FIELD_INDEXED};
 
     use query_parser::{QueryBuildContext, QueryParseError};
@@ -185,6 +319,49 @@ mod tests {
                     scorer: TermScorer::default(),
                 }
             ],
+            tie_breaker: 0.0,
+        }));
+    }
+
+    #[test]
+    fn test_minimum_should_match() {
+        let mut schema = Schema::new();
+        let bar_field = schema.add_field("bar".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let baz_field = schema.add_field("baz".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "query": "hello big world",
+            "fields": ["bar", "baz"],
+            "minimum_should_match": "50%"
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        // One of the three terms is needed, which is the same as a disjunction
+        let field_query = |field| Query::Disjunction {
+            queries: vec![
+                Query::term(field, Term::from_string("hello")),
+                Query::term(field, Term::from_string("big")),
+                Query::term(field, Term::from_string("world")),
+            ],
+        };
+
+        assert_eq!(query, Ok(Query::DisjunctionMax {
+            queries: vec![field_query(bar_field), field_query(baz_field)],
+            tie_breaker: 0.0,
+        }));
+
+        let query = parse(&json!({
+            "query": "hello big world",
+            "fields": ["bar"],
+            "minimum_should_match": "-1"
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::MinimumShouldMatch {
+            queries: vec![
+                Query::term(bar_field, Term::from_string("hello")),
+                Query::term(bar_field, Term::from_string("big")),
+                Query::term(bar_field, Term::from_string("world")),
+            ],
+            minimum: 2,
         }));
     }
 
@@ -232,6 +409,7 @@ mod tests {
                     ],
                 }
             ],
+            tie_breaker: 0.0,
         }));
     }
 
@@ -262,6 +440,7 @@ mod tests {
                     scorer: TermScorer::default_with_boost(2.0f32),
                 }
             ],
+            tie_breaker: 0.0,
         }));
     }
 
@@ -292,6 +471,7 @@ mod tests {
                     scorer: TermScorer::default_with_boost(2.0f32),
                 }
             ],
+            tie_breaker: 0.0,
         }));
     }
 
@@ -321,6 +501,7 @@ mod tests {
                     scorer: TermScorer::default(),
                 }
             ],
+            tie_breaker: 0.0,
         }));
     }
 
@@ -351,6 +532,7 @@ mod tests {
                     scorer: TermScorer::default_with_boost(2.0f32),
                 }
             ],
+            tie_breaker: 0.0,
         }));
     }
 
@@ -399,9 +581,151 @@ mod tests {
                     ],
                 }
             ],
+            tie_breaker: 0.0,
+        }));
+    }
+
+    #[test]
+    fn test_bool_prefix() {
+        let mut schema = Schema::new();
+        let bar_field = schema.add_field("bar".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let baz_field = schema.add_field("baz".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"query\": \"quick bro\",
+            \"fields\": [\"bar\", \"baz\"],
+            \"type\": \"bool_prefix\"
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Disjunction {
+            queries: vec![
+                Query::Disjunction {
+                    queries: vec![
+                        Query::Term {
+                            field: bar_field,
+                            term: Term::from_string("quick"),
+                            scorer: TermScorer::default(),
+                        },
+                        Query::MultiTerm {
+                            field: bar_field,
+                            term_selector: MultiTermSelector::Prefix("bro".to_string()),
+                            scorer: TermScorer::default(),
+                        }
+                    ],
+                },
+                Query::Disjunction {
+                    queries: vec![
+                        Query::Term {
+                            field: baz_field,
+                            term: Term::from_string("quick"),
+                            scorer: TermScorer::default(),
+                        },
+                        Query::MultiTerm {
+                            field: baz_field,
+                            term_selector: MultiTermSelector::Prefix("bro".to_string()),
+                            scorer: TermScorer::default(),
+                        }
+                    ],
+                }
+            ],
+        }));
+    }
+
+    #[test]
+    fn test_best_fields_with_tie_breaker() {
+        let mut schema = Schema::new();
+        let bar_field = schema.add_field("bar".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let baz_field = schema.add_field("baz".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "query": "foo",
+            "fields": ["bar", "baz"],
+            "type": "best_fields",
+            "tie_breaker": 0.3
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::DisjunctionMax {
+            queries: vec![
+                Query::term(bar_field, Term::from_string("foo")),
+                Query::term(baz_field, Term::from_string("foo")),
+            ],
+            tie_breaker: 0.3,
+        }));
+    }
+
+    #[test]
+    fn test_most_fields() {
+        let mut schema = Schema::new();
+        let bar_field = schema.add_field("bar".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let baz_field = schema.add_field("baz".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "query": "foo",
+            "fields": ["bar", "baz"],
+            "type": "most_fields"
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::DisjunctionMax {
+            queries: vec![
+                Query::term(bar_field, Term::from_string("foo")),
+                Query::term(baz_field, Term::from_string("foo")),
+            ],
+            tie_breaker: 1.0,
         }));
     }
 
+    #[test]
+    fn test_cross_fields() {
+        let mut schema = Schema::new();
+        let first_name_field = schema.add_field("first_name".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let last_name_field = schema.add_field("last_name".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "query": "will smith",
+            "fields": ["first_name", "last_name^2"],
+            "type": "cross_fields",
+            "operator": "and"
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        // Each term can match in either field
+        let blended_term = |term| Query::BlendedTerm {
+            fields: vec![(first_name_field, 1.0), (last_name_field, 2.0)],
+            term: Term::from_string(term),
+            tie_breaker: 0.0,
+            scorer: TermScorer::default(),
+        };
+
+        assert_eq!(query, Ok(Query::Conjunction {
+            queries: vec![blended_term("will"), blended_term("smith")],
+        }));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_tie_breaker() {
+        let query = parse(&json!({
+            "query": "foo",
+            "fields": ["bar"],
+            "tie_breaker": 1.5
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+    }
+
+    #[test]
+    fn test_gives_error_for_unrecognised_type() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"query\": \"foo\",
+            \"fields\": [\"bar\"],
+            \"type\": \"foo\"
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+    }
+
     #[test]
     fn test_gives_error_for_incorrect_type() {
         // String
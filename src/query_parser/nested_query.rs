@@ -0,0 +1,153 @@
+//! Parses "nested" queries
+
+use serde_json::Value as Json;
+use search::Query;
+use search::schema::{Schema, FieldType};
+use search::query::nested::NestedScoreMode;
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, parse_inner};
+use query_parser::utils::{parse_string, parse_float};
+
+
+#[derive(Debug)]
+struct NestedQueryBuilder {
+    path: String,
+    query: Box<QueryBuilder>,
+    score_mode: NestedScoreMode,
+    boost: f32,
+}
+
+
+impl QueryBuilder for NestedQueryBuilder {
+    fn check(&self, context: &QueryBuildContext) -> Result<(), QueryParseError> {
+        self.query.check(context)
+    }
+
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+        // The path must be a nested mapping
+        let path = match schema.get_field_by_name(&self.path) {
+            Some(path) if schema.get(&path).map(|field_info| field_info.field_type == FieldType::Nested).unwrap_or(false) => path,
+            _ => return Query::None,
+        };
+
+        Query::Nested {
+            path: path,
+            query: Box::new(self.query.build(context, schema)),
+            score_mode: self.score_mode,
+        }.boost(self.boost)
+    }
+}
+
+
+fn parse_score_mode(json: &Json) -> Result<NestedScoreMode, QueryParseError> {
+    match json.as_str() {
+        Some("avg") => Ok(NestedScoreMode::Avg),
+        Some("sum") => Ok(NestedScoreMode::Sum),
+        Some("min") => Ok(NestedScoreMode::Min),
+        Some("max") => Ok(NestedScoreMode::Max),
+        Some("none") => Ok(NestedScoreMode::None),
+        Some(_) => Err(QueryParseError::InvalidValue),
+        None => Err(QueryParseError::ExpectedString),
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    // Get configuration
+    let mut path = None;
+    let mut query = None;
+    let mut score_mode = NestedScoreMode::default();
+    let mut boost = 1.0f32;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "path" => {
+                path = Some(parse_string(value)?);
+            }
+            "query" => {
+                query = Some(parse_inner(value, key)?);
+            }
+            "score_mode" => {
+                score_mode = parse_score_mode(value)?;
+            }
+            "ignore_unmapped" => {
+                // Unmapped paths never match anything, so this doesn't change the results
+                value.as_bool().ok_or(QueryParseError::InvalidValue)?;
+            }
+            "boost" => {
+                boost = parse_float(value)?;
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(Box::new(NestedQueryBuilder {
+        path: path.ok_or(QueryParseError::ExpectedKey("path"))?,
+        query: query.ok_or(QueryParseError::ExpectedKey("query"))?,
+        score_mode: score_mode,
+        boost: boost,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use search::{Term, Query, TermScorer};
+    use search::schema::{Schema, FieldType, FIELD_INDEXED};
+    use search::query::nested::NestedScoreMode;
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    #[test]
+    fn test_nested_query() {
+        let mut schema = Schema::new();
+        let comments_field = schema.add_field("comments".to_string(), FieldType::Nested, FIELD_INDEXED).unwrap();
+        let author_field = schema.add_field("comments.author".to_string(), FieldType::PlainString, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "path": "comments",
+            "query": {"term": {"comments.author": "alice"}},
+            "score_mode": "max",
+            "boost": 2
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Nested {
+            path: comments_field,
+            query: Box::new(Query::Term {
+                field: author_field,
+                term: Term::from_string("alice"),
+                scorer: TermScorer::default_with_boost(2.0f32),
+            }),
+            score_mode: NestedScoreMode::Max,
+        }));
+
+        // Paths must be nested mappings
+        let query = parse(&json!({
+            "path": "comments.author",
+            "query": {"match_all": {}}
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::None));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_score_mode() {
+        let query = parse(&json!({
+            "path": "comments",
+            "query": {"match_all": {}},
+            "score_mode": "median"
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+
+        let query = parse(&json!({
+            "query": {"match_all": {}}
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("path")));
+    }
+}
@@ -4,7 +4,7 @@ use serde_json::Value as Json;
 use search::Query;
 use search::schema::Schema;
 
-use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, parse as parse_query};
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, parse as parse_query, parse_inner, get_query_parser};
 
 
 #[derive(Debug)]
@@ -14,6 +14,10 @@ struct NotQueryBuilder {
 
 
 impl QueryBuilder for NotQueryBuilder {
+    fn check(&self, context: &QueryBuildContext) -> Result<(), QueryParseError> {
+        self.query.check(context)
+    }
+
     fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
         Query::Exclude {
             query: Box::new(Query::all()),
@@ -24,6 +28,26 @@ impl QueryBuilder for NotQueryBuilder {
 
 
 pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    // The query can be given in the "filter" key
+    if object.contains_key("filter") {
+        if let Some(key) = object.keys().find(|key| *key != "filter") {
+            return Err(QueryParseError::UnrecognisedKey(key.clone()));
+        }
+
+        return Ok(Box::new(NotQueryBuilder {
+            query: parse_inner(&object["filter"], "filter")?,
+        }));
+    }
+
+    // Or directly, in which case the only key is the type of the query
+    if object.len() > 1 {
+        if let Some(key) = object.keys().find(|key| get_query_parser(key).is_none()) {
+            return Err(QueryParseError::UnrecognisedKey(key.clone()));
+        }
+    }
+
     Ok(Box::new(NotQueryBuilder {
         query: parse_query(json)?,
     }))
@@ -96,4 +120,40 @@ mod tests {
 
         assert_eq!(query.err(), Some(QueryParseError::ExpectedObject));
     }
+
+    #[test]
+    fn test_not_query_filter_key() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "filter": {"term": {"test": "foo"}}
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Exclude {
+            query: Box::new(Query::all()),
+            exclude: Box::new(Query::Term {
+                field: test_field,
+                term: Term::from_string("foo"),
+                scorer: TermScorer::default(),
+            }),
+        }))
+    }
+
+    #[test]
+    fn test_gives_error_for_unrecognised_key() {
+        let query = parse(&json!({
+            "filter": {"term": {"test": "foo"}},
+            "foo": "bar"
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("foo".to_string())));
+
+        let query = parse(&json!({
+            "term": {"test": "foo"},
+            "foo": "bar"
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("foo".to_string())));
+    }
 }
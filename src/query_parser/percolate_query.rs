@@ -0,0 +1,115 @@
+//! Parses "percolate" queries
+
+use serde_json::{Map, Value as Json};
+use search::Query;
+use search::schema::Schema;
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::utils::{parse_string, parse_float};
+
+
+#[derive(Debug)]
+struct PercolateQueryBuilder {
+    field: String,
+    documents: Vec<Map<String, Json>>,
+    boost: f32,
+}
+
+
+impl QueryBuilder for PercolateQueryBuilder {
+    fn build(&self, context: &QueryBuildContext, _schema: &Schema) -> Query {
+        // The stored queries are read from the index being searched
+        let (index, index_metadata) = match (context.index, context.index_metadata) {
+            (Some(index), Some(index_metadata)) => (index, index_metadata),
+            _ => return Query::None,
+        };
+
+        match index.percolate(index_metadata, &self.field, &self.documents) {
+            Ok(doc_ids) => {
+                Query::DocIds {
+                    doc_ids: doc_ids,
+                    score: self.boost,
+                }
+            }
+            Err(_) => Query::None,
+        }
+    }
+}
+
+
+fn parse_document(json: &Json) -> Result<Map<String, Json>, QueryParseError> {
+    json.as_object().cloned().ok_or(QueryParseError::ExpectedObject)
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    // Get configuration
+    let mut field = None;
+    let mut documents = None;
+    let mut boost = 1.0f32;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "field" => {
+                field = Some(parse_string(value)?);
+            }
+            "document" => {
+                documents = Some(vec![parse_document(value)?]);
+            }
+            "documents" => {
+                let array = value.as_array().ok_or(QueryParseError::ExpectedArray)?;
+                documents = Some(array.iter().map(parse_document).collect::<Result<Vec<_>, _>>()?);
+            }
+            "boost" => {
+                boost = parse_float(value)?;
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(Box::new(PercolateQueryBuilder {
+        field: field.ok_or(QueryParseError::ExpectedKey("field"))?,
+        documents: documents.ok_or(QueryParseError::ExpectedKey("document"))?,
+        boost: boost,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use search::Query;
+    use search::schema::Schema;
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    #[test]
+    fn test_percolate_query_without_index() {
+        // Stored queries can only be read from the index being searched
+        let query = parse(&json!({
+            "field": "query",
+            "document": {"title": "hello"}
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &Schema::new())));
+
+        assert_eq!(query, Ok(Query::None));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_documents() {
+        let query = parse(&json!({
+            "field": "query",
+            "documents": [{"title": "hello"}, "world"]
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedObject));
+
+        let query = parse(&json!({
+            "field": "query"
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("document")));
+    }
+}
@@ -0,0 +1,149 @@
+//! Parses "pinned" queries
+
+use serde_json::Value as Json;
+use search::Query;
+use search::schema::Schema;
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, parse_inner};
+use query_parser::utils::parse_string;
+
+
+/// The score of the last pinned document, this is higher than any organic score
+const PINNED_SCORE: f32 = 1.0e30;
+
+
+#[derive(Debug)]
+struct PinnedQueryBuilder {
+    ids: Vec<String>,
+    organic: Box<QueryBuilder>,
+}
+
+
+/// Works out the score of the pinned document at the position
+///
+/// Each document is given the next float up from the one after it, so they
+/// keep their order however many documents are pinned.
+fn pinned_score(position: usize, num_pinned: usize) -> f32 {
+    f32::from_bits(PINNED_SCORE.to_bits() + (num_pinned - position - 1) as u32)
+}
+
+
+impl QueryBuilder for PinnedQueryBuilder {
+    fn check(&self, context: &QueryBuildContext) -> Result<(), QueryParseError> {
+        self.organic.check(context)
+    }
+
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+        let mut queries = self.ids.iter().enumerate().map(|(position, id)| {
+            Query::Ids {
+                keys: vec![id.clone()],
+                score: pinned_score(position, self.ids.len()),
+            }
+        }).collect::<Vec<_>>();
+
+        // Pinned documents that also match the organic query keep their pinned score
+        queries.push(self.organic.build(context, schema));
+
+        Query::DisjunctionMax {
+            queries: queries,
+            tie_breaker: 0.0,
+        }
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    // Get configuration
+    let mut ids = None;
+    let mut organic = None;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "ids" => {
+                let values = value.as_array().ok_or(QueryParseError::ExpectedArray)?;
+                let mut unique_ids: Vec<String> = Vec::new();
+
+                // Documents are pinned at the first position they are given in
+                for id in values.iter().map(parse_string) {
+                    let id = id?;
+                    if !unique_ids.contains(&id) {
+                        unique_ids.push(id);
+                    }
+                }
+
+                ids = Some(unique_ids);
+            }
+            "organic" => {
+                organic = Some(parse_inner(value, key)?);
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(Box::new(PinnedQueryBuilder {
+        ids: ids.ok_or(QueryParseError::ExpectedKey("ids"))?,
+        organic: organic.ok_or(QueryParseError::ExpectedKey("organic"))?,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use search::{Term, Query};
+    use search::schema::{Schema, FieldType, FIELD_INDEXED};
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::{parse, pinned_score};
+
+    #[test]
+    fn test_pinned_query() {
+        let mut schema = Schema::new();
+        let text_field = schema.add_field("text".to_string(), FieldType::PlainString, FIELD_INDEXED).unwrap();
+
+        let query = parse(&json!({
+            "ids": ["3", "1", "3"],
+            "organic": {"term": {"text": "apple"}}
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::DisjunctionMax {
+            queries: vec![
+                Query::Ids {
+                    keys: vec!["3".to_string()],
+                    score: pinned_score(0, 2),
+                },
+                Query::Ids {
+                    keys: vec!["1".to_string()],
+                    score: pinned_score(1, 2),
+                },
+                Query::term(text_field, Term::from_string("apple")),
+            ],
+            tie_breaker: 0.0,
+        }));
+    }
+
+    #[test]
+    fn test_pinned_scores_keep_their_order() {
+        let scores = (0..1000).map(|position| pinned_score(position, 1000)).collect::<Vec<_>>();
+
+        assert!(scores.windows(2).all(|pair| pair[0] > pair[1]));
+        assert!(scores[999] >= 1.0e30);
+    }
+
+    #[test]
+    fn test_gives_error_for_missing_keys() {
+        let query = parse(&json!({
+            "ids": ["1"]
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("organic")));
+
+        let query = parse(&json!({
+            "organic": {"match_all": {}}
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("ids")));
+    }
+}
@@ -3,33 +3,96 @@
 use serde_json::Value as Json;
 use search::{Query, MultiTermSelector, TermScorer};
 use search::schema::Schema;
+use search::query::wildcard::{WildcardPattern, escape};
 
 use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
-use query_parser::utils::parse_float;
+use query_parser::utils::{parse_string, parse_float};
+
+
+/// How the prefix is expanded into terms
+#[derive(Debug, PartialEq)]
+enum Rewrite {
+    /// Score each matching term
+    ScoringBoolean,
+
+    /// Give every match the same score
+    ConstantScore,
+
+    /// Score no more than this many of the matching terms
+    TopTerms(usize),
+}
 
 
 #[derive(Debug)]
 struct PrefixQueryBuilder {
     field: String,
     prefix: String,
+    rewrite: Rewrite,
+    case_insensitive: bool,
     boost: f32,
 }
 
 
 impl QueryBuilder for PrefixQueryBuilder {
     fn build(&self, _context: &QueryBuildContext, schema: &Schema) -> Query {
+        // Case insensitive prefixes are matched as the prefix followed by a star
+        let prefix_selector = if self.case_insensitive {
+            let pattern = WildcardPattern::new(&(escape(&self.prefix) + "*")).case_insensitive();
+            MultiTermSelector::Wildcard(pattern)
+        } else {
+            MultiTermSelector::Prefix(self.prefix.clone())
+        };
+
+        let term_selector = match self.rewrite {
+            Rewrite::TopTerms(max_terms) => MultiTermSelector::Limit {
+                selector: Box::new(prefix_selector),
+                max_terms: max_terms,
+            },
+            _ => prefix_selector,
+        };
+
         let query = Query::MultiTerm {
             field: schema.get_field_by_name(&self.field).unwrap(),
-            term_selector: MultiTermSelector::Prefix(self.prefix.clone()),
+            term_selector: term_selector,
             scorer: TermScorer::default(),
         };
 
+        let query = match self.rewrite {
+            Rewrite::ConstantScore => Query::all().filter(query),
+            _ => query,
+        };
+
         // Add boost
         query.boost(self.boost)
     }
 }
 
 
+fn parse_rewrite(json: &Json) -> Result<Rewrite, QueryParseError> {
+    let rewrite = parse_string(json)?;
+
+    match rewrite.as_ref() {
+        "scoring_boolean" => Ok(Rewrite::ScoringBoolean),
+        "constant_score" | "constant_score_boolean" | "constant_score_filter" => Ok(Rewrite::ConstantScore),
+        _ => {
+            // "top_terms_N" and "top_terms_boost_N"
+            let max_terms = if rewrite.starts_with("top_terms_boost_") {
+                &rewrite["top_terms_boost_".len()..]
+            } else if rewrite.starts_with("top_terms_") {
+                &rewrite["top_terms_".len()..]
+            } else {
+                return Err(QueryParseError::InvalidValue);
+            };
+
+            match max_terms.parse() {
+                Ok(max_terms) if max_terms > 0 => Ok(Rewrite::TopTerms(max_terms)),
+                _ => Err(QueryParseError::InvalidValue),
+            }
+        }
+    }
+}
+
+
 pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
     let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
 
@@ -43,6 +106,8 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
 
     // Get configuration
     let mut value: Option<&Json> = None;
+    let mut rewrite = Rewrite::ScoringBoolean;
+    let mut case_insensitive = false;
     let mut boost = 1.0f32;
 
     match *object {
@@ -56,6 +121,12 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
                     "prefix" => {
                         value = Some(val);
                     }
+                    "rewrite" => {
+                        rewrite = parse_rewrite(val)?;
+                    }
+                    "case_insensitive" => {
+                        case_insensitive = val.as_bool().ok_or(QueryParseError::InvalidValue)?;
+                    }
                     "boost" => {
                         boost = parse_float(val)?;
                     }
@@ -72,6 +143,8 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
                 Ok(Box::new(PrefixQueryBuilder {
                     field: field_name.clone(),
                     prefix: string.clone(),
+                    rewrite: rewrite,
+                    case_insensitive: case_insensitive,
                     boost: boost,
                 }))
             } else {
@@ -89,6 +162,7 @@ mod tests {
 
     use search::{Query, MultiTermSelector, TermScorer};
     use search::schema::{Schema, FieldType, FIELD_INDEXED};
+    use search::query::wildcard::WildcardPattern;
 
     use query_parser::{QueryBuildContext, QueryParseError};
 
@@ -194,6 +268,90 @@ mod tests {
         }));
     }
 
+    #[test]
+    fn test_with_top_terms_rewrite() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"value\": \"bar\",
+                \"rewrite\": \"top_terms_10\"
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::MultiTerm {
+            field: foo_field,
+            term_selector: MultiTermSelector::Limit {
+                selector: Box::new(MultiTermSelector::Prefix("bar".to_string())),
+                max_terms: 10,
+            },
+            scorer: TermScorer::default(),
+        }));
+    }
+
+    #[test]
+    fn test_with_constant_score_rewrite() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"value\": \"bar\",
+                \"rewrite\": \"constant_score\",
+                \"boost\": 2.0
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Filter {
+            query: Box::new(Query::All { score: 2.0f32 }),
+            filter: Box::new(Query::MultiTerm {
+                field: foo_field,
+                term_selector: MultiTermSelector::Prefix("bar".to_string()),
+                scorer: TermScorer::default(),
+            }),
+        }));
+    }
+
+    #[test]
+    fn test_case_insensitive() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::PlainString, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"value\": \"Bar?\",
+                \"case_insensitive\": true
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::MultiTerm {
+            field: foo_field,
+            term_selector: MultiTermSelector::Wildcard(WildcardPattern::new("Bar\\?*").case_insensitive()),
+            scorer: TermScorer::default(),
+        }));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_rewrite() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"value\": \"bar\",
+                \"rewrite\": \"top_terms_many\"
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+    }
+
     #[test]
     fn test_gives_error_for_incorrect_type() {
         // Array
@@ -0,0 +1,335 @@
+//! Parses "query_string" queries
+
+use serde_json::Value as Json;
+use search::{Term, Token, Query, MultiTermSelector, TermScorer};
+use search::schema::Schema;
+use search::query::levenshtein::LevenshteinAutomaton;
+
+use mapping::FieldSearchOptions;
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::utils::{parse_string, parse_float, Operator, parse_operator, Fuzziness};
+use query_parser::query_string_syntax::{QueryStringNode, parse_query_string};
+
+
+/// The number of terms that a fuzzy clause expands to
+const FUZZY_MAX_EXPANSIONS: usize = 50;
+
+
+#[derive(Debug)]
+struct QueryStringQueryBuilder {
+    query: QueryStringNode,
+    fields: Vec<String>,
+    default_operator: Operator,
+    boost: f32,
+}
+
+
+impl QueryBuilder for QueryStringQueryBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+        build_query_string_node(&self.query, &self.fields, self.default_operator, context, schema).boost(self.boost)
+    }
+}
+
+
+/// Tokenises text with the analyzer of the field
+pub fn analyze(context: &QueryBuildContext, field_name: &str, text: &str) -> Vec<Term> {
+    // Get search options for field
+    let field_search_options = match context.index_metadata {
+        Some(index_metadata) => {
+            match index_metadata.get_field_mapping(field_name) {
+                Some(field_mapping) => field_mapping.get_search_options(),
+                None => FieldSearchOptions::default(),  // TODO: error?
+            }
+        }
+        None => FieldSearchOptions::default(),  // TODO: error?
+    };
+
+    match field_search_options.analyzer {
+        Some(ref analyzer) => {
+            let token_stream = analyzer.initialise(text);
+            token_stream.map(|token: Token| token.term).collect()
+        }
+        None => vec![Term::from_string(text)],
+    }
+}
+
+
+/// Analyzes a single word, such as a prefix, keeping it as it is if the
+/// analyzer splits it up
+fn analyze_word(context: &QueryBuildContext, field_name: &str, text: &str) -> String {
+    let mut terms = analyze(context, field_name, text);
+
+    match terms.len() {
+        1 => String::from_utf8_lossy(terms.pop().unwrap().as_bytes()).into_owned(),
+        _ => text.to_string(),
+    }
+}
+
+
+fn combine(mut queries: Vec<Query>, operator: Operator) -> Query {
+    match queries.len() {
+        0 => Query::None,
+        1 => queries.pop().unwrap(),
+        _ => {
+            match operator {
+                Operator::Or => Query::Disjunction { queries: queries },
+                Operator::And => Query::Conjunction { queries: queries },
+            }
+        }
+    }
+}
+
+
+/// Builds a clause that has a field or is run against a single field
+fn build_field_clause(node: &QueryStringNode, field_name: &str, default_operator: Operator, context: &QueryBuildContext, schema: &Schema) -> Query {
+    let field = match schema.get_field_by_name(field_name) {
+        Some(field) => field,
+        None => return Query::None,
+    };
+
+    match *node {
+        QueryStringNode::Term{ref text, ..} => {
+            let queries = analyze(context, field_name, text).into_iter().map(|term| Query::term(field, term)).collect();
+            combine(queries, default_operator)
+        }
+        QueryStringNode::Phrase{ref text, slop, ..} => {
+            let mut terms = analyze(context, field_name, text);
+
+            match terms.len() {
+                0 => Query::None,
+                1 => Query::term(field, terms.pop().unwrap()),
+                _ => Query::Phrase {
+                    field: field,
+                    terms: terms,
+                    slop: slop,
+                    scorer: TermScorer::default(),
+                },
+            }
+        }
+        QueryStringNode::Prefix{ref prefix, ..} => {
+            Query::MultiTerm {
+                field: field,
+                term_selector: MultiTermSelector::Prefix(analyze_word(context, field_name, prefix)),
+                scorer: TermScorer::default(),
+            }
+        }
+        QueryStringNode::Fuzzy{ref text, max_distance, ..} => {
+            let value = analyze_word(context, field_name, text);
+            let max_distance = max_distance.unwrap_or_else(|| Fuzziness::default().max_distance(&value));
+
+            Query::MultiTerm {
+                field: field,
+                term_selector: MultiTermSelector::Fuzzy {
+                    automaton: LevenshteinAutomaton::new(&value, max_distance, 0).with_transpositions(true),
+                    max_expansions: FUZZY_MAX_EXPANSIONS,
+                },
+                scorer: TermScorer::default(),
+            }
+        }
+        QueryStringNode::Boolean{..} | QueryStringNode::Boost(..) => {
+            build_query_string_node(node, &[field_name.to_string()], default_operator, context, schema)
+        }
+    }
+}
+
+
+/// Converts a parsed query string into a query. Clauses that don't name a
+/// field are run against each of the given fields, keeping the best score
+pub fn build_query_string_node(node: &QueryStringNode, fields: &[String], default_operator: Operator, context: &QueryBuildContext, schema: &Schema) -> Query {
+    let field = match *node {
+        QueryStringNode::Term{ref field, ..} |
+        QueryStringNode::Phrase{ref field, ..} |
+        QueryStringNode::Prefix{ref field, ..} |
+        QueryStringNode::Fuzzy{ref field, ..} => field,
+        QueryStringNode::Boolean{ref must, ref should, ref must_not} => {
+            let build = |clauses: &Vec<QueryStringNode>| clauses.iter().map(|clause| build_query_string_node(clause, fields, default_operator, context, schema)).collect::<Vec<_>>();
+            let must = build(must);
+            let should = build(should);
+            let must_not = build(must_not);
+
+            // "should" clauses only add to the score if there are any "must" clauses
+            let query = match (must.is_empty(), should.is_empty()) {
+                (true, true) => {
+                    if must_not.is_empty() {
+                        // A blank query string
+                        return Query::None;
+                    }
+
+                    Query::all()
+                }
+                (false, true) => combine(must, Operator::And),
+                (true, false) => combine(should, Operator::Or),
+                (false, false) => {
+                    let mut queries = vec![combine(must.clone(), Operator::And)];
+                    queries.extend(should);
+                    Query::Disjunction { queries: queries }.filter(combine(must, Operator::And))
+                }
+            };
+
+            if must_not.is_empty() {
+                return query;
+            }
+
+            return query.exclude(combine(must_not, Operator::Or));
+        }
+        QueryStringNode::Boost(ref node, boost) => {
+            return build_query_string_node(node, fields, default_operator, context, schema).boost(boost);
+        }
+    };
+
+    match *field {
+        Some(ref field_name) => build_field_clause(node, field_name, default_operator, context, schema),
+        None => {
+            let mut queries = fields.iter().map(|field_name| build_field_clause(node, field_name, default_operator, context, schema)).collect::<Vec<_>>();
+
+            match queries.len() {
+                0 => Query::None,
+                1 => queries.pop().unwrap(),
+                _ => Query::DisjunctionMax { queries: queries, tie_breaker: 0.0 },
+            }
+        }
+    }
+}
+
+
+/// Parses the fields that clauses without a field are run against
+pub fn parse_fields(json: &Json) -> Result<Vec<String>, QueryParseError> {
+    let array = json.as_array().ok_or(QueryParseError::ExpectedArray)?;
+    array.iter().map(parse_string).collect()
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    // Get configuration
+    let mut query = None;
+    let mut fields = vec!["_all".to_string()];
+    let mut default_operator = Operator::Or;
+    let mut boost = 1.0f32;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "query" => {
+                query = Some(parse_string(value)?);
+            }
+            "default_field" => {
+                fields = vec![parse_string(value)?];
+            }
+            "fields" => {
+                fields = parse_fields(value)?;
+            }
+            "default_operator" => {
+                default_operator = parse_operator(&Json::String(parse_string(value)?.to_lowercase()))?;
+            }
+            "boost" => {
+                boost = parse_float(value)?;
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    let query = query.ok_or(QueryParseError::ExpectedKey("query"))?;
+
+    Ok(Box::new(QueryStringQueryBuilder {
+        query: parse_query_string(&query, default_operator).map_err(QueryParseError::InvalidQueryString)?,
+        fields: fields,
+        default_operator: default_operator,
+        boost: boost,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use search::{Term, Query, MultiTermSelector, TermScorer};
+    use search::schema::{Schema, FieldType, FIELD_INDEXED};
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+    use query_parser::query_string_syntax::QueryStringSyntaxError;
+
+    use super::parse;
+
+    #[test]
+    fn test_query_string_query() {
+        let mut schema = Schema::new();
+        let title_field = schema.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let body_field = schema.add_field("body".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"query\": \"title:Hello AND \\\"quick fox\\\"~1 -bar*\",
+            \"default_field\": \"body\"
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Conjunction {
+            queries: vec![
+                Query::term(title_field, Term::from_string("hello")),
+                Query::Phrase {
+                    field: body_field,
+                    terms: vec![Term::from_string("quick"), Term::from_string("fox")],
+                    slop: 1,
+                    scorer: TermScorer::default(),
+                }.exclude(Query::MultiTerm {
+                    field: body_field,
+                    term_selector: MultiTermSelector::Prefix("bar".to_string()),
+                    scorer: TermScorer::default(),
+                }),
+            ],
+        }));
+    }
+
+    #[test]
+    fn test_query_string_query_with_fields() {
+        let mut schema = Schema::new();
+        let title_field = schema.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let body_field = schema.add_field("body".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"query\": \"foo bar\",
+            \"fields\": [\"title\", \"body\"],
+            \"default_operator\": \"AND\",
+            \"boost\": 2.0
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        let field_query = |term: &str| Query::DisjunctionMax {
+            queries: vec![
+                Query::Term { field: title_field, term: Term::from_string(term), scorer: TermScorer::default_with_boost(2.0f32) },
+                Query::Term { field: body_field, term: Term::from_string(term), scorer: TermScorer::default_with_boost(2.0f32) },
+            ],
+            tie_breaker: 0.0,
+        };
+
+        assert_eq!(query, Ok(Query::Conjunction {
+            queries: vec![field_query("foo"), field_query("bar")],
+        }));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_syntax() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"query\": \"(foo OR bar\"
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidQueryString(QueryStringSyntaxError::UnclosedGroup(0))));
+    }
+
+    #[test]
+    fn test_gives_error_for_missing_query() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"default_field\": \"foo\"
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("query")));
+    }
+}
@@ -0,0 +1,537 @@
+//! Parses the Lucene query syntax used by "query_string" queries
+//!
+//! For example: `title:hello AND (foo OR bar) -baz "exact phrase"~2`
+//!
+//! The syntax is parsed into a tree of clauses which is converted into a
+//! query once the analyzers of the fields are known. "OR" binds more loosely
+//! than "AND", which binds more loosely than clauses that are just written
+//! next to each other. Clauses next to each other are required if they are
+//! prefixed with "+", excluded if they are prefixed with "-", "!" or "NOT",
+//! and are otherwise combined with the default operator.
+
+use std::fmt;
+
+use query_parser::utils::Operator;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryStringNode {
+    /// A word, which is analyzed into one or more terms
+    Term {
+        field: Option<String>,
+        text: String,
+    },
+
+    /// Words in double quotes
+    Phrase {
+        field: Option<String>,
+        text: String,
+        slop: u32,
+    },
+
+    /// A word ending in "*"
+    Prefix {
+        field: Option<String>,
+        prefix: String,
+    },
+
+    /// A word followed by "~". The distance is chosen from the length of the
+    /// word if one isn't given
+    Fuzzy {
+        field: Option<String>,
+        text: String,
+        max_distance: Option<u32>,
+    },
+
+    Boolean {
+        must: Vec<QueryStringNode>,
+        should: Vec<QueryStringNode>,
+        must_not: Vec<QueryStringNode>,
+    },
+
+    Boost(Box<QueryStringNode>, f32),
+}
+
+
+#[derive(Debug, PartialEq)]
+pub enum QueryStringSyntaxError {
+    /// A character that can't appear at this position
+    UnexpectedCharacter(usize, char),
+
+    /// The query ended where another clause was expected
+    UnexpectedEnd,
+
+    /// A "(" at this position doesn't have a matching ")"
+    UnclosedGroup(usize),
+
+    /// A '"' at this position doesn't have a matching '"'
+    UnclosedPhrase(usize),
+
+    /// The number after a "~" or "^" at this position isn't valid
+    InvalidNumber(usize),
+}
+
+
+impl fmt::Display for QueryStringSyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            QueryStringSyntaxError::UnexpectedCharacter(position, c) => write!(f, "unexpected character {:?} at position {}", c, position),
+            QueryStringSyntaxError::UnexpectedEnd => write!(f, "unexpected end of query"),
+            QueryStringSyntaxError::UnclosedGroup(position) => write!(f, "\"(\" at position {} isn't closed", position),
+            QueryStringSyntaxError::UnclosedPhrase(position) => write!(f, "'\"' at position {} isn't closed", position),
+            QueryStringSyntaxError::InvalidNumber(position) => write!(f, "invalid number at position {}", position),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Occur {
+    Must,
+    MustNot,
+    Default,
+}
+
+
+/// Characters that end a word unless they are escaped with "\"
+fn is_special_character(c: char) -> bool {
+    c.is_whitespace() || "()\":^~".contains(c)
+}
+
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+    default_operator: Operator,
+}
+
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).cloned()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().map_or(false, |c| c.is_whitespace()) {
+            self.position += 1;
+        }
+    }
+
+    fn is_at(&self, text: &str) -> bool {
+        text.chars().enumerate().all(|(i, c)| self.chars.get(self.position + i) == Some(&c))
+    }
+
+    /// Checks for an upper case keyword, which must be followed by whitespace
+    /// or the start of another clause
+    fn is_at_keyword(&self, keyword: &str) -> bool {
+        if !self.is_at(keyword) {
+            return false;
+        }
+
+        match self.chars.get(self.position + keyword.len()) {
+            Some(&c) => c.is_whitespace() || c == '(' || c == '"',
+            None => true,
+        }
+    }
+
+    /// Consumes whichever of the ways of writing an operator is next
+    fn eat_operator(&mut self, keyword: &str, symbol: &str) -> bool {
+        self.skip_whitespace();
+
+        let length = if self.is_at_keyword(keyword) {
+            keyword.len()
+        } else if self.is_at(symbol) {
+            symbol.len()
+        } else {
+            return false;
+        };
+
+        self.position += length;
+        true
+    }
+
+    fn parse_disjunction(&mut self, field: Option<&str>) -> Result<QueryStringNode, QueryStringSyntaxError> {
+        let mut clauses = vec![self.parse_conjunction(field)?];
+
+        while self.eat_operator("OR", "||") {
+            clauses.push(self.parse_conjunction(field)?);
+        }
+
+        if clauses.len() == 1 {
+            return Ok(clauses.pop().unwrap());
+        }
+
+        Ok(QueryStringNode::Boolean {
+            must: Vec::new(),
+            should: clauses,
+            must_not: Vec::new(),
+        })
+    }
+
+    fn parse_conjunction(&mut self, field: Option<&str>) -> Result<QueryStringNode, QueryStringSyntaxError> {
+        let mut clauses = vec![self.parse_sequence(field)?];
+
+        while self.eat_operator("AND", "&&") {
+            clauses.push(self.parse_sequence(field)?);
+        }
+
+        if clauses.len() == 1 {
+            return Ok(clauses.pop().unwrap());
+        }
+
+        Ok(QueryStringNode::Boolean {
+            must: clauses,
+            should: Vec::new(),
+            must_not: Vec::new(),
+        })
+    }
+
+    /// Parses clauses that are written next to each other
+    fn parse_sequence(&mut self, field: Option<&str>) -> Result<QueryStringNode, QueryStringSyntaxError> {
+        let mut clauses = Vec::new();
+
+        loop {
+            self.skip_whitespace();
+
+            let at_end = match self.peek() {
+                None | Some(')') => true,
+                _ => self.is_at_keyword("AND") || self.is_at_keyword("OR") || self.is_at("&&") || self.is_at("||"),
+            };
+
+            if at_end {
+                break;
+            }
+
+            clauses.push(self.parse_clause(field)?);
+        }
+
+        if clauses.is_empty() {
+            return match self.peek() {
+                Some(c) => Err(QueryStringSyntaxError::UnexpectedCharacter(self.position, c)),
+                None => Err(QueryStringSyntaxError::UnexpectedEnd),
+            };
+        }
+
+        if clauses.len() == 1 && clauses[0].0 == Occur::Default {
+            return Ok(clauses.pop().unwrap().1);
+        }
+
+        let mut must = Vec::new();
+        let mut should = Vec::new();
+        let mut must_not = Vec::new();
+
+        for (occur, clause) in clauses {
+            match (occur, self.default_operator) {
+                (Occur::Must, _) | (Occur::Default, Operator::And) => must.push(clause),
+                (Occur::Default, Operator::Or) => should.push(clause),
+                (Occur::MustNot, _) => must_not.push(clause),
+            }
+        }
+
+        Ok(QueryStringNode::Boolean {
+            must: must,
+            should: should,
+            must_not: must_not,
+        })
+    }
+
+    fn parse_clause(&mut self, field: Option<&str>) -> Result<(Occur, QueryStringNode), QueryStringSyntaxError> {
+        let occur = match self.peek() {
+            Some('+') => {
+                self.position += 1;
+                Occur::Must
+            }
+            Some('-') | Some('!') => {
+                self.position += 1;
+                Occur::MustNot
+            }
+            _ if self.is_at_keyword("NOT") => {
+                self.position += 3;
+                self.skip_whitespace();
+                Occur::MustNot
+            }
+            _ => Occur::Default,
+        };
+
+        let node = self.parse_primary(field)?;
+
+        if self.peek() == Some('^') {
+            let position = self.position;
+            self.position += 1;
+
+            let boost = self.read_word().0.parse::<f32>().map_err(|_| QueryStringSyntaxError::InvalidNumber(position))?;
+            return Ok((occur, QueryStringNode::Boost(Box::new(node), boost)));
+        }
+
+        Ok((occur, node))
+    }
+
+    fn parse_primary(&mut self, field: Option<&str>) -> Result<QueryStringNode, QueryStringSyntaxError> {
+        let start = self.position;
+
+        match self.peek() {
+            Some('(') => {
+                self.position += 1;
+                let node = self.parse_disjunction(field)?;
+
+                self.skip_whitespace();
+                if self.peek() != Some(')') {
+                    return Err(QueryStringSyntaxError::UnclosedGroup(start));
+                }
+
+                self.position += 1;
+                Ok(node)
+            }
+            Some('"') => {
+                self.position += 1;
+
+                let mut text = String::new();
+                loop {
+                    match self.peek() {
+                        Some('"') => break,
+                        Some('\\') if self.position + 1 < self.chars.len() => {
+                            text.push(self.chars[self.position + 1]);
+                            self.position += 2;
+                        }
+                        Some(c) => {
+                            text.push(c);
+                            self.position += 1;
+                        }
+                        None => return Err(QueryStringSyntaxError::UnclosedPhrase(start)),
+                    }
+                }
+
+                self.position += 1;
+
+                let slop = match self.parse_tilde()? {
+                    Some(slop) => slop.unwrap_or(0),
+                    None => 0,
+                };
+
+                Ok(QueryStringNode::Phrase {
+                    field: field.map(|field| field.to_string()),
+                    text: text,
+                    slop: slop,
+                })
+            }
+            Some(c) if is_special_character(c) => Err(QueryStringSyntaxError::UnexpectedCharacter(self.position, c)),
+            None => Err(QueryStringSyntaxError::UnexpectedEnd),
+            Some(_) => {
+                let (word, is_prefix) = self.read_word();
+
+                // A word followed by ":" names the field of the next clause
+                if self.peek() == Some(':') {
+                    self.position += 1;
+                    return self.parse_primary(Some(&word));
+                }
+
+                let field = field.map(|field| field.to_string());
+
+                if is_prefix {
+                    return Ok(QueryStringNode::Prefix {
+                        field: field,
+                        prefix: word,
+                    });
+                }
+
+                match self.parse_tilde()? {
+                    Some(max_distance) => {
+                        Ok(QueryStringNode::Fuzzy {
+                            field: field,
+                            text: word,
+                            max_distance: max_distance,
+                        })
+                    }
+                    None => {
+                        Ok(QueryStringNode::Term {
+                            field: field,
+                            text: word,
+                        })
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reads a word, handling escapes. Also returns whether it ended with an
+    /// unescaped "*", which is removed
+    fn read_word(&mut self) -> (String, bool) {
+        let mut word = String::new();
+        let mut is_prefix = false;
+
+        while let Some(c) = self.peek() {
+            if is_special_character(c) {
+                break;
+            }
+
+            self.position += 1;
+            is_prefix = false;
+
+            match c {
+                '\\' => {
+                    if let Some(escaped) = self.peek() {
+                        word.push(escaped);
+                        self.position += 1;
+                    }
+                }
+                '*' => {
+                    word.push(c);
+                    is_prefix = true;
+                }
+                _ => word.push(c),
+            }
+        }
+
+        if is_prefix {
+            word.pop();
+        }
+
+        (word, is_prefix)
+    }
+
+    /// Parses a "~" followed by an optional number
+    fn parse_tilde(&mut self) -> Result<Option<Option<u32>>, QueryStringSyntaxError> {
+        if self.peek() != Some('~') {
+            return Ok(None);
+        }
+
+        let position = self.position;
+        self.position += 1;
+
+        let number = self.read_word().0;
+        if number.is_empty() {
+            return Ok(Some(None));
+        }
+
+        match number.parse::<u32>() {
+            Ok(number) => Ok(Some(Some(number))),
+            Err(_) => Err(QueryStringSyntaxError::InvalidNumber(position)),
+        }
+    }
+}
+
+
+/// Parses a query string. Clauses that don't have a field have None in
+/// place of it. A blank query string gives a boolean node without any clauses
+pub fn parse_query_string(query: &str, default_operator: Operator) -> Result<QueryStringNode, QueryStringSyntaxError> {
+    let mut parser = Parser {
+        chars: query.chars().collect(),
+        position: 0,
+        default_operator: default_operator,
+    };
+
+    parser.skip_whitespace();
+    if parser.peek().is_none() {
+        return Ok(QueryStringNode::Boolean {
+            must: Vec::new(),
+            should: Vec::new(),
+            must_not: Vec::new(),
+        });
+    }
+
+    let node = parser.parse_disjunction(None)?;
+
+    // Anything left must be a ")" without a "("
+    parser.skip_whitespace();
+    if let Some(c) = parser.peek() {
+        return Err(QueryStringSyntaxError::UnexpectedCharacter(parser.position, c));
+    }
+
+    Ok(node)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use query_parser::utils::Operator;
+
+    use super::{parse_query_string, QueryStringNode, QueryStringSyntaxError};
+
+    fn term(field: Option<&str>, text: &str) -> QueryStringNode {
+        QueryStringNode::Term {
+            field: field.map(|field| field.to_string()),
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_operators() {
+        assert_eq!(parse_query_string("title:hello AND (foo OR bar) -baz", Operator::Or), Ok(QueryStringNode::Boolean {
+            must: vec![
+                term(Some("title"), "hello"),
+                QueryStringNode::Boolean {
+                    must: vec![],
+                    should: vec![
+                        QueryStringNode::Boolean {
+                            must: vec![],
+                            should: vec![term(None, "foo"), term(None, "bar")],
+                            must_not: vec![],
+                        },
+                    ],
+                    must_not: vec![term(None, "baz")],
+                },
+            ],
+            should: vec![],
+            must_not: vec![],
+        }));
+
+        assert_eq!(parse_query_string("+foo bar", Operator::And), Ok(QueryStringNode::Boolean {
+            must: vec![term(None, "foo"), term(None, "bar")],
+            should: vec![],
+            must_not: vec![],
+        }));
+
+        assert_eq!(parse_query_string("NOT foo || !bar", Operator::Or), Ok(QueryStringNode::Boolean {
+            must: vec![],
+            should: vec![
+                QueryStringNode::Boolean { must: vec![], should: vec![], must_not: vec![term(None, "foo")] },
+                QueryStringNode::Boolean { must: vec![], should: vec![], must_not: vec![term(None, "bar")] },
+            ],
+            must_not: vec![],
+        }));
+    }
+
+    #[test]
+    fn test_parse_terms() {
+        assert_eq!(parse_query_string("title:\"exact phrase\"~2", Operator::Or), Ok(QueryStringNode::Phrase {
+            field: Some("title".to_string()),
+            text: "exact phrase".to_string(),
+            slop: 2,
+        }));
+
+        assert_eq!(parse_query_string("quic*", Operator::Or), Ok(QueryStringNode::Prefix {
+            field: None,
+            prefix: "quic".to_string(),
+        }));
+
+        assert_eq!(parse_query_string("quikc~ brwn~1", Operator::Or), Ok(QueryStringNode::Boolean {
+            must: vec![],
+            should: vec![
+                QueryStringNode::Fuzzy { field: None, text: "quikc".to_string(), max_distance: None },
+                QueryStringNode::Fuzzy { field: None, text: "brwn".to_string(), max_distance: Some(1) },
+            ],
+            must_not: vec![],
+        }));
+
+        assert_eq!(parse_query_string("foo^2", Operator::Or), Ok(QueryStringNode::Boost(Box::new(term(None, "foo")), 2.0)));
+
+        // Escaped characters are part of the word
+        assert_eq!(parse_query_string("a\\:b\\*", Operator::Or), Ok(term(None, "a:b*")));
+
+        // A field applies to every clause of a group
+        assert_eq!(parse_query_string("title:(foo bar)", Operator::Or), Ok(QueryStringNode::Boolean {
+            must: vec![],
+            should: vec![term(Some("title"), "foo"), term(Some("title"), "bar")],
+            must_not: vec![],
+        }));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(parse_query_string("(foo bar", Operator::Or), Err(QueryStringSyntaxError::UnclosedGroup(0)));
+        assert_eq!(parse_query_string("foo \"bar", Operator::Or), Err(QueryStringSyntaxError::UnclosedPhrase(4)));
+        assert_eq!(parse_query_string("foo AND", Operator::Or), Err(QueryStringSyntaxError::UnexpectedEnd));
+        assert_eq!(parse_query_string("foo)", Operator::Or), Err(QueryStringSyntaxError::UnexpectedCharacter(3, ')')));
+        assert_eq!(parse_query_string("foo^bar", Operator::Or), Err(QueryStringSyntaxError::InvalidNumber(3)));
+    }
+}
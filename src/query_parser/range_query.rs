@@ -0,0 +1,363 @@
+//! Parses "range" queries
+
+use chrono::{DateTime, Utc, Timelike};
+use serde_json::Value as Json;
+use search::{Query, MultiTermSelector, TermScorer};
+use search::schema::{Schema, FieldType};
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::utils::parse_float;
+use query_parser::date_math::{self, Rounding};
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BoundType {
+    Lower,
+    Upper,
+}
+
+
+#[derive(Debug, PartialEq)]
+struct Bound {
+    value: Json,
+    inclusive: bool,
+
+    /// Date math expressions are worked out when the query is parsed. This is
+    /// the date they gave, in microseconds since the epoch
+    date_math: Option<i64>,
+}
+
+
+#[derive(Debug)]
+struct RangeQueryBuilder {
+    field: String,
+    lower: Option<Bound>,
+    upper: Option<Bound>,
+    boost: f32,
+}
+
+
+/// Converts a date into microseconds since the epoch, which is how dates are indexed
+fn date_to_micros(date: DateTime<Utc>) -> i64 {
+    date.timestamp() * 1000000 + (date.nanosecond() / 1000) as i64
+}
+
+
+/// Parses a date as either milliseconds since the epoch, an RFC 3339 string or
+/// a "yyyy-mm-dd" string. Returns microseconds since the epoch, which is how
+/// dates are indexed
+pub fn parse_date(json: &Json) -> Option<i64> {
+    match *json {
+        Json::Number(ref number) => number.as_i64().and_then(|millis| millis.checked_mul(1000)),
+        Json::String(ref string) => date_math::parse_date_string(string).map(date_to_micros),
+        _ => None,
+    }
+}
+
+
+/// Finds the value of the lowest (for lower bounds) or highest (for upper
+/// bounds) indexed integer that is inside the bound
+fn coerce_integer_bound(bound: &Bound, bound_type: BoundType) -> Option<i64> {
+    let (integer_value, float_value) = match bound.value {
+        Json::Number(ref number) => (number.as_i64(), number.as_f64()),
+        Json::String(ref string) => (string.parse::<i64>().ok(), string.parse::<f64>().ok()),
+        _ => return None,
+    };
+
+    if let Some(value) = integer_value {
+        return match (bound_type, bound.inclusive) {
+            (_, true) => Some(value),
+            (BoundType::Lower, false) => value.checked_add(1),
+            (BoundType::Upper, false) => value.checked_sub(1),
+        };
+    }
+
+    // Fractional bounds are rounded towards the inside of the range
+    let value = float_value?;
+    let value = match (bound_type, bound.inclusive) {
+        (BoundType::Lower, true) => value.ceil(),
+        (BoundType::Lower, false) => value.floor() + 1.0,
+        (BoundType::Upper, true) => value.floor(),
+        (BoundType::Upper, false) => value.ceil() - 1.0,
+    };
+
+    if value < i64::min_value() as f64 || value > i64::max_value() as f64 {
+        return None;
+    }
+
+    Some(value as i64)
+}
+
+
+fn coerce_date_bound(bound: &Bound, bound_type: BoundType) -> Option<i64> {
+    let value = match bound.date_math {
+        Some(value) => value,
+        None => parse_date(&bound.value)?,
+    };
+
+    match (bound_type, bound.inclusive) {
+        (_, true) => Some(value),
+        (BoundType::Lower, false) => value.checked_add(1),
+        (BoundType::Upper, false) => value.checked_sub(1),
+    }
+}
+
+
+impl QueryBuilder for RangeQueryBuilder {
+    fn build(&self, _context: &QueryBuildContext, schema: &Schema) -> Query {
+        let field = match schema.get_field_by_name(&self.field) {
+            Some(field) => field,
+            None => return Query::None,
+        };
+
+        // The field type decides how bounds are interpreted. Integers and dates
+        // are both indexed as 64 bit integers so either can then be selected the same way
+        let coerce_bound: fn(&Bound, BoundType) -> Option<i64> = match schema.get(&field).map(|field_info| &field_info.field_type) {
+            Some(&FieldType::I64) => coerce_integer_bound,
+            Some(&FieldType::DateTime) => coerce_date_bound,
+            _ => return Query::None,
+        };
+
+        let gte = match self.lower {
+            Some(ref bound) => {
+                match coerce_bound(bound, BoundType::Lower) {
+                    Some(value) => Some(value),
+                    None => return Query::None,
+                }
+            }
+            None => None,
+        };
+
+        let lte = match self.upper {
+            Some(ref bound) => {
+                match coerce_bound(bound, BoundType::Upper) {
+                    Some(value) => Some(value),
+                    None => return Query::None,
+                }
+            }
+            None => None,
+        };
+
+        // Every matching document gets the same score
+        let query = Query::All{ score: 1.0f32 }.filter(Query::MultiTerm {
+            field: field,
+            term_selector: MultiTermSelector::IntegerRange {
+                gte: gte,
+                lte: lte,
+            },
+            scorer: TermScorer::default(),
+        });
+
+        // Add boost
+        query.boost(self.boost)
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let field_name = if object.len() == 1 {
+        object.keys().collect::<Vec<_>>()[0]
+    } else {
+        return Err(QueryParseError::ExpectedSingleKey)
+    };
+
+    let object = object.get(field_name).unwrap().as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    // Get configuration
+    let mut lower = None;
+    let mut upper = None;
+    let mut boost = 1.0f32;
+
+    // Every expression in the query is relative to the same time
+    let now = Utc::now();
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "gt" | "gte" | "lt" | "lte" => {
+                match *value {
+                    Json::Number(_) | Json::String(_) => {},
+                    _ => return Err(QueryParseError::InvalidValue),
+                }
+
+                let inclusive = key.ends_with('e');
+
+                // Rounding includes the whole unit in "gte" and "lte" bounds, and
+                // excludes it from "gt" and "lt" bounds
+                let date_math = match value.as_str() {
+                    Some(expression) if date_math::is_date_math(expression) => {
+                        let rounding = match (key.starts_with("gt"), inclusive) {
+                            (true, true) | (false, false) => Rounding::Down,
+                            (true, false) | (false, true) => Rounding::Up,
+                        };
+
+                        let date = date_math::evaluate(expression, now, rounding).ok_or(QueryParseError::InvalidValue)?;
+                        Some(date_to_micros(date))
+                    }
+                    _ => None,
+                };
+
+                let bound = Bound {
+                    value: value.clone(),
+                    inclusive: inclusive,
+                    date_math: date_math,
+                };
+
+                if key.starts_with("gt") {
+                    lower = Some(bound);
+                } else {
+                    upper = Some(bound);
+                }
+            }
+            "boost" => {
+                boost = parse_float(value)?;
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(Box::new(RangeQueryBuilder {
+        field: field_name.clone(),
+        lower: lower,
+        upper: upper,
+        boost: boost,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use search::{Query, MultiTermSelector, TermScorer};
+    use search::schema::{Schema, FieldId, FieldType, FIELD_INDEXED};
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    fn range_query(field: FieldId, gte: Option<i64>, lte: Option<i64>, score: f32) -> Query {
+        Query::Filter {
+            query: Box::new(Query::All { score: score }),
+            filter: Box::new(Query::MultiTerm {
+                field: field,
+                term_selector: MultiTermSelector::IntegerRange {
+                    gte: gte,
+                    lte: lte,
+                },
+                scorer: TermScorer::default(),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_range_query() {
+        let mut schema = Schema::new();
+        let age_field = schema.add_field("age".to_string(), FieldType::I64, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"age\": {
+                \"gte\": 10,
+                \"lt\": 20
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(range_query(age_field, Some(10), Some(19), 1.0f32)));
+    }
+
+    #[test]
+    fn test_float_bounds() {
+        let mut schema = Schema::new();
+        let age_field = schema.add_field("age".to_string(), FieldType::I64, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"age\": {
+                \"gt\": 10.5,
+                \"lte\": 19.5
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(range_query(age_field, Some(11), Some(19), 1.0f32)));
+    }
+
+    #[test]
+    fn test_date_bounds() {
+        let mut schema = Schema::new();
+        let date_field = schema.add_field("date".to_string(), FieldType::DateTime, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"date\": {
+                \"gt\": \"2017-01-01\",
+                \"lte\": \"2017-01-02T00:00:00Z\",
+                \"boost\": 2.0
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(range_query(date_field, Some(1483228800000001), Some(1483315200000000), 2.0f32)));
+    }
+
+    #[test]
+    fn test_date_math_bounds() {
+        let mut schema = Schema::new();
+        let date_field = schema.add_field("date".to_string(), FieldType::DateTime, FIELD_INDEXED).unwrap();
+
+        // Rounding includes the whole month in the "lte" bound and excludes the
+        // whole day from the "gt" bound
+        let query = parse(&json!({
+            "date": {
+                "gt": "2017-01-01T12:00:00Z||/d",
+                "lte": "2017-01-15||+1M/M"
+            }
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(range_query(date_field, Some(1483315200000000), Some(1488326399999999), 1.0f32)));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_date_math() {
+        let query = parse(&json!({
+            "date": {
+                "gte": "now-7x"
+            }
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+    }
+
+    #[test]
+    fn test_invalid_bound_matches_nothing() {
+        let mut schema = Schema::new();
+        schema.add_field("date".to_string(), FieldType::DateTime, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"date\": {
+                \"gte\": \"yesterday\"
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::None));
+    }
+
+    #[test]
+    fn test_gives_error_for_extra_key() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"age\": {
+                \"gte\": 10,
+                \"hello\": \"world\"
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("hello".to_string())));
+    }
+}
@@ -0,0 +1,197 @@
+//! Parses "rank_feature" queries
+
+use serde_json::Value as Json;
+use search::Query;
+use search::schema::Schema;
+use search::query::rank_feature::RankFeatureFunction;
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::utils::{parse_string, parse_float};
+
+
+#[derive(Debug)]
+struct RankFeatureQueryBuilder {
+    field: String,
+    function: RankFeatureFunction,
+    boost: f32,
+}
+
+
+impl QueryBuilder for RankFeatureQueryBuilder {
+    fn build(&self, _context: &QueryBuildContext, schema: &Schema) -> Query {
+        // Features of rank_features fields are referenced as "field.feature"
+        let (field, feature) = match schema.get_field_by_name(&self.field) {
+            Some(field) => (field, ""),
+            None => {
+                let mut split = self.field.splitn(2, '.');
+                match (split.next().and_then(|field_name| schema.get_field_by_name(field_name)), split.next()) {
+                    (Some(field), Some(feature)) => (field, feature),
+                    _ => return Query::None,
+                }
+            }
+        };
+
+        Query::RankFeature {
+            field: field,
+            feature: feature.to_string(),
+            function: self.function.clone(),
+            boost: self.boost,
+        }
+    }
+}
+
+
+fn parse_function(function_name: &str, json: &Json) -> Result<RankFeatureFunction, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut pivot = None;
+    let mut scaling_factor = None;
+    let mut exponent = None;
+
+    for (key, value) in object.iter() {
+        match (function_name, key.as_ref()) {
+            ("saturation", "pivot") | ("sigmoid", "pivot") => {
+                pivot = Some(parse_float(value)?);
+            }
+            ("log", "scaling_factor") => {
+                scaling_factor = Some(parse_float(value)?);
+            }
+            ("sigmoid", "exponent") => {
+                exponent = Some(parse_float(value)?);
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    match function_name {
+        "saturation" => Ok(RankFeatureFunction::Saturation {
+            pivot: pivot,
+        }),
+        "log" => Ok(RankFeatureFunction::Log {
+            scaling_factor: scaling_factor.ok_or(QueryParseError::ExpectedKey("scaling_factor"))?,
+        }),
+        _ => Ok(RankFeatureFunction::Sigmoid {
+            pivot: pivot.ok_or(QueryParseError::ExpectedKey("pivot"))?,
+            exponent: exponent.ok_or(QueryParseError::ExpectedKey("exponent"))?,
+        }),
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut field = None;
+    let mut function = None;
+    let mut boost = 1.0f32;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "field" => {
+                field = Some(parse_string(value)?);
+            }
+            "saturation" | "log" | "sigmoid" => {
+                // Only one function can be used
+                if function.is_some() {
+                    return Err(QueryParseError::InvalidValue);
+                }
+
+                function = Some(parse_function(key, value)?);
+            }
+            "boost" => {
+                boost = parse_float(value)?;
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(Box::new(RankFeatureQueryBuilder {
+        field: field.ok_or(QueryParseError::ExpectedKey("field"))?,
+        function: function.unwrap_or(RankFeatureFunction::Saturation { pivot: None }),
+        boost: boost,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use search::Query;
+    use search::schema::{Schema, FieldType, FIELD_INDEXED};
+    use search::query::rank_feature::RankFeatureFunction;
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    #[test]
+    fn test_rank_feature_query() {
+        let mut schema = Schema::new();
+        let pagerank_field = schema.add_field("pagerank".to_string(), FieldType::PlainString, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"field\": \"pagerank\"
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::RankFeature {
+            field: pagerank_field,
+            feature: "".to_string(),
+            function: RankFeatureFunction::Saturation { pivot: None },
+            boost: 1.0f32,
+        }));
+    }
+
+    #[test]
+    fn test_rank_features_field() {
+        let mut schema = Schema::new();
+        let topics_field = schema.add_field("topics".to_string(), FieldType::PlainString, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"field\": \"topics.politics\",
+            \"sigmoid\": {
+                \"pivot\": 7,
+                \"exponent\": 0.5
+            },
+            \"boost\": 2.0
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::RankFeature {
+            field: topics_field,
+            feature: "politics".to_string(),
+            function: RankFeatureFunction::Sigmoid { pivot: 7.0f32, exponent: 0.5f32 },
+            boost: 2.0f32,
+        }));
+    }
+
+    #[test]
+    fn test_gives_error_for_missing_scaling_factor() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"field\": \"pagerank\",
+            \"log\": {}
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("scaling_factor")));
+    }
+
+    #[test]
+    fn test_gives_error_for_multiple_functions() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"field\": \"pagerank\",
+            \"log\": {
+                \"scaling_factor\": 4
+            },
+            \"saturation\": {}
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+    }
+}
@@ -0,0 +1,109 @@
+//! Parses "script" queries
+
+use std::collections::HashMap;
+
+use serde_json::Value as Json;
+use search::Query;
+use search::schema::Schema;
+
+use script::{Expression, ScriptValue, parse_script, parse_script_params};
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::utils::parse_float;
+
+
+#[derive(Debug)]
+struct ScriptQueryBuilder {
+    script: Expression,
+    params: HashMap<String, ScriptValue>,
+    boost: f32,
+}
+
+
+impl QueryBuilder for ScriptQueryBuilder {
+    fn build(&self, _context: &QueryBuildContext, _schema: &Schema) -> Query {
+        Query::Script {
+            script: self.script.clone(),
+            params: self.params.clone(),
+            score: self.boost,
+        }
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    // Get configuration
+    let mut script = None;
+    let mut boost = 1.0f32;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "script" => {
+                script = Some((
+                    parse_script(value).map_err(|_| QueryParseError::InvalidValue)?,
+                    parse_script_params(value),
+                ));
+            }
+            "boost" => {
+                boost = parse_float(value)?;
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    let (script, params) = script.ok_or(QueryParseError::ExpectedKey("script"))?;
+    Ok(Box::new(ScriptQueryBuilder {
+        script: script,
+        params: params,
+        boost: boost,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use search::Query;
+    use search::schema::Schema;
+
+    use script::{Expression, ScriptValue};
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    #[test]
+    fn test_script_query() {
+        let query = parse(&json!({
+            "script": {
+                "source": "doc['price'] * doc['qty'] > params.min",
+                "params": {"min": 100}
+            },
+            "boost": 2
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &Schema::new())));
+
+        assert_eq!(query, Ok(Query::Script {
+            script: Expression::parse("doc['price'] * doc['qty'] > params.min").unwrap(),
+            params: hashmap! {
+                "min".to_string() => ScriptValue::Number(100.0),
+            },
+            score: 2.0f32,
+        }));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_script() {
+        let query = parse(&json!({
+            "script": {"source": "doc['price'] >", "lang": "painless"}
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+
+        let query = parse(&json!({
+            "boost": 2
+        }));
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("script")));
+    }
+}
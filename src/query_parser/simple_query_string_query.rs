@@ -0,0 +1,447 @@
+//! Parses "simple_query_string" queries
+//!
+//! These use a simpler syntax than "query_string" queries that is safe to
+//! expose to users directly, as it never gives an error. "+" requires both
+//! sides, "|" requires either side, "-" excludes the next clause, double
+//! quotes make a phrase and "*" at the end of a word makes a prefix. Anything
+//! that can't be parsed, like an unclosed group, is handled as well as it can
+//! be or ignored.
+
+use serde_json::Value as Json;
+use search::Query;
+use search::schema::Schema;
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::utils::{parse_string, parse_float, Operator, parse_operator};
+use query_parser::query_string_syntax::QueryStringNode;
+use query_parser::query_string_query::{build_query_string_node, parse_fields};
+
+
+#[derive(Debug)]
+struct SimpleQueryStringQueryBuilder {
+    query: QueryStringNode,
+    fields: Vec<String>,
+    default_operator: Operator,
+    boost: f32,
+}
+
+
+impl QueryBuilder for SimpleQueryStringQueryBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+        build_query_string_node(&self.query, &self.fields, self.default_operator, context, schema).boost(self.boost)
+    }
+}
+
+
+/// Characters that end a word unless they are escaped with "\"
+fn is_special_character(c: char) -> bool {
+    c.is_whitespace() || "+|\"()~".contains(c)
+}
+
+
+fn boolean(must: Vec<QueryStringNode>, should: Vec<QueryStringNode>, must_not: Vec<QueryStringNode>) -> QueryStringNode {
+    QueryStringNode::Boolean {
+        must: must,
+        should: should,
+        must_not: must_not,
+    }
+}
+
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+    default_operator: Operator,
+}
+
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).cloned()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().map_or(false, |c| c.is_whitespace()) {
+            self.position += 1;
+        }
+    }
+
+    fn parse_disjunction(&mut self, depth: usize) -> Option<QueryStringNode> {
+        let mut clauses = Vec::new();
+
+        loop {
+            clauses.extend(self.parse_conjunction(depth));
+
+            self.skip_whitespace();
+            if self.peek() != Some('|') {
+                break;
+            }
+
+            self.position += 1;
+        }
+
+        match clauses.len() {
+            0 => None,
+            1 => clauses.pop(),
+            _ => Some(boolean(Vec::new(), clauses, Vec::new())),
+        }
+    }
+
+    fn parse_conjunction(&mut self, depth: usize) -> Option<QueryStringNode> {
+        let mut clauses = Vec::new();
+
+        loop {
+            clauses.extend(self.parse_sequence(depth));
+
+            self.skip_whitespace();
+            if self.peek() != Some('+') {
+                break;
+            }
+
+            self.position += 1;
+        }
+
+        match clauses.len() {
+            0 => None,
+            1 => clauses.pop(),
+            _ => Some(boolean(clauses, Vec::new(), Vec::new())),
+        }
+    }
+
+    /// Parses clauses that are written next to each other
+    fn parse_sequence(&mut self, depth: usize) -> Option<QueryStringNode> {
+        let mut clauses = Vec::new();
+
+        loop {
+            self.skip_whitespace();
+
+            match self.peek() {
+                None | Some('|') | Some('+') => break,
+                Some(')') if depth > 0 => break,
+                Some(')') => {
+                    // Ignore a ")" without a "("
+                    self.position += 1;
+                    continue;
+                }
+                _ => {}
+            }
+
+            clauses.extend(self.parse_clause(depth));
+        }
+
+        if clauses.len() == 1 && !clauses[0].0 {
+            return clauses.pop().map(|(_, clause)| clause);
+        }
+
+        let mut positive = Vec::new();
+        let mut must_not = Vec::new();
+
+        for (is_negated, clause) in clauses {
+            if is_negated {
+                must_not.push(clause);
+            } else {
+                positive.push(clause);
+            }
+        }
+
+        if positive.is_empty() && must_not.is_empty() {
+            return None;
+        }
+
+        Some(match self.default_operator {
+            Operator::And => boolean(positive, Vec::new(), must_not),
+            Operator::Or => boolean(Vec::new(), positive, must_not),
+        })
+    }
+
+    /// Parses a clause, along with whether it is negated. Always moves forward
+    fn parse_clause(&mut self, depth: usize) -> Option<(bool, QueryStringNode)> {
+        let start = self.position;
+
+        let is_negated = self.peek() == Some('-');
+        if is_negated {
+            self.position += 1;
+        }
+
+        let node = match self.peek() {
+            Some('(') => {
+                self.position += 1;
+                let node = self.parse_disjunction(depth + 1);
+
+                // Groups that aren't closed end with the query
+                if self.peek() == Some(')') {
+                    self.position += 1;
+                }
+
+                node
+            }
+            Some('"') => {
+                self.position += 1;
+
+                // Phrases that aren't closed end with the query
+                let mut text = String::new();
+                while let Some(c) = self.peek() {
+                    self.position += 1;
+
+                    match c {
+                        '"' => break,
+                        '\\' => {
+                            if let Some(escaped) = self.peek() {
+                                text.push(escaped);
+                                self.position += 1;
+                            }
+                        }
+                        _ => text.push(c),
+                    }
+                }
+
+                let slop = self.parse_tilde().and_then(|slop| slop).unwrap_or(0);
+
+                Some(QueryStringNode::Phrase {
+                    field: None,
+                    text: text,
+                    slop: slop,
+                })
+            }
+            _ => {
+                let (word, is_prefix) = self.read_word();
+
+                if word.is_empty() {
+                    None
+                } else if is_prefix {
+                    Some(QueryStringNode::Prefix {
+                        field: None,
+                        prefix: word,
+                    })
+                } else {
+                    match self.parse_tilde() {
+                        Some(max_distance) => {
+                            Some(QueryStringNode::Fuzzy {
+                                field: None,
+                                text: word,
+                                max_distance: max_distance.map(|max_distance| max_distance.min(2)),
+                            })
+                        }
+                        None => {
+                            Some(QueryStringNode::Term {
+                                field: None,
+                                text: word,
+                            })
+                        }
+                    }
+                }
+            }
+        };
+
+        // Skip a character that can't start a clause, such as a "~" on its own
+        if self.position == start || (is_negated && self.position == start + 1 && node.is_none()) {
+            self.position += 1;
+        }
+
+        node.map(|node| (is_negated, node))
+    }
+
+    /// Reads a word, handling escapes. Also returns whether it ended with an
+    /// unescaped "*", which is removed
+    fn read_word(&mut self) -> (String, bool) {
+        let mut word = String::new();
+        let mut is_prefix = false;
+
+        while let Some(c) = self.peek() {
+            if is_special_character(c) {
+                break;
+            }
+
+            self.position += 1;
+            is_prefix = false;
+
+            match c {
+                '\\' => {
+                    if let Some(escaped) = self.peek() {
+                        word.push(escaped);
+                        self.position += 1;
+                    }
+                }
+                '*' => {
+                    word.push(c);
+                    is_prefix = true;
+                }
+                _ => word.push(c),
+            }
+        }
+
+        if is_prefix {
+            word.pop();
+        }
+
+        (word, is_prefix)
+    }
+
+    /// Parses a "~" followed by an optional number. Numbers that can't be
+    /// read are ignored
+    fn parse_tilde(&mut self) -> Option<Option<u32>> {
+        if self.peek() != Some('~') {
+            return None;
+        }
+
+        self.position += 1;
+
+        let mut number = String::new();
+        while let Some(c) = self.peek().filter(|c| c.is_digit(10)) {
+            number.push(c);
+            self.position += 1;
+        }
+
+        Some(number.parse::<u32>().ok())
+    }
+}
+
+
+/// Parses a simple query string, which always succeeds. Query strings without
+/// any clauses give a boolean node without any clauses
+pub fn parse_simple_query_string(query: &str, default_operator: Operator) -> QueryStringNode {
+    let mut parser = Parser {
+        chars: query.chars().collect(),
+        position: 0,
+        default_operator: default_operator,
+    };
+
+    let mut clauses = Vec::new();
+    while parser.position < parser.chars.len() {
+        clauses.extend(parser.parse_disjunction(0));
+    }
+
+    match clauses.len() {
+        1 => clauses.pop().unwrap(),
+        _ => boolean(Vec::new(), clauses, Vec::new()),
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    // Get configuration
+    let mut query = None;
+    let mut fields = vec!["_all".to_string()];
+    let mut default_operator = Operator::Or;
+    let mut boost = 1.0f32;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "query" => {
+                query = Some(parse_string(value)?);
+            }
+            "fields" => {
+                fields = parse_fields(value)?;
+            }
+            "default_operator" => {
+                default_operator = parse_operator(&Json::String(parse_string(value)?.to_lowercase()))?;
+            }
+            "boost" => {
+                boost = parse_float(value)?;
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    let query = query.ok_or(QueryParseError::ExpectedKey("query"))?;
+
+    Ok(Box::new(SimpleQueryStringQueryBuilder {
+        query: parse_simple_query_string(&query, default_operator),
+        fields: fields,
+        default_operator: default_operator,
+        boost: boost,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use search::{Term, Query, MultiTermSelector, TermScorer};
+    use search::schema::{Schema, FieldType, FIELD_INDEXED};
+
+    use query_parser::QueryBuildContext;
+    use query_parser::utils::Operator;
+    use query_parser::query_string_syntax::QueryStringNode;
+
+    use super::{parse, parse_simple_query_string};
+
+    fn term(text: &str) -> QueryStringNode {
+        QueryStringNode::Term {
+            field: None,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_simple_query_string() {
+        assert_eq!(parse_simple_query_string("foo + bar | baz", Operator::Or), QueryStringNode::Boolean {
+            must: vec![],
+            should: vec![
+                QueryStringNode::Boolean { must: vec![term("foo"), term("bar")], should: vec![], must_not: vec![] },
+                term("baz"),
+            ],
+            must_not: vec![],
+        });
+
+        assert_eq!(parse_simple_query_string("\"fried eggs\"~2 -eggplant pota*", Operator::And), QueryStringNode::Boolean {
+            must: vec![
+                QueryStringNode::Phrase { field: None, text: "fried eggs".to_string(), slop: 2 },
+                QueryStringNode::Prefix { field: None, prefix: "pota".to_string() },
+            ],
+            should: vec![],
+            must_not: vec![term("eggplant")],
+        });
+    }
+
+    #[test]
+    fn test_parse_malformed_simple_query_string() {
+        // Unclosed groups and phrases end with the query
+        assert_eq!(parse_simple_query_string("(foo | bar", Operator::Or), QueryStringNode::Boolean {
+            must: vec![],
+            should: vec![term("foo"), term("bar")],
+            must_not: vec![],
+        });
+        assert_eq!(parse_simple_query_string("\"foo bar", Operator::Or), QueryStringNode::Phrase { field: None, text: "foo bar".to_string(), slop: 0 });
+
+        // Stray operators are ignored
+        assert_eq!(parse_simple_query_string("foo )) + | ~ -", Operator::Or), term("foo"));
+        assert_eq!(parse_simple_query_string("+|", Operator::Or), QueryStringNode::Boolean { must: vec![], should: vec![], must_not: vec![] });
+        assert_eq!(parse_simple_query_string("foo~x", Operator::Or), QueryStringNode::Boolean {
+            must: vec![],
+            should: vec![QueryStringNode::Fuzzy { field: None, text: "foo".to_string(), max_distance: None }, term("x")],
+            must_not: vec![],
+        });
+    }
+
+    #[test]
+    fn test_simple_query_string_query() {
+        let mut schema = Schema::new();
+        let title_field = schema.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"query\": \"Hello -wor*\",
+            \"fields\": [\"title\"]
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::term(title_field, Term::from_string("hello")).exclude(Query::MultiTerm {
+            field: title_field,
+            term_selector: MultiTermSelector::Prefix("wor".to_string()),
+            scorer: TermScorer::default(),
+        })));
+
+        // Blank queries match nothing
+        let query = parse(&serde_json::from_str("
+        {
+            \"query\": \"  \"
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::None));
+    }
+}
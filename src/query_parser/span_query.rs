@@ -0,0 +1,368 @@
+//! Parses "span_term", "span_near", "span_first" and "span_or" queries
+//!
+//! Spans are the same as the intervals of an intervals query, so these are
+//! built into intervals sources. Unlike intervals queries, the terms of span
+//! queries are not analyzed.
+
+use serde_json::Value as Json;
+use search::{Term, Query, TermScorer};
+use search::schema::Schema;
+use search::query::intervals::IntervalsSource;
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::utils::{parse_float, json_value_to_term};
+
+
+#[derive(Debug)]
+struct SpanQueryBuilder {
+    field: String,
+    source: IntervalsSource<Term>,
+    boost: f32,
+}
+
+
+impl QueryBuilder for SpanQueryBuilder {
+    fn build(&self, _context: &QueryBuildContext, schema: &Schema) -> Query {
+        let field = match schema.get_field_by_name(&self.field) {
+            Some(field) => field,
+            None => return Query::None,
+        };
+
+        Query::Intervals {
+            field: field,
+            source: self.source.clone(),
+            scorer: TermScorer::default_with_boost(self.boost),
+        }
+    }
+}
+
+
+/// A span query, along with the field that it is on
+struct Span {
+    field: String,
+    source: IntervalsSource<Term>,
+    boost: f32,
+}
+
+
+/// Parses a span query given as an object with a single key naming its type.
+/// The clauses of span queries must be span queries themselves
+fn parse_span_clause(json: &Json) -> Result<Span, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    if object.len() != 1 {
+        return Err(QueryParseError::ExpectedSingleKey);
+    }
+
+    let (span_type, span_json) = object.iter().next().unwrap();
+    match span_type.as_ref() {
+        "span_term" => parse_span_term_source(span_json),
+        "span_near" => parse_span_near_source(span_json),
+        "span_first" => parse_span_first_source(span_json),
+        "span_or" => parse_span_or_source(span_json),
+        _ => Err(QueryParseError::UnrecognisedQueryType(span_type.clone())),
+    }
+}
+
+
+/// Parses the clauses of a span query, checking that they are all on the same field
+fn parse_span_clauses(json: &Json) -> Result<(String, Vec<IntervalsSource<Term>>), QueryParseError> {
+    let array = json.as_array().ok_or(QueryParseError::ExpectedArray)?;
+    let mut field: Option<String> = None;
+    let mut sources = Vec::with_capacity(array.len());
+
+    for clause_json in array.iter() {
+        let clause = parse_span_clause(clause_json)?;
+
+        match field {
+            Some(ref field) if *field != clause.field => return Err(QueryParseError::InvalidValue),
+            Some(_) => {}
+            None => field = Some(clause.field),
+        }
+
+        sources.push(clause.source);
+    }
+
+    // Spans can't be made from nothing
+    match field {
+        Some(field) => Ok((field, sources)),
+        None => Err(QueryParseError::InvalidValue),
+    }
+}
+
+
+fn parse_span_term_source(json: &Json) -> Result<Span, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let field_name = if object.len() == 1 {
+        object.keys().collect::<Vec<_>>()[0]
+    } else {
+        return Err(QueryParseError::ExpectedSingleKey)
+    };
+
+    let object = object.get(field_name).unwrap();
+
+    // Get configuration
+    let mut term: Option<Term> = None;
+    let mut boost = 1.0f32;
+
+    match *object {
+        Json::Object(ref inner_object) => {
+            for (key, val) in inner_object.iter() {
+                match key.as_ref() {
+                    "value" | "term" => {
+                        term = Some(json_value_to_term(val).ok_or(QueryParseError::InvalidValue)?);
+                    }
+                    "boost" => {
+                        boost = parse_float(val)?;
+                    }
+                    _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+                }
+            }
+        }
+        _ => term = json_value_to_term(object),
+    }
+
+    Ok(Span {
+        field: field_name.clone(),
+        source: IntervalsSource::Term(term.ok_or(QueryParseError::ExpectedKey("value"))?),
+        boost: boost,
+    })
+}
+
+
+fn parse_span_near_source(json: &Json) -> Result<Span, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    // Get configuration
+    let mut clauses = None;
+    let mut slop = 0;
+    let mut in_order = true;
+    let mut boost = 1.0f32;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "clauses" => {
+                clauses = Some(parse_span_clauses(value)?);
+            }
+            "slop" => {
+                slop = value.as_u64().ok_or(QueryParseError::InvalidValue)? as u32;
+            }
+            "in_order" => {
+                in_order = value.as_bool().ok_or(QueryParseError::InvalidValue)?;
+            }
+            "boost" => {
+                boost = parse_float(value)?;
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    // The slop is the most positions that can be between the clauses
+    let (field, sources) = clauses.ok_or(QueryParseError::ExpectedKey("clauses"))?;
+    Ok(Span {
+        field: field,
+        source: IntervalsSource::AllOf {
+            sources: sources,
+            ordered: in_order,
+            max_gaps: Some(slop),
+        },
+        boost: boost,
+    })
+}
+
+
+fn parse_span_first_source(json: &Json) -> Result<Span, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    // Get configuration
+    let mut span = None;
+    let mut end = None;
+    let mut boost = 1.0f32;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "match" => {
+                span = Some(parse_span_clause(value)?);
+            }
+            "end" => {
+                end = Some(value.as_u64().ok_or(QueryParseError::InvalidValue)? as u32);
+            }
+            "boost" => {
+                boost = parse_float(value)?;
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    // Positions start at one, so a span in the first "end" positions ends by that position
+    let span = span.ok_or(QueryParseError::ExpectedKey("match"))?;
+    Ok(Span {
+        field: span.field,
+        source: IntervalsSource::First {
+            source: Box::new(span.source),
+            end: end.ok_or(QueryParseError::ExpectedKey("end"))?,
+        },
+        boost: boost,
+    })
+}
+
+
+fn parse_span_or_source(json: &Json) -> Result<Span, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    // Get configuration
+    let mut clauses = None;
+    let mut boost = 1.0f32;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "clauses" => {
+                clauses = Some(parse_span_clauses(value)?);
+            }
+            "boost" => {
+                boost = parse_float(value)?;
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    let (field, sources) = clauses.ok_or(QueryParseError::ExpectedKey("clauses"))?;
+    Ok(Span {
+        field: field,
+        source: IntervalsSource::AnyOf {
+            sources: sources,
+        },
+        boost: boost,
+    })
+}
+
+
+fn build_span_query(span: Span) -> Box<QueryBuilder> {
+    Box::new(SpanQueryBuilder {
+        field: span.field,
+        source: span.source,
+        boost: span.boost,
+    })
+}
+
+
+pub fn parse_span_term(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    parse_span_term_source(json).map(build_span_query)
+}
+
+
+pub fn parse_span_near(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    parse_span_near_source(json).map(build_span_query)
+}
+
+
+pub fn parse_span_first(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    parse_span_first_source(json).map(build_span_query)
+}
+
+
+pub fn parse_span_or(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    parse_span_or_source(json).map(build_span_query)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use search::{Term, Query, TermScorer};
+    use search::schema::{Schema, FieldType, FIELD_INDEXED};
+    use search::query::intervals::IntervalsSource;
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::{parse_span_term, parse_span_near, parse_span_first};
+
+    #[test]
+    fn test_span_term_query() {
+        let mut schema = Schema::new();
+        let body_field = schema.add_field("body".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse_span_term(&json!({
+            "body": {"value": "Porridge", "boost": 2}
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Intervals {
+            field: body_field,
+            source: IntervalsSource::Term(Term::from_string("Porridge")),
+            scorer: TermScorer::default_with_boost(2.0f32),
+        }));
+    }
+
+    #[test]
+    fn test_span_near_query() {
+        let mut schema = Schema::new();
+        let body_field = schema.add_field("body".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse_span_near(&json!({
+            "clauses": [
+                {"span_term": {"body": "hot"}},
+                {"span_or": {"clauses": [{"span_term": {"body": "porridge"}}, {"span_term": {"body": "soup"}}]}}
+            ],
+            "slop": 5,
+            "in_order": true
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Intervals {
+            field: body_field,
+            source: IntervalsSource::AllOf {
+                sources: vec![
+                    IntervalsSource::Term(Term::from_string("hot")),
+                    IntervalsSource::AnyOf {
+                        sources: vec![
+                            IntervalsSource::Term(Term::from_string("porridge")),
+                            IntervalsSource::Term(Term::from_string("soup")),
+                        ],
+                    },
+                ],
+                ordered: true,
+                max_gaps: Some(5),
+            },
+            scorer: TermScorer::default(),
+        }));
+    }
+
+    #[test]
+    fn test_span_first_query() {
+        let mut schema = Schema::new();
+        let body_field = schema.add_field("body".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse_span_first(&json!({
+            "match": {"span_term": {"body": "porridge"}},
+            "end": 3
+        })).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Intervals {
+            field: body_field,
+            source: IntervalsSource::First {
+                source: Box::new(IntervalsSource::Term(Term::from_string("porridge"))),
+                end: 3,
+            },
+            scorer: TermScorer::default(),
+        }));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_clauses() {
+        // Clauses must be on the same field
+        let query = parse_span_near(&json!({
+            "clauses": [{"span_term": {"body": "hot"}}, {"span_term": {"title": "porridge"}}]
+        }));
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+
+        // Clauses must be span queries
+        let query = parse_span_near(&json!({
+            "clauses": [{"term": {"body": "hot"}}]
+        }));
+        assert_eq!(query.err(), Some(QueryParseError::UnrecognisedQueryType("term".to_string())));
+
+        let query = parse_span_first(&json!({
+            "match": {"span_term": {"body": "porridge"}}
+        }));
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("end")));
+    }
+}
@@ -1,27 +1,47 @@
 //! Parses "term" queries
 
+use std::str;
+
 use serde_json::Value as Json;
-use search::{Term, Query, TermScorer};
+use search::{Term, Query, MultiTermSelector, TermScorer};
 use search::schema::Schema;
+use search::query::wildcard::{WildcardPattern, escape};
 
 use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
-use query_parser::utils::{parse_float, json_value_to_term};
+use query_parser::utils::{parse_float, json_value_to_term, resolve_field, resolved_field_term};
 
 
 #[derive(Debug)]
 struct TermQueryBuilder {
     field: String,
     term: Term,
+    case_insensitive: bool,
     boost: f32,
 }
 
 
 impl QueryBuilder for TermQueryBuilder {
     fn build(&self, _context: &QueryBuildContext, schema: &Schema) -> Query {
-        let query = Query::Term {
-            field: schema.get_field_by_name(&self.field).unwrap(),
-            term: self.term.clone(),
-            scorer: TermScorer::default(),
+        let (field, key) = resolve_field(schema, &self.field).unwrap();
+        let term = resolved_field_term(key, &self.term);
+
+        // Case insensitive terms can match many terms in the dictionary, so these are
+        // found with a pattern that matches the whole term
+        let query = match (self.case_insensitive, str::from_utf8(term.as_bytes())) {
+            (true, Ok(value)) => {
+                Query::MultiTerm {
+                    field: field,
+                    term_selector: MultiTermSelector::Wildcard(WildcardPattern::new(&escape(value)).case_insensitive()),
+                    scorer: TermScorer::default(),
+                }
+            }
+            _ => {
+                Query::Term {
+                    field: field,
+                    term: term,
+                    scorer: TermScorer::default(),
+                }
+            }
         };
 
         // Add boost
@@ -43,6 +63,7 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
 
     // Get configuration
     let mut term: Option<Term> = None;
+    let mut case_insensitive = false;
     let mut boost = 1.0f32;
 
     match *object {
@@ -56,6 +77,9 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
                             return Err(QueryParseError::InvalidValue);
                         }
                     }
+                    "case_insensitive" => {
+                        case_insensitive = val.as_bool().ok_or(QueryParseError::InvalidValue)?;
+                    }
                     "boost" => {
                         boost = parse_float(val)?;
                     }
@@ -66,11 +90,19 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
         _ => term = json_value_to_term(object),
     }
 
+    // Only strings have a case
+    if let Json::Object(ref inner_object) = *object {
+        if !inner_object.get("value").map(|value| value.is_string()).unwrap_or(false) {
+            case_insensitive = false;
+        }
+    }
+
     match term {
         Some(term) => {
             Ok(Box::new(TermQueryBuilder {
                 field: field_name.clone(),
                 term: term,
+                case_insensitive: case_insensitive,
                 boost: boost,
             }))
         }
@@ -83,13 +115,32 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
 mod tests {
     use serde_json;
 
-    use search::{Term, Query, TermScorer};
+    use search::{Term, Query, MultiTermSelector, TermScorer};
     use search::schema::{Schema, FieldType, FIELD_INDEXED};
+    use search::query::wildcard::WildcardPattern;
 
     use query_parser::{QueryBuildContext, QueryParseError};
 
     use super::parse;
 
+    #[test]
+    fn test_flattened_field_key() {
+        let mut schema = Schema::new();
+        let labels_field = schema.add_field("labels".to_string(), FieldType::Flattened, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"labels.app.tier\": \"frontend\"
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Term {
+            field: labels_field,
+            term: Term::from_bytes(b"app.tier\0frontend"),
+            scorer: TermScorer::default(),
+        }));
+    }
+
     #[test]
     fn test_term_query() {
         let mut schema = Schema::new();
@@ -148,6 +199,43 @@ mod tests {
         }));
     }
 
+    #[test]
+    fn test_case_insensitive() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::PlainString, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"value\": \"Bar*\",
+                \"case_insensitive\": true
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::MultiTerm {
+            field: foo_field,
+            term_selector: MultiTermSelector::Wildcard(WildcardPattern::new("Bar\\*").case_insensitive()),
+            scorer: TermScorer::default(),
+        }));
+
+        // Numbers don't have a case
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"value\": 123,
+                \"case_insensitive\": true
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Term {
+            field: foo_field,
+            term: Term::from_integer(123),
+            scorer: TermScorer::default(),
+        }));
+    }
+
     #[test]
     fn test_with_boost() {
         let mut schema = Schema::new();
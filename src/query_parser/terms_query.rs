@@ -1,27 +1,94 @@
-//! Parses "match" queries
+//! Parses "terms" queries
 
 use serde_json::Value as Json;
 use search::{Term, Query, TermScorer};
 use search::schema::Schema;
 
 use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
-use query_parser::utils::json_value_to_term;
+use query_parser::utils::{parse_string, json_value_to_term, resolve_field, resolved_field_term};
+
+
+#[derive(Debug)]
+enum TermsSource {
+    /// The terms were given in the query
+    Terms(Vec<Term>),
+
+    /// The terms must be fetched from a field of another document at query time
+    Lookup {
+        index: String,
+        id: String,
+        path: String,
+    },
+}
+
 
 #[derive(Debug)]
 struct TermsQueryBuilder {
     field: String,
-    terms: Vec<Term>,
+    source: TermsSource,
+}
+
+
+impl TermsQueryBuilder {
+    fn lookup_terms(&self, context: &QueryBuildContext, index_name: &str, id: &str, path: &str) -> Result<Vec<Term>, QueryParseError> {
+        let cluster_metadata = match context.cluster_metadata {
+            Some(cluster_metadata) => cluster_metadata,
+            None => return Ok(Vec::new()),
+        };
+
+        let index = match cluster_metadata.names.find_canonical(index_name).and_then(|index_ref| cluster_metadata.indices.get(&index_ref)) {
+            Some(index) => index,
+            None => return Err(QueryParseError::TermsLookupFailed(format!("index {:?} not found", index_name))),
+        };
+
+        // The metadata of the index being searched is already locked
+        let terms = match context.index.and_then(|searched_index| if searched_index.id() == index.id() { context.index_metadata } else { None }) {
+            Some(index_metadata) => index.lookup_terms(index_metadata, id, path),
+            None => index.lookup_terms(&index.metadata.read().unwrap(), id, path),
+        };
+
+        terms.map_err(QueryParseError::TermsLookupFailed)
+    }
 }
 
 
 impl QueryBuilder for TermsQueryBuilder {
-    fn build(&self, _context: &QueryBuildContext, schema: &Schema) -> Query {
+    fn check(&self, context: &QueryBuildContext) -> Result<(), QueryParseError> {
+        match self.source {
+            TermsSource::Terms(_) => Ok(()),
+            TermsSource::Lookup{ref index, ref id, ref path} => self.lookup_terms(context, index, id, path).map(|_| ()),
+        }
+    }
+
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+        let looked_up_terms;
+        let terms = match self.source {
+            TermsSource::Terms(ref terms) => terms,
+            TermsSource::Lookup{ref index, ref id, ref path} => {
+                // Failed lookups are reported by check, before the query is built
+                looked_up_terms = self.lookup_terms(context, index, id, path).unwrap_or_else(|_| Vec::new());
+                &looked_up_terms
+            }
+        };
+
+        let (field, key) = resolve_field(schema, &self.field).unwrap();
+
+        // Filters don't need each term to be scored, so the documents can be
+        // found with a single union of the postings lists
+        if !context.score_required() {
+            return Query::TermSet {
+                field: field,
+                terms: terms.iter().map(|term| resolved_field_term(key, term)).collect(),
+                score: 1.0f32,
+            };
+        }
+
         // Create a term query for each token
         let mut queries = Vec::new();
-        for term in self.terms.iter() {
+        for term in terms.iter() {
             queries.push(Query::Term {
-                field: schema.get_field_by_name(&self.field).unwrap(),
-                term: term.clone(),
+                field: field,
+                term: resolved_field_term(key, term),
                 scorer: TermScorer::default(),
             });
         }
@@ -31,6 +98,38 @@ impl QueryBuilder for TermsQueryBuilder {
 }
 
 
+fn parse_lookup(object: &::serde_json::Map<String, Json>) -> Result<TermsSource, QueryParseError> {
+    let mut index = None;
+    let mut id = None;
+    let mut path = None;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "index" => {
+                index = Some(parse_string(value)?);
+            }
+            "id" => {
+                id = Some(parse_string(value)?);
+            }
+            "path" => {
+                path = Some(parse_string(value)?);
+            }
+            "type" => {
+                // Mapping types are not needed to find a document
+                parse_string(value)?;
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(TermsSource::Lookup {
+        index: index.ok_or(QueryParseError::ExpectedKey("index"))?,
+        id: id.ok_or(QueryParseError::ExpectedKey("id"))?,
+        path: path.ok_or(QueryParseError::ExpectedKey("path"))?,
+    })
+}
+
+
 pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
     let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
 
@@ -41,15 +140,17 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
     };
 
     // Get configuration
-    let terms: Vec<Term> = if let &Json::Array(ref arr) = object.get(field_name).unwrap() {
-        arr.iter().filter_map(|term| json_value_to_term(&term)).collect()
-    } else {
-        return Err(QueryParseError::ExpectedArray);
+    let source = match *object.get(field_name).unwrap() {
+        Json::Array(ref arr) => {
+            TermsSource::Terms(arr.iter().filter_map(|term| json_value_to_term(&term)).collect())
+        }
+        Json::Object(ref lookup) => parse_lookup(lookup)?,
+        _ => return Err(QueryParseError::ExpectedArray),
     };
 
     Ok(Box::new(TermsQueryBuilder {
         field: field_name.clone(),
-        terms: terms,
+        source: source,
     }))
 }
 
@@ -62,6 +163,7 @@ mod tests {
 
     use query_parser::{QueryBuildContext, QueryParseError};
     use search::schema::{Schema, FieldType, FIELD_INDEXED};
+    use cluster::metadata::ClusterMetadata;
 
     use super::parse;
 
@@ -92,6 +194,27 @@ mod tests {
         }))
     }
 
+    #[test]
+    fn test_terms_filter() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": [\"bar\", \"baz\"]
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new().no_score(), &schema)));
+
+        assert_eq!(query, Ok(Query::TermSet {
+            field: foo_field,
+            terms: vec![
+                Term::from_string("bar"),
+                Term::from_string("baz"),
+            ],
+            score: 1.0f32,
+        }))
+    }
+
     #[test]
     fn test_gives_error_for_incorrect_type() {
         // Array
@@ -129,7 +252,7 @@ mod tests {
         }
         ").unwrap());
 
-        assert_eq!(query.err(), Some(QueryParseError::ExpectedArray));
+        assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("query".to_string())));
 
         // String
         let query = parse(&serde_json::from_str("
@@ -141,6 +264,58 @@ mod tests {
         assert_eq!(query.err(), Some(QueryParseError::ExpectedArray));
     }
 
+    #[test]
+    fn test_terms_lookup_query() {
+        let mut schema = Schema::new();
+        schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        // The terms are looked up when the query is built, there is no cluster to look them up in here
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"index\": \"users\",
+                \"id\": \"u1\",
+                \"path\": \"friends\"
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Disjunction {
+            queries: vec![],
+        }))
+    }
+
+    #[test]
+    fn test_terms_lookup_query_missing_index() {
+        let cluster_metadata = ClusterMetadata::new();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"index\": \"users\",
+                \"id\": \"u1\",
+                \"path\": \"friends\"
+            }
+        }
+        ").unwrap()).unwrap();
+
+        assert_eq!(query.check(&QueryBuildContext::new().set_cluster_metadata(&cluster_metadata)), Err(QueryParseError::TermsLookupFailed("index \"users\" not found".to_string())));
+    }
+
+    #[test]
+    fn test_terms_lookup_query_missing_key() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"index\": \"users\",
+                \"path\": \"friends\"
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("id")));
+    }
+
     #[test]
     fn test_gives_error_for_missing_query() {
         let query = parse(&serde_json::from_str("
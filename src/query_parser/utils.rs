@@ -1,6 +1,10 @@
 use serde_json::Value as Json;
 use search::term::Term;
+use search::schema::{Schema, FieldId, FieldType};
 
+use analysis::AnalyzerSpec;
+use mapping::{FieldSearchOptions, flattened_keyed_term};
+use index::metadata::IndexMetadata;
 use query_parser::QueryParseError;
 
 
@@ -25,7 +29,7 @@ pub fn parse_float(json: &Json) -> Result<f32, QueryParseError> {
 }
 
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Operator {
     Or,
     And,
@@ -46,6 +50,230 @@ pub fn parse_operator(json: &Json) -> Result<Operator, QueryParseError> {
 }
 
 
+/// The maximum edit distance of a fuzzy match
+#[derive(Debug, PartialEq)]
+pub enum Fuzziness {
+    /// Allow this many edits
+    Fixed(u32),
+
+    /// No edits for values shorter than low, one edit for values shorter than
+    /// high and two for anything longer
+    Auto {
+        low: usize,
+        high: usize,
+    },
+}
+
+
+impl Fuzziness {
+    pub fn max_distance(&self, value: &str) -> u32 {
+        match *self {
+            Fuzziness::Fixed(max_distance) => max_distance,
+            Fuzziness::Auto{low, high} => {
+                let length = value.chars().count();
+
+                if length < low {
+                    0
+                } else if length < high {
+                    1
+                } else {
+                    2
+                }
+            }
+        }
+    }
+}
+
+
+impl Default for Fuzziness {
+    fn default() -> Fuzziness {
+        Fuzziness::Auto {
+            low: 3,
+            high: 6,
+        }
+    }
+}
+
+
+/// Parses a number of edits from 0 to 2, "AUTO" or "AUTO:low,high"
+pub fn parse_fuzziness(json: &Json) -> Result<Fuzziness, QueryParseError> {
+    let fuzziness = match *json {
+        Json::Number(ref number) => {
+            match number.as_u64() {
+                Some(max_distance) => Fuzziness::Fixed(max_distance as u32),
+                None => return Err(QueryParseError::InvalidValue),
+            }
+        }
+        Json::String(ref string) if string == "AUTO" => Fuzziness::default(),
+        Json::String(ref string) if string.starts_with("AUTO:") => {
+            let mut split = string["AUTO:".len()..].splitn(2, ',');
+
+            match (split.next().map(|low| low.parse()), split.next().map(|high| high.parse())) {
+                (Some(Ok(low)), Some(Ok(high))) if low <= high => Fuzziness::Auto { low: low, high: high },
+                _ => return Err(QueryParseError::InvalidValue),
+            }
+        }
+        Json::String(ref string) => {
+            match string.parse() {
+                Ok(max_distance) => Fuzziness::Fixed(max_distance),
+                Err(_) => return Err(QueryParseError::InvalidValue),
+            }
+        }
+        _ => return Err(QueryParseError::InvalidValue),
+    };
+
+    match fuzziness {
+        Fuzziness::Fixed(max_distance) if max_distance > 2 => Err(QueryParseError::InvalidValue),
+        fuzziness => Ok(fuzziness),
+    }
+}
+
+
+/// How the terms that a multi term query expands to are scored
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rewrite {
+    /// Matching documents score the boost, however many terms they have
+    ConstantScore,
+
+    /// Each term is scored as a term query would
+    ScoringBoolean,
+
+    /// Like ScoringBoolean, but only this many of the terms are used
+    TopTerms(usize),
+}
+
+
+/// Parses a rewrite method. The blended and boost variants are treated like the
+/// ones they're based on
+pub fn parse_rewrite(json: &Json) -> Result<Rewrite, QueryParseError> {
+    let rewrite = parse_string(json)?;
+
+    match rewrite.as_ref() {
+        "constant_score" | "constant_score_boolean" | "constant_score_blended" => return Ok(Rewrite::ConstantScore),
+        "scoring_boolean" => return Ok(Rewrite::ScoringBoolean),
+        _ => {}
+    }
+
+    for prefix in ["top_terms_blended_freqs_", "top_terms_boost_", "top_terms_"].iter() {
+        if rewrite.starts_with(prefix) {
+            return match rewrite[prefix.len()..].parse() {
+                Ok(size) if size > 0 => Ok(Rewrite::TopTerms(size)),
+                _ => Err(QueryParseError::InvalidValue),
+            };
+        }
+    }
+
+    Err(QueryParseError::InvalidValue)
+}
+
+
+/// How many of the optional clauses of a query must match
+#[derive(Debug, Clone, PartialEq)]
+pub enum MinimumShouldMatch {
+    /// This many clauses must match. Negative counts are the number of clauses that can be missed
+    Count(i64),
+
+    /// This percentage of the clauses must match, rounded down. Negative
+    /// percentages are the percentage of clauses that can be missed
+    Percentage(f64),
+
+    /// Each threshold is paired with what applies when there are more clauses
+    /// than it. All clauses must match when there are fewer than the lowest threshold
+    Conditional(Vec<(usize, MinimumShouldMatch)>),
+}
+
+
+impl MinimumShouldMatch {
+    /// Works out how many of the clauses must match
+    pub fn resolve(&self, num_clauses: usize) -> usize {
+        let minimum = match *self {
+            MinimumShouldMatch::Count(count) if count < 0 => num_clauses as i64 + count,
+            MinimumShouldMatch::Count(count) => count,
+            MinimumShouldMatch::Percentage(percentage) => {
+                let clauses = (num_clauses as f64 * percentage.abs() / 100.0).floor() as i64;
+
+                if percentage < 0.0 {
+                    num_clauses as i64 - clauses
+                } else {
+                    clauses
+                }
+            }
+            MinimumShouldMatch::Conditional(ref conditions) => {
+                match conditions.iter().filter(|&&(threshold, _)| num_clauses > threshold).last() {
+                    Some(&(_, ref minimum_should_match)) => minimum_should_match.resolve(num_clauses) as i64,
+                    None => num_clauses as i64,
+                }
+            }
+        };
+
+        if minimum < 0 {
+            0
+        } else {
+            minimum as usize
+        }
+    }
+}
+
+
+fn parse_minimum_should_match_str(value: &str) -> Result<MinimumShouldMatch, QueryParseError> {
+    if value.ends_with('%') {
+        match value[..value.len() - 1].parse() {
+            Ok(percentage) => Ok(MinimumShouldMatch::Percentage(percentage)),
+            Err(_) => Err(QueryParseError::InvalidValue),
+        }
+    } else {
+        match value.parse() {
+            Ok(count) => Ok(MinimumShouldMatch::Count(count)),
+            Err(_) => Err(QueryParseError::InvalidValue),
+        }
+    }
+}
+
+
+/// Parses a count, such as 2 or "-1", a percentage, such as "75%" or "-25%",
+/// or a list of conditions, such as "2<-25% 9<-3"
+pub fn parse_minimum_should_match(json: &Json) -> Result<MinimumShouldMatch, QueryParseError> {
+    let value = match *json {
+        Json::Number(ref number) => {
+            return match number.as_i64() {
+                Some(count) => Ok(MinimumShouldMatch::Count(count)),
+                None => Err(QueryParseError::InvalidValue),
+            };
+        }
+        Json::String(ref value) => value.trim(),
+        _ => return Err(QueryParseError::InvalidValue),
+    };
+
+    if !value.contains('<') {
+        return parse_minimum_should_match_str(value);
+    }
+
+    let mut conditions: Vec<(usize, MinimumShouldMatch)> = Vec::new();
+    for condition in value.split_whitespace() {
+        let mut split = condition.splitn(2, '<');
+
+        let threshold = match split.next().map(|threshold| threshold.parse()) {
+            Some(Ok(threshold)) => threshold,
+            _ => return Err(QueryParseError::InvalidValue),
+        };
+
+        let minimum_should_match = match split.next() {
+            Some(value) => parse_minimum_should_match_str(value)?,
+            None => return Err(QueryParseError::InvalidValue),
+        };
+
+        // Thresholds must go up
+        if conditions.last().map_or(false, |&(last_threshold, _)| threshold <= last_threshold) {
+            return Err(QueryParseError::InvalidValue);
+        }
+
+        conditions.push((threshold, minimum_should_match));
+    }
+
+    Ok(MinimumShouldMatch::Conditional(conditions))
+}
+
+
 pub fn parse_field_and_boost(json: &Json) -> Result<(String, f32), QueryParseError> {
     let string = parse_string(json)?;
 
@@ -75,3 +303,97 @@ pub fn json_value_to_term(json: &Json) -> Option<Term> {
         &Json::Object(_) => None,
     }
 }
+
+
+/// Finds a field by name
+///
+/// Names that don't match a field may refer to a key inside a flattened field, such
+/// as "labels.env". These give the flattened field along with the key.
+pub fn resolve_field<'a>(schema: &Schema, field_name: &'a str) -> Option<(FieldId, Option<&'a str>)> {
+    if let Some(field) = schema.get_field_by_name(field_name) {
+        return Some((field, None));
+    }
+
+    for (position, _) in field_name.match_indices('.') {
+        if let Some(field) = schema.get_field_by_name(&field_name[..position]) {
+            if schema.get(&field).map(|field_info| field_info.field_type == FieldType::Flattened).unwrap_or(false) {
+                return Some((field, Some(&field_name[position + 1..])));
+            }
+        }
+    }
+
+    None
+}
+
+
+/// Finds the analyzer to tokenise the query text for a field with
+///
+/// An analyzer named in the query overrides the search analyzer of the field. It's
+/// looked up in the analyzers of the index, the field's analyzer is used if it isn't there
+pub fn search_analyzer(index_metadata: Option<&IndexMetadata>, field_name: &str, analyzer_name: Option<&str>) -> Option<AnalyzerSpec> {
+    let index_metadata = match index_metadata {
+        Some(index_metadata) => index_metadata,
+        None => return FieldSearchOptions::default().analyzer,  // TODO: error?
+    };
+
+    if let Some(analyzer) = analyzer_name.and_then(|analyzer_name| index_metadata.analyzers().get(analyzer_name)) {
+        return Some(analyzer.clone());
+    }
+
+    match index_metadata.get_field_mapping(field_name) {
+        Some(field_mapping) => field_mapping.get_search_options().analyzer,
+        None => FieldSearchOptions::default().analyzer,  // TODO: error?
+    }
+}
+
+
+/// Converts a term into the term to search for in a field that was found with resolve_field
+pub fn resolved_field_term(key: Option<&str>, term: &Term) -> Term {
+    match key {
+        Some(key) => flattened_keyed_term(key, term.as_bytes()),
+        None => term.clone(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use query_parser::QueryParseError;
+
+    use super::{MinimumShouldMatch, parse_minimum_should_match};
+
+    #[test]
+    fn test_parse_minimum_should_match() {
+        assert_eq!(parse_minimum_should_match(&json!(2)), Ok(MinimumShouldMatch::Count(2)));
+        assert_eq!(parse_minimum_should_match(&json!("-1")), Ok(MinimumShouldMatch::Count(-1)));
+        assert_eq!(parse_minimum_should_match(&json!("75%")), Ok(MinimumShouldMatch::Percentage(75.0)));
+        assert_eq!(parse_minimum_should_match(&json!("2<-25% 9<-3")), Ok(MinimumShouldMatch::Conditional(vec![
+            (2, MinimumShouldMatch::Percentage(-25.0)),
+            (9, MinimumShouldMatch::Count(-3)),
+        ])));
+
+        assert_eq!(parse_minimum_should_match(&json!("most")), Err(QueryParseError::InvalidValue));
+        assert_eq!(parse_minimum_should_match(&json!("3<")), Err(QueryParseError::InvalidValue));
+        assert_eq!(parse_minimum_should_match(&json!("9<-3 2<-25%")), Err(QueryParseError::InvalidValue));
+        assert_eq!(parse_minimum_should_match(&json!(true)), Err(QueryParseError::InvalidValue));
+    }
+
+    #[test]
+    fn test_resolve_minimum_should_match() {
+        assert_eq!(MinimumShouldMatch::Count(2).resolve(4), 2);
+        assert_eq!(MinimumShouldMatch::Count(-1).resolve(4), 3);
+        assert_eq!(MinimumShouldMatch::Count(-5).resolve(4), 0);
+        assert_eq!(MinimumShouldMatch::Percentage(75.0).resolve(3), 2);
+        assert_eq!(MinimumShouldMatch::Percentage(-25.0).resolve(3), 3);
+
+        let conditional = parse_minimum_should_match(&json!("3<90%")).unwrap();
+        assert_eq!(conditional.resolve(2), 2);
+        assert_eq!(conditional.resolve(3), 3);
+        assert_eq!(conditional.resolve(5), 4);
+
+        let conditional = parse_minimum_should_match(&json!("2<-25% 9<-3")).unwrap();
+        assert_eq!(conditional.resolve(1), 1);
+        assert_eq!(conditional.resolve(4), 3);
+        assert_eq!(conditional.resolve(12), 9);
+    }
+}
@@ -0,0 +1,219 @@
+//! Parses "wildcard" queries
+
+use serde_json::Value as Json;
+use search::{Query, MultiTermSelector, TermScorer};
+use search::schema::{Schema, FieldType};
+use search::query::wildcard::{WildcardPattern, trigram_term};
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::utils::parse_float;
+
+
+#[derive(Debug)]
+struct WildcardQueryBuilder {
+    field: String,
+    pattern: String,
+    case_insensitive: bool,
+    boost: f32,
+}
+
+
+impl QueryBuilder for WildcardQueryBuilder {
+    fn build(&self, _context: &QueryBuildContext, schema: &Schema) -> Query {
+        let field = schema.get_field_by_name(&self.field).unwrap();
+        let pattern = if self.case_insensitive {
+            WildcardPattern::new(&self.pattern).case_insensitive()
+        } else {
+            WildcardPattern::new(&self.pattern)
+        };
+        let trigrams = pattern.trigrams();
+
+        // Wildcard fields can find candidates by their trigrams. Other fields, and
+        // patterns that are too short to have any trigrams, test every term instead.
+        // Trigrams are indexed with their original case so they can't be used for
+        // case insensitive patterns
+        let is_wildcard_field = schema.get(&field).map(|field_info| field_info.field_type == FieldType::Wildcard).unwrap_or(false);
+        let query = if is_wildcard_field && !trigrams.is_empty() && !pattern.is_case_insensitive() {
+            Query::Wildcard {
+                field: field,
+                trigrams: trigrams.iter().map(|trigram| trigram_term(trigram)).collect(),
+                pattern: pattern,
+                score: 1.0f32,
+            }
+        } else {
+            Query::MultiTerm {
+                field: field,
+                term_selector: MultiTermSelector::Wildcard(pattern),
+                scorer: TermScorer::default(),
+            }
+        };
+
+        // Add boost
+        query.boost(self.boost)
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let field_name = if object.len() == 1 {
+        object.keys().collect::<Vec<_>>()[0]
+    } else {
+        return Err(QueryParseError::ExpectedSingleKey)
+    };
+
+    let object = object.get(field_name).unwrap();
+
+    // Get configuration
+    let mut value: Option<&Json> = None;
+    let mut case_insensitive = false;
+    let mut boost = 1.0f32;
+
+    match *object {
+        Json::String(_) => value = Some(object),
+        Json::Object(ref inner_object) => {
+            for (key, val) in inner_object.iter() {
+                match key.as_ref() {
+                    "value" => {
+                        value = Some(val);
+                    }
+                    "wildcard" => {
+                        value = Some(val);
+                    }
+                    "case_insensitive" => {
+                        case_insensitive = val.as_bool().ok_or(QueryParseError::InvalidValue)?;
+                    }
+                    "boost" => {
+                        boost = parse_float(val)?;
+                    }
+                    _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+                }
+            }
+        }
+        _ => return Err(QueryParseError::ExpectedObjectOrString),
+    }
+
+    match value {
+        Some(value) => {
+            if let Json::String(ref string) = *value {
+                Ok(Box::new(WildcardQueryBuilder {
+                    field: field_name.clone(),
+                    pattern: string.clone(),
+                    case_insensitive: case_insensitive,
+                    boost: boost,
+                }))
+            } else {
+                Err(QueryParseError::ExpectedString)
+            }
+        }
+        None => Err(QueryParseError::ExpectedKey("value"))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use search::{Query, MultiTermSelector, TermScorer};
+    use search::schema::{Schema, FieldType, FIELD_INDEXED, FIELD_STORED};
+    use search::query::wildcard::{WildcardPattern, trigram_term};
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    #[test]
+    fn test_wildcard_query() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Wildcard, FIELD_INDEXED | FIELD_STORED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"value\": \"*error*\"
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Wildcard {
+            field: foo_field,
+            trigrams: vec![trigram_term("err"), trigram_term("rro"), trigram_term("ror")],
+            pattern: WildcardPattern::new("*error*"),
+            score: 1.0f32,
+        }));
+    }
+
+    #[test]
+    fn test_without_trigrams() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Wildcard, FIELD_INDEXED | FIELD_STORED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": \"*er*\"
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::MultiTerm {
+            field: foo_field,
+            term_selector: MultiTermSelector::Wildcard(WildcardPattern::new("*er*")),
+            scorer: TermScorer::default(),
+        }));
+    }
+
+    #[test]
+    fn test_case_insensitive() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Wildcard, FIELD_INDEXED | FIELD_STORED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"value\": \"*Error*\",
+                \"case_insensitive\": true
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::MultiTerm {
+            field: foo_field,
+            term_selector: MultiTermSelector::Wildcard(WildcardPattern::new("*Error*").case_insensitive()),
+            scorer: TermScorer::default(),
+        }));
+    }
+
+    #[test]
+    fn test_on_text_field() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"wildcard\": \"*error*\",
+                \"boost\": 2.0
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::MultiTerm {
+            field: foo_field,
+            term_selector: MultiTermSelector::Wildcard(WildcardPattern::new("*error*")),
+            scorer: TermScorer::default_with_boost(2.0f32),
+        }));
+    }
+
+    #[test]
+    fn test_gives_error_for_missing_value() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("value")));
+    }
+}
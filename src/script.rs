@@ -0,0 +1,501 @@
+//! Scripts
+//!
+//! Elasticsearch runs Painless wherever a request holds a script. We support a
+//! small expression language instead, which covers arithmetic, comparisons and
+//! boolean logic over named variables such as "params.total_sales / 10 > 2".
+
+use std::str::Chars;
+use std::iter::Peekable;
+use std::collections::HashMap;
+
+use serde_json;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScriptValue {
+    Number(f64),
+    Boolean(bool),
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BinaryOperator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    And,
+    Or,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Literal(ScriptValue),
+
+    /// A value given to the script. "params.name" reads the variable "name" and
+    /// "doc['name'].value" reads the variable "doc.name"
+    Variable(String),
+
+    Negate(Box<Expression>),
+    Not(Box<Expression>),
+    Binary(BinaryOperator, Box<Expression>, Box<Expression>),
+}
+
+
+#[derive(Debug, PartialEq)]
+pub enum ScriptParseError {
+    UnexpectedCharacter(char),
+    UnexpectedEnd,
+    ExpectedString,
+    ExpectedKey(&'static str),
+    UnsupportedLanguage(String),
+}
+
+
+#[derive(Debug, PartialEq)]
+pub enum ScriptError {
+    UnknownVariable(String),
+
+    /// An operator was given a number where it needed a boolean, or the other way around
+    TypeMismatch,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Identifier(String),
+    Operator(&'static str),
+    OpenParen,
+    CloseParen,
+}
+
+
+struct Lexer<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+
+impl<'a> Lexer<'a> {
+    fn next_token(&mut self) -> Result<Option<Token>, ScriptParseError> {
+        while self.chars.peek().map_or(false, |c| c.is_whitespace()) {
+            self.chars.next();
+        }
+
+        let c = match self.chars.next() {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+
+        let token = match c {
+            '0'..='9' | '.' => {
+                let mut number = c.to_string();
+                while let Some(&c) = self.chars.peek() {
+                    if !(c.is_digit(10) || c == '.') {
+                        break;
+                    }
+
+                    number.push(c);
+                    self.chars.next();
+                }
+
+                Token::Number(number.parse().map_err(|_| ScriptParseError::UnexpectedCharacter(c))?)
+            }
+            'a'..='z' | 'A'..='Z' | '_' => {
+                // Identifiers can hold dots so "params.name" is read as one
+                let mut identifier = c.to_string();
+                while let Some(&c) = self.chars.peek() {
+                    if !(c.is_alphanumeric() || c == '_' || c == '.') {
+                        break;
+                    }
+
+                    identifier.push(c);
+                    self.chars.next();
+                }
+
+                if identifier == "doc" && self.chars.peek() == Some(&'[') {
+                    self.chars.next();
+                    return Ok(Some(Token::Identifier(format!("doc.{}", self.read_doc_field()?))));
+                }
+
+                Token::Identifier(identifier)
+            }
+            '(' => Token::OpenParen,
+            ')' => Token::CloseParen,
+            '+' => Token::Operator("+"),
+            '-' => Token::Operator("-"),
+            '*' => Token::Operator("*"),
+            '/' => Token::Operator("/"),
+            '%' => Token::Operator("%"),
+            '=' | '!' | '<' | '>' | '&' | '|' => {
+                let next = self.chars.peek().cloned();
+                let operator = match (c, next) {
+                    ('=', Some('=')) => "==",
+                    ('!', Some('=')) => "!=",
+                    ('<', Some('=')) => "<=",
+                    ('>', Some('=')) => ">=",
+                    ('&', Some('&')) => "&&",
+                    ('|', Some('|')) => "||",
+                    ('!', _) => return Ok(Some(Token::Operator("!"))),
+                    ('<', _) => return Ok(Some(Token::Operator("<"))),
+                    ('>', _) => return Ok(Some(Token::Operator(">"))),
+                    _ => return Err(ScriptParseError::UnexpectedCharacter(c)),
+                };
+
+                self.chars.next();
+                Token::Operator(operator)
+            }
+            c => return Err(ScriptParseError::UnexpectedCharacter(c)),
+        };
+
+        Ok(Some(token))
+    }
+
+    /// Reads the rest of "doc['name'].value" after the opening bracket, giving the name
+    fn read_doc_field(&mut self) -> Result<String, ScriptParseError> {
+        let quote = match self.chars.next() {
+            Some(c) if c == '\'' || c == '"' => c,
+            Some(c) => return Err(ScriptParseError::UnexpectedCharacter(c)),
+            None => return Err(ScriptParseError::UnexpectedEnd),
+        };
+
+        let mut name = String::new();
+        loop {
+            match self.chars.next() {
+                Some(c) if c == quote => break,
+                Some(c) => name.push(c),
+                None => return Err(ScriptParseError::UnexpectedEnd),
+            }
+        }
+
+        match self.chars.next() {
+            Some(']') => {}
+            Some(c) => return Err(ScriptParseError::UnexpectedCharacter(c)),
+            None => return Err(ScriptParseError::UnexpectedEnd),
+        }
+
+        // ".value" is optional, as there's nothing else that can be read from a field
+        if self.chars.peek() == Some(&'.') {
+            self.chars.next();
+
+            for expected in "value".chars() {
+                match self.chars.next() {
+                    Some(c) if c == expected => {}
+                    Some(c) => return Err(ScriptParseError::UnexpectedCharacter(c)),
+                    None => return Err(ScriptParseError::UnexpectedEnd),
+                }
+            }
+        }
+
+        Ok(name)
+    }
+}
+
+
+/// Each level of operator precedence, from the loosest to the tightest
+const BINARY_OPERATORS: &'static [&'static [(&'static str, BinaryOperator)]] = &[
+    &[("||", BinaryOperator::Or)],
+    &[("&&", BinaryOperator::And)],
+    &[
+        ("==", BinaryOperator::Equal),
+        ("!=", BinaryOperator::NotEqual),
+        ("<", BinaryOperator::Less),
+        ("<=", BinaryOperator::LessOrEqual),
+        (">", BinaryOperator::Greater),
+        (">=", BinaryOperator::GreaterOrEqual),
+    ],
+    &[("+", BinaryOperator::Add), ("-", BinaryOperator::Subtract)],
+    &[("*", BinaryOperator::Multiply), ("/", BinaryOperator::Divide), ("%", BinaryOperator::Remainder)],
+];
+
+
+fn unexpected_token(token: Token) -> ScriptParseError {
+    let c = match token {
+        Token::Number(number) => number.to_string().chars().next().unwrap(),
+        Token::Identifier(identifier) => identifier.chars().next().unwrap(),
+        Token::Operator(operator) => operator.chars().next().unwrap(),
+        Token::OpenParen => '(',
+        Token::CloseParen => ')',
+    };
+
+    ScriptParseError::UnexpectedCharacter(c)
+}
+
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn parse_binary(&mut self, level: usize) -> Result<Expression, ScriptParseError> {
+        if level == BINARY_OPERATORS.len() {
+            return self.parse_unary();
+        }
+
+        let mut expression = self.parse_binary(level + 1)?;
+
+        loop {
+            let operator = match self.peek() {
+                Some(&Token::Operator(symbol)) => {
+                    BINARY_OPERATORS[level].iter().find(|&&(candidate, _)| candidate == symbol).map(|&(_, ref operator)| operator.clone())
+                }
+                _ => None,
+            };
+
+            match operator {
+                Some(operator) => {
+                    self.next();
+                    let right = self.parse_binary(level + 1)?;
+                    expression = Expression::Binary(operator, Box::new(expression), Box::new(right));
+                }
+                None => return Ok(expression),
+            }
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expression, ScriptParseError> {
+        match self.next() {
+            Some(Token::Operator("-")) => Ok(Expression::Negate(Box::new(self.parse_unary()?))),
+            Some(Token::Operator("!")) => Ok(Expression::Not(Box::new(self.parse_unary()?))),
+            Some(Token::Number(number)) => Ok(Expression::Literal(ScriptValue::Number(number))),
+            Some(Token::Identifier(identifier)) => {
+                match identifier.as_ref() {
+                    "true" => Ok(Expression::Literal(ScriptValue::Boolean(true))),
+                    "false" => Ok(Expression::Literal(ScriptValue::Boolean(false))),
+                    _ => {
+                        let name = if identifier.starts_with("params.") {
+                            identifier["params.".len()..].to_string()
+                        } else {
+                            identifier
+                        };
+
+                        Ok(Expression::Variable(name))
+                    }
+                }
+            }
+            Some(Token::OpenParen) => {
+                let expression = self.parse_binary(0)?;
+
+                match self.next() {
+                    Some(Token::CloseParen) => Ok(expression),
+                    Some(token) => Err(unexpected_token(token)),
+                    None => Err(ScriptParseError::UnexpectedEnd),
+                }
+            }
+            Some(token) => Err(unexpected_token(token)),
+            None => Err(ScriptParseError::UnexpectedEnd),
+        }
+    }
+}
+
+
+impl Expression {
+    pub fn parse(source: &str) -> Result<Expression, ScriptParseError> {
+        // Painless statements can end with a semicolon
+        let source = source.trim().trim_end_matches(';');
+
+        let mut lexer = Lexer { chars: source.chars().peekable() };
+        let mut tokens = Vec::new();
+        while let Some(token) = lexer.next_token()? {
+            tokens.push(token);
+        }
+
+        let mut parser = Parser { tokens: tokens, position: 0 };
+        let expression = parser.parse_binary(0)?;
+
+        match parser.next() {
+            Some(token) => Err(unexpected_token(token)),
+            None => Ok(expression),
+        }
+    }
+
+    /// Runs the expression, reading variables with the given function
+    pub fn evaluate<F: Fn(&str) -> Option<ScriptValue>>(&self, variables: &F) -> Result<ScriptValue, ScriptError> {
+        match *self {
+            Expression::Literal(value) => Ok(value),
+            Expression::Variable(ref name) => variables(name).ok_or_else(|| ScriptError::UnknownVariable(name.clone())),
+            Expression::Negate(ref expression) => Ok(ScriptValue::Number(-expression.evaluate(variables)?.as_number()?)),
+            Expression::Not(ref expression) => Ok(ScriptValue::Boolean(!expression.evaluate(variables)?.as_boolean()?)),
+            Expression::Binary(ref operator, ref left, ref right) => {
+                let left = left.evaluate(variables)?;
+
+                // Skip the right side where it can't change the result
+                match (operator, left) {
+                    (&BinaryOperator::And, ScriptValue::Boolean(false)) => return Ok(left),
+                    (&BinaryOperator::Or, ScriptValue::Boolean(true)) => return Ok(left),
+                    _ => {}
+                }
+
+                let right = right.evaluate(variables)?;
+
+                Ok(match *operator {
+                    BinaryOperator::Add => ScriptValue::Number(left.as_number()? + right.as_number()?),
+                    BinaryOperator::Subtract => ScriptValue::Number(left.as_number()? - right.as_number()?),
+                    BinaryOperator::Multiply => ScriptValue::Number(left.as_number()? * right.as_number()?),
+                    BinaryOperator::Divide => ScriptValue::Number(left.as_number()? / right.as_number()?),
+                    BinaryOperator::Remainder => ScriptValue::Number(left.as_number()? % right.as_number()?),
+                    BinaryOperator::Equal => ScriptValue::Boolean(left == right),
+                    BinaryOperator::NotEqual => ScriptValue::Boolean(left != right),
+                    BinaryOperator::Less => ScriptValue::Boolean(left.as_number()? < right.as_number()?),
+                    BinaryOperator::LessOrEqual => ScriptValue::Boolean(left.as_number()? <= right.as_number()?),
+                    BinaryOperator::Greater => ScriptValue::Boolean(left.as_number()? > right.as_number()?),
+                    BinaryOperator::GreaterOrEqual => ScriptValue::Boolean(left.as_number()? >= right.as_number()?),
+                    BinaryOperator::And | BinaryOperator::Or => ScriptValue::Boolean(right.as_boolean()?),
+                })
+            }
+        }
+    }
+}
+
+
+impl ScriptValue {
+    pub fn as_number(&self) -> Result<f64, ScriptError> {
+        match *self {
+            ScriptValue::Number(number) => Ok(number),
+            ScriptValue::Boolean(_) => Err(ScriptError::TypeMismatch),
+        }
+    }
+
+    pub fn as_boolean(&self) -> Result<bool, ScriptError> {
+        match *self {
+            ScriptValue::Boolean(boolean) => Ok(boolean),
+            ScriptValue::Number(_) => Err(ScriptError::TypeMismatch),
+        }
+    }
+}
+
+
+/// Parses a script, given either as its source or as an object with a "source" key
+pub fn parse_script(json: &serde_json::Value) -> Result<Expression, ScriptParseError> {
+    match *json {
+        serde_json::Value::String(ref source) => Expression::parse(source),
+        serde_json::Value::Object(ref object) => {
+            if let Some(lang) = object.get("lang") {
+                match lang.as_str() {
+                    Some("expression") | Some("painless") => {}
+                    Some(lang) => return Err(ScriptParseError::UnsupportedLanguage(lang.to_string())),
+                    None => return Err(ScriptParseError::ExpectedString),
+                }
+            }
+
+            let source = object.get("source").or_else(|| object.get("inline")).ok_or(ScriptParseError::ExpectedKey("source"))?;
+            Expression::parse(source.as_str().ok_or(ScriptParseError::ExpectedString)?)
+        }
+        _ => Err(ScriptParseError::ExpectedString),
+    }
+}
+
+
+/// Reads the "params" of a script object. Only numbers and booleans can be used by
+/// expressions so other values are left out
+pub fn parse_script_params(json: &serde_json::Value) -> HashMap<String, ScriptValue> {
+    let mut params = HashMap::new();
+
+    if let Some(object) = json.get("params").and_then(|params| params.as_object()) {
+        for (name, value) in object.iter() {
+            match *value {
+                serde_json::Value::Number(ref number) => {
+                    if let Some(number) = number.as_f64() {
+                        params.insert(name.clone(), ScriptValue::Number(number));
+                    }
+                }
+                serde_json::Value::Bool(boolean) => {
+                    params.insert(name.clone(), ScriptValue::Boolean(boolean));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    params
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{Expression, BinaryOperator, ScriptValue, ScriptError, ScriptParseError, parse_script_params};
+
+    fn evaluate(source: &str) -> Result<ScriptValue, ScriptError> {
+        let variables = |name: &str| {
+            match name {
+                "sales" => Some(ScriptValue::Number(120.0)),
+                "count" => Some(ScriptValue::Number(4.0)),
+                _ => None,
+            }
+        };
+
+        Expression::parse(source).unwrap().evaluate(&variables)
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(evaluate("1 + 2 * 3"), Ok(ScriptValue::Number(7.0)));
+        assert_eq!(evaluate("(1 + 2) * 3"), Ok(ScriptValue::Number(9.0)));
+        assert_eq!(evaluate("10 - 4 - 3"), Ok(ScriptValue::Number(3.0)));
+        assert_eq!(evaluate("-params.count % 3"), Ok(ScriptValue::Number(-1.0)));
+        assert_eq!(evaluate("params.sales / params.count"), Ok(ScriptValue::Number(30.0)));
+    }
+
+    #[test]
+    fn test_comparisons() {
+        assert_eq!(evaluate("params.sales > 100"), Ok(ScriptValue::Boolean(true)));
+        assert_eq!(evaluate("params.sales / count <= 20 || count == 4"), Ok(ScriptValue::Boolean(true)));
+        assert_eq!(evaluate("!(sales >= 120) && true"), Ok(ScriptValue::Boolean(false)));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(evaluate("params.missing + 1"), Err(ScriptError::UnknownVariable("missing".to_string())));
+        assert_eq!(evaluate("true + 1"), Err(ScriptError::TypeMismatch));
+
+        assert_eq!(Expression::parse("1 +"), Err(ScriptParseError::UnexpectedEnd));
+        assert_eq!(Expression::parse("(1 + 2"), Err(ScriptParseError::UnexpectedEnd));
+        assert_eq!(Expression::parse("1 # 2"), Err(ScriptParseError::UnexpectedCharacter('#')));
+        assert_eq!(Expression::parse("1 2"), Err(ScriptParseError::UnexpectedCharacter('2')));
+    }
+
+    #[test]
+    fn test_doc_values() {
+        assert_eq!(Expression::parse("doc['price'].value * 2"), Ok(Expression::Binary(
+            BinaryOperator::Multiply,
+            Box::new(Expression::Variable("doc.price".to_string())),
+            Box::new(Expression::Literal(ScriptValue::Number(2.0))),
+        )));
+        assert_eq!(Expression::parse("doc[\"price\"]"), Ok(Expression::Variable("doc.price".to_string())));
+
+        assert_eq!(Expression::parse("doc['price'"), Err(ScriptParseError::UnexpectedEnd));
+        assert_eq!(Expression::parse("doc['price'].size()"), Err(ScriptParseError::UnexpectedCharacter('s')));
+    }
+
+    #[test]
+    fn test_params() {
+        let params = parse_script_params(&json!({
+            "source": "params.factor * 2",
+            "params": {"factor": 1.5, "enabled": true, "name": "ignored"}
+        }));
+
+        assert_eq!(params.len(), 2);
+        assert_eq!(params.get("factor"), Some(&ScriptValue::Number(1.5)));
+        assert_eq!(params.get("enabled"), Some(&ScriptValue::Boolean(true)));
+    }
+}
@@ -51,6 +51,7 @@ fn bench_insert_single_document(b: &mut Bencher) {
             key: i.to_string(),
             indexed_fields: indexed_fields,
             stored_fields: stored_fields,
+            nested_documents: Vec::new(),
         });
     });
 }
@@ -85,6 +86,7 @@ fn bench_insert_documents_parallel(b: &mut Bencher) {
             key: (i + 1).to_string(),
             indexed_fields: indexed_fields,
             stored_fields: stored_fields,
+            nested_documents: Vec::new(),
         });
     }
 
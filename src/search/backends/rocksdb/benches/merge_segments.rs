@@ -47,6 +47,7 @@ fn bench_merge_segments(b: &mut Bencher) {
             key: i.to_string(),
             indexed_fields: indexed_fields,
             stored_fields: stored_fields,
+            nested_documents: Vec::new(),
         });
     }
 
@@ -0,0 +1,245 @@
+use std::str;
+use std::sync::Mutex;
+
+use rocksdb::{self, DB, WriteBatch};
+use byteorder::{ByteOrder, LittleEndian};
+use chrono::{NaiveDateTime, DateTime, Utc};
+
+use super::{RocksDBStore, RocksDBReader};
+use super::key_builder::KeyBuilder;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChangeOperation {
+    Index,
+    Delete,
+}
+
+impl ChangeOperation {
+    fn to_byte(&self) -> u8 {
+        match *self {
+            ChangeOperation::Index => b'i',
+            ChangeOperation::Delete => b'd',
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<ChangeOperation> {
+        match byte {
+            b'i' => Some(ChangeOperation::Index),
+            b'd' => Some(ChangeOperation::Delete),
+            _ => None,
+        }
+    }
+}
+
+/// A write that was made to the index
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub seq_no: u64,
+    pub operation: ChangeOperation,
+    pub key: String,
+    pub time: DateTime<Utc>,
+}
+
+fn encode_time(time: DateTime<Utc>) -> i64 {
+    time.timestamp() * 1000000 + time.timestamp_subsec_micros() as i64
+}
+
+fn decode_time(timestamp_with_micros: i64) -> DateTime<Utc> {
+    let timestamp = timestamp_with_micros / 1000000;
+    let micros = timestamp_with_micros % 1000000;
+    let datetime = NaiveDateTime::from_timestamp(timestamp, (micros * 1000) as u32);
+    DateTime::from_utc(datetime, Utc)
+}
+
+/// Decodes a change log entry. Entries are the operation, followed by the time and the document key
+fn decode_entry(seq_no: u64, entry: &[u8]) -> Change {
+    Change {
+        seq_no: seq_no,
+        operation: ChangeOperation::from_byte(entry[0]).expect("unrecognised change log operation"),
+        key: String::from_utf8_lossy(&entry[9..]).into_owned(),
+        time: decode_time(LittleEndian::read_i64(&entry[1..9])),
+    }
+}
+
+/// Manages the index's "change log"
+///
+/// Every write to the index is assigned a sequence number. These are allocated in
+/// increasing order and an entry is written into the change log for each one so
+/// that other systems can find out what has changed since a given sequence number.
+/// The sequence number of the last write to each document is also recorded.
+///
+/// Entries for deleted and replaced documents are kept until they are older than the
+/// index's retention period so the history can be replayed by consumers that fall behind.
+pub struct ChangeLogManager {
+    next_seq_no: Mutex<u64>,
+}
+
+impl ChangeLogManager {
+    /// Generates a new change log
+    pub fn new(db: &DB) -> Result<ChangeLogManager, rocksdb::Error> {
+        db.put(b".next_seq_no", b"1")?;
+
+        Ok(ChangeLogManager {
+            next_seq_no: Mutex::new(1),
+        })
+    }
+
+    /// Loads the change log from an index
+    pub fn open(db: &DB) -> Result<ChangeLogManager, rocksdb::Error> {
+        let next_seq_no = match db.get(b".next_seq_no")? {
+            Some(next_seq_no) => {
+                next_seq_no.to_utf8().unwrap().parse::<u64>().unwrap()
+            }
+            None => 1,
+        };
+
+        Ok(ChangeLogManager {
+            next_seq_no: Mutex::new(next_seq_no),
+        })
+    }
+
+    /// Records a write to a document and returns its sequence number
+    ///
+    /// The entry is added to the write batch that makes the change, so the change
+    /// and its entry are written together
+    pub fn record(&self, db: &DB, mut write_batch: WriteBatch, operation: ChangeOperation, doc_key: &[u8]) -> Result<u64, rocksdb::Error> {
+        // The lock is held until the write completes so entries always become visible in order.
+        // The time is taken under the lock as well, so entries are in time order too
+        let mut next_seq_no = self.next_seq_no.lock().unwrap();
+        let seq_no = *next_seq_no;
+        let time = Utc::now();
+
+        write_batch.put(b".next_seq_no", (seq_no + 1).to_string().as_bytes())?;
+
+        // Write change log entry
+        let kb = KeyBuilder::change_log_entry(seq_no);
+        let mut entry = vec![0; 9 + doc_key.len()];
+        entry[0] = operation.to_byte();
+        LittleEndian::write_i64(&mut entry[1..9], encode_time(time));
+        entry[9..].copy_from_slice(doc_key);
+        write_batch.put(&kb.key(), &entry)?;
+
+        // Update the document's sequence number
+        let kb = KeyBuilder::document_seq_no(doc_key);
+        match operation {
+            ChangeOperation::Index => {
+                let mut seq_no_bytes = [0; 8];
+                LittleEndian::write_u64(&mut seq_no_bytes, seq_no);
+                write_batch.put(&kb.key(), &seq_no_bytes)?;
+            }
+            ChangeOperation::Delete => {
+                write_batch.delete(&kb.key())?;
+            }
+        }
+
+        db.write(write_batch)?;
+
+        *next_seq_no += 1;
+        Ok(seq_no)
+    }
+
+    /// The sequence number of the most recent write, zero if nothing has been written yet
+    pub fn max_seq_no(&self) -> u64 {
+        *self.next_seq_no.lock().unwrap() - 1
+    }
+
+    /// Removes entries that were recorded before the given time
+    ///
+    /// Returns the number of entries that were removed
+    pub fn trim(&self, db: &DB, older_than: DateTime<Utc>) -> Result<u64, rocksdb::Error> {
+        let mut write_batch = WriteBatch::default();
+        let mut trimmed = 0;
+
+        // Entries are recorded in time order so we can stop at the first one that is new enough
+        let mut iter = db.raw_iterator();
+        iter.seek(b"c");
+        while iter.valid() {
+            let k = iter.key().unwrap();
+
+            if k[0] != b'c' {
+                break;
+            }
+
+            let v = iter.value().unwrap();
+            if decode_time(LittleEndian::read_i64(&v[1..9])) >= older_than {
+                break;
+            }
+
+            write_batch.delete(&k)?;
+            trimmed += 1;
+
+            iter.next();
+        }
+
+        if trimmed > 0 {
+            db.write(write_batch)?;
+        }
+
+        Ok(trimmed)
+    }
+}
+
+impl RocksDBStore {
+    /// Removes change log entries that were recorded before the given time
+    pub fn trim_change_log(&self, older_than: DateTime<Utc>) -> Result<u64, rocksdb::Error> {
+        self.change_log.trim(&self.db, older_than)
+    }
+}
+
+impl<'a> RocksDBReader<'a> {
+    /// Returns the sequence number of the last write to a document
+    pub fn get_document_seq_no(&self, doc_key: &str) -> Result<Option<u64>, rocksdb::Error> {
+        let kb = KeyBuilder::document_seq_no(doc_key.as_bytes());
+        match self.snapshot.get(&kb.key())? {
+            Some(value) => Ok(Some(LittleEndian::read_u64(&value))),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns up to `limit` changes that were made after the given sequence number, oldest first
+    pub fn changes_since(&self, seq_no: u64, limit: usize) -> Result<Vec<Change>, rocksdb::Error> {
+        let mut changes = Vec::new();
+        let mut iter = self.snapshot.raw_iterator();
+        iter.seek(KeyBuilder::change_log_entry(seq_no + 1).key());
+
+        while iter.valid() && changes.len() < limit {
+            let k = iter.key().unwrap();
+
+            if k[0] != b'c' {
+                break;
+            }
+
+            let v = iter.value().unwrap();
+            let change_seq_no = str::from_utf8(&k[1..]).unwrap().parse::<u64>().unwrap();
+            changes.push(decode_entry(change_seq_no, &v));
+
+            iter.next();
+        }
+
+        Ok(changes)
+    }
+
+    /// The sequence number of the most recent write, zero if nothing has been written yet
+    pub fn max_seq_no(&self) -> u64 {
+        self.store.change_log.max_seq_no()
+    }
+
+    /// The sequence number of the oldest change that is still in the change log
+    ///
+    /// Changes before this have been trimmed so can't be replayed. If the change log is
+    /// empty, this is the sequence number that will be assigned to the next write.
+    pub fn min_retained_seq_no(&self) -> u64 {
+        let mut iter = self.snapshot.raw_iterator();
+        iter.seek(b"c");
+
+        if iter.valid() {
+            let k = iter.key().unwrap();
+
+            if k[0] == b'c' {
+                return str::from_utf8(&k[1..]).unwrap().parse::<u64>().unwrap();
+            }
+        }
+
+        self.max_seq_no() + 1
+    }
+}
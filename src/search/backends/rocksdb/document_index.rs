@@ -68,9 +68,11 @@ impl DocumentIndexManager {
         Ok(())
     }
 
-    pub fn insert_or_replace_key(&self, db: &DB, key: &Vec<u8>, doc_id: DocId) -> Result<Option<DocId>, rocksdb::Error> {
+    /// Points the key at a new document, deleting the document it pointed to before
+    ///
+    /// The changes are added to the write batch, which the caller must write
+    pub fn insert_or_replace_key(&self, write_batch: &mut WriteBatch, key: &Vec<u8>, doc_id: DocId) -> Result<Option<DocId>, rocksdb::Error> {
         // Update primary_key_index
-        let mut write_batch = WriteBatch::default();
         let previous_doc_id = self.primary_key_index.write().unwrap().insert(key.clone(), doc_id);
 
         let kb = KeyBuilder::primary_key_index(key);
@@ -81,25 +83,21 @@ impl DocumentIndexManager {
 
         // If there was a document there previously, delete it
         if let Some(previous_doc_id) = previous_doc_id {
-            try!(self.delete_document_by_id_unchecked(&mut write_batch, previous_doc_id));
+            try!(self.delete_document_by_id_unchecked(write_batch, previous_doc_id));
         }
 
-        // Write document data
-        try!(db.write(write_batch));
-
         Ok(previous_doc_id)
     }
 
-    pub fn delete_document_by_key(&self, db: &DB, key: &Vec<u8>) -> Result<Option<DocId>, rocksdb::Error> {
+    /// Deletes the document that the key points to
+    ///
+    /// The changes are added to the write batch, which the caller must write
+    pub fn delete_document_by_key(&self, write_batch: &mut WriteBatch, key: &Vec<u8>) -> Result<Option<DocId>, rocksdb::Error> {
         // Remove document from index
         let doc_id = self.primary_key_index.write().unwrap().remove(key);
 
         if let Some(doc_id) = doc_id {
-            let mut write_batch = WriteBatch::default();
-
-            try!(self.delete_document_by_id_unchecked(&mut write_batch, doc_id));
-
-            try!(db.write(write_batch));
+            try!(self.delete_document_by_id_unchecked(write_batch, doc_id));
         }
 
         Ok(doc_id)
@@ -109,6 +107,17 @@ impl DocumentIndexManager {
         self.primary_key_index.read().unwrap().contains_key(key)
     }
 
+    pub fn get_document_id_by_key(&self, key: &Vec<u8>) -> Option<DocId> {
+        self.primary_key_index.read().unwrap().get(key).cloned()
+    }
+
+    /// Returns every key with the document it points to
+    pub fn keys_by_document_id(&self) -> Vec<(DocId, Vec<u8>)> {
+        self.primary_key_index.read().unwrap().iter()
+            .map(|(key, doc_id)| (*doc_id, key.clone()))
+            .collect()
+    }
+
     pub fn commit_segment_merge(&self, db: &DB, mut write_batch: WriteBatch, source_segments: &Vec<u32>, dest_segment: u32, doc_id_mapping: &FnvHashMap<DocId, u16>) -> Result<(), SegmentMergeError> {
         // Lock the primary key index
         let mut primary_key_index = self.primary_key_index.write().unwrap();
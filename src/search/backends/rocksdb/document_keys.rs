@@ -0,0 +1,35 @@
+//! Backfilling the keys of documents in segments
+//!
+//! Segments used to only record which document a key points to, in the primary
+//! key index, and not the key of each document. Stores created before then are
+//! given the keys of their live documents from the primary key index when
+//! they're opened. Documents that were deleted or inserted append-only have no
+//! key in the index, so they stay without one.
+
+use rocksdb::WriteBatch;
+
+use super::{RocksDBStore, DOCUMENT_KEYS_MARKER};
+use super::key_builder::KeyBuilder;
+
+
+impl RocksDBStore {
+    /// Writes the keys of the documents in the primary key index into their segments
+    ///
+    /// Documents that already have a key are left alone. The keys and the marker
+    /// are written in a single batch, so an interrupted backfill starts again
+    pub fn backfill_document_keys(&self) -> Result<(), String> {
+        let mut write_batch = WriteBatch::default();
+
+        for (doc_id, key) in self.document_index.keys_by_document_id() {
+            let kb = KeyBuilder::segment_document_key((doc_id.0).0, doc_id.1);
+            if self.db.get(&kb.key())?.is_none() {
+                write_batch.put(&kb.key(), &self.seal_stored_value(&kb.key(), &key))?;
+            }
+        }
+
+        write_batch.put(DOCUMENT_KEYS_MARKER, b"")?;
+        self.db.write(write_batch)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,171 @@
+//! Encryption of stored values at rest
+//!
+//! Stored field values (which include the document source), the per-document
+//! frequencies, positions and lengths, and the document keys of each segment are
+//! sealed with AES-256-GCM before they're written. Each value gets its own random
+//! nonce, which is kept in front of the ciphertext, and is bound to the RocksDB key
+//! it's stored under, so a value can't be moved to another key without detection.
+//! Segment merges reseal the values they move.
+//!
+//! Everything else is written in plaintext. This includes the term dictionary
+//! (every indexed term), the postings lists, the document index and the change
+//! log (which are both keyed by document key), so this doesn't protect the
+//! contents of indexed fields.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+
+
+/// Somewhere to get the data key from
+///
+/// Key management services can be hooked in by implementing this.
+pub trait KeyProvider: Send + Sync {
+    /// Returns the 256 bit data key
+    fn load_key(&self) -> Result<Vec<u8>, String>;
+}
+
+
+/// Reads the data key from a file that contains the 32 raw bytes of the key
+#[derive(Debug, Clone)]
+pub struct KeyFile {
+    path: PathBuf,
+}
+
+
+impl KeyFile {
+    pub fn new<P: AsRef<Path>>(path: P) -> KeyFile {
+        KeyFile {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+}
+
+
+impl KeyProvider for KeyFile {
+    fn load_key(&self) -> Result<Vec<u8>, String> {
+        let mut file = File::open(&self.path).map_err(|e| format!("unable to open key file {:?}: {}", self.path, e))?;
+        let mut key = Vec::new();
+        file.read_to_end(&mut key).map_err(|e| format!("unable to read key file {:?}: {}", self.path, e))?;
+        Ok(key)
+    }
+}
+
+
+pub struct StoreEncryption {
+    key: LessSafeKey,
+    rng: SystemRandom,
+}
+
+
+impl StoreEncryption {
+    pub fn new(key: &[u8]) -> Result<StoreEncryption, String> {
+        if key.len() != AES_256_GCM.key_len() {
+            return Err(format!("encryption key must be {} bytes, got {}", AES_256_GCM.key_len(), key.len()));
+        }
+
+        Ok(StoreEncryption {
+            key: LessSafeKey::new(UnboundKey::new(&AES_256_GCM, key).map_err(|_| "invalid encryption key".to_string())?),
+            rng: SystemRandom::new(),
+        })
+    }
+
+    pub fn from_provider(provider: &dyn KeyProvider) -> Result<StoreEncryption, String> {
+        StoreEncryption::new(&provider.load_key()?)
+    }
+
+    /// Encrypts a value. The nonce is returned in front of the ciphertext and tag
+    ///
+    /// The value can only be opened with the same `aad`
+    pub fn seal(&self, value: &[u8], aad: &[u8]) -> Vec<u8> {
+        let mut nonce = [0; NONCE_LEN];
+        self.rng.fill(&mut nonce).expect("unable to generate nonce");
+
+        let mut sealed = Vec::with_capacity(NONCE_LEN + value.len() + AES_256_GCM.tag_len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(value);
+
+        let tag = self.key.seal_in_place_separate_tag(Nonce::assume_unique_for_key(nonce), Aad::from(aad), &mut sealed[NONCE_LEN..])
+            .expect("unable to encrypt value");
+        sealed.extend_from_slice(tag.as_ref());
+
+        sealed
+    }
+
+    /// Decrypts a value that was encrypted with `seal`
+    ///
+    /// This fails if the value was encrypted with a different key or `aad`, or has
+    /// been changed
+    pub fn open(&self, sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>, String> {
+        if sealed.len() < NONCE_LEN + AES_256_GCM.tag_len() {
+            return Err("encrypted value is too short".to_string());
+        }
+
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| "invalid nonce".to_string())?;
+        let mut in_out = ciphertext.to_vec();
+        let value_len = self.key.open_in_place(nonce, Aad::from(aad), &mut in_out)
+            .map_err(|_| "unable to decrypt value".to_string())?
+            .len();
+
+        in_out.truncate(value_len);
+        Ok(in_out)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::StoreEncryption;
+
+    #[test]
+    fn test_seal_and_open() {
+        let encryption = StoreEncryption::new(&[7; 32]).unwrap();
+
+        let sealed = encryption.seal(b"hello world", b"v1/1/1/val");
+        assert!(!sealed.windows(11).any(|window| window == b"hello world"));
+        assert_eq!(encryption.open(&sealed, b"v1/1/1/val"), Ok(b"hello world".to_vec()));
+    }
+
+    #[test]
+    fn test_nonce_is_random() {
+        let encryption = StoreEncryption::new(&[7; 32]).unwrap();
+
+        assert!(encryption.seal(b"hello", b"v1/1/1/val") != encryption.seal(b"hello", b"v1/1/1/val"));
+    }
+
+    #[test]
+    fn test_open_with_wrong_key() {
+        let encryption = StoreEncryption::new(&[7; 32]).unwrap();
+        let other_encryption = StoreEncryption::new(&[8; 32]).unwrap();
+
+        assert!(other_encryption.open(&encryption.seal(b"hello", b"v1/1/1/val"), b"v1/1/1/val").is_err());
+    }
+
+    #[test]
+    fn test_open_tampered_value() {
+        let encryption = StoreEncryption::new(&[7; 32]).unwrap();
+
+        let mut sealed = encryption.seal(b"hello", b"v1/1/1/val");
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        assert!(encryption.open(&sealed, b"v1/1/1/val").is_err());
+    }
+
+    #[test]
+    fn test_open_with_wrong_aad() {
+        let encryption = StoreEncryption::new(&[7; 32]).unwrap();
+
+        // A value copied to another key can't be opened there
+        let sealed = encryption.seal(b"hello", b"v1/1/1/val");
+        assert!(encryption.open(&sealed, b"v1/2/1/val").is_err());
+    }
+
+    #[test]
+    fn test_invalid_key_length() {
+        assert!(StoreEncryption::new(&[7; 16]).is_err());
+    }
+}
@@ -36,6 +36,21 @@ impl KeyBuilder {
         kb
     }
 
+    /// The external key of a document, for resolving hits back to their keys
+    pub fn segment_document_key(segment: u32, doc_local_id: u16) -> KeyBuilder {
+        let mut kb = KeyBuilder::segment_document_keys_prefix(segment);
+        kb.push_string(doc_local_id.to_string().as_bytes());
+        kb
+    }
+
+    pub fn segment_document_keys_prefix(segment: u32) -> KeyBuilder {
+        let mut kb = KeyBuilder::new();
+        kb.push_char(b'i');
+        kb.push_string(segment.to_string().as_bytes());
+        kb.separator();
+        kb
+    }
+
     pub fn primary_key_index(key: &[u8]) -> KeyBuilder {
         let mut kb = KeyBuilder::with_capacity(1 + key.len());
         kb.push_char(b'k');
@@ -140,6 +155,22 @@ impl KeyBuilder {
         kb
     }
 
+    pub fn change_log_entry(seq_no: u64) -> KeyBuilder {
+        let mut kb = KeyBuilder::new();
+        kb.push_char(b'c');
+
+        // Zero-padded so the entries are sorted by sequence number
+        kb.push_string(format!("{:020}", seq_no).as_bytes());
+        kb
+    }
+
+    pub fn document_seq_no(key: &[u8]) -> KeyBuilder {
+        let mut kb = KeyBuilder::with_capacity(1 + key.len());
+        kb.push_char(b'q');
+        kb.push_string(key);
+        kb
+    }
+
     #[inline]
     pub fn key(&self) -> &[u8] {
         &self.key[..]
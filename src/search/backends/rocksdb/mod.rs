@@ -7,17 +7,22 @@ mod segment_builder;
 mod term_dictionary;
 mod document_index;
 mod search;
+mod term_format;
+mod document_keys;
+pub mod change_log;
+pub mod encryption;
 
 use std::str;
 use std::fmt;
 use std::path::Path;
 use std::sync::Arc;
 
-use rocksdb::{self, DB, WriteBatch, Options, MergeOperands, Snapshot};
+use rocksdb::{self, DB, WriteBatch, WriteOptions, Options, MergeOperands, Snapshot};
 use search::{Document, DocId, TermId};
 use search::document::FieldValue;
 use search::schema::{Schema, FieldType, FieldFlags, FieldId, AddFieldError};
 use search::segment::SegmentId;
+use search::query::wildcard::VALUE_SEPARATOR;
 use byteorder::{ByteOrder, LittleEndian};
 use chrono::{NaiveDateTime, DateTime, Utc};
 use fnv::FnvHashMap;
@@ -27,6 +32,22 @@ use self::key_builder::KeyBuilder;
 use self::segment_manager::SegmentManager;
 use self::term_dictionary::TermDictionaryManager;
 use self::document_index::DocumentIndexManager;
+use self::change_log::{ChangeLogManager, ChangeOperation};
+use self::encryption::StoreEncryption;
+
+/// Sealed and saved when an encrypted store is created, so the key can be checked when it's opened
+const ENCRYPTION_CHECK_VALUE: &'static [u8] = b"rusticsearch";
+
+/// The way terms are encoded in the term dictionary, saved when a store is created
+///
+/// Version 2 changed integers and dates to an order-preserving encoding. Stores
+/// without a version were created before that, in version 1, and are migrated
+/// when they're opened.
+const TERM_FORMAT_VERSION: u32 = 2;
+
+/// Saved when a store is created, or once the keys of its documents have been
+/// backfilled, so segments can be relied on to have keys for their documents
+const DOCUMENT_KEYS_MARKER: &'static [u8] = b".document_keys";
 
 fn merge_keys(key: &[u8], existing_val: Option<&[u8]>, operands: &mut MergeOperands) -> Option<Vec<u8>> {
     match key[0] {
@@ -105,29 +126,66 @@ impl From<segment_builder::DocumentInsertError> for DocumentInsertError {
     }
 }
 
+/// The order of the documents in merged segments
+///
+/// Segments that are written for new documents only hold one document (and the
+/// documents nested in it), so with this every segment is in order. Searches
+/// sorted the same way only need the first matches of each segment.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SegmentSort {
+    pub field: FieldId,
+
+    /// Sorts by the highest value of each document, highest first. Otherwise
+    /// documents are sorted by their lowest value, lowest first
+    pub descending: bool,
+}
+
 pub struct RocksDBStore {
     schema: Arc<Schema>,
     db: DB,
     term_dictionary: TermDictionaryManager,
     segments: SegmentManager,
     document_index: DocumentIndexManager,
+    change_log: ChangeLogManager,
+    encryption: Option<Arc<StoreEncryption>>,
 }
 
 impl RocksDBStore {
     pub fn create<P: AsRef<Path>>(path: P) -> Result<RocksDBStore, String> {
+        RocksDBStore::create_with_schema(path, Schema::new())
+    }
+
+    /// Creates a store that starts with the fields of another schema. Documents
+    /// prepared for a store with that schema can be inserted into this one
+    pub fn create_with_schema<P: AsRef<Path>>(path: P, schema: Schema) -> Result<RocksDBStore, String> {
+        RocksDBStore::create_with_encryption(path, schema, None)
+    }
+
+    /// Creates a store that encrypts its stored values with the given key, if there is one
+    pub fn create_with_encryption<P: AsRef<Path>>(path: P, schema: Schema, encryption: Option<Arc<StoreEncryption>>) -> Result<RocksDBStore, String> {
         let mut opts = Options::default();
         opts.set_merge_operator("merge operator", merge_keys, None);
         opts.create_if_missing(true);
         let db = try!(DB::open(&opts, path));
 
         // Schema
-        let schema = Schema::new();
         let schema_encoded = match serde_json::to_string(&schema) {
             Ok(schema_encoded) => schema_encoded,
             Err(e) => return Err(format!("schema encode error: {:?}", e).into()),
         };
         try!(db.put(b".schema", schema_encoded.as_bytes()));
 
+        // Term format
+        try!(db.put(b".term_format", TERM_FORMAT_VERSION.to_string().as_bytes()));
+
+        // Document keys
+        try!(db.put(DOCUMENT_KEYS_MARKER, b""));
+
+        // Encryption check
+        if let Some(ref encryption) = encryption {
+            try!(db.put(b".encryption", &encryption.seal(ENCRYPTION_CHECK_VALUE, b".encryption")));
+        }
+
         // Segment manager
         let segments = try!(SegmentManager::new(&db));
 
@@ -137,16 +195,29 @@ impl RocksDBStore {
         // Document index
         let document_index = try!(DocumentIndexManager::new(&db));
 
+        // Change log
+        let change_log = ChangeLogManager::new(&db)?;
+
         Ok(RocksDBStore {
             schema: Arc::new(schema),
             db: db,
             term_dictionary: term_dictionary,
             segments: segments,
             document_index: document_index,
+            change_log: change_log,
+            encryption: encryption,
         })
     }
 
     pub fn open<P: AsRef<Path>>(path: P) -> Result<RocksDBStore, String> {
+        RocksDBStore::open_with_encryption(path, None)
+    }
+
+    /// Opens a store that may be encrypted
+    ///
+    /// The key is only used if the store was created with encryption. Stores that were
+    /// created without it stay unencrypted.
+    pub fn open_with_encryption<P: AsRef<Path>>(path: P, encryption: Option<Arc<StoreEncryption>>) -> Result<RocksDBStore, String> {
         let mut opts = Options::default();
         opts.set_merge_operator("merge operator", merge_keys, None);
         let db = try!(DB::open(&opts, path));
@@ -162,6 +233,29 @@ impl RocksDBStore {
             None => return Err("unable to find schema in store".into()),
         };
 
+        // Term format
+        let term_format = match try!(db.get(b".term_format")) {
+            Some(term_format) => term_format.to_utf8().and_then(|term_format| term_format.parse::<u32>().ok()),
+            None => Some(1),
+        };
+
+        if term_format != Some(1) && term_format != Some(TERM_FORMAT_VERSION) {
+            return Err(format!("store uses term format {:?} but only 1 and {} are supported", term_format, TERM_FORMAT_VERSION));
+        }
+
+        // Encryption check
+        let encryption = match (try!(db.get(b".encryption")), encryption) {
+            (Some(check_value), Some(encryption)) => {
+                if encryption.open(&check_value, b".encryption").ok().as_ref().map(|value| &value[..]) != Some(ENCRYPTION_CHECK_VALUE) {
+                    return Err("store is encrypted with a different key".into());
+                }
+
+                Some(encryption)
+            }
+            (Some(_), None) => return Err("store is encrypted but no key was given".into()),
+            (None, _) => None,
+        };
+
         // Segment manager
         let segments = try!(SegmentManager::open(&db));
 
@@ -171,19 +265,76 @@ impl RocksDBStore {
         // Document index
         let document_index = try!(DocumentIndexManager::open(&db));
 
-        Ok(RocksDBStore {
+        // Change log
+        let change_log = ChangeLogManager::open(&db)?;
+
+        let store = RocksDBStore {
             schema: Arc::new(schema),
             db: db,
             term_dictionary: term_dictionary,
             segments: segments,
             document_index: document_index,
-        })
+            change_log: change_log,
+            encryption: encryption,
+        };
+
+        if term_format == Some(1) {
+            store.migrate_integer_terms()?;
+        }
+
+        if try!(store.db.get(DOCUMENT_KEYS_MARKER)).is_none() {
+            store.backfill_document_keys()?;
+        }
+
+        Ok(store)
     }
 
     pub fn path(&self) -> &Path {
         self.db.path()
     }
 
+    pub fn is_encrypted(&self) -> bool {
+        self.encryption.is_some()
+    }
+
+    /// Encrypts a stored value before it's written under `key`, if the store is encrypted
+    fn seal_stored_value(&self, key: &[u8], value: &[u8]) -> Vec<u8> {
+        match self.encryption {
+            Some(ref encryption) => encryption.seal(value, key),
+            None => value.to_vec(),
+        }
+    }
+
+    /// Decrypts a stored value that was read from `key`, if the store is encrypted
+    fn open_stored_value(&self, key: &[u8], value: &[u8]) -> Result<Vec<u8>, String> {
+        match self.encryption {
+            Some(ref encryption) => encryption.open(value, key),
+            None => Ok(value.to_vec()),
+        }
+    }
+
+    /// Moves a stored value from one key to another
+    ///
+    /// Sealed values are bound to their keys, so they're decrypted and sealed again
+    fn reseal_stored_value(&self, old_key: &[u8], new_key: &[u8], value: &[u8]) -> Result<Vec<u8>, String> {
+        match self.encryption {
+            Some(ref encryption) => Ok(encryption.seal(&encryption.open(value, old_key)?, new_key)),
+            None => Ok(value.to_vec()),
+        }
+    }
+
+    /// Syncs all writes made so far to disk
+    ///
+    /// Writes are normally only passed to the OS, which can lose them if the machine
+    /// crashes. RocksDB syncs its write-ahead log up to and including any write that
+    /// asks for it, so this makes a small synced write.
+    pub fn sync(&self) -> Result<(), rocksdb::Error> {
+        let mut write_options = WriteOptions::default();
+        write_options.set_sync(true);
+
+        self.db.put_opt(b".last_sync", Utc::now().to_rfc3339().as_bytes(), &write_options)
+    }
+
     pub fn add_field(&mut self, name: String, field_type: FieldType, field_flags: FieldFlags) -> Result<FieldId, AddFieldError> {
         let mut schema_copy = (*self.schema).clone();
         let field_id = try!(schema_copy.add_field(name, field_type, field_flags));
@@ -209,20 +360,41 @@ impl RocksDBStore {
         field_removed
     }
 
-    pub fn insert_or_update_document(&self, doc: &Document) -> Result<(), DocumentInsertError> {
+    /// Inserts a document, replacing any existing document with the same key
+    ///
+    /// Returns the sequence number that was assigned to the write
+    pub fn insert_or_update_document(&self, doc: &Document) -> Result<u64, DocumentInsertError> {
         // Build segment in memory
         let mut builder = segment_builder::SegmentBuilder::new();
         let doc_key = doc.key.clone();
-        try!(builder.add_document(doc));
+        let doc_local_id = try!(builder.add_document(doc));
 
         // Write the segment
         let segment = try!(self.write_segment(&builder));
 
         // Update document index
-        let doc_id = DocId(SegmentId(segment), 0);
-        try!(self.document_index.insert_or_replace_key(&self.db, &doc_key.as_bytes().iter().cloned().collect(), doc_id));
+        let mut write_batch = WriteBatch::default();
+        let doc_id = DocId(SegmentId(segment), doc_local_id);
+        try!(self.document_index.insert_or_replace_key(&mut write_batch, &doc_key.as_bytes().iter().cloned().collect(), doc_id));
+
+        // Record the change, this writes the document index changes with it
+        let seq_no = self.change_log.record(&self.db, write_batch, ChangeOperation::Index, doc_key.as_bytes())?;
+
+        Ok(seq_no)
+    }
+
+    /// Inserts a document without looking for an existing document with the same key
+    ///
+    /// This is for indices that are only ever added to. The document isn't put in
+    /// the document index, so it can't be replaced or deleted by its key later.
+    pub fn insert_document_append_only(&self, doc: &Document) -> Result<u64, DocumentInsertError> {
+        let mut builder = segment_builder::SegmentBuilder::new();
+        builder.add_document(doc)?;
+        self.write_segment(&builder)?;
+
+        let seq_no = self.change_log.record(&self.db, WriteBatch::default(), ChangeOperation::Index, doc.key.as_bytes())?;
 
-        Ok(())
+        Ok(seq_no)
     }
 
     pub fn write_segment(&self, builder: &segment_builder::SegmentBuilder) -> Result<u32, rocksdb::Error> {
@@ -260,13 +432,46 @@ impl RocksDBStore {
 
         // Write stored fields
         for (&(field_id, doc_id, ref value_type), value) in builder.stored_field_values.iter() {
-            let kb = KeyBuilder::stored_field_value(segment, doc_id, field_id.0, value_type);
-            try!(write_batch.put(&kb.key(), value));
+            // Term frequencies and positions are keyed by the builder's term ids
+            let value_type = match value_type.get(..2) {
+                Some(b"tf") | Some(b"tp") => {
+                    let term_id = str::from_utf8(&value_type[2..]).ok().and_then(|term_id| term_id.parse::<u32>().ok()).expect("invalid term id in value type");
+                    let new_term_id = term_dictionary_map.get(&TermId(term_id)).expect("TermId not in term_dictionary_map");
+
+                    let mut new_value_type = value_type[..2].to_vec();
+                    new_value_type.extend(new_term_id.0.to_string().as_bytes());
+                    new_value_type
+                }
+                _ => value_type.clone(),
+            };
+
+            let kb = KeyBuilder::stored_field_value(segment, doc_id, field_id.0, &value_type);
+            try!(write_batch.put(&kb.key(), &self.seal_stored_value(&kb.key(), value)));
+        }
+
+        // Write document keys
+        // These are only needed to resolve hits, so they're kept apart from the postings
+        for (doc_id, key) in builder.document_keys.iter() {
+            let kb = KeyBuilder::segment_document_key(segment, *doc_id);
+            try!(write_batch.put(&kb.key(), &self.seal_stored_value(&kb.key(), key.as_bytes())));
         }
 
         // Write statistics
         for (name, value) in builder.statistics.iter() {
-            let kb = KeyBuilder::segment_stat(segment, name);
+            // Term document frequencies are also keyed by the builder's term ids
+            let name = match name.get(..4) {
+                Some(b"tdf-") => {
+                    let mut parts = str::from_utf8(&name[4..]).expect("invalid statistic name").split('-');
+                    let field_id = parts.next().and_then(|field_id| field_id.parse::<u32>().ok()).expect("invalid field id in statistic name");
+                    let term_id = parts.next().and_then(|term_id| term_id.parse::<u32>().ok()).expect("invalid term id in statistic name");
+                    let new_term_id = term_dictionary_map.get(&TermId(term_id)).expect("TermId not in term_dictionary_map");
+
+                    KeyBuilder::segment_stat_term_doc_frequency_stat_name(field_id, new_term_id.0)
+                }
+                _ => name.clone(),
+            };
+
+            let kb = KeyBuilder::segment_stat(segment, &name);
 
             let mut value_bytes = [0; 8];
             LittleEndian::write_i64(&mut value_bytes, *value);
@@ -280,8 +485,12 @@ impl RocksDBStore {
     }
 
     pub fn remove_document_by_key(&self, doc_key: &str) -> Result<bool, rocksdb::Error> {
-        match try!(self.document_index.delete_document_by_key(&self.db, &doc_key.as_bytes().iter().cloned().collect())) {
-            Some(_doc_id) => Ok(true),
+        let mut write_batch = WriteBatch::default();
+        match try!(self.document_index.delete_document_by_key(&mut write_batch, &doc_key.as_bytes().iter().cloned().collect())) {
+            Some(_doc_id) => {
+                self.change_log.record(&self.db, write_batch, ChangeOperation::Delete, doc_key.as_bytes())?;
+                Ok(true)
+            }
             None => Ok(false),
         }
     }
@@ -300,6 +509,7 @@ impl fmt::Debug for RocksDBStore {
     }
 }
 
+#[derive(Debug)]
 pub enum StoredFieldReadError {
     /// The provided FieldId wasn't valid for this index
     InvalidFieldId(FieldId),
@@ -315,6 +525,9 @@ pub enum StoredFieldReadError {
 
     /// An integer/datetime field was read but the value wasn't 8 bytes
     IntegerFieldValueSizeError(usize),
+
+    /// The value couldn't be decrypted
+    DecryptionError(String),
 }
 
 impl From<rocksdb::Error> for StoredFieldReadError {
@@ -338,6 +551,34 @@ impl<'a> RocksDBReader<'a> {
         self.store.document_index.contains_document_key(&doc_key.as_bytes().iter().cloned().collect())
     }
 
+    pub fn get_document_id_by_key(&self, doc_key: &str) -> Option<DocId> {
+        // TODO: use snapshot
+        self.store.document_index.get_document_id_by_key(&doc_key.as_bytes().iter().cloned().collect())
+    }
+
+    /// Reads the external key of a document
+    ///
+    /// Documents are only known by their internal ids while searching, this
+    /// resolves the ids of the hits back to the keys they were indexed with.
+    pub fn read_document_key(&self, doc_id: DocId) -> Result<Option<String>, StoredFieldReadError> {
+        let kb = KeyBuilder::segment_document_key((doc_id.0).0, doc_id.1);
+
+        match try!(self.snapshot.get(&kb.key())) {
+            Some(value) => {
+                let value = self.store.open_stored_value(&kb.key(), &value).map_err(StoredFieldReadError::DecryptionError)?;
+
+                match String::from_utf8(value) {
+                    Ok(key) => Ok(Some(key)),
+                    Err(e) => {
+                        let utf8_error = e.utf8_error();
+                        Err(StoredFieldReadError::TextFieldUTF8DecodeError(e.into_bytes(), utf8_error))
+                    }
+                }
+            }
+            None => Ok(None),
+        }
+    }
+
     pub fn read_stored_field(&self, field_id: FieldId, doc_id: DocId) -> Result<Option<FieldValue>, StoredFieldReadError> {
         let field_info = match self.schema().get(&field_id) {
             Some(field_info) => field_info,
@@ -348,8 +589,24 @@ impl<'a> RocksDBReader<'a> {
 
         match try!(self.snapshot.get(&kb.key())) {
             Some(value) => {
+                let value = self.store.open_stored_value(&kb.key(), &value).map_err(StoredFieldReadError::DecryptionError)?;
+
                 match field_info.field_type {
                     FieldType::Text | FieldType::PlainString => {
+                        // Arrays are stored with their values separated by VALUE_SEPARATOR
+                        match str::from_utf8(&value) {
+                            Ok(value_str) if value_str.contains(VALUE_SEPARATOR) => {
+                                Ok(Some(FieldValue::StringArray(value_str.split(VALUE_SEPARATOR).map(|value| value.to_string()).collect())))
+                            }
+                            Ok(value_str) => {
+                                Ok(Some(FieldValue::String(value_str.to_string())))
+                            }
+                            Err(e) => {
+                                Err(StoredFieldReadError::TextFieldUTF8DecodeError(value.to_vec(), e))
+                            }
+                        }
+                    }
+                    FieldType::Wildcard | FieldType::Flattened | FieldType::Nested => {
                         match str::from_utf8(&value) {
                             Ok(value_str) => {
                                 Ok(Some(FieldValue::String(value_str.to_string())))
@@ -360,11 +617,15 @@ impl<'a> RocksDBReader<'a> {
                         }
                     }
                     FieldType::I64 => {
-                        if value.len() != 8 {
+                        if value.len() == 0 || value.len() % 8 != 0 {
                             return Err(StoredFieldReadError::IntegerFieldValueSizeError(value.len()));
                         }
 
-                        Ok(Some(FieldValue::Integer(LittleEndian::read_i64(&value))))
+                        if value.len() == 8 {
+                            Ok(Some(FieldValue::Integer(LittleEndian::read_i64(&value))))
+                        } else {
+                            Ok(Some(FieldValue::IntegerArray(value.chunks(8).map(LittleEndian::read_i64).collect())))
+                        }
                     }
                     FieldType::Boolean => {
                         if value[..] == [b't'] {
@@ -398,17 +659,37 @@ impl<'a> RocksDBReader<'a> {
 mod tests {
     use std::fs::remove_dir_all;
     use std::path::Path;
+    use std::sync::Arc;
 
     use rocksdb::DB;
+    use chrono::{Utc, Duration};
     use fnv::FnvHashMap;
     use search::{Term, Token, Document};
-    use search::document::FieldValue;
-    use search::schema::{FieldType, FIELD_INDEXED, FIELD_STORED};
+    use search::document::{DocId, FieldValue};
+    use search::schema::{Schema, FieldType, FIELD_INDEXED, FIELD_STORED};
     use search::query::Query;
     use search::query::term_scorer::TermScorer;
+    use search::query::multi_term_selector::MultiTermSelector;
+    use search::query::levenshtein::LevenshteinAutomaton;
+    use search::query::wildcard::{WildcardPattern, trigrams_of, trigram_term};
+    use search::query::rank_feature::{RankFeatureFunction, rank_feature_term};
+    use search::query::geo_shape::{Geometry, SpatialRelation, CoordinateSystem, geo_shape_index_terms, geo_shape_query_terms};
+    use search::query::geo_distance::bounding_box;
+    use search::query::function_score::{ScoreFunction, FilteredScoreFunction, ScoreMode, BoostMode, FieldValueModifier, DecayFunction};
+    use search::query::distance_feature::DistanceFeatureOrigin;
+    use search::query::intervals::{IntervalsSource, IntervalFilter};
+    use search::query::nested::{NestedScoreMode, nested_document_marker};
     use search::collectors::top_score::TopScoreCollector;
+    use search::collectors::index_order::IndexOrderCollector;
+    use search::collectors::doc_id_set::DocIdSetCollector;
+    use search::segment::SegmentId;
+    use script::{Expression, ScriptValue};
 
-    use super::RocksDBStore;
+    use super::{RocksDBStore, SegmentSort, DOCUMENT_KEYS_MARKER};
+    use super::segment_ops::SegmentMergeError;
+    use super::key_builder::KeyBuilder;
+    use super::encryption::StoreEncryption;
+    use super::change_log::ChangeOperation;
 
     fn remove_dir_all_ignore_error<P: AsRef<Path>>(path: P) {
         match remove_dir_all(&path) {
@@ -441,6 +722,235 @@ mod tests {
         assert!(store.is_ok());
     }
 
+    #[test]
+    fn test_read_stored_integer_array() {
+        remove_dir_all_ignore_error("test_indices/test_read_stored_integer_array");
+
+        let mut store = RocksDBStore::create("test_indices/test_read_stored_integer_array").unwrap();
+        let prices_field = store.add_field("prices".to_string(), FieldType::I64, FIELD_STORED).unwrap();
+
+        let mut stored_fields = FnvHashMap::default();
+        stored_fields.insert(prices_field, FieldValue::IntegerArray(vec![30, 10, 20]));
+
+        store.insert_or_update_document(&Document {
+            key: "test_doc".to_string(),
+            indexed_fields: FnvHashMap::default(),
+            stored_fields: stored_fields,
+            nested_documents: Vec::new(),
+        }).unwrap();
+
+        let index_reader = store.reader();
+        let doc_id = index_reader.get_document_id_by_key("test_doc").unwrap();
+
+        match index_reader.read_stored_field(prices_field, doc_id) {
+            Ok(Some(FieldValue::IntegerArray(values))) => assert_eq!(values, vec![30, 10, 20]),
+            value => panic!("unexpected stored value: {:?}", value),
+        }
+    }
+
+    #[test]
+    fn test_encryption() {
+        remove_dir_all_ignore_error("test_indices/test_encryption");
+
+        let encryption = Arc::new(StoreEncryption::new(&[7; 32]).unwrap());
+
+        {
+            let mut store = RocksDBStore::create_with_encryption("test_indices/test_encryption", Schema::new(), Some(encryption.clone())).unwrap();
+            let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED | FIELD_STORED).unwrap();
+
+            let mut indexed_fields = FnvHashMap::default();
+            indexed_fields.insert(title_field, vec![Token { term: Term::from_string("secret"), position: 1 }].into());
+            let mut stored_fields = FnvHashMap::default();
+            stored_fields.insert(title_field, FieldValue::String("top secret".to_string()));
+
+            store.insert_or_update_document(&Document {
+                key: "test_doc".to_string(),
+                indexed_fields: indexed_fields,
+                stored_fields: stored_fields,
+                nested_documents: Vec::new(),
+            }).unwrap();
+
+            let index_reader = store.reader();
+            let doc_id = index_reader.get_document_id_by_key("test_doc").unwrap();
+
+            // The value is encrypted on disk
+            let kb = KeyBuilder::stored_field_value((doc_id.0).0, doc_id.1, title_field.0, b"val");
+            let raw_value = store.db.get(&kb.key()).unwrap().unwrap();
+            assert!(!raw_value.windows(10).any(|window| window == b"top secret"));
+
+            // But not when it's read, or searched
+            match index_reader.read_stored_field(title_field, doc_id) {
+                Ok(Some(FieldValue::String(value))) => assert_eq!(value, "top secret"),
+                value => panic!("unexpected stored value: {:?}", value),
+            }
+
+            let mut collector = TopScoreCollector::new(10);
+            index_reader.search(&mut collector, &Query::term(title_field, Term::from_string("secret"))).unwrap();
+            assert_eq!(collector.into_sorted_vec().len(), 1);
+
+            // Values are bound to their keys, so they must still open after they're moved by a merge
+            let mut stored_fields = FnvHashMap::default();
+            stored_fields.insert(title_field, FieldValue::String("also secret".to_string()));
+            store.insert_or_update_document(&Document {
+                key: "another_test_doc".to_string(),
+                indexed_fields: FnvHashMap::default(),
+                stored_fields: stored_fields,
+                nested_documents: Vec::new(),
+            }).unwrap();
+
+            store.merge_segments(&vec![1, 2]).unwrap();
+            store.purge_segments(&vec![1, 2]).unwrap();
+
+            let index_reader = store.reader();
+            let doc_id = index_reader.get_document_id_by_key("test_doc").unwrap();
+            assert_eq!(index_reader.read_document_key(doc_id).unwrap(), Some("test_doc".to_string()));
+            match index_reader.read_stored_field(title_field, doc_id) {
+                Ok(Some(FieldValue::String(value))) => assert_eq!(value, "top secret"),
+                value => panic!("unexpected stored value: {:?}", value),
+            }
+        }
+
+        // The store can't be opened without the key, or with the wrong one
+        assert!(RocksDBStore::open("test_indices/test_encryption").is_err());
+        assert!(RocksDBStore::open_with_encryption("test_indices/test_encryption", Some(Arc::new(StoreEncryption::new(&[8; 32]).unwrap()))).is_err());
+        assert!(RocksDBStore::open_with_encryption("test_indices/test_encryption", Some(encryption)).unwrap().is_encrypted());
+    }
+
+    #[test]
+    fn test_merge_segments_sorted() {
+        remove_dir_all_ignore_error("test_indices/test_merge_segments_sorted");
+
+        let mut store = RocksDBStore::create("test_indices/test_merge_segments_sorted").unwrap();
+        let ts_field = store.add_field("ts".to_string(), FieldType::I64, FIELD_STORED).unwrap();
+
+        for &(key, ts) in [("a", Some(5)), ("b", None), ("c", Some(1)), ("d", Some(3))].iter() {
+            let mut stored_fields = FnvHashMap::default();
+            if let Some(ts) = ts {
+                stored_fields.insert(ts_field, FieldValue::Integer(ts));
+            }
+
+            store.insert_or_update_document(&Document {
+                key: key.to_string(),
+                indexed_fields: FnvHashMap::default(),
+                stored_fields: stored_fields,
+                nested_documents: Vec::new(),
+            }).unwrap();
+        }
+
+        let read_keys = |store: &RocksDBStore, segment: u32| {
+            let index_reader = store.reader();
+            (0..4).map(|doc_id| index_reader.read_document_key(DocId(SegmentId(segment), doc_id)).unwrap().unwrap()).collect::<Vec<_>>()
+        };
+
+        // Documents without a value go last
+        let segment = store.merge_segments_sorted(&vec![1, 2, 3, 4], Some(SegmentSort { field: ts_field, descending: false })).unwrap();
+        store.purge_segments(&vec![1, 2, 3, 4]).unwrap();
+        assert_eq!(read_keys(&store, segment), vec!["c", "d", "a", "b"]);
+
+        let merged_segment = store.merge_segments_sorted(&vec![segment], Some(SegmentSort { field: ts_field, descending: true })).unwrap();
+        store.purge_segments(&vec![segment]).unwrap();
+        assert_eq!(read_keys(&store, merged_segment), vec!["a", "d", "c", "b"]);
+
+        // The document index follows the documents
+        let index_reader = store.reader();
+        assert_eq!(index_reader.get_document_id_by_key("d"), Some(DocId(SegmentId(merged_segment), 1)));
+    }
+
+    #[test]
+    fn test_open_with_old_term_format() {
+        remove_dir_all_ignore_error("test_indices/test_open_with_old_term_format");
+
+        // Stores created before the term format was recorded have integers encoded little-endian
+        let old_integer = |value: i64| Term::from_bytes(&value.to_le_bytes());
+
+        {
+            let mut store = RocksDBStore::create("test_indices/test_open_with_old_term_format").unwrap();
+            let age_field = store.add_field("age".to_string(), FieldType::I64, FIELD_INDEXED).unwrap();
+            let code_field = store.add_field("code".to_string(), FieldType::PlainString, FIELD_INDEXED).unwrap();
+
+            for &(key, ages, code) in [("a", &[-5][..], "abcdefgh"), ("b", &[5, 5][..], "abcdefgh"), ("c", &[10][..], "ijklmnop"), ("d", &[15, 20][..], "ijklmnop")].iter() {
+                let mut indexed_fields = FnvHashMap::default();
+                indexed_fields.insert(age_field, ages.iter().enumerate().map(|(i, age)| Token { term: old_integer(*age), position: i as u32 + 1 }).collect::<Vec<_>>().into());
+                indexed_fields.insert(code_field, vec![Token { term: Term::from_string(code), position: 1 }].into());
+
+                store.insert_or_update_document(&Document {
+                    key: key.to_string(),
+                    indexed_fields: indexed_fields,
+                    stored_fields: FnvHashMap::default(),
+                    nested_documents: Vec::new(),
+                }).unwrap();
+            }
+
+            store.db.delete(b".term_format").unwrap();
+        }
+
+        let store = RocksDBStore::open("test_indices/test_open_with_old_term_format").unwrap();
+        assert_eq!(store.db.get(b".term_format").unwrap().unwrap().to_utf8(), Some("2"));
+
+        let age_field = store.schema.get_field_by_name("age").unwrap();
+        let code_field = store.schema.get_field_by_name("code").unwrap();
+        let index_reader = store.reader();
+        let count = |query: Query| {
+            let mut collector = IndexOrderCollector::new(10);
+            index_reader.search(&mut collector, &query).unwrap();
+            collector.get_total_count()
+        };
+
+        // Integer terms are found in order
+        assert_eq!(count(Query::MultiTerm {
+            field: age_field,
+            term_selector: MultiTermSelector::IntegerRange { gte: None, lte: Some(7) },
+            scorer: TermScorer::default(),
+        }), 2);
+        assert_eq!(count(Query::MultiTerm {
+            field: age_field,
+            term_selector: MultiTermSelector::IntegerRange { gte: Some(12), lte: None },
+            scorer: TermScorer::default(),
+        }), 1);
+        assert_eq!(count(Query::Term {
+            field: age_field,
+            term: Term::from_integer(5),
+            scorer: TermScorer::default(),
+        }), 1);
+
+        // Terms of other fields are left alone, even if they're 8 bytes long
+        assert_eq!(count(Query::Term {
+            field: code_field,
+            term: Term::from_string("abcdefgh"),
+            scorer: TermScorer::default(),
+        }), 2);
+
+        // Term frequencies and positions move to the new terms
+        let mut collector = TopScoreCollector::new(10);
+        index_reader.search(&mut collector, &Query::Term {
+            field: age_field,
+            term: Term::from_integer(5),
+            scorer: TermScorer::default(),
+        }).unwrap();
+        let b_score = collector.into_sorted_vec()[0].score().unwrap();
+
+        let mut collector = TopScoreCollector::new(10);
+        index_reader.search(&mut collector, &Query::Term {
+            field: age_field,
+            term: Term::from_integer(15),
+            scorer: TermScorer::default(),
+        }).unwrap();
+        let d_score = collector.into_sorted_vec()[0].score().unwrap();
+        assert!(b_score > d_score);
+    }
+
+    #[test]
+    fn test_open_with_unknown_term_format() {
+        remove_dir_all_ignore_error("test_indices/test_open_with_unknown_term_format");
+
+        {
+            let store = RocksDBStore::create("test_indices/test_open_with_unknown_term_format").unwrap();
+            store.db.put(b".term_format", b"3").unwrap();
+        }
+
+        assert!(RocksDBStore::open("test_indices/test_open_with_unknown_term_format").is_err());
+    }
+
     fn make_test_store(path: &str) -> RocksDBStore {
         let mut store = RocksDBStore::create(path).unwrap();
         let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
@@ -475,6 +985,7 @@ mod tests {
             key: "test_doc".to_string(),
             indexed_fields: indexed_fields,
             stored_fields: stored_fields,
+            nested_documents: Vec::new(),
         }).unwrap();
 
         let mut indexed_fields = FnvHashMap::default();
@@ -504,6 +1015,7 @@ mod tests {
             key: "another_test_doc".to_string(),
             indexed_fields: indexed_fields,
             stored_fields: stored_fields,
+            nested_documents: Vec::new(),
         }).unwrap();
 
         store.merge_segments(&vec![1, 2]).unwrap();
@@ -579,4 +1091,1046 @@ mod tests {
         let docs = collector.into_sorted_vec();
         println!("{:?}", docs);
     }
+
+    #[test]
+    fn test_search_index_order() {
+        remove_dir_all_ignore_error("test_indices/test_search_index_order");
+
+        let store = make_test_store("test_indices/test_search_index_order");
+        let body_field = store.schema.get_field_by_name("body").unwrap();
+
+        let index_reader = store.reader();
+
+        // Both documents match, but the collector only needs the first one
+        let query = Query::term(body_field, Term::from_string("lorem"));
+
+        let mut collector = IndexOrderCollector::new(1);
+        index_reader.search(&mut collector, &query).unwrap();
+
+        assert_eq!(collector.get_total_count(), 2);
+
+        let docs = collector.into_sorted_vec();
+        assert_eq!(docs.len(), 1);
+        assert_eq!(docs[0].score(), None);
+    }
+
+    #[test]
+    fn test_search_wildcard() {
+        remove_dir_all_ignore_error("test_indices/test_search_wildcard");
+
+        let mut store = RocksDBStore::create("test_indices/test_search_wildcard").unwrap();
+        let message_field = store.add_field("message".to_string(), FieldType::Wildcard, FIELD_INDEXED | FIELD_STORED).unwrap();
+
+        for (key, message) in vec![("match", "an error with a substring"), ("no_substring", "errors everywhere"), ("no_error", "nothing")] {
+            let mut tokens = vec![Token { term: Term::from_string(message), position: 1 }];
+            for trigram in trigrams_of(message) {
+                tokens.push(Token { term: trigram_term(&trigram), position: 1 });
+            }
+
+            let mut indexed_fields = FnvHashMap::default();
+            indexed_fields.insert(message_field, tokens.into());
+
+            let mut stored_fields = FnvHashMap::default();
+            stored_fields.insert(message_field, FieldValue::String(message.to_string()));
+
+            store.insert_or_update_document(&Document {
+                key: key.to_string(),
+                indexed_fields: indexed_fields,
+                stored_fields: stored_fields,
+                nested_documents: Vec::new(),
+            }).unwrap();
+        }
+
+        let index_reader = store.reader();
+        let pattern = WildcardPattern::new("*error*substring*");
+        let query = Query::Wildcard {
+            field: message_field,
+            trigrams: pattern.trigrams().iter().map(|trigram| trigram_term(trigram)).collect(),
+            pattern: pattern,
+            score: 1.0f32,
+        };
+
+        let mut collector = IndexOrderCollector::new(10);
+        index_reader.search(&mut collector, &query).unwrap();
+
+        // "errors everywhere" has the trigrams from "error" but fails verification
+        assert_eq!(collector.get_total_count(), 1);
+    }
+
+    #[test]
+    fn test_search_integer_range() {
+        remove_dir_all_ignore_error("test_indices/test_search_integer_range");
+
+        let mut store = RocksDBStore::create("test_indices/test_search_integer_range").unwrap();
+        let age_field = store.add_field("age".to_string(), FieldType::I64, FIELD_INDEXED).unwrap();
+
+        for age in vec![-5, 5, 10, 15, 20] {
+            let mut indexed_fields = FnvHashMap::default();
+            indexed_fields.insert(age_field, vec![Token { term: Term::from_integer(age), position: 1 }].into());
+
+            store.insert_or_update_document(&Document {
+                key: age.to_string(),
+                indexed_fields: indexed_fields,
+                stored_fields: FnvHashMap::default(),
+                nested_documents: Vec::new(),
+            }).unwrap();
+        }
+
+        let index_reader = store.reader();
+        let query = Query::MultiTerm {
+            field: age_field,
+            term_selector: MultiTermSelector::IntegerRange { gte: Some(10), lte: Some(19) },
+            scorer: TermScorer::default(),
+        };
+
+        let mut collector = IndexOrderCollector::new(10);
+        index_reader.search(&mut collector, &query).unwrap();
+
+        assert_eq!(collector.get_total_count(), 2);
+
+        // Negative values are before positive ones in the term dictionary
+        let query = Query::MultiTerm {
+            field: age_field,
+            term_selector: MultiTermSelector::IntegerRange { gte: None, lte: Some(7) },
+            scorer: TermScorer::default(),
+        };
+
+        let mut collector = IndexOrderCollector::new(10);
+        index_reader.search(&mut collector, &query).unwrap();
+
+        assert_eq!(collector.get_total_count(), 2);
+    }
+
+    #[test]
+    fn test_search_phrase() {
+        remove_dir_all_ignore_error("test_indices/test_search_phrase");
+
+        let store = make_test_store("test_indices/test_search_phrase");
+        let title_field = store.schema.get_field_by_name("title").unwrap();
+        let index_reader = store.reader();
+
+        let phrase_count = |terms: Vec<&str>, slop| {
+            let query = Query::Phrase {
+                field: title_field,
+                terms: terms.iter().map(|term| Term::from_string(term)).collect(),
+                slop: slop,
+                scorer: TermScorer::default(),
+            };
+
+            let mut collector = TopScoreCollector::new(10);
+            index_reader.search(&mut collector, &query).unwrap();
+            collector.into_sorted_vec().len()
+        };
+
+        assert_eq!(phrase_count(vec!["hello", "world"], 0), 1);
+        assert_eq!(phrase_count(vec!["world", "hello"], 0), 0);
+        assert_eq!(phrase_count(vec!["world", "hello"], 2), 1);
+        assert_eq!(phrase_count(vec!["hello", "partner"], 2), 0);
+    }
+
+    #[test]
+    fn test_search_phrase_prefix() {
+        remove_dir_all_ignore_error("test_indices/test_search_phrase_prefix");
+
+        let store = make_test_store("test_indices/test_search_phrase_prefix");
+        let body_field = store.schema.get_field_by_name("body").unwrap();
+        let index_reader = store.reader();
+
+        let phrase_prefix_count = |terms: Vec<&str>, prefix: &str| {
+            let query = Query::PhrasePrefix {
+                field: body_field,
+                terms: terms.iter().map(|term| Term::from_string(term)).collect(),
+                last_term_selector: MultiTermSelector::Prefix(prefix.to_string()),
+                slop: 0,
+                scorer: TermScorer::default(),
+            };
+
+            let mut collector = TopScoreCollector::new(10);
+            index_reader.search(&mut collector, &query).unwrap();
+            collector.into_sorted_vec().len()
+        };
+
+        assert_eq!(phrase_prefix_count(vec!["lorem", "ipsum"], "do"), 2);
+        assert_eq!(phrase_prefix_count(vec!["lorem"], "ips"), 2);
+        assert_eq!(phrase_prefix_count(vec!["lorem"], "do"), 0);
+        assert_eq!(phrase_prefix_count(vec!["lorem"], "x"), 0);
+        assert_eq!(phrase_prefix_count(vec!["hello"], "wor"), 0);
+    }
+
+    #[test]
+    fn test_search_exists() {
+        remove_dir_all_ignore_error("test_indices/test_search_exists");
+
+        let mut store = RocksDBStore::create("test_indices/test_search_exists").unwrap();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let body_field = store.add_field("body".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let bodies = vec![("with_body", true), ("without_body", false), ("empty_body", false)];
+        for &(key, has_body) in bodies.iter() {
+            let mut indexed_fields = FnvHashMap::default();
+            indexed_fields.insert(title_field, vec![Token { term: Term::from_string(key), position: 1 }].into());
+
+            if has_body {
+                indexed_fields.insert(body_field, vec![Token { term: Term::from_string("hello"), position: 1 }].into());
+            } else if key == "empty_body" {
+                indexed_fields.insert(body_field, Vec::<Token>::new().into());
+            }
+
+            store.insert_or_update_document(&Document {
+                key: key.to_string(),
+                indexed_fields: indexed_fields,
+                stored_fields: FnvHashMap::default(),
+                nested_documents: Vec::new(),
+            }).unwrap();
+        }
+
+        let index_reader = store.reader();
+        let exists_count = |field| {
+            let mut collector = IndexOrderCollector::new(10);
+            index_reader.search(&mut collector, &Query::Exists { field: field, score: 1.0 }).unwrap();
+            collector.get_total_count()
+        };
+
+        assert_eq!(exists_count(title_field), 3);
+        assert_eq!(exists_count(body_field), 1);
+    }
+
+    #[test]
+    fn test_search_term_set() {
+        remove_dir_all_ignore_error("test_indices/test_search_term_set");
+
+        let store = make_test_store("test_indices/test_search_term_set");
+        let title_field = store.schema.get_field_by_name("title").unwrap();
+        let index_reader = store.reader();
+
+        let term_set_count = |terms: Vec<&str>| {
+            let query = Query::TermSet {
+                field: title_field,
+                terms: terms.iter().map(|term| Term::from_string(term)).collect(),
+                score: 1.0,
+            };
+
+            let mut collector = IndexOrderCollector::new(10);
+            index_reader.search(&mut collector, &query).unwrap();
+            collector.get_total_count()
+        };
+
+        assert_eq!(term_set_count(vec!["hello"]), 1);
+        assert_eq!(term_set_count(vec!["hello", "howdy", "missing"]), 2);
+        assert_eq!(term_set_count(vec!["hello", "world"]), 1);
+        assert_eq!(term_set_count(vec![]), 0);
+
+        // Thousands of terms are unioned in one step
+        let mut terms = (0..5000).map(|i| i.to_string()).collect::<Vec<_>>();
+        terms.push("partner".to_string());
+        assert_eq!(term_set_count(terms.iter().map(|term| term.as_str()).collect()), 1);
+    }
+
+    #[test]
+    fn test_search_ids() {
+        remove_dir_all_ignore_error("test_indices/test_search_ids");
+
+        let store = make_test_store("test_indices/test_search_ids");
+        let index_reader = store.reader();
+
+        let ids_count = |keys: Vec<&str>| {
+            let query = Query::Ids {
+                keys: keys.iter().map(|key| key.to_string()).collect(),
+                score: 1.0,
+            };
+
+            let mut collector = IndexOrderCollector::new(10);
+            index_reader.search(&mut collector, &query).unwrap();
+            collector.get_total_count()
+        };
+
+        assert_eq!(ids_count(vec!["test_doc"]), 1);
+        assert_eq!(ids_count(vec!["test_doc", "another_test_doc", "missing_doc"]), 2);
+        assert_eq!(ids_count(vec![]), 0);
+
+        // Documents can be matched by their internal ids too
+        let query = Query::DocIds {
+            doc_ids: vec![index_reader.get_document_id_by_key("test_doc").unwrap()],
+            score: 1.0,
+        };
+
+        let mut collector = IndexOrderCollector::new(10);
+        index_reader.search(&mut collector, &query).unwrap();
+        assert_eq!(collector.get_total_count(), 1);
+    }
+
+    #[test]
+    fn test_search_random_score() {
+        remove_dir_all_ignore_error("test_indices/test_search_random_score");
+
+        let store = make_test_store("test_indices/test_search_random_score");
+        let pk_field = store.schema.get_field_by_name("pk").unwrap();
+        let index_reader = store.reader();
+
+        let scores = |seed: u64| {
+            let query = Query::FunctionScore {
+                query: Box::new(Query::all()),
+                functions: vec![FilteredScoreFunction {
+                    filter: None,
+                    function: ScoreFunction::RandomScore { seed: seed, field: Some(pk_field) },
+                    weight: 2.0,
+                }],
+                score_mode: ScoreMode::Multiply,
+                boost_mode: BoostMode::Replace,
+                max_boost: ::std::f32::MAX,
+                boost: 1.0,
+            };
+
+            let mut collector = TopScoreCollector::new(10);
+            index_reader.search(&mut collector, &query).unwrap();
+
+            let mut scores = collector.into_sorted_vec().iter().map(|doc| (doc.doc_id(), doc.score().unwrap())).collect::<Vec<_>>();
+            scores.sort_by_key(|&(doc_id, _)| doc_id);
+            scores
+        };
+
+        // The same seed always gives the same scores
+        assert_eq!(scores(1), scores(1));
+        assert!(scores(1) != scores(2));
+        assert_eq!(scores(1).len(), 2);
+        assert!(scores(1).iter().all(|&(_, score)| score >= 0.0 && score < 2.0));
+    }
+
+    #[test]
+    fn test_search_field_value_factor_and_decay() {
+        remove_dir_all_ignore_error("test_indices/test_search_field_value_factor_and_decay");
+
+        let mut store = RocksDBStore::create("test_indices/test_search_field_value_factor_and_decay").unwrap();
+        let year_field = store.add_field("year".to_string(), FieldType::I64, FIELD_INDEXED | FIELD_STORED).unwrap();
+
+        for &(key, year) in [("old", 1990), ("newest", 2020)].iter() {
+            let mut indexed_fields = FnvHashMap::default();
+            indexed_fields.insert(year_field, vec![Token { term: Term::from_integer(year), position: 1 }].into());
+
+            let mut stored_fields = FnvHashMap::default();
+            stored_fields.insert(year_field, FieldValue::Integer(year));
+
+            store.insert_or_update_document(&Document {
+                key: key.to_string(),
+                indexed_fields: indexed_fields,
+                stored_fields: stored_fields,
+                nested_documents: Vec::new(),
+            }).unwrap();
+        }
+
+        let index_reader = store.reader();
+        let scores = |function: ScoreFunction| {
+            let query = Query::FunctionScore {
+                query: Box::new(Query::all()),
+                functions: vec![FilteredScoreFunction {
+                    filter: None,
+                    function: function,
+                    weight: 1.0,
+                }],
+                score_mode: ScoreMode::Multiply,
+                boost_mode: BoostMode::Replace,
+                max_boost: ::std::f32::MAX,
+                boost: 1.0,
+            };
+
+            let mut collector = TopScoreCollector::new(10);
+            index_reader.search(&mut collector, &query).unwrap();
+            collector.into_sorted_vec().iter().map(|doc| doc.score().unwrap()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(scores(ScoreFunction::FieldValueFactor {
+            field: year_field,
+            factor: 0.5,
+            modifier: FieldValueModifier::None,
+            missing: None,
+        }), vec![1010.0, 995.0]);
+
+        assert_eq!(scores(ScoreFunction::Decay {
+            field: year_field,
+            function: DecayFunction::Linear,
+            origin: DistanceFeatureOrigin::Integer(2020),
+            scale: 15.0,
+            offset: 0.0,
+            decay: 0.5,
+        }), vec![1.0, 0.0]);
+    }
+
+    #[test]
+    fn test_search_distance_feature() {
+        remove_dir_all_ignore_error("test_indices/test_search_distance_feature");
+
+        let mut store = RocksDBStore::create("test_indices/test_search_distance_feature").unwrap();
+        let year_field = store.add_field("year".to_string(), FieldType::I64, FIELD_INDEXED | FIELD_STORED).unwrap();
+
+        for &(key, year) in [("old", 1990), ("recent", 2018), ("newest", 2020)].iter() {
+            let mut indexed_fields = FnvHashMap::default();
+            indexed_fields.insert(year_field, vec![Token { term: Term::from_integer(year), position: 1 }].into());
+
+            let mut stored_fields = FnvHashMap::default();
+            stored_fields.insert(year_field, FieldValue::Integer(year));
+
+            store.insert_or_update_document(&Document {
+                key: key.to_string(),
+                indexed_fields: indexed_fields,
+                stored_fields: stored_fields,
+                nested_documents: Vec::new(),
+            }).unwrap();
+        }
+
+        let index_reader = store.reader();
+        let query = Query::DistanceFeature {
+            field: year_field,
+            origin: DistanceFeatureOrigin::Integer(2020),
+            pivot: 2.0,
+            boost: 1.0,
+        };
+
+        let mut collector = TopScoreCollector::new(10);
+        index_reader.search(&mut collector, &query).unwrap();
+
+        let scores = collector.into_sorted_vec().iter().map(|doc| doc.score().unwrap()).collect::<Vec<_>>();
+        assert_eq!(scores, vec![1.0, 0.5, 2.0 / 32.0]);
+    }
+
+    #[test]
+    fn test_search_script() {
+        remove_dir_all_ignore_error("test_indices/test_search_script");
+
+        let mut store = RocksDBStore::create("test_indices/test_search_script").unwrap();
+        let price_field = store.add_field("price".to_string(), FieldType::I64, FIELD_INDEXED | FIELD_STORED).unwrap();
+        let qty_field = store.add_field("qty".to_string(), FieldType::I64, FIELD_INDEXED | FIELD_STORED).unwrap();
+
+        for &(key, price, qty) in [("cheap", 5, 10), ("bulk", 20, 10), ("single", 150, 1)].iter() {
+            let mut indexed_fields = FnvHashMap::default();
+            indexed_fields.insert(price_field, vec![Token { term: Term::from_integer(price), position: 1 }].into());
+            indexed_fields.insert(qty_field, vec![Token { term: Term::from_integer(qty), position: 1 }].into());
+
+            let mut stored_fields = FnvHashMap::default();
+            stored_fields.insert(price_field, FieldValue::Integer(price));
+            stored_fields.insert(qty_field, FieldValue::Integer(qty));
+
+            store.insert_or_update_document(&Document {
+                key: key.to_string(),
+                indexed_fields: indexed_fields,
+                stored_fields: stored_fields,
+                nested_documents: Vec::new(),
+            }).unwrap();
+        }
+
+        let index_reader = store.reader();
+        let script_count = |source: &str| {
+            let query = Query::Script {
+                script: Expression::parse(source).unwrap(),
+                params: hashmap! {
+                    "min".to_string() => ScriptValue::Number(100.0),
+                },
+                score: 1.0,
+            };
+
+            let mut collector = IndexOrderCollector::new(10);
+            index_reader.search(&mut collector, &query).unwrap();
+            collector.get_total_count()
+        };
+
+        assert_eq!(script_count("doc['price'] * doc['qty'] > params.min"), 2);
+        assert_eq!(script_count("doc['price'] < 10"), 1);
+
+        // Scripts that don't read any fields match every document or none of them
+        assert_eq!(script_count("params.min > 50"), 3);
+        assert_eq!(script_count("params.min > 500"), 0);
+
+        // Missing fields and scripts that don't give a boolean never match
+        assert_eq!(script_count("doc['weight'] > 1"), 0);
+        assert_eq!(script_count("doc['price'] + 1"), 0);
+    }
+
+    #[test]
+    fn test_search_intervals() {
+        remove_dir_all_ignore_error("test_indices/test_search_intervals");
+
+        let store = make_test_store("test_indices/test_search_intervals");
+        let title_field = store.schema.get_field_by_name("title").unwrap();
+        let index_reader = store.reader();
+
+        let count = |source: IntervalsSource<Term>| {
+            let query = Query::Intervals {
+                field: title_field,
+                source: source,
+                scorer: TermScorer::default(),
+            };
+
+            let mut collector = IndexOrderCollector::new(10);
+            index_reader.search(&mut collector, &query).unwrap();
+            collector.get_total_count()
+        };
+
+        let all_of = |first: &str, second: &str, ordered: bool| IntervalsSource::AllOf {
+            sources: vec![IntervalsSource::Term(Term::from_string(first)), IntervalsSource::Term(Term::from_string(second))],
+            ordered: ordered,
+            max_gaps: Some(0),
+        };
+
+        assert_eq!(count(all_of("hello", "world", true)), 1);
+        assert_eq!(count(all_of("world", "hello", true)), 0);
+        assert_eq!(count(all_of("world", "hello", false)), 1);
+        assert_eq!(count(all_of("hello", "partner", false)), 0);
+
+        let filtered = IntervalsSource::Filtered {
+            source: Box::new(IntervalsSource::AnyOf {
+                sources: vec![IntervalsSource::Term(Term::from_string("hello")), IntervalsSource::Term(Term::from_string("howdy"))],
+            }),
+            filter: IntervalFilter::NotContainedBy,
+            filter_source: Box::new(all_of("hello", "world", true)),
+        };
+
+        assert_eq!(count(filtered), 1);
+
+        let first = |end: u32| IntervalsSource::First {
+            source: Box::new(IntervalsSource::Term(Term::from_string("world"))),
+            end: end,
+        };
+
+        assert_eq!(count(first(1)), 0);
+        assert_eq!(count(first(2)), 1);
+    }
+
+    #[test]
+    fn test_search_common_terms() {
+        remove_dir_all_ignore_error("test_indices/test_search_common_terms");
+
+        let store = make_test_store("test_indices/test_search_common_terms");
+        let body_field = store.schema.get_field_by_name("body").unwrap();
+        let index_reader = store.reader();
+
+        let count = |terms: &[&str], require_all: bool| {
+            let query = Query::CommonTerms {
+                field: body_field,
+                terms: terms.iter().map(|term| Term::from_string(term)).collect(),
+                cutoff_frequency: 1.0,
+                require_all: require_all,
+                scorer: TermScorer::default(),
+            };
+
+            let mut collector = IndexOrderCollector::new(10);
+            index_reader.search(&mut collector, &query).unwrap();
+            collector.get_total_count()
+        };
+
+        // Both documents have "lorem", so it's only matched when there are no other terms
+        assert_eq!(count(&["lorem", "hello"], false), 0);
+        assert_eq!(count(&["lorem", "ipsum"], true), 2);
+    }
+
+    #[test]
+    fn test_search_blended_term() {
+        remove_dir_all_ignore_error("test_indices/test_search_blended_term");
+
+        let store = make_test_store("test_indices/test_search_blended_term");
+        let title_field = store.schema.get_field_by_name("title").unwrap();
+        let body_field = store.schema.get_field_by_name("body").unwrap();
+        let index_reader = store.reader();
+
+        let count = |term: &str| {
+            let query = Query::BlendedTerm {
+                fields: vec![(title_field, 1.0), (body_field, 1.0)],
+                term: Term::from_string(term),
+                tie_breaker: 0.0,
+                scorer: TermScorer::default(),
+            };
+
+            let mut collector = IndexOrderCollector::new(10);
+            index_reader.search(&mut collector, &query).unwrap();
+            collector.get_total_count()
+        };
+
+        // Terms are matched in any of the fields
+        assert_eq!(count("hello"), 1);
+        assert_eq!(count("lorem"), 2);
+        assert_eq!(count("goodbye"), 0);
+    }
+
+    #[test]
+    fn test_search_fuzzy() {
+        remove_dir_all_ignore_error("test_indices/test_search_fuzzy");
+
+        let store = make_test_store("test_indices/test_search_fuzzy");
+        let title_field = store.schema.get_field_by_name("title").unwrap();
+        let index_reader = store.reader();
+
+        // Only "hello" is within one edit of "hallo"
+        let query = Query::MultiTerm {
+            field: title_field,
+            term_selector: MultiTermSelector::Fuzzy {
+                automaton: LevenshteinAutomaton::new("hallo", 1, 0),
+                max_expansions: 50,
+            },
+            scorer: TermScorer::default(),
+        };
+
+        let mut collector = IndexOrderCollector::new(10);
+        index_reader.search(&mut collector, &query).unwrap();
+
+        assert_eq!(collector.get_total_count(), 1);
+    }
+
+    #[test]
+    fn test_search_geo_shape() {
+        remove_dir_all_ignore_error("test_indices/test_search_geo_shape");
+
+        let mut store = RocksDBStore::create("test_indices/test_search_geo_shape").unwrap();
+        let location_field = store.add_field("location".to_string(), FieldType::PlainString, FIELD_INDEXED | FIELD_STORED).unwrap();
+
+        let shapes = vec![
+            ("berlin", json!({"type": "point", "coordinates": [13.4, 52.5]})),
+            ("brandenburg", json!({"type": "envelope", "coordinates": [[11.3, 53.5], [14.7, 51.4]]})),
+            ("paris", json!({"type": "point", "coordinates": [2.35, 48.85]})),
+        ];
+
+        for (key, shape_json) in shapes {
+            let shape = Geometry::from_json(&shape_json, CoordinateSystem::Geographic).unwrap();
+            let tokens = geo_shape_index_terms(&shape, CoordinateSystem::Geographic).into_iter().map(|term| Token { term: term, position: 1 }).collect::<Vec<_>>();
+
+            let mut indexed_fields = FnvHashMap::default();
+            indexed_fields.insert(location_field, tokens.into());
+
+            let mut stored_fields = FnvHashMap::default();
+            stored_fields.insert(location_field, FieldValue::String(shape_json.to_string()));
+
+            store.insert_or_update_document(&Document {
+                key: key.to_string(),
+                indexed_fields: indexed_fields,
+                stored_fields: stored_fields,
+                nested_documents: Vec::new(),
+            }).unwrap();
+        }
+
+        let index_reader = store.reader();
+        let search = |shape: Geometry, relation: SpatialRelation| {
+            let query = Query::GeoShape {
+                field: location_field,
+                tiles: geo_shape_query_terms(&shape, relation, CoordinateSystem::Geographic),
+                shape: shape,
+                relation: relation,
+                system: CoordinateSystem::Geographic,
+                score: 1.0f32,
+            };
+
+            let mut collector = IndexOrderCollector::new(10);
+            index_reader.search(&mut collector, &query).unwrap();
+            collector.get_total_count()
+        };
+
+        let central_berlin = Geometry::Envelope((13.0, 53.0), (14.0, 52.0));
+        assert_eq!(search(central_berlin.clone(), SpatialRelation::Intersects), 2);
+        assert_eq!(search(central_berlin.clone(), SpatialRelation::Within), 1);
+        assert_eq!(search(central_berlin, SpatialRelation::Disjoint), 1);
+    }
+
+    #[test]
+    fn test_search_geo_distance() {
+        remove_dir_all_ignore_error("test_indices/test_search_geo_distance");
+
+        let mut store = RocksDBStore::create("test_indices/test_search_geo_distance").unwrap();
+        let location_field = store.add_field("location".to_string(), FieldType::PlainString, FIELD_INDEXED | FIELD_STORED).unwrap();
+
+        let points = vec![
+            ("berlin", json!({"type": "point", "coordinates": [13.4, 52.5]})),
+            ("potsdam", json!({"type": "point", "coordinates": [13.06, 52.4]})),
+            ("paris_and_hamburg", json!({"type": "multipoint", "coordinates": [[2.35, 48.85], [10.0, 53.55]]})),
+        ];
+
+        for (key, point_json) in points {
+            let point = Geometry::from_json(&point_json, CoordinateSystem::Geographic).unwrap();
+            let tokens = geo_shape_index_terms(&point, CoordinateSystem::Geographic).into_iter().map(|term| Token { term: term, position: 1 }).collect::<Vec<_>>();
+
+            let mut indexed_fields = FnvHashMap::default();
+            indexed_fields.insert(location_field, tokens.into());
+
+            let mut stored_fields = FnvHashMap::default();
+            stored_fields.insert(location_field, FieldValue::String(point_json.to_string()));
+
+            store.insert_or_update_document(&Document {
+                key: key.to_string(),
+                indexed_fields: indexed_fields,
+                stored_fields: stored_fields,
+                nested_documents: Vec::new(),
+            }).unwrap();
+        }
+
+        let index_reader = store.reader();
+        let search = |origin: (f64, f64), distance: f64| {
+            let query = Query::GeoDistance {
+                field: location_field,
+                tiles: geo_shape_query_terms(&bounding_box(origin, distance), SpatialRelation::Intersects, CoordinateSystem::Geographic),
+                origin: origin,
+                distance: distance,
+                score: 1.0f32,
+            };
+
+            let mut collector = IndexOrderCollector::new(10);
+            index_reader.search(&mut collector, &query).unwrap();
+            collector.get_total_count()
+        };
+
+        // Potsdam is about 25km from Berlin, Hamburg is about 255km away
+        assert_eq!(search((13.4, 52.5), 5000.0), 1);
+        assert_eq!(search((13.4, 52.5), 30000.0), 2);
+        assert_eq!(search((13.4, 52.5), 300000.0), 3);
+
+        // Only one point of a document needs to be close
+        assert_eq!(search((2.3, 48.9), 10000.0), 1);
+    }
+
+    #[test]
+    fn test_search_minimum_should_match() {
+        remove_dir_all_ignore_error("test_indices/test_search_minimum_should_match");
+
+        let mut store = RocksDBStore::create("test_indices/test_search_minimum_should_match").unwrap();
+        let body_field = store.add_field("body".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        for &(key, ref words) in [("one", vec!["quick"]), ("two", vec!["quick", "brown"]), ("three", vec!["quick", "brown", "fox"])].iter() {
+            let mut indexed_fields = FnvHashMap::default();
+            indexed_fields.insert(body_field, words.iter().enumerate().map(|(position, word)| Token { term: Term::from_string(word), position: position as u32 + 1 }).collect::<Vec<_>>().into());
+
+            store.insert_or_update_document(&Document {
+                key: key.to_string(),
+                indexed_fields: indexed_fields,
+                stored_fields: FnvHashMap::default(),
+                nested_documents: Vec::new(),
+            }).unwrap();
+        }
+
+        let index_reader = store.reader();
+        let queries = vec![
+            Query::term(body_field, Term::from_string("quick")),
+            Query::term(body_field, Term::from_string("brown")),
+            Query::term(body_field, Term::from_string("fox")),
+        ];
+
+        // Count the documents that have at least this many of the words
+        let search = |minimum| {
+            let mut collector = TopScoreCollector::new(10);
+            index_reader.search(&mut collector, &Query::minimum_should_match(queries.clone(), minimum)).unwrap();
+            collector.into_sorted_vec().len()
+        };
+
+        assert_eq!(search(1), 3);
+        assert_eq!(search(2), 2);
+        assert_eq!(search(3), 1);
+        assert_eq!(search(4), 0);
+    }
+
+    #[test]
+    fn test_search_allow_partial() {
+        remove_dir_all_ignore_error("test_indices/test_search_allow_partial");
+
+        let mut store = RocksDBStore::create("test_indices/test_search_allow_partial").unwrap();
+        let body_field = store.add_field("body".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        // Each document is in its own segment
+        for key in vec!["first", "second"] {
+            let mut indexed_fields = FnvHashMap::default();
+            indexed_fields.insert(body_field, vec![Token { term: Term::from_string("lorem"), position: 1 }].into());
+
+            store.insert_or_update_document(&Document {
+                key: key.to_string(),
+                indexed_fields: indexed_fields,
+                stored_fields: FnvHashMap::default(),
+                nested_documents: Vec::new(),
+            }).unwrap();
+        }
+
+        let query = Query::term(body_field, Term::from_string("lorem"));
+        let first_segment = {
+            let mut collector = DocIdSetCollector::new();
+            store.reader().search(&mut collector, &query).unwrap();
+            (collector.doc_ids()[0].0).0
+        };
+
+        // Corrupt the postings list of the first segment
+        let term_id = store.term_dictionary.get(&Term::from_string("lorem")).unwrap();
+        store.db.put(&KeyBuilder::segment_postings_list(first_segment, body_field.0, term_id.0).key(), b"corrupt").unwrap();
+
+        let index_reader = store.reader();
+
+        let mut collector = IndexOrderCollector::new(10);
+        assert!(index_reader.search(&mut collector, &query).is_err());
+
+        // The other segment can still be searched
+        let mut collector = IndexOrderCollector::new(10);
+        let (num_segments, failures) = index_reader.search_allow_partial(&mut collector, &query);
+        assert_eq!(num_segments, 2);
+        assert_eq!(failures.iter().map(|failure| failure.segment).collect::<Vec<_>>(), vec![SegmentId(first_segment)]);
+        assert_eq!(collector.get_total_count(), 1);
+    }
+
+    #[test]
+    fn test_insert_document_append_only() {
+        remove_dir_all_ignore_error("test_indices/test_insert_document_append_only");
+
+        let store = make_test_store("test_indices/test_insert_document_append_only");
+        let title_field = store.schema.get_field_by_name("title").unwrap();
+
+        // Documents with the same key don't replace each other
+        for _ in 0..2 {
+            let mut indexed_fields = FnvHashMap::default();
+            indexed_fields.insert(title_field, vec![Token { term: Term::from_string("log"), position: 1 }].into());
+
+            store.insert_document_append_only(&Document {
+                key: "log_line".to_string(),
+                indexed_fields: indexed_fields,
+                stored_fields: FnvHashMap::default(),
+                nested_documents: Vec::new(),
+            }).unwrap();
+        }
+
+        let index_reader = store.reader();
+        let mut collector = IndexOrderCollector::new(10);
+        index_reader.search(&mut collector, &Query::term(title_field, Term::from_string("log"))).unwrap();
+        assert_eq!(collector.get_total_count(), 2);
+
+        // They aren't in the document index
+        assert!(!index_reader.contains_document_key("log_line"));
+        assert!(index_reader.contains_document_key("test_doc"));
+    }
+
+    #[test]
+    fn test_read_document_key() {
+        remove_dir_all_ignore_error("test_indices/test_read_document_key");
+
+        // The documents were merged into a new segment, their keys must follow them
+        let store = make_test_store("test_indices/test_read_document_key");
+        let index_reader = store.reader();
+
+        for key in vec!["test_doc", "another_test_doc"] {
+            let doc_id = index_reader.get_document_id_by_key(key).unwrap();
+            assert_ne!((doc_id.0).0, 1);
+            assert_ne!((doc_id.0).0, 2);
+            assert_eq!(index_reader.read_document_key(doc_id).unwrap(), Some(key.to_string()));
+        }
+
+        // The keys of the old segments were purged
+        assert_eq!(index_reader.read_document_key(DocId(SegmentId(1), 0)).unwrap(), None);
+    }
+
+    #[test]
+    fn test_open_without_document_keys() {
+        remove_dir_all_ignore_error("test_indices/test_open_without_document_keys");
+
+        // Stores created before segments had document keys have no marker
+        {
+            let store = make_test_store("test_indices/test_open_without_document_keys");
+            for key in vec!["test_doc", "another_test_doc"] {
+                let doc_id = store.reader().get_document_id_by_key(key).unwrap();
+                store.db.delete(&KeyBuilder::segment_document_key((doc_id.0).0, doc_id.1).key()).unwrap();
+            }
+            store.db.delete(DOCUMENT_KEYS_MARKER).unwrap();
+        }
+
+        // The keys are backfilled from the primary key index
+        let store = RocksDBStore::open("test_indices/test_open_without_document_keys").unwrap();
+        assert!(store.db.get(DOCUMENT_KEYS_MARKER).unwrap().is_some());
+
+        let index_reader = store.reader();
+        for key in vec!["test_doc", "another_test_doc"] {
+            let doc_id = index_reader.get_document_id_by_key(key).unwrap();
+            assert_eq!(index_reader.read_document_key(doc_id).unwrap(), Some(key.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_merge_segments_with_invalid_key() {
+        remove_dir_all_ignore_error("test_indices/test_merge_segments_with_invalid_key");
+
+        let store = make_test_store("test_indices/test_merge_segments_with_invalid_key");
+        store.db.put(b"d1/x/3", b"").unwrap();
+
+        match store.merge_segments(&vec![3]) {
+            Err(SegmentMergeError::InvalidKey(key)) => assert_eq!(key, b"d1/x/3".to_vec()),
+            result => panic!("unexpected result {:?}", result),
+        }
+
+        match store.purge_segments(&vec![3]) {
+            Err(SegmentMergeError::InvalidKey(key)) => assert_eq!(key, b"d1/x/3".to_vec()),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_search_rank_feature() {
+        remove_dir_all_ignore_error("test_indices/test_search_rank_feature");
+
+        let mut store = RocksDBStore::create("test_indices/test_search_rank_feature").unwrap();
+        let pagerank_field = store.add_field("pagerank".to_string(), FieldType::PlainString, FIELD_INDEXED).unwrap();
+
+        for (key, pagerank) in vec![("low", 2.0f32), ("high", 8.0f32)] {
+            let mut indexed_fields = FnvHashMap::default();
+            indexed_fields.insert(pagerank_field, vec![Token { term: rank_feature_term("", pagerank), position: 1 }].into());
+
+            store.insert_or_update_document(&Document {
+                key: key.to_string(),
+                indexed_fields: indexed_fields,
+                stored_fields: FnvHashMap::default(),
+                nested_documents: Vec::new(),
+            }).unwrap();
+        }
+
+        let index_reader = store.reader();
+        let query = Query::RankFeature {
+            field: pagerank_field,
+            feature: "".to_string(),
+            function: RankFeatureFunction::Saturation { pivot: None },
+            boost: 1.0f32,
+        };
+
+        let mut collector = TopScoreCollector::new(10);
+        index_reader.search(&mut collector, &query).unwrap();
+
+        // The pivot defaults to the geometric mean of the values, which is 4
+        let docs = collector.into_sorted_vec();
+        assert_eq!(docs.len(), 2);
+        assert_eq!(docs[0].score(), Some(8.0f32 / 12.0f32));
+        assert_eq!(docs[1].score(), Some(2.0f32 / 6.0f32));
+    }
+
+    #[test]
+    fn test_search_nested() {
+        remove_dir_all_ignore_error("test_indices/test_search_nested");
+
+        let mut store = RocksDBStore::create("test_indices/test_search_nested").unwrap();
+        let comments_field = store.add_field("comments".to_string(), FieldType::Nested, FIELD_INDEXED).unwrap();
+        let author_field = store.add_field("comments.author".to_string(), FieldType::PlainString, FIELD_INDEXED).unwrap();
+        let rating_field = store.add_field("comments.rating".to_string(), FieldType::PlainString, FIELD_INDEXED).unwrap();
+
+        let insert = |key: &str, comments: &[(&str, &str)]| {
+            let nested_documents = comments.iter().map(|&(author, rating)| {
+                let mut indexed_fields = FnvHashMap::default();
+                indexed_fields.insert(comments_field, nested_document_marker());
+                indexed_fields.insert(author_field, vec![Token { term: Term::from_string(author), position: 1 }].into());
+                indexed_fields.insert(rating_field, vec![Token { term: Term::from_string(rating), position: 1 }].into());
+
+                Document {
+                    key: key.to_string(),
+                    indexed_fields: indexed_fields,
+                    stored_fields: FnvHashMap::default(),
+                    nested_documents: Vec::new(),
+                }
+            }).collect();
+
+            store.insert_or_update_document(&Document {
+                key: key.to_string(),
+                indexed_fields: FnvHashMap::default(),
+                stored_fields: FnvHashMap::default(),
+                nested_documents: nested_documents,
+            }).unwrap();
+        };
+
+        insert("first", &[("alice", "good"), ("bob", "bad")]);
+        insert("second", &[("alice", "bad"), ("bob", "good")]);
+        insert("third", &[]);
+
+        let term = |field, term: &str| Query::term(field, Term::from_string(term));
+        let nested = |query: Query, score_mode: NestedScoreMode| Query::Nested {
+            path: comments_field,
+            query: Box::new(query),
+            score_mode: score_mode,
+        };
+
+        let search = |query: &Query| {
+            let index_reader = store.reader();
+            let mut collector = TopScoreCollector::new(10);
+            index_reader.search(&mut collector, query).unwrap();
+            collector.into_sorted_vec().iter().map(|doc| (doc.doc_id(), doc.score().unwrap())).collect::<Vec<_>>()
+        };
+
+        // Nested documents are hidden from other queries
+        assert_eq!(search(&Query::All { score: 1.0f32 }).len(), 3);
+
+        // Both terms must be in the same nested document
+        let alice_good = Query::Conjunction {
+            queries: vec![term(author_field, "alice"), term(rating_field, "good")],
+        };
+        let first_doc_id = store.reader().get_document_id_by_key("first").unwrap();
+        assert_eq!(search(&nested(alice_good, NestedScoreMode::Avg)).iter().map(|&(doc_id, _)| doc_id).collect::<Vec<_>>(), vec![first_doc_id.as_u64()]);
+
+        // The scores of the nested documents are combined
+        let all_comments = |score_mode| nested(Query::All { score: 2.0f32 }, score_mode);
+        assert_eq!(search(&all_comments(NestedScoreMode::Sum)).iter().map(|&(_, score)| score).collect::<Vec<_>>(), vec![4.0f32, 4.0f32]);
+        assert_eq!(search(&all_comments(NestedScoreMode::Avg)).iter().map(|&(_, score)| score).collect::<Vec<_>>(), vec![2.0f32, 2.0f32]);
+
+        let index_reader = store.reader();
+        assert_eq!(index_reader.get_nested_documents(first_doc_id, comments_field).unwrap().len(), 2);
+
+        // Nested documents can be searched directly
+        let mut collector = DocIdSetCollector::new();
+        index_reader.search_nested(&mut collector, comments_field, &term(rating_field, "good")).unwrap();
+        assert_eq!(collector.len(), 2);
+
+        let first_comments = index_reader.get_nested_documents(first_doc_id, comments_field).unwrap();
+        assert!(collector.contains(first_comments[0]));
+        assert!(!collector.contains(first_comments[1]));
+
+        let mut collector = DocIdSetCollector::new();
+        assert!(index_reader.search_nested_allow_partial(&mut collector, comments_field, &term(rating_field, "good")).is_empty());
+        assert_eq!(collector.len(), 2);
+    }
+
+    #[test]
+    fn test_change_log() {
+        remove_dir_all_ignore_error("test_indices/test_change_log");
+
+        {
+            let store = make_test_store("test_indices/test_change_log");
+            assert_eq!(store.remove_document_by_key("test_doc").unwrap(), true);
+
+            // Deleting a document that doesn't exist isn't recorded
+            assert_eq!(store.remove_document_by_key("test_doc").unwrap(), false);
+
+            let index_reader = store.reader();
+            assert_eq!(index_reader.max_seq_no(), 3);
+            assert_eq!(index_reader.get_document_seq_no("test_doc").unwrap(), None);
+            assert_eq!(index_reader.get_document_seq_no("another_test_doc").unwrap(), Some(2));
+
+            let changes = index_reader.changes_since(1, 10).unwrap().into_iter()
+                .map(|change| (change.seq_no, change.operation, change.key))
+                .collect::<Vec<_>>();
+            assert_eq!(changes, vec![
+                (2, ChangeOperation::Index, "another_test_doc".to_string()),
+                (3, ChangeOperation::Delete, "test_doc".to_string()),
+            ]);
+
+            assert_eq!(index_reader.changes_since(0, 1).unwrap().len(), 1);
+
+            // Entries are in time order as well as sequence number order
+            let times = index_reader.changes_since(0, 10).unwrap().into_iter().map(|change| change.time).collect::<Vec<_>>();
+            assert!(times.windows(2).all(|pair| pair[0] <= pair[1]));
+        }
+
+        // Sequence numbers carry on from where they left off after reopening the store
+        let store = RocksDBStore::open("test_indices/test_change_log").unwrap();
+        assert_eq!(store.remove_document_by_key("another_test_doc").unwrap(), true);
+        assert_eq!(store.reader().max_seq_no(), 4);
+    }
+
+    #[test]
+    fn test_trim_change_log() {
+        remove_dir_all_ignore_error("test_indices/test_trim_change_log");
+
+        let store = make_test_store("test_indices/test_trim_change_log");
+        let first_change_time = store.reader().changes_since(0, 1).unwrap()[0].time;
+        assert_eq!(store.reader().min_retained_seq_no(), 1);
+
+        // Nothing was recorded before the first change
+        assert_eq!(store.trim_change_log(first_change_time).unwrap(), 0);
+
+        // Trim everything
+        assert_eq!(store.trim_change_log(Utc::now() + Duration::seconds(1)).unwrap(), 2);
+
+        let index_reader = store.reader();
+        assert_eq!(index_reader.changes_since(0, 10).unwrap(), vec![]);
+        assert_eq!(index_reader.min_retained_seq_no(), 3);
+
+        // Trimming doesn't affect the sequence numbers of documents
+        assert_eq!(index_reader.get_document_seq_no("test_doc").unwrap(), Some(1));
+    }
 }
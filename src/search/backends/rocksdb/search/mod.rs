@@ -1,18 +1,47 @@
 mod statistics;
 mod planner;
 
+use std::collections::HashMap;
+
 use roaring::RoaringBitmap;
-use search::segment::Segment;
+use serde_json;
+use search::segment::{Segment, SegmentFailure};
+use search::schema::FieldId;
+use search::term::TermId;
+use search::document::DocId;
 use search::query::Query;
+use search::query::term_scorer::TermScorer;
+use search::query::wildcard::VALUE_SEPARATOR;
+use search::query::geo_shape::Geometry;
+use search::query::geo_distance::stored_point_distance;
+use search::query::phrase::phrase_slop;
+use search::query::function_score::{ScoreFunction, random_score, stored_numeric_value};
+use search::query::distance_feature::distance_feature_score;
+use search::query::nested::{nested_parents, nested_children};
+use search::query::script::stored_script_value;
 use search::collectors::{Collector, DocumentMatch};
+use script::ScriptValue;
 use byteorder::{ByteOrder, LittleEndian};
 
 use super::RocksDBReader;
+use super::segment::RocksDBSegment;
 use self::statistics::{StatisticsReader, RocksDBStatisticsReader};
-use self::planner::{SearchPlan, plan_query};
-use self::planner::boolean_query::BooleanQueryOp;
+use self::planner::{SearchPlan, plan_query, plan_nested_query};
+use self::planner::boolean_query::{BooleanQueryOp, nested_document_markers};
 use self::planner::score_function::{CombinatorScorer, ScoreFunctionOp};
 
+/// Loads the documents nested within a path, from the markers of the path and of the nested paths inside it
+fn load_nested_documents<S: Segment>(markers: &[(FieldId, TermId)], segment: &S) -> Result<RoaringBitmap, String> {
+    let mut nested_docs = RoaringBitmap::new();
+    for &(field_id, term_id) in markers.iter() {
+        if let Some(postings) = segment.load_postings_list(field_id, term_id)? {
+            nested_docs.union_with(&postings);
+        }
+    }
+
+    Ok(nested_docs)
+}
+
 fn run_boolean_query<S: Segment>(boolean_query: &Vec<BooleanQueryOp>, is_negated: bool, segment: &S) -> Result<RoaringBitmap, String> {
     // Execute boolean query
     let mut stack = Vec::new();
@@ -27,12 +56,185 @@ fn run_boolean_query<S: Segment>(boolean_query: &Vec<BooleanQueryOp>, is_negated
                     None => stack.push(RoaringBitmap::new()),
                 }
             }
+            BooleanQueryOp::PushPostingsListUnion(field_id, ref term_ids) => {
+                let mut doc_id_set = RoaringBitmap::new();
+                for term_id in term_ids.iter() {
+                    if let Some(postings) = try!(segment.load_postings_list(field_id, *term_id)) {
+                        doc_id_set.union_with(&postings);
+                    }
+                }
+
+                stack.push(doc_id_set);
+            }
             BooleanQueryOp::PushDeletionList => {
                     match try!(segment.load_deletion_list()) {
                     Some(doc_id_set) => stack.push(doc_id_set),
                     None => stack.push(RoaringBitmap::new()),
                 }
             }
+            BooleanQueryOp::PushDocIds(ref doc_ids) => {
+                let mut doc_id_set = RoaringBitmap::new();
+                for doc_id in doc_ids.iter().filter(|doc_id| doc_id.0 == segment.id()) {
+                    doc_id_set.insert(doc_id.1 as u32);
+                }
+
+                stack.push(doc_id_set);
+            }
+            BooleanQueryOp::FilterWildcard(field_id, ref pattern) => {
+                let candidates = stack.pop().expect("boolean query executor: stack underflow");
+                let mut matches = RoaringBitmap::new();
+
+                for doc_id in candidates.iter() {
+                    let value = match segment.load_stored_field_value_raw(doc_id as u16, field_id, b"val")? {
+                        Some(value) => value,
+                        None => continue,
+                    };
+
+                    let value = String::from_utf8_lossy(&value);
+                    if value.split(VALUE_SEPARATOR).any(|value| pattern.matches(value)) {
+                        matches.insert(doc_id);
+                    }
+                }
+
+                stack.push(matches);
+            }
+            BooleanQueryOp::FilterGeoShape(field_id, ref shape, relation, system) => {
+                let candidates = stack.pop().expect("boolean query executor: stack underflow");
+                let mut matches = RoaringBitmap::new();
+
+                for doc_id in candidates.iter() {
+                    let value = match segment.load_stored_field_value_raw(doc_id as u16, field_id, b"val")? {
+                        Some(value) => value,
+                        None => continue,
+                    };
+
+                    let stored_shape = serde_json::from_slice(&value).ok().and_then(|json| Geometry::from_json(&json, system));
+                    if let Some(stored_shape) = stored_shape {
+                        if stored_shape.relates(shape, relation) {
+                            matches.insert(doc_id);
+                        }
+                    }
+                }
+
+                stack.push(matches);
+            }
+            BooleanQueryOp::FilterGeoDistance(field_id, origin, distance) => {
+                let candidates = stack.pop().expect("boolean query executor: stack underflow");
+                let mut matches = RoaringBitmap::new();
+
+                for doc_id in candidates.iter() {
+                    let value = match segment.load_stored_field_value_raw(doc_id as u16, field_id, b"val")? {
+                        Some(value) => value,
+                        None => continue,
+                    };
+
+                    if stored_point_distance(origin, &value).map_or(false, |point_distance| point_distance <= distance) {
+                        matches.insert(doc_id);
+                    }
+                }
+
+                stack.push(matches);
+            }
+            BooleanQueryOp::FilterPhrase(field_id, ref term_ids, slop) => {
+                let candidates = stack.pop().expect("boolean query executor: stack underflow");
+                let mut matches = RoaringBitmap::new();
+
+                for doc_id in candidates.iter() {
+                    let mut positions = Vec::with_capacity(term_ids.len());
+                    for term_id in term_ids.iter() {
+                        positions.push(segment.load_term_positions(doc_id as u16, field_id, *term_id)?.iter().collect::<Vec<_>>());
+                    }
+
+                    match phrase_slop(&positions) {
+                        Some(phrase_slop) if phrase_slop <= slop => {
+                            matches.insert(doc_id);
+                        }
+                        _ => {}
+                    }
+                }
+
+                stack.push(matches);
+            }
+            BooleanQueryOp::FilterIntervals(field_id, ref source) => {
+                let candidates = stack.pop().expect("boolean query executor: stack underflow");
+                let mut matches = RoaringBitmap::new();
+
+                // Positions are only stored for documents that contain the term
+                let mut postings_lists = Vec::new();
+                for term_id in source.terms().into_iter().filter_map(|term_id| *term_id) {
+                    if let Some(postings) = segment.load_postings_list(field_id, term_id)? {
+                        postings_lists.push((term_id, postings));
+                    }
+                }
+
+                for doc_id in candidates.iter() {
+                    let mut positions = Vec::with_capacity(postings_lists.len());
+                    for &(term_id, ref postings) in postings_lists.iter() {
+                        if postings.contains(doc_id) {
+                            positions.push((term_id, segment.load_term_positions(doc_id as u16, field_id, term_id)?.iter().collect::<Vec<_>>()));
+                        }
+                    }
+
+                    let intervals = source.intervals(&|term_id: &Option<TermId>| {
+                        positions.iter().find(|&&(position_term_id, _)| Some(position_term_id) == *term_id).map_or_else(Vec::new, |&(_, ref term_positions)| term_positions.clone())
+                    });
+
+                    if !intervals.is_empty() {
+                        matches.insert(doc_id);
+                    }
+                }
+
+                stack.push(matches);
+            }
+            BooleanQueryOp::FilterScript(ref fields, ref script, ref params) => {
+                let candidates = stack.pop().expect("boolean query executor: stack underflow");
+                let mut matches = RoaringBitmap::new();
+
+                for doc_id in candidates.iter() {
+                    let mut values = HashMap::with_capacity(fields.len());
+                    for &(ref name, field_id, ref field_type) in fields.iter() {
+                        if let Some(value) = segment.load_stored_field_value_raw(doc_id as u16, field_id, b"val")?.and_then(|value| stored_script_value(field_type, &value)) {
+                            values.insert(name.as_str(), value);
+                        }
+                    }
+
+                    let variables = |name: &str| {
+                        if name.starts_with("doc.") {
+                            values.get(&name["doc.".len()..]).cloned()
+                        } else {
+                            params.get(name).cloned()
+                        }
+                    };
+
+                    // Scripts that fail, such as ones that read a field without a stored value, don't match
+                    if script.evaluate(&variables) == Ok(ScriptValue::Boolean(true)) {
+                        matches.insert(doc_id);
+                    }
+                }
+
+                stack.push(matches);
+            }
+            BooleanQueryOp::FilterMinimumShouldMatch(ref queries, minimum) => {
+                let candidates = stack.pop().expect("boolean query executor: stack underflow");
+
+                let mut query_matches = Vec::with_capacity(queries.len());
+                for &(ref boolean_query, is_negated) in queries.iter() {
+                    query_matches.push(run_boolean_query(boolean_query, is_negated, segment)?);
+                }
+
+                let mut matches = RoaringBitmap::new();
+                for doc_id in candidates.iter() {
+                    if query_matches.iter().filter(|query_matches| query_matches.contains(doc_id)).count() >= minimum {
+                        matches.insert(doc_id);
+                    }
+                }
+
+                stack.push(matches);
+            }
+            BooleanQueryOp::NestedParents(ref markers) => {
+                let nested_docs = stack.pop().expect("boolean query executor: stack underflow");
+                stack.push(nested_parents(&nested_docs, &load_nested_documents(markers, segment)?));
+            }
             BooleanQueryOp::And => {
                 let b = stack.pop().expect("boolean query executor: stack underflow");
                 let a = stack.last_mut().expect("boolean query executor: stack underflow");
@@ -76,6 +278,58 @@ fn run_boolean_query<S: Segment>(boolean_query: &Vec<BooleanQueryOp>, is_negated
     Ok(matches)
 }
 
+/// Scores a term in a field of the document, returns None if the field doesn't have the term
+fn score_term<S: Segment, R: StatisticsReader>(doc_id: u16, field_id: FieldId, term_id: TermId, scorer: &TermScorer, term_document_frequency: i64, segment: &S, stats: &mut R) -> Result<Option<f32>, String> {
+    // TODO: Check this isn't really slow
+    match try!(segment.load_postings_list(field_id, term_id)) {
+        Some(postings) => {
+            if !postings.contains(doc_id as u32) {
+                return Ok(None);
+            }
+
+            // Read field length
+            // TODO: we only need this for BM25
+            let field_length_raw = try!(segment.load_stored_field_value_raw(doc_id, field_id, b"len"));
+            let field_length = match field_length_raw {
+                Some(value) => {
+                    let length_sqrt = (value[0] as f32) / 3.0 + 1.0;
+                    length_sqrt * length_sqrt
+                }
+                None => 1.0
+            };
+
+            // Read term frequency
+            let mut value_type = vec![b't', b'f'];
+            value_type.extend(term_id.0.to_string().as_bytes());
+            let term_frequency_raw = try!(segment.load_stored_field_value_raw(doc_id, field_id, &value_type));
+            let term_frequency = match term_frequency_raw {
+                Some(value) => LittleEndian::read_i64(&value),
+                None => 1,
+            };
+
+            let score = scorer.similarity_model.score(term_frequency as u32, field_length, try!(stats.total_tokens(field_id)) as u64, try!(stats.total_docs(field_id)) as u64, term_document_frequency as u64);
+            Ok(Some(score * scorer.boost))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Takes the highest of the scores and adds the rest of them multiplied by the tie breaker
+fn combine_max_with_tie_breaker(scores: Vec<f32>, tie_breaker: f32) -> f32 {
+    let mut max_score = 0.0f32;
+    let mut total_score = 0.0f32;
+
+    for score in scores {
+        if score > max_score {
+            max_score = score;
+        }
+
+        total_score += score;
+    }
+
+    max_score + (total_score - max_score) * tie_breaker
+}
+
 fn score_doc<S: Segment, R: StatisticsReader>(doc_id: u16, score_function: &Vec<ScoreFunctionOp>, segment: &S, stats: &mut R) -> Result<f32, String> {
     // Execute score function
     let mut stack = Vec::new();
@@ -83,38 +337,115 @@ fn score_doc<S: Segment, R: StatisticsReader>(doc_id: u16, score_function: &Vec<
         match *op {
             ScoreFunctionOp::Literal(val) => stack.push(val),
             ScoreFunctionOp::TermScorer(field_id, term_id, ref scorer) => {
-                // TODO: Check this isn't really slow
-                match try!(segment.load_postings_list(field_id, term_id)) {
-                    Some(postings) => {
+                let term_document_frequency = try!(stats.term_document_frequency(field_id, term_id));
+                let score = try!(score_term(doc_id, field_id, term_id, scorer, term_document_frequency, segment, stats));
+                stack.push(score.unwrap_or(0.0f32));
+            }
+            ScoreFunctionOp::BlendedTermScorer(ref fields, term_id, tie_breaker, ref scorer) => {
+                // Blend the statistics of the fields by taking the highest document frequency
+                let mut term_document_frequency = 0;
+                for &(field_id, _) in fields.iter() {
+                    term_document_frequency = term_document_frequency.max(try!(stats.term_document_frequency(field_id, term_id)));
+                }
+
+                let mut field_scores = Vec::new();
+                for &(field_id, field_boost) in fields.iter() {
+                    if let Some(score) = try!(score_term(doc_id, field_id, term_id, scorer, term_document_frequency, segment, stats)) {
+                        field_scores.push(score * field_boost);
+                    }
+                }
+
+                stack.push(combine_max_with_tie_breaker(field_scores, tie_breaker));
+            }
+            ScoreFunctionOp::RankFeature(field_id, ref terms, ref function, boost) => {
+                let mut score = 0.0f32;
+
+                for &(term_id, value) in terms.iter() {
+                    if let Some(postings) = segment.load_postings_list(field_id, term_id)? {
                         if postings.contains(doc_id as u32) {
-                            // Read field length
-                            // TODO: we only need this for BM25
-                            let field_length_raw = try!(segment.load_stored_field_value_raw(doc_id, field_id, b"len"));
-                            let field_length = match field_length_raw {
-                                Some(value) => {
-                                    let length_sqrt = (value[0] as f32) / 3.0 + 1.0;
-                                    length_sqrt * length_sqrt
-                                }
-                                None => 1.0
-                            };
+                            score = function.score(value) * boost;
+                            break;
+                        }
+                    }
+                }
 
-                            // Read term frequency
-                            let mut value_type = vec![b't', b'f'];
-                            value_type.extend(term_id.0.to_string().as_bytes());
-                            let term_frequency_raw = try!(segment.load_stored_field_value_raw(doc_id, field_id, &value_type));
-                            let term_frequency = match term_frequency_raw {
-                                Some(value) => LittleEndian::read_i64(&value),
-                                None => 1,
+                stack.push(score);
+            }
+            ScoreFunctionOp::DistanceFeature(field_id, ref origin, pivot, boost) => {
+                let distance = match segment.load_stored_field_value_raw(doc_id, field_id, b"val")? {
+                    Some(value) => origin.distance(&value),
+                    None => None,
+                };
+
+                match distance {
+                    Some(distance) => stack.push(distance_feature_score(distance, pivot) * boost),
+                    None => stack.push(0.0f32),
+                }
+            }
+            ScoreFunctionOp::FunctionScore(ref functions, score_mode, boost_mode, max_boost, boost) => {
+                let query_score = stack.pop().expect("document scorer: stack underflow");
+
+                let mut function_scores = Vec::with_capacity(functions.len());
+                for function in functions.iter() {
+                    if let Some((ref boolean_query, is_negated)) = function.filter {
+                        if !run_boolean_query(boolean_query, is_negated, segment)?.contains(doc_id as u32) {
+                            continue;
+                        }
+                    }
+
+                    let score = match function.function {
+                        ScoreFunction::Weight => 1.0f32,
+                        ScoreFunction::RandomScore{seed, field} => {
+                            let value = match field {
+                                Some(field_id) => segment.load_stored_field_value_raw(doc_id, field_id, b"val")?,
+                                None => None,
                             };
 
-                            let score = scorer.similarity_model.score(term_frequency as u32, field_length, try!(stats.total_tokens(field_id)) as u64, try!(stats.total_docs(field_id)) as u64, try!(stats.term_document_frequency(field_id, term_id)) as u64);
-                            stack.push(score * scorer.boost);
-                        } else {
-                            stack.push(0.0f32);
+                            match value {
+                                Some(value) => random_score(seed, &value),
+                                None => {
+                                    let mut doc_id_bytes = [0; 8];
+                                    LittleEndian::write_u64(&mut doc_id_bytes, segment.doc_id(doc_id).as_u64());
+                                    random_score(seed, &doc_id_bytes)
+                                }
+                            }
                         }
+                        ScoreFunction::FieldValueFactor{field, factor, modifier, missing} => {
+                            let value = segment.load_stored_field_value_raw(doc_id, field, b"val")?.and_then(|value| stored_numeric_value(&value));
+
+                            match value.or(missing) {
+                                Some(value) => modifier.apply(value * factor) as f32,
+                                None => continue,
+                            }
+                        }
+                        ScoreFunction::Decay{field, function, ref origin, scale, offset, decay} => {
+                            let distance = segment.load_stored_field_value_raw(doc_id, field, b"val")?.and_then(|value| origin.distance(&value));
+
+                            match distance {
+                                Some(distance) => function.score(distance, scale, offset, decay),
+                                None => 1.0f32,
+                            }
+                        }
+                    };
+
+                    function_scores.push(score * function.weight);
+                }
+
+                let function_score = score_mode.combine(&function_scores).min(max_boost);
+                stack.push(boost_mode.combine(query_score, function_score) * boost);
+            }
+            ScoreFunctionOp::Nested(ref nested_query) => {
+                let (ref boolean_query, is_negated) = nested_query.boolean_query;
+                let matches = run_boolean_query(boolean_query, is_negated, segment)?;
+
+                let mut nested_scores = Vec::new();
+                for nested_doc_id in nested_children(doc_id as u32, &load_nested_documents(&nested_query.markers, segment)?) {
+                    if matches.contains(nested_doc_id) {
+                        nested_scores.push(score_doc(nested_doc_id as u16, &nested_query.score_function, segment, stats)?);
                     }
-                    None => stack.push(0.0f32),
                 }
+
+                stack.push(nested_query.score_mode.combine(&nested_scores));
             }
             ScoreFunctionOp::CombinatorScorer(num_vals, ref scorer) => {
                 let score = match *scorer {
@@ -127,17 +458,14 @@ fn score_doc<S: Segment, R: StatisticsReader>(doc_id: u16, score_function: &Vec<
 
                         total_score / num_vals as f32
                     }
-                    CombinatorScorer::Max => {
-                        let mut max_score = 0.0f32;
+                    CombinatorScorer::Max(tie_breaker) => {
+                        let mut scores = Vec::with_capacity(num_vals as usize);
 
                         for _ in 0..num_vals {
-                            let score = stack.pop().expect("document scorer: stack underflow");
-                            if score > max_score {
-                                max_score = score
-                            }
+                            scores.push(stack.pop().expect("document scorer: stack underflow"));
                         }
 
-                        max_score
+                        combine_max_with_tie_breaker(scores, tie_breaker)
                     }
                 };
 
@@ -157,15 +485,38 @@ fn score_doc<S: Segment, R: StatisticsReader>(doc_id: u16, score_function: &Vec<
 fn search_segment<C: Collector, S: Segment, R: StatisticsReader>(collector: &mut C, plan: &SearchPlan, segment: &S, stats: &mut R) -> Result<(), String> {
     let matches = try!(run_boolean_query(&plan.boolean_query, plan.boolean_query_is_negated, segment));
 
-    // Score documents and pass to collector
-    for doc in matches.iter() {
-        let score = try!(score_doc(doc as u16, &plan.score_function, segment, stats));
+    // Stop early if the collector doesn't need all of the matches
+    let num_matches = matches.len() as usize;
+    let num_collected = match collector.segment_limit() {
+        Some(limit) if limit < num_matches => limit,
+        _ => num_matches,
+    };
 
+    // Score documents and pass to collector
+    // All of the documents are scored first so a segment that fails part way
+    // through doesn't leave some of its documents in the collector
+    let mut doc_matches = Vec::with_capacity(num_collected);
+    for doc in matches.iter().take(num_collected) {
         let doc_id = segment.doc_id(doc as u16);
-        let doc_match = DocumentMatch::new_scored(doc_id.as_u64(), score);
+
+        let doc_match = if collector.needs_score() {
+            let score = try!(score_doc(doc as u16, &plan.score_function, segment, stats));
+            DocumentMatch::new_scored(doc_id.as_u64(), score)
+        } else {
+            DocumentMatch::new_unscored(doc_id.as_u64())
+        };
+
+        doc_matches.push(doc_match);
+    }
+
+    for doc_match in doc_matches {
         collector.collect(doc_match);
     }
 
+    if num_collected < num_matches {
+        collector.skip((num_matches - num_collected) as u64);
+    }
+
     Ok(())
 }
 
@@ -179,9 +530,87 @@ impl<'a> RocksDBReader<'a> {
 
         // Run query on each segment
         for segment in self.store.segments.iter_active(&self) {
+            if collector.is_finished() {
+                break;
+            }
+
             try!(search_segment(collector, &plan, &segment, &mut stats));
         }
 
         Ok(())
     }
+
+    /// Runs a query like `search`, but carries on to the other segments when
+    /// one of them can't be searched. Returns the number of segments that were
+    /// searched along with the ones that failed
+    pub fn search_allow_partial<C: Collector>(&self, collector: &mut C, query: &Query) -> (usize, Vec<SegmentFailure>) {
+        let plan = plan_query(&self, query, collector.needs_score());
+        let mut stats = RocksDBStatisticsReader::new(&self);
+
+        let mut num_segments = 0;
+        let mut failures = Vec::new();
+        for segment in self.store.segments.iter_active(&self) {
+            num_segments += 1;
+
+            // Segments that are skipped still count as searched
+            if collector.is_finished() {
+                continue;
+            }
+
+            if let Err(reason) = search_segment(collector, &plan, &segment, &mut stats) {
+                failures.push(SegmentFailure {
+                    segment: segment.id(),
+                    reason: reason,
+                });
+            }
+        }
+
+        (num_segments, failures)
+    }
+
+    /// Runs a query against the nested documents in the path rather than the top level documents
+    pub fn search_nested<C: Collector>(&self, collector: &mut C, path: FieldId, query: &Query) -> Result<(), String> {
+        let plan = plan_nested_query(&self, path, query, collector.needs_score());
+        let mut stats = RocksDBStatisticsReader::new(&self);
+
+        for segment in self.store.segments.iter_active(&self) {
+            search_segment(collector, &plan, &segment, &mut stats)?;
+        }
+
+        Ok(())
+    }
+
+    /// Like search_nested, but carries on past segments that fail and returns them
+    pub fn search_nested_allow_partial<C: Collector>(&self, collector: &mut C, path: FieldId, query: &Query) -> Vec<SegmentFailure> {
+        let plan = plan_nested_query(&self, path, query, collector.needs_score());
+        let mut stats = RocksDBStatisticsReader::new(&self);
+
+        let mut failures = Vec::new();
+        for segment in self.store.segments.iter_active(&self) {
+            if let Err(reason) = search_segment(collector, &plan, &segment, &mut stats) {
+                failures.push(SegmentFailure {
+                    segment: segment.id(),
+                    reason: reason,
+                });
+            }
+        }
+
+        failures
+    }
+
+    /// Finds the nested documents of a document in the path, in the order of
+    /// their objects. Documents in nested paths inside it aren't included
+    pub fn get_nested_documents(&self, doc_id: DocId, path: FieldId) -> Result<Vec<DocId>, String> {
+        let segment = RocksDBSegment::new(&self, (doc_id.0).0);
+        let markers = nested_document_markers(&self, Some(path));
+        let path_markers = markers.iter().filter(|&&(field_id, _)| field_id == path).cloned().collect::<Vec<_>>();
+
+        let path_docs = load_nested_documents(&path_markers, &segment)?;
+        let nested_doc_ids = nested_children(doc_id.1 as u32, &load_nested_documents(&markers, &segment)?).into_iter()
+            .filter(|nested_doc_id| path_docs.contains(*nested_doc_id))
+            .map(|nested_doc_id| segment.doc_id(nested_doc_id as u16))
+            .collect();
+
+        Ok(nested_doc_ids)
+    }
 }
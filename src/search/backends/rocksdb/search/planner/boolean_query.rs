@@ -1,16 +1,46 @@
 use std::rc::Rc;
+use std::collections::HashMap;
 
-use search::schema::FieldId;
-use search::term::TermId;
+use search::schema::{FieldId, FieldType};
+use search::term::{Term, TermId};
+use search::document::DocId;
 use search::Query;
+use search::query::wildcard::WildcardPattern;
+use search::query::rank_feature::rank_feature_selector;
+use search::query::geo_shape::{Geometry, Coordinate, SpatialRelation, CoordinateSystem};
+use search::query::exists::field_exists_term;
+use search::query::intervals::IntervalsSource;
+use search::query::term_scorer::TermScorer;
+use search::query::script::script_fields;
+use script::{Expression, ScriptValue};
 
 use super::super::RocksDBReader;
+use super::super::statistics::{StatisticsReader, RocksDBStatisticsReader};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum BooleanQueryOp {
     PushEmpty,
     PushPostingsList(FieldId, TermId),
+    PushPostingsListUnion(FieldId, Vec<TermId>),
     PushDeletionList,
+    PushDocIds(Vec<DocId>),
+    FilterWildcard(FieldId, WildcardPattern),
+    FilterGeoShape(FieldId, Geometry, SpatialRelation, CoordinateSystem),
+
+    /// Checks that a stored point of each candidate is within the distance in metres of the origin
+    FilterGeoDistance(FieldId, Coordinate, f64),
+    FilterPhrase(FieldId, Vec<TermId>, u32),
+    FilterIntervals(FieldId, IntervalsSource<Option<TermId>>),
+
+    /// Runs the script on each candidate. Takes the fields that the script reads along with their names
+    FilterScript(Vec<(String, FieldId, FieldType)>, Expression, HashMap<String, ScriptValue>),
+
+    /// Keeps the candidates that match at least the minimum number of the queries
+    FilterMinimumShouldMatch(Vec<(Vec<BooleanQueryOp>, bool)>, usize),
+
+    /// Replaces nested documents with their parents. Takes the markers of the
+    /// path and of the nested paths inside it
+    NestedParents(Vec<(FieldId, TermId)>),
     And,
     Or,
     AndNot,
@@ -35,7 +65,12 @@ enum BooleanQueryBlock {
         child_a: Rc<BooleanQueryBlock>,
         child_b: Rc<BooleanQueryBlock>,
         return_type: BooleanQueryBlockReturnType,
-    }
+    },
+    Filter {
+        op: BooleanQueryOp,
+        child: Rc<BooleanQueryBlock>,
+        return_type: BooleanQueryBlockReturnType,
+    },
 }
 
 impl BooleanQueryBlock {
@@ -45,6 +80,7 @@ impl BooleanQueryBlock {
         match *self {
             Leaf{return_type, ..} => return_type,
             Combinator{return_type, ..} => return_type,
+            Filter{return_type, ..} => return_type,
         }
     }
 
@@ -54,6 +90,7 @@ impl BooleanQueryBlock {
         match *self {
             Leaf{ref mut return_type, ..} => *return_type = new_type,
             Combinator{ref mut return_type, ..} => *return_type = new_type,
+            Filter{ref mut return_type, ..} => *return_type = new_type,
         }
     }
 
@@ -69,6 +106,10 @@ impl BooleanQueryBlock {
                 child_b.build(boolean_query);
                 boolean_query.push(op.clone());
             }
+            Filter{ref op, ref child, ..} => {
+                child.build(boolean_query);
+                boolean_query.push(op.clone());
+            }
         }
     }
 }
@@ -117,6 +158,24 @@ impl BooleanQueryBuilder {
         }));
     }
 
+    /// Pushes the union of the postings lists of the terms. This is quicker than
+    /// pushing each postings list and combining them with `or_combinator`
+    pub fn push_postings_list_union(&mut self, field_id: FieldId, term_ids: Vec<TermId>) {
+        use self::BooleanQueryOp::*;
+        use self::BooleanQueryBlock::*;
+        use self::BooleanQueryBlockReturnType::*;
+
+        if term_ids.is_empty() {
+            self.push_empty();
+            return;
+        }
+
+        self.stack.push(Rc::new(Leaf{
+            op: PushPostingsListUnion(field_id, term_ids),
+            return_type: Sparse,
+        }));
+    }
+
     pub fn push_deletion_list(&mut self) {
         use self::BooleanQueryOp::*;
         use self::BooleanQueryBlock::*;
@@ -128,6 +187,78 @@ impl BooleanQueryBuilder {
         }));
     }
 
+    pub fn push_doc_ids(&mut self, doc_ids: Vec<DocId>) {
+        use self::BooleanQueryOp::*;
+        use self::BooleanQueryBlock::*;
+        use self::BooleanQueryBlockReturnType::*;
+
+        if doc_ids.is_empty() {
+            self.push_empty();
+            return;
+        }
+
+        self.stack.push(Rc::new(Leaf{
+            op: PushDocIds(doc_ids),
+            return_type: Sparse,
+        }));
+    }
+
+    /// Checks the candidates on the top of the stack with the filter operation
+    fn filter_candidates(&mut self, op: BooleanQueryOp) {
+        use self::BooleanQueryBlock::*;
+        use self::BooleanQueryBlockReturnType::*;
+
+        let a = self.stack.pop().expect("stack underflow");
+
+        match a.return_type() {
+            // Nothing to check
+            Empty => self.push_empty(),
+
+            Sparse => {
+                self.stack.push(Rc::new(Filter{
+                    op: op,
+                    child: a,
+                    return_type: Sparse,
+                }));
+            }
+
+            // Candidates always come from postings lists
+            Full | NegatedSparse => panic!("filter applied to a negated block"),
+        }
+    }
+
+    pub fn filter_wildcard(&mut self, field_id: FieldId, pattern: WildcardPattern) {
+        self.filter_candidates(BooleanQueryOp::FilterWildcard(field_id, pattern));
+    }
+
+    pub fn filter_phrase(&mut self, field_id: FieldId, term_ids: Vec<TermId>, slop: u32) {
+        self.filter_candidates(BooleanQueryOp::FilterPhrase(field_id, term_ids, slop));
+    }
+
+    pub fn filter_intervals(&mut self, field_id: FieldId, source: IntervalsSource<Option<TermId>>) {
+        self.filter_candidates(BooleanQueryOp::FilterIntervals(field_id, source));
+    }
+
+    pub fn filter_script(&mut self, fields: Vec<(String, FieldId, FieldType)>, script: Expression, params: HashMap<String, ScriptValue>) {
+        self.filter_candidates(BooleanQueryOp::FilterScript(fields, script, params));
+    }
+
+    pub fn filter_geo_shape(&mut self, field_id: FieldId, shape: Geometry, relation: SpatialRelation, system: CoordinateSystem) {
+        self.filter_candidates(BooleanQueryOp::FilterGeoShape(field_id, shape, relation, system));
+    }
+
+    pub fn filter_geo_distance(&mut self, field_id: FieldId, origin: Coordinate, distance: f64) {
+        self.filter_candidates(BooleanQueryOp::FilterGeoDistance(field_id, origin, distance));
+    }
+
+    pub fn filter_minimum_should_match(&mut self, queries: Vec<(Vec<BooleanQueryOp>, bool)>, minimum: usize) {
+        self.filter_candidates(BooleanQueryOp::FilterMinimumShouldMatch(queries, minimum));
+    }
+
+    pub fn nested_parents(&mut self, markers: Vec<(FieldId, TermId)>) {
+        self.filter_candidates(BooleanQueryOp::NestedParents(markers));
+    }
+
     pub fn and_combinator(&mut self) {
         use self::BooleanQueryOp::*;
         use self::BooleanQueryBlock::*;
@@ -353,6 +484,117 @@ fn plan_boolean_query_combinator<J: Fn(&mut BooleanQueryBuilder) -> ()> (index_r
     }
 }
 
+/// Finds the documents that could match an intervals source from the terms they contain
+fn plan_intervals_candidates(builder: &mut BooleanQueryBuilder, field: FieldId, source: &IntervalsSource<Option<TermId>>) {
+    match *source {
+        IntervalsSource::Term(Some(term_id)) => builder.push_postings_list(field, term_id),
+        IntervalsSource::Term(None) => builder.push_empty(),
+        IntervalsSource::AllOf{ref sources, ..} => {
+            if sources.is_empty() {
+                builder.push_empty();
+                return;
+            }
+
+            builder.push_full();
+            for source in sources.iter() {
+                plan_intervals_candidates(builder, field, source);
+                builder.and_combinator();
+            }
+        }
+        IntervalsSource::AnyOf{ref sources} => {
+            builder.push_empty();
+            for source in sources.iter() {
+                plan_intervals_candidates(builder, field, source);
+                builder.or_combinator();
+            }
+        }
+        IntervalsSource::Filtered{ref source, filter, ref filter_source} => {
+            plan_intervals_candidates(builder, field, source);
+
+            if filter.requires_filter_source() {
+                plan_intervals_candidates(builder, field, filter_source);
+                builder.and_combinator();
+            }
+        }
+        IntervalsSource::First{ref source, ..} => plan_intervals_candidates(builder, field, source),
+    }
+}
+
+/// Splits the terms of a common terms query into term queries for the low and high frequency terms
+fn split_common_terms(index_reader: &RocksDBReader, field: FieldId, terms: &[Term], cutoff_frequency: f64) -> (Vec<Query>, Vec<Query>) {
+    let mut stats = RocksDBStatisticsReader::new(index_reader);
+
+    let cutoff_frequency = if cutoff_frequency < 1.0 {
+        cutoff_frequency * stats.total_docs(field).unwrap_or(0) as f64
+    } else {
+        cutoff_frequency
+    };
+
+    let mut low_frequency_terms = Vec::new();
+    let mut high_frequency_terms = Vec::new();
+    for term in terms.iter() {
+        let term_query = Query::Term {
+            field: field,
+            term: term.clone(),
+            scorer: TermScorer::default(),
+        };
+
+        // Terms that don't exist will never match, so they can't slow the query down
+        let document_frequency = match index_reader.store.term_dictionary.get(term) {
+            Some(term_id) => stats.term_document_frequency(field, term_id).unwrap_or(0),
+            None => 0,
+        };
+
+        if document_frequency as f64 > cutoff_frequency {
+            high_frequency_terms.push(term_query);
+        } else {
+            low_frequency_terms.push(term_query);
+        }
+    }
+
+    (low_frequency_terms, high_frequency_terms)
+}
+
+/// Finds the postings lists that mark the nested documents in the path, along
+/// with those in nested paths inside it. Without a path, all nested documents
+/// are included
+pub fn nested_document_markers(index_reader: &RocksDBReader, path: Option<FieldId>) -> Vec<(FieldId, TermId)> {
+    let marker_term_id = match index_reader.store.term_dictionary.get(&field_exists_term()) {
+        Some(term_id) => term_id,
+        None => return Vec::new(),
+    };
+
+    let schema = index_reader.schema();
+    let path_prefix = path.and_then(|path| schema.get(&path)).map(|field_info| format!("{}.", field_info.name()));
+
+    let mut markers = schema.iter()
+        .filter(|&(field_id, field_info)| {
+            field_info.field_type == FieldType::Nested && match (path, path_prefix.as_ref()) {
+                (Some(path), Some(path_prefix)) => *field_id == path || field_info.name().starts_with(path_prefix.as_str()),
+                (Some(_), None) => false,
+                (None, _) => true,
+            }
+        })
+        .map(|(field_id, _)| (*field_id, marker_term_id))
+        .collect::<Vec<_>>();
+
+    markers.sort_by_key(|&(field_id, _)| field_id.0);
+    markers
+}
+
+/// Finds the nested documents in the path that match the query
+pub fn plan_nested_documents(index_reader: &RocksDBReader, mut builder: &mut BooleanQueryBuilder, path: FieldId, query: &Query) {
+    plan_boolean_query(index_reader, &mut builder, query);
+
+    // Queries such as match_all would match the documents of other paths too
+    match index_reader.store.term_dictionary.get(&field_exists_term()) {
+        Some(term_id) => builder.push_postings_list(path, term_id),
+        None => builder.push_empty(),
+    }
+
+    builder.and_combinator();
+}
+
 pub fn plan_boolean_query(index_reader: &RocksDBReader, mut builder: &mut BooleanQueryBuilder, query: &Query) {
     match *query {
         Query::All{..} => {
@@ -374,6 +616,23 @@ pub fn plan_boolean_query(index_reader: &RocksDBReader, mut builder: &mut Boolea
 
             builder.push_postings_list(field, term_id);
         }
+        Query::BlendedTerm{ref fields, ref term, ..} => {
+            // Get term
+            let term_id = match index_reader.store.term_dictionary.get(term) {
+                Some(term_id) => term_id,
+                None => {
+                    // Term doesn't exist, so will never match
+                    builder.push_empty();
+                    return
+                }
+            };
+
+            builder.push_empty();
+            for &(field, _) in fields.iter() {
+                builder.push_postings_list(field, term_id);
+                builder.or_combinator();
+            }
+        }
         Query::MultiTerm{field, ref term_selector, ..} => {
             // Get terms
             builder.push_empty();
@@ -382,14 +641,229 @@ pub fn plan_boolean_query(index_reader: &RocksDBReader, mut builder: &mut Boolea
                 builder.or_combinator();
             }
         }
+        Query::TermSet{field, ref terms, ..} => {
+            // Terms that aren't in the dictionary can't match anything
+            let term_ids = terms.iter().filter_map(|term| index_reader.store.term_dictionary.get(term)).collect();
+            builder.push_postings_list_union(field, term_ids);
+        }
+        Query::Phrase{field, ref terms, slop, ..} => {
+            // Get terms
+            let term_ids = terms.iter().map(|term| index_reader.store.term_dictionary.get(term)).collect::<Option<Vec<_>>>();
+            let term_ids = match term_ids {
+                Some(ref term_ids) if !term_ids.is_empty() => term_ids.clone(),
+                _ => {
+                    // One of the terms doesn't exist, so will never match
+                    builder.push_empty();
+                    return
+                }
+            };
+
+            // Find candidates
+            builder.push_full();
+            for term_id in term_ids.iter() {
+                builder.push_postings_list(field, *term_id);
+                builder.and_combinator();
+            }
+
+            // Check candidates against the positions of the terms
+            builder.filter_phrase(field, term_ids, slop);
+        }
+        Query::PhrasePrefix{field, ref terms, ref last_term_selector, slop, ..} => {
+            // Get terms
+            let term_ids = match terms.iter().map(|term| index_reader.store.term_dictionary.get(term)).collect::<Option<Vec<_>>>() {
+                Some(term_ids) => term_ids,
+                None => {
+                    // One of the terms doesn't exist, so will never match
+                    builder.push_empty();
+                    return
+                }
+            };
+
+            // Match a phrase for each term that the last term could be
+            builder.push_empty();
+            for last_term_id in index_reader.store.term_dictionary.select(last_term_selector) {
+                let mut phrase_term_ids = term_ids.clone();
+                phrase_term_ids.push(last_term_id);
+
+                // Find candidates
+                builder.push_full();
+                for term_id in phrase_term_ids.iter() {
+                    builder.push_postings_list(field, *term_id);
+                    builder.and_combinator();
+                }
+
+                // Check candidates against the positions of the terms
+                builder.filter_phrase(field, phrase_term_ids, slop);
+                builder.or_combinator();
+            }
+        }
+        Query::Intervals{field, ref source, ..} => {
+            let source = source.map_terms(&|term| index_reader.store.term_dictionary.get(term));
+
+            // Find candidates
+            plan_intervals_candidates(&mut builder, field, &source);
+
+            // Check candidates against the positions of the terms
+            builder.filter_intervals(field, source);
+        }
+        Query::CommonTerms{field, ref terms, cutoff_frequency, require_all, ..} => {
+            let (low_frequency_terms, high_frequency_terms) = split_common_terms(index_reader, field, terms, cutoff_frequency);
+
+            // High frequency terms are only matched when there aren't any others
+            let term_queries = if low_frequency_terms.is_empty() {
+                high_frequency_terms
+            } else {
+                low_frequency_terms
+            };
+
+            if require_all {
+                plan_boolean_query_combinator(index_reader, &mut builder, &term_queries, |builder| builder.and_combinator());
+            } else {
+                plan_boolean_query_combinator(index_reader, &mut builder, &term_queries, |builder| builder.or_combinator());
+            }
+        }
+        Query::Ids{ref keys, ..} => {
+            // Find the current version of each document
+            let doc_ids = keys.iter()
+                .filter_map(|key| index_reader.store.document_index.get_document_id_by_key(&key.as_bytes().to_vec()))
+                .collect();
+
+            builder.push_doc_ids(doc_ids);
+        }
+        Query::DocIds{ref doc_ids, ..} => {
+            builder.push_doc_ids(doc_ids.clone());
+        }
+        Query::Exists{field, ..} | Query::DistanceFeature{field, ..} => {
+            match index_reader.store.term_dictionary.get(&field_exists_term()) {
+                Some(term_id) => builder.push_postings_list(field, term_id),
+                None => builder.push_empty(),
+            }
+        }
+        Query::GeoShape{field, ref tiles, ref shape, relation, system, ..} => {
+            // Find candidates
+            builder.push_empty();
+            for tile in tiles.iter() {
+                if let Some(term_id) = index_reader.store.term_dictionary.get(tile) {
+                    builder.push_postings_list(field, term_id);
+                    builder.or_combinator();
+                }
+            }
+
+            // Check candidates against their stored shape
+            builder.filter_geo_shape(field, shape.clone(), relation, system);
+        }
+        Query::GeoDistance{field, ref tiles, origin, distance, ..} => {
+            // Find candidates in the bounding box
+            builder.push_empty();
+            for tile in tiles.iter() {
+                if let Some(term_id) = index_reader.store.term_dictionary.get(tile) {
+                    builder.push_postings_list(field, term_id);
+                    builder.or_combinator();
+                }
+            }
+
+            // Check the distance to their stored points
+            builder.filter_geo_distance(field, origin, distance);
+        }
+        Query::RankFeature{field, ref feature, ..} => {
+            // Get terms
+            builder.push_empty();
+            for term_id in index_reader.store.term_dictionary.select(&rank_feature_selector(feature)) {
+                builder.push_postings_list(field, term_id);
+                builder.or_combinator();
+            }
+        }
+        Query::Wildcard{field, ref trigrams, ref pattern, ..} => {
+            // Find candidates
+            builder.push_full();
+            for trigram in trigrams.iter() {
+                match index_reader.store.term_dictionary.get(trigram) {
+                    Some(term_id) => builder.push_postings_list(field, term_id),
+                    None => builder.push_empty(),
+                }
+
+                builder.and_combinator();
+            }
+
+            if trigrams.is_empty() {
+                // Without any trigrams, every document would be a candidate
+                builder.push_empty();
+                builder.and_combinator();
+            }
+
+            // Check candidates against the pattern
+            builder.filter_wildcard(field, pattern.clone());
+        }
+        Query::FunctionScore{ref query, ..} => {
+            plan_boolean_query(index_reader, &mut builder, query);
+        }
+        Query::Nested{path, ref query, ..} => {
+            plan_nested_documents(index_reader, &mut builder, path, query);
+            builder.nested_parents(nested_document_markers(index_reader, Some(path)));
+        }
+        Query::Script{ref script, ref params, ..} => {
+            let schema = index_reader.schema();
+            let fields = script_fields(script).into_iter()
+                .map(|name| {
+                    let field = schema.get_field_by_name(&name)?;
+                    let field_type = schema.get(&field)?.field_type.clone();
+                    Some((name, field, field_type))
+                })
+                .collect::<Option<Vec<_>>>();
+
+            let fields = match fields {
+                Some(fields) => fields,
+                None => {
+                    // The script reads a field that doesn't exist, so will never match
+                    builder.push_empty();
+                    return;
+                }
+            };
+
+            if fields.is_empty() {
+                // Scripts that only read params give the same result for every document
+                match script.evaluate(&|name: &str| params.get(name).cloned()) {
+                    Ok(ScriptValue::Boolean(true)) => builder.push_full(),
+                    _ => builder.push_empty(),
+                }
+
+                return;
+            }
+
+            // Candidates have a value in every field the script reads
+            builder.push_full();
+            for &(_, field, _) in fields.iter() {
+                match index_reader.store.term_dictionary.get(&field_exists_term()) {
+                    Some(term_id) => builder.push_postings_list(field, term_id),
+                    None => builder.push_empty(),
+                }
+
+                builder.and_combinator();
+            }
+
+            builder.filter_script(fields, script.clone(), params.clone());
+        }
         Query::Conjunction{ref queries} => {
             plan_boolean_query_combinator(index_reader, &mut builder, queries, |builder| builder.and_combinator());
         }
         Query::Disjunction{ref queries} => {
             plan_boolean_query_combinator(index_reader, &mut builder, queries, |builder| builder.or_combinator());
         }
-        Query::DisjunctionMax{ref queries} => {
+        Query::DisjunctionMax{ref queries, ..} => {
+            plan_boolean_query_combinator(index_reader, &mut builder, queries, |builder| builder.or_combinator());
+        }
+        Query::MinimumShouldMatch{ref queries, minimum} => {
+            // Documents that match any of the queries are candidates, these are
+            // then checked against each query on its own
             plan_boolean_query_combinator(index_reader, &mut builder, queries, |builder| builder.or_combinator());
+
+            let query_plans = queries.iter().map(|query| {
+                let mut query_builder = BooleanQueryBuilder::new();
+                plan_boolean_query(index_reader, &mut query_builder, query);
+                query_builder.build()
+            }).collect();
+
+            builder.filter_minimum_should_match(query_plans, minimum);
         }
         Query::Filter{ref query, ref filter} => {
             plan_boolean_query(index_reader, &mut builder, query);
@@ -401,6 +875,9 @@ pub fn plan_boolean_query(index_reader: &RocksDBReader, mut builder: &mut Boolea
             plan_boolean_query(index_reader, &mut builder, exclude);
             builder.andnot_combinator();
         }
+        Query::Named{ref query, ..} => {
+            plan_boolean_query(index_reader, &mut builder, query);
+        }
     }
 }
 
@@ -408,6 +885,7 @@ pub fn plan_boolean_query(index_reader: &RocksDBReader, mut builder: &mut Boolea
 mod builder_tests {
     use search::schema::FieldId;
     use search::term::TermId;
+    use search::query::wildcard::WildcardPattern;
 
     use super::BooleanQueryOp;
     use super::BooleanQueryBuilder;
@@ -468,6 +946,37 @@ mod builder_tests {
         assert_eq!(negated, false);
     }
 
+    #[test]
+    fn test_filter_wildcard() {
+        let mut builder = BooleanQueryBuilder::new();
+
+        builder.push_postings_list(FieldId(0), TermId(0));
+        builder.filter_wildcard(FieldId(0), WildcardPattern::new("*foo*"));
+
+        let (query, negated) = builder.build();
+
+        assert_eq!(query, vec![
+            BooleanQueryOp::PushPostingsList(FieldId(0), TermId(0)),
+            BooleanQueryOp::FilterWildcard(FieldId(0), WildcardPattern::new("*foo*")),
+        ]);
+        assert_eq!(negated, false);
+    }
+
+    #[test]
+    fn test_filter_wildcard_empty() {
+        let mut builder = BooleanQueryBuilder::new();
+
+        builder.push_empty();
+        builder.filter_wildcard(FieldId(0), WildcardPattern::new("*foo*"));
+
+        let (query, negated) = builder.build();
+
+        assert_eq!(query, vec![
+            BooleanQueryOp::PushEmpty,
+        ]);
+        assert_eq!(negated, false);
+    }
+
     #[test]
     fn test_and_combinator() {
         let mut builder = BooleanQueryBuilder::new();
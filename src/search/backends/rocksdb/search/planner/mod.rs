@@ -2,9 +2,10 @@ pub mod boolean_query;
 pub mod score_function;
 
 use search::Query;
+use search::schema::FieldId;
 
 use super::super::RocksDBReader;
-use self::boolean_query::{BooleanQueryOp, BooleanQueryBuilder, plan_boolean_query};
+use self::boolean_query::{BooleanQueryOp, BooleanQueryBuilder, plan_boolean_query, nested_document_markers, plan_nested_documents};
 use self::score_function::{ScoreFunctionOp, plan_score_function};
 
 #[derive(Debug)]
@@ -25,12 +26,30 @@ impl SearchPlan {
 }
 
 pub fn plan_query(index_reader: &RocksDBReader, query: &Query, score: bool) -> SearchPlan {
-    let mut plan = SearchPlan::new();
-
     // Plan boolean query
     let mut builder = BooleanQueryBuilder::new();
     plan_boolean_query(index_reader, &mut builder, query);
 
+    // Nested documents are only matched through nested queries on their parents
+    for (field, term_id) in nested_document_markers(index_reader, None) {
+        builder.push_postings_list(field, term_id);
+        builder.andnot_combinator();
+    }
+
+    finish_plan(index_reader, builder, query, score)
+}
+
+/// Plans a query that matches the nested documents in the path, rather than top level documents
+pub fn plan_nested_query(index_reader: &RocksDBReader, path: FieldId, query: &Query, score: bool) -> SearchPlan {
+    let mut builder = BooleanQueryBuilder::new();
+    plan_nested_documents(index_reader, &mut builder, path, query);
+
+    finish_plan(index_reader, builder, query, score)
+}
+
+fn finish_plan(index_reader: &RocksDBReader, mut builder: BooleanQueryBuilder, query: &Query, score: bool) -> SearchPlan {
+    let mut plan = SearchPlan::new();
+
     // Add operations to exclude deleted documents to boolean query
     builder.push_deletion_list();
     builder.andnot_combinator();
@@ -2,20 +2,65 @@ use search::schema::FieldId;
 use search::term::TermId;
 use search::Query;
 use search::query::term_scorer::TermScorer;
+use search::query::rank_feature::{RankFeatureFunction, rank_feature_selector, rank_feature_value};
+use search::query::function_score::{ScoreFunction, ScoreMode, BoostMode};
+use search::query::distance_feature::DistanceFeatureOrigin;
+use search::query::nested::NestedScoreMode;
 
 use super::super::RocksDBReader;
+use super::boolean_query::{BooleanQueryOp, BooleanQueryBuilder, plan_boolean_query, plan_nested_documents, nested_document_markers};
 
 #[derive(Debug, Clone)]
 pub enum CombinatorScorer {
     Avg,
-    Max,
+
+    /// Takes the highest score, adding the others multiplied by the tie breaker
+    Max(f32),
 }
 
 #[derive(Debug, Clone)]
 pub enum ScoreFunctionOp {
     Literal(f32),
     TermScorer(FieldId, TermId, TermScorer),
+
+    /// Scores the term in each of the fields as if it had the highest document frequency of them all, then
+    /// combines the scores of the fields with the tie breaker
+    BlendedTermScorer(Vec<(FieldId, f32)>, TermId, f32, TermScorer),
     CombinatorScorer(u32, CombinatorScorer),
+
+    /// Scores the value of the first of the terms that the document has
+    RankFeature(FieldId, Vec<(TermId, f32)>, RankFeatureFunction, f32),
+
+    /// Scores the distance between the stored value of the field and the origin
+    DistanceFeature(FieldId, DistanceFeatureOrigin, f64, f32),
+
+    /// Pops the score of the query and adjusts it with the functions
+    FunctionScore(Vec<PlannedScoreFunction>, ScoreMode, BoostMode, f32, f32),
+
+    /// Combines the scores of the nested documents that match the boolean query
+    Nested(PlannedNestedQuery),
+}
+
+#[derive(Debug, Clone)]
+pub struct PlannedNestedQuery {
+    /// The boolean query that finds the matching nested documents along with whether it is negated
+    pub boolean_query: (Vec<BooleanQueryOp>, bool),
+
+    /// The markers of the path and of the nested paths inside it
+    pub markers: Vec<(FieldId, TermId)>,
+
+    /// Scores each of the matching nested documents
+    pub score_function: Vec<ScoreFunctionOp>,
+
+    pub score_mode: NestedScoreMode,
+}
+
+#[derive(Debug, Clone)]
+pub struct PlannedScoreFunction {
+    /// The boolean query of the filter along with whether it is negated
+    pub filter: Option<(Vec<BooleanQueryOp>, bool)>,
+    pub function: ScoreFunction,
+    pub weight: f32,
 }
 
 fn plan_score_function_combinator(index_reader: &RocksDBReader, mut score_function: &mut Vec<ScoreFunctionOp>, queries: &Vec<Query>, scorer: CombinatorScorer) {
@@ -77,14 +122,150 @@ pub fn plan_score_function(index_reader: &RocksDBReader, mut score_function: &mu
                 _ => score_function.push(ScoreFunctionOp::CombinatorScorer(total_terms, CombinatorScorer::Avg)),
             }
         }
+        Query::Phrase{field, ref terms, ref scorer, ..} => {
+            // Score each term as a conjunction would
+            let term_queries = terms.iter().map(|term| Query::Term {
+                field: field,
+                term: term.clone(),
+                scorer: scorer.clone(),
+            }).collect::<Vec<_>>();
+
+            plan_score_function_combinator(index_reader, &mut score_function, &term_queries, CombinatorScorer::Avg);
+        }
+        Query::PhrasePrefix{field, ref terms, ref last_term_selector, ref scorer, ..} => {
+            // Score the last term as a prefix query would
+            let mut term_queries = terms.iter().map(|term| Query::Term {
+                field: field,
+                term: term.clone(),
+                scorer: scorer.clone(),
+            }).collect::<Vec<_>>();
+
+            term_queries.push(Query::MultiTerm {
+                field: field,
+                term_selector: last_term_selector.clone(),
+                scorer: scorer.clone(),
+            });
+
+            plan_score_function_combinator(index_reader, &mut score_function, &term_queries, CombinatorScorer::Avg);
+        }
+        Query::CommonTerms{field, ref terms, ref scorer, ..} => {
+            // Score each term as a disjunction would, so high frequency terms add to the score of documents that match
+            let term_queries = terms.iter().map(|term| Query::Term {
+                field: field,
+                term: term.clone(),
+                scorer: scorer.clone(),
+            }).collect::<Vec<_>>();
+
+            plan_score_function_combinator(index_reader, &mut score_function, &term_queries, CombinatorScorer::Avg);
+        }
+        Query::BlendedTerm{ref fields, ref term, tie_breaker, ref scorer} => {
+            // Get term
+            let term_id = match index_reader.store.term_dictionary.get(term) {
+                Some(term_id) => term_id,
+                None => {
+                    // Term doesn't exist, so will never match
+                    score_function.push(ScoreFunctionOp::Literal(0.0f32));
+                    return
+                }
+            };
+
+            score_function.push(ScoreFunctionOp::BlendedTermScorer(fields.clone(), term_id, tie_breaker, scorer.clone()));
+        }
+        Query::Intervals{field, ref source, ref scorer} => {
+            // Score each term as a disjunction would
+            let mut terms = source.terms();
+            terms.sort();
+            terms.dedup();
+
+            let term_queries = terms.into_iter().map(|term| Query::Term {
+                field: field,
+                term: term.clone(),
+                scorer: scorer.clone(),
+            }).collect::<Vec<_>>();
+
+            plan_score_function_combinator(index_reader, &mut score_function, &term_queries, CombinatorScorer::Avg);
+        }
+        Query::TermSet{ref score, ..} | Query::Ids{ref score, ..} | Query::DocIds{ref score, ..} | Query::Script{ref score, ..} => {
+            score_function.push(ScoreFunctionOp::Literal(*score));
+        }
+        Query::Exists{ref score, ..} => {
+            score_function.push(ScoreFunctionOp::Literal(*score));
+        }
+        Query::Wildcard{ref score, ..} => {
+            score_function.push(ScoreFunctionOp::Literal(*score));
+        }
+        Query::GeoShape{ref score, ..} => {
+            score_function.push(ScoreFunctionOp::Literal(*score));
+        }
+        Query::GeoDistance{ref score, ..} => {
+            score_function.push(ScoreFunctionOp::Literal(*score));
+        }
+        Query::RankFeature{field, ref feature, ref function, boost} => {
+            // Get terms along with the values they hold
+            let terms = index_reader.store.term_dictionary.select_terms(&rank_feature_selector(feature)).into_iter()
+                .filter_map(|(term, term_id)| rank_feature_value(feature, &term).map(|value| (term_id, value)))
+                .collect::<Vec<_>>();
+
+            // Saturation defaults to a pivot at the geometric mean of the values
+            let function = match *function {
+                RankFeatureFunction::Saturation{pivot: None} if !terms.is_empty() => {
+                    let total_log = terms.iter().map(|&(_, value)| value.ln()).sum::<f32>();
+                    RankFeatureFunction::Saturation {
+                        pivot: Some((total_log / terms.len() as f32).exp()),
+                    }
+                }
+                _ => function.clone(),
+            };
+
+            score_function.push(ScoreFunctionOp::RankFeature(field, terms, function, boost));
+        }
+        Query::DistanceFeature{field, ref origin, pivot, boost} => {
+            score_function.push(ScoreFunctionOp::DistanceFeature(field, origin.clone(), pivot, boost));
+        }
+        Query::FunctionScore{ref query, ref functions, score_mode, boost_mode, max_boost, boost} => {
+            plan_score_function(index_reader, &mut score_function, query);
+
+            let functions = functions.iter().map(|function| {
+                let filter = function.filter.as_ref().map(|filter| {
+                    let mut builder = BooleanQueryBuilder::new();
+                    plan_boolean_query(index_reader, &mut builder, filter);
+                    builder.build()
+                });
+
+                PlannedScoreFunction {
+                    filter: filter,
+                    function: function.function.clone(),
+                    weight: function.weight,
+                }
+            }).collect();
+
+            score_function.push(ScoreFunctionOp::FunctionScore(functions, score_mode, boost_mode, max_boost, boost));
+        }
+        Query::Nested{path, ref query, score_mode} => {
+            let mut builder = BooleanQueryBuilder::new();
+            plan_nested_documents(index_reader, &mut builder, path, query);
+
+            let mut nested_score_function = Vec::new();
+            plan_score_function(index_reader, &mut nested_score_function, query);
+
+            score_function.push(ScoreFunctionOp::Nested(PlannedNestedQuery {
+                boolean_query: builder.build(),
+                markers: nested_document_markers(index_reader, Some(path)),
+                score_function: nested_score_function,
+                score_mode: score_mode,
+            }));
+        }
         Query::Conjunction{ref queries} => {
             plan_score_function_combinator(index_reader, &mut score_function, queries, CombinatorScorer::Avg);
         }
         Query::Disjunction{ref queries} => {
             plan_score_function_combinator(index_reader, &mut score_function, queries, CombinatorScorer::Avg);
         }
-        Query::DisjunctionMax{ref queries} => {
-            plan_score_function_combinator(index_reader, &mut score_function, queries, CombinatorScorer::Max);
+        Query::DisjunctionMax{ref queries, tie_breaker} => {
+            plan_score_function_combinator(index_reader, &mut score_function, queries, CombinatorScorer::Max(tie_breaker));
+        }
+        Query::MinimumShouldMatch{ref queries, ..} => {
+            plan_score_function_combinator(index_reader, &mut score_function, queries, CombinatorScorer::Avg);
         }
         Query::Filter{ref query, ..} => {
             plan_score_function(index_reader, &mut score_function, query);
@@ -92,5 +273,8 @@ pub fn plan_score_function(index_reader: &RocksDBReader, mut score_function: &mu
         Query::Exclude{ref query, ..} => {
             plan_score_function(index_reader, &mut score_function, query);
         }
+        Query::Named{ref query, ..} => {
+            plan_score_function(index_reader, &mut score_function, query);
+        }
     }
 }
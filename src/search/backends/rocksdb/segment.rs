@@ -9,6 +9,11 @@ use byteorder::{ByteOrder, LittleEndian};
 use super::RocksDBReader;
 use super::key_builder::KeyBuilder;
 
+/// Reads a postings or deletion list. These only fail to read if the database is corrupt
+fn read_doc_id_set(bytes: &[u8]) -> Result<RoaringBitmap, String> {
+    RoaringBitmap::deserialize_from(Cursor::new(bytes)).map_err(|e| format!("corrupt document id set: {}", e))
+}
+
 pub struct RocksDBSegment<'a> {
     reader: &'a RocksDBReader<'a>,
     id: u32,
@@ -36,19 +41,25 @@ impl<'a> Segment for RocksDBSegment<'a> {
 
     fn load_stored_field_value_raw(&self, doc_local_id: u16, field_id: FieldId, value_type: &[u8]) -> Result<Option<Vec<u8>>, String> {
         let kb = KeyBuilder::stored_field_value(self.id, doc_local_id, field_id.0, value_type);
-        let val = try!(self.reader.snapshot.get(&kb.key()));
-        Ok(val.map(|v| v.to_vec()))
+        match try!(self.reader.snapshot.get(&kb.key())) {
+            Some(value) => self.reader.store.open_stored_value(&kb.key(), &value).map(Some),
+            None => Ok(None),
+        }
     }
 
     fn load_postings_list(&self, field_id: FieldId, term_id: TermId) -> Result<Option<RoaringBitmap>, String> {
         let kb = KeyBuilder::segment_postings_list(self.id, field_id.0, term_id.0);
-        let doc_id_set = try!(self.reader.snapshot.get(&kb.key())).map(|doc_id_set| RoaringBitmap::deserialize_from(Cursor::new(&doc_id_set[..])).unwrap());
-        Ok(doc_id_set)
+        match try!(self.reader.snapshot.get(&kb.key())) {
+            Some(doc_id_set) => read_doc_id_set(&doc_id_set).map(Some),
+            None => Ok(None),
+        }
     }
 
     fn load_deletion_list(&self) -> Result<Option<RoaringBitmap>, String> {
         let kb = KeyBuilder::segment_del_list(self.id);
-        let doc_id_set = try!(self.reader.snapshot.get(&kb.key())).map(|doc_id_set| RoaringBitmap::deserialize_from(Cursor::new(&doc_id_set[..])).unwrap());
-        Ok(doc_id_set)
+        match try!(self.reader.snapshot.get(&kb.key())) {
+            Some(doc_id_set) => read_doc_id_set(&doc_id_set).map(Some),
+            None => Ok(None),
+        }
     }
 }
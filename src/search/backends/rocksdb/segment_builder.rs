@@ -3,6 +3,7 @@ use std::collections::HashMap;
 use search::{Document, Term, TermId};
 use search::schema::FieldId;
 use search::segment::{SegmentId, Segment};
+use search::query::exists::field_exists_term;
 use byteorder::{LittleEndian, WriteBytesExt};
 use roaring::RoaringBitmap;
 use fnv::FnvHashMap;
@@ -17,6 +18,9 @@ pub struct SegmentBuilder {
     pub postings_lists: FnvHashMap<(FieldId, TermId), RoaringBitmap>,
     pub statistics: FnvHashMap<Vec<u8>, i64>,
     pub stored_field_values: FnvHashMap<(FieldId, u16, Vec<u8>), Vec<u8>>,
+
+    /// The external keys of the documents. Nested documents don't have their own
+    pub document_keys: FnvHashMap<u16, String>,
 }
 
 #[derive(Debug)]
@@ -34,6 +38,7 @@ impl SegmentBuilder {
             postings_lists: FnvHashMap::default(),
             statistics: FnvHashMap::default(),
             stored_field_values: FnvHashMap::default(),
+            document_keys: FnvHashMap::default(),
         }
     }
 
@@ -51,6 +56,19 @@ impl SegmentBuilder {
     }
 
     pub fn add_document(&mut self, doc: &Document) -> Result<u16, DocumentInsertError> {
+        let doc_id = self.add_document_data(doc)?;
+        self.document_keys.insert(doc_id, doc.key.clone());
+
+        Ok(doc_id)
+    }
+
+    fn add_document_data(&mut self, doc: &Document) -> Result<u16, DocumentInsertError> {
+        // Nested documents go first, so their parent is the next document
+        // that isn't nested within the same path
+        for nested_doc in doc.nested_documents.iter() {
+            self.add_document_data(nested_doc)?;
+        }
+
         // Get document ord
         let doc_id = self.current_doc;
         self.current_doc += 1;
@@ -88,12 +106,32 @@ impl SegmentBuilder {
                     self.stored_field_values.insert((*field_id, doc_id, value_type), frequency_bytes);
                 }
 
+                // Write term positions
+                // These are used by phrase queries. Like term frequencies, a missing key
+                // means the term is only at the first position
+                if frequency != 1 || !positions.contains(1) {
+                    let mut value_type = vec![b't', b'p'];
+                    value_type.extend(term_id.0.to_string().as_bytes());
+
+                    let mut positions_bytes: Vec<u8> = Vec::new();
+                    positions.serialize_into(&mut positions_bytes).unwrap();
+
+                    self.stored_field_values.insert((*field_id, doc_id, value_type), positions_bytes);
+                }
+
                 // Increment term document frequency
                 let stat_name = KeyBuilder::segment_stat_term_doc_frequency_stat_name(field_id.0, term_id.0);
                 let stat = self.statistics.entry(stat_name).or_insert(0);
                 *stat += 1;
             }
 
+            // Mark the field as having a value
+            // Used by exists queries
+            if field_token_count > 0 {
+                let exists_term_id = self.get_term_id(&field_exists_term());
+                self.postings_lists.entry((*field_id, exists_term_id)).or_insert_with(RoaringBitmap::new).insert(doc_id as u32);
+            }
+
             // Field length
             // Used by the BM25 similarity model
             let length = ((field_token_count as f32).sqrt() - 1.0) * 3.0;
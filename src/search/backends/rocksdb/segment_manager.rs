@@ -1,7 +1,8 @@
 use std::str;
+use std::vec;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
-use rocksdb::{self, DB, DBRawIterator};
+use rocksdb::{self, DB};
 
 use super::RocksDBReader;
 use super::segment::RocksDBSegment;
@@ -48,45 +49,44 @@ impl SegmentManager {
         Ok(next_segment)
     }
 
-    /// Iterates currently active segments
+    /// Iterates currently active segments, in order of their ids
+    ///
+    /// Segment ids are kept as strings in the keys, so they're sorted here.
+    /// Documents in earlier segments have lower ids, so this is index order.
     pub fn iter_active<'a>(&self, reader: &'a RocksDBReader) -> ActiveSegmentsIterator<'a> {
+        let mut segment_ids = Vec::new();
+
         let mut iter = reader.snapshot.raw_iterator();
         iter.seek(b"a");
+        while iter.valid() {
+            let k = iter.key().unwrap();
+
+            if k[0] != b'a' {
+                break;
+            }
+
+            segment_ids.push(str::from_utf8(&k[1..]).unwrap().parse::<u32>().unwrap());
+            iter.next();
+        }
+
+        segment_ids.sort();
+
         ActiveSegmentsIterator {
             reader: reader,
-            iter: iter,
-            fused: false,
+            segment_ids: segment_ids.into_iter(),
         }
     }
 }
 
 pub struct ActiveSegmentsIterator<'a> {
     reader: &'a RocksDBReader<'a>,
-    iter: DBRawIterator,
-    fused: bool,
+    segment_ids: vec::IntoIter<u32>,
 }
 
 impl<'a> Iterator for ActiveSegmentsIterator<'a> {
     type Item = RocksDBSegment<'a>;
 
     fn next(&mut self) -> Option<RocksDBSegment<'a>> {
-        if !self.fused && self.iter.valid() {
-            let segment_id = {
-                let k = self.iter.key().unwrap();
-
-                if k[0] != b'a' {
-                    self.fused = true;
-                    return None;
-                }
-
-                str::from_utf8(&k[1..]).unwrap().parse::<u32>().unwrap()
-            };
-
-            self.iter.next();
-
-            Some(RocksDBSegment::new(self.reader, segment_id))
-        } else {
-            None
-        }
+        self.segment_ids.next().map(|segment_id| RocksDBSegment::new(self.reader, segment_id))
     }
 }
@@ -1,5 +1,6 @@
 use std::str;
 use std::io::Cursor;
+use std::cmp::Ordering;
 
 use rocksdb::{self, WriteBatch, WriteOptions};
 use roaring::RoaringBitmap;
@@ -8,13 +9,16 @@ use search::segment::SegmentId;
 use byteorder::{ByteOrder, LittleEndian};
 use fnv::{FnvHashMap, FnvHashSet};
 
-use super::RocksDBStore;
+use super::{RocksDBStore, SegmentSort};
 use super::key_builder::KeyBuilder;
 
 #[derive(Debug)]
 pub enum SegmentMergeError {
     TooManyDocs,
     RocksDBError(rocksdb::Error),
+    DecryptionError(String),
+    InvalidKey(Vec<u8>),
+    InvalidValue(Vec<u8>),
 }
 
 impl From<rocksdb::Error> for SegmentMergeError {
@@ -28,10 +32,59 @@ impl From<SegmentMergeError> for String {
         match e {
             SegmentMergeError::TooManyDocs => "Too many docs".to_string(),
             SegmentMergeError::RocksDBError(e) => e.into(),
+            SegmentMergeError::DecryptionError(e) => e,
+            SegmentMergeError::InvalidKey(key) => format!("invalid key {:?}", String::from_utf8_lossy(&key)),
+            SegmentMergeError::InvalidValue(key) => format!("invalid value for key {:?}", String::from_utf8_lossy(&key)),
         }
     }
 }
 
+/// Parses one of the numeric parts of a key
+fn parse_key_id(part: Option<&[u8]>, key: &[u8]) -> Result<u32, SegmentMergeError> {
+    part.and_then(|part| str::from_utf8(part).ok())
+        .and_then(|part| part.parse::<u32>().ok())
+        .ok_or_else(|| SegmentMergeError::InvalidKey(key.to_vec()))
+}
+
+/// Converts postings list key strings "d1/2/3" into tuples of 3 i32s (1, 2, 3)
+fn parse_postings_list_key(key: &[u8]) -> Result<(u32, u32, u32), SegmentMergeError> {
+    let mut parts_iter = key[1..].split(|b| *b == b'/');
+    let field = parse_key_id(parts_iter.next(), key)?;
+    let term = parse_key_id(parts_iter.next(), key)?;
+    let segment = parse_key_id(parts_iter.next(), key)?;
+
+    Ok((field, term, segment))
+}
+
+/// Converts stored value key strings "v1/2/3/v" into tuples of 3 i32s and a Vec<u8> (1, 2, 3, vec![b'v', b'a', b'l'])
+fn parse_stored_value_key(key: &[u8]) -> Result<(u32, u32, u32, Vec<u8>), SegmentMergeError> {
+    let mut parts_iter = key[1..].splitn(4, |b| *b == b'/');
+    let segment = parse_key_id(parts_iter.next(), key)?;
+    let doc_id = parse_key_id(parts_iter.next(), key)?;
+    let field_id = parse_key_id(parts_iter.next(), key)?;
+    let value_type = parts_iter.next().ok_or_else(|| SegmentMergeError::InvalidKey(key.to_vec()))?.to_vec();
+
+    Ok((segment, doc_id, field_id, value_type))
+}
+
+/// Converts document key key strings "i1/2" into tuples of 2 u32s (1, 2)
+fn parse_document_key_key(key: &[u8]) -> Result<(u32, u32), SegmentMergeError> {
+    let mut parts_iter = key[1..].split(|b| *b == b'/');
+    let segment = parse_key_id(parts_iter.next(), key)?;
+    let doc_id = parse_key_id(parts_iter.next(), key)?;
+
+    Ok((segment, doc_id))
+}
+
+/// Converts statistic key strings "s1/total_docs" into tuples of 1 i32 and a Vec<u8> (1, ['t', 'o', 't', ...])
+fn parse_statistic_key(key: &[u8]) -> Result<(u32, Vec<u8>), SegmentMergeError> {
+    let mut parts_iter = key[1..].splitn(2, |b| *b == b'/');
+    let segment = parse_key_id(parts_iter.next(), key)?;
+    let statistic_name = parts_iter.next().ok_or_else(|| SegmentMergeError::InvalidKey(key.to_vec()))?.to_vec();
+
+    Ok((segment, statistic_name))
+}
+
 impl RocksDBStore {
     fn merge_segment_data(&self, source_segments: &Vec<u32>, dest_segment: u32, doc_id_mapping: &FnvHashMap<DocId, u16>) -> Result<(), SegmentMergeError> {
         // Put source_segments in a FnvHashSet as this is much faster for performing contains queries against
@@ -48,11 +101,6 @@ impl RocksDBStore {
         // in a lot of unwanted data, we firstly iterate the keys, it they one of the source segments
         // looking for then we load them and append them to our new segment.
 
-        /// Converts postings list key strings "d1/2/3" into tuples of 3 i32s (1, 2, 3)
-        fn parse_postings_list_key(key: &[u8]) -> (u32, u32, u32) {
-            let mut nums_iter = key[1..].split(|b| *b == b'/').map(|s| str::from_utf8(s).unwrap().parse::<u32>().unwrap());
-            (nums_iter.next().unwrap(), nums_iter.next().unwrap(), nums_iter.next().unwrap())
-        }
 
         let mut current_td_key: Option<(u32, u32)> = None;
         let mut current_td = RoaringBitmap::new();
@@ -67,7 +115,7 @@ impl RocksDBStore {
                 break;
             }
 
-            let (field, term, segment) = parse_postings_list_key(&k);
+            let (field, term, segment) = parse_postings_list_key(&k)?;
 
             if source_segments_btree.contains(&segment) {
                 if current_td_key != Some((field, term)) {
@@ -85,7 +133,7 @@ impl RocksDBStore {
                 }
 
                 // Merge postings list into the new one (and remap the doc ids)
-                let bitmap = RoaringBitmap::deserialize_from(Cursor::new(iter.value().unwrap())).unwrap();
+                let bitmap = RoaringBitmap::deserialize_from(Cursor::new(iter.value().unwrap())).map_err(|_| SegmentMergeError::InvalidValue(k.to_vec()))?;
                 for doc_id in bitmap.iter() {
                     let doc_id = DocId(SegmentId(segment), doc_id as u16);
                     let new_doc_id = doc_id_mapping.get(&doc_id).unwrap();
@@ -112,16 +160,6 @@ impl RocksDBStore {
         // - Remap their doc ids to the one in the new segment
         // - Write the value back with the new segment/doc ids in the key
 
-        /// Converts stored value key strings "v1/2/3/v" into tuples of 3 i32s and a Vec<u8> (1, 2, 3, vec![b'v', b'a', b'l'])
-        fn parse_stored_value_key(key: &[u8]) -> (u32, u32, u32, Vec<u8>) {
-            let mut parts_iter = key[1..].split(|b| *b == b'/');
-            let segment = str::from_utf8(parts_iter.next().unwrap()).unwrap().parse::<u32>().unwrap();
-            let doc_id = str::from_utf8(parts_iter.next().unwrap()).unwrap().parse::<u32>().unwrap();
-            let field_id = str::from_utf8(parts_iter.next().unwrap()).unwrap().parse::<u32>().unwrap();
-            let value_type = parts_iter.next().unwrap().to_vec();
-
-            (segment, doc_id, field_id, value_type)
-        }
 
         for source_segment in source_segments.iter() {
             let kb = KeyBuilder::segment_stored_values_prefix(*source_segment);
@@ -135,7 +173,7 @@ impl RocksDBStore {
                     break;
                 }
 
-                let (segment, doc_id, field, value_type) = parse_stored_value_key(&k);
+                let (segment, doc_id, field, value_type) = parse_stored_value_key(&k)?;
 
                 if segment != *source_segment {
                     // Segment finished
@@ -148,7 +186,44 @@ impl RocksDBStore {
 
                 // Write value into new segment
                 let kb = KeyBuilder::stored_field_value(dest_segment, *new_doc_id, field, &value_type);
-                try!(self.db.put_opt(&kb.key(), unsafe { &iter.value_inner().unwrap() }, &write_options));
+                let value = self.reseal_stored_value(&k, &kb.key(), unsafe { &iter.value_inner().unwrap() }).map_err(SegmentMergeError::DecryptionError)?;
+                try!(self.db.put_opt(&kb.key(), &value, &write_options));
+
+                iter.next();
+            }
+        }
+
+        // Merge the document keys
+        // These are keyed by segment and doc id like the stored values, so they're remapped
+        // in the same way
+
+        for source_segment in source_segments.iter() {
+            let kb = KeyBuilder::segment_document_keys_prefix(*source_segment);
+            let mut iter = self.db.raw_iterator();
+            iter.seek(&kb.key());
+            while iter.valid() {
+                let k = iter.key().unwrap();
+
+                if k[0] != b'i' {
+                    // No more document keys to move
+                    break;
+                }
+
+                let (segment, doc_id) = parse_document_key_key(&k)?;
+
+                if segment != *source_segment {
+                    // Segment finished
+                    break;
+                }
+
+                // Remap doc id
+                let doc_id = DocId(SegmentId(segment), doc_id as u16);
+                let new_doc_id = doc_id_mapping.get(&doc_id).unwrap();
+
+                // Write key into new segment
+                let kb = KeyBuilder::segment_document_key(dest_segment, *new_doc_id);
+                let value = self.reseal_stored_value(&k, &kb.key(), unsafe { &iter.value_inner().unwrap() }).map_err(SegmentMergeError::DecryptionError)?;
+                try!(self.db.put_opt(&kb.key(), &value, &write_options));
 
                 iter.next();
             }
@@ -160,14 +235,6 @@ impl RocksDBStore {
 
         let mut statistics = FnvHashMap::default();
 
-        /// Converts statistic key strings "s1/total_docs" into tuples of 1 i32 and a Vec<u8> (1, ['t', 'o', 't', ...])
-        fn parse_statistic_key(key: &[u8]) -> (u32, Vec<u8>) {
-            let mut parts_iter = key[1..].split(|b| *b == b'/');
-            let segment = str::from_utf8(parts_iter.next().unwrap()).unwrap().parse::<u32>().unwrap();
-            let statistic_name = parts_iter.next().unwrap().to_vec();
-
-            (segment, statistic_name)
-        }
 
         // Fetch and merge statistics
         for source_segment in source_segments.iter() {
@@ -182,7 +249,7 @@ impl RocksDBStore {
                     break;
                 }
 
-                let (segment, statistic_name) = parse_statistic_key(&k);
+                let (segment, statistic_name) = parse_statistic_key(&k)?;
 
                 if segment != *source_segment {
                     // Segment finished
@@ -190,8 +257,13 @@ impl RocksDBStore {
                 }
 
 
+                let value = unsafe { iter.value_inner().unwrap() };
+                if value.len() != 8 {
+                    return Err(SegmentMergeError::InvalidValue(k.to_vec()));
+                }
+
                 let stat = statistics.entry(statistic_name).or_insert(0);
-                *stat += LittleEndian::read_i64(unsafe { &iter.value_inner().unwrap() });
+                *stat += LittleEndian::read_i64(value);
 
                 iter.next();
             }
@@ -235,17 +307,16 @@ impl RocksDBStore {
     }
 
     pub fn merge_segments(&self, source_segments: &Vec<u32>) -> Result<u32, SegmentMergeError> {
-        let dest_segment = try!(self.segments.new_segment(&self.db));
-
-        // Generate a mapping between the ids of the documents in the old segments to the new one
-        // This packs the id spaces of the old segments together:
-        // For example, say we have to merge 3 segments with 100 documents each:
-        //  - The first segment's ids will be the same as before
-        //  - The second segment's ids will be remapped to 100 - 199
-        //  - The third segment's ids will be remapped to 200 - 299
+        self.merge_segments_sorted(source_segments, None)
+    }
 
-        let mut doc_id_mapping: FnvHashMap<DocId, u16> = FnvHashMap::default();
-        let mut current_doc_id: u32 = 0;
+    /// Finds the documents of each segment with the documents nested in them
+    ///
+    /// Nested documents are just before the document they're nested in, which is
+    /// the only one of them with a key. These must stay together when documents
+    /// are reordered.
+    fn load_document_blocks(&self, source_segments: &Vec<u32>) -> Result<Vec<Vec<DocId>>, SegmentMergeError> {
+        let mut blocks = Vec::new();
 
         for source_segment in source_segments.iter() {
             let kb = KeyBuilder::segment_stat(*source_segment, b"total_docs");
@@ -256,17 +327,87 @@ impl RocksDBStore {
                 None => continue,
             };
 
+            let mut block = Vec::new();
             for source_doc_id in 0..total_docs {
-                if current_doc_id >= 65536 {
-                    return Err(SegmentMergeError::TooManyDocs);
+                block.push(DocId(SegmentId(*source_segment), source_doc_id as u16));
+
+                let kb = KeyBuilder::segment_document_key(*source_segment, source_doc_id as u16);
+                if try!(self.db.get(&kb.key())).is_some() {
+                    blocks.push(block);
+                    block = Vec::new();
                 }
+            }
 
-                let from = DocId(SegmentId(*source_segment), source_doc_id as u16);
-                doc_id_mapping.insert(from, current_doc_id as u16);
-                current_doc_id += 1;
+            if !block.is_empty() {
+                blocks.push(block);
             }
         }
 
+        Ok(blocks)
+    }
+
+    /// Merges the segments into a new one. If there's a sort, the documents are
+    /// put in that order, otherwise they are kept in the order of the segments
+    pub fn merge_segments_sorted(&self, source_segments: &Vec<u32>, sort: Option<SegmentSort>) -> Result<u32, SegmentMergeError> {
+        let mut blocks = self.load_document_blocks(source_segments)?;
+
+        if let Some(sort) = sort {
+            // Blocks are ordered by the value of their last document, which is the one the
+            // others are nested in. Documents without a value go last
+            let reader = self.reader();
+            let mut keyed_blocks = blocks.into_iter()
+                .map(|block| {
+                    let values = match reader.read_stored_field(sort.field, *block.last().unwrap()) {
+                        Ok(Some(value)) => value.as_numbers(),
+                        _ => Vec::new(),
+                    };
+
+                    let key = if sort.descending {
+                        values.into_iter().fold(None, |max: Option<f64>, value| Some(max.map_or(value, |max| max.max(value))))
+                    } else {
+                        values.into_iter().fold(None, |min: Option<f64>, value| Some(min.map_or(value, |min| min.min(value))))
+                    };
+
+                    (key, block)
+                })
+                .collect::<Vec<_>>();
+
+            // This is a stable sort, so documents with the same value stay in the order of the segments
+            keyed_blocks.sort_by(|&(a, _), &(b, _)| {
+                match (a, b) {
+                    (Some(a), Some(b)) if sort.descending => b.partial_cmp(&a).unwrap_or(Ordering::Equal),
+                    (Some(a), Some(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+                    (Some(_), None) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    (None, None) => Ordering::Equal,
+                }
+            });
+
+            blocks = keyed_blocks.into_iter().map(|(_, block)| block).collect();
+        }
+
+        let dest_segment = try!(self.segments.new_segment(&self.db));
+
+        // Generate a mapping between the ids of the documents in the old segments to the new one
+        // This packs the id spaces of the old segments together:
+        // For example, say we have to merge 3 segments with 100 documents each:
+        //  - The first segment's ids will be the same as before
+        //  - The second segment's ids will be remapped to 100 - 199
+        //  - The third segment's ids will be remapped to 200 - 299
+        // If the documents are sorted, they're packed together in that order instead
+
+        let mut doc_id_mapping: FnvHashMap<DocId, u16> = FnvHashMap::default();
+        let mut current_doc_id: u32 = 0;
+
+        for from in blocks.into_iter().flat_map(|block| block.into_iter()) {
+            if current_doc_id >= 65536 {
+                return Err(SegmentMergeError::TooManyDocs);
+            }
+
+            doc_id_mapping.insert(from, current_doc_id as u16);
+            current_doc_id += 1;
+        }
+
         // Merge segment data
         // Most of the heavy lifting happens here. This merges all the immutable parts of
         // the segment (which is everything but the deletion list). It does not activate the
@@ -288,7 +429,7 @@ impl RocksDBStore {
         Ok(dest_segment)
     }
 
-    pub fn purge_segments(&self, segments: &Vec<u32>) -> Result<(), rocksdb::Error> {
+    pub fn purge_segments(&self, segments: &Vec<u32>) -> Result<(), SegmentMergeError> {
         // Put segments in a FnvHashSet as this is much faster for performing contains queries against
         let segments_btree = segments.iter().collect::<FnvHashSet<_>>();
 
@@ -298,11 +439,6 @@ impl RocksDBStore {
 
         // Purge term directories
 
-        /// Converts postings list key strings "d1/2/3" into tuples of 3 i32s (1, 2, 3)
-        fn parse_postings_list_key(key: &[u8]) -> (u32, u32, u32) {
-            let mut nums_iter = key[1..].split(|b| *b == b'/').map(|s| str::from_utf8(s).unwrap().parse::<u32>().unwrap());
-            (nums_iter.next().unwrap(), nums_iter.next().unwrap(), nums_iter.next().unwrap())
-        }
 
         let mut iter = self.db.raw_iterator();
         iter.seek(b"d");
@@ -314,7 +450,7 @@ impl RocksDBStore {
                 break;
             }
 
-            let (_, _, segment) = parse_postings_list_key(&k);
+            let (_, _, segment) = parse_postings_list_key(&k)?;
 
             if segments_btree.contains(&segment) {
                 try!(self.db.delete(&k));
@@ -325,16 +461,6 @@ impl RocksDBStore {
 
         // Purge the stored values
 
-        /// Converts stored value key strings "v1/2/3/v" into tuples of 3 i32s and a Vec<u8> (1, 2, 3, vec![b'v', b'a', b'l'])
-        fn parse_stored_value_key(key: &[u8]) -> (u32, u32, u32, Vec<u8>) {
-            let mut parts_iter = key[1..].split(|b| *b == b'/');
-            let segment = str::from_utf8(parts_iter.next().unwrap()).unwrap().parse::<u32>().unwrap();
-            let doc_id = str::from_utf8(parts_iter.next().unwrap()).unwrap().parse::<u32>().unwrap();
-            let field_id = str::from_utf8(parts_iter.next().unwrap()).unwrap().parse::<u32>().unwrap();
-            let value_type = parts_iter.next().unwrap().to_vec();
-
-            (segment, doc_id, field_id, value_type)
-        }
 
         for source_segment in segments.iter() {
             let kb = KeyBuilder::segment_stored_values_prefix(*source_segment);
@@ -348,7 +474,7 @@ impl RocksDBStore {
                     break;
                 }
 
-                let (segment, _, _, _) = parse_stored_value_key(&k);
+                let (segment, _, _, _) = parse_stored_value_key(&k)?;
 
                 if segment != *source_segment {
                     // Segment finished
@@ -361,17 +487,36 @@ impl RocksDBStore {
             }
         }
 
-        // Purge the statistics
+        // Purge the document keys
+
+        for source_segment in segments.iter() {
+            let kb = KeyBuilder::segment_document_keys_prefix(*source_segment);
+            let mut iter = self.db.raw_iterator();
+            iter.seek(&kb.key());
+            while iter.valid() {
+                let k = iter.key().unwrap();
+
+                if k[0] != b'i' {
+                    // No more document keys to delete
+                    break;
+                }
 
-        /// Converts statistic key strings "s1/total_docs" into tuples of 1 i32 and a Vec<u8> (1, ['t', 'o', 't', ...])
-        fn parse_statistic_key(key: &[u8]) -> (u32, Vec<u8>) {
-            let mut parts_iter = key[1..].split(|b| *b == b'/');
-            let segment = str::from_utf8(parts_iter.next().unwrap()).unwrap().parse::<u32>().unwrap();
-            let statistic_name = parts_iter.next().unwrap().to_vec();
+                let (segment, _) = parse_document_key_key(&k)?;
 
-            (segment, statistic_name)
+                if segment != *source_segment {
+                    // Segment finished
+                    break;
+                }
+
+                try!(self.db.delete_opt(&k, &write_options));
+
+                iter.next();
+            }
         }
 
+        // Purge the statistics
+
+
         for source_segment in segments.iter() {
             let kb = KeyBuilder::segment_stat_prefix(*source_segment);
             let mut iter = self.db.raw_iterator();
@@ -384,7 +529,7 @@ impl RocksDBStore {
                     break;
                 }
 
-                let (segment, _) = parse_statistic_key(&k);
+                let (segment, _) = parse_statistic_key(&k)?;
 
                 if segment != *source_segment {
                     // Segment finished
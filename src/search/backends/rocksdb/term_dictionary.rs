@@ -1,9 +1,10 @@
 use std::str;
 use std::sync::{Mutex, RwLock};
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use rocksdb::{self, DB};
+use fnv::FnvHashMap;
 use search::{Term, TermId};
 use search::query::multi_term_selector::MultiTermSelector;
 
@@ -16,9 +17,10 @@ use super::key_builder::KeyBuilder;
 ///
 /// The term dictionary is a mapping between terms and their internal IDs
 /// (aka. TermId). It is entirely held in memory and persisted to the disk.
+/// Terms are kept in order so fuzzy queries can skip over ranges of them.
 pub struct TermDictionaryManager {
     next_term_id: AtomicUsize,
-    terms: RwLock<HashMap<Term, TermId>>,
+    terms: RwLock<BTreeMap<Term, TermId>>,
     write_lock: Mutex<i32>,
 }
 
@@ -31,7 +33,7 @@ impl TermDictionaryManager {
 
         Ok(TermDictionaryManager {
             next_term_id: AtomicUsize::new(1),
-            terms: RwLock::new(HashMap::new()),
+            terms: RwLock::new(BTreeMap::new()),
             write_lock: Mutex::new(0),
         })
     }
@@ -46,7 +48,7 @@ impl TermDictionaryManager {
         };
 
         // Read dictionary
-        let mut terms = HashMap::new();
+        let mut terms = BTreeMap::new();
         let mut iter = db.raw_iterator();
         iter.seek(b"t");
         while iter.valid() {
@@ -74,14 +76,65 @@ impl TermDictionaryManager {
         self.terms.read().unwrap().get(term).cloned()
     }
 
+    /// Returns the term of each TermId
+    pub fn terms_by_id(&self) -> FnvHashMap<TermId, Term> {
+        self.terms.read().unwrap().iter()
+            .map(|(term, term_id)| (*term_id, term.clone()))
+            .collect()
+    }
+
     /// Iterates over terms in the dictionary which match the selector
     pub fn select(&self, term_selector: &MultiTermSelector) -> Vec<TermId> {
-        self.terms.read().unwrap().iter()
+        self.select_terms(term_selector).into_iter()
+            .map(|(_term, term_id)| term_id)
+            .collect()
+    }
+
+    /// Like select, but also returns the terms
+    pub fn select_terms(&self, term_selector: &MultiTermSelector) -> Vec<(Term, TermId)> {
+        if let MultiTermSelector::Fuzzy{ref automaton, max_expansions} = *term_selector {
+            // Walk the dictionary with the automaton rather than checking every term
+            let terms = self.terms.read().unwrap();
+            let mut matches = automaton.intersect(&terms);
+
+            // Keep the closest terms
+            matches.sort_by(|a, b| a.2.cmp(&b.2).then_with(|| a.0.cmp(b.0)));
+            matches.truncate(max_expansions);
+
+            return matches.into_iter()
+                .map(|(term, term_id, _distance)| (term.clone(), *term_id))
+                .collect();
+        }
+
+        if let MultiTermSelector::IntegerRange{gte, lte} = *term_selector {
+            // Integers are encoded in order, so only the terms between the bounds need
+            // to be checked. Other terms may be mixed in among them
+            let gte = Term::from_integer(gte.unwrap_or(i64::MIN));
+            let lte = Term::from_integer(lte.unwrap_or(i64::MAX));
+            if gte > lte {
+                return Vec::new();
+            }
+
+            return self.terms.read().unwrap().range(gte..=lte)
+                .filter(|&(term, _term_id)| term_selector.matches(term))
+                .map(|(term, term_id)| (term.clone(), *term_id))
+                .collect();
+        }
+
+        let mut terms = self.terms.read().unwrap().iter()
             .filter(|&(term, _term_id)| {
                 term_selector.matches(term)
             })
-            .map(|(_term, term_id)| *term_id)
-            .collect()
+            .map(|(term, term_id)| (term.clone(), *term_id))
+            .collect::<Vec<_>>();
+
+        // Sort the terms so the same ones are always picked
+        if let MultiTermSelector::Limit{max_terms, ..} = *term_selector {
+            terms.sort_by(|a, b| a.0.cmp(&b.0));
+            terms.truncate(max_terms);
+        }
+
+        terms
     }
 
     /// Retrieves the TermId for the given term, adding the term to the
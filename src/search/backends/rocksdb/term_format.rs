@@ -0,0 +1,191 @@
+//! Migrating stores to the current term format
+//!
+//! Stores in term format 1 encoded integer and date terms little-endian. Terms
+//! don't record their type, but postings lists, term statistics and term
+//! frequencies and positions are all keyed by field, and the schema has the type
+//! of each field. So the terms of integer and date fields can be found through
+//! those keys, re-encoded and given the ids of their new terms.
+
+use std::str;
+use std::collections::HashSet;
+
+use rocksdb::WriteBatch;
+use byteorder::{ByteOrder, LittleEndian};
+use fnv::FnvHashMap;
+
+use search::{Term, TermId};
+use search::schema::FieldType;
+
+use super::RocksDBStore;
+use super::key_builder::KeyBuilder;
+
+
+fn parse_id(id: &[u8], key: &[u8]) -> Result<u32, String> {
+    str::from_utf8(id).ok().and_then(|id| id.parse::<u32>().ok()).ok_or_else(|| format!("invalid key while migrating terms: {:?}", key))
+}
+
+
+/// Finds the ids of the terms in term format 2 that replace term format 1 integer terms
+struct TermMapping<'a> {
+    store: &'a RocksDBStore,
+    terms_by_id: FnvHashMap<TermId, Term>,
+    new_term_ids: FnvHashMap<u32, Option<u32>>,
+}
+
+
+impl<'a> TermMapping<'a> {
+    fn new(store: &'a RocksDBStore) -> TermMapping<'a> {
+        TermMapping {
+            store: store,
+            terms_by_id: store.term_dictionary.terms_by_id(),
+            new_term_ids: FnvHashMap::default(),
+        }
+    }
+
+    /// Returns the id of the re-encoded term, or None if the term isn't an integer
+    /// (such as the term that marks the field as having a value)
+    fn get(&mut self, term_id: u32) -> Result<Option<u32>, String> {
+        if let Some(new_term_id) = self.new_term_ids.get(&term_id) {
+            return Ok(*new_term_id);
+        }
+
+        let new_term_id = match self.terms_by_id.get(&TermId(term_id)) {
+            Some(term) if term.as_bytes().len() == 8 => {
+                let new_term = Term::from_integer(LittleEndian::read_i64(term.as_bytes()));
+                Some(self.store.term_dictionary.get_or_create(&self.store.db, &new_term)?.0)
+            }
+            _ => None,
+        };
+
+        self.new_term_ids.insert(term_id, new_term_id);
+        Ok(new_term_id)
+    }
+}
+
+
+impl RocksDBStore {
+    /// Converts the integer and date terms of a store in term format 1
+    ///
+    /// The renamed keys and the new term format are written in a single batch, so
+    /// a migration that's interrupted starts again from the beginning. The old
+    /// terms are left in the term dictionary, but nothing refers to them
+    pub fn migrate_integer_terms(&self) -> Result<(), String> {
+        let integer_fields = self.schema.iter()
+            .filter(|&(_, field_info)| field_info.field_type == FieldType::I64 || field_info.field_type == FieldType::DateTime)
+            .map(|(field_id, _)| field_id.0)
+            .collect::<HashSet<u32>>();
+
+        let mut mapping = TermMapping::new(self);
+        let mut deletes = Vec::new();
+        let mut puts = Vec::new();
+
+        // Postings lists: "d{field}/{term}/{segment}"
+        let mut iter = self.db.raw_iterator();
+        iter.seek(b"d");
+        while iter.valid() {
+            let k = iter.key().unwrap();
+
+            if k[0] != b'd' {
+                break;
+            }
+
+            let parts = k[1..].split(|b| *b == b'/').collect::<Vec<_>>();
+            if parts.len() != 3 {
+                return Err(format!("invalid key while migrating terms: {:?}", k));
+            }
+
+            let field_id = parse_id(parts[0], &k)?;
+            if integer_fields.contains(&field_id) {
+                if let Some(new_term_id) = mapping.get(parse_id(parts[1], &k)?)? {
+                    let kb = KeyBuilder::segment_postings_list(parse_id(parts[2], &k)?, field_id, new_term_id);
+                    puts.push((kb.key().to_vec(), iter.value().unwrap()));
+                    deletes.push(k);
+                }
+            }
+
+            iter.next();
+        }
+
+        // Term document frequencies: "s{segment}/tdf-{field}-{term}"
+        let mut iter = self.db.raw_iterator();
+        iter.seek(b"s");
+        while iter.valid() {
+            let k = iter.key().unwrap();
+
+            if k[0] != b's' {
+                break;
+            }
+
+            let mut parts = k[1..].splitn(2, |b| *b == b'/');
+            let segment = parse_id(parts.next().unwrap(), &k)?;
+            let name = parts.next().ok_or_else(|| format!("invalid key while migrating terms: {:?}", k))?;
+
+            if name.starts_with(b"tdf-") {
+                let ids = name[4..].split(|b| *b == b'-').collect::<Vec<_>>();
+                if ids.len() != 2 {
+                    return Err(format!("invalid key while migrating terms: {:?}", k));
+                }
+
+                let field_id = parse_id(ids[0], &k)?;
+                if integer_fields.contains(&field_id) {
+                    if let Some(new_term_id) = mapping.get(parse_id(ids[1], &k)?)? {
+                        let kb = KeyBuilder::segment_stat(segment, &KeyBuilder::segment_stat_term_doc_frequency_stat_name(field_id, new_term_id));
+                        puts.push((kb.key().to_vec(), iter.value().unwrap()));
+                        deletes.push(k);
+                    }
+                }
+            }
+
+            iter.next();
+        }
+
+        // Term frequencies and positions: "v{segment}/{doc}/{field}/tf{term}" and "tp{term}"
+        let mut iter = self.db.raw_iterator();
+        iter.seek(b"v");
+        while iter.valid() {
+            let k = iter.key().unwrap();
+
+            if k[0] != b'v' {
+                break;
+            }
+
+            let parts = k[1..].splitn(4, |b| *b == b'/').collect::<Vec<_>>();
+            if parts.len() != 4 {
+                return Err(format!("invalid key while migrating terms: {:?}", k));
+            }
+
+            let field_id = parse_id(parts[2], &k)?;
+            let value_type = parts[3];
+            if integer_fields.contains(&field_id) && (value_type.starts_with(b"tf") || value_type.starts_with(b"tp")) {
+                if let Some(new_term_id) = mapping.get(parse_id(&value_type[2..], &k)?)? {
+                    let segment = parse_id(parts[0], &k)?;
+                    let doc_id = parse_id(parts[1], &k)?;
+
+                    let mut new_value_type = value_type[..2].to_vec();
+                    new_value_type.extend(new_term_id.to_string().as_bytes());
+
+                    let kb = KeyBuilder::stored_field_value(segment, doc_id as u16, field_id, &new_value_type);
+                    let value = self.reseal_stored_value(&k, &kb.key(), &iter.value().unwrap())?;
+                    puts.push((kb.key().to_vec(), value));
+                    deletes.push(k);
+                }
+            }
+
+            iter.next();
+        }
+
+        // A new key may be the old key of another term, so every old key is
+        // deleted before the new ones are written
+        let mut write_batch = WriteBatch::default();
+        for key in deletes.iter() {
+            write_batch.delete(key)?;
+        }
+        for &(ref key, ref value) in puts.iter() {
+            write_batch.put(key, value)?;
+        }
+        write_batch.put(b".term_format", b"2")?;
+        self.db.write(write_batch)?;
+
+        Ok(())
+    }
+}
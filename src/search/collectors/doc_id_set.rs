@@ -0,0 +1,101 @@
+use roaring::RoaringBitmap;
+use fnv::FnvHashMap;
+
+use search::document::DocId;
+use search::segment::SegmentId;
+use search::collectors::{Collector, DocumentMatch};
+
+/// Collects the ids of every matching document into a bitmap for each segment,
+/// so the matches of different queries can be intersected
+#[derive(Debug)]
+pub struct DocIdSetCollector {
+    segments: FnvHashMap<u32, RoaringBitmap>,
+}
+
+impl DocIdSetCollector {
+    pub fn new() -> DocIdSetCollector {
+        DocIdSetCollector {
+            segments: FnvHashMap::default(),
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.segments.values().map(|bitmap| bitmap.len() as u64).sum()
+    }
+
+    pub fn contains(&self, doc_id: DocId) -> bool {
+        let DocId(segment, local_id) = doc_id;
+        self.segments.get(&segment.0).map_or(false, |bitmap| bitmap.contains(local_id as u32))
+    }
+
+    /// Returns the ids of the collected documents, in index order
+    pub fn doc_ids(&self) -> Vec<DocId> {
+        let mut segments = self.segments.iter().collect::<Vec<_>>();
+        segments.sort_by_key(|&(segment, _)| *segment);
+
+        segments.into_iter()
+            .flat_map(|(segment, bitmap)| bitmap.iter().map(move |local_id| DocId(SegmentId(*segment), local_id as u16)))
+            .collect()
+    }
+
+    /// Counts the documents that were collected by both collectors
+    pub fn intersection_len(&self, other: &DocIdSetCollector) -> u64 {
+        self.segments.iter()
+            .filter_map(|(segment, bitmap)| other.segments.get(segment).map(|other_bitmap| (bitmap & other_bitmap).len() as u64))
+            .sum()
+    }
+}
+
+impl Collector for DocIdSetCollector {
+    fn needs_score(&self) -> bool {
+        false
+    }
+
+    fn collect(&mut self, doc: DocumentMatch) {
+        let DocId(segment, local_id) = DocId::from_u64(doc.doc_id());
+        self.segments.entry(segment.0).or_insert_with(RoaringBitmap::new).insert(local_id as u32);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use search::document::DocId;
+    use search::segment::SegmentId;
+    use search::collectors::{Collector, DocumentMatch};
+    use super::DocIdSetCollector;
+
+    #[test]
+    fn test_doc_id_set_collector_intersection() {
+        let mut a = DocIdSetCollector::new();
+        let mut b = DocIdSetCollector::new();
+
+        // Same local ids in different segments are different documents
+        for doc_id in [1, 2, 1 << 16 | 1].iter() {
+            a.collect(DocumentMatch::new_unscored(*doc_id));
+        }
+
+        for doc_id in [2, 3, 2 << 16 | 1].iter() {
+            b.collect(DocumentMatch::new_unscored(*doc_id));
+        }
+
+        assert_eq!(a.len(), 3);
+        assert_eq!(b.len(), 3);
+        assert_eq!(a.intersection_len(&b), 1);
+        assert_eq!(a.intersection_len(&a), 3);
+    }
+
+    #[test]
+    fn test_doc_id_set_collector_doc_ids() {
+        let mut collector = DocIdSetCollector::new();
+
+        for doc_id in [2 << 16 | 1, 3, 1].iter() {
+            collector.collect(DocumentMatch::new_unscored(*doc_id));
+        }
+
+        assert_eq!(collector.doc_ids(), vec![
+            DocId(SegmentId(0), 1),
+            DocId(SegmentId(0), 3),
+            DocId(SegmentId(2), 1),
+        ]);
+    }
+}
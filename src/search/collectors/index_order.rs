@@ -0,0 +1,153 @@
+use std::cmp;
+use std::collections::BinaryHeap;
+
+use search::collectors::{Collector, DocumentMatch};
+
+/// Collects the first documents in index order
+///
+/// Index order is the order of the segments, then of the documents within each one. This is
+/// the cheapest sort order. As documents within each segment are matched in index order, this
+/// collector only needs the first `max_docs` matches from each segment. The remaining matches
+/// are only counted.
+///
+/// This isn't the order documents were indexed in. Merges move documents into a new segment,
+/// after the ones that weren't merged, and sort them if the index is sorted.
+///
+/// Segments are searched in order. With a total limit, the search stops once the
+/// first `max_docs` documents have been found and more than the limit have been counted.
+#[derive(Debug)]
+pub struct IndexOrderCollector {
+    max_docs: usize,
+    heap: BinaryHeap<u64>,
+    total_count: u64,
+    total_limit: Option<u64>,
+}
+
+impl IndexOrderCollector {
+    pub fn new(max_docs: usize) -> IndexOrderCollector {
+        IndexOrderCollector {
+            max_docs: max_docs,
+            heap: BinaryHeap::with_capacity(max_docs + 1),
+            total_count: 0,
+            total_limit: None,
+        }
+    }
+
+    /// Stops counting matches once there are more than `limit` of them
+    pub fn with_total_limit(mut self, limit: u64) -> IndexOrderCollector {
+        self.total_limit = Some(limit);
+        self
+    }
+
+    /// The total number of documents that matched, including ones that weren't collected
+    ///
+    /// If there's a limit, this stops at one more than the limit.
+    pub fn get_total_count(&self) -> u64 {
+        self.total_count
+    }
+
+    fn count(&mut self, num_docs: u64) {
+        self.total_count += num_docs;
+
+        if let Some(limit) = self.total_limit {
+            self.total_count = cmp::min(self.total_count, limit + 1);
+        }
+    }
+
+    pub fn into_sorted_vec(self) -> Vec<DocumentMatch> {
+        self.heap.into_sorted_vec().iter()
+            .map(|doc_id| DocumentMatch::new_unscored(*doc_id))
+            .collect()
+    }
+}
+
+impl Collector for IndexOrderCollector {
+    fn needs_score(&self) -> bool {
+        false
+    }
+
+    fn segment_limit(&self) -> Option<usize> {
+        Some(self.max_docs)
+    }
+
+    fn collect(&mut self, doc: DocumentMatch) {
+        self.count(1);
+
+        // Keep the lowest document ids
+        self.heap.push(doc.doc_id());
+        if self.heap.len() > self.max_docs {
+            self.heap.pop();
+        }
+    }
+
+    fn skip(&mut self, num_docs: u64) {
+        self.count(num_docs);
+    }
+
+    fn is_finished(&self) -> bool {
+        // Later segments only have higher document ids, so they can't change the hits
+        self.heap.len() >= self.max_docs && self.total_limit.map_or(false, |limit| self.total_count > limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use search::collectors::{Collector, DocumentMatch};
+    use super::IndexOrderCollector;
+
+    #[test]
+    fn test_index_order_collector_inital_state() {
+        let collector = IndexOrderCollector::new(10);
+
+        assert_eq!(collector.get_total_count(), 0);
+        assert_eq!(collector.into_sorted_vec().len(), 0);
+    }
+
+    #[test]
+    fn test_index_order_collector_needs_score() {
+        let collector = IndexOrderCollector::new(10);
+
+        assert_eq!(collector.needs_score(), false);
+        assert_eq!(collector.segment_limit(), Some(10));
+    }
+
+    #[test]
+    fn test_index_order_collector_collect() {
+        let mut collector = IndexOrderCollector::new(2);
+
+        // Documents from a later segment may be collected first
+        collector.collect(DocumentMatch::new_unscored(1 << 16 | 0));
+        collector.collect(DocumentMatch::new_unscored(1 << 16 | 1));
+        collector.skip(5);
+        collector.collect(DocumentMatch::new_unscored(0));
+        collector.collect(DocumentMatch::new_unscored(3));
+
+        assert_eq!(collector.get_total_count(), 9);
+
+        let docs = collector.into_sorted_vec();
+        assert_eq!(docs.len(), 2);
+        assert_eq!(docs[0].doc_id(), 0);
+        assert_eq!(docs[1].doc_id(), 3);
+    }
+
+    #[test]
+    fn test_index_order_collector_total_limit() {
+        let mut collector = IndexOrderCollector::new(2).with_total_limit(3);
+
+        collector.collect(DocumentMatch::new_unscored(0));
+        collector.collect(DocumentMatch::new_unscored(1));
+        assert_eq!(collector.is_finished(), false);
+
+        collector.skip(5);
+        assert_eq!(collector.get_total_count(), 4);
+        assert_eq!(collector.is_finished(), true);
+
+        // Without a limit, all of the matches are counted
+        let mut collector = IndexOrderCollector::new(2);
+        collector.collect(DocumentMatch::new_unscored(0));
+        collector.collect(DocumentMatch::new_unscored(1));
+        collector.skip(5);
+        assert_eq!(collector.get_total_count(), 7);
+        assert_eq!(collector.is_finished(), false);
+    }
+}
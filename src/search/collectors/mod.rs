@@ -1,5 +1,8 @@
 pub mod total_count;
 pub mod top_score;
+pub mod index_order;
+pub mod doc_id_set;
+pub mod sort_value;
 
 #[derive(Debug)]
 pub struct DocumentMatch {
@@ -36,4 +39,21 @@ impl DocumentMatch {
 pub trait Collector {
     fn needs_score(&self) -> bool;
     fn collect(&mut self, doc: DocumentMatch);
+
+    /// The maximum number of matches this collector needs from each segment
+    ///
+    /// Matches are passed to the collector in index order, so collectors that only need the
+    /// first few documents can use this to avoid scoring the rest
+    fn segment_limit(&self) -> Option<usize> {
+        None
+    }
+
+    /// Called with the number of matches in a segment that were not collected because of the segment limit
+    fn skip(&mut self, _num_docs: u64) {}
+
+    /// Checked before each segment is searched. Collectors that already have everything they
+    /// need return true, so the remaining segments aren't searched
+    fn is_finished(&self) -> bool {
+        false
+    }
 }
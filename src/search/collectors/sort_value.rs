@@ -0,0 +1,190 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use search::collectors::{Collector, DocumentMatch};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct SortedDocument {
+    id: u64,
+    score: Option<f32>,
+    value: Option<f64>,
+
+    /// The value to order by, negated when sorting in descending order. NaN is never stored here
+    key: Option<f64>,
+}
+
+impl Eq for SortedDocument {}
+
+impl Ord for SortedDocument {
+    fn cmp(&self, other: &SortedDocument) -> Ordering {
+        // Documents without a value come last, ties are broken by index order
+        let key_ordering = match (self.key, other.key) {
+            (Some(a), Some(b)) => a.partial_cmp(&b).unwrap(),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        };
+
+        key_ordering.then_with(|| self.id.cmp(&other.id))
+    }
+}
+
+impl PartialOrd for SortedDocument {
+    fn partial_cmp(&self, other: &SortedDocument) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Collects the documents with the lowest (or highest) sort values
+///
+/// The sort value of each matching document is worked out by a function of its id
+/// and score, such as a script or the distance to the points in one of its fields.
+/// Documents that the function gives no value for are sorted after the rest.
+///
+/// If the index is sorted in the same order, the documents in each segment are
+/// already sorted so only the first `max_docs` matches of each one are collected.
+pub struct SortValueCollector<F: FnMut(u64, Option<f32>) -> Option<f64>> {
+    max_docs: usize,
+    descending: bool,
+    needs_score: bool,
+    sorted_segments: bool,
+    sort_value: F,
+    heap: BinaryHeap<SortedDocument>,
+    total_count: u64,
+}
+
+impl<F: FnMut(u64, Option<f32>) -> Option<f64>> SortValueCollector<F> {
+    pub fn new(max_docs: usize, descending: bool, needs_score: bool, sort_value: F) -> SortValueCollector<F> {
+        SortValueCollector {
+            max_docs: max_docs,
+            descending: descending,
+            needs_score: needs_score,
+            sorted_segments: false,
+            sort_value: sort_value,
+            heap: BinaryHeap::with_capacity(max_docs + 1),
+            total_count: 0,
+        }
+    }
+
+    /// Only collects the first matches of each segment, as the documents in each
+    /// segment are in the same order as this sort
+    pub fn with_sorted_segments(mut self) -> SortValueCollector<F> {
+        self.sorted_segments = true;
+        self
+    }
+
+    /// The total number of documents that matched, including ones that didn't make the top
+    pub fn get_total_count(&self) -> u64 {
+        self.total_count
+    }
+
+    /// Returns the collected documents in order, along with their sort values
+    pub fn into_sorted_vec(self) -> Vec<(DocumentMatch, Option<f64>)> {
+        self.heap.into_sorted_vec().iter()
+            .map(|sorted_document| {
+                let doc_match = match sorted_document.score {
+                    Some(score) => DocumentMatch::new_scored(sorted_document.id, score),
+                    None => DocumentMatch::new_unscored(sorted_document.id),
+                };
+
+                (doc_match, sorted_document.value)
+            })
+            .collect()
+    }
+}
+
+impl<F: FnMut(u64, Option<f32>) -> Option<f64>> Collector for SortValueCollector<F> {
+    fn needs_score(&self) -> bool {
+        self.needs_score
+    }
+
+    fn segment_limit(&self) -> Option<usize> {
+        if self.sorted_segments {
+            Some(self.max_docs)
+        } else {
+            None
+        }
+    }
+
+    fn skip(&mut self, num_docs: u64) {
+        self.total_count += num_docs;
+    }
+
+    fn collect(&mut self, doc: DocumentMatch) {
+        let value = (self.sort_value)(doc.doc_id(), doc.score()).and_then(|value| {
+            if value.is_nan() { None } else { Some(value) }
+        });
+        let descending = self.descending;
+
+        self.heap.push(SortedDocument {
+            id: doc.doc_id(),
+            score: doc.score(),
+            value: value,
+            key: value.map(|value| if descending { -value } else { value }),
+        });
+        self.total_count += 1;
+
+        // Drop the last document if there's too many
+        if self.heap.len() > self.max_docs {
+            self.heap.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use search::collectors::{Collector, DocumentMatch};
+    use super::SortValueCollector;
+
+    #[test]
+    fn test_sort_value_collector_ascending() {
+        let values = [3.0, 1.0, 2.0, 5.0];
+        let mut collector = SortValueCollector::new(3, false, false, |doc_id, _score| Some(values[doc_id as usize]));
+
+        for doc_id in 0..4 {
+            collector.collect(DocumentMatch::new_unscored(doc_id));
+        }
+
+        assert_eq!(collector.get_total_count(), 4);
+
+        let docs = collector.into_sorted_vec();
+        assert_eq!(docs.iter().map(|&(ref doc, value)| (doc.doc_id(), value)).collect::<Vec<_>>(), vec![
+            (1, Some(1.0)),
+            (2, Some(2.0)),
+            (0, Some(3.0)),
+        ]);
+    }
+
+    #[test]
+    fn test_sort_value_collector_descending_with_missing_values() {
+        let mut collector = SortValueCollector::new(10, true, true, |doc_id, score| {
+            if doc_id == 1 { None } else { score.map(|score| score as f64 * 2.0) }
+        });
+
+        collector.collect(DocumentMatch::new_scored(0, 1.0f32));
+        collector.collect(DocumentMatch::new_scored(1, 5.0f32));
+        collector.collect(DocumentMatch::new_scored(2, 2.0f32));
+
+        let docs = collector.into_sorted_vec();
+        assert_eq!(docs.iter().map(|&(ref doc, value)| (doc.doc_id(), value)).collect::<Vec<_>>(), vec![
+            (2, Some(4.0)),
+            (0, Some(2.0)),
+            (1, None),
+        ]);
+        assert_eq!(docs[0].0.score(), Some(2.0f32));
+    }
+
+    #[test]
+    fn test_sort_value_collector_sorted_segments() {
+        let collector = SortValueCollector::new(3, false, false, |_doc_id, _score| None);
+        assert_eq!(collector.segment_limit(), None);
+
+        let mut collector = collector.with_sorted_segments();
+        assert_eq!(collector.segment_limit(), Some(3));
+
+        // Matches past the limit are still counted
+        collector.collect(DocumentMatch::new_unscored(0));
+        collector.skip(4);
+        assert_eq!(collector.get_total_count(), 5);
+    }
+}
@@ -49,6 +49,8 @@ impl PartialOrd for ScoredDocument {
 pub struct TopScoreCollector {
     max_docs: usize,
     heap: BinaryHeap<ScoredDocument>,
+    total_count: u64,
+    total_limit: Option<u64>,
 }
 
 impl TopScoreCollector {
@@ -56,9 +58,31 @@ impl TopScoreCollector {
         TopScoreCollector {
             max_docs: max_docs,
             heap: BinaryHeap::with_capacity(max_docs + 1),
+            total_count: 0,
+            total_limit: None,
         }
     }
 
+    /// Stops counting matches once there are more than `limit` of them
+    ///
+    /// Every match still has to be scored to find the top ones, so this only
+    /// stops the search early if no documents are kept.
+    pub fn with_total_limit(mut self, limit: u64) -> TopScoreCollector {
+        self.total_limit = Some(limit);
+        self
+    }
+
+    /// The total number of documents that matched, including ones that didn't make the top
+    ///
+    /// If there's a limit, this stops at one more than the limit.
+    pub fn get_total_count(&self) -> u64 {
+        self.total_count
+    }
+
+    fn is_counting_finished(&self) -> bool {
+        self.total_limit.map_or(false, |limit| self.total_count > limit)
+    }
+
     pub fn into_sorted_vec(self) -> Vec<DocumentMatch> {
         self.heap.into_sorted_vec().iter()
             .map(|scored_document| {
@@ -101,12 +125,19 @@ impl Collector for TopScoreCollector {
 
         // Now insert the document into the heap
         self.heap.push(scored_document);
+        if !self.is_counting_finished() {
+            self.total_count += 1;
+        }
 
         // Now reduce the heap size if it's too big
         if self.heap.len() > self.max_docs {
             self.heap.pop();
         }
     }
+
+    fn is_finished(&self) -> bool {
+        self.max_docs == 0 && self.is_counting_finished()
+    }
 }
 
 #[cfg(test)]
@@ -154,9 +185,32 @@ mod tests {
         collector.collect(DocumentMatch::new_scored(1, 0.5f32));
         collector.collect(DocumentMatch::new_scored(2, 2.0f32));
 
+        assert_eq!(collector.get_total_count(), 3);
+
         let docs = collector.into_sorted_vec();
         assert_eq!(docs.len(), 2);
         assert_eq!(docs[0].id, 2);
         assert_eq!(docs[1].id, 0);
     }
+
+    #[test]
+    fn test_top_score_collector_total_limit() {
+        let mut collector = TopScoreCollector::new(1).with_total_limit(2);
+
+        for doc_id in 0..5 {
+            collector.collect(DocumentMatch::new_scored(doc_id, doc_id as f32));
+        }
+
+        // Counting stops past the limit, but every match is still considered for the top
+        assert_eq!(collector.get_total_count(), 3);
+        assert_eq!(collector.is_finished(), false);
+        assert_eq!(collector.into_sorted_vec()[0].id, 4);
+
+        // Only counting searches can stop early
+        let mut collector = TopScoreCollector::new(0).with_total_limit(2);
+        for doc_id in 0..3 {
+            collector.collect(DocumentMatch::new_scored(doc_id, 1.0f32));
+        }
+        assert_eq!(collector.is_finished(), true);
+    }
 }
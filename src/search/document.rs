@@ -5,6 +5,7 @@ use fnv::FnvHashMap;
 use search::term_vector::TermVector;
 use search::schema::FieldId;
 use search::segment::SegmentId;
+use search::query::wildcard::VALUE_SEPARATOR;
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub struct DocId(pub SegmentId, pub u16);
@@ -25,6 +26,14 @@ impl DocId {
 pub enum FieldValue {
     String(String),
     Integer(i64),
+
+    /// The values of an integer field that was given an array. These are
+    /// stored one after the other
+    IntegerArray(Vec<i64>),
+
+    /// The values of a string field that was given an array. These are stored
+    /// one after the other, separated by VALUE_SEPARATOR
+    StringArray(Vec<String>),
     Boolean(bool),
     DateTime(DateTime<Utc>),
 }
@@ -46,6 +55,14 @@ impl FieldValue {
                 bytes.write_i64::<LittleEndian>(value).unwrap();
                 bytes
             }
+            FieldValue::IntegerArray(ref values) => {
+                let mut bytes = Vec::with_capacity(values.len() * 8);
+                for value in values.iter() {
+                    bytes.write_i64::<LittleEndian>(*value).unwrap();
+                }
+                bytes
+            }
+            FieldValue::StringArray(ref strings) => strings.join(&VALUE_SEPARATOR.to_string()).into_bytes(),
             FieldValue::Boolean(value) => {
                 if value {
                     vec![b't']
@@ -63,6 +80,18 @@ impl FieldValue {
             }
         }
     }
+
+    /// The value as numbers, for sorting and aggregating. Booleans are given as
+    /// 0 or 1 and dates are given in milliseconds since the epoch
+    pub fn as_numbers(&self) -> Vec<f64> {
+        match *self {
+            FieldValue::Integer(value) => vec![value as f64],
+            FieldValue::IntegerArray(ref values) => values.iter().map(|value| *value as f64).collect(),
+            FieldValue::Boolean(value) => vec![if value { 1.0 } else { 0.0 }],
+            FieldValue::DateTime(value) => vec![(value.timestamp() * 1000 + value.timestamp_subsec_millis() as i64) as f64],
+            FieldValue::String(_) | FieldValue::StringArray(_) => Vec::new(),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -70,4 +99,7 @@ pub struct Document {
     pub key: String,
     pub indexed_fields: FnvHashMap<FieldId, TermVector>,
     pub stored_fields: FnvHashMap<FieldId, FieldValue>,
+
+    /// Documents for the objects of nested fields. These are indexed just before this document
+    pub nested_documents: Vec<Document>,
 }
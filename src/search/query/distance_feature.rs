@@ -0,0 +1,69 @@
+//! Distance features
+//!
+//! Distance feature queries score documents by how close the value of a
+//! field is to an origin, giving pivot / (pivot + distance). This is cheaper
+//! than a decay function as it's worked out directly from the stored value of
+//! the field. Dates and integers are only stored if the field is mapped with
+//! "store", documents without a stored value score zero.
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use search::query::geo_shape::Coordinate;
+use search::query::geo_distance::stored_point_distance;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DistanceFeatureOrigin {
+    Integer(i64),
+
+    /// Microseconds since the epoch, which is how dates are stored
+    Date(i64),
+
+    GeoPoint(Coordinate),
+}
+
+impl DistanceFeatureOrigin {
+    /// Finds the distance from the origin to the stored value of a field. For
+    /// fields with many points, the closest one is used
+    pub fn distance(&self, stored_value: &[u8]) -> Option<f64> {
+        match *self {
+            DistanceFeatureOrigin::Integer(origin) | DistanceFeatureOrigin::Date(origin) => {
+                if stored_value.len() != 8 {
+                    return None;
+                }
+
+                Some((LittleEndian::read_i64(stored_value) as f64 - origin as f64).abs())
+            }
+            DistanceFeatureOrigin::GeoPoint(origin) => stored_point_distance(origin, stored_value),
+        }
+    }
+}
+
+pub fn distance_feature_score(distance: f64, pivot: f64) -> f32 {
+    (pivot / (pivot + distance)) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{ByteOrder, LittleEndian};
+
+    use super::{DistanceFeatureOrigin, distance_feature_score};
+
+    #[test]
+    fn test_distance() {
+        let mut bytes = [0; 8];
+        LittleEndian::write_i64(&mut bytes, 70);
+        assert_eq!(DistanceFeatureOrigin::Integer(100).distance(&bytes), Some(30.0));
+        assert_eq!(DistanceFeatureOrigin::Integer(100).distance(b"foo"), None);
+
+        // The closest point is used
+        let origin = DistanceFeatureOrigin::GeoPoint((4.9, 52.37));
+        let distance = origin.distance(br#"{"type": "multipoint", "coordinates": [[2.35, 48.86], [4.9, 52.37]]}"#);
+        assert_eq!(distance, Some(0.0));
+    }
+
+    #[test]
+    fn test_distance_feature_score() {
+        assert_eq!(distance_feature_score(0.0, 10.0), 1.0);
+        assert_eq!(distance_feature_score(10.0, 10.0), 0.5);
+    }
+}
@@ -0,0 +1,15 @@
+//! Field exists queries
+//!
+//! Every document is indexed with a marker term in each field that it has at
+//! least one token in, so finding the documents that have a value for a field
+//! is a single postings list lookup.
+
+use search::term::Term;
+
+/// The marker term. 0xFF can't appear in UTF-8, so this can't be confused with
+/// a value or be matched by wildcard or fuzzy queries
+pub const FIELD_EXISTS_TERM: &'static [u8] = &[0xFF];
+
+pub fn field_exists_term() -> Term {
+    Term::from_bytes(FIELD_EXISTS_TERM)
+}
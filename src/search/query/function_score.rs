@@ -0,0 +1,272 @@
+//! Function scores
+//!
+//! Function score queries replace or adjust the score of another query using
+//! functions of each matching document. Functions can be limited to documents
+//! that match a filter, and their results are multiplied by a weight before
+//! being combined.
+//!
+//! Functions that use the value of a field read its stored value, so these
+//! fields must be mapped with "store".
+
+use std::hash::Hasher;
+
+use fnv::FnvHasher;
+use byteorder::{ByteOrder, LittleEndian};
+
+use search::schema::FieldId;
+use search::Query;
+use search::query::distance_feature::DistanceFeatureOrigin;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScoreFunction {
+    /// Scores every document as 1, leaving just the weight
+    Weight,
+
+    /// Scores each document with a pseudo-random number between 0 and 1
+    /// Documents get the same scores with the same seed. If a field is given,
+    /// this is worked out from the value of the field, otherwise it is worked
+    /// out from the internal ID of the document, which changes when segments
+    /// are merged
+    RandomScore {
+        seed: u64,
+        field: Option<FieldId>,
+    },
+
+    /// Scores each document by the value of a numeric field, multiplied by the
+    /// factor before the modifier is applied. Documents without a value use
+    /// the missing value, or aren't scored by the function if there isn't one
+    FieldValueFactor {
+        field: FieldId,
+        factor: f64,
+        modifier: FieldValueModifier,
+        missing: Option<f64>,
+    },
+
+    /// Scores each document by how far the value of the field is from the
+    /// origin. Documents score 1 within the offset of the origin, falling to
+    /// the decay at the scale past the offset. Documents without a value score 1
+    Decay {
+        field: FieldId,
+        function: DecayFunction,
+        origin: DistanceFeatureOrigin,
+        scale: f64,
+        offset: f64,
+        decay: f64,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldValueModifier {
+    None,
+    Log,
+    Log1p,
+    Log2p,
+    Ln,
+    Ln1p,
+    Ln2p,
+    Square,
+    Sqrt,
+    Reciprocal,
+}
+
+impl FieldValueModifier {
+    /// Applies the modifier. Values that the modifier isn't defined for, such
+    /// as the log of a negative number, give 0
+    pub fn apply(&self, value: f64) -> f64 {
+        let result = match *self {
+            FieldValueModifier::None => value,
+            FieldValueModifier::Log => value.log10(),
+            FieldValueModifier::Log1p => (value + 1.0).log10(),
+            FieldValueModifier::Log2p => (value + 2.0).log10(),
+            FieldValueModifier::Ln => value.ln(),
+            FieldValueModifier::Ln1p => value.ln_1p(),
+            FieldValueModifier::Ln2p => (value + 2.0).ln(),
+            FieldValueModifier::Square => value * value,
+            FieldValueModifier::Sqrt => value.sqrt(),
+            FieldValueModifier::Reciprocal => 1.0 / value,
+        };
+
+        if result.is_finite() { result } else { 0.0 }
+    }
+}
+
+/// The shape of the curve of a decay function
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DecayFunction {
+    Gauss,
+    Linear,
+    Exp,
+}
+
+impl DecayFunction {
+    /// Scores a distance from the origin. The curves are set so that distances
+    /// of the offset plus the scale score the decay
+    pub fn score(&self, distance: f64, scale: f64, offset: f64, decay: f64) -> f32 {
+        let distance = (distance - offset).max(0.0);
+
+        let score = match *self {
+            DecayFunction::Gauss => {
+                let variance = -scale * scale / (2.0 * decay.ln());
+                (-distance * distance / (2.0 * variance)).exp()
+            }
+            DecayFunction::Linear => {
+                let zero_at = scale / (1.0 - decay);
+                ((zero_at - distance) / zero_at).max(0.0)
+            }
+            DecayFunction::Exp => (decay.ln() / scale * distance).exp(),
+        };
+
+        score as f32
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilteredScoreFunction {
+    /// Only documents that match the filter are scored by the function
+    pub filter: Option<Query>,
+    pub function: ScoreFunction,
+    pub weight: f32,
+}
+
+/// How the results of the functions are combined
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScoreMode {
+    Multiply,
+    Sum,
+    Avg,
+    First,
+    Max,
+    Min,
+}
+
+impl ScoreMode {
+    /// Combines the results of the functions that matched the document. If
+    /// none of them matched, the document scores 1
+    pub fn combine(&self, scores: &[f32]) -> f32 {
+        if scores.is_empty() {
+            return 1.0f32;
+        }
+
+        match *self {
+            ScoreMode::Multiply => scores.iter().product(),
+            ScoreMode::Sum => scores.iter().sum(),
+            ScoreMode::Avg => scores.iter().sum::<f32>() / scores.len() as f32,
+            ScoreMode::First => scores[0],
+            ScoreMode::Max => scores.iter().cloned().fold(scores[0], f32::max),
+            ScoreMode::Min => scores.iter().cloned().fold(scores[0], f32::min),
+        }
+    }
+}
+
+/// How the combined result of the functions is combined with the query score
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BoostMode {
+    Multiply,
+    Replace,
+    Sum,
+    Avg,
+    Max,
+    Min,
+}
+
+impl BoostMode {
+    pub fn combine(&self, query_score: f32, function_score: f32) -> f32 {
+        match *self {
+            BoostMode::Multiply => query_score * function_score,
+            BoostMode::Replace => function_score,
+            BoostMode::Sum => query_score + function_score,
+            BoostMode::Avg => (query_score + function_score) / 2.0f32,
+            BoostMode::Max => query_score.max(function_score),
+            BoostMode::Min => query_score.min(function_score),
+        }
+    }
+}
+
+/// Hashes the value with the seed to get a number between 0 and 1. FNV is
+/// used because, unlike the standard library's hasher, its output won't
+/// change between versions
+pub fn random_score(seed: u64, value: &[u8]) -> f32 {
+    let mut hasher = FnvHasher::default();
+    hasher.write_u64(seed);
+    hasher.write(value);
+
+    // Mix the bits as FNV doesn't spread the final bytes of the value over
+    // the high bits of the hash
+    let mut hash = hasher.finish();
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+
+    // Use 24 bits so the score can be represented exactly
+    (hash >> 40) as f32 / (1u32 << 24) as f32
+}
+
+/// Reads the stored value of an integer or date field
+pub fn stored_numeric_value(stored_value: &[u8]) -> Option<f64> {
+    if stored_value.len() != 8 {
+        return None;
+    }
+
+    Some(LittleEndian::read_i64(stored_value) as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ScoreMode, BoostMode, FieldValueModifier, DecayFunction, random_score};
+
+    #[test]
+    fn test_score_modes() {
+        let scores = [2.0f32, 4.0f32, 1.0f32];
+
+        assert_eq!(ScoreMode::Multiply.combine(&scores), 8.0f32);
+        assert_eq!(ScoreMode::Sum.combine(&scores), 7.0f32);
+        assert_eq!(ScoreMode::Avg.combine(&scores), 7.0f32 / 3.0f32);
+        assert_eq!(ScoreMode::First.combine(&scores), 2.0f32);
+        assert_eq!(ScoreMode::Max.combine(&scores), 4.0f32);
+        assert_eq!(ScoreMode::Min.combine(&scores), 1.0f32);
+        assert_eq!(ScoreMode::Sum.combine(&[]), 1.0f32);
+    }
+
+    #[test]
+    fn test_boost_modes() {
+        assert_eq!(BoostMode::Multiply.combine(2.0f32, 3.0f32), 6.0f32);
+        assert_eq!(BoostMode::Replace.combine(2.0f32, 3.0f32), 3.0f32);
+        assert_eq!(BoostMode::Avg.combine(2.0f32, 3.0f32), 2.5f32);
+        assert_eq!(BoostMode::Min.combine(2.0f32, 3.0f32), 2.0f32);
+    }
+
+    #[test]
+    fn test_field_value_modifiers() {
+        assert_eq!(FieldValueModifier::None.apply(4.0), 4.0);
+        assert_eq!(FieldValueModifier::Log1p.apply(99.0), 2.0);
+        assert_eq!(FieldValueModifier::Sqrt.apply(4.0), 2.0);
+        assert_eq!(FieldValueModifier::Reciprocal.apply(4.0), 0.25);
+        assert_eq!(FieldValueModifier::Log.apply(-1.0), 0.0);
+        assert_eq!(FieldValueModifier::Reciprocal.apply(0.0), 0.0);
+    }
+
+    #[test]
+    fn test_decay_functions() {
+        for function in [DecayFunction::Gauss, DecayFunction::Linear, DecayFunction::Exp].iter() {
+            assert_eq!(function.score(5.0, 10.0, 5.0, 0.5), 1.0);
+            assert!((function.score(15.0, 10.0, 5.0, 0.5) - 0.5).abs() < 1e-6);
+            assert!(function.score(25.0, 10.0, 5.0, 0.5) < 0.5);
+        }
+
+        // Linear decays reach zero
+        assert_eq!(DecayFunction::Linear.score(100.0, 10.0, 0.0, 0.5), 0.0);
+    }
+
+    #[test]
+    fn test_random_score() {
+        // The same seed and value always give the same score
+        assert_eq!(random_score(42, b"doc1"), random_score(42, b"doc1"));
+        assert!(random_score(42, b"doc1") != random_score(43, b"doc1"));
+        assert!(random_score(42, b"doc1") != random_score(42, b"doc2"));
+
+        for i in 0..1000u32 {
+            let score = random_score(7, i.to_string().as_bytes());
+            assert!(score >= 0.0f32 && score < 1.0f32);
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! Geographic distances
+//!
+//! Distances between geo points are worked out along the surface of the
+//! earth, which is treated as a sphere. Distances are given in metres unless
+//! they have a unit, such as "12km".
+
+use serde_json;
+
+use search::query::geo_shape::{Geometry, Coordinate, CoordinateSystem};
+
+/// The mean radius of the earth in metres
+const EARTH_RADIUS: f64 = 6371008.7714;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DistanceUnit {
+    Millimetres,
+    Centimetres,
+    Metres,
+    Kilometres,
+    Inches,
+    Feet,
+    Yards,
+    Miles,
+    NauticalMiles,
+}
+
+impl DistanceUnit {
+    pub fn from_str(unit: &str) -> Option<DistanceUnit> {
+        match unit {
+            "mm" | "millimeters" => Some(DistanceUnit::Millimetres),
+            "cm" | "centimeters" => Some(DistanceUnit::Centimetres),
+            "m" | "meters" => Some(DistanceUnit::Metres),
+            "km" | "kilometers" => Some(DistanceUnit::Kilometres),
+            "in" | "inch" => Some(DistanceUnit::Inches),
+            "ft" | "feet" => Some(DistanceUnit::Feet),
+            "yd" | "yards" => Some(DistanceUnit::Yards),
+            "mi" | "miles" => Some(DistanceUnit::Miles),
+            "nmi" | "NM" => Some(DistanceUnit::NauticalMiles),
+            _ => None,
+        }
+    }
+
+    /// The number of metres in one of this unit
+    pub fn metres(&self) -> f64 {
+        match *self {
+            DistanceUnit::Millimetres => 0.001,
+            DistanceUnit::Centimetres => 0.01,
+            DistanceUnit::Metres => 1.0,
+            DistanceUnit::Kilometres => 1000.0,
+            DistanceUnit::Inches => 0.0254,
+            DistanceUnit::Feet => 0.3048,
+            DistanceUnit::Yards => 0.9144,
+            DistanceUnit::Miles => 1609.344,
+            DistanceUnit::NauticalMiles => 1852.0,
+        }
+    }
+}
+
+impl Default for DistanceUnit {
+    fn default() -> DistanceUnit {
+        DistanceUnit::Metres
+    }
+}
+
+/// Finds the distance in metres between two longitude, latitude pairs
+pub fn haversine_distance(a: Coordinate, b: Coordinate) -> f64 {
+    let (lat_a, lat_b) = (a.1.to_radians(), b.1.to_radians());
+    let delta_lat = lat_b - lat_a;
+    let delta_lon = (b.0 - a.0).to_radians();
+
+    let h = (delta_lat / 2.0).sin().powi(2) + lat_a.cos() * lat_b.cos() * (delta_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS * h.sqrt().min(1.0).asin()
+}
+
+/// Parses a distance with an optional unit, such as "12km", into metres
+pub fn parse_distance(distance: &str) -> Option<f64> {
+    let distance = distance.trim();
+    let unit_start = distance.find(|c: char| c.is_alphabetic()).unwrap_or(distance.len());
+    let (value, unit) = distance.split_at(unit_start);
+
+    let unit = match unit {
+        "" => DistanceUnit::default(),
+        unit => DistanceUnit::from_str(unit)?,
+    };
+
+    value.trim().parse::<f64>().ok().map(|value| value * unit.metres())
+}
+
+/// Finds the distance in metres from the origin to the closest point in the
+/// stored value of a geo_point field
+pub fn stored_point_distance(origin: Coordinate, stored_value: &[u8]) -> Option<f64> {
+    let json = serde_json::from_slice(stored_value).ok()?;
+    let points = match Geometry::from_json(&json, CoordinateSystem::Geographic)? {
+        Geometry::Point(point) => vec![point],
+        Geometry::MultiPoint(points) => points,
+        _ => return None,
+    };
+
+    points.into_iter().map(|point| haversine_distance(origin, point)).fold(None, |closest: Option<f64>, distance| {
+        Some(closest.map_or(distance, |closest| closest.min(distance)))
+    })
+}
+
+/// Finds a rectangle that holds every point within the distance of the origin
+///
+/// This is used to find candidates for geo distance queries from the tiles that
+/// points are indexed with. Rectangles that would cross a pole or the
+/// antimeridian take all longitudes instead.
+pub fn bounding_box(origin: Coordinate, distance: f64) -> Geometry {
+    let (lon, lat) = origin;
+    let delta_lat = (distance / EARTH_RADIUS).to_degrees();
+    let (min_lat, max_lat) = (lat - delta_lat, lat + delta_lat);
+
+    if min_lat <= -90.0 || max_lat >= 90.0 {
+        return Geometry::Envelope((-180.0, max_lat.min(90.0)), (180.0, min_lat.max(-90.0)));
+    }
+
+    // Lines of longitude get closer together away from the equator
+    let delta_lon = ((distance / EARTH_RADIUS).sin() / lat.to_radians().cos()).min(1.0).asin().to_degrees();
+    let (min_lon, max_lon) = (lon - delta_lon, lon + delta_lon);
+
+    if min_lon < -180.0 || max_lon > 180.0 {
+        return Geometry::Envelope((-180.0, max_lat), (180.0, min_lat));
+    }
+
+    Geometry::Envelope((min_lon, max_lat), (max_lon, min_lat))
+}
+
+#[cfg(test)]
+mod tests {
+    use search::query::geo_shape::Geometry;
+
+    use super::{parse_distance, haversine_distance, bounding_box};
+
+    #[test]
+    fn test_parse_distance() {
+        assert_eq!(parse_distance("12km"), Some(12000.0));
+        assert_eq!(parse_distance("2 mi"), Some(3218.688));
+        assert_eq!(parse_distance("150"), Some(150.0));
+        assert_eq!(parse_distance("1.5m"), Some(1.5));
+        assert_eq!(parse_distance("12 furlongs"), None);
+        assert_eq!(parse_distance("km"), None);
+    }
+
+    #[test]
+    fn test_bounding_box() {
+        // Points on the edges of the box in each direction are just outside the distance
+        let berlin = (13.4, 52.5);
+        let (left, top, right, bottom) = match bounding_box(berlin, 50000.0) {
+            Geometry::Envelope((left, top), (right, bottom)) => (left, top, right, bottom),
+            shape => panic!("expected an envelope, got {:?}", shape),
+        };
+
+        assert!(left < 13.4 && right > 13.4 && bottom < 52.5 && top > 52.5);
+        for &edge in [(left, 52.5), (right, 52.5), (13.4, top), (13.4, bottom)].iter() {
+            assert!(haversine_distance(berlin, edge) >= 49999.0);
+        }
+
+        // Boxes near the poles or the antimeridian take every longitude
+        assert_eq!(bounding_box((0.0, 89.9), 50000.0), Geometry::Envelope((-180.0, 90.0), (180.0, 89.9 - (50000.0f64 / 6371008.7714).to_degrees())));
+        match bounding_box((179.9, 0.0), 50000.0) {
+            Geometry::Envelope((left, _), (right, _)) => assert_eq!((left, right), (-180.0, 180.0)),
+            shape => panic!("expected an envelope, got {:?}", shape),
+        }
+    }
+}
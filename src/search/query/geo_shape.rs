@@ -0,0 +1,609 @@
+//! Geo and cartesian shapes
+//!
+//! Shapes are indexed by the tiles that they cover. The coordinate space is
+//! divided into a quadtree of tiles and each shape is indexed with the deepest
+//! level of tiles where it touches no more than MAX_COVERING_TILES of them. Every
+//! ancestor of those tiles is indexed too, so a query only needs to look up the
+//! tiles covering its own shape and their ancestors to find every candidate.
+//! Candidates are then checked against their stored shape.
+
+use serde_json::Value as Json;
+
+use search::term::Term;
+
+/// Geo shape terms start with this byte so they can't be confused with other values
+pub const GEO_SHAPE_TERM_PREFIX: u8 = 0x03;
+
+/// Cartesian coordinates must be within this distance of the origin on both axes
+pub const CARTESIAN_LIMIT: f64 = 1099511627776.0;
+
+/// The highest number of tiles that a shape can be indexed with
+pub const MAX_COVERING_TILES: usize = 16;
+
+/// Marks a tile that the shape was indexed with
+const LEAF_TILE_MARKER: u8 = b'l';
+
+/// Marks a tile that contains one of the tiles that the shape was indexed with
+const COVER_TILE_MARKER: u8 = b'c';
+
+/// An x, y pair. For geographic coordinates, this is a longitude, latitude pair
+pub type Coordinate = (f64, f64);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CoordinateSystem {
+    /// Longitudes and latitudes (WGS84)
+    Geographic,
+
+    /// Points on a flat plane, such as a floor plan
+    Cartesian,
+}
+
+impl CoordinateSystem {
+    /// Returns the area that the tiles cover as (min x, min y, max x, max y)
+    fn bounds(&self) -> (f64, f64, f64, f64) {
+        match *self {
+            CoordinateSystem::Geographic => (-180.0, -90.0, 180.0, 90.0),
+            CoordinateSystem::Cartesian => (-CARTESIAN_LIMIT, -CARTESIAN_LIMIT, CARTESIAN_LIMIT, CARTESIAN_LIMIT),
+        }
+    }
+
+    /// Returns the deepest level of tiles. Geographic tiles at this level are
+    /// around 40 metres wide and cartesian ones are 1/128 wide
+    fn max_tile_level(&self) -> u32 {
+        match *self {
+            CoordinateSystem::Geographic => 20,
+            CoordinateSystem::Cartesian => 48,
+        }
+    }
+
+    fn contains(&self, coordinate: Coordinate) -> bool {
+        let (min_x, min_y, max_x, max_y) = self.bounds();
+        coordinate.0 >= min_x && coordinate.0 <= max_x && coordinate.1 >= min_y && coordinate.1 <= max_y
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Geometry {
+    Point(Coordinate),
+    MultiPoint(Vec<Coordinate>),
+    LineString(Vec<Coordinate>),
+    MultiLineString(Vec<Vec<Coordinate>>),
+
+    /// The first ring is the outside of the polygon, any others are holes
+    Polygon(Vec<Vec<Coordinate>>),
+    MultiPolygon(Vec<Vec<Vec<Coordinate>>>),
+
+    /// A rectangle given by its top left and bottom right corners
+    Envelope(Coordinate, Coordinate),
+    Collection(Vec<Geometry>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpatialRelation {
+    /// Matches shapes that touch the query shape
+    Intersects,
+
+    /// Matches shapes that are entirely inside the query shape
+    Within,
+
+    /// Matches shapes that don't touch the query shape
+    Disjoint,
+}
+
+/// Parses an [x, y] array
+pub fn parse_coordinate(json: &Json, system: CoordinateSystem) -> Option<Coordinate> {
+    let array = json.as_array()?;
+    if array.len() < 2 {
+        return None;
+    }
+
+    let coordinate = (array[0].as_f64()?, array[1].as_f64()?);
+    if !system.contains(coordinate) {
+        return None;
+    }
+
+    Some(coordinate)
+}
+
+fn parse_coordinates(json: &Json, system: CoordinateSystem) -> Option<Vec<Coordinate>> {
+    json.as_array()?.iter().map(|coordinate| parse_coordinate(coordinate, system)).collect()
+}
+
+fn parse_polygon(json: &Json, system: CoordinateSystem) -> Option<Vec<Vec<Coordinate>>> {
+    let rings = json.as_array()?.iter().map(|ring| parse_coordinates(ring, system)).collect::<Option<Vec<_>>>()?;
+
+    // Rings must be closed
+    if rings.is_empty() || rings.iter().any(|ring| ring.len() < 4 || ring.first() != ring.last()) {
+        return None;
+    }
+
+    Some(rings)
+}
+
+impl Geometry {
+    /// Parses a GeoJSON geometry, or an "envelope". Arrays of geometries are read as a collection
+    pub fn from_json(json: &Json, system: CoordinateSystem) -> Option<Geometry> {
+        if let Json::Array(ref array) = *json {
+            return array.iter().map(|geometry| Geometry::from_json(geometry, system)).collect::<Option<Vec<_>>>().map(Geometry::Collection);
+        }
+
+        let shape_type = json.get("type")?.as_str()?.to_lowercase();
+        if shape_type == "geometrycollection" {
+            return Geometry::from_json(json.get("geometries")?, system);
+        }
+
+        let coordinates = json.get("coordinates")?;
+        match shape_type.as_ref() {
+            "point" => parse_coordinate(coordinates, system).map(Geometry::Point),
+            "multipoint" => parse_coordinates(coordinates, system).map(Geometry::MultiPoint),
+            "linestring" => {
+                let line = parse_coordinates(coordinates, system)?;
+                if line.len() < 2 {
+                    return None;
+                }
+
+                Some(Geometry::LineString(line))
+            }
+            "multilinestring" => {
+                let lines = coordinates.as_array()?.iter().map(|line| parse_coordinates(line, system)).collect::<Option<Vec<_>>>()?;
+                if lines.iter().any(|line| line.len() < 2) {
+                    return None;
+                }
+
+                Some(Geometry::MultiLineString(lines))
+            }
+            "polygon" => parse_polygon(coordinates, system).map(Geometry::Polygon),
+            "multipolygon" => coordinates.as_array()?.iter().map(|polygon| parse_polygon(polygon, system)).collect::<Option<Vec<_>>>().map(Geometry::MultiPolygon),
+            "envelope" => {
+                let corners = parse_coordinates(coordinates, system)?;
+                if corners.len() != 2 {
+                    return None;
+                }
+
+                Some(Geometry::Envelope(corners[0], corners[1]))
+            }
+            _ => None,
+        }
+    }
+
+    /// Splits the geometry into points, line segments and polygons
+    fn primitives(&self) -> Primitives {
+        let mut primitives = Primitives {
+            points: Vec::new(),
+            lines: Vec::new(),
+            polygons: Vec::new(),
+        };
+
+        self.collect_primitives(&mut primitives);
+        primitives
+    }
+
+    fn collect_primitives(&self, primitives: &mut Primitives) {
+        match *self {
+            Geometry::Point(point) => primitives.points.push(point),
+            Geometry::MultiPoint(ref points) => primitives.points.extend(points.iter().cloned()),
+            Geometry::LineString(ref line) => primitives.lines.extend(line.windows(2).map(|segment| (segment[0], segment[1]))),
+            Geometry::MultiLineString(ref lines) => {
+                for line in lines.iter() {
+                    primitives.lines.extend(line.windows(2).map(|segment| (segment[0], segment[1])));
+                }
+            }
+            Geometry::Polygon(ref polygon) => primitives.polygons.push(polygon.clone()),
+            Geometry::MultiPolygon(ref polygons) => primitives.polygons.extend(polygons.iter().cloned()),
+            Geometry::Envelope((left, top), (right, bottom)) => {
+                primitives.polygons.push(vec![vec![(left, top), (right, top), (right, bottom), (left, bottom), (left, top)]]);
+            }
+            Geometry::Collection(ref geometries) => {
+                for geometry in geometries.iter() {
+                    geometry.collect_primitives(primitives);
+                }
+            }
+        }
+    }
+
+    /// Returns the bounding box as (min lon, min lat, max lon, max lat)
+    fn bounding_box(&self) -> Option<(f64, f64, f64, f64)> {
+        let primitives = self.primitives();
+        let mut bounding_box: Option<(f64, f64, f64, f64)> = None;
+
+        for &(x, y) in primitives.vertices().iter() {
+            bounding_box = Some(match bounding_box {
+                Some((min_x, min_y, max_x, max_y)) => (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y)),
+                None => (x, y, x, y),
+            });
+        }
+
+        bounding_box
+    }
+
+    /// Checks if the two geometries share any point
+    pub fn intersects(&self, other: &Geometry) -> bool {
+        let a = self.primitives();
+        let b = other.primitives();
+
+        if a.points.iter().any(|point| b.covers(*point)) || b.points.iter().any(|point| a.covers(*point)) {
+            return true;
+        }
+
+        let a_edges = a.edges();
+        let b_edges = b.edges();
+        for &(a1, a2) in a_edges.iter() {
+            for &(b1, b2) in b_edges.iter() {
+                if segments_intersect(a1, a2, b1, b2) {
+                    return true;
+                }
+            }
+        }
+
+        // Without any crossing edges, one could still be entirely inside a polygon of the other
+        a.vertices().iter().any(|point| b.polygons.iter().any(|polygon| point_in_polygon(*point, polygon))) ||
+            b.vertices().iter().any(|point| a.polygons.iter().any(|polygon| point_in_polygon(*point, polygon)))
+    }
+
+    /// Checks if this geometry is entirely inside the other one
+    pub fn within(&self, other: &Geometry) -> bool {
+        let a = self.primitives();
+        let b = other.primitives();
+
+        let vertices = a.vertices();
+        if vertices.is_empty() || !vertices.iter().all(|point| b.covers(*point)) {
+            return false;
+        }
+
+        // Edges can't leave the other geometry between vertices
+        let b_boundary = b.polygon_edges();
+        for &(a1, a2) in a.edges().iter() {
+            if !b.covers(((a1.0 + a2.0) / 2.0, (a1.1 + a2.1) / 2.0)) {
+                return false;
+            }
+
+            if b_boundary.iter().any(|&(b1, b2)| segments_cross(a1, a2, b1, b2)) {
+                return false;
+            }
+        }
+
+        // Polygons can't surround holes in the other geometry
+        for polygon in b.polygons.iter() {
+            for hole in polygon.iter().skip(1) {
+                if hole.iter().any(|point| a.polygons.iter().any(|a_polygon| point_in_polygon(*point, a_polygon) && !on_polygon_boundary(*point, a_polygon))) {
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+
+    /// Checks if the geometry has the relation to the other one
+    pub fn relates(&self, other: &Geometry, relation: SpatialRelation) -> bool {
+        match relation {
+            SpatialRelation::Intersects => self.intersects(other),
+            SpatialRelation::Within => self.within(other),
+            SpatialRelation::Disjoint => !self.intersects(other),
+        }
+    }
+}
+
+struct Primitives {
+    points: Vec<Coordinate>,
+    lines: Vec<(Coordinate, Coordinate)>,
+    polygons: Vec<Vec<Vec<Coordinate>>>,
+}
+
+impl Primitives {
+    fn vertices(&self) -> Vec<Coordinate> {
+        let mut vertices = self.points.clone();
+        for &(a, b) in self.lines.iter() {
+            vertices.push(a);
+            vertices.push(b);
+        }
+
+        for polygon in self.polygons.iter() {
+            for ring in polygon.iter() {
+                vertices.extend(ring.iter().cloned());
+            }
+        }
+
+        vertices
+    }
+
+    fn polygon_edges(&self) -> Vec<(Coordinate, Coordinate)> {
+        let mut edges = Vec::new();
+        for polygon in self.polygons.iter() {
+            for ring in polygon.iter() {
+                edges.extend(ring.windows(2).map(|segment| (segment[0], segment[1])));
+            }
+        }
+
+        edges
+    }
+
+    fn edges(&self) -> Vec<(Coordinate, Coordinate)> {
+        let mut edges = self.lines.clone();
+        edges.extend(self.polygon_edges());
+        edges
+    }
+
+    /// Checks if the point is on or inside any of the primitives
+    fn covers(&self, point: Coordinate) -> bool {
+        self.points.iter().any(|other| *other == point) ||
+            self.lines.iter().any(|&(a, b)| point_on_segment(point, a, b)) ||
+            self.polygons.iter().any(|polygon| point_in_polygon(point, polygon))
+    }
+}
+
+fn orientation(a: Coordinate, b: Coordinate, c: Coordinate) -> f64 {
+    (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)
+}
+
+fn point_on_segment(point: Coordinate, a: Coordinate, b: Coordinate) -> bool {
+    orientation(a, b, point) == 0.0 &&
+        point.0 >= a.0.min(b.0) && point.0 <= a.0.max(b.0) &&
+        point.1 >= a.1.min(b.1) && point.1 <= a.1.max(b.1)
+}
+
+/// Checks if the segments cross each other at a point that isn't the end of either one
+fn segments_cross(a1: Coordinate, a2: Coordinate, b1: Coordinate, b2: Coordinate) -> bool {
+    let d1 = orientation(b1, b2, a1);
+    let d2 = orientation(b1, b2, a2);
+    let d3 = orientation(a1, a2, b1);
+    let d4 = orientation(a1, a2, b2);
+
+    d1 * d2 < 0.0 && d3 * d4 < 0.0
+}
+
+fn segments_intersect(a1: Coordinate, a2: Coordinate, b1: Coordinate, b2: Coordinate) -> bool {
+    segments_cross(a1, a2, b1, b2) ||
+        point_on_segment(a1, b1, b2) || point_on_segment(a2, b1, b2) ||
+        point_on_segment(b1, a1, a2) || point_on_segment(b2, a1, a2)
+}
+
+fn point_in_ring(point: Coordinate, ring: &[Coordinate]) -> bool {
+    let mut inside = false;
+
+    for segment in ring.windows(2) {
+        let (a, b) = (segment[0], segment[1]);
+        if (a.1 > point.1) != (b.1 > point.1) && point.0 < (b.0 - a.0) * (point.1 - a.1) / (b.1 - a.1) + a.0 {
+            inside = !inside;
+        }
+    }
+
+    inside
+}
+
+fn on_polygon_boundary(point: Coordinate, polygon: &[Vec<Coordinate>]) -> bool {
+    polygon.iter().any(|ring| ring.windows(2).any(|segment| point_on_segment(point, segment[0], segment[1])))
+}
+
+/// Checks if the point is inside the polygon or on its boundary
+fn point_in_polygon(point: Coordinate, polygon: &[Vec<Coordinate>]) -> bool {
+    if on_polygon_boundary(point, polygon) {
+        return true;
+    }
+
+    match polygon.split_first() {
+        Some((outer, holes)) => point_in_ring(point, outer) && !holes.iter().any(|hole| point_in_ring(point, hole)),
+        None => false,
+    }
+}
+
+/// Finds the column and row of the tile at the level that contains the coordinate
+fn tile_at(coordinate: Coordinate, level: u32, system: CoordinateSystem) -> (u64, u64) {
+    let (min_x, min_y, max_x, max_y) = system.bounds();
+    let tiles = 1u64 << level;
+    let column = ((coordinate.0 - min_x) / (max_x - min_x) * tiles as f64) as u64;
+    let row = ((coordinate.1 - min_y) / (max_y - min_y) * tiles as f64) as u64;
+
+    // Coordinates on the far edges belong to the last tile
+    (column.min(tiles - 1), row.min(tiles - 1))
+}
+
+/// Gives the tile a name made of one digit per level, so the names of its
+/// ancestors are prefixes of its own
+fn tile_name(column: u64, row: u64, level: u32) -> String {
+    (0..level).rev().map(|i| {
+        let digit = ((column >> i) & 1) + 2 * ((row >> i) & 1);
+        (b'0' + digit as u8) as char
+    }).collect()
+}
+
+fn tile_geometry(column: u64, row: u64, level: u32, system: CoordinateSystem) -> Geometry {
+    let (min_x, min_y, max_x, max_y) = system.bounds();
+    let width = (max_x - min_x) / (1u64 << level) as f64;
+    let height = (max_y - min_y) / (1u64 << level) as f64;
+    let left = column as f64 * width + min_x;
+    let bottom = row as f64 * height + min_y;
+
+    Geometry::Envelope((left, bottom + height), (left + width, bottom))
+}
+
+/// Finds the names of the tiles that the geometry is indexed with
+fn covering_tiles(geometry: &Geometry, system: CoordinateSystem) -> Vec<String> {
+    let (min_x, min_y, max_x, max_y) = match geometry.bounding_box() {
+        Some(bounding_box) => bounding_box,
+        None => return Vec::new(),
+    };
+
+    // Find the deepest level where the bounding box is in few enough tiles
+    let mut level = 0;
+    for next_level in 1..(system.max_tile_level() + 1) {
+        let (min_column, min_row) = tile_at((min_x, min_y), next_level, system);
+        let (max_column, max_row) = tile_at((max_x, max_y), next_level, system);
+        let num_tiles = (max_column - min_column + 1) as usize * (max_row - min_row + 1) as usize;
+
+        if num_tiles > MAX_COVERING_TILES {
+            break;
+        }
+
+        level = next_level;
+    }
+
+    // Skip tiles in the bounding box that the geometry doesn't touch
+    let (min_column, min_row) = tile_at((min_x, min_y), level, system);
+    let (max_column, max_row) = tile_at((max_x, max_y), level, system);
+    let mut tiles = Vec::new();
+    for column in min_column..(max_column + 1) {
+        for row in min_row..(max_row + 1) {
+            if tile_geometry(column, row, level, system).intersects(geometry) {
+                tiles.push(tile_name(column, row, level));
+            }
+        }
+    }
+
+    tiles
+}
+
+fn tile_term(marker: u8, tile: &str) -> Term {
+    let mut bytes = Vec::with_capacity(tile.len() + 2);
+    bytes.push(GEO_SHAPE_TERM_PREFIX);
+    bytes.push(marker);
+    bytes.extend(tile.as_bytes());
+    Term::from_bytes(&bytes)
+}
+
+/// Builds the terms that a geometry is indexed as
+pub fn geo_shape_index_terms(geometry: &Geometry, system: CoordinateSystem) -> Vec<Term> {
+    let mut terms = Vec::new();
+
+    for tile in covering_tiles(geometry, system) {
+        terms.push(tile_term(LEAF_TILE_MARKER, &tile));
+
+        for length in 0..(tile.len() + 1) {
+            let term = tile_term(COVER_TILE_MARKER, &tile[..length]);
+            if !terms.contains(&term) {
+                terms.push(term);
+            }
+        }
+    }
+
+    terms
+}
+
+/// Builds the terms that find the candidates for a query. Every document that
+/// may have the relation to the geometry has at least one of these terms
+pub fn geo_shape_query_terms(geometry: &Geometry, relation: SpatialRelation, system: CoordinateSystem) -> Vec<Term> {
+    // Any shape could be disjoint, so check all of them
+    if relation == SpatialRelation::Disjoint {
+        return vec![tile_term(COVER_TILE_MARKER, "")];
+    }
+
+    let mut terms = Vec::new();
+    for tile in covering_tiles(geometry, system) {
+        // Shapes indexed with this tile or any tile inside it
+        terms.push(tile_term(COVER_TILE_MARKER, &tile));
+
+        // Shapes indexed with a tile that contains this one
+        for length in 0..tile.len() {
+            let term = tile_term(LEAF_TILE_MARKER, &tile[..length]);
+            if !terms.contains(&term) {
+                terms.push(term);
+            }
+        }
+    }
+
+    terms
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Geometry, SpatialRelation, CoordinateSystem, covering_tiles, geo_shape_index_terms, geo_shape_query_terms, tile_name};
+
+    fn square(left: f64, bottom: f64, size: f64) -> Geometry {
+        Geometry::Envelope((left, bottom + size), (left + size, bottom))
+    }
+
+    #[test]
+    fn test_from_json() {
+        assert_eq!(Geometry::from_json(&json!({"type": "Point", "coordinates": [-77.03, 38.89]}), CoordinateSystem::Geographic), Some(Geometry::Point((-77.03, 38.89))));
+        assert_eq!(Geometry::from_json(&json!({"type": "envelope", "coordinates": [[-45, 45], [45, -45]]}), CoordinateSystem::Geographic), Some(Geometry::Envelope((-45.0, 45.0), (45.0, -45.0))));
+
+        // Polygons must be closed
+        assert_eq!(Geometry::from_json(&json!({"type": "polygon", "coordinates": [[[0, 0], [1, 0], [1, 1], [0, 1]]]}), CoordinateSystem::Geographic), None);
+
+        // Out of range
+        assert_eq!(Geometry::from_json(&json!({"type": "point", "coordinates": [200, 0]}), CoordinateSystem::Geographic), None);
+    }
+
+    #[test]
+    fn test_relations() {
+        let big = square(0.0, 0.0, 10.0);
+        let small = square(2.0, 2.0, 2.0);
+        let overlapping = square(8.0, 8.0, 4.0);
+        let far = square(20.0, 20.0, 1.0);
+
+        assert!(small.within(&big));
+        assert!(!big.within(&small));
+        assert!(!overlapping.within(&big));
+
+        assert!(overlapping.intersects(&big));
+        assert!(small.intersects(&big));
+        assert!(big.intersects(&small));
+        assert!(far.relates(&big, SpatialRelation::Disjoint));
+
+        let line = Geometry::LineString(vec![(-5.0, 5.0), (15.0, 5.0)]);
+        assert!(line.intersects(&big));
+        assert!(!line.within(&big));
+        assert!(Geometry::Point((5.0, 5.0)).within(&big));
+    }
+
+    #[test]
+    fn test_within_polygon_with_hole() {
+        let with_hole = Geometry::Polygon(vec![
+            vec![(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0), (0.0, 0.0)],
+            vec![(4.0, 4.0), (6.0, 4.0), (6.0, 6.0), (4.0, 6.0), (4.0, 4.0)],
+        ]);
+
+        assert!(!Geometry::Point((5.0, 5.0)).within(&with_hole));
+        assert!(square(1.0, 1.0, 1.0).within(&with_hole));
+        assert!(!square(3.0, 3.0, 4.0).within(&with_hole));
+    }
+
+    #[test]
+    fn test_tile_name() {
+        assert_eq!(tile_name(0, 0, 0), "");
+        assert_eq!(tile_name(1, 0, 1), "1");
+        assert_eq!(tile_name(3, 2, 2), "31");
+    }
+
+    #[test]
+    fn test_covering_tiles() {
+        // Points are indexed with a single tile at the deepest level
+        let tiles = covering_tiles(&Geometry::Point((-77.03, 38.89)), CoordinateSystem::Geographic);
+        assert_eq!(tiles.len(), 1);
+        assert_eq!(tiles[0].len(), 20);
+
+        let tiles = covering_tiles(&square(0.0, 0.0, 10.0), CoordinateSystem::Geographic);
+        assert!(tiles.len() <= 16);
+    }
+
+    #[test]
+    fn test_cartesian_coordinates() {
+        // Cartesian coordinates aren't limited to longitudes and latitudes
+        let shape = Geometry::from_json(&json!({"type": "point", "coordinates": [1000.5, -250.0]}), CoordinateSystem::Cartesian);
+        assert_eq!(shape, Some(Geometry::Point((1000.5, -250.0))));
+        assert_eq!(Geometry::from_json(&json!({"type": "point", "coordinates": [1000.5, -250.0]}), CoordinateSystem::Geographic), None);
+
+        let tiles = covering_tiles(&shape.unwrap(), CoordinateSystem::Cartesian);
+        assert_eq!(tiles.len(), 1);
+        assert_eq!(tiles[0].len(), 48);
+    }
+
+    #[test]
+    fn test_query_terms_find_indexed_shapes() {
+        let point = Geometry::Point((5.0, 5.0));
+        let index_terms = geo_shape_index_terms(&point, CoordinateSystem::Geographic);
+
+        // A large query shape is covered by coarse tiles that contain the point's tile
+        let query_terms = geo_shape_query_terms(&square(-50.0, -50.0, 100.0), SpatialRelation::Intersects, CoordinateSystem::Geographic);
+        assert!(query_terms.iter().any(|term| index_terms.contains(term)));
+
+        // A small query shape is covered by fine tiles inside a large indexed shape's tiles
+        let index_terms = geo_shape_index_terms(&square(-50.0, -50.0, 100.0), CoordinateSystem::Geographic);
+        let query_terms = geo_shape_query_terms(&point, SpatialRelation::Intersects, CoordinateSystem::Geographic);
+        assert!(query_terms.iter().any(|term| index_terms.contains(term)));
+
+        // But not one that is far away
+        let query_terms = geo_shape_query_terms(&Geometry::Point((100.0, 80.0)), SpatialRelation::Intersects, CoordinateSystem::Geographic);
+        assert!(!query_terms.iter().any(|term| index_terms.contains(term)));
+    }
+}
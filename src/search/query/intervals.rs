@@ -0,0 +1,333 @@
+//! Interval matching
+//!
+//! Intervals are the ranges of positions that a source matches in a field,
+//! from the position of the first term to the position of the last. Sources
+//! are built up from the positions of single terms. Only minimal intervals
+//! are kept, so an interval is dropped if another one from the same source
+//! fits inside it.
+
+/// A range of positions, both inclusive
+pub type Interval = (u32, u32);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IntervalsSource<T> {
+    /// Each position of the term
+    Term(T),
+
+    /// Intervals that hold an interval from each of the sources
+    AllOf {
+        sources: Vec<IntervalsSource<T>>,
+
+        /// Requires the intervals of the sources to be in the same order as the sources
+        ordered: bool,
+
+        /// The most positions that can be between the intervals of the sources
+        max_gaps: Option<u32>,
+    },
+
+    /// The intervals of any of the sources
+    AnyOf {
+        sources: Vec<IntervalsSource<T>>,
+    },
+
+    /// The intervals of the source that pass the filter
+    Filtered {
+        source: Box<IntervalsSource<T>>,
+        filter: IntervalFilter,
+        filter_source: Box<IntervalsSource<T>>,
+    },
+
+    /// The intervals of the source that end at or before the position, so only
+    /// match near the start of the field
+    First {
+        source: Box<IntervalsSource<T>>,
+        end: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IntervalFilter {
+    Containing,
+    ContainedBy,
+    NotContaining,
+    NotContainedBy,
+    Overlapping,
+    NotOverlapping,
+    Before,
+    After,
+}
+
+impl IntervalFilter {
+    /// Checks if all documents that pass the filter must also match the filter source
+    pub fn requires_filter_source(&self) -> bool {
+        match *self {
+            IntervalFilter::NotContaining | IntervalFilter::NotContainedBy | IntervalFilter::NotOverlapping => false,
+            _ => true,
+        }
+    }
+
+    fn matches(&self, interval: Interval, filter_intervals: &[Interval]) -> bool {
+        let contains = |outer: Interval, inner: Interval| outer.0 <= inner.0 && inner.1 <= outer.1;
+        let overlaps = |a: Interval, b: Interval| a.0 <= b.1 && b.0 <= a.1;
+
+        match *self {
+            IntervalFilter::Containing => filter_intervals.iter().any(|&filter| contains(interval, filter)),
+            IntervalFilter::ContainedBy => filter_intervals.iter().any(|&filter| contains(filter, interval)),
+            IntervalFilter::NotContaining => !filter_intervals.iter().any(|&filter| contains(interval, filter)),
+            IntervalFilter::NotContainedBy => !filter_intervals.iter().any(|&filter| contains(filter, interval)),
+            IntervalFilter::Overlapping => filter_intervals.iter().any(|&filter| overlaps(interval, filter)),
+            IntervalFilter::NotOverlapping => !filter_intervals.iter().any(|&filter| overlaps(interval, filter)),
+            IntervalFilter::Before => filter_intervals.iter().any(|&filter| interval.1 < filter.0),
+            IntervalFilter::After => filter_intervals.iter().any(|&filter| interval.0 > filter.1),
+        }
+    }
+}
+
+impl<T> IntervalsSource<T> {
+    /// Converts the terms of the source, such as into term IDs
+    pub fn map_terms<U, F: Fn(&T) -> U>(&self, map: &F) -> IntervalsSource<U> {
+        match *self {
+            IntervalsSource::Term(ref term) => IntervalsSource::Term(map(term)),
+            IntervalsSource::AllOf{ref sources, ordered, max_gaps} => {
+                IntervalsSource::AllOf {
+                    sources: sources.iter().map(|source| source.map_terms(map)).collect(),
+                    ordered: ordered,
+                    max_gaps: max_gaps,
+                }
+            }
+            IntervalsSource::AnyOf{ref sources} => {
+                IntervalsSource::AnyOf {
+                    sources: sources.iter().map(|source| source.map_terms(map)).collect(),
+                }
+            }
+            IntervalsSource::Filtered{ref source, filter, ref filter_source} => {
+                IntervalsSource::Filtered {
+                    source: Box::new(source.map_terms(map)),
+                    filter: filter,
+                    filter_source: Box::new(filter_source.map_terms(map)),
+                }
+            }
+            IntervalsSource::First{ref source, end} => {
+                IntervalsSource::First {
+                    source: Box::new(source.map_terms(map)),
+                    end: end,
+                }
+            }
+        }
+    }
+
+    /// Collects every term in the source
+    pub fn terms(&self) -> Vec<&T> {
+        match *self {
+            IntervalsSource::Term(ref term) => vec![term],
+            IntervalsSource::AllOf{ref sources, ..} | IntervalsSource::AnyOf{ref sources} => {
+                sources.iter().flat_map(|source| source.terms()).collect()
+            }
+            IntervalsSource::Filtered{ref source, ref filter_source, ..} => {
+                let mut terms = source.terms();
+                terms.extend(filter_source.terms());
+                terms
+            }
+            IntervalsSource::First{ref source, ..} => source.terms(),
+        }
+    }
+
+    /// Finds the minimal intervals of the source, sorted. Takes a function
+    /// that gives the sorted positions of a term
+    pub fn intervals<F: Fn(&T) -> Vec<u32>>(&self, positions: &F) -> Vec<Interval> {
+        match *self {
+            IntervalsSource::Term(ref term) => positions(term).into_iter().map(|position| (position, position)).collect(),
+            IntervalsSource::AllOf{ref sources, ordered, max_gaps} => {
+                let source_intervals = sources.iter().map(|source| source.intervals(positions)).collect::<Vec<_>>();
+
+                if ordered {
+                    ordered_intervals(&source_intervals, max_gaps)
+                } else {
+                    unordered_intervals(&source_intervals, max_gaps)
+                }
+            }
+            IntervalsSource::AnyOf{ref sources} => {
+                let intervals = sources.iter().flat_map(|source| source.intervals(positions)).collect();
+                minimise(intervals)
+            }
+            IntervalsSource::Filtered{ref source, filter, ref filter_source} => {
+                let filter_intervals = filter_source.intervals(positions);
+                source.intervals(positions).into_iter().filter(|interval| filter.matches(*interval, &filter_intervals)).collect()
+            }
+            IntervalsSource::First{ref source, end} => {
+                source.intervals(positions).into_iter().filter(|interval| interval.1 <= end).collect()
+            }
+        }
+    }
+}
+
+/// Sorts the intervals and removes any that hold another one
+fn minimise(mut intervals: Vec<Interval>) -> Vec<Interval> {
+    intervals.sort();
+    intervals.dedup();
+
+    // With intervals sorted by start, an interval holds a later one if it ends
+    // at or after it. So an interval is kept if it ends before all later ones
+    let mut minimal: Vec<Interval> = Vec::with_capacity(intervals.len());
+    for interval in intervals.into_iter().rev() {
+        if minimal.last().map_or(true, |next| interval.1 < next.1) {
+            minimal.push(interval);
+        }
+    }
+
+    minimal.reverse();
+    minimal
+}
+
+fn length(interval: Interval) -> u32 {
+    interval.1 - interval.0 + 1
+}
+
+/// Takes an interval from each source so that each one starts after the
+/// previous one ends. The first interval of each source that fits is used, as
+/// this leaves the fewest gaps
+fn ordered_intervals(source_intervals: &[Vec<Interval>], max_gaps: Option<u32>) -> Vec<Interval> {
+    if source_intervals.is_empty() {
+        return Vec::new();
+    }
+
+    let mut intervals = Vec::new();
+
+    'start: for &first in source_intervals[0].iter() {
+        let mut end = first.1;
+        let mut gaps = 0;
+
+        for next_intervals in source_intervals[1..].iter() {
+            match next_intervals.iter().find(|next| next.0 > end) {
+                Some(&next) => {
+                    gaps += next.0 - end - 1;
+                    end = next.1;
+                }
+                None => break 'start,
+            }
+        }
+
+        if max_gaps.map_or(true, |max_gaps| gaps <= max_gaps) {
+            intervals.push((first.0, end));
+        }
+    }
+
+    minimise(intervals)
+}
+
+/// Takes an interval from each source in any order. Finds the smallest
+/// windows that hold one from each by moving forward whichever source has the
+/// earliest interval in the window
+fn unordered_intervals(source_intervals: &[Vec<Interval>], max_gaps: Option<u32>) -> Vec<Interval> {
+    if source_intervals.is_empty() || source_intervals.iter().any(|intervals| intervals.is_empty()) {
+        return Vec::new();
+    }
+
+    let mut cursors = vec![0; source_intervals.len()];
+    let mut intervals = Vec::new();
+
+    loop {
+        let current = cursors.iter().enumerate().map(|(source, cursor)| source_intervals[source][*cursor]).collect::<Vec<_>>();
+        let start = current.iter().map(|interval| interval.0).min().unwrap();
+        let end = current.iter().map(|interval| interval.1).max().unwrap();
+
+        // Intervals of different sources can overlap, so this can't go below zero
+        let total_length = current.iter().map(|interval| length(*interval)).sum::<u32>();
+        let gaps = length((start, end)).saturating_sub(total_length);
+
+        if max_gaps.map_or(true, |max_gaps| gaps <= max_gaps) {
+            intervals.push((start, end));
+        }
+
+        let earliest = (0..current.len()).min_by_key(|source| current[*source]).unwrap();
+        cursors[earliest] += 1;
+        if cursors[earliest] == source_intervals[earliest].len() {
+            return minimise(intervals);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{IntervalsSource, IntervalFilter};
+
+    fn term(term: &'static str) -> IntervalsSource<&'static str> {
+        IntervalsSource::Term(term)
+    }
+
+    fn all_of(sources: Vec<IntervalsSource<&'static str>>, ordered: bool, max_gaps: Option<u32>) -> IntervalsSource<&'static str> {
+        IntervalsSource::AllOf {
+            sources: sources,
+            ordered: ordered,
+            max_gaps: max_gaps,
+        }
+    }
+
+    /// Works out the intervals of the source in a field with the given text
+    fn intervals(source: &IntervalsSource<&'static str>, text: &str) -> Vec<(u32, u32)> {
+        let mut positions = HashMap::new();
+        for (position, word) in text.split_whitespace().enumerate() {
+            positions.entry(word.to_string()).or_insert_with(Vec::new).push(position as u32 + 1);
+        }
+
+        source.intervals(&|term: &&str| positions.get(*term).cloned().unwrap_or_else(Vec::new))
+    }
+
+    #[test]
+    fn test_ordered_intervals() {
+        let source = all_of(vec![term("my"), term("favourite"), term("food")], true, None);
+        assert_eq!(intervals(&source, "my favourite food is cold porridge"), vec![(1, 3)]);
+        assert_eq!(intervals(&source, "my favourite hot food"), vec![(1, 4)]);
+        assert_eq!(intervals(&source, "food is my favourite"), vec![]);
+
+        // Gaps are the positions between the terms
+        let source = all_of(vec![term("my"), term("food")], true, Some(1));
+        assert_eq!(intervals(&source, "my favourite food"), vec![(1, 3)]);
+        assert_eq!(intervals(&source, "my very favourite food"), vec![]);
+    }
+
+    #[test]
+    fn test_unordered_intervals() {
+        let source = all_of(vec![term("cold"), term("porridge")], false, Some(0));
+        assert_eq!(intervals(&source, "porridge cold porridge"), vec![(1, 2), (2, 3)]);
+        assert_eq!(intervals(&source, "cold hot porridge"), vec![]);
+    }
+
+    #[test]
+    fn test_any_of_intervals() {
+        let source = IntervalsSource::AnyOf {
+            sources: vec![term("hot"), all_of(vec![term("cold"), term("porridge")], true, None)],
+        };
+
+        assert_eq!(intervals(&source, "cold hot porridge"), vec![(2, 2)]);
+        assert_eq!(intervals(&source, "cold porridge"), vec![(1, 2)]);
+    }
+
+    #[test]
+    fn test_filtered_intervals() {
+        let source = |filter| IntervalsSource::Filtered {
+            source: Box::new(all_of(vec![term("hot"), term("porridge")], true, None)),
+            filter: filter,
+            filter_source: Box::new(term("cold")),
+        };
+
+        assert_eq!(intervals(&source(IntervalFilter::NotContaining), "hot cold porridge"), vec![]);
+        assert_eq!(intervals(&source(IntervalFilter::NotContaining), "hot porridge cold"), vec![(1, 2)]);
+        assert_eq!(intervals(&source(IntervalFilter::Before), "hot porridge cold"), vec![(1, 2)]);
+        assert_eq!(intervals(&source(IntervalFilter::After), "hot porridge cold"), vec![]);
+        assert_eq!(intervals(&source(IntervalFilter::Containing), "hot cold porridge"), vec![(1, 3)]);
+    }
+
+    #[test]
+    fn test_first_intervals() {
+        let source = IntervalsSource::First {
+            source: Box::new(all_of(vec![term("hot"), term("porridge")], true, None)),
+            end: 3,
+        };
+
+        assert_eq!(intervals(&source, "very hot porridge"), vec![(2, 3)]);
+        assert_eq!(intervals(&source, "some very hot porridge"), vec![]);
+    }
+}
@@ -0,0 +1,269 @@
+//! Levenshtein automata
+//!
+//! A Levenshtein automaton accepts every string within an edit distance of a
+//! value. Each state is a row of the edit distance table for the characters read
+//! so far, so terms that share a prefix share the work of reading it. Walking
+//! the sorted term dictionary alongside the automaton lets every term under a
+//! prefix be skipped as soon as that prefix can no longer match, rather than
+//! computing the edit distance of every term in the dictionary.
+//!
+//! Swapping two adjacent characters can optionally be counted as a single edit
+//! rather than two. A character can't be edited again after being swapped, as
+//! this keeps the state to the last two rows of the table.
+
+use std::str;
+use std::cmp::min;
+use std::collections::BTreeMap;
+use std::collections::Bound::{Included, Excluded, Unbounded};
+
+use search::term::Term;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LevenshteinAutomaton {
+    /// Characters at the start of the value that must match exactly
+    prefix: String,
+
+    /// The rest of the value
+    value: Vec<char>,
+
+    max_distance: u32,
+
+    /// Counts swapping two adjacent characters as one edit
+    transpositions: bool,
+}
+
+/// The edit distance between the characters read so far and each prefix of the
+/// value. Distances are capped at one more than the maximum
+#[derive(Debug, Clone, PartialEq)]
+pub struct LevenshteinState {
+    row: Vec<u32>,
+
+    /// The row before this one and the last character read. Only kept for
+    /// transpositions
+    previous: Option<(Vec<u32>, char)>,
+}
+
+impl LevenshteinAutomaton {
+    pub fn new(value: &str, max_distance: u32, prefix_length: usize) -> LevenshteinAutomaton {
+        LevenshteinAutomaton {
+            prefix: value.chars().take(prefix_length).collect(),
+            value: value.chars().skip(prefix_length).collect(),
+            max_distance: max_distance,
+            transpositions: false,
+        }
+    }
+
+    pub fn with_transpositions(mut self, transpositions: bool) -> LevenshteinAutomaton {
+        self.transpositions = transpositions;
+        self
+    }
+
+    pub fn max_distance(&self) -> u32 {
+        self.max_distance
+    }
+
+    pub fn start(&self) -> LevenshteinState {
+        let limit = self.max_distance + 1;
+
+        LevenshteinState {
+            row: (0..self.value.len() as u32 + 1).map(|distance| min(distance, limit)).collect(),
+            previous: None,
+        }
+    }
+
+    pub fn step(&self, state: &LevenshteinState, c: char) -> LevenshteinState {
+        let limit = self.max_distance + 1;
+        let mut row = Vec::with_capacity(state.row.len());
+        row.push(min(state.row[0] + 1, limit));
+
+        for (i, value_char) in self.value.iter().enumerate() {
+            let substitution_cost = if *value_char == c { 0 } else { 1 };
+            let mut distance = min(min(row[i] + 1, state.row[i + 1] + 1), state.row[i] + substitution_cost);
+
+            // Check if the last two characters read are the value's two characters swapped
+            if let Some((ref previous_row, previous_char)) = state.previous {
+                if i > 0 && c == self.value[i - 1] && previous_char == *value_char {
+                    distance = min(distance, previous_row[i - 1] + 1);
+                }
+            }
+
+            row.push(min(distance, limit));
+        }
+
+        LevenshteinState {
+            previous: if self.transpositions { Some((state.row.clone(), c)) } else { None },
+            row: row,
+        }
+    }
+
+    /// Checks if reading more characters could lead to a match
+    pub fn can_match(&self, state: &LevenshteinState) -> bool {
+        state.row.iter().any(|distance| *distance <= self.max_distance)
+    }
+
+    /// Returns the edit distance if the characters read so far match
+    pub fn distance(&self, state: &LevenshteinState) -> Option<u32> {
+        match state.row.last() {
+            Some(distance) if *distance <= self.max_distance => Some(*distance),
+            _ => None,
+        }
+    }
+
+    /// Returns the edit distance between a string and the value, if it matches
+    pub fn eval(&self, string: &str) -> Option<u32> {
+        if !string.starts_with(&self.prefix) {
+            return None;
+        }
+
+        let mut state = self.start();
+        for c in string[self.prefix.len()..].chars() {
+            state = self.step(&state, c);
+
+            if !self.can_match(&state) {
+                return None;
+            }
+        }
+
+        self.distance(&state)
+    }
+
+    /// Finds the terms that match, along with their edit distances
+    pub fn intersect<'a, V>(&self, terms: &'a BTreeMap<Term, V>) -> Vec<(&'a Term, &'a V, u32)> {
+        let prefix = self.prefix.as_bytes();
+        let mut matches = Vec::new();
+        let mut lower_bound = Included(Term::from_bytes(prefix));
+
+        // The characters of the last term read after the prefix, and the
+        // state after reading each of them as long as the automaton could match
+        let mut previous = String::new();
+        let mut states = vec![self.start()];
+
+        while let Some((term, value)) = terms.range((lower_bound.clone(), Unbounded)).next() {
+            let bytes = term.as_bytes();
+            if !bytes.starts_with(prefix) {
+                break;
+            }
+
+            let suffix = match str::from_utf8(&bytes[prefix.len()..]) {
+                Ok(suffix) => suffix,
+                Err(_) => {
+                    lower_bound = Excluded(term.clone());
+                    continue;
+                }
+            };
+
+            // Reuse the states of the prefix shared with the last term
+            let common_length = previous.chars().zip(suffix.chars()).take_while(|&(a, b)| a == b).count();
+            states.truncate(common_length + 1);
+
+            let mut dead_end = None;
+            for (offset, c) in suffix.char_indices().skip(common_length) {
+                let state = self.step(states.last().unwrap(), c);
+
+                if !self.can_match(&state) {
+                    dead_end = Some(prefix.len() + offset + c.len_utf8());
+                    break;
+                }
+
+                states.push(state);
+            }
+
+            previous = suffix.to_string();
+
+            match dead_end {
+                Some(end) => {
+                    // Nothing that starts with these characters can match
+                    match prefix_successor(&bytes[..end]) {
+                        Some(successor) => lower_bound = Included(successor),
+                        None => break,
+                    }
+                }
+                None => {
+                    if let Some(distance) = self.distance(states.last().unwrap()) {
+                        matches.push((term, value, distance));
+                    }
+
+                    lower_bound = Excluded(term.clone());
+                }
+            }
+        }
+
+        matches
+    }
+}
+
+/// Returns the first term after all of those that start with the bytes
+fn prefix_successor(bytes: &[u8]) -> Option<Term> {
+    let mut bytes = bytes.to_vec();
+
+    while let Some(last) = bytes.pop() {
+        if last < 0xFF {
+            bytes.push(last + 1);
+            return Some(Term::from_bytes(&bytes));
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use search::term::Term;
+
+    use super::LevenshteinAutomaton;
+
+    #[test]
+    fn test_eval() {
+        let automaton = LevenshteinAutomaton::new("kitten", 2, 0);
+
+        assert_eq!(automaton.eval("kitten"), Some(0));
+        assert_eq!(automaton.eval("sitten"), Some(1));
+        assert_eq!(automaton.eval("sittin"), Some(2));
+        assert_eq!(automaton.eval("kitt"), Some(2));
+        assert_eq!(automaton.eval("sitting"), None);
+        assert_eq!(automaton.eval(""), None);
+    }
+
+    #[test]
+    fn test_eval_with_transpositions() {
+        let automaton = LevenshteinAutomaton::new("kitten", 1, 0);
+        assert_eq!(automaton.eval("iktten"), None);
+
+        let automaton = automaton.with_transpositions(true);
+        assert_eq!(automaton.eval("iktten"), Some(1));
+        assert_eq!(automaton.eval("kitetn"), Some(1));
+        assert_eq!(automaton.eval("kittne"), Some(1));
+        assert_eq!(automaton.eval("iktetn"), None);
+
+        // Swapped characters can't be edited again
+        let automaton = LevenshteinAutomaton::new("ca", 2, 0).with_transpositions(true);
+        assert_eq!(automaton.eval("abc"), None);
+    }
+
+    #[test]
+    fn test_eval_with_prefix() {
+        let automaton = LevenshteinAutomaton::new("kitten", 1, 2);
+
+        assert_eq!(automaton.eval("kitteh"), Some(1));
+        assert_eq!(automaton.eval("sitten"), None);
+    }
+
+    #[test]
+    fn test_intersect() {
+        let mut terms = BTreeMap::new();
+        for (i, term) in ["bitten", "kitchen", "kitten", "kittens", "mitten", "sitting", "written"].iter().enumerate() {
+            terms.insert(Term::from_string(term), i);
+        }
+        terms.insert(Term::from_integer(123), 100);
+
+        let automaton = LevenshteinAutomaton::new("kitten", 1, 0);
+        let matches = automaton.intersect(&terms).into_iter().map(|(_term, i, distance)| (*i, distance)).collect::<Vec<_>>();
+        assert_eq!(matches, vec![(0, 1), (2, 0), (3, 1), (4, 1)]);
+
+        let automaton = LevenshteinAutomaton::new("kitten", 2, 1);
+        let matches = automaton.intersect(&terms).into_iter().map(|(_term, i, distance)| (*i, distance)).collect::<Vec<_>>();
+        assert_eq!(matches, vec![(1, 2), (2, 0), (3, 1)]);
+    }
+}
@@ -1,12 +1,35 @@
 pub mod multi_term_selector;
 pub mod term_scorer;
+pub mod wildcard;
+pub mod rank_feature;
+pub mod geo_shape;
+pub mod geo_distance;
+pub mod levenshtein;
+pub mod phrase;
+pub mod exists;
+pub mod function_score;
+pub mod distance_feature;
+pub mod intervals;
+pub mod nested;
+pub mod script;
+
+use std::collections::HashMap;
 
 use search::term::Term;
+use search::document::DocId;
 use search::schema::FieldId;
 use search::query::multi_term_selector::MultiTermSelector;
 use search::query::term_scorer::TermScorer;
+use search::query::wildcard::WildcardPattern;
+use search::query::rank_feature::RankFeatureFunction;
+use search::query::geo_shape::{Geometry, Coordinate, SpatialRelation, CoordinateSystem};
+use search::query::function_score::{FilteredScoreFunction, ScoreMode, BoostMode};
+use search::query::distance_feature::DistanceFeatureOrigin;
+use search::query::intervals::IntervalsSource;
+use search::query::nested::NestedScoreMode;
+use script::{Expression, ScriptValue};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Query {
     /// Matches all documents, assigning the specified score to each one
     All {
@@ -42,6 +65,259 @@ pub enum Query {
         scorer: TermScorer,
     },
 
+    /// Matches documents that contain the terms as a phrase
+    /// Candidates are documents that contain all of the terms, these are then checked against the positions of the terms
+    Phrase {
+        /// The field being searched
+        field: FieldId,
+
+        /// The terms of the phrase in order
+        terms: Vec<Term>,
+
+        /// The number of moves that the terms can be apart from the phrase
+        slop: u32,
+
+        /// The method of scoring each term
+        scorer: TermScorer,
+    },
+
+    /// Matches documents that contain the terms as a phrase, followed by any of the terms that the selector matches
+    /// This is run as a disjunction of phrases, one for each of the selected terms
+    PhrasePrefix {
+        /// The field being searched
+        field: FieldId,
+
+        /// The terms of the phrase in order, before the last one
+        terms: Vec<Term>,
+
+        /// Selects the terms that the last one of the phrase could be
+        last_term_selector: MultiTermSelector,
+
+        /// The number of moves that the terms can be apart from the phrase
+        slop: u32,
+
+        /// The method of scoring each term
+        scorer: TermScorer,
+    },
+
+    /// Matches documents that contain the low frequency terms, the high frequency terms only add to the score
+    /// Terms are split by their document frequency when the query is planned. If all terms are high frequency, they are matched instead
+    CommonTerms {
+        /// The field being searched
+        field: FieldId,
+
+        /// The terms to search for
+        terms: Vec<Term>,
+
+        /// Terms in more documents than this are high frequency. Values below 1 are a fraction of the documents with the field
+        cutoff_frequency: f64,
+
+        /// Requires all of the matched terms, rather than any of them
+        require_all: bool,
+
+        /// The method of scoring each term
+        scorer: TermScorer,
+    },
+
+    /// Matches documents that contain the term in any of the fields
+    /// Each field is scored as if the term was as common in it as in the field where it's most common, so the
+    /// score isn't dominated by a field where the term happens to be rare
+    BlendedTerm {
+        /// The fields being searched along with their boosts
+        fields: Vec<(FieldId, f32)>,
+
+        /// The term to search for
+        term: Term,
+
+        /// How much the scores of the other matching fields add to the score of the best one
+        tie_breaker: f32,
+
+        /// The method of scoring each match
+        scorer: TermScorer,
+    },
+
+    /// Matches documents that contain any of the terms in the field, assigning the
+    /// same score to each one
+    ///
+    /// This is for filtering by large sets of terms. The postings lists of the
+    /// terms are unioned together in one step rather than combined one by one
+    TermSet {
+        /// The field being searched
+        field: FieldId,
+
+        /// The terms to match
+        terms: Vec<Term>,
+
+        /// The score to assign to each document
+        score: f32,
+    },
+
+    /// Matches documents by their keys
+    Ids {
+        /// The keys of the documents
+        keys: Vec<String>,
+
+        /// The score to assign to each document
+        score: f32,
+    },
+
+    /// Matches documents by their internal ids
+    /// Documents get a new id each time they are updated, so these must be found just before searching
+    DocIds {
+        doc_ids: Vec<DocId>,
+
+        /// The score to assign to each document
+        score: f32,
+    },
+
+    /// Matches documents where the intervals source matches the positions of the terms in the field
+    Intervals {
+        /// The field being searched
+        field: FieldId,
+
+        source: IntervalsSource<Term>,
+
+        /// The scoring function to use for each term in the source
+        scorer: TermScorer,
+    },
+
+    /// Matches documents that have at least one token in the field
+    Exists {
+        /// The field being searched
+        field: FieldId,
+
+        /// The score to assign to each document
+        score: f32,
+    },
+
+    /// Matches documents with a value in a wildcard field that matches the pattern
+    /// Candidates are documents that contain all of the trigrams, these are then checked against their stored value
+    Wildcard {
+        /// The field being searched
+        field: FieldId,
+
+        /// Trigram terms taken from the pattern. There must be at least one
+        trigrams: Vec<Term>,
+
+        /// The pattern to check candidates against
+        pattern: WildcardPattern,
+
+        /// The score to assign to each document
+        score: f32,
+    },
+
+    /// Matches documents with a shape in a geo shape field that has the relation to the query shape
+    /// Candidates are documents that have any of the tile terms, these are then checked against their stored shape
+    GeoShape {
+        /// The field being searched
+        field: FieldId,
+
+        /// Tile terms that cover the query shape
+        tiles: Vec<Term>,
+
+        /// The shape to check candidates against
+        shape: Geometry,
+
+        /// How the shapes of matching documents relate to the query shape
+        relation: SpatialRelation,
+
+        /// Whether the field holds geographic or cartesian shapes
+        system: CoordinateSystem,
+
+        /// The score to assign to each document
+        score: f32,
+    },
+
+    /// Matches documents with a point in a geo point field that is within the distance of the origin
+    /// Candidates are documents that have any of the tile terms of a box around the circle, these are
+    /// then checked against their stored points
+    GeoDistance {
+        /// The field being searched
+        field: FieldId,
+
+        /// Tile terms that cover the bounding box of the circle
+        tiles: Vec<Term>,
+
+        /// The centre of the circle
+        origin: Coordinate,
+
+        /// The radius of the circle in metres
+        distance: f64,
+
+        /// The score to assign to each document
+        score: f32,
+    },
+
+    /// Matches documents that have a value for the rank feature, scoring them by that value
+    RankFeature {
+        /// The field being searched
+        field: FieldId,
+
+        /// The name of the feature. Features of rank_feature fields don't have a name
+        feature: String,
+
+        /// Converts the value of the feature into a score
+        function: RankFeatureFunction,
+
+        /// Multiplies the score
+        boost: f32,
+    },
+
+    /// Matches documents that have a value in the field, scoring them by how close it is to the origin
+    DistanceFeature {
+        /// The field being searched
+        field: FieldId,
+
+        origin: DistanceFeatureOrigin,
+
+        /// The distance at which documents score half of the boost
+        pivot: f64,
+
+        /// Multiplies the score
+        boost: f32,
+    },
+
+    /// Matches the same documents as the inner query, adjusting their scores with functions
+    FunctionScore {
+        query: Box<Query>,
+        functions: Vec<FilteredScoreFunction>,
+
+        /// How the results of the functions are combined with each other
+        score_mode: ScoreMode,
+
+        /// How the combined result of the functions is combined with the score of the query
+        boost_mode: BoostMode,
+
+        /// The combined result of the functions is limited to this
+        max_boost: f32,
+
+        /// Multiplies the final score
+        boost: f32,
+    },
+
+    /// Matches documents that have a nested document in the path that matches the inner query
+    /// The scores of the matching nested documents are combined into the score of their parent
+    Nested {
+        /// The field of the nested path
+        path: FieldId,
+
+        /// The query to run against the nested documents
+        query: Box<Query>,
+
+        score_mode: NestedScoreMode,
+    },
+
+    /// Matches documents that the script returns true for
+    /// The script reads the stored values of fields and the params
+    Script {
+        script: Expression,
+
+        params: HashMap<String, ScriptValue>,
+
+        /// The score to assign to each document
+        score: f32,
+    },
+
     /// Joins two queries with an AND operator
     /// This intersects the results of the queries. The scores are combined by average
     Conjunction {
@@ -59,6 +335,16 @@ pub enum Query {
     /// Unlike a regular Disjunction query, this takes the highest score of each query for a particular match
     DisjunctionMax {
         queries: Vec<Query>,
+
+        /// How much the scores of the other matching queries add to the highest score. With 1.0, the scores are summed
+        tie_breaker: f32,
+    },
+
+    /// Matches documents that match at least the minimum number of the queries
+    /// The scores are combined by average, the same as a Disjunction
+    MinimumShouldMatch {
+        queries: Vec<Query>,
+        minimum: usize,
     },
 
     /// Removes documents that do not match the "filter" query from the results
@@ -73,6 +359,13 @@ pub enum Query {
         query: Box<Query>,
         exclude: Box<Query>
     },
+
+    /// Gives the query a name so the hits can report whether they matched it
+    /// This doesn't change which documents match or how they are scored
+    Named {
+        name: String,
+        query: Box<Query>,
+    },
 }
 
 impl Query {
@@ -92,6 +385,21 @@ impl Query {
         }
     }
 
+    /// Creates a query that matches documents that match at least the minimum number of the queries
+    pub fn minimum_should_match(mut queries: Vec<Query>, minimum: usize) -> Query {
+        if minimum > queries.len() {
+            return Query::None;
+        }
+
+        match (minimum, queries.len()) {
+            (_, 0) => Query::None,
+            (_, 1) => queries.pop().unwrap(),
+            (0, _) | (1, _) => Query::Disjunction { queries: queries },
+            (minimum, num_queries) if minimum == num_queries => Query::Conjunction { queries: queries },
+            _ => Query::MinimumShouldMatch { queries: queries, minimum: minimum },
+        }
+    }
+
     /// Filters the query by another query
     /// Only documents that match the other query will remain in the results but the other query will not affect the score
     pub fn filter(self, filter: Query) -> Query {
@@ -133,6 +441,57 @@ impl Query {
             Query::MultiTerm{ref mut scorer, ..} => {
                 scorer.boost *= add_boost;
             }
+            Query::Phrase{ref mut scorer, ..} => {
+                scorer.boost *= add_boost;
+            }
+            Query::PhrasePrefix{ref mut scorer, ..} => {
+                scorer.boost *= add_boost;
+            }
+            Query::CommonTerms{ref mut scorer, ..} => {
+                scorer.boost *= add_boost;
+            }
+            Query::BlendedTerm{ref mut scorer, ..} => {
+                scorer.boost *= add_boost;
+            }
+            Query::Intervals{ref mut scorer, ..} => {
+                scorer.boost *= add_boost;
+            }
+            Query::TermSet{ref mut score, ..} => {
+                *score *= add_boost;
+            }
+            Query::Ids{ref mut score, ..} => {
+                *score *= add_boost;
+            }
+            Query::DocIds{ref mut score, ..} => {
+                *score *= add_boost;
+            }
+            Query::Exists{ref mut score, ..} => {
+                *score *= add_boost;
+            }
+            Query::Wildcard{ref mut score, ..} => {
+                *score *= add_boost;
+            }
+            Query::GeoShape{ref mut score, ..} => {
+                *score *= add_boost;
+            }
+            Query::GeoDistance{ref mut score, ..} => {
+                *score *= add_boost;
+            }
+            Query::RankFeature{ref mut boost, ..} => {
+                *boost *= add_boost;
+            }
+            Query::DistanceFeature{ref mut boost, ..} => {
+                *boost *= add_boost;
+            }
+            Query::FunctionScore{ref mut boost, ..} => {
+                *boost *= add_boost;
+            }
+            Query::Nested{ref mut query, ..} => {
+                query.add_boost(add_boost);
+            }
+            Query::Script{ref mut score, ..} => {
+                *score *= add_boost;
+            }
             Query::Conjunction{ref mut queries} => {
                 for query in queries {
                     query.add_boost(add_boost);
@@ -143,7 +502,12 @@ impl Query {
                     query.add_boost(add_boost);
                 }
             }
-            Query::DisjunctionMax{ref mut queries} => {
+            Query::DisjunctionMax{ref mut queries, ..} => {
+                for query in queries {
+                    query.add_boost(add_boost);
+                }
+            }
+            Query::MinimumShouldMatch{ref mut queries, ..} => {
                 for query in queries {
                     query.add_boost(add_boost);
                 }
@@ -154,6 +518,126 @@ impl Query {
             Query::Exclude{ref mut query, ..} => {
                 query.add_boost(add_boost);
             }
+            Query::Named{ref mut query, ..} => {
+                query.add_boost(add_boost);
+            }
+        }
+    }
+
+    /// Finds the named queries within this query
+    ///
+    /// Queries inside nested queries are left out as they match the nested
+    /// documents rather than the documents that are returned.
+    pub fn named_queries(&self) -> Vec<(&str, &Query)> {
+        let mut named_queries = Vec::new();
+        self.collect_named_queries(&mut named_queries);
+        named_queries
+    }
+
+    fn collect_named_queries<'a>(&'a self, named_queries: &mut Vec<(&'a str, &'a Query)>) {
+        match *self {
+            Query::Named{ref name, ref query} => {
+                named_queries.push((name, query));
+                query.collect_named_queries(named_queries);
+            }
+            Query::FunctionScore{ref query, ..} |
+            Query::Exclude{ref query, ..} => query.collect_named_queries(named_queries),
+            Query::Filter{ref query, ref filter} => {
+                query.collect_named_queries(named_queries);
+                filter.collect_named_queries(named_queries);
+            }
+            Query::Conjunction{ref queries} |
+            Query::Disjunction{ref queries} |
+            Query::DisjunctionMax{ref queries, ..} |
+            Query::MinimumShouldMatch{ref queries, ..} => {
+                for query in queries.iter() {
+                    query.collect_named_queries(named_queries);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Checks if the documents that the query matches in a segment only depend on
+    /// the query and the contents of that segment
+    ///
+    /// Segments never change, except for deletions which are applied separately,
+    /// so the matches of these queries can be cached and reused for other searches.
+    /// Queries that find documents by key depend on the current version of each
+    /// document, and scripts are too costly to compare, so these aren't cacheable.
+    pub fn is_cacheable(&self) -> bool {
+        match *self {
+            Query::Ids{..} |
+            Query::DocIds{..} |
+            Query::Script{..} => false,
+            Query::FunctionScore{ref query, ..} |
+            Query::Nested{ref query, ..} |
+            Query::Named{ref query, ..} => query.is_cacheable(),
+            Query::Filter{ref query, ref filter} => query.is_cacheable() && filter.is_cacheable(),
+            Query::Exclude{ref query, ref exclude} => query.is_cacheable() && exclude.is_cacheable(),
+            Query::Conjunction{ref queries} |
+            Query::Disjunction{ref queries} |
+            Query::DisjunctionMax{ref queries, ..} |
+            Query::MinimumShouldMatch{ref queries, ..} => queries.iter().all(|query| query.is_cacheable()),
+            _ => true,
+        }
+    }
+
+    /// Finds the queries that are only used to filter the results and are cacheable
+    ///
+    /// These are hints for a filter cache. Each filter is returned whole, without
+    /// also returning the parts inside it.
+    pub fn cacheable_filters(&self) -> Vec<&Query> {
+        let mut filters = Vec::new();
+        self.collect_cacheable_filters(&mut filters);
+        filters
+    }
+
+    fn collect_cacheable_filters<'a>(&'a self, filters: &mut Vec<&'a Query>) {
+        match *self {
+            Query::Filter{ref query, ref filter} => {
+                query.collect_cacheable_filters(filters);
+                filter.collect_filter_clauses(filters);
+            }
+            Query::Exclude{ref query, ref exclude} => {
+                query.collect_cacheable_filters(filters);
+                exclude.collect_filter_clauses(filters);
+            }
+            Query::FunctionScore{ref query, ..} |
+            Query::Named{ref query, ..} => query.collect_cacheable_filters(filters),
+            Query::Conjunction{ref queries} |
+            Query::Disjunction{ref queries} |
+            Query::DisjunctionMax{ref queries, ..} |
+            Query::MinimumShouldMatch{ref queries, ..} => {
+                for query in queries.iter() {
+                    query.collect_cacheable_filters(filters);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Collects a query in filter position. If it isn't cacheable as a whole,
+    /// the cacheable clauses inside it are collected instead
+    fn collect_filter_clauses<'a>(&'a self, filters: &mut Vec<&'a Query>) {
+        if self.is_cacheable() {
+            filters.push(self);
+            return;
+        }
+
+        match *self {
+            Query::Conjunction{ref queries} |
+            Query::Disjunction{ref queries} |
+            Query::DisjunctionMax{ref queries, ..} |
+            Query::MinimumShouldMatch{ref queries, ..} => {
+                for query in queries.iter() {
+                    query.collect_filter_clauses(filters);
+                }
+            }
+            Query::Filter{..} |
+            Query::Exclude{..} => self.collect_cacheable_filters(filters),
+            Query::Named{ref query, ..} => query.collect_filter_clauses(filters),
+            _ => {}
         }
     }
 }
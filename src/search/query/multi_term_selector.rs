@@ -1,8 +1,33 @@
+use std::str;
+
 use search::term::Term;
+use search::query::wildcard::{WildcardPattern, TRIGRAM_TERM_PREFIX};
+use search::query::levenshtein::LevenshteinAutomaton;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum MultiTermSelector {
     Prefix(String),
+    Wildcard(WildcardPattern),
+
+    /// Selects no more than max_terms of the terms that the other selector
+    /// matches, taking the first ones in term order
+    Limit {
+        selector: Box<MultiTermSelector>,
+        max_terms: usize,
+    },
+
+    /// Selects integer and date terms between two inclusive bounds
+    IntegerRange {
+        gte: Option<i64>,
+        lte: Option<i64>,
+    },
+
+    /// Selects terms that the automaton accepts, keeping no more than
+    /// max_expansions of those with the smallest edit distances
+    Fuzzy {
+        automaton: LevenshteinAutomaton,
+        max_expansions: usize,
+    },
 }
 
 impl MultiTermSelector {
@@ -11,6 +36,32 @@ impl MultiTermSelector {
             MultiTermSelector::Prefix(ref prefix) => {
                 return term.as_bytes().starts_with(prefix.as_bytes());
             }
+            MultiTermSelector::Wildcard(ref pattern) => {
+                // Trigrams of wildcard fields aren't values, so shouldn't be matched
+                if term.as_bytes().first() == Some(&TRIGRAM_TERM_PREFIX) {
+                    return false;
+                }
+
+                match str::from_utf8(term.as_bytes()) {
+                    Ok(term) => pattern.matches(term),
+                    Err(_) => false,
+                }
+            }
+            MultiTermSelector::Limit{ref selector, ..} => selector.matches(term),
+            MultiTermSelector::IntegerRange{gte, lte} => {
+                match term.as_integer() {
+                    Some(value) => {
+                        gte.map_or(true, |gte| value >= gte) && lte.map_or(true, |lte| value <= lte)
+                    }
+                    None => false,
+                }
+            }
+            MultiTermSelector::Fuzzy{ref automaton, ..} => {
+                match str::from_utf8(term.as_bytes()) {
+                    Ok(term) => automaton.eval(term).is_some(),
+                    Err(_) => false,
+                }
+            }
         }
     }
 }
@@ -0,0 +1,140 @@
+//! Nested documents
+//!
+//! Each object of a nested field is indexed as a separate document, placed
+//! just before its parent in the same segment. Nested documents have a marker
+//! in the field of their path, so the parent of a nested document is the next
+//! document that isn't nested within the same path. Nested documents are
+//! hidden from all queries other than nested queries on their path.
+
+use roaring::RoaringBitmap;
+
+use search::term_vector::TermVector;
+use search::query::exists::field_exists_term;
+
+/// The marker that is indexed into the path field of each nested document.
+/// This is the same term that exists queries look for
+pub fn nested_document_marker() -> TermVector {
+    let mut positions = RoaringBitmap::new();
+    positions.insert(1);
+
+    let mut term_vector = TermVector::new();
+    term_vector.insert(field_exists_term(), positions);
+    term_vector
+}
+
+/// How the scores of the matching nested documents are combined into the score of their parent
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NestedScoreMode {
+    Avg,
+    Sum,
+    Min,
+    Max,
+    None,
+}
+
+impl Default for NestedScoreMode {
+    fn default() -> NestedScoreMode {
+        NestedScoreMode::Avg
+    }
+}
+
+impl NestedScoreMode {
+    pub fn combine(&self, scores: &[f32]) -> f32 {
+        if scores.is_empty() {
+            return 0.0f32;
+        }
+
+        match *self {
+            NestedScoreMode::Avg => scores.iter().sum::<f32>() / scores.len() as f32,
+            NestedScoreMode::Sum => scores.iter().sum::<f32>(),
+            NestedScoreMode::Min => scores.iter().cloned().fold(f32::INFINITY, f32::min),
+            NestedScoreMode::Max => scores.iter().cloned().fold(f32::NEG_INFINITY, f32::max),
+            NestedScoreMode::None => 0.0f32,
+        }
+    }
+}
+
+/// Finds the parents of the nested documents. Documents in the nested set are
+/// those with a marker in the path field or in the path field of any nested
+/// mapping inside it
+pub fn nested_parents(nested_docs: &RoaringBitmap, within_path: &RoaringBitmap) -> RoaringBitmap {
+    let mut parents = RoaringBitmap::new();
+    let mut last_parent = None;
+
+    for doc_id in nested_docs.iter() {
+        // Everything between a document and its parent is nested in the same path,
+        // so documents before the last parent found share it
+        if last_parent.map_or(false, |last_parent| doc_id < last_parent) {
+            continue;
+        }
+
+        let mut parent = doc_id + 1;
+        while within_path.contains(parent) {
+            parent += 1;
+        }
+
+        parents.insert(parent);
+        last_parent = Some(parent);
+    }
+
+    parents
+}
+
+/// Finds the nested documents of a parent, in the order they were indexed
+pub fn nested_children(parent: u32, within_path: &RoaringBitmap) -> Vec<u32> {
+    let mut children = Vec::new();
+    let mut doc_id = parent;
+
+    while doc_id > 0 && within_path.contains(doc_id - 1) {
+        doc_id -= 1;
+        children.push(doc_id);
+    }
+
+    children.reverse();
+    children
+}
+
+#[cfg(test)]
+mod tests {
+    use roaring::RoaringBitmap;
+
+    use super::{NestedScoreMode, nested_parents, nested_children};
+
+    fn bitmap(doc_ids: &[u32]) -> RoaringBitmap {
+        let mut bitmap = RoaringBitmap::new();
+        for doc_id in doc_ids {
+            bitmap.insert(*doc_id);
+        }
+
+        bitmap
+    }
+
+    #[test]
+    fn test_nested_parents() {
+        // Documents 0-1 are nested in 2, 4-6 are nested in 7
+        let within_path = bitmap(&[0, 1, 4, 5, 6]);
+
+        assert_eq!(nested_parents(&bitmap(&[1, 4, 6]), &within_path), bitmap(&[2, 7]));
+        assert_eq!(nested_parents(&bitmap(&[]), &within_path), bitmap(&[]));
+    }
+
+    #[test]
+    fn test_nested_children() {
+        let within_path = bitmap(&[0, 1, 4, 5, 6]);
+
+        assert_eq!(nested_children(2, &within_path), vec![0, 1]);
+        assert_eq!(nested_children(3, &within_path), Vec::<u32>::new());
+        assert_eq!(nested_children(7, &within_path), vec![4, 5, 6]);
+    }
+
+    #[test]
+    fn test_combine_scores() {
+        let scores = [1.0f32, 2.0, 6.0];
+
+        assert_eq!(NestedScoreMode::Avg.combine(&scores), 3.0);
+        assert_eq!(NestedScoreMode::Sum.combine(&scores), 9.0);
+        assert_eq!(NestedScoreMode::Min.combine(&scores), 1.0);
+        assert_eq!(NestedScoreMode::Max.combine(&scores), 6.0);
+        assert_eq!(NestedScoreMode::None.combine(&scores), 0.0);
+    }
+}
@@ -0,0 +1,76 @@
+//! Phrase matching
+//!
+//! The positions of each term in a document are stored alongside its term
+//! frequency. Subtracting the term's place in the phrase from each of its
+//! positions lines up the positions of a phrase that appears in order, so the
+//! number of moves needed to make the phrase appear is the smallest spread of
+//! these offsets that still takes one from each term. Terms that are swapped
+//! around need two moves each, as they would in Lucene.
+
+/// Finds the fewest moves needed for the terms to appear as a phrase. Takes
+/// the positions of each term of the phrase in order, each sorted
+pub fn phrase_slop(positions: &[Vec<u32>]) -> Option<u32> {
+    if positions.is_empty() || positions.iter().any(|term_positions| term_positions.is_empty()) {
+        return None;
+    }
+
+    // Offsets of each term's positions from where the phrase would start
+    let offsets = positions.iter().enumerate()
+        .map(|(i, term_positions)| term_positions.iter().map(|position| *position as i64 - i as i64).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    // Find the smallest range that holds an offset of every term by moving
+    // whichever term is furthest behind forward
+    let mut cursors = vec![0; offsets.len()];
+    let mut best = None;
+
+    loop {
+        let mut min_term = 0;
+        let mut max_offset = offsets[0][cursors[0]];
+
+        for (term, term_offsets) in offsets.iter().enumerate() {
+            let offset = term_offsets[cursors[term]];
+
+            if offset < offsets[min_term][cursors[min_term]] {
+                min_term = term;
+            }
+
+            if offset > max_offset {
+                max_offset = offset;
+            }
+        }
+
+        let spread = (max_offset - offsets[min_term][cursors[min_term]]) as u32;
+        if best.map_or(true, |best| spread < best) {
+            best = Some(spread);
+        }
+
+        cursors[min_term] += 1;
+        if spread == 0 || cursors[min_term] == offsets[min_term].len() {
+            return best;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::phrase_slop;
+
+    #[test]
+    fn test_phrase_slop() {
+        // "quick brown fox"
+        assert_eq!(phrase_slop(&[vec![2], vec![3], vec![4]]), Some(0));
+
+        // "quick fox" in "the quick brown fox"
+        assert_eq!(phrase_slop(&[vec![2], vec![4]]), Some(1));
+
+        // "fox quick" in "the quick brown fox"
+        assert_eq!(phrase_slop(&[vec![4], vec![2]]), Some(3));
+
+        // Picks the closest positions
+        assert_eq!(phrase_slop(&[vec![1, 10, 20], vec![5, 11, 30]]), Some(0));
+
+        // A term is missing
+        assert_eq!(phrase_slop(&[vec![1], vec![]]), None);
+    }
+}
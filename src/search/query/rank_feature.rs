@@ -0,0 +1,118 @@
+//! Rank features
+//!
+//! Rank features are positive numbers attached to documents, such as a page
+//! rank, that are used to adjust their score. Each value is indexed as a term
+//! that holds the feature name with the value encoded after it. Values are
+//! rounded to nine significant bits so the number of distinct terms stays
+//! small enough to check every one of them while scoring.
+
+use byteorder::{ByteOrder, BigEndian};
+
+use search::term::Term;
+use search::query::multi_term_selector::MultiTermSelector;
+
+/// Rank feature terms start with this byte so they can't be confused with other values
+pub const RANK_FEATURE_TERM_PREFIX: u8 = 0x02;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RankFeatureFunction {
+    /// S / (S + pivot). If no pivot is given, the geometric mean of the indexed values is used
+    Saturation {
+        pivot: Option<f32>,
+    },
+
+    /// log(scaling_factor + S)
+    Log {
+        scaling_factor: f32,
+    },
+
+    /// S^exponent / (S^exponent + pivot^exponent)
+    Sigmoid {
+        pivot: f32,
+        exponent: f32,
+    },
+}
+
+impl RankFeatureFunction {
+    pub fn score(&self, value: f32) -> f32 {
+        match *self {
+            RankFeatureFunction::Saturation{pivot} => {
+                let pivot = pivot.unwrap_or(1.0f32);
+                value / (value + pivot)
+            }
+            RankFeatureFunction::Log{scaling_factor} => {
+                (scaling_factor + value).ln()
+            }
+            RankFeatureFunction::Sigmoid{pivot, exponent} => {
+                let value = value.powf(exponent);
+                value / (value + pivot.powf(exponent))
+            }
+        }
+    }
+}
+
+fn rank_feature_term_prefix(feature: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(feature.len() + 2);
+    bytes.push(RANK_FEATURE_TERM_PREFIX);
+    bytes.extend(feature.as_bytes());
+    bytes.push(0);
+    bytes
+}
+
+/// Builds the term that a feature's value is indexed as. Values must be positive
+pub fn rank_feature_term(feature: &str, value: f32) -> Term {
+    let mut bytes = rank_feature_term_prefix(feature);
+
+    // Positive floats don't use the sign bit, so this fits into 16 bits
+    let mut value_bytes = [0; 2];
+    BigEndian::write_u16(&mut value_bytes, (value.to_bits() >> 15) as u16);
+    bytes.extend(&value_bytes);
+
+    Term::from_bytes(&bytes)
+}
+
+/// Reads the value out of a term built by rank_feature_term
+pub fn rank_feature_value(feature: &str, term: &Term) -> Option<f32> {
+    let prefix = rank_feature_term_prefix(feature);
+    let bytes = term.as_bytes();
+
+    if bytes.len() != prefix.len() + 2 || !bytes.starts_with(&prefix) {
+        return None;
+    }
+
+    let bits = BigEndian::read_u16(&bytes[prefix.len()..]) as u32;
+    Some(f32::from_bits(bits << 15))
+}
+
+/// Selects every term that holds a value of the feature
+pub fn rank_feature_selector(feature: &str) -> MultiTermSelector {
+    MultiTermSelector::Prefix(String::from_utf8(rank_feature_term_prefix(feature)).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use search::term::Term;
+
+    use super::{RankFeatureFunction, rank_feature_term, rank_feature_value};
+
+    #[test]
+    fn test_rank_feature_term_round_trip() {
+        let term = rank_feature_term("pagerank", 8.0f32);
+        assert_eq!(rank_feature_value("pagerank", &term), Some(8.0f32));
+
+        // Values are rounded down to nine significant bits
+        let term = rank_feature_term("pagerank", 1.001f32);
+        assert_eq!(rank_feature_value("pagerank", &term), Some(1.0f32));
+
+        // Terms of other features don't have a value
+        assert_eq!(rank_feature_value("url_length", &term), None);
+        assert_eq!(rank_feature_value("pagerank", &Term::from_string("pagerank")), None);
+    }
+
+    #[test]
+    fn test_functions() {
+        assert_eq!(RankFeatureFunction::Saturation{pivot: Some(8.0f32)}.score(8.0f32), 0.5f32);
+        assert_eq!(RankFeatureFunction::Log{scaling_factor: 1.0f32}.score(0.0f32), 0.0f32);
+        assert_eq!(RankFeatureFunction::Sigmoid{pivot: 2.0f32, exponent: 2.0f32}.score(2.0f32), 0.5f32);
+    }
+}
@@ -0,0 +1,90 @@
+//! Script queries
+//!
+//! Script queries match documents that a script returns true for. Scripts read
+//! the stored values of fields as "doc.name", so these fields must be mapped
+//! with "store". Candidates are the documents with a value in each field that
+//! the script reads, as the script can't be run on the others.
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use script::{Expression, ScriptValue};
+use search::schema::FieldType;
+
+/// Finds the names of the fields that the script reads, in the order they first appear
+pub fn script_fields(expression: &Expression) -> Vec<String> {
+    fn collect(expression: &Expression, fields: &mut Vec<String>) {
+        match *expression {
+            Expression::Literal(_) => {}
+            Expression::Variable(ref name) => {
+                if name.starts_with("doc.") && !fields.iter().any(|field| *field == name["doc.".len()..]) {
+                    fields.push(name["doc.".len()..].to_string());
+                }
+            }
+            Expression::Negate(ref expression) | Expression::Not(ref expression) => collect(expression, fields),
+            Expression::Binary(_, ref left, ref right) => {
+                collect(left, fields);
+                collect(right, fields);
+            }
+        }
+    }
+
+    let mut fields = Vec::new();
+    collect(expression, &mut fields);
+    fields
+}
+
+/// Reads the stored value of a field for a script. Integer fields with many
+/// values give the first one and dates are given in milliseconds since the epoch
+pub fn stored_script_value(field_type: &FieldType, stored_value: &[u8]) -> Option<ScriptValue> {
+    match *field_type {
+        FieldType::I64 | FieldType::DateTime => {
+            if stored_value.len() < 8 || stored_value.len() % 8 != 0 {
+                return None;
+            }
+
+            let value = LittleEndian::read_i64(&stored_value[..8]);
+            if *field_type == FieldType::DateTime {
+                Some(ScriptValue::Number((value / 1000) as f64))
+            } else {
+                Some(ScriptValue::Number(value as f64))
+            }
+        }
+        FieldType::Boolean => {
+            match stored_value {
+                b"t" => Some(ScriptValue::Boolean(true)),
+                b"f" => Some(ScriptValue::Boolean(false)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{LittleEndian, WriteBytesExt};
+
+    use script::{Expression, ScriptValue};
+    use search::schema::FieldType;
+
+    use super::{script_fields, stored_script_value};
+
+    #[test]
+    fn test_script_fields() {
+        let expression = Expression::parse("doc['price'] * doc['qty'] > params.min && doc['price'].value < 100").unwrap();
+
+        assert_eq!(script_fields(&expression), vec!["price".to_string(), "qty".to_string()]);
+    }
+
+    #[test]
+    fn test_stored_script_value() {
+        let mut prices = Vec::new();
+        prices.write_i64::<LittleEndian>(30).unwrap();
+        prices.write_i64::<LittleEndian>(10).unwrap();
+
+        assert_eq!(stored_script_value(&FieldType::I64, &prices), Some(ScriptValue::Number(30.0)));
+        assert_eq!(stored_script_value(&FieldType::I64, &prices[..4]), None);
+        assert_eq!(stored_script_value(&FieldType::Boolean, b"t"), Some(ScriptValue::Boolean(true)));
+        assert_eq!(stored_script_value(&FieldType::Text, b"hello"), None);
+    }
+}
@@ -0,0 +1,268 @@
+//! Wildcard patterns
+//!
+//! Wildcard fields index every trigram (run of three characters) of each value
+//! alongside the value itself. A pattern such as "*error*substring*" can then be
+//! run by intersecting the postings lists of the trigrams in its literal parts
+//! and checking each candidate's stored value against the pattern, rather than
+//! testing every term in the dictionary.
+
+use search::term::Term;
+
+/// Trigram terms start with this byte so they can't be confused with whole values
+pub const TRIGRAM_TERM_PREFIX: u8 = 0x01;
+
+/// Separates the values of multi-valued wildcard fields in the stored value
+pub const VALUE_SEPARATOR: char = '\u{0}';
+
+#[derive(Debug, Clone, PartialEq)]
+enum PatternToken {
+    Literal(char),
+
+    /// "?", matches exactly one character
+    AnyChar,
+
+    /// "*", matches zero or more characters
+    AnyString,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WildcardPattern {
+    pattern: String,
+    tokens: Vec<PatternToken>,
+    case_insensitive: bool,
+}
+
+impl WildcardPattern {
+    /// Compiles a pattern. "*" and "?" can be matched literally by escaping them with a backslash
+    pub fn new(pattern: &str) -> WildcardPattern {
+        let mut tokens = Vec::new();
+        let mut chars = pattern.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '*' => {
+                    // Consecutive stars are the same as one
+                    if tokens.last() != Some(&PatternToken::AnyString) {
+                        tokens.push(PatternToken::AnyString);
+                    }
+                }
+                '?' => tokens.push(PatternToken::AnyChar),
+                '\\' => tokens.push(PatternToken::Literal(chars.next().unwrap_or('\\'))),
+                c => tokens.push(PatternToken::Literal(c)),
+            }
+        }
+
+        WildcardPattern {
+            pattern: pattern.to_string(),
+            tokens: tokens,
+            case_insensitive: false,
+        }
+    }
+
+    /// Makes the literal characters of the pattern match regardless of case
+    pub fn case_insensitive(mut self) -> WildcardPattern {
+        self.tokens = self.tokens.into_iter()
+            .flat_map(|token| {
+                match token {
+                    PatternToken::Literal(c) => c.to_lowercase().map(PatternToken::Literal).collect(),
+                    token => vec![token],
+                }
+            })
+            .collect();
+        self.case_insensitive = true;
+        self
+    }
+
+    pub fn is_case_insensitive(&self) -> bool {
+        self.case_insensitive
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Checks if the whole of the value matches the pattern
+    pub fn matches(&self, value: &str) -> bool {
+        let value = if self.case_insensitive {
+            value.to_lowercase().chars().collect::<Vec<char>>()
+        } else {
+            value.chars().collect::<Vec<char>>()
+        };
+        let mut token_pos = 0;
+        let mut value_pos = 0;
+
+        // Where to resume from if the current attempt fails: the token after the
+        // last star and the position in the value that the star would extend to
+        let mut backtrack: Option<(usize, usize)> = None;
+
+        while value_pos < value.len() {
+            match self.tokens.get(token_pos) {
+                Some(&PatternToken::Literal(c)) if c == value[value_pos] => {
+                    token_pos += 1;
+                    value_pos += 1;
+                    continue;
+                }
+                Some(&PatternToken::AnyChar) => {
+                    token_pos += 1;
+                    value_pos += 1;
+                    continue;
+                }
+                Some(&PatternToken::AnyString) => {
+                    token_pos += 1;
+                    backtrack = Some((token_pos, value_pos));
+                    continue;
+                }
+                _ => {}
+            }
+
+            // Mismatch, let the last star swallow one more character
+            match backtrack {
+                Some((star_token_pos, star_value_pos)) => {
+                    token_pos = star_token_pos;
+                    value_pos = star_value_pos + 1;
+                    backtrack = Some((star_token_pos, star_value_pos + 1));
+                }
+                None => return false,
+            }
+        }
+
+        // Any remaining tokens must be stars
+        self.tokens[token_pos..].iter().all(|token| *token == PatternToken::AnyString)
+    }
+
+    /// Returns the trigrams that every matching value must contain
+    ///
+    /// These are taken from the runs of literal characters in the pattern. Runs that
+    /// are shorter than three characters don't give us any trigrams.
+    pub fn trigrams(&self) -> Vec<String> {
+        let mut trigrams = Vec::new();
+        let mut literal = String::new();
+
+        for token in self.tokens.iter().chain(Some(&PatternToken::AnyString)) {
+            match *token {
+                PatternToken::Literal(c) => literal.push(c),
+                _ => {
+                    for trigram in trigrams_of(&literal) {
+                        if !trigrams.contains(&trigram) {
+                            trigrams.push(trigram);
+                        }
+                    }
+
+                    literal.clear();
+                }
+            }
+        }
+
+        trigrams
+    }
+}
+
+/// Escapes a value so that a pattern made from it only matches the value itself
+pub fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        if c == '*' || c == '?' || c == '\\' {
+            escaped.push('\\');
+        }
+
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+/// Returns every trigram of the value, in order
+pub fn trigrams_of(value: &str) -> Vec<String> {
+    let chars = value.chars().collect::<Vec<char>>();
+
+    if chars.len() < 3 {
+        return Vec::new();
+    }
+
+    chars.windows(3).map(|window| window.iter().collect()).collect()
+}
+
+/// Builds the term that a trigram is indexed as
+pub fn trigram_term(trigram: &str) -> Term {
+    let mut bytes = Vec::with_capacity(trigram.len() + 1);
+    bytes.push(TRIGRAM_TERM_PREFIX);
+    bytes.extend(trigram.as_bytes());
+    Term::from_bytes(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WildcardPattern, escape, trigrams_of, trigram_term};
+
+    #[test]
+    fn test_matches() {
+        let pattern = WildcardPattern::new("*error*substring*");
+        assert!(pattern.matches("an error with a substring"));
+        assert!(pattern.matches("errorsubstring"));
+        assert!(!pattern.matches("a substring before an error"));
+
+        let pattern = WildcardPattern::new("ki?er");
+        assert!(pattern.matches("kiwer"));
+        assert!(!pattern.matches("kier"));
+        assert!(!pattern.matches("kiwers"));
+
+        let pattern = WildcardPattern::new("*");
+        assert!(pattern.matches(""));
+        assert!(pattern.matches("anything"));
+    }
+
+    #[test]
+    fn test_matches_backtracks() {
+        let pattern = WildcardPattern::new("*aab");
+        assert!(pattern.matches("aaab"));
+        assert!(!pattern.matches("aaba"));
+    }
+
+    #[test]
+    fn test_matches_escaped() {
+        let pattern = WildcardPattern::new("what\\?");
+        assert!(pattern.matches("what?"));
+        assert!(!pattern.matches("whats"));
+    }
+
+    #[test]
+    fn test_matches_case_insensitive() {
+        let pattern = WildcardPattern::new("Error*").case_insensitive();
+        assert!(pattern.matches("ERROR: disk full"));
+        assert!(pattern.matches("error"));
+        assert!(!pattern.matches("an error"));
+
+        // Patterns are case sensitive by default
+        assert!(!WildcardPattern::new("Error*").matches("error"));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a*b?c\\d"), "a\\*b\\?c\\\\d");
+        assert!(WildcardPattern::new(&escape("what?")).matches("what?"));
+        assert!(!WildcardPattern::new(&escape("what?")).matches("whats"));
+    }
+
+    #[test]
+    fn test_trigrams() {
+        let pattern = WildcardPattern::new("*error*ab?cdef*");
+        assert_eq!(pattern.trigrams(), vec!["err", "rro", "ror", "cde", "def"]);
+
+        // No literal runs long enough
+        let pattern = WildcardPattern::new("*ab*c?d*");
+        assert!(pattern.trigrams().is_empty());
+    }
+
+    #[test]
+    fn test_trigrams_of() {
+        assert_eq!(trigrams_of("hello"), vec!["hel", "ell", "llo"]);
+        assert_eq!(trigrams_of("hé!"), vec!["hé!"]);
+        assert!(trigrams_of("hi").is_empty());
+    }
+
+    #[test]
+    fn test_trigram_term() {
+        assert_eq!(trigram_term("abc").as_bytes(), &[0x01, b'a', b'b', b'c']);
+    }
+}
@@ -75,6 +75,11 @@ pub enum FieldType {
     I64,
     Boolean,
     DateTime,
+    Wildcard,
+    Flattened,
+
+    /// The path of a nested mapping. Only nested documents have a value in this field
+    Nested,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -92,6 +97,10 @@ impl FieldInfo {
             field_flags: field_flags,
         }
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
@@ -1,3 +1,5 @@
+use std::io::Cursor;
+
 use roaring::RoaringBitmap;
 
 use search::schema::FieldId;
@@ -7,6 +9,13 @@ use search::document::DocId;
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub struct SegmentId(pub u32);
 
+/// A segment that couldn't be searched, such as one with corrupt postings lists
+#[derive(Debug, Clone, PartialEq)]
+pub struct SegmentFailure {
+    pub segment: SegmentId,
+    pub reason: String,
+}
+
 pub trait Segment {
     fn load_statistic(&self, stat_name: &[u8]) -> Result<Option<i64>, String>;
     fn load_stored_field_value_raw(&self, doc_local_id: u16, field_id: FieldId, value_type: &[u8]) -> Result<Option<Vec<u8>>, String>;
@@ -17,4 +26,21 @@ pub trait Segment {
     fn doc_id(&self, local_id: u16) -> DocId {
         DocId(self.id(), local_id)
     }
+
+    /// Loads the positions of a term in a document that is known to contain it
+    fn load_term_positions(&self, doc_local_id: u16, field_id: FieldId, term_id: TermId) -> Result<RoaringBitmap, String> {
+        let mut value_type = vec![b't', b'p'];
+        value_type.extend(term_id.0.to_string().as_bytes());
+
+        match self.load_stored_field_value_raw(doc_local_id, field_id, &value_type)? {
+            Some(positions) => RoaringBitmap::deserialize_from(Cursor::new(&positions[..])).map_err(|e| e.to_string()),
+            None => {
+                // Like term frequencies, positions are only written if they
+                // aren't just the first position
+                let mut positions = RoaringBitmap::new();
+                positions.insert(1);
+                Ok(positions)
+            }
+        }
+    }
 }
@@ -1,11 +1,30 @@
 use chrono::{DateTime, Utc, Timelike};
-use byteorder::{WriteBytesExt, LittleEndian};
+use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian};
 
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub struct TermId(pub u32);
 
 
+/// Integers are stored big-endian with this bit flipped, so negative numbers
+/// come before positive ones
+const SIGN_BIT: u64 = 1 << 63;
+
+
+/// A value in the index, encoded as bytes
+///
+/// Terms are ordered by comparing their bytes. The term dictionary is kept in
+/// this order, so terms of the same type are encoded such that it agrees with
+/// the order of their values:
+///
+///  - Strings are in the order of their UTF-8 bytes, which is the same as the
+///    order of their code points
+///  - `false` is before `true`
+///  - Integers and dates are big-endian with the sign bit flipped, so negative
+///    values come before positive ones
+///
+/// Terms don't record their type, so terms of different types are still
+/// compared by their bytes. That order is total and stable but has no meaning.
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
 pub struct Term(Vec<u8>);
 
@@ -34,29 +53,142 @@ impl Term {
 
     pub fn from_integer(value: i64) -> Term {
         let mut bytes = Vec::with_capacity(8);
-        bytes.write_i64::<LittleEndian>(value).unwrap();
+        bytes.write_u64::<BigEndian>(value as u64 ^ SIGN_BIT).unwrap();
         Term(bytes)
     }
 
     pub fn from_datetime(value: &DateTime<Utc>) -> Term {
-        let mut bytes = Vec::with_capacity(0);
         let timestamp = value.timestamp();
         let micros = value.nanosecond() / 1000;
         let timestamp_with_micros = timestamp * 1000000 + micros as i64;
-        bytes.write_i64::<LittleEndian>(timestamp_with_micros).unwrap();
-        Term(bytes)
+        Term::from_integer(timestamp_with_micros)
     }
 
     pub fn as_bytes(&self) -> &[u8] {
         &self.0
     }
+
+    /// Decodes terms that were created with from_integer or from_datetime
+    pub fn as_integer(&self) -> Option<i64> {
+        if self.0.len() != 8 {
+            return None;
+        }
+
+        (&self.0[..]).read_u64::<BigEndian>().ok().map(|value| (value ^ SIGN_BIT) as i64)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use chrono::{DateTime, Utc, Timelike};
+    use std::char;
+    use std::cmp::Ordering;
+    use std::fmt::Debug;
+
+    use chrono::{DateTime, Utc, Timelike, TimeZone};
     use super::Term;
 
+    const CASES: usize = 1000;
+
+    /// Generates values for the property tests
+    ///
+    /// This is a xorshift generator with a fixed seed, so failures are repeatable.
+    struct Generator(u64);
+
+    impl Generator {
+        fn new() -> Generator {
+            Generator(0x2545_f491_4f6c_dd1d)
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, max: u64) -> u64 {
+            self.next_u64() % max
+        }
+
+        /// Generates integers, favouring ones around zero and the edges of the range
+        fn integer(&mut self) -> i64 {
+            match self.below(4) {
+                0 => self.below(200) as i64 - 100,
+                1 => [i64::min_value(), i64::min_value() + 1, -1, 0, 1, i64::max_value() - 1, i64::max_value()][self.below(7) as usize],
+                _ => self.next_u64() as i64,
+            }
+        }
+
+        /// Generates strings, including multi-byte characters
+        fn string(&mut self) -> String {
+            let length = self.below(8);
+            (0..length).map(|_| {
+                match self.below(3) {
+                    0 => (b'a' + self.below(4) as u8) as char,
+                    1 => char::from_u32(0x80 + self.below(0x800) as u32).unwrap(),
+                    _ => char::from_u32(0x10000 + self.below(0x1000) as u32).unwrap(),
+                }
+            }).collect()
+        }
+
+        fn boolean(&mut self) -> bool {
+            self.below(2) == 1
+        }
+    }
+
+    /// Checks that encoding values as terms gives the same order as the values
+    fn check_order_preserved<T: Ord + Debug, G: FnMut(&mut Generator) -> T, E: Fn(&T) -> Term>(mut generate: G, encode: E) {
+        let mut generator = Generator::new();
+
+        for _ in 0..CASES {
+            let a = generate(&mut generator);
+            let b = generate(&mut generator);
+
+            assert_eq!(encode(&a).cmp(&encode(&b)), a.cmp(&b), "order of {:?} and {:?} wasn't preserved", a, b);
+            assert_eq!(encode(&a).cmp(&encode(&a)), Ordering::Equal);
+        }
+    }
+
+    /// Checks that values come back unchanged after being encoded as terms
+    fn check_round_trip<T: PartialEq + Debug, G: FnMut(&mut Generator) -> T, E: Fn(&T) -> Term, D: Fn(&Term) -> Option<T>>(mut generate: G, encode: E, decode: D) {
+        let mut generator = Generator::new();
+
+        for _ in 0..CASES {
+            let value = generate(&mut generator);
+            assert_eq!(decode(&encode(&value)), Some(value));
+        }
+    }
+
+    #[test]
+    fn test_string_order() {
+        check_order_preserved(|g| g.string(), |value| Term::from_string(value));
+    }
+
+    #[test]
+    fn test_boolean_order() {
+        check_order_preserved(|g| g.boolean(), |value| Term::from_boolean(*value));
+    }
+
+    #[test]
+    fn test_integer_order() {
+        check_order_preserved(|g| g.integer(), |value| Term::from_integer(*value));
+    }
+
+    #[test]
+    fn test_datetime_order() {
+        check_order_preserved(|g| Utc.timestamp(g.below(1 << 34) as i64 - (1 << 33), g.below(1_000_000) as u32 * 1000), |value| Term::from_datetime(value));
+    }
+
+    #[test]
+    fn test_string_round_trip() {
+        check_round_trip(|g| g.string(), |value| Term::from_string(value), |term| String::from_utf8(term.as_bytes().to_vec()).ok());
+    }
+
+    #[test]
+    fn test_integer_round_trip_generated() {
+        check_round_trip(|g| g.integer(), |value| Term::from_integer(*value), |term| term.as_integer());
+    }
+
     #[test]
     fn test_string_to_bytes() {
         let term = Term::from_string("foo");
@@ -71,6 +203,12 @@ mod tests {
         assert_eq!(term.as_bytes().to_vec(), vec![227, 129, 147, 227, 130, 147, 227, 129, 171, 227, 129, 161, 227, 129, 175])
     }
 
+    #[test]
+    fn test_integer_round_trip() {
+        assert_eq!(Term::from_integer(-123).as_integer(), Some(-123));
+        assert_eq!(Term::from_string("foo").as_integer(), None);
+    }
+
     #[test]
     fn test_blank_string_to_bytes() {
         let term = Term::from_string("");
@@ -98,14 +236,14 @@ mod tests {
     fn test_integer_to_bytes() {
         let term = Term::from_integer(123);
 
-        assert_eq!(term.as_bytes().to_vec(), vec![123, 0, 0, 0, 0, 0, 0, 0])
+        assert_eq!(term.as_bytes().to_vec(), vec![128, 0, 0, 0, 0, 0, 0, 123])
     }
 
     #[test]
     fn test_negative_integer_to_bytes() {
         let term = Term::from_integer(-123);
 
-        assert_eq!(term.as_bytes().to_vec(), vec![133, 255, 255, 255, 255, 255, 255, 255])
+        assert_eq!(term.as_bytes().to_vec(), vec![127, 255, 255, 255, 255, 255, 255, 133])
     }
 
     #[test]
@@ -113,7 +251,7 @@ mod tests {
         let date = "2016-07-23T16:15:00+01:00".parse::<DateTime<Utc>>().unwrap();
         let term = Term::from_datetime(&date);
 
-        assert_eq!(term.as_bytes().to_vec(), vec![0, 101, 191, 3, 79, 56, 5, 0])
+        assert_eq!(term.as_bytes().to_vec(), vec![128, 5, 56, 79, 3, 191, 101, 0])
     }
 
     #[test]
@@ -123,7 +261,7 @@ mod tests {
         let term = Term::from_datetime(&date);
 
         // This is exactly 123123 higher than the result of "test_datetime_to_bytes"
-        assert_eq!(term.as_bytes().to_vec(), vec![243, 69, 193, 3, 79, 56, 5, 0])
+        assert_eq!(term.as_bytes().to_vec(), vec![128, 5, 56, 79, 3, 193, 69, 243])
     }
 
     #[test]
@@ -132,6 +270,6 @@ mod tests {
         let term = Term::from_datetime(&date);
 
         // This is exactly 3_600_000_000 lower than the result of "test_datetime_to_bytes"
-        assert_eq!(term.as_bytes().to_vec(), vec![0, 193, 43, 45, 78, 56, 5, 0])
+        assert_eq!(term.as_bytes().to_vec(), vec![128, 5, 56, 78, 45, 43, 193, 0])
     }
 }
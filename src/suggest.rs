@@ -0,0 +1,461 @@
+//! Suggesters
+//!
+//! Only the completion suggester is implemented. It finds the inputs of
+//! completion fields that start with the prefix, along with their weights.
+//! Suggestions can be restricted to documents with any of a list of context
+//! values, the boost of the best matching context is multiplied into the
+//! weight of the suggestion.
+
+use std::cmp::Ordering;
+
+use serde_json::{Map, Value as Json};
+use search::{Query, Term};
+use search::document::FieldValue;
+use search::query::multi_term_selector::MultiTermSelector;
+use search::query::term_scorer::TermScorer;
+use search::backends::rocksdb::RocksDBReader;
+use search::collectors::doc_id_set::DocIdSetCollector;
+
+use mapping::FieldType;
+use mapping::completion::{CompletionContext, CompletionEntry, completion_term};
+use index::metadata::IndexMetadata;
+
+
+/// The most suggestions to give, unless the request gives a size
+const DEFAULT_SUGGESTION_SIZE: usize = 5;
+
+
+/// A context value that suggestions can come from
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContextQuery {
+    /// A category or a geo point, depending on the type of the context
+    pub value: Json,
+
+    /// For geo contexts, the length of the geohash to match
+    pub precision: Option<u32>,
+
+    pub boost: f64,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompletionSuggester {
+    pub name: String,
+    pub prefix: String,
+    pub field: String,
+    pub size: usize,
+    pub skip_duplicates: bool,
+
+    /// The values that suggestions must have in at least one of the contexts
+    pub contexts: Vec<(String, Vec<ContextQuery>)>,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SuggestOption {
+    pub text: String,
+    pub index: String,
+    pub score: f64,
+}
+
+
+fn parse_context_query(json: &Json) -> Result<ContextQuery, String> {
+    match *json {
+        Json::Object(ref object) if object.contains_key("context") => {
+            let mut query = ContextQuery {
+                value: Json::Null,
+                precision: None,
+                boost: 1.0,
+            };
+
+            for (key, value) in object.iter() {
+                match key.as_ref() {
+                    "context" => query.value = value.clone(),
+                    "precision" => query.precision = Some(value.as_u64().ok_or_else(|| "context precision must be a positive integer".to_string())? as u32),
+                    "boost" => query.boost = value.as_f64().ok_or_else(|| "context boost must be a number".to_string())?,
+                    _ => return Err(format!("unrecognised context option {:?}", key)),
+                }
+            }
+
+            Ok(query)
+        }
+        _ => {
+            Ok(ContextQuery {
+                value: json.clone(),
+                precision: None,
+                boost: 1.0,
+            })
+        }
+    }
+}
+
+
+fn parse_completion(name: &str, prefix: String, json: &Json) -> Result<CompletionSuggester, String> {
+    let object = json.as_object().ok_or_else(|| "completion must be an object".to_string())?;
+    let mut suggester = CompletionSuggester {
+        name: name.to_string(),
+        prefix: prefix,
+        field: String::new(),
+        size: DEFAULT_SUGGESTION_SIZE,
+        skip_duplicates: false,
+        contexts: Vec::new(),
+    };
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "field" => suggester.field = value.as_str().ok_or_else(|| "field must be a string".to_string())?.to_string(),
+            "size" => suggester.size = value.as_u64().ok_or_else(|| "size must be a positive integer".to_string())? as usize,
+            "skip_duplicates" => suggester.skip_duplicates = value.as_bool().ok_or_else(|| "skip_duplicates must be a boolean".to_string())?,
+            "contexts" => {
+                let contexts_object = value.as_object().ok_or_else(|| "contexts must be an object".to_string())?;
+                for (context_name, queries_json) in contexts_object.iter() {
+                    // Geo points can be given as an array of numbers
+                    let queries = match *queries_json {
+                        Json::Array(ref array) if !array.first().map_or(false, |item| item.is_number()) => {
+                            array.iter().map(parse_context_query).collect::<Result<Vec<_>, _>>()?
+                        }
+                        _ => vec![parse_context_query(queries_json)?],
+                    };
+
+                    suggester.contexts.push((context_name.clone(), queries));
+                }
+            }
+            _ => return Err(format!("unrecognised completion option {:?}", key)),
+        }
+    }
+
+    if suggester.field.is_empty() {
+        return Err("completion must have a field".to_string());
+    }
+
+    Ok(suggester)
+}
+
+
+/// Parses the "suggest" section of a search request
+///
+/// A "text" at the top level is used by suggesters that don't give a prefix.
+pub fn parse_suggest(json: &Json) -> Result<Vec<CompletionSuggester>, String> {
+    let object = json.as_object().ok_or_else(|| "suggest must be an object".to_string())?;
+    let global_text = object.get("text").and_then(|text| text.as_str());
+
+    let mut suggesters = Vec::new();
+    for (name, suggester_json) in object.iter() {
+        if name == "text" {
+            continue;
+        }
+
+        let suggester_object = suggester_json.as_object().ok_or_else(|| format!("suggester {:?} must be an object", name))?;
+        let mut prefix = global_text.map(|text| text.to_string());
+        let mut suggester = None;
+
+        for (key, value) in suggester_object.iter() {
+            match key.as_ref() {
+                "prefix" | "text" => prefix = Some(value.as_str().ok_or_else(|| format!("{} must be a string", key))?.to_string()),
+                "completion" => suggester = Some(value),
+                _ => return Err(format!("unsupported suggester {:?}", key)),
+            }
+        }
+
+        match (prefix, suggester) {
+            (Some(prefix), Some(completion_json)) => suggesters.push(parse_completion(name, prefix, completion_json)?),
+            (None, _) => return Err(format!("suggester {:?} must have a prefix", name)),
+            (_, None) => return Err(format!("suggester {:?} must have a type", name)),
+        }
+    }
+
+    Ok(suggesters)
+}
+
+
+fn prefix_selector(term: Term) -> MultiTermSelector {
+    // Completion terms are made from strings, so are always valid UTF-8
+    MultiTermSelector::Prefix(String::from_utf8(term.as_bytes().to_vec()).unwrap())
+}
+
+
+/// Finds the boost that an entry gets from the contexts of the request. Returns
+/// None if the entry isn't in any of them
+fn entry_boost(entry: &CompletionEntry, contexts: &[(&CompletionContext, String, f64)]) -> Option<f64> {
+    if contexts.is_empty() {
+        return Some(1.0);
+    }
+
+    contexts.iter()
+        .filter(|&&(context, ref query_value, _)| {
+            entry.contexts.get(&context.name).map_or(false, |values| values.iter().any(|value| context.matches(value, query_value)))
+        })
+        .map(|&(_, _, boost)| boost)
+        .fold(None, |best: Option<f64>, boost| Some(best.map_or(boost, |best| best.max(boost))))
+}
+
+
+/// Sorts suggestions by score, then removes any past the size of the suggester
+pub fn merge_options(mut options: Vec<SuggestOption>, suggester: &CompletionSuggester) -> Vec<SuggestOption> {
+    options.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal).then_with(|| a.text.cmp(&b.text)));
+
+    if suggester.skip_duplicates {
+        let mut seen = Vec::new();
+        options.retain(|option| {
+            if seen.contains(&option.text) {
+                false
+            } else {
+                seen.push(option.text.clone());
+                true
+            }
+        });
+    }
+
+    options.truncate(suggester.size);
+    options
+}
+
+
+/// Runs a completion suggester against an index
+pub fn run_completion(suggester: &CompletionSuggester, index_reader: &RocksDBReader, index_metadata: &IndexMetadata, index_name: &str) -> Result<Vec<SuggestOption>, String> {
+    let field_mapping = match index_metadata.get_field_mapping(&suggester.field) {
+        Some(field_mapping) if field_mapping.data_type == FieldType::Completion => field_mapping,
+        _ => return Err(format!("field {:?} is not a completion field", suggester.field)),
+    };
+
+    let field = match index_reader.schema().get_field_by_name(&suggester.field) {
+        Some(field) => field,
+        None => return Ok(Vec::new()),
+    };
+
+    // Read the context values, these must be in the mapping of the field
+    let mut contexts = Vec::new();
+    for &(ref name, ref queries) in suggester.contexts.iter() {
+        let context = field_mapping.contexts.iter().find(|context| context.name == *name).ok_or_else(|| format!("unknown context {:?}", name))?;
+
+        for query in queries.iter() {
+            let value = context.parse_value(&query.value, query.precision).ok_or_else(|| format!("invalid value for context {:?}", name))?;
+            contexts.push((context, value, query.boost));
+        }
+    }
+
+    // Find the documents with an input that starts with the prefix
+    let selectors = if contexts.is_empty() {
+        vec![prefix_selector(completion_term(None, &suggester.prefix))]
+    } else {
+        contexts.iter().map(|&(context, ref value, _)| prefix_selector(completion_term(Some((&context.name, value)), &suggester.prefix))).collect()
+    };
+
+    let query = Query::Disjunction {
+        queries: selectors.into_iter().map(|selector| {
+            Query::MultiTerm {
+                field: field,
+                term_selector: selector,
+                scorer: TermScorer::default(),
+            }
+        }).collect(),
+    };
+
+    let mut collector = DocIdSetCollector::new();
+    index_reader.search(&mut collector, &query)?;
+
+    // Each document gives its best suggestion
+    let prefix = suggester.prefix.to_lowercase();
+    let mut options = Vec::new();
+    for doc_id in collector.doc_ids() {
+        let entries = match index_reader.read_stored_field(field, doc_id) {
+            Ok(Some(FieldValue::String(value))) => ::serde_json::from_str(&value).map(|json| CompletionEntry::from_stored_json(&json)).unwrap_or_default(),
+            _ => continue,
+        };
+
+        let mut best: Option<SuggestOption> = None;
+        for entry in entries.iter() {
+            let boost = match entry_boost(entry, &contexts) {
+                Some(boost) => boost,
+                None => continue,
+            };
+
+            for input in entry.inputs.iter().filter(|input| input.to_lowercase().starts_with(&prefix)) {
+                let score = entry.weight as f64 * boost;
+                if best.as_ref().map_or(true, |best| score > best.score) {
+                    best = Some(SuggestOption {
+                        text: input.clone(),
+                        index: index_name.to_string(),
+                        score: score,
+                    });
+                }
+            }
+        }
+
+        options.extend(best);
+    }
+
+    Ok(merge_options(options, suggester))
+}
+
+
+/// Renders the suggestions the way Elasticsearch does, as a list of the words
+/// that were suggested for. Completion suggesters have one of these
+pub fn render_suggestions(suggester: &CompletionSuggester, options: &[SuggestOption]) -> Json {
+    let options_json = options.iter().map(|option| {
+        let mut option_json = Map::new();
+        option_json.insert("text".to_string(), json!(option.text));
+        option_json.insert("_index".to_string(), json!(option.index));
+        option_json.insert("_score".to_string(), json!(option.score));
+        Json::Object(option_json)
+    }).collect::<Vec<_>>();
+
+    json!([{
+        "text": suggester.prefix,
+        "offset": 0,
+        "length": suggester.prefix.chars().count(),
+        "options": options_json,
+    }])
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::remove_dir_all;
+
+    use fnv::FnvHashMap;
+    use search::Document;
+    use search::schema::{FieldType, FIELD_INDEXED, FIELD_STORED};
+    use search::backends::rocksdb::RocksDBStore;
+
+    use mapping::{self, Mapping, MappingProperty, FieldMapping};
+    use mapping::completion::{CompletionContext, CompletionContextType};
+    use index::metadata::IndexMetadata;
+
+    use super::{parse_suggest, CompletionSuggester, ContextQuery, SuggestOption, run_completion, merge_options};
+
+    #[test]
+    fn test_parse_suggest() {
+        let suggesters = parse_suggest(&json!({
+            "text": "nir",
+            "song-suggest": {
+                "completion": {
+                    "field": "suggest",
+                    "size": 3,
+                    "contexts": {
+                        "tenant": ["acme", {"context": "globex", "boost": 2}],
+                        "location": {"context": [13.4, 52.5], "precision": 2}
+                    }
+                }
+            }
+        }));
+
+        assert_eq!(suggesters, Ok(vec![CompletionSuggester {
+            name: "song-suggest".to_string(),
+            prefix: "nir".to_string(),
+            field: "suggest".to_string(),
+            size: 3,
+            skip_duplicates: false,
+            contexts: vec![
+                ("location".to_string(), vec![ContextQuery {value: json!([13.4, 52.5]), precision: Some(2), boost: 1.0}]),
+                ("tenant".to_string(), vec![
+                    ContextQuery {value: json!("acme"), precision: None, boost: 1.0},
+                    ContextQuery {value: json!("globex"), precision: None, boost: 2.0},
+                ]),
+            ],
+        }]));
+
+        assert!(parse_suggest(&json!({"my-suggest": {"text": "nir", "term": {"field": "title"}}})).is_err());
+        assert!(parse_suggest(&json!({"my-suggest": {"completion": {"field": "suggest"}}})).is_err());
+    }
+
+    #[test]
+    fn test_merge_options() {
+        let option = |text: &str, score| SuggestOption {
+            text: text.to_string(),
+            index: "songs".to_string(),
+            score: score,
+        };
+
+        let mut suggester = parse_suggest(&json!({"s": {"prefix": "n", "completion": {"field": "suggest", "size": 2}}})).unwrap().remove(0);
+        assert_eq!(merge_options(vec![option("Nirvana", 1.0), option("Nevermind", 3.0), option("Nirvana", 2.0)], &suggester), vec![
+            option("Nevermind", 3.0),
+            option("Nirvana", 2.0),
+        ]);
+
+        suggester.skip_duplicates = true;
+        suggester.size = 5;
+        assert_eq!(merge_options(vec![option("Nirvana", 1.0), option("Nevermind", 3.0), option("Nirvana", 2.0)], &suggester), vec![
+            option("Nevermind", 3.0),
+            option("Nirvana", 2.0),
+        ]);
+    }
+
+    #[test]
+    fn test_run_completion_with_contexts() {
+        let path = "test_indices/test_run_completion_with_contexts";
+        let _ = remove_dir_all(path);
+
+        let mut store = RocksDBStore::create(path).unwrap();
+        let suggest_field = store.add_field("suggest".to_string(), FieldType::PlainString, FIELD_INDEXED | FIELD_STORED).unwrap();
+
+        let mut suggest_mapping = FieldMapping::default();
+        suggest_mapping.data_type = mapping::FieldType::Completion;
+        suggest_mapping.index_ref = Some(suggest_field);
+        suggest_mapping.is_stored = true;
+        suggest_mapping.contexts = vec![
+            CompletionContext {
+                name: "tenant".to_string(),
+                context_type: CompletionContextType::Category,
+            },
+        ];
+
+        let values = vec![
+            ("1", json!({"input": "Nirvana", "weight": 10, "contexts": {"tenant": "acme"}})),
+            ("2", json!({"input": ["Nine Inch Nails", "NIN"], "weight": 5, "contexts": {"tenant": ["acme", "globex"]}})),
+            ("3", json!({"input": "Nickelback", "weight": 20, "contexts": {"tenant": "globex"}})),
+            ("4", json!({"input": "Metallica", "weight": 30, "contexts": {"tenant": "acme"}})),
+        ];
+
+        for (key, value) in values {
+            let mut indexed_fields = FnvHashMap::default();
+            indexed_fields.insert(suggest_field, suggest_mapping.process_value_for_index(&value).unwrap().unwrap());
+
+            let mut stored_fields = FnvHashMap::default();
+            stored_fields.insert(suggest_field, suggest_mapping.process_value_for_store(&value).unwrap().unwrap());
+
+            store.insert_or_update_document(&Document {
+                key: key.to_string(),
+                indexed_fields: indexed_fields,
+                stored_fields: stored_fields,
+                nested_documents: Vec::new(),
+            }).unwrap();
+        }
+
+        let mut mapping = Mapping {
+            properties: Default::default(),
+        };
+        mapping.properties.insert("suggest".to_string(), MappingProperty::Field(suggest_mapping));
+
+        let mut index_metadata = IndexMetadata::default();
+        index_metadata.mappings.insert("songs".to_string(), mapping);
+
+        let index_reader = store.reader();
+        let suggest = |json| {
+            let suggester = parse_suggest(&json).unwrap().remove(0);
+            run_completion(&suggester, &index_reader, &index_metadata, "songs").map(|options| {
+                options.into_iter().map(|option| (option.text, option.score)).collect::<Vec<_>>()
+            })
+        };
+
+        assert_eq!(suggest(json!({"s": {"prefix": "ni", "completion": {"field": "suggest"}}})), Ok(vec![
+            ("Nickelback".to_string(), 20.0),
+            ("Nirvana".to_string(), 10.0),
+            ("Nine Inch Nails".to_string(), 5.0),
+        ]));
+
+        assert_eq!(suggest(json!({"s": {"prefix": "ni", "completion": {"field": "suggest", "contexts": {"tenant": "acme"}}}})), Ok(vec![
+            ("Nirvana".to_string(), 10.0),
+            ("Nine Inch Nails".to_string(), 5.0),
+        ]));
+
+        // Boosts of the best matching context are multiplied into the weight
+        assert_eq!(suggest(json!({"s": {"prefix": "ni", "completion": {"field": "suggest", "contexts": {"tenant": ["acme", {"context": "globex", "boost": 3}]}}}})), Ok(vec![
+            ("Nickelback".to_string(), 60.0),
+            ("Nine Inch Nails".to_string(), 15.0),
+            ("Nirvana".to_string(), 10.0),
+        ]));
+
+        assert!(suggest(json!({"s": {"prefix": "ni", "completion": {"field": "suggest", "contexts": {"genre": "grunge"}}}})).is_err());
+    }
+}
@@ -1,29 +1,43 @@
-use std::sync::RwLock;
+use std::sync::{Arc, RwLock};
 use std::path::{Path, PathBuf};
 use std::fs;
 
 use slog::Logger;
 use search::backends::rocksdb::RocksDBStore;
-use uuid::Uuid;
+use search::backends::rocksdb::encryption::StoreEncryption;
 
 use index::Index;
 use index::metadata::IndexMetadata;
 use cluster::metadata::ClusterMetadata;
+use ingest::file::{save_pipelines, load_pipelines};
+use watcher::file::{save_watches, load_watches};
+use thread_pool::ThreadPools;
+use audit::AuditLog;
 
 
 pub struct System {
     pub log: Logger,
     data_dir: PathBuf,
     pub metadata: RwLock<ClusterMetadata>,
+    pub thread_pools: ThreadPools,
+    pub audit_log: AuditLog,
+
+    /// Encrypts the stored values of new indices, and decrypts those of existing encrypted indices
+    pub store_encryption: Option<Arc<StoreEncryption>>,
 }
 
 
 impl System {
     pub fn new(log: Logger, data_dir: PathBuf) -> System {
+        let audit_log = AuditLog::new(data_dir.join("audit.log"));
+
         System {
             log: log,
             data_dir: data_dir,
             metadata: RwLock::new(ClusterMetadata::new()),
+            thread_pools: ThreadPools::new(),
+            audit_log: audit_log,
+            store_encryption: None,
         }
     }
 
@@ -33,15 +47,82 @@ impl System {
         dir
     }
 
-    fn load_index(&self, id: Uuid, name: String, path: &Path) -> Result<Index, String> {
-        let store = RocksDBStore::open(path)?;
+    pub fn get_pipelines_path(&self) -> PathBuf {
+        self.data_dir.join("pipelines.json")
+    }
+
+    /// Saves the ingest pipelines, so they're still there after a restart
+    pub fn save_pipelines(&self, cluster_metadata: &ClusterMetadata) -> Result<(), String> {
+        fs::create_dir_all(&self.data_dir).map_err(|e| format!("failed to save pipelines: {}", e))?;
+        save_pipelines(&cluster_metadata.pipelines, self.get_pipelines_path())
+    }
+
+    pub fn load_pipelines(&self) {
+        let path = self.get_pipelines_path();
+        if !path.exists() {
+            return;
+        }
+
+        match load_pipelines(&path) {
+            Ok(pipelines) => {
+                let mut cluster_metadata = self.metadata.write().unwrap();
+                for (name, pipeline) in pipelines {
+                    info!(self.log, "loaded pipeline"; "pipeline" => &name);
+                    cluster_metadata.pipelines.insert(name, pipeline);
+                }
+            }
+            Err(e) => {
+                error!(self.log, "load pipelines failed"; "error" => e);
+            }
+        }
+    }
+
+    pub fn get_watches_path(&self) -> PathBuf {
+        self.data_dir.join("watches.json")
+    }
+
+    /// Saves the watches and their history, so they're still there after a restart
+    pub fn save_watches(&self, cluster_metadata: &ClusterMetadata) -> Result<(), String> {
+        fs::create_dir_all(&self.data_dir).map_err(|e| format!("failed to save watches: {}", e))?;
+        save_watches(&cluster_metadata.watches, self.get_watches_path())
+    }
+
+    pub fn load_watches(&self) {
+        let path = self.get_watches_path();
+        if !path.exists() {
+            return;
+        }
+
+        match load_watches(&path) {
+            Ok(watches) => {
+                let mut cluster_metadata = self.metadata.write().unwrap();
+                for (watch_id, watch) in watches {
+                    info!(self.log, "loaded watch"; "watch" => &watch_id);
+                    cluster_metadata.watches.insert(watch_id, Arc::new(watch));
+                }
+            }
+            Err(e) => {
+                error!(self.log, "load watches failed"; "error" => e);
+            }
+        }
+    }
+
+    fn load_index(&self, path: &Path) -> Result<Index, String> {
+        let store = RocksDBStore::open_with_encryption(path, self.store_encryption.clone())?;
 
         // Load metadata
         let mut metadata_path = path.to_path_buf();
         metadata_path.push("metadata.json");
-        let metadata = IndexMetadata::load(metadata_path)?;
+        let mut metadata = IndexMetadata::load(&metadata_path)?;
+
+        // Indices created before they had UUIDs are stored under their name. Save
+        // the UUID they've just been given so it stays the same between restarts
+        if metadata.provided_name.is_empty() {
+            metadata.provided_name = path.file_name().unwrap().to_str().unwrap().to_owned();
+            metadata.save(&metadata_path)?;
+        }
 
-        Ok(Index::new(id, name, metadata, store))
+        Ok(Index::new(metadata.uuid, metadata.provided_name.clone(), metadata, store))
     }
 
     pub fn load_indices(&self) {
@@ -51,18 +132,19 @@ impl System {
                 for file in files {
                     let path = file.unwrap().path();
                     if path.is_dir() {
-                        let index_name: String = path.file_name().unwrap().to_str().unwrap().to_owned();
+                        let dir_name: String = path.file_name().unwrap().to_str().unwrap().to_owned();
 
-                        match self.load_index(Uuid::new_v4(), index_name.clone().to_owned(), path.as_path()) {
+                        match self.load_index(path.as_path()) {
                             Ok(index) => {
+                                let index_name = index.canonical_name().to_string();
                                 let mut cluster_metadata = self.metadata.write().unwrap();
                                 let index_ref = cluster_metadata.insert_index(index);
                                 cluster_metadata.names.insert_canonical(index_name.clone(), index_ref).unwrap();
 
-                                info!(self.log, "loaded index"; "index" => index_name);
+                                info!(self.log, "loaded index"; "index" => index_name, "uuid" => format!("{}", index_ref.id()));
                             }
                             Err(e) => {
-                                error!(self.log, "load index failed"; "index" => index_name, "error" => e);
+                                error!(self.log, "load index failed"; "dir" => dir_name, "error" => e);
                             }
                         }
                     }
@@ -0,0 +1,280 @@
+//! Thread pools
+//!
+//! Requests are handled on the HTTP server's worker threads. To stop one kind of
+//! work from tying up all of them, each request must take a slot in the pool for
+//! its kind of work first. When all of a pool's slots are taken the request waits
+//! in the pool's queue, and once the queue is full further requests are rejected.
+//!
+//! A queued request parks its worker thread, so each pool can tie up at most
+//! its size plus its queue size of the workers. The queues are kept short so that
+//! a flood of one kind of request can't take the workers that the others need.
+//!
+//! Segment merges take a slot in the merge pool, so the maintenance task skips
+//! merging while a merge requested through the API is running. There's no
+//! snapshot pool as there's no snapshot feature for it to bound.
+
+use std::sync::{Mutex, Condvar};
+
+use serde_json;
+
+
+#[derive(Debug)]
+struct ThreadPoolState {
+    size: usize,
+    queue_size: usize,
+    active: usize,
+    queued: usize,
+    completed: u64,
+    rejected: u64,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThreadPoolStats {
+    pub size: usize,
+    pub queue_size: usize,
+    pub active: usize,
+    pub queue: usize,
+    pub completed: u64,
+    pub rejected: u64,
+}
+
+
+impl ThreadPoolStats {
+    pub fn as_json(&self) -> serde_json::Value {
+        json!({
+            "size": self.size,
+            "queue_size": self.queue_size,
+            "active": self.active,
+            "queue": self.queue,
+            "completed": self.completed,
+            "rejected": self.rejected,
+        })
+    }
+}
+
+
+/// Returned when a pool's queue is full
+#[derive(Debug, Clone, PartialEq)]
+pub struct RejectedExecution {
+    pub pool: &'static str,
+    pub stats: ThreadPoolStats,
+}
+
+
+#[derive(Debug)]
+pub struct ThreadPool {
+    name: &'static str,
+    state: Mutex<ThreadPoolState>,
+    slot_released: Condvar,
+}
+
+
+impl ThreadPool {
+    pub fn new(name: &'static str, size: usize, queue_size: usize) -> ThreadPool {
+        ThreadPool {
+            name: name,
+            state: Mutex::new(ThreadPoolState {
+                size: size,
+                queue_size: queue_size,
+                active: 0,
+                queued: 0,
+                completed: 0,
+                rejected: 0,
+            }),
+            slot_released: Condvar::new(),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Takes a slot in the pool, waiting in the queue if they are all taken and
+    /// failing if the queue is full too
+    ///
+    /// The slot is given back when the returned permit is dropped
+    pub fn acquire<'a>(&'a self) -> Result<ThreadPoolPermit<'a>, RejectedExecution> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.active >= state.size {
+            if state.queued >= state.queue_size {
+                state.rejected += 1;
+                return Err(RejectedExecution {
+                    pool: self.name,
+                    stats: stats_from_state(&state),
+                });
+            }
+
+            state.queued += 1;
+            while state.active >= state.size {
+                state = self.slot_released.wait(state).unwrap();
+            }
+            state.queued -= 1;
+        }
+
+        state.active += 1;
+        Ok(ThreadPoolPermit {
+            pool: self,
+        })
+    }
+
+    /// Changes the number of slots and the length of the queue
+    ///
+    /// Requests that already have a slot keep it, and requests that are already
+    /// queued keep their place, even if the pool is shrunk
+    pub fn resize(&self, size: usize, queue_size: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.size = size;
+        state.queue_size = queue_size;
+
+        self.slot_released.notify_all();
+    }
+
+    pub fn stats(&self) -> ThreadPoolStats {
+        stats_from_state(&self.state.lock().unwrap())
+    }
+}
+
+
+fn stats_from_state(state: &ThreadPoolState) -> ThreadPoolStats {
+    ThreadPoolStats {
+        size: state.size,
+        queue_size: state.queue_size,
+        active: state.active,
+        queue: state.queued,
+        completed: state.completed,
+        rejected: state.rejected,
+    }
+}
+
+
+pub struct ThreadPoolPermit<'a> {
+    pool: &'a ThreadPool,
+}
+
+
+impl<'a> Drop for ThreadPoolPermit<'a> {
+    fn drop(&mut self) {
+        let mut state = self.pool.state.lock().unwrap();
+        state.active -= 1;
+        state.completed += 1;
+
+        self.pool.slot_released.notify_one();
+    }
+}
+
+
+#[derive(Debug)]
+pub struct ThreadPools {
+    pub search: ThreadPool,
+    pub index: ThreadPool,
+
+    /// Merges aren't safe to run alongside each other, so this pool always has
+    /// one slot and no queue, and can't be resized
+    pub merge: ThreadPool,
+}
+
+
+impl ThreadPools {
+    pub fn new() -> ThreadPools {
+        ThreadPools {
+            search: ThreadPool::new("search", 16, 16),
+            index: ThreadPool::new("index", 8, 8),
+            merge: ThreadPool::new("merge", 1, 0),
+        }
+    }
+
+    /// Finds a pool that can be resized
+    pub fn get(&self, name: &str) -> Option<&ThreadPool> {
+        match name {
+            "search" => Some(&self.search),
+            "index" => Some(&self.index),
+            _ => None,
+        }
+    }
+
+    pub fn iter(&self) -> Vec<&ThreadPool> {
+        vec![&self.search, &self.index, &self.merge]
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{ThreadPool, ThreadPools};
+
+    #[test]
+    fn test_acquire() {
+        let pool = ThreadPool::new("test", 2, 0);
+
+        {
+            let _first = pool.acquire().unwrap();
+            let _second = pool.acquire().unwrap();
+            assert_eq!(pool.stats().active, 2);
+
+            // No slots left and no space in the queue
+            let error = pool.acquire().err().expect("acquire() was supposed to return an error, but didn't");
+            assert_eq!(error.pool, "test");
+            assert_eq!(error.stats.rejected, 1);
+        }
+
+        let stats = pool.stats();
+        assert_eq!(stats.active, 0);
+        assert_eq!(stats.completed, 2);
+        assert_eq!(stats.rejected, 1);
+    }
+
+    #[test]
+    fn test_acquire_waits_in_queue() {
+        let pool = Arc::new(ThreadPool::new("test", 1, 1));
+        let permit = pool.acquire().unwrap();
+
+        let waiting_thread = {
+            let pool = pool.clone();
+            thread::spawn(move || {
+                let _permit = pool.acquire().unwrap();
+            })
+        };
+
+        while pool.stats().queue == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        // The queue is full now
+        let error = pool.acquire().err().expect("acquire() was supposed to return an error, but didn't");
+        assert_eq!(error.stats.queue, 1);
+        assert_eq!(error.stats.queue_size, 1);
+
+        drop(permit);
+        waiting_thread.join().unwrap();
+
+        let stats = pool.stats();
+        assert_eq!(stats.queue, 0);
+        assert_eq!(stats.completed, 2);
+        assert_eq!(stats.rejected, 1);
+    }
+
+    #[test]
+    fn test_resize() {
+        let pool = ThreadPool::new("test", 1, 0);
+        let _permit = pool.acquire().unwrap();
+        assert!(pool.acquire().is_err());
+
+        pool.resize(2, 0);
+        assert!(pool.acquire().is_ok());
+    }
+
+    #[test]
+    fn test_merge_pool_cant_be_resized() {
+        let thread_pools = ThreadPools::new();
+
+        assert!(thread_pools.get("search").is_some());
+        assert!(thread_pools.get("merge").is_none());
+        assert_eq!(thread_pools.iter().iter().map(|pool| pool.name()).collect::<Vec<_>>(), vec!["search", "index", "merge"]);
+    }
+}
@@ -0,0 +1,128 @@
+//! Saving watches
+//!
+//! Watches are kept in a single file in the data directory, keyed by their ids.
+//! Each is saved as the JSON it was created with, along with its history. The
+//! file is rewritten whenever a watch is put or deleted, and after watches run.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::io::{Read, Write};
+use std::fs::File;
+use std::sync::Arc;
+
+use serde_json;
+use atomicwrites::{AtomicFile, AllowOverwrite};
+
+use watcher::{Watch, WatchRecord};
+use watcher::parse::parse as parse_watch;
+
+
+pub fn save_watches<P: AsRef<Path>>(watches: &HashMap<String, Arc<Watch>>, path: P) -> Result<(), String> {
+    let mut json = serde_json::Map::new();
+    for (watch_id, watch) in watches.iter() {
+        json.insert(watch_id.clone(), json!({
+            "watch": watch.source,
+            "history": watch.history(),
+        }));
+    }
+    let s = format!("{}", serde_json::Value::Object(json));
+
+    let file = AtomicFile::new(path, AllowOverwrite);
+    file.write(|f| {
+        f.write_all(s.as_bytes())
+    }).map_err(|e| format!("failed to save watches: {}", e))
+}
+
+
+pub fn load_watches<P: AsRef<Path>>(path: P) -> Result<HashMap<String, Watch>, String> {
+    let mut file = File::open(path).map_err(|e| format!("failed to load watches: {}", e))?;
+    let mut s = String::new();
+    file.read_to_string(&mut s).map_err(|e| format!("failed to load watches: {}", e))?;
+
+    let json: serde_json::Value = serde_json::from_str(&s).map_err(|e| format!("failed to load watches: {}", e))?;
+    let json = json.as_object().ok_or("failed to load watches: expected an object".to_string())?;
+
+    let mut watches = HashMap::new();
+    for (watch_id, watch_json) in json.iter() {
+        let watch_json = watch_json.as_object().ok_or(format!("failed to load watch {}: expected an object", watch_id))?;
+
+        let source = watch_json.get("watch").ok_or(format!("failed to load watch {}: expected key \"watch\"", watch_id))?;
+        let watch = parse_watch(source).map_err(|e| format!("failed to load watch {}: {:?}", watch_id, e))?;
+
+        if let Some(history_json) = watch_json.get("history") {
+            let history: Vec<WatchRecord> = serde_json::from_value(history_json.clone()).map_err(|e| format!("failed to load history of watch {}: {}", watch_id, e))?;
+            watch.restore_history(history);
+        }
+
+        watches.insert(watch_id.clone(), watch);
+    }
+
+    Ok(watches)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs;
+    use std::sync::Arc;
+
+    use uuid::Uuid;
+
+    use watcher::{WatchCondition, WatchPayload};
+    use watcher::parse::parse as parse_watch;
+
+    use super::{save_watches, load_watches};
+
+    #[test]
+    fn test_save_and_load_watches() {
+        let path = env::temp_dir().join(format!("rusticsearch-watches-{}.json", Uuid::new_v4()));
+
+        let watch = parse_watch(&json!({
+            "trigger": {
+                "schedule": {
+                    "interval": "5m"
+                }
+            },
+            "input": {
+                "search": {
+                    "request": {
+                        "indices": ["requests"],
+                        "body": {
+                            "aggs": {
+                                "max_latency": {"max": {"field": "latency"}}
+                            }
+                        }
+                    }
+                }
+            },
+            "condition": {
+                "compare": {
+                    "ctx.payload.aggregations.max_latency.value": {"gt": 2.5}
+                }
+            }
+        })).unwrap();
+        let record = watch.execute("slow_requests", Ok(WatchPayload {
+            hit_count: 12,
+            aggregations: json!({"max_latency": {"value": 1.5}}).as_object().unwrap().clone(),
+        }));
+
+        let mut watches = HashMap::new();
+        watches.insert("slow_requests".to_string(), Arc::new(watch));
+        save_watches(&watches, &path).unwrap();
+
+        let loaded = load_watches(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded["slow_requests"].index, "requests");
+        assert_eq!(loaded["slow_requests"].aggregations, watches["slow_requests"].aggregations);
+        assert_eq!(loaded["slow_requests"].source, watches["slow_requests"].source);
+        match loaded["slow_requests"].condition {
+            WatchCondition::AggregationValue{..} => {}
+            ref condition => panic!("unexpected condition {:?}", condition),
+        }
+        assert_eq!(loaded["slow_requests"].history(), vec![record]);
+    }
+}
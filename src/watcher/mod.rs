@@ -0,0 +1,435 @@
+//! The watcher module
+//!
+//! Watches run a query against an index on a schedule and fire a set of
+//! actions whenever the result meets the watch's condition.
+//!
+//! Conditions can compare the hit count, or a value in the results of the
+//! aggregations the watch's search runs. Watches are saved to the data directory
+//! along with their history, which is saved again after every run.
+
+pub mod parse;
+pub mod file;
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde_json;
+use chrono::{DateTime, Utc};
+use hyper::Client;
+use hyper::header::ContentType;
+
+use query_parser::{QueryBuildContext, parse as parse_query};
+use aggregations::parse::parse_aggregations;
+use cluster::metadata::ClusterMetadata;
+use api::search_api::count_with_aggregations;
+
+
+/// The number of records to keep in each watch's history
+const MAX_HISTORY: usize = 100;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Eq,
+    NotEq,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+}
+
+
+impl Comparison {
+    pub fn compare<T: PartialOrd>(self, actual: T, value: T) -> bool {
+        match self {
+            Comparison::Eq => actual == value,
+            Comparison::NotEq => actual != value,
+            Comparison::Gt => actual > value,
+            Comparison::Gte => actual >= value,
+            Comparison::Lt => actual < value,
+            Comparison::Lte => actual <= value,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WatchCondition {
+    Always,
+    Never,
+    HitCount {
+        comparison: Comparison,
+        value: u64,
+    },
+
+    /// Compares a value in the rendered aggregations, found by following the
+    /// path from the name of the aggregation. Array elements are given by index
+    /// (eg, "errors_over_time.buckets.0.doc_count")
+    AggregationValue {
+        path: Vec<String>,
+        comparison: Comparison,
+        value: f64,
+    },
+}
+
+
+impl WatchCondition {
+    pub fn evaluate(&self, payload: &WatchPayload) -> Result<bool, String> {
+        match *self {
+            WatchCondition::Always => Ok(true),
+            WatchCondition::Never => Ok(false),
+            WatchCondition::HitCount{comparison, value} => {
+                Ok(comparison.compare(payload.hit_count, value))
+            }
+            WatchCondition::AggregationValue{ref path, comparison, value} => {
+                let actual = payload.aggregation_value(path)?;
+                Ok(comparison.compare(actual, value))
+            }
+        }
+    }
+}
+
+
+/// The results of a watch's search, which its condition is evaluated against
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatchPayload {
+    pub hit_count: u64,
+    pub aggregations: serde_json::Map<String, serde_json::Value>,
+}
+
+
+impl WatchPayload {
+    fn aggregation_value(&self, path: &[String]) -> Result<f64, String> {
+        let not_found = || format!("aggregation value not found: {}", path.join("."));
+
+        let (name, path_rest) = path.split_first().ok_or_else(&not_found)?;
+        let mut value = self.aggregations.get(name).ok_or_else(&not_found)?;
+        for key in path_rest.iter() {
+            value = match *value {
+                serde_json::Value::Object(ref object) => object.get(key),
+                serde_json::Value::Array(ref array) => key.parse::<usize>().ok().and_then(|index| array.get(index)),
+                _ => None,
+            }.ok_or_else(&not_found)?;
+        }
+
+        // Metrics of aggregations with no documents are rendered as null
+        value.as_f64().ok_or_else(|| format!("aggregation value isn't a number: {}", path.join(".")))
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WatchAction {
+    Webhook {
+        url: String,
+    },
+}
+
+
+impl WatchAction {
+    fn execute(&self, payload: &serde_json::Value) -> Result<(), String> {
+        match *self {
+            WatchAction::Webhook{ref url} => {
+                let mut client = Client::new();
+                client.set_read_timeout(Some(Duration::from_secs(10)));
+                client.set_write_timeout(Some(Duration::from_secs(10)));
+
+                let body = format!("{}", payload);
+                let response = client.post(url.as_str())
+                                     .header(ContentType::json())
+                                     .body(body.as_str())
+                                     .send()
+                                     .map_err(|e| format!("webhook request failed: {}", e))?;
+
+                if !response.status.is_success() {
+                    return Err(format!("webhook returned {}", response.status));
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WatchRecord {
+    pub time: DateTime<Utc>,
+    pub hit_count: Option<u64>,
+    pub fired: bool,
+    pub errors: Vec<String>,
+}
+
+
+#[derive(Debug)]
+struct WatchState {
+    next_run: Option<Instant>,
+    history: VecDeque<WatchRecord>,
+}
+
+
+#[derive(Debug)]
+pub struct Watch {
+    pub index: String,
+    pub query: serde_json::Value,
+
+    /// Parsed on each run like the query, as parsed aggregations can't be shared
+    /// between threads. Null if the watch has none
+    pub aggregations: serde_json::Value,
+    pub interval: Duration,
+    pub condition: WatchCondition,
+    pub actions: Vec<(String, WatchAction)>,
+
+    /// The JSON the watch was created with, which is what gets saved
+    pub source: serde_json::Value,
+    state: Mutex<WatchState>,
+}
+
+
+impl Watch {
+    pub fn new(index: String, query: serde_json::Value, interval: Duration, condition: WatchCondition, actions: Vec<(String, WatchAction)>) -> Watch {
+        Watch {
+            index: index,
+            query: query,
+            aggregations: serde_json::Value::Null,
+            interval: interval,
+            condition: condition,
+            actions: actions,
+            source: serde_json::Value::Null,
+            state: Mutex::new(WatchState {
+                next_run: None,
+                history: VecDeque::new(),
+            }),
+        }
+    }
+
+    pub fn with_aggregations(mut self, aggregations: serde_json::Value) -> Watch {
+        self.aggregations = aggregations;
+        self
+    }
+
+    pub fn with_source(mut self, source: serde_json::Value) -> Watch {
+        self.source = source;
+        self
+    }
+
+    /// Checks if the watch is due to run, and if so, schedules its next run
+    pub fn check_due(&self, now: Instant) -> bool {
+        let mut state = self.state.lock().unwrap();
+
+        let due = match state.next_run {
+            Some(next_run) => now >= next_run,
+            None => true,
+        };
+
+        if due {
+            state.next_run = Some(now + self.interval);
+        }
+
+        due
+    }
+
+    /// Runs the watch's query, counting the hits and running its aggregations
+    ///
+    /// This is the only part of a run that needs the cluster metadata, so the
+    /// scheduler can release its lock before the actions are executed
+    pub fn search(&self, cluster_metadata: &ClusterMetadata) -> Result<WatchPayload, String> {
+        let index_ref = cluster_metadata.names.find_canonical(&self.index).ok_or(format!("index not found: {}", self.index))?;
+        let index = cluster_metadata.indices.get(&index_ref).ok_or(format!("index not found: {}", self.index))?;
+
+        let query = parse_query(&self.query).map_err(|e| format!("query error: {:?}", e))?;
+        {
+            let index_metadata = index.metadata.read().unwrap();
+            let context = QueryBuildContext::new().set_index(index).set_index_metadata(&index_metadata).set_cluster_metadata(cluster_metadata).no_score();
+            query.check(&context).map_err(|e| format!("query error: {}", e))?;
+        }
+
+        let mut aggregations = match self.aggregations {
+            serde_json::Value::Null => Default::default(),
+            ref aggregations_json => parse_aggregations(aggregations_json).map_err(|e| format!("aggregation error: {}", e))?,
+        }.collector();
+        let hit_count = count_with_aggregations(index, cluster_metadata, &*query, &mut aggregations)?;
+
+        Ok(WatchPayload {
+            hit_count: hit_count,
+            aggregations: aggregations.render_map().map_err(|e| format!("aggregation error: {}", e))?,
+        })
+    }
+
+    /// Evaluates the condition against the result of search and executes the
+    /// actions if it was met
+    ///
+    /// The result is recorded in the watch's history and returned
+    pub fn execute(&self, id: &str, payload: Result<WatchPayload, String>) -> WatchRecord {
+        let time = Utc::now();
+        let mut errors = Vec::new();
+        let mut fired = false;
+
+        let payload = match payload {
+            Ok(payload) => Some(payload),
+            Err(e) => {
+                errors.push(e);
+                None
+            }
+        };
+
+        if let Some(ref payload) = payload {
+            match self.condition.evaluate(payload) {
+                Ok(true) => {
+                    fired = true;
+
+                    let mut payload_json = json!({
+                        "watch_id": id,
+                        "time": time,
+                        "hits": {
+                            "total": payload.hit_count,
+                        },
+                    });
+                    if !payload.aggregations.is_empty() {
+                        payload_json.as_object_mut().unwrap().insert("aggregations".to_string(), serde_json::Value::Object(payload.aggregations.clone()));
+                    }
+
+                    for &(ref action_name, ref action) in self.actions.iter() {
+                        if let Err(e) = action.execute(&payload_json) {
+                            errors.push(format!("action {}: {}", action_name, e));
+                        }
+                    }
+                }
+                Ok(false) => {}
+                Err(e) => errors.push(format!("condition error: {}", e)),
+            }
+        }
+
+        let record = WatchRecord {
+            time: time,
+            hit_count: payload.map(|payload| payload.hit_count),
+            fired: fired,
+            errors: errors,
+        };
+
+        let mut state = self.state.lock().unwrap();
+        state.history.push_back(record.clone());
+        while state.history.len() > MAX_HISTORY {
+            state.history.pop_front();
+        }
+
+        record
+    }
+
+    /// Returns the most recent runs of this watch, oldest first
+    pub fn history(&self) -> Vec<WatchRecord> {
+        self.state.lock().unwrap().history.iter().cloned().collect()
+    }
+
+    /// Replaces the history of this watch, with the one it was saved with
+    pub fn restore_history(&self, history: Vec<WatchRecord>) {
+        let mut state = self.state.lock().unwrap();
+        state.history = history.into_iter().collect();
+        while state.history.len() > MAX_HISTORY {
+            state.history.pop_front();
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use serde_json;
+
+    use super::{Watch, WatchCondition, WatchPayload, Comparison};
+
+    fn make_payload(hit_count: u64, aggregations: serde_json::Value) -> WatchPayload {
+        WatchPayload {
+            hit_count: hit_count,
+            aggregations: aggregations.as_object().unwrap().clone(),
+        }
+    }
+
+    #[test]
+    fn test_hit_count_condition() {
+        let condition = WatchCondition::HitCount {
+            comparison: Comparison::Gt,
+            value: 5,
+        };
+
+        assert_eq!(condition.evaluate(&make_payload(5, json!({}))), Ok(false));
+        assert_eq!(condition.evaluate(&make_payload(6, json!({}))), Ok(true));
+
+        let condition = WatchCondition::HitCount {
+            comparison: Comparison::Lte,
+            value: 5,
+        };
+
+        assert_eq!(condition.evaluate(&make_payload(5, json!({}))), Ok(true));
+        assert_eq!(condition.evaluate(&make_payload(6, json!({}))), Ok(false));
+    }
+
+    #[test]
+    fn test_aggregation_value_condition() {
+        let condition = WatchCondition::AggregationValue {
+            path: vec!["max_latency".to_string(), "value".to_string()],
+            comparison: Comparison::Gte,
+            value: 500.0,
+        };
+
+        assert_eq!(condition.evaluate(&make_payload(10, json!({"max_latency": {"value": 499.5}}))), Ok(false));
+        assert_eq!(condition.evaluate(&make_payload(10, json!({"max_latency": {"value": 500.0}}))), Ok(true));
+
+        // Metrics of aggregations that saw no documents have no value to compare
+        assert_eq!(condition.evaluate(&make_payload(0, json!({"max_latency": {"value": null}}))), Err("aggregation value isn't a number: max_latency.value".to_string()));
+        assert_eq!(condition.evaluate(&make_payload(0, json!({}))), Err("aggregation value not found: max_latency.value".to_string()));
+    }
+
+    #[test]
+    fn test_aggregation_value_condition_bucket() {
+        let condition = WatchCondition::AggregationValue {
+            path: vec!["levels".to_string(), "buckets".to_string(), "0".to_string(), "doc_count".to_string()],
+            comparison: Comparison::Gt,
+            value: 100.0,
+        };
+
+        let payload = make_payload(150, json!({
+            "levels": {
+                "buckets": [
+                    {"key": "error", "doc_count": 120},
+                    {"key": "warning", "doc_count": 30}
+                ]
+            }
+        }));
+        assert_eq!(condition.evaluate(&payload), Ok(true));
+
+        let condition = WatchCondition::AggregationValue {
+            path: vec!["levels".to_string(), "buckets".to_string(), "2".to_string(), "doc_count".to_string()],
+            comparison: Comparison::Gt,
+            value: 100.0,
+        };
+        assert_eq!(condition.evaluate(&payload), Err("aggregation value not found: levels.buckets.2.doc_count".to_string()));
+    }
+
+    #[test]
+    fn test_restore_history() {
+        let watch = Watch::new("test".to_string(), json!({"match_all": {}}), Duration::from_secs(60), WatchCondition::Always, vec![]);
+        let record = watch.execute("test", Ok(make_payload(3, json!({}))));
+        assert_eq!(record.hit_count, Some(3));
+        assert_eq!(record.fired, true);
+
+        let restored = Watch::new("test".to_string(), json!({"match_all": {}}), Duration::from_secs(60), WatchCondition::Always, vec![]);
+        restored.restore_history(watch.history());
+        assert_eq!(restored.history(), vec![record]);
+    }
+
+    #[test]
+    fn test_check_due() {
+        let watch = Watch::new("test".to_string(), json!({"match_all": {}}), Duration::from_secs(60), WatchCondition::Always, vec![]);
+        let now = Instant::now();
+
+        // Watches run straight away when first registered
+        assert_eq!(watch.check_due(now), true);
+        assert_eq!(watch.check_due(now), false);
+        assert_eq!(watch.check_due(now + Duration::from_secs(59)), false);
+        assert_eq!(watch.check_due(now + Duration::from_secs(60)), true);
+    }
+}
@@ -0,0 +1,385 @@
+use std::time::Duration;
+
+use serde_json;
+
+use aggregations::tree::Aggregations;
+use aggregations::parse::{AggregationParseError, parse_aggregations};
+use watcher::{Watch, WatchCondition, WatchAction, Comparison};
+
+
+#[derive(Debug, PartialEq)]
+pub enum WatchParseError {
+    ExpectedObject,
+    ExpectedArray,
+    ExpectedString,
+    ExpectedPositiveInteger,
+    ExpectedNumber,
+    ExpectedKey(String),
+    ExpectedSingleIndex,
+    InvalidInterval(String),
+    UnrecognisedCondition(String),
+    UnrecognisedComparison(String),
+    UnrecognisedCompareTarget(String),
+    UnrecognisedAction(String),
+    InvalidAggregations(AggregationParseError),
+}
+
+
+/// Parses an interval such as "30s", "5m", "1h" or "1d"
+pub fn parse_interval(interval: &str) -> Result<Duration, WatchParseError> {
+    let split_at = interval.find(|c: char| !c.is_digit(10)).unwrap_or(interval.len());
+    let (number, unit) = interval.split_at(split_at);
+
+    let number: u64 = match number.parse() {
+        Ok(number) => number,
+        Err(_) => return Err(WatchParseError::InvalidInterval(interval.to_string())),
+    };
+
+    let seconds = match unit {
+        "s" => number,
+        "m" => number * 60,
+        "h" => number * 60 * 60,
+        "d" => number * 60 * 60 * 24,
+        _ => return Err(WatchParseError::InvalidInterval(interval.to_string())),
+    };
+
+    if seconds == 0 {
+        return Err(WatchParseError::InvalidInterval(interval.to_string()));
+    }
+
+    Ok(Duration::from_secs(seconds))
+}
+
+
+fn get_object<'a>(data: &'a serde_json::Map<String, serde_json::Value>, key: &str) -> Result<&'a serde_json::Map<String, serde_json::Value>, WatchParseError> {
+    let value = data.get(key).ok_or(WatchParseError::ExpectedKey(key.to_string()))?;
+    value.as_object().ok_or(WatchParseError::ExpectedObject)
+}
+
+
+fn parse_trigger(data: &serde_json::Map<String, serde_json::Value>) -> Result<Duration, WatchParseError> {
+    let schedule = get_object(data, "schedule")?;
+    let interval_json = schedule.get("interval").ok_or(WatchParseError::ExpectedKey("interval".to_string()))?;
+    let interval = interval_json.as_str().ok_or(WatchParseError::ExpectedString)?;
+
+    parse_interval(interval)
+}
+
+
+/// Parses the search of a watch, returning its index, query and aggregations
+fn parse_input(data: &serde_json::Map<String, serde_json::Value>) -> Result<(String, serde_json::Value, serde_json::Value), WatchParseError> {
+    let search = get_object(data, "search")?;
+    let request = get_object(search, "request")?;
+
+    let indices_json = request.get("indices").ok_or(WatchParseError::ExpectedKey("indices".to_string()))?;
+    let indices = indices_json.as_array().ok_or(WatchParseError::ExpectedArray)?;
+    if indices.len() != 1 {
+        return Err(WatchParseError::ExpectedSingleIndex);
+    }
+    let index = indices[0].as_str().ok_or(WatchParseError::ExpectedString)?;
+
+    let (query, aggregations) = match request.get("body") {
+        Some(body_json) => {
+            let body = body_json.as_object().ok_or(WatchParseError::ExpectedObject)?;
+            let query = body.get("query").cloned().unwrap_or(json!({"match_all": {}}));
+            let aggregations = body.get("aggs").or_else(|| body.get("aggregations")).cloned().unwrap_or(serde_json::Value::Null);
+            (query, aggregations)
+        }
+        None => (json!({"match_all": {}}), serde_json::Value::Null),
+    };
+
+    Ok((index.to_string(), query, aggregations))
+}
+
+
+/// Parses the condition of a watch. Aggregation values can only be compared if
+/// the watch's search runs an aggregation with that name
+fn parse_condition(data: &serde_json::Map<String, serde_json::Value>, aggregations: &Aggregations) -> Result<WatchCondition, WatchParseError> {
+    if data.len() != 1 {
+        return Err(WatchParseError::ExpectedObject);
+    }
+
+    let (condition_type, condition_json) = data.iter().next().unwrap();
+
+    match condition_type.as_ref() {
+        "always" => Ok(WatchCondition::Always),
+        "never" => Ok(WatchCondition::Never),
+        "compare" => {
+            let compare = condition_json.as_object().ok_or(WatchParseError::ExpectedObject)?;
+            if compare.len() != 1 {
+                return Err(WatchParseError::ExpectedObject);
+            }
+
+            let (target, comparison_json) = compare.iter().next().unwrap();
+
+            let comparison_object = comparison_json.as_object().ok_or(WatchParseError::ExpectedObject)?;
+            if comparison_object.len() != 1 {
+                return Err(WatchParseError::ExpectedObject);
+            }
+
+            let (comparison_name, value_json) = comparison_object.iter().next().unwrap();
+            let comparison = match comparison_name.as_ref() {
+                "eq" => Comparison::Eq,
+                "not_eq" => Comparison::NotEq,
+                "gt" => Comparison::Gt,
+                "gte" => Comparison::Gte,
+                "lt" => Comparison::Lt,
+                "lte" => Comparison::Lte,
+                _ => return Err(WatchParseError::UnrecognisedComparison(comparison_name.to_string())),
+            };
+
+            if target == "ctx.payload.hits.total" {
+                let value = value_json.as_u64().ok_or(WatchParseError::ExpectedPositiveInteger)?;
+
+                return Ok(WatchCondition::HitCount {
+                    comparison: comparison,
+                    value: value,
+                });
+            }
+
+            if target.starts_with("ctx.payload.aggregations.") {
+                let path = target["ctx.payload.aggregations.".len()..].split('.').map(|key| key.to_string()).collect::<Vec<_>>();
+                if !aggregations.aggregations.iter().any(|aggregation| aggregation.name == path[0]) {
+                    return Err(WatchParseError::UnrecognisedCompareTarget(target.to_string()));
+                }
+
+                let value = value_json.as_f64().ok_or(WatchParseError::ExpectedNumber)?;
+
+                return Ok(WatchCondition::AggregationValue {
+                    path: path,
+                    comparison: comparison,
+                    value: value,
+                });
+            }
+
+            Err(WatchParseError::UnrecognisedCompareTarget(target.to_string()))
+        }
+        _ => Err(WatchParseError::UnrecognisedCondition(condition_type.to_string())),
+    }
+}
+
+
+fn parse_action(data: &serde_json::Map<String, serde_json::Value>) -> Result<WatchAction, WatchParseError> {
+    if let Some(webhook_json) = data.get("webhook") {
+        let webhook = webhook_json.as_object().ok_or(WatchParseError::ExpectedObject)?;
+        let url_json = webhook.get("url").ok_or(WatchParseError::ExpectedKey("url".to_string()))?;
+        let url = url_json.as_str().ok_or(WatchParseError::ExpectedString)?;
+
+        return Ok(WatchAction::Webhook {
+            url: url.to_string(),
+        });
+    }
+
+    match data.keys().next() {
+        Some(action_type) => Err(WatchParseError::UnrecognisedAction(action_type.to_string())),
+        None => Err(WatchParseError::ExpectedKey("webhook".to_string())),
+    }
+}
+
+
+pub fn parse(json: &serde_json::Value) -> Result<Watch, WatchParseError> {
+    let data = json.as_object().ok_or(WatchParseError::ExpectedObject)?;
+
+    let interval = parse_trigger(get_object(data, "trigger")?)?;
+    let (index, query, aggregations_json) = parse_input(get_object(data, "input")?)?;
+    let aggregations = match aggregations_json {
+        serde_json::Value::Null => Aggregations::new(),
+        ref aggregations_json => parse_aggregations(aggregations_json).map_err(WatchParseError::InvalidAggregations)?,
+    };
+
+    let condition = match data.get("condition") {
+        Some(condition_json) => {
+            parse_condition(condition_json.as_object().ok_or(WatchParseError::ExpectedObject)?, &aggregations)?
+        }
+        None => WatchCondition::Always,
+    };
+
+    let mut actions = Vec::new();
+    if let Some(actions_json) = data.get("actions") {
+        let actions_object = actions_json.as_object().ok_or(WatchParseError::ExpectedObject)?;
+
+        for (action_name, action_json) in actions_object.iter() {
+            let action = parse_action(action_json.as_object().ok_or(WatchParseError::ExpectedObject)?)?;
+            actions.push((action_name.clone(), action));
+        }
+    }
+
+    Ok(Watch::new(index, query, interval, condition, actions).with_aggregations(aggregations_json).with_source(json.clone()))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json;
+
+    use watcher::{WatchCondition, WatchAction, Comparison};
+
+    use super::{parse, parse_interval, WatchParseError};
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_interval("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_interval("2h"), Ok(Duration::from_secs(7200)));
+        assert_eq!(parse_interval("1d"), Ok(Duration::from_secs(86400)));
+        assert_eq!(parse_interval("0s"), Err(WatchParseError::InvalidInterval("0s".to_string())));
+        assert_eq!(parse_interval("5"), Err(WatchParseError::InvalidInterval("5".to_string())));
+        assert_eq!(parse_interval("m"), Err(WatchParseError::InvalidInterval("m".to_string())));
+    }
+
+    #[test]
+    fn test_watch() {
+        let watch = parse(&json!({
+            "trigger": {
+                "schedule": {
+                    "interval": "10m"
+                }
+            },
+            "input": {
+                "search": {
+                    "request": {
+                        "indices": ["logs"],
+                        "body": {
+                            "query": {
+                                "term": {"level": "error"}
+                            }
+                        }
+                    }
+                }
+            },
+            "condition": {
+                "compare": {
+                    "ctx.payload.hits.total": {"gte": 10}
+                }
+            },
+            "actions": {
+                "notify_ops": {
+                    "webhook": {
+                        "url": "http://localhost:8080/alert"
+                    }
+                }
+            }
+        })).expect("parse() returned an error");
+
+        assert_eq!(watch.index, "logs");
+        assert_eq!(watch.query, json!({"term": {"level": "error"}}));
+        assert_eq!(watch.interval, Duration::from_secs(600));
+        assert_eq!(watch.condition, WatchCondition::HitCount {
+            comparison: Comparison::Gte,
+            value: 10,
+        });
+        assert_eq!(watch.actions, vec![
+            ("notify_ops".to_string(), WatchAction::Webhook {
+                url: "http://localhost:8080/alert".to_string(),
+            }),
+        ]);
+    }
+
+    #[test]
+    fn test_watch_defaults() {
+        let watch = parse(&json!({
+            "trigger": {
+                "schedule": {
+                    "interval": "1h"
+                }
+            },
+            "input": {
+                "search": {
+                    "request": {
+                        "indices": ["logs"]
+                    }
+                }
+            }
+        })).expect("parse() returned an error");
+
+        assert_eq!(watch.query, json!({"match_all": {}}));
+        assert_eq!(watch.aggregations, serde_json::Value::Null);
+        assert_eq!(watch.condition, WatchCondition::Always);
+        assert!(watch.actions.is_empty());
+    }
+
+    #[test]
+    fn test_unrecognised_condition() {
+        let error = parse(&json!({
+            "trigger": {
+                "schedule": {
+                    "interval": "1h"
+                }
+            },
+            "input": {
+                "search": {
+                    "request": {
+                        "indices": ["logs"]
+                    }
+                }
+            },
+            "condition": {
+                "script": {}
+            }
+        })).err().expect("parse() was supposed to return an error, but didn't");
+
+        assert_eq!(error, WatchParseError::UnrecognisedCondition("script".to_string()));
+    }
+
+    #[test]
+    fn test_aggregation_condition() {
+        let watch = parse(&json!({
+            "trigger": {
+                "schedule": {
+                    "interval": "5m"
+                }
+            },
+            "input": {
+                "search": {
+                    "request": {
+                        "indices": ["requests"],
+                        "body": {
+                            "aggs": {
+                                "max_latency": {"max": {"field": "latency"}}
+                            }
+                        }
+                    }
+                }
+            },
+            "condition": {
+                "compare": {
+                    "ctx.payload.aggregations.max_latency.value": {"gt": 2.5}
+                }
+            }
+        })).expect("parse() returned an error");
+
+        assert_eq!(watch.aggregations, json!({"max_latency": {"max": {"field": "latency"}}}));
+        assert_eq!(watch.condition, WatchCondition::AggregationValue {
+            path: vec!["max_latency".to_string(), "value".to_string()],
+            comparison: Comparison::Gt,
+            value: 2.5,
+        });
+    }
+
+    #[test]
+    fn test_aggregation_condition_unknown_aggregation() {
+        let error = parse(&json!({
+            "trigger": {
+                "schedule": {
+                    "interval": "5m"
+                }
+            },
+            "input": {
+                "search": {
+                    "request": {
+                        "indices": ["requests"]
+                    }
+                }
+            },
+            "condition": {
+                "compare": {
+                    "ctx.payload.aggregations.max_latency.value": {"gt": 2.5}
+                }
+            }
+        })).err().expect("parse() was supposed to return an error, but didn't");
+
+        assert_eq!(error, WatchParseError::UnrecognisedCompareTarget("ctx.payload.aggregations.max_latency.value".to_string()));
+    }
+}